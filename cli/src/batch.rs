@@ -0,0 +1,628 @@
+//! The batch engine behind `compress`, `convert` and `resize`: a [`BatchRunner`] reads
+//! each file, runs an [`Operation`] on it in a thread pool, leaves it alone when
+//! nothing was gained, backs up and writes the rest, and collects a [`Report`].
+//! Front ends keep what is theirs (collecting files, planning outputs, printing)
+//! and add their own bookkeeping (caches, manifests, sidecars) through
+//! [`BatchHooks`]; progress goes to a [`ProgressSink`].
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+
+use crate::cancel::CancelToken;
+use crate::chain::ChainProfile;
+use crate::checksum::sha256_hex;
+use crate::config::ProcessingConfig;
+use crate::converter::convert_image;
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::io::{create_backup, read_file, resolve_output, write_file, RetryPolicy};
+use crate::outcome::ProcessOutcome;
+use crate::parallel::{BudgetGuard, MemoryBudget, Parallelism};
+use crate::pipeline::Pipeline;
+use crate::processor::audio::convert_audio;
+use crate::progress::{Progress, ProgressEvent, ProgressSink};
+use crate::report::{FileResult, Report};
+use crate::transform::{resize_image, ResizeOptions};
+
+/// What a batch does to each file
+pub trait Operation: Sync {
+    /// Formats the operation reads, for collecting the batch's files
+    fn sources(&self) -> Vec<Format>;
+
+    /// The format `path` is written as when the operation changes it. Such
+    /// outputs are new files, written whatever their size.
+    fn target(&self, path: &Path) -> Option<Format>;
+
+    /// Where `path`, found under `input`, is written given the `output` argument:
+    /// the same file by default, else mirrored under `output` (see [`resolve_output`]),
+    /// with the extension of [`Operation::target`]
+    fn output(&self, path: &Path, input: &Path, output: Option<&Path>) -> PathBuf {
+        let resolved = resolve_output(path, input, output);
+        match self.target(path) {
+            Some(format) => resolved.with_extension(format.extension()),
+            None => resolved,
+        }
+    }
+
+    /// Process `data`, read from `path`
+    fn process<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError>;
+}
+
+/// `compress`: each file through a [`Pipeline`], optionally converting one format
+/// with a [`ChainProfile`]
+pub struct Compress {
+    pipeline: Pipeline,
+    profile: Option<ChainProfile>,
+}
+
+impl Compress {
+    pub fn new(pipeline: Pipeline) -> Self {
+        Self { pipeline, profile: None }
+    }
+
+    /// Run `profile`'s chain on its source format; those files get the target's extension
+    pub fn with_profile(mut self, profile: ChainProfile) -> Self {
+        self.pipeline.set_chain(profile.source(), profile.chain());
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// The pipeline, e.g. to give it a progress sink for stages
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+}
+
+impl Operation for Compress {
+    fn sources(&self) -> Vec<Format> {
+        self.pipeline.supported_formats()
+    }
+
+    fn target(&self, path: &Path) -> Option<Format> {
+        self.profile.filter(|profile| Format::from_path(path) == Some(profile.source())).map(ChainProfile::target)
+    }
+
+    fn process<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.pipeline.process_file_with_stats(path, data, config)
+    }
+}
+
+/// `convert`: every file to one target format. Images are converted in process,
+/// videos and audio through ffmpeg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Convert(pub Format);
+
+impl Operation for Convert {
+    fn sources(&self) -> Vec<Format> {
+        // Videos can become animations or WebM; only videos can become WebM; WAV and FLAC convert into each other
+        match self.0 {
+            Format::Webm => vec![Format::Mp4],
+            Format::Opus => Format::OPUS_SOURCES.to_vec(),
+            Format::Flac => vec![Format::Wav],
+            Format::Wav => vec![Format::Flac],
+            format if Format::ANIMATIONS.contains(&format) => [&Format::IMAGE_SOURCES[..], &[Format::Mp4]].concat(),
+            _ => Format::IMAGE_SOURCES.to_vec(),
+        }
+    }
+
+    fn target(&self, _path: &Path) -> Option<Format> {
+        Some(self.0)
+    }
+
+    /// Next to the input, or flat in an `output` directory, or `output` itself
+    fn output(&self, path: &Path, _input: &Path, output: Option<&Path>) -> PathBuf {
+        match output {
+            Some(dir) if dir.is_dir() => {
+                let file_name = path.file_stem().unwrap_or_default();
+                dir.join(format!("{}.{}", file_name.to_string_lossy(), self.0.extension()))
+            }
+            Some(file) => file.to_path_buf(),
+            None => path.with_extension(self.0.extension()),
+        }
+    }
+
+    fn process<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let converted = match Format::detect(path, data) {
+            #[cfg(feature = "mp4")]
+            Some(Format::Mp4) => match self.0 {
+                Format::Webm => crate::processor::mp4::video_to_webm(data, config)?,
+                target => crate::processor::mp4::video_to_animation(data, target, config)?,
+            },
+            #[cfg(not(feature = "mp4"))]
+            Some(Format::Mp4) => return Err(ProcessingError::UnsupportedFormat("mp4".to_string())),
+            Some(Format::Wav | Format::Flac | Format::Mp3) => convert_audio(data, self.0, config)?,
+            _ => convert_image(data, self.0, config)?,
+        };
+        Ok(ProcessOutcome::new(converted, self.0))
+    }
+}
+
+/// `resize`: every image scaled and re-encoded in its own format, through the
+/// format's optimizer with `compress`
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    pub options: ResizeOptions,
+    pub compress: bool,
+}
+
+impl Operation for Resize {
+    fn sources(&self) -> Vec<Format> {
+        Format::IMAGES.to_vec()
+    }
+
+    /// The input's own format: a resized image is written whatever its size
+    fn target(&self, path: &Path) -> Option<Format> {
+        Format::from_path(path)
+    }
+
+    /// Keeps the input's extension, e.g. `.jpeg`
+    fn output(&self, path: &Path, input: &Path, output: Option<&Path>) -> PathBuf {
+        resolve_output(path, input, output)
+    }
+
+    fn process<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let format = Format::detect(path, data).unwrap_or(Format::Png);
+        resize_image(data, format, &self.options, self.compress, config, &Progress::none())
+    }
+}
+
+/// One file of a batch, as the hooks see it
+#[derive(Debug, Clone, Copy)]
+pub struct BatchFile<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    /// The input as read
+    pub data: &'a [u8],
+    /// Hex SHA-256 of `data`, with [`BatchRunner::checksum_input`]
+    pub input_sha256: Option<&'a str>,
+}
+
+/// What became of a processed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The output was written
+    Written,
+    /// Processing changed nothing; the input was left as it was
+    Unchanged,
+    /// The output was not smaller, so the input was left as it was
+    Skipped,
+}
+
+impl FileStatus {
+    /// Where the file's result now is: the output when written, else the input
+    pub fn path<'a>(self, file: &BatchFile<'a>) -> &'a Path {
+        match self {
+            FileStatus::Written => file.output,
+            FileStatus::Unchanged | FileStatus::Skipped => file.input,
+        }
+    }
+}
+
+/// Bookkeeping a front end adds to a batch. Hooks run on the worker threads;
+/// their errors fail the file.
+pub trait BatchHooks: Sync {
+    /// The result of a file that needs no processing (e.g. cached), or None to process it
+    fn skip(&self, _file: &BatchFile) -> Result<Option<FileResult>, ProcessingError> {
+        Ok(None)
+    }
+
+    /// Adjust a fresh outcome before it is compared with the input
+    fn processed(&self, _file: &BatchFile, _outcome: &mut ProcessOutcome) -> Result<(), ProcessingError> {
+        Ok(())
+    }
+
+    /// An outcome about to be written, also in dry runs; may add warnings
+    fn before_write(&self, _file: &BatchFile, _outcome: &mut ProcessOutcome) {}
+
+    /// The file is settled: [`FileStatus::path`] holds data whose hex SHA-256 is
+    /// `sha256`; may add warnings. Dry runs call it only for files left as they are.
+    fn finished(
+        &self,
+        _file: &BatchFile,
+        _outcome: &mut ProcessOutcome,
+        _status: FileStatus,
+        _sha256: &str,
+    ) -> Result<(), ProcessingError> {
+        Ok(())
+    }
+}
+
+/// No bookkeeping
+impl BatchHooks for () {}
+
+/// Runs an [`Operation`] over many files
+pub struct BatchRunner<'a> {
+    operation: &'a dyn Operation,
+    hooks: &'a dyn BatchHooks,
+    parallelism: Parallelism,
+    cancel: CancelToken,
+    budget: Option<&'a MemoryBudget>,
+    retry: RetryPolicy,
+    checksum_input: bool,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl<'a> BatchRunner<'a> {
+    pub fn new(operation: &'a dyn Operation) -> Self {
+        Self {
+            operation,
+            hooks: &(),
+            parallelism: Parallelism::default(),
+            cancel: CancelToken::new(),
+            budget: None,
+            retry: RetryPolicy::default(),
+            checksum_input: false,
+            progress: None,
+        }
+    }
+
+    pub fn hooks(mut self, hooks: &'a dyn BatchHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Process files in `parallelism` instead of rayon's global pool
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Stop picking up files once `token` is cancelled; files already running finish
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Hold each file back until twice its size fits in `budget`
+    pub fn memory_budget(mut self, budget: &'a MemoryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Repeat reads, writes and backups that fail transiently per `retry`
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Hash each input for [`FileResult::input_sha256`]
+    pub fn checksum_input(mut self, checksum_input: bool) -> Self {
+        self.checksum_input = checksum_input;
+        self
+    }
+
+    /// Report the batch's start and totals and each file's start and result to `sink`.
+    /// Stages come from the operation (e.g. [`Pipeline::set_progress_sink`]).
+    pub fn progress_sink(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(sink);
+        self
+    }
+
+    /// Process `files`, each written to its entry in `outputs` (in place when it has
+    /// none). Files are handed to the pool in list order, each once its memory is
+    /// reserved, so call this from outside the pool. A dry run (`config.dry_run`)
+    /// writes nothing. Files stopped by cancellation are left out of the report.
+    pub fn run(&self, files: &[PathBuf], outputs: &HashMap<PathBuf, PathBuf>, config: &ProcessingConfig) -> Report {
+        self.event(&ProgressEvent::BatchStarted { files: files.len() });
+        let report = Mutex::new(Report::new());
+        let process = |path: &PathBuf, size: u64| {
+            let started = Instant::now();
+            self.event(&ProgressEvent::FileStarted { path, size });
+
+            let output = outputs.get(path).map_or(path.as_path(), PathBuf::as_path);
+            match self.run_file(path, output, config) {
+                Ok(mut result) => {
+                    result.duration = Some(started.elapsed());
+                    self.event(&ProgressEvent::FileDone {
+                        path,
+                        original_size: result.original_size,
+                        output_size: result.compressed_size,
+                        bytes_saved: result.original_size.saturating_sub(result.compressed_size),
+                    });
+                    report.lock().unwrap().add(result);
+                }
+                Err(error) => {
+                    self.event(&ProgressEvent::FileFailed { path, error: &error });
+                    // Stopped by cancellation: counted as not processed, not as failed
+                    if matches!(error, ProcessingError::Cancelled) {
+                        log::debug!("Cancelled {}", path.display());
+                        return;
+                    }
+                    log::error!("Error processing {}: {}", path.display(), error);
+                    let result = FileResult::failed(path.clone(), error.to_string(), Some(started.elapsed()));
+                    report.lock().unwrap().add(result);
+                }
+            }
+        };
+        schedule(files, &self.parallelism, self.budget, &self.cancel, process);
+
+        let report = report.into_inner().unwrap();
+        let failed = report.error_count();
+        self.event(&ProgressEvent::BatchDone {
+            processed: report.results.len() - failed,
+            failed,
+            bytes_saved: report.total_original().saturating_sub(report.total_compressed()),
+        });
+        report
+    }
+
+    fn run_file(&self, path: &Path, output: &Path, config: &ProcessingConfig) -> Result<FileResult, ProcessingError> {
+        let data = self.retry.run(|| read_file(path))?;
+        let input_sha256 = self.checksum_input.then(|| sha256_hex(&data));
+        let file = BatchFile { input: path, output, data: &data, input_sha256: input_sha256.as_deref() };
+        let original_size = data.len() as u64;
+        if let Some(result) = self.hooks.skip(&file)? {
+            log::debug!("Skipping {} — already processed with these settings", path.display());
+            return Ok(FileResult { input_sha256, ..result });
+        }
+
+        let mut outcome = self.operation.process(path, &data, config)?;
+        self.hooks.processed(&file, &mut outcome)?;
+        let compressed_size = outcome.data.len() as u64;
+        let status = if outcome.is_unchanged() {
+            FileStatus::Unchanged
+        } else if compressed_size >= original_size && self.operation.target(path).is_none() {
+            log::debug!(
+                "Skipping {} — compressed ({}) >= original ({})",
+                path.display(),
+                compressed_size,
+                original_size
+            );
+            FileStatus::Skipped
+        } else {
+            FileStatus::Written
+        };
+
+        let mut result = FileResult::new(path.to_path_buf(), original_size, original_size);
+        if status != FileStatus::Written {
+            // Nothing to do for this file: leave it untouched on disk
+            let sha256 = sha256_hex(&data);
+            self.hooks.finished(&file, &mut outcome, status, &sha256)?;
+            return Ok(FileResult {
+                skipped: status == FileStatus::Skipped,
+                unchanged: status == FileStatus::Unchanged,
+                bitrate: outcome.bitrate,
+                warnings: outcome.warnings,
+                output_sha256: Some(sha256),
+                input_sha256,
+                ..result
+            });
+        }
+
+        self.hooks.before_write(&file, &mut outcome);
+        result.compressed_size = compressed_size;
+        if !config.dry_run {
+            if config.backup {
+                self.retry.run(|| create_backup(output))?;
+            }
+            self.retry.run(|| write_file(output, &outcome.data))?;
+            let sha256 = sha256_hex(&outcome.data);
+            self.hooks.finished(&file, &mut outcome, status, &sha256)?;
+            result.output_sha256 = Some(sha256);
+        }
+        Ok(FileResult {
+            metadata_removed: outcome.metadata_removed,
+            bitrate: outcome.bitrate,
+            warnings: outcome.warnings,
+            input_sha256,
+            ..result
+        })
+    }
+
+    fn event(&self, event: &ProgressEvent) {
+        if let Some(sink) = &self.progress {
+            sink.event(event);
+        }
+    }
+}
+
+/// Run `job` on each of `files` in `parallelism`, handing them out in list order;
+/// `job` gets a file and its size. With a `budget`, each file waits until twice
+/// its size fits. Once `cancel` is cancelled, files not yet started are dropped.
+///
+/// Reservations are taken and released on the calling thread, never on a worker:
+/// a worker waiting on parallel work inside one file runs other files' jobs, and
+/// must never block on the budget that file holds. Call this from outside the pool.
+pub fn schedule(
+    files: &[PathBuf],
+    parallelism: &Parallelism,
+    budget: Option<&MemoryBudget>,
+    cancel: &CancelToken,
+    job: impl Fn(&PathBuf, u64) + Sync,
+) {
+    parallelism.in_place_scope(|scope| {
+        let (finished, done) = mpsc::channel();
+        let mut reserved = HashMap::new();
+        for (i, path) in files.iter().enumerate() {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let size = std::fs::metadata(path).map_or(0, |meta| meta.len());
+            if let Some(guard) = budget.map(|budget| reserve(budget, path, size, &done, &mut reserved)) {
+                reserved.insert(i, guard);
+            }
+            let (job, finished) = (&job, Finished(i, finished.clone()));
+            scope.spawn(move |_| {
+                let _finished = finished;
+                if !cancel.is_cancelled() {
+                    job(path, size);
+                }
+            });
+        }
+    });
+}
+
+/// Hold back until the file fits the memory budget: its bytes plus about as much
+/// again for the processed copy. While waiting, releases the reservations in
+/// `reserved` of the files reported on `done`.
+fn reserve<'a>(
+    budget: &'a MemoryBudget,
+    path: &Path,
+    size: u64,
+    done: &mpsc::Receiver<usize>,
+    reserved: &mut HashMap<usize, BudgetGuard<'a>>,
+) -> BudgetGuard<'a> {
+    let bytes = size.saturating_mul(2);
+    if bytes > budget.limit() {
+        log::debug!("{} is larger than the memory budget, processing it alone", path.display());
+    }
+    loop {
+        for finished in done.try_iter() {
+            reserved.remove(&finished);
+        }
+        if let Some(guard) = budget.try_acquire(bytes) {
+            return guard;
+        }
+        if reserved.is_empty() {
+            // The bytes in the way are someone else's
+            return budget.acquire(bytes);
+        }
+        if let Ok(finished) = done.recv() {
+            reserved.remove(&finished);
+        }
+    }
+}
+
+/// Sends a file's index when its job ends, also by panicking, so the thread
+/// waiting to release its reservation does not wait forever
+struct Finished(usize, mpsc::Sender<usize>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let _ = self.1.send(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Statuses [`BatchHooks::finished`] was called with
+    #[derive(Default)]
+    struct Settled(Mutex<Vec<FileStatus>>);
+
+    impl BatchHooks for Settled {
+        fn finished(
+            &self,
+            _file: &BatchFile,
+            _outcome: &mut ProcessOutcome,
+            status: FileStatus,
+            _sha256: &str,
+        ) -> Result<(), ProcessingError> {
+            self.0.lock().unwrap().push(status);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_runner() {
+        let dir = std::env::temp_dir().join(format!("ip_batch_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut png = Vec::new();
+        image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 90]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let files = vec![dir.join("gradient.png"), dir.join("broken.png")];
+        fs::write(&files[0], &png).unwrap();
+        fs::write(&files[1], b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let convert = Convert(Format::Jpeg);
+        assert_eq!(convert.output(&files[0], &dir, None), dir.join("gradient.jpg"));
+        let outputs: HashMap<PathBuf, PathBuf> =
+            files.iter().map(|path| (path.clone(), convert.output(path, &dir, None))).collect();
+        let report = BatchRunner::new(&convert).retry(RetryPolicy::none()).run(&files, &outputs, &Default::default());
+        assert_eq!((report.success_count(), report.error_count()), (1, 1));
+        let jpeg = fs::read(dir.join("gradient.jpg")).unwrap();
+        assert_eq!(Format::from_bytes(&jpeg), Some(Format::Jpeg));
+        let written = report.results.iter().find(|result| result.error.is_none()).unwrap();
+        assert_eq!(written.output_sha256.as_deref(), Some(sha256_hex(&jpeg).as_str()));
+
+        // Compressing the JPEG again in a dry run touches nothing
+        let config = ProcessingConfig { dry_run: true, ..Default::default() };
+        let compress = Compress::new(Pipeline::with_default_processors());
+        let jpegs = [dir.join("gradient.jpg")];
+        let settled = Settled::default();
+        let report = BatchRunner::new(&compress).hooks(&settled).run(&jpegs, &HashMap::new(), &config);
+        assert_eq!(report.error_count(), 0);
+        assert_eq!(fs::read(&jpegs[0]).unwrap(), jpeg);
+        // Left as it is, so it is still settled for the manifest
+        assert_eq!(*settled.0.lock().unwrap(), [FileStatus::Unchanged]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Waits on parallel work inside each file, like oxipng, and records the
+    /// most bytes the budget held meanwhile
+    struct Nested<'a>(&'a MemoryBudget, Mutex<u64>);
+
+    impl Operation for Nested<'_> {
+        fn sources(&self) -> Vec<Format> {
+            vec![Format::Png]
+        }
+
+        fn target(&self, _path: &Path) -> Option<Format> {
+            None
+        }
+
+        fn process<'a>(
+            &self,
+            _path: &Path,
+            data: &'a [u8],
+            _config: &ProcessingConfig,
+        ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+            rayon::join(|| std::thread::sleep(std::time::Duration::from_millis(20)), || ());
+            let mut peak = self.1.lock().unwrap();
+            *peak = (*peak).max(self.0.in_use());
+            Ok(ProcessOutcome::unchanged(data, Format::Png))
+        }
+    }
+
+    #[test]
+    fn test_runner_memory_budget_with_nested_work() {
+        let dir = std::env::temp_dir().join(format!("ip_batch_budget_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files: Vec<PathBuf> = (0..8).map(|i| dir.join(format!("{}.png", i))).collect();
+        for file in &files {
+            fs::write(file, [0; 40]).unwrap();
+        }
+
+        // Each file reserves 80 bytes, so only one fits at a time; the idle
+        // worker must not take another file while the first waits on its join
+        let budget = MemoryBudget::new(100);
+        let nested = Nested(&budget, Mutex::new(0));
+        let report = BatchRunner::new(&nested)
+            .parallelism(Parallelism::threads(2).unwrap())
+            .memory_budget(&budget)
+            .run(&files, &HashMap::new(), &Default::default());
+        assert_eq!(report.results.len(), 8);
+        assert_eq!(*nested.1.lock().unwrap(), 80);
+        assert_eq!(budget.in_use(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
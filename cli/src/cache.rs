@@ -0,0 +1,173 @@
+//! Record of files already processed, so re-runs over large trees can skip them.
+//!
+//! Each entry maps an output path to the content hash of the source it was made
+//! from, the hash of what was written and a fingerprint of the settings. A file
+//! is skipped when its current content matches either hash under the same
+//! fingerprint: the source was already processed into that output, or the file
+//! is itself that output (in-place runs).
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::ProcessingConfig;
+use crate::error::ProcessingError;
+
+/// Default cache file name, placed in the input directory
+pub const CACHE_FILE_NAME: &str = ".image_preparer_cache";
+
+/// 64-bit FNV-1a of the content. Not collision-proof against crafted input, but
+/// together with the size ample for telling files in one tree apart.
+pub fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+}
+
+/// Content hash and size of one version of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Digest {
+    pub hash: u64,
+    pub size: u64,
+}
+
+impl Digest {
+    pub fn of(data: &[u8]) -> Self {
+        Self { hash: content_hash(data), size: data.len() as u64 }
+    }
+}
+
+/// Hash of every setting that affects the output, plus the crate version so an
+/// upgrade re-processes everything
+pub fn config_fingerprint(config: &ProcessingConfig) -> u64 {
+    let relevant = ProcessingConfig {
+        dry_run: false,
+        backup: false,
+        jobs: None,
+        low_priority: false,
+        ..config.clone()
+    };
+    content_hash(format!("{} {:?}", env!("CARGO_PKG_VERSION"), relevant).as_bytes())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    source: Digest,
+    output: Digest,
+    fingerprint: u64,
+}
+
+/// Processed-file cache backed by a plain text file, one entry per line
+#[derive(Debug, Default)]
+pub struct ProcessedCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+    dirty: bool,
+}
+
+impl ProcessedCache {
+    /// Load the cache at `path`; a missing file gives an empty cache and
+    /// unreadable lines are dropped
+    pub fn load(path: &Path) -> Result<Self, ProcessingError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ProcessingError::ReadFile { path: path.to_path_buf(), source: e }),
+        };
+        let entries = text.lines().filter_map(parse_line).collect();
+        Ok(Self { path: path.to_path_buf(), entries, dirty: false })
+    }
+
+    /// True when a file with `digest`, about to be processed into `output`, was
+    /// already processed with the settings behind `fingerprint`
+    pub fn is_processed(&self, output: &Path, digest: Digest, fingerprint: u64) -> bool {
+        self.entries.get(output).is_some_and(|entry| {
+            entry.fingerprint == fingerprint && (digest == entry.source || digest == entry.output) && output.exists()
+        })
+    }
+
+    /// Remember that `source` was processed into `written` at `output`
+    pub fn record(&mut self, output: &Path, source: Digest, written: Digest, fingerprint: u64) {
+        let entry = Entry { source, output: written, fingerprint };
+        if self.entries.insert(output.to_path_buf(), entry) != Some(entry) {
+            self.dirty = true;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the cache back if anything was recorded
+    pub fn save(&self) -> Result<(), ProcessingError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut text = String::new();
+        for (path, entry) in entries {
+            text.push_str(&format!(
+                "{:016x} {} {:016x} {} {:016x} {}\n",
+                entry.source.hash,
+                entry.source.size,
+                entry.output.hash,
+                entry.output.size,
+                entry.fingerprint,
+                path.display()
+            ));
+        }
+        fs::write(&self.path, text).map_err(|e| ProcessingError::WriteFile { path: self.path.clone(), source: e })
+    }
+}
+
+/// `source-hash source-size output-hash output-size fingerprint path`; the path
+/// is last so it may contain spaces
+fn parse_line(line: &str) -> Option<(PathBuf, Entry)> {
+    let mut fields = line.splitn(6, ' ');
+    let mut digest = || {
+        let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+        Some(Digest { hash, size: fields.next()?.parse().ok()? })
+    };
+    let source = digest()?;
+    let output = digest()?;
+    let fingerprint = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let path = PathBuf::from(fields.next().filter(|path| !path.is_empty())?);
+    Some((path, Entry { source, output, fingerprint }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ip_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("my photo.png");
+        fs::write(&output, b"small").unwrap();
+        let cache_path = dir.join(CACHE_FILE_NAME);
+
+        let config = ProcessingConfig::default();
+        let fingerprint = config_fingerprint(&config);
+        let mut cache = ProcessedCache::load(&cache_path).unwrap();
+        assert!(cache.is_empty());
+        cache.record(&output, Digest::of(b"original"), Digest::of(b"small"), fingerprint);
+        cache.save().unwrap();
+
+        let cache = ProcessedCache::load(&cache_path).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.is_processed(&output, Digest::of(b"original"), fingerprint));
+        assert!(cache.is_processed(&output, Digest::of(b"small"), fingerprint));
+        assert!(!cache.is_processed(&output, Digest::of(b"edited"), fingerprint));
+        let other = ProcessingConfig { quality: 50, ..ProcessingConfig::default() };
+        assert!(!cache.is_processed(&output, Digest::of(b"small"), config_fingerprint(&other)));
+        // Dry-run, backup and the thread count do not change the output
+        let backup = ProcessingConfig { backup: true, jobs: Some(2), ..ProcessingConfig::default() };
+        assert_eq!(config_fingerprint(&backup), fingerprint);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
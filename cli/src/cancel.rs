@@ -0,0 +1,38 @@
+//! Cooperative cancellation of batch runs. A [`CancelToken`] is shared between
+//! whoever decides to stop (the CLI's Ctrl+C handler, a server shutting down)
+//! and the [`Pipeline`](crate::pipeline::Pipeline), which checks it before
+//! starting each file. Work already running finishes; ffmpeg runs are killed
+//! separately with `ffmpeg::cancel_all`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::ProcessingError;
+
+/// Cheap to clone; every clone sees the same cancellation
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every holder of the token to stop. Cannot be undone.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// [`ProcessingError::Cancelled`] once cancelled
+    pub fn check(&self) -> Result<(), ProcessingError> {
+        if self.is_cancelled() {
+            Err(ProcessingError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::str::FromStr;
+
+use crate::config::{ProcessingConfig, StripMode};
+use crate::converter::{convert_image, transcode_image};
+use crate::error::ProcessingError;
+use crate::format::Format;
+#[cfg(feature = "avif")]
+use crate::processor::avif::AvifProcessor;
+#[cfg(feature = "gif")]
+use crate::processor::gif::{strip_gif_metadata, GifProcessor};
+use crate::processor::jpeg::{strip_jpeg_metadata, JpegProcessor};
+#[cfg(feature = "mp3")]
+use crate::processor::mp3::Mp3Processor;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4::{strip_mp4_stream, Mp4Processor};
+use crate::processor::png::{optimize_lossless, quantize_png, strip_png_metadata, PngProcessor};
+use crate::processor::ogg::{strip_ogg_metadata, OggProcessor};
+use crate::processor::wav::{strip_wav_metadata, WavProcessor};
+#[cfg(feature = "webp")]
+use crate::processor::webp::{strip_webp_metadata, WebpProcessor};
+use crate::processor::ImageProcessor;
+use crate::progress::Progress;
+
+/// One step of a processing chain. A step may change the format (e.g. convert),
+/// so it returns the bytes together with their new format.
+pub trait Step: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError>;
+}
+
+/// Ordered list of steps run one after another for a format.
+///
+/// ```
+/// use image_preparer::chain::{Chain, ConvertStep, StripStep};
+/// use image_preparer::Format;
+///
+/// let chain = Chain::new().then(ConvertStep(Format::Webp)).then(StripStep);
+/// assert_eq!(chain.step_names(), ["convert-webp", "strip"]);
+/// ```
+#[derive(Default)]
+pub struct Chain {
+    steps: Vec<Box<dyn Step>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a step.
+    pub fn then(mut self, step: impl Step + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Append an already boxed step.
+    pub fn push(&mut self, step: Box<dyn Step>) {
+        self.steps.push(step);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn step_names(&self) -> Vec<&str> {
+        self.steps.iter().map(|s| s.name()).collect()
+    }
+
+    /// Build a chain from a comma-separated spec such as `"quantize,optimize"`
+    /// or `"convert-webp,strip"`, so the order can come from configuration.
+    pub fn parse(spec: &str) -> Result<Self, ProcessingError> {
+        let mut chain = Chain::new();
+        for name in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            chain.push(name.parse::<StepKind>()?.into_step());
+        }
+        Ok(chain)
+    }
+
+    /// Run every step in order, feeding each step's output into the next.
+    pub fn run(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        let mut data = input.to_vec();
+        let mut format = format;
+        for step in &self.steps {
+            log::debug!("Chain step '{}' on {}", step.name(), format.as_str());
+            let (next, next_format) = step.apply(&data, format, config, progress)?;
+            data = next;
+            format = next_format;
+        }
+        Ok((data, format))
+    }
+}
+
+/// Built-in steps that can be named in a chain spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    /// The format's full default processor
+    Compress,
+    /// PNG palette quantization (imagequant)
+    Quantize,
+    /// PNG lossless re-compression (oxipng)
+    Optimize,
+    /// Metadata strip only, for any format
+    Strip,
+    /// Convert to another image format
+    Convert(Format),
+    /// Convert to another image format and run its optimizer on the one decode
+    Transcode(Format),
+}
+
+impl FromStr for StepKind {
+    type Err = ProcessingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "compress" => Ok(StepKind::Compress),
+            "quantize" => Ok(StepKind::Quantize),
+            "optimize" => Ok(StepKind::Optimize),
+            "strip" => Ok(StepKind::Strip),
+            other => {
+                let (target, step): (_, fn(Format) -> StepKind) = if let Some(target) = other.strip_prefix("convert-") {
+                    (target, StepKind::Convert)
+                } else if let Some(target) = other.strip_prefix("transcode-") {
+                    (target, StepKind::Transcode)
+                } else {
+                    return Err(ProcessingError::InvalidConfig(format!("unknown chain step: {}", s)));
+                };
+                match target.parse::<Format>()? {
+                    format if format.is_image() => Ok(step(format)),
+                    format => Err(ProcessingError::UnsupportedFormat(format!(
+                        "cannot convert to {}",
+                        format.as_str()
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+impl StepKind {
+    pub fn into_step(self) -> Box<dyn Step> {
+        match self {
+            StepKind::Compress => Box::new(CompressStep),
+            StepKind::Quantize => Box::new(QuantizeStep),
+            StepKind::Optimize => Box::new(OptimizeStep),
+            StepKind::Strip => Box::new(StripStep),
+            StepKind::Convert(target) => Box::new(ConvertStep(target)),
+            StepKind::Transcode(target) => Box::new(TranscodeStep(target)),
+        }
+    }
+}
+
+/// Runs the default processor for the current format
+pub struct CompressStep;
+
+impl Step for CompressStep {
+    fn name(&self) -> &str {
+        "compress"
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        let processor: &dyn ImageProcessor = match format {
+            Format::Png => &PngProcessor,
+            Format::Jpeg => &JpegProcessor,
+            #[cfg(feature = "mp3")]
+            Format::Mp3 => &Mp3Processor,
+            #[cfg(feature = "webp")]
+            Format::Webp => &WebpProcessor,
+            #[cfg(feature = "avif")]
+            Format::Avif => &AvifProcessor,
+            #[cfg(feature = "gif")]
+            Format::Gif => &GifProcessor,
+            #[cfg(feature = "mp4")]
+            Format::Mp4 => &Mp4Processor,
+            Format::Wav => &WavProcessor,
+            Format::Opus | Format::Ogg => &OggProcessor,
+            _ => return Err(ProcessingError::UnsupportedFormat(format.as_str().to_string())),
+        };
+        Ok((processor.process_with_progress(input, config, progress)?, format))
+    }
+}
+
+/// Wraps any [`ImageProcessor`] as a format-preserving step
+pub struct ProcessorStep(pub Box<dyn ImageProcessor>);
+
+impl Step for ProcessorStep {
+    fn name(&self) -> &str {
+        "processor"
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        if !self.0.supported_formats().contains(&format) {
+            return Err(ProcessingError::UnsupportedFormat(format.as_str().to_string()));
+        }
+        Ok((self.0.process_with_progress(input, config, progress)?, format))
+    }
+}
+
+/// PNG palette quantization without the final oxipng pass
+pub struct QuantizeStep;
+
+impl Step for QuantizeStep {
+    fn name(&self) -> &str {
+        "quantize"
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        require_format(self, format, Format::Png)?;
+        Ok((quantize_png(input, config, progress)?, format))
+    }
+}
+
+/// PNG lossless re-compression + strip via oxipng
+pub struct OptimizeStep;
+
+impl Step for OptimizeStep {
+    fn name(&self) -> &str {
+        "optimize"
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        require_format(self, format, Format::Png)?;
+        Ok((optimize_lossless(input, config, progress)?, format))
+    }
+}
+
+/// Metadata strip honouring `config.strip`, without touching the payload
+pub struct StripStep;
+
+impl Step for StripStep {
+    fn name(&self) -> &str {
+        "strip"
+    }
+
+    #[cfg_attr(not(feature = "mp3"), allow(unused_variables))]
+    fn apply(
+        &self,
+        input: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        if config.strip == StripMode::None {
+            return Ok((input.to_vec(), format));
+        }
+
+        let output = match format {
+            Format::Png => strip_png_metadata(input, config.strip, &config.png.keep_chunks)?,
+            Format::Jpeg => strip_jpeg_metadata(input, config.strip)?,
+            #[cfg(feature = "webp")]
+            Format::Webp => strip_webp_metadata(input, config.strip)?,
+            #[cfg(feature = "gif")]
+            Format::Gif => strip_gif_metadata(input, config.strip)?,
+            #[cfg(feature = "mp3")]
+            Format::Mp3 => Mp3Processor.process_with_progress(input, config, progress)?,
+            #[cfg(feature = "mp4")]
+            Format::Mp4 => {
+                let mut output = Vec::with_capacity(input.len());
+                strip_mp4_stream(&mut &input[..], &mut output, config.strip)?;
+                output
+            }
+            Format::Wav => strip_wav_metadata(input, config.strip, config.wav.broadcast)?,
+            Format::Opus | Format::Ogg => strip_ogg_metadata(input, config.strip)?,
+            _ => return Err(ProcessingError::UnsupportedFormat(format.as_str().to_string())),
+        };
+        Ok((output, format))
+    }
+}
+
+/// Converts to another image format
+pub struct ConvertStep(pub Format);
+
+impl Step for ConvertStep {
+    fn name(&self) -> &str {
+        match self.0 {
+            Format::Png => "convert-png",
+            Format::Jpeg => "convert-jpg",
+            Format::Webp => "convert-webp",
+            _ => "convert",
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        _format: Format,
+        config: &ProcessingConfig,
+        _progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        Ok((convert_image(input, self.0, config)?, self.0))
+    }
+}
+
+/// Converts to another image format and optimizes the result in the same pass:
+/// one decode, one lossy encode (see [`transcode_image`])
+pub struct TranscodeStep(pub Format);
+
+impl Step for TranscodeStep {
+    fn name(&self) -> &str {
+        match self.0 {
+            Format::Png => "transcode-png",
+            Format::Jpeg => "transcode-jpg",
+            Format::Webp => "transcode-webp",
+            _ => "transcode",
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &[u8],
+        _format: Format,
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        let (output, _) = transcode_image(input, self.0, config, progress)?;
+        Ok((output, self.0))
+    }
+}
+
+/// Built-in convert-and-optimize chains for `compress --profile`: inputs of
+/// [`ChainProfile::source`] become optimized [`ChainProfile::target`] files,
+/// other inputs are compressed as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ChainProfile {
+    /// PNG to quantized/optimized WebP
+    PngToWebp,
+    /// PNG to JPEG, for photos saved as PNG
+    PngToJpg,
+    /// JPEG to WebP
+    JpgToWebp,
+}
+
+impl ChainProfile {
+    pub fn source(self) -> Format {
+        match self {
+            ChainProfile::PngToWebp | ChainProfile::PngToJpg => Format::Png,
+            ChainProfile::JpgToWebp => Format::Jpeg,
+        }
+    }
+
+    pub fn target(self) -> Format {
+        match self {
+            ChainProfile::PngToWebp | ChainProfile::JpgToWebp => Format::Webp,
+            ChainProfile::PngToJpg => Format::Jpeg,
+        }
+    }
+
+    pub fn chain(self) -> Chain {
+        Chain::new().then(TranscodeStep(self.target()))
+    }
+}
+
+fn require_format(step: &dyn Step, actual: Format, expected: Format) -> Result<(), ProcessingError> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ProcessingError::UnsupportedFormat(format!(
+            "step '{}' needs {}, got {}",
+            step.name(),
+            expected.as_str(),
+            actual.as_str()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "webp")]
+    fn test_transcode_profile() {
+        let image = image::RgbaImage::from_fn(32, 32, |x, y| image::Rgba([(x * 8) as u8, (y * 8) as u8, 90, 255]));
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        let chain = ChainProfile::PngToWebp.chain();
+        assert_eq!(chain.step_names(), ["transcode-webp"]);
+        let (webp, format) = chain.run(&png, Format::Png, &ProcessingConfig::default(), &Progress::none()).unwrap();
+        assert_eq!(format, Format::Webp);
+        assert_eq!(Format::from_bytes(&webp), Some(Format::Webp));
+
+        assert_eq!("transcode-jpg".parse::<StepKind>().unwrap(), StepKind::Transcode(Format::Jpeg));
+        assert!("transcode-mp3".parse::<StepKind>().is_err());
+    }
+
+    #[test]
+    fn test_strip_and_compress_steps() {
+        let image = image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([(x * 16) as u8, (y * 16) as u8, 40]));
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let text = [("Comment".to_string(), "draft".to_string())];
+        let tagged = crate::processor::png::with_text_chunks(&png, &text, &[]).unwrap();
+        let config = ProcessingConfig::default();
+
+        // Strip drops the chunk and leaves the compressed image data as it was
+        let (stripped, _) = StripStep.apply(&tagged, Format::Png, &config, &Progress::none()).unwrap();
+        assert_eq!(stripped, png);
+        // There is no AVIF metadata stripper
+        let avif = StripStep.apply(&png, Format::Avif, &config, &Progress::none());
+        assert!(matches!(avif, Err(ProcessingError::UnsupportedFormat(_))));
+
+        let chain = Chain::parse("convert-jpg,strip,compress").unwrap();
+        let (jpeg, format) = chain.run(&png, Format::Png, &config, &Progress::none()).unwrap();
+        assert_eq!((format, Format::from_bytes(&jpeg)), (Format::Jpeg, Some(Format::Jpeg)));
+    }
+}
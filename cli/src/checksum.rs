@@ -0,0 +1,84 @@
+//! SHA-256 checksums of outputs (and optionally inputs), so deployment
+//! pipelines can verify what they ship and see which assets actually changed.
+//!
+//! `--checksums FILE` writes them in the format `sha256sum` reads, one output
+//! per line. Paths under the file's directory are written relative to it, so
+//! `cd` there and run `sha256sum -c FILE` to verify:
+//!
+//! ```text
+//! 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  dist/logo.png
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::ProcessingError;
+
+/// Lowercase hex SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checksum list collected over a batch run and written once at the end
+#[derive(Debug)]
+pub struct ChecksumList {
+    path: PathBuf,
+    /// Absolute directory of the list; paths below it are written relative to it
+    base: Option<PathBuf>,
+    /// Hex SHA-256 by output path
+    entries: BTreeMap<String, String>,
+}
+
+impl ChecksumList {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let base = std::path::absolute(&path).ok().and_then(|path| path.parent().map(Path::to_path_buf));
+        Self { path, base, entries: BTreeMap::new() }
+    }
+
+    /// Record the checksum of `output`, whose contents are `sha256` (see [`sha256_hex`])
+    pub fn add(&mut self, output: &Path, sha256: String) {
+        let relative = self.base.as_deref().and_then(|base| {
+            let absolute = std::path::absolute(output).ok()?;
+            absolute.strip_prefix(base).ok().map(Path::to_path_buf)
+        });
+        let output = relative.as_deref().unwrap_or(output);
+        self.entries.insert(output.to_string_lossy().replace('\\', "/"), sha256);
+    }
+
+    /// `sha256sum` lines, sorted by path
+    pub fn to_text(&self) -> String {
+        self.entries.iter().map(|(path, sha256)| format!("{}  {}\n", sha256, path)).collect()
+    }
+
+    /// Write the list, replacing any previous one
+    pub fn save(&self) -> Result<(), ProcessingError> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|source| ProcessingError::WriteFile { path: dir.to_path_buf(), source })?;
+        }
+        std::fs::write(&self.path, self.to_text())
+            .map_err(|source| ProcessingError::WriteFile { path: self.path.clone(), source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_list() {
+        let hash = sha256_hex(b"abc");
+        assert_eq!(hash, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        let dir = std::env::temp_dir().join("ip_checksums");
+        let mut list = ChecksumList::new(dir.join("SHA256SUMS"));
+        list.add(&dir.join("dist/b.png"), sha256_hex(b""));
+        list.add(&dir.join("dist/a.png"), hash.clone());
+        let text = list.to_text();
+        assert_eq!(text.lines().next(), Some(format!("{}  dist/a.png", hash).as_str()));
+        assert_eq!(text.lines().count(), 2);
+    }
+}
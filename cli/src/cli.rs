@@ -1,8 +1,24 @@
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, Parser, Subcommand};
+use image::Rgba;
 
-use crate::config::{ProcessingConfig, StripMode};
+use crate::chain::ChainProfile;
+use crate::comparison::ComparisonStyle;
+use crate::config::{
+    parse_timestamp, AudioTrack, CoverArt, CoverFormat, HwAccel, Mp3Options, Mp4Options, PngFilter, PngOptions,
+    ProcessingConfig, SilenceTrim, StripMode, TimeRange, VideoCodec, WavOptions,
+};
+use crate::format::{parse_extension_mapping, Format};
+use crate::io::{CollisionPolicy, DateTemplate, FileOrder, RetryPolicy};
+use crate::limits::Limits;
+use crate::report::ReportFormat;
+use crate::responsive::ResponsiveOptions;
+use crate::transform::{Filter, Fit, ResizeOptions, Target};
+use crate::waveform;
 
 /// CLI tool for image/video compression, conversion, and metadata management
 #[derive(Debug, Parser)]
@@ -14,8 +30,96 @@ pub struct Cli {
     /// Verbose output
     #[arg(short, long, global = true)]
     pub verbose: bool,
+
+    /// ffmpeg binary to use (default: $IP_FFMPEG, then `ffmpeg` on PATH)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub ffmpeg_path: Option<PathBuf>,
+
+    /// Worker threads for batch runs (default: sized to the mix of image and video files)
+    #[arg(short = 'j', long, global = true, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Run at low CPU priority (and ffmpeg with it) so a big batch leaves the desktop usable
+    #[arg(long, global = true)]
+    pub nice: bool,
+
+    /// Most ffmpeg processes running at once, whatever the thread count (0 = no cap)
+    #[arg(long, global = true, value_name = "N", default_value_t = 2)]
+    pub ffmpeg_jobs: usize,
+
+    /// Kill an ffmpeg run that takes longer than this many seconds
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub ffmpeg_timeout: Option<u64>,
+
+    /// Cap on file data held in memory by parallel jobs, e.g. 2G or 512M; large files wait their turn
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub max_memory: Option<u64>,
+
+    /// Order files are scheduled in batch runs; largest first keeps all threads busy until the end
+    #[arg(long, global = true, value_enum, default_value_t = FileOrder::Size)]
+    pub order: FileOrder,
+
+    /// Write a JSON manifest mapping each source to its output, content hash, sizes and dimensions
+    #[arg(long, global = true, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// Write the SHA-256 of every output in `sha256sum` format, for verifying deployed files
+    #[arg(long, global = true, value_name = "FILE")]
+    pub checksums: Option<PathBuf>,
+
+    /// Also hash each input, recorded in sidecars and reports next to the output's checksum
+    #[arg(long, global = true)]
+    pub checksum_input: bool,
+
+    /// Print the per-file results (path, sizes, savings, status, error, duration) in this format
+    /// instead of the summary, for CI pipelines
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    pub report_format: Option<ReportFormat>,
+
+    /// Write the per-file results to this file as well as printing the summary; the format
+    /// follows --report-format, else the extension (.json, .csv, .md), else JSON
+    #[arg(long, global = true, value_name = "FILE")]
+    pub report_file: Option<PathBuf>,
+
+    /// What to do when several inputs would be written to the same output, e.g. photo.jpg and
+    /// photo.jpeg converted to PNG: stop before processing, or add -1, -2, ... to the names
+    #[arg(long, global = true, value_enum, value_name = "POLICY", default_value_t = CollisionPolicy::Error)]
+    pub on_collision: CollisionPolicy,
+
+    /// Walk directories without skipping paths matched by .gitignore or .ipignore files
+    #[arg(long, global = true)]
+    pub no_ignore: bool,
+
+    /// Treat files with another extension as a known format, e.g. 'jfif=jpg' or 'pngx=png' (repeatable)
+    #[arg(long, global = true, value_name = "EXT=FORMAT", value_parser = parse_extension_mapping)]
+    pub map_extension: Vec<(String, Format)>,
+
+    /// Put outputs in date directories under the output directory, e.g. '{year}/{month}', dated by
+    /// the EXIF capture date or else the file's modification time. Same-named files from different
+    /// directories collide there; see --on-collision
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    pub organize_by_date: Option<DateTemplate>,
+
+    /// Plain output without colors (also when NO_COLOR is set or output is not a terminal)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Retries for reads and writes that fail transiently (timeouts, network shares, sync locks)
+    #[arg(long, global = true, value_name = "N", default_value_t = RetryPolicy::default().retries)]
+    pub retries: u32,
+
+    /// Settings file to use instead of image-preparer.toml in the working directory; flags given
+    /// on the command line override it
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Ignore image-preparer.toml in the working directory
+    #[arg(long, global = true, conflicts_with = "config")]
+    pub no_config: bool,
 }
 
+// Parsed once per run, so the size of the Compress variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Compress images or videos
@@ -38,10 +142,20 @@ pub enum Command {
         #[arg(long)]
         no_lossy: bool,
 
+        /// Pick the lowest quality per image whose SSIM reaches this (0–1, e.g. 0.97); overrides -q for
+        /// PNG, WebP and AVIF (compress keeps JPEGs at their own quality)
+        #[arg(long, value_name = "SSIM", value_parser = parse_ssim, conflicts_with = "no_lossy")]
+        target_ssim: Option<f64>,
+
         /// Metadata strip mode
         #[arg(long, value_enum, default_value_t = StripMode::All)]
         strip: StripMode,
 
+        /// Keep the stored pixel orientation instead of rotating JPEGs upright per their EXIF
+        /// orientation when re-encoding or removing the tag
+        #[arg(long)]
+        no_auto_orient: bool,
+
         /// Process directories recursively
         #[arg(short, long)]
         recursive: bool,
@@ -53,9 +167,151 @@ pub enum Command {
         /// Show what would be done without writing files
         #[arg(long)]
         dry_run: bool,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        /// Process every file, without reading or updating the cache of files already processed with the
+        /// same settings
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Cache of processed files, by content hash and settings (default: .image_preparer_cache in the
+        /// input directory, or next to a single input file)
+        #[arg(long, alias = "cache-file", value_name = "FILE", conflicts_with = "no_cache")]
+        cache_path: Option<PathBuf>,
+
+        /// The cache is on by default; kept so existing scripts still parse
+        #[arg(long, hide = true)]
+        skip_processed: bool,
+
+        /// Embed a marker with the settings fingerprint in written PNG, MP3 and MP4 files
+        #[arg(long)]
+        mark: bool,
+
+        /// Process files even if a marker or the cache says they are done
+        #[arg(long)]
+        force: bool,
+
+        /// Write an original-vs-processed PNG per changed image into this directory for review; with
+        /// --dry-run the images are still processed, but only the comparisons are written
+        #[arg(long, value_name = "DIR")]
+        save_comparison: Option<PathBuf>,
+
+        /// Layout of the --save-comparison images (default: side-by-side)
+        #[arg(long, value_enum, requires = "save_comparison")]
+        comparison_style: Option<ComparisonStyle>,
+
+        /// Write {output}.report.json next to each output with sizes, what was done, warnings and settings
+        #[arg(long)]
+        sidecar: bool,
+
+        /// List files with identical contents (and symlinks to them) before processing
+        #[arg(long)]
+        find_duplicates: bool,
+
+        /// Process one copy of each set of identical files and hardlink (or copy) its output to the
+        /// other copies' output paths; implies --find-duplicates
+        #[arg(long)]
+        dedupe_exact: bool,
+
+        /// Convert and optimize in one pass: e.g. png-to-webp turns PNGs into optimized WebPs (written next
+        /// to the originals, or under the output directory) while other files are compressed as usual
+        #[arg(long, value_enum, value_name = "PROFILE")]
+        profile: Option<ChainProfile>,
+
+        #[command(flatten)]
+        png: PngArgs,
+
+        #[command(flatten)]
+        mp3: Mp3Args,
+
+        #[command(flatten)]
+        mp4: Mp4Args,
+
+        #[command(flatten)]
+        wav: WavArgs,
+
+        #[command(flatten)]
+        silence: SilenceArgs,
+    },
+
+    /// Show what compress would do to each file (processor, settings, ffmpeg, metadata stripped)
+    /// without writing anything
+    Explain {
+        /// Input file or directory
+        input: PathBuf,
+
+        /// Output file or directory compress would write to (default: in-place)
+        output: Option<PathBuf>,
+
+        /// Quantization quality 0–100
+        #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
+        quality: u8,
+
+        /// Speed vs quality: 1 (slowest/best) to 10 (fastest/worst)
+        #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..=10))]
+        speed: i32,
+
+        /// Skip lossy compression — only lossless optimization + strip metadata
+        #[arg(long)]
+        no_lossy: bool,
+
+        /// Pick the lowest quality per image whose SSIM reaches this (0–1); overrides -q for PNG, WebP
+        /// and AVIF
+        #[arg(long, value_name = "SSIM", value_parser = parse_ssim, conflicts_with = "no_lossy")]
+        target_ssim: Option<f64>,
+
+        /// Metadata strip mode
+        #[arg(long, value_enum, default_value_t = StripMode::All)]
+        strip: StripMode,
+
+        /// Keep the stored pixel orientation instead of rotating JPEGs upright per their EXIF
+        /// orientation when re-encoding or removing the tag
+        #[arg(long)]
+        no_auto_orient: bool,
+
+        /// Process directories recursively
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Leave out the cache: report files it would skip as processed
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Cache file, as for compress (default: .image_preparer_cache in the input directory)
+        #[arg(long, alias = "cache-file", value_name = "FILE", conflicts_with = "no_cache")]
+        cache_path: Option<PathBuf>,
+
+        /// The cache is on by default; kept so existing scripts still parse
+        #[arg(long, hide = true)]
+        skip_processed: bool,
+
+        /// Explain as if compress ran with --force: markers and the cache are ignored
+        #[arg(long)]
+        force: bool,
+
+        /// Conversion profile, as for compress
+        #[arg(long, value_enum, value_name = "PROFILE")]
+        profile: Option<ChainProfile>,
+
+        #[command(flatten)]
+        png: PngArgs,
+
+        #[command(flatten)]
+        mp3: Mp3Args,
+
+        #[command(flatten)]
+        mp4: Mp4Args,
+
+        #[command(flatten)]
+        wav: WavArgs,
+
+        #[command(flatten)]
+        silence: SilenceArgs,
     },
 
-    /// Convert images between formats (PNG, JPG, WebP)
+    /// Convert images between PNG, JPG, WebP and AVIF, videos to animated GIF/WebP, WAV to/from FLAC, or audio to Opus
     Convert {
         /// Input file or directory
         input: PathBuf,
@@ -63,18 +319,111 @@ pub enum Command {
         /// Output file or directory (required for conversion)
         output: Option<PathBuf>,
 
-        /// Target format (png, jpg, jpeg, webp)
-        #[arg(long, short = 't', value_name = "FORMAT", required = true)]
-        to: String,
+        /// Target format (png, jpg, jpeg, webp, avif; gif, webp or webm for MP4 input; flac for WAV, wav for FLAC;
+        /// opus for WAV, FLAC or MP3)
+        #[arg(long, short = 't', value_name = "FORMAT", required_unless_present = "preset")]
+        to: Option<String>,
+
+        /// Generate a set of outputs per image instead of one file (replaces --to)
+        #[arg(long, value_enum)]
+        preset: Option<ConvertPreset>,
+
+        /// Quality for lossy formats (0-100)
+        #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
+        quality: u8,
+
+        /// Use lossless compression where applicable
+        #[arg(long)]
+        no_lossy: bool,
+
+        /// Pick the lowest quality per image whose SSIM reaches this (0–1, e.g. 0.97); overrides -q for
+        /// PNG, JPEG, WebP and AVIF
+        #[arg(long, value_name = "SSIM", value_parser = parse_ssim, conflicts_with = "no_lossy")]
+        target_ssim: Option<f64>,
+
+        /// Tags kept when converting audio (images and video are always stripped)
+        #[arg(long, value_enum, default_value_t = StripMode::All)]
+        strip: StripMode,
+
+        /// Keep the stored pixel orientation instead of rotating images upright per their EXIF orientation
+        #[arg(long)]
+        no_auto_orient: bool,
+
+        /// Process directories recursively
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Create .bak backup before overwriting
+        #[arg(long)]
+        backup: bool,
+
+        /// Frame rate of animations made from video
+        #[arg(id = "animation_fps", long = "fps", default_value_t = 12.0)]
+        fps: f32,
+
+        /// Width of animations made from video, keeping the aspect ratio (default: source width)
+        #[arg(id = "animation_width", long = "width", value_name = "PIXELS")]
+        width: Option<u32>,
+
+        /// Keep only START-END of videos converted to GIF, WebP or WebM, e.g. 00:00:05-00:01:30 or 5-90
+        /// (END optional)
+        #[arg(long, value_name = "START-END")]
+        trim: Option<TimeRange>,
+
+        /// Opus bitrate, e.g. 32k for speech or 96k for music (kbit/s, 6–510)
+        #[arg(id = "opus_bitrate", long = "audio-bitrate", value_name = "BITRATE", default_value = "64k",
+              value_parser = parse_opus_bitrate)]
+        audio_bitrate: u32,
+
+        /// Page of multi-page TIFF inputs to convert, from 1
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        page: u32,
+
+        /// Width of the image to take from ICO inputs, e.g. 32 (default: the largest; `inspect` lists them)
+        #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..=256))]
+        ico_size: Option<u32>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        #[command(flatten)]
+        responsive: ResponsiveArgs,
+    },
+
+    /// Resize images to a width, height, box, longest side or percentage, re-encoded in their own format
+    #[command(group(
+        clap::ArgGroup::new("size").required(true).multiple(true).args(["width", "height", "max", "percent"])
+    ))]
+    Resize {
+        /// Input file or directory
+        input: PathBuf,
+
+        /// Output file or directory (default: overwrite in-place)
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        size: ResizeArgs,
+
+        /// Encode through the format's optimizer (quantization + oxipng for PNG) on the same decode
+        #[arg(long)]
+        then_compress: bool,
 
         /// Quality for lossy formats (0-100)
         #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
         quality: u8,
 
+        /// Speed vs quality: 1 (slowest/best) to 10 (fastest/worst)
+        #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..=10))]
+        speed: i32,
+
         /// Use lossless compression where applicable
         #[arg(long)]
         no_lossy: bool,
 
+        /// Keep the stored pixel orientation instead of rotating images upright per their EXIF orientation
+        #[arg(long)]
+        no_auto_orient: bool,
+
         /// Process directories recursively
         #[arg(short, long)]
         recursive: bool,
@@ -82,6 +431,68 @@ pub enum Command {
         /// Create .bak backup before overwriting
         #[arg(long)]
         backup: bool,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        #[command(flatten)]
+        png: PngArgs,
+    },
+
+    /// Compress (or with --to, convert) files as they appear or change in a directory, until Ctrl+C
+    Watch {
+        /// Directory to watch
+        input: PathBuf,
+
+        /// Output directory (default: overwrite in-place, or next to the input with --to)
+        output: Option<PathBuf>,
+
+        /// Convert to this format instead of compressing (same targets as convert)
+        #[arg(long, short = 't', value_name = "FORMAT")]
+        to: Option<String>,
+
+        /// Quality for lossy formats (0-100)
+        #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
+        quality: u8,
+
+        /// Speed vs quality: 1 (slowest/best) to 10 (fastest/worst)
+        #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..=10))]
+        speed: i32,
+
+        /// Use lossless compression where applicable
+        #[arg(long)]
+        no_lossy: bool,
+
+        /// Metadata strip mode
+        #[arg(long, value_enum, default_value_t = StripMode::All)]
+        strip: StripMode,
+
+        /// Keep the stored pixel orientation instead of rotating JPEGs upright per their EXIF
+        /// orientation when re-encoding or removing the tag
+        #[arg(long)]
+        no_auto_orient: bool,
+
+        /// Watch subdirectories too
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Milliseconds a file must go unchanged before it is processed
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        debounce: u64,
+
+        /// Skip paths matching this gitignore-style pattern, e.g. 'drafts/' or '*.tmp.png' (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+
+        /// Create .bak backup before overwriting
+        #[arg(long)]
+        backup: bool,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+
+        #[command(flatten)]
+        png: PngArgs,
     },
 
     /// Display file metadata without processing
@@ -92,9 +503,14 @@ pub enum Command {
         /// Process directories recursively
         #[arg(short, long)]
         recursive: bool,
+
+        /// One table row per file (format, dimensions/duration, metadata size, sensitive findings)
+        /// instead of the full report; inspect a single file for its details
+        #[arg(long)]
+        summary: bool,
     },
 
-    /// Extract frames from MP4 videos to PNG images
+    /// Extract frames from MP4 videos to PNG, JPEG, WebP or AVIF images
     Extract {
         /// Input MP4 file
         input: PathBuf,
@@ -105,11 +521,173 @@ pub enum Command {
         /// Frames per second to extract (default: 1). Use 0 to extract all frames
         #[arg(long, short = 'f', default_value_t = 1.0)]
         fps: f32,
+
+        /// Extract every Nth frame instead of a fixed rate
+        #[arg(long, value_name = "N", conflicts_with = "fps", value_parser = clap::value_parser!(u32).range(1..))]
+        every: Option<u32>,
+
+        /// One frame per scene; scene-change threshold 0.0–1.0 (0.3 suits most footage)
+        #[arg(
+            long,
+            value_name = "THRESHOLD",
+            conflicts_with_all = ["fps", "every"],
+            value_parser = parse_scene_threshold
+        )]
+        scenes: Option<f32>,
+
+        /// Start of the segment to extract from (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_time)]
+        start: Option<Duration>,
+
+        /// End of the segment to extract from (HH:MM:SS, MM:SS or seconds)
+        #[arg(long, value_parser = parse_time)]
+        end: Option<Duration>,
+
+        /// Image format of the frames (png, jpg, webp, avif; AVIF frames are always encoded as with --optimize)
+        #[arg(long, value_name = "FORMAT", default_value = "png", value_parser = parse_image_format)]
+        frame_format: Format,
+
+        /// Quality 0–100 for JPEG/WebP/AVIF frames, and for PNG frames with --optimize
+        #[arg(long, default_value_t = 85, value_parser = clap::value_parser!(u8).range(0..=100))]
+        frame_quality: u8,
+
+        /// Compress frames in parallel as ffmpeg decodes them instead of saving ffmpeg's raw output
+        #[arg(long)]
+        optimize: bool,
+    },
+
+    /// Render the waveform of an MP3, WAV or FLAC file as an optimized PNG
+    Waveform {
+        /// Input audio file
+        input: PathBuf,
+
+        /// Output PNG (default: input name with .png)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Image width in pixels, one peak per column
+        #[arg(long, default_value_t = 1200, value_parser = clap::value_parser!(u32).range(16..=16384))]
+        width: u32,
+
+        /// Image height in pixels
+        #[arg(long, default_value_t = 200, value_parser = clap::value_parser!(u32).range(16..=4096))]
+        height: u32,
+
+        /// Waveform color, #rrggbb or #rrggbbaa
+        #[arg(long, default_value = "#3fb950", value_parser = parse_color)]
+        color: Rgba<u8>,
+
+        /// Background color (default: transparent)
+        #[arg(long, value_parser = parse_color)]
+        background: Option<Rgba<u8>>,
+    },
+
+    /// Generate favicon.ico, browser/iOS/Android PNG icons and a web app manifest from one logo
+    Favicon {
+        /// Logo (PNG, JPEG, WebP or AVIF), ideally square and at least 512 px
+        input: PathBuf,
+
+        /// Directory the set is written to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+
+        /// Behind the Apple touch and maskable icons; also the manifest's theme color
+        #[arg(long, default_value = "#ffffff", value_parser = parse_color)]
+        background: Rgba<u8>,
+
+        /// App name in site.webmanifest
+        #[arg(long, default_value = "")]
+        name: String,
+
+        /// URL path the icons are served from
+        #[arg(long, default_value = "/")]
+        base_url: String,
+
+        /// Quantization quality 0–100 for the PNG icons
+        #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(0..=100))]
+        quality: u8,
+    },
+
+    /// Read, set or delete the title, artist and other tags of MP3, FLAC, MP4 and PNG files
+    Meta {
+        #[command(subcommand)]
+        action: MetaAction,
+    },
+
+    /// Check ffmpeg, ffprobe, their encoders and the temp directory, and list which operations work
+    Doctor,
+}
+
+/// `meta` operations
+#[derive(Debug, Subcommand)]
+pub enum MetaAction {
+    /// List the file's tags
+    Get {
+        /// Input file
+        input: PathBuf,
+    },
+
+    /// Set tags, replacing earlier values of the same tag
+    Set {
+        /// Input file
+        input: PathBuf,
+
+        /// Output file (default: rewrite the input in place)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[arg(long)]
+        title: Option<String>,
+
+        #[arg(long)]
+        artist: Option<String>,
+
+        #[arg(long)]
+        album: Option<String>,
+
+        /// Year or full date
+        #[arg(long)]
+        year: Option<String>,
+
+        #[arg(long)]
+        genre: Option<String>,
+
+        #[arg(long)]
+        comment: Option<String>,
+
+        /// Track number, optionally with the total: 3 or 3/12
+        #[arg(long)]
+        track: Option<String>,
+
+        /// PNG text chunk, e.g. --png-text Software=GIMP (repeatable)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        png_text: Vec<(String, String)>,
+    },
+
+    /// Delete tags: field names (title, artist, ...) or the format's own keys (TPE2, LABEL, ©too, Software)
+    Delete {
+        /// Input file
+        input: PathBuf,
+
+        #[arg(required = true, value_name = "KEY")]
+        keys: Vec<String>,
+
+        /// Output file (default: rewrite the input in place)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 }
 
 impl Cli {
-    pub fn to_config(&self, cmd_quality: u8, cmd_speed: i32, cmd_no_lossy: bool, cmd_strip: StripMode, cmd_dry_run: bool, cmd_backup: bool) -> ProcessingConfig {
+    pub fn to_config(
+        &self,
+        cmd_quality: u8,
+        cmd_speed: i32,
+        cmd_no_lossy: bool,
+        cmd_strip: StripMode,
+        cmd_dry_run: bool,
+        cmd_backup: bool,
+    ) -> ProcessingConfig {
         ProcessingConfig {
             quality: cmd_quality,
             speed: cmd_speed,
@@ -117,8 +695,737 @@ impl Cli {
             strip: cmd_strip,
             dry_run: cmd_dry_run,
             backup: cmd_backup,
-            extract_frames: false,
-            fps: 0.0,
+            ..ProcessingConfig::default()
+        }
+    }
+}
+
+/// Ids of the subcommand's arguments given on the command line (not defaulted)
+pub fn given_args(matches: &ArgMatches) -> Vec<String> {
+    let Some((_, matches)) = matches.subcommand() else {
+        return Vec::new();
+    };
+    matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// `config` (usually from a config file) with the settings of the `given`
+/// arguments taken from `flags`, the config those arguments built
+pub fn override_given(mut config: ProcessingConfig, flags: &ProcessingConfig, given: &[String]) -> ProcessingConfig {
+    config.dry_run = flags.dry_run;
+    for id in given {
+        match id.as_str() {
+            "quality" => config.quality = flags.quality,
+            "speed" => config.speed = flags.speed,
+            "no_lossy" => config.no_lossy = flags.no_lossy,
+            "strip" => config.strip = flags.strip,
+            "no_auto_orient" => config.auto_orient = flags.auto_orient,
+            "target_ssim" => config.target_ssim = flags.target_ssim,
+            "backup" => config.backup = flags.backup,
+            "max_pixels" => config.limits.max_pixels = flags.limits.max_pixels,
+            "max_decoded_size" => config.limits.max_alloc = flags.limits.max_alloc,
+            "max_input_size" => config.limits.max_input_size = flags.limits.max_input_size,
+            "trim_silence" | "silence_threshold" | "silence_duration" => config.trim_silence = flags.trim_silence,
+            "max_colors" => config.png.max_colors = flags.png.max_colors,
+            "png_preset" => config.png.preset = flags.png.preset,
+            "png_level" => config.png.deflate_level = flags.png.deflate_level,
+            "zopfli" => config.png.zopfli = flags.png.zopfli,
+            "png_filters" => config.png.filters = flags.png.filters.clone(),
+            "interlace" => config.png.interlace = flags.png.interlace,
+            "optimize_alpha" => config.png.optimize_alpha = flags.png.optimize_alpha,
+            "keep_chunks" => config.png.keep_chunks = flags.png.keep_chunks.clone(),
+            "mp3_bitrate" => config.mp3.bitrate = flags.mp3.bitrate,
+            "mp3_vbr" => config.mp3.vbr = flags.mp3.vbr,
+            "keep_cover" | "cover_size" | "cover_format" => config.mp3.cover_art = flags.mp3.cover_art,
+            "replaygain" => config.mp3.replaygain = flags.mp3.replaygain,
+            "id3_padding" => config.mp3.id3_padding = flags.mp3.id3_padding,
+            "video_codec" => config.mp4.codec = flags.mp4.codec,
+            "hwaccel" => config.mp4.hwaccel = flags.mp4.hwaccel,
+            "crf" => config.mp4.crf = flags.mp4.crf,
+            "video_bitrate" => config.mp4.video_bitrate = flags.mp4.video_bitrate,
+            "two_pass" => config.mp4.two_pass = flags.mp4.two_pass,
+            "audio_bitrate" => {
+                config.mp4.audio_bitrate = flags.mp4.audio_bitrate;
+                config.mp3.bitrate = flags.mp3.bitrate;
+            }
+            "no_audio" | "copy_audio" => config.mp4.audio = flags.mp4.audio,
+            "trim" => config.mp4.trim = flags.mp4.trim,
+            "video_scale" => config.mp4.scale = flags.mp4.scale,
+            "video_max_height" => config.mp4.max_height = flags.mp4.max_height,
+            "force_sdr" => config.mp4.force_sdr = flags.mp4.force_sdr,
+            "defragment" => config.mp4.defragment = flags.mp4.defragment,
+            "animation_fps" => config.mp4.animation_fps = flags.mp4.animation_fps,
+            "animation_width" => config.mp4.animation_width = flags.mp4.animation_width,
+            "opus_bitrate" => config.opus.bitrate = flags.opus.bitrate,
+            "sample_rate" => config.wav.sample_rate = flags.wav.sample_rate,
+            "bit_depth" => config.wav.bit_depth = flags.wav.bit_depth,
+            "broadcast" => config.wav.broadcast = flags.wav.broadcast,
+            "page" => config.tiff.page = flags.tiff.page,
+            "ico_size" => config.ico.size = flags.ico.size,
+            // Inputs, outputs and options that are not processing settings
+            _ => {}
+        }
+    }
+    config
+}
+
+/// Multi-output conversions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConvertPreset {
+    /// Each image at several widths as AVIF, WebP and its own format, with a srcset snippet
+    Responsive,
+}
+
+/// Listing written next to each responsive set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Snippet {
+    /// `{name}.srcset.html` with a `<picture>` element
+    Html,
+    /// `{name}.srcset.json` with the variants' paths, types, dimensions and sizes
+    Json,
+}
+
+/// `--preset responsive` settings
+#[derive(Debug, Args)]
+pub struct ResponsiveArgs {
+    /// Widths to generate (default: 480,960,1440,1920); those above the source width are skipped
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        value_delimiter = ',',
+        requires = "preset",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub widths: Vec<u32>,
+
+    /// Formats of every width, preferred first; the last is the `<img>` fallback
+    /// (default: avif,webp plus the source's format, or webp for sources other than PNG and JPEG)
+    #[arg(long, value_name = "FORMAT", value_delimiter = ',', requires = "preset")]
+    pub formats: Vec<Format>,
+
+    /// `sizes` attribute for the snippet, e.g. "(min-width: 800px) 50vw, 100vw" (default: 100vw)
+    #[arg(long, requires = "preset")]
+    pub sizes: Option<String>,
+
+    /// Snippet written next to each set (default: html)
+    #[arg(long, value_enum, requires = "preset")]
+    pub snippet: Option<Snippet>,
+}
+
+impl ResponsiveArgs {
+    pub fn to_options(&self, source: Format) -> ResponsiveOptions {
+        let defaults = ResponsiveOptions::for_source(source);
+        ResponsiveOptions {
+            widths: if self.widths.is_empty() { defaults.widths } else { self.widths.clone() },
+            formats: if self.formats.is_empty() { defaults.formats } else { self.formats.clone() },
+            sizes: self.sizes.clone().unwrap_or(defaults.sizes),
+        }
+    }
+}
+
+/// Target size, fit and filter for `resize`
+#[derive(Debug, Args)]
+pub struct ResizeArgs {
+    /// Width in pixels; with --height, the box filled according to --fit
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+
+    /// Height in pixels
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+
+    /// Longest side in pixels; smaller images keep their size
+    #[arg(long, value_name = "PIXELS", conflicts_with_all = ["width", "height", "percent"],
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub max: Option<u32>,
+
+    /// Scale both sides by this percentage, e.g. 50 or 50%
+    #[arg(long, conflicts_with_all = ["width", "height"], value_parser = parse_percent)]
+    pub percent: Option<f32>,
+
+    /// How --width and --height together are filled
+    #[arg(long, value_enum, default_value_t = Fit::Contain)]
+    pub fit: Fit,
+
+    /// Resampling filter
+    #[arg(long, value_enum, default_value_t = Filter::Lanczos3)]
+    pub filter: Filter,
+}
+
+impl ResizeArgs {
+    pub fn to_options(&self) -> ResizeOptions {
+        let target = match (self.width, self.height, self.max, self.percent) {
+            (Some(width), Some(height), _, _) => Target::Box { width, height },
+            (Some(width), None, _, _) => Target::Width(width),
+            (None, Some(height), _, _) => Target::Height(height),
+            (None, None, Some(max), _) => Target::MaxDimension(max),
+            (None, None, None, percent) => Target::Percent(percent.unwrap_or(100.0)),
+        };
+        ResizeOptions { target, fit: self.fit, filter: self.filter }
+    }
+}
+
+/// Decompression-bomb limits, checked before decoding; unset ones keep the defaults
+#[derive(Debug, Args)]
+pub struct LimitArgs {
+    /// Reject images larger than this many pixels (default: 100000000)
+    #[arg(long, value_name = "N")]
+    pub max_pixels: Option<u64>,
+
+    /// Reject images whose decoded pixels would take more than this, e.g. 1G (default: 512M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub max_decoded_size: Option<u64>,
+
+    /// Reject input files larger than this, e.g. 200M (default: no limit)
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub max_input_size: Option<u64>,
+}
+
+impl LimitArgs {
+    pub fn apply(&self, limits: &mut Limits) {
+        if self.max_pixels.is_some() {
+            limits.max_pixels = self.max_pixels;
+        }
+        if self.max_decoded_size.is_some() {
+            limits.max_alloc = self.max_decoded_size;
+        }
+        if self.max_input_size.is_some() {
+            limits.max_input_size = self.max_input_size;
+        }
+    }
+}
+
+/// PNG quantization and oxipng tuning
+#[derive(Debug, Args)]
+pub struct PngArgs {
+    /// Largest palette for lossy PNG, 2–256
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(2..=256))]
+    pub max_colors: u32,
+
+    /// oxipng preset 0 (fast) to 6 (slowest/smallest)
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=6))]
+    pub png_preset: u8,
+
+    /// libdeflate level 0–12 (default: from the preset)
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=12))]
+    pub png_level: Option<u8>,
+
+    /// Use Zopfli for the PNG pass (much slower, slightly smaller)
+    #[arg(long)]
+    pub zopfli: bool,
+
+    /// Row filters to try, e.g. none,sub,up,average,paeth,minsum,entropy,bigrams,bigent,brute
+    #[arg(long, value_name = "FILTERS", value_delimiter = ',')]
+    pub png_filters: Vec<PngFilter>,
+
+    /// Write Adam7-interlaced PNGs (default: de-interlace)
+    #[arg(long)]
+    pub interlace: bool,
+
+    /// Allow altering the color of fully transparent pixels
+    #[arg(long)]
+    pub optimize_alpha: bool,
+
+    /// PNG chunks to keep regardless of --strip, e.g. iCCP,tEXt
+    #[arg(long, value_name = "CHUNKS", value_delimiter = ',', value_parser = parse_chunk_name)]
+    pub keep_chunks: Vec<[u8; 4]>,
+}
+
+impl PngArgs {
+    pub fn to_options(&self) -> PngOptions {
+        PngOptions {
+            quality: None,
+            max_colors: self.max_colors,
+            preset: self.png_preset,
+            deflate_level: self.png_level,
+            zopfli: self.zopfli,
+            filters: self.png_filters.clone(),
+            interlace: Some(self.interlace),
+            optimize_alpha: self.optimize_alpha,
+            keep_chunks: self.keep_chunks.clone(),
+        }
+    }
+}
+
+/// MP3 re-encoding (off unless a bitrate or VBR is chosen), cover art and loudness tagging
+#[derive(Debug, Args)]
+pub struct Mp3Args {
+    /// Re-encode MP3 audio at this constant bitrate, e.g. 128k or 96 (kbit/s, 8–320); overrides
+    /// --audio-bitrate for MP3
+    #[arg(long, value_name = "KBPS", value_parser = parse_mp3_bitrate, conflicts_with = "no_lossy")]
+    pub mp3_bitrate: Option<u32>,
+
+    /// Re-encode MP3 audio as VBR, with the LAME level mapped from --quality
+    #[arg(long, conflicts_with_all = ["mp3_bitrate", "no_lossy"])]
+    pub mp3_vbr: bool,
+
+    /// With --strip safe, keep MP3 cover art downscaled and recompressed instead of dropping it
+    #[arg(long)]
+    pub keep_cover: bool,
+
+    /// Longest side of kept cover art in pixels
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = 600,
+        value_parser = clap::value_parser!(u32).range(16..=4096),
+        requires = "keep_cover"
+    )]
+    pub cover_size: u32,
+
+    /// Encoding of kept cover art (WebP is smaller but not every player shows it)
+    #[arg(long, value_enum, default_value_t = CoverFormat::Jpeg, requires = "keep_cover")]
+    pub cover_format: CoverFormat,
+
+    /// Measure MP3 loudness (EBU R128, needs ffmpeg) and write ReplayGain TXXX/RVA2 tags
+    #[arg(long)]
+    pub replaygain: bool,
+
+    /// Padding after kept ID3v2 tags in bytes: 0 for the smallest files, e.g. 4096 for faster later tag edits
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(0..=1_048_576))]
+    pub id3_padding: Option<u32>,
+}
+
+impl Mp3Args {
+    /// `audio_bitrate` is the shared `--audio-bitrate`, used for MP3 at up to 320 kbit/s
+    /// unless `--mp3-bitrate` or `--mp3-vbr` says otherwise
+    pub fn to_options(&self, audio_bitrate: Option<u32>) -> Mp3Options {
+        let audio_bitrate = audio_bitrate.filter(|_| !self.mp3_vbr).map(|kbps| kbps.min(320));
+        Mp3Options {
+            bitrate: self.mp3_bitrate.or(audio_bitrate),
+            vbr: self.mp3_vbr,
+            cover_art: self.keep_cover.then_some(CoverArt { max_size: self.cover_size, format: self.cover_format }),
+            replaygain: self.replaygain,
+            id3_padding: self.id3_padding,
+            ..Mp3Options::default()
+        }
+    }
+}
+
+/// MP4 re-encoding overrides
+#[derive(Debug, Args)]
+pub struct Mp4Args {
+    /// Video codec for re-encoding (the local ffmpeg must include its encoder)
+    #[arg(long, value_enum, default_value_t = VideoCodec::H264)]
+    pub video_codec: VideoCodec,
+
+    /// Hardware encoder for MP4; auto picks the first one ffmpeg offers, else software
+    #[arg(long, value_enum, default_value_t = HwAccel::None)]
+    pub hwaccel: HwAccel,
+
+    /// Encoder CRF for MP4 (0–51 for h264/hevc, 0–63 for vp9/av1), overriding --quality
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=63))]
+    pub crf: Option<u8>,
+
+    /// Target MP4 video bitrate instead of CRF, e.g. 2M or 800k
+    #[arg(long, value_name = "BITRATE", value_parser = parse_bitrate, conflicts_with = "crf")]
+    pub video_bitrate: Option<u64>,
+
+    /// Encode twice to hit --video-bitrate more accurately
+    #[arg(long, requires = "video_bitrate")]
+    pub two_pass: bool,
+
+    /// Audio bitrate, e.g. 128k: AAC when re-encoding MP4 (kbit/s, 8–512, default: 128), and turns on
+    /// MP3 re-encoding at that bitrate (capped at 320)
+    #[arg(long, value_name = "KBPS", value_parser = parse_audio_bitrate)]
+    pub audio_bitrate: Option<u32>,
+
+    /// Remove the audio track when re-encoding MP4
+    #[arg(long, conflicts_with = "copy_audio")]
+    pub no_audio: bool,
+
+    /// Keep the original audio stream instead of re-encoding it to AAC
+    #[arg(long)]
+    pub copy_audio: bool,
+
+    /// Keep only START-END of the video, e.g. 00:00:05-00:01:30 or 5-90 (END optional)
+    #[arg(long, value_name = "START-END", conflicts_with = "no_lossy")]
+    pub trim: Option<TimeRange>,
+
+    /// Scale MP4 video to exactly WIDTHxHEIGHT as displayed (after rotation), e.g. 1280x720
+    #[arg(long, value_name = "WxH", value_parser = parse_video_size, conflicts_with = "video_max_height")]
+    pub video_scale: Option<(u32, u32)>,
+
+    /// Downscale MP4 video to at most this height, keeping the aspect ratio
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(2..))]
+    pub video_max_height: Option<u32>,
+
+    /// Tone-map HDR video to SDR when re-encoding with a codec that cannot keep HDR
+    #[arg(long)]
+    pub force_sdr: bool,
+
+    /// With --no-lossy, remux fragmented MP4 (fMP4/DASH) into a regular MP4 via ffmpeg
+    #[arg(long, requires = "no_lossy")]
+    pub defragment: bool,
+}
+
+impl Mp4Args {
+    pub fn to_options(&self) -> Mp4Options {
+        Mp4Options {
+            codec: self.video_codec,
+            hwaccel: self.hwaccel,
+            crf: self.crf,
+            video_bitrate: self.video_bitrate,
+            two_pass: self.two_pass,
+            audio: if self.no_audio {
+                AudioTrack::Drop
+            } else if self.copy_audio {
+                AudioTrack::Copy
+            } else {
+                AudioTrack::Aac
+            },
+            audio_bitrate: self.audio_bitrate.unwrap_or(Mp4Options::default().audio_bitrate),
+            trim: self.trim,
+            scale: self.video_scale,
+            max_height: self.video_max_height,
+            force_sdr: self.force_sdr,
+            defragment: self.defragment,
+            ..Mp4Options::default()
+        }
+    }
+}
+
+/// WAV delivery-copy settings
+#[derive(Debug, Args)]
+pub struct WavArgs {
+    /// Downsample WAV to this rate, e.g. 48000 or 44.1k (never upsamples)
+    #[arg(long, value_name = "HZ", value_parser = parse_sample_rate, conflicts_with = "no_lossy")]
+    pub sample_rate: Option<u32>,
+
+    /// Reduce WAV bit depth to 8, 16 or 24 with triangular dither
+    #[arg(long, value_name = "BITS", value_parser = parse_bit_depth, conflicts_with = "no_lossy")]
+    pub bit_depth: Option<u16>,
+
+    /// Keep Broadcast WAV bext and iXML chunks (origination data) whatever --strip says
+    #[arg(long)]
+    pub broadcast: bool,
+}
+
+impl WavArgs {
+    pub fn to_options(&self) -> WavOptions {
+        WavOptions {
+            sample_rate: self.sample_rate,
+            bit_depth: self.bit_depth,
+            broadcast: self.broadcast,
+        }
+    }
+}
+
+/// Leading/trailing silence removal for WAV and MP3
+#[derive(Debug, Args)]
+pub struct SilenceArgs {
+    /// Cut leading and trailing silence from WAV and MP3 (MP3 needs ffmpeg; cut on frame boundaries)
+    #[arg(long)]
+    pub trim_silence: bool,
+
+    /// Level in dBFS below which audio counts as silence
+    #[arg(long, value_name = "DB", default_value_t = -50.0, allow_negative_numbers = true, requires = "trim_silence")]
+    pub silence_threshold: f32,
+
+    /// Shortest silence in seconds that gets cut
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 0.5,
+        value_parser = parse_silence_duration,
+        requires = "trim_silence"
+    )]
+    pub silence_duration: f64,
+}
+
+impl SilenceArgs {
+    pub fn to_trim(&self) -> Option<SilenceTrim> {
+        self.trim_silence.then(|| SilenceTrim {
+            threshold_db: self.silence_threshold,
+            min_duration: Duration::from_secs_f64(self.silence_duration),
+        })
+    }
+}
+
+fn parse_silence_duration(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(secs),
+        _ => Err(format!("'{}' is not a duration in seconds", s)),
+    }
+}
+
+fn parse_time(s: &str) -> Result<Duration, String> {
+    parse_timestamp(s).ok_or_else(|| format!("invalid time '{}', expected HH:MM:SS, MM:SS or seconds", s))
+}
+
+/// Hz from `48000`, `48k` or `44.1k`
+fn parse_sample_rate(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let hz = match s.strip_suffix(['k', 'K']) {
+        Some(khz) => khz.parse::<f64>().ok().map(|khz| (khz * 1000.0).round() as u32),
+        None => s.parse::<u32>().ok(),
+    };
+    hz.filter(|hz| (8_000..=384_000).contains(hz))
+        .ok_or_else(|| format!("'{}' is not a sample rate between 8k and 384k", s))
+}
+
+fn parse_bit_depth(s: &str) -> Result<u16, String> {
+    match s.trim().parse::<u16>() {
+        Ok(bits @ (8 | 16 | 24)) => Ok(bits),
+        _ => Err(format!("'{}' is not a bit depth (8, 16 or 24)", s)),
+    }
+}
+
+/// kbit/s from `128k` or `128`
+fn parse_mp3_bitrate(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_end_matches(['k', 'K']);
+    match digits.parse::<u32>() {
+        Ok(kbps) if (8..=320).contains(&kbps) => Ok(kbps),
+        _ => Err(format!("'{}' is not an MP3 bitrate between 8k and 320k", s)),
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("'{}' is not KEY=VALUE", s)),
+    }
+}
+
+fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    waveform::parse_color(s).ok_or_else(|| format!("'{}' is not a color, expected #rrggbb or #rrggbbaa", s))
+}
+
+/// kbit/s from `128k` or `128`
+fn parse_audio_bitrate(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_end_matches(['k', 'K']);
+    match digits.parse::<u32>() {
+        Ok(kbps) if (8..=512).contains(&kbps) => Ok(kbps),
+        _ => Err(format!("'{}' is not an audio bitrate between 8k and 512k", s)),
+    }
+}
+
+/// kbit/s from `32k` or `32`
+fn parse_opus_bitrate(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_end_matches(['k', 'K']);
+    match digits.parse::<u32>() {
+        Ok(kbps) if (6..=510).contains(&kbps) => Ok(kbps),
+        _ => Err(format!("'{}' is not an Opus bitrate between 6k and 510k", s)),
+    }
+}
+
+fn parse_scene_threshold(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(t) if (0.0..=1.0).contains(&t) => Ok(t),
+        _ => Err(format!("'{}' is not a threshold between 0.0 and 1.0", s)),
+    }
+}
+
+fn parse_image_format(s: &str) -> Result<Format, String> {
+    s.parse::<Format>()
+        .ok()
+        .filter(Format::is_image)
+        .ok_or_else(|| format!("'{}' is not an image format (png, jpg, webp, avif)", s))
+}
+
+/// Bits per second from `2500000`, `800k` or `2M`
+fn parse_bitrate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last() {
+        Some('k' | 'K') => (&s[..s.len() - 1], 1_000.0),
+        Some('m' | 'M') => (&s[..s.len() - 1], 1_000_000.0),
+        _ => (s, 1.0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| (n * multiplier) as u64)
+        .filter(|&bits| bits > 0)
+        .ok_or_else(|| format!("'{}' is not a bitrate like 2M or 800k", s))
+}
+
+fn parse_ssim(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|ssim| *ssim > 0.0 && *ssim <= 1.0)
+        .ok_or_else(|| format!("'{}' is not an SSIM target between 0 and 1, e.g. 0.97", s))
+}
+
+fn parse_percent(s: &str) -> Result<f32, String> {
+    s.trim_end_matches('%')
+        .parse::<f32>()
+        .ok()
+        .filter(|percent| *percent > 0.0 && percent.is_finite())
+        .ok_or_else(|| format!("'{}' is not a percentage above 0, e.g. 50", s))
+}
+
+/// Bytes from `2G`, `512M`, `64K` (binary units) or a plain number
+fn parse_memory_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last() {
+        Some('k' | 'K') => (&s[..s.len() - 1], 1u64 << 10),
+        Some('m' | 'M') => (&s[..s.len() - 1], 1 << 20),
+        Some('g' | 'G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| (n * multiplier as f64) as u64)
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("'{}' is not a memory size like 2G or 512M", s))
+}
+
+fn parse_video_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+        .filter(|&(w, h): &(u32, u32)| w > 0 && h > 0)
+        .ok_or_else(|| format!("'{}' is not a size like 1280x720", s))
+}
+
+fn parse_chunk_name(s: &str) -> Result<[u8; 4], String> {
+    <[u8; 4]>::try_from(s.as_bytes())
+        .ok()
+        .filter(|name| name.iter().all(u8::is_ascii_alphabetic))
+        .ok_or_else(|| format!("'{}' is not a 4-letter PNG chunk name", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{IcoOptions, TiffOptions};
+    use crate::config_file::ConfigFile;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_override_given() {
+        let file = ConfigFile::parse("quality = 60\n[tiff]\npage = 5\n").unwrap();
+        let args = ["image_preparer", "convert", "scan.tif", "--to", "png", "--page", "2"];
+        let given = given_args(&Cli::command().get_matches_from(args));
+        let flags = ProcessingConfig { tiff: TiffOptions { page: 2 }, ..ProcessingConfig::default() };
+
+        let config = override_given(file.apply_to(&flags).unwrap(), &flags, &given);
+        assert_eq!(config.tiff.page, 2);
+        assert_eq!(config.quality, 60);
+
+        let file = ConfigFile::parse("[ico]\nsize = 256\n").unwrap();
+        let args = ["image_preparer", "convert", "app.ico", "--to", "png", "--ico-size", "32"];
+        let given = given_args(&Cli::command().get_matches_from(args));
+        let flags = ProcessingConfig { ico: IcoOptions { size: Some(32) }, ..ProcessingConfig::default() };
+        assert_eq!(override_given(file.apply_to(&flags).unwrap(), &flags, &given).ico.size, Some(32));
+
+        // Flags not given leave the file's value, given ones win
+        let file = ConfigFile::parse("[limits]\nmax_pixels = 5000\nmax_input_size = 1024\n").unwrap();
+        let args = ["image_preparer", "compress", "in", "--max-pixels", "9000"];
+        let given = given_args(&Cli::command().get_matches_from(args));
+        let mut flags = ProcessingConfig::default();
+        flags.limits.max_pixels = Some(9000);
+        let config = override_given(file.apply_to(&flags).unwrap(), &flags, &given);
+        assert_eq!((config.limits.max_pixels, config.limits.max_input_size), (Some(9000), Some(1024)));
+    }
+
+    #[test]
+    fn test_responsive_formats() {
+        let parse = |extra: &[&str]| {
+            let args = ["image_preparer", "convert", "hero.jpg", "out", "--preset", "responsive"];
+            match Cli::try_parse_from(args.iter().chain(extra)).map(|cli| cli.command) {
+                Ok(Command::Convert { responsive, .. }) => Ok(responsive.to_options(Format::Jpeg).formats),
+                Ok(_) => unreachable!("parsed as convert"),
+                Err(e) => Err(e.kind()),
+            }
+        };
+        assert_eq!(parse(&["--formats", "avif,jpg"]), Ok(vec![Format::Avif, Format::Jpeg]));
+        assert_eq!(parse(&[]), Ok(ResponsiveOptions::for_source(Format::Jpeg).formats));
+        assert_eq!(parse(&["--formats", "bmpx"]), Err(clap::error::ErrorKind::ValueValidation));
+    }
+
+    #[test]
+    fn test_parse_bitrate() {
+        assert_eq!(parse_bitrate("2M"), Ok(2_000_000));
+        assert_eq!(parse_bitrate("1.5m"), Ok(1_500_000));
+        assert_eq!(parse_bitrate(" 800k "), Ok(800_000));
+        assert_eq!(parse_bitrate("96000"), Ok(96_000));
+        for bad in ["", "0", "-1M", "fast", "2G"] {
+            assert!(parse_bitrate(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_convert_trim() {
+        let args = ["image_preparer", "convert", "clip.mp4", "clip.gif", "--to", "gif", "--trim", "5-1:30"];
+        let Command::Convert { trim, .. } = Cli::try_parse_from(args).unwrap().command else {
+            unreachable!("parsed as convert")
+        };
+        assert_eq!(trim, Some("00:00:05-00:01:30".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_audio_bitrate() {
+        let parse = |extra: &[&str]| {
+            let args = ["image_preparer", "compress", "memos"];
+            match Cli::try_parse_from(args.iter().chain(extra)).map(|cli| cli.command) {
+                Ok(Command::Compress { mp3, mp4, .. }) => {
+                    Ok((mp3.to_options(mp4.audio_bitrate).bitrate, mp4.to_options().audio_bitrate))
+                }
+                Ok(_) => unreachable!("parsed as compress"),
+                Err(e) => Err(e.kind()),
+            }
+        };
+        assert_eq!(parse(&[]), Ok((None, 128)));
+        assert_eq!(parse(&["--audio-bitrate", "96k"]), Ok((Some(96), 96)));
+        assert_eq!(parse(&["--audio-bitrate", "384"]), Ok((Some(320), 384)));
+        assert_eq!(parse(&["--audio-bitrate", "96k", "--mp3-bitrate", "64"]), Ok((Some(64), 96)));
+        assert_eq!(parse(&["--audio-bitrate", "96k", "--mp3-vbr"]), Ok((None, 96)));
+        assert_eq!(parse(&["--audio-bitrate", "4k"]), Err(clap::error::ErrorKind::ValueValidation));
+    }
+
+    #[test]
+    fn test_png_args() {
+        let parse = |extra: &[&str]| {
+            let args = ["image_preparer", "compress", "shots"];
+            match Cli::try_parse_from(args.iter().chain(extra)).map(|cli| cli.command) {
+                Ok(Command::Compress { png, .. }) => Ok(png.to_options()),
+                Ok(_) => unreachable!("parsed as compress"),
+                Err(e) => Err(e.kind()),
+            }
+        };
+        let defaults = parse(&[]).unwrap();
+        assert_eq!((defaults.preset, defaults.interlace, defaults.filters.len()), (4, Some(false), 0));
+
+        let flags = ["--png-preset", "6", "--png-filters", "paeth,minsum", "--keep-chunks", "iCCP,tEXt", "--interlace"];
+        let png = parse(&flags).unwrap();
+        assert_eq!(png.preset, 6);
+        assert_eq!(png.filters, [PngFilter::Paeth, PngFilter::MinSum]);
+        assert_eq!(png.keep_chunks, [*b"iCCP", *b"tEXt"]);
+        assert_eq!(png.interlace, Some(true));
+
+        assert_eq!(parse(&["--png-preset", "7"]).unwrap_err(), clap::error::ErrorKind::ValueValidation);
+        assert_eq!(parse(&["--png-level", "13"]).unwrap_err(), clap::error::ErrorKind::ValueValidation);
+        assert_eq!(parse(&["--png-filters", "median"]).unwrap_err(), clap::error::ErrorKind::ValueValidation);
+        assert_eq!(parse(&["--keep-chunks", "iCC"]).unwrap_err(), clap::error::ErrorKind::ValueValidation);
+        assert_eq!(parse(&["--keep-chunks", "iC1P"]).unwrap_err(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_limit_args() {
+        let flags = ["--max-pixels", "1000", "--max-decoded-size", "64M", "--max-input-size", "1G"];
+        let commands: [&[&str]; 4] = [
+            &["compress", "in"],
+            &["convert", "in", "out", "--to", "png"],
+            &["resize", "in", "--max", "9"],
+            &["watch", "in"],
+        ];
+        for command in commands {
+            let cli = Cli::try_parse_from(["image_preparer"].iter().chain(command).chain(&flags)).unwrap();
+            let limits = match cli.command {
+                Command::Compress { limits, .. }
+                | Command::Convert { limits, .. }
+                | Command::Resize { limits, .. }
+                | Command::Watch { limits, .. } => limits,
+                _ => unreachable!("parsed as {:?}", command),
+            };
+            let mut applied = Limits::default();
+            limits.apply(&mut applied);
+            let expected = Limits { max_pixels: Some(1000), max_alloc: Some(64 << 20), max_input_size: Some(1 << 30) };
+            assert_eq!(applied, expected, "{:?}", command);
         }
     }
 }
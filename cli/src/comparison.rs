@@ -0,0 +1,110 @@
+//! Before/after composites for reviewing lossy settings: the original and the
+//! processed image in one PNG, either next to each other or split down the
+//! middle. Both sit on a checkerboard so alpha changes show up too.
+
+use std::io::Cursor;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+use crate::error::ProcessingError;
+use crate::limits::{decode_image, Limits};
+
+/// Gap between the two halves of a side-by-side composite
+const GAP: u32 = 8;
+/// Edge of a checkerboard square
+const CHECKER: u32 = 8;
+const SEPARATOR: Rgba<u8> = Rgba([0x80, 0x80, 0x80, 0xff]);
+
+/// Layout of a comparison image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ComparisonStyle {
+    /// Original on the left, processed on the right, at full size
+    #[default]
+    SideBySide,
+    /// One image: the left half from the original, the right half processed, split by a line
+    Split,
+}
+
+/// PNG composite of `original` and `processed` (both encoded images). A
+/// processed image of other dimensions is scaled to the original's.
+pub fn render_comparison(
+    original: &[u8],
+    processed: &[u8],
+    style: ComparisonStyle,
+    limits: &Limits,
+) -> Result<Vec<u8>, ProcessingError> {
+    let before = decode_image(original, None, limits)?.to_rgba8();
+    let mut after = decode_image(processed, None, limits)?.to_rgba8();
+    let (width, height) = before.dimensions();
+    if after.dimensions() != (width, height) {
+        after = imageops::resize(&after, width, height, FilterType::Lanczos3);
+    }
+
+    let composite = match style {
+        ComparisonStyle::SideBySide => {
+            let mut canvas = RgbaImage::from_pixel(2 * width + GAP, height, SEPARATOR);
+            imageops::replace(&mut canvas, &on_checkerboard(&before), 0, 0);
+            imageops::replace(&mut canvas, &on_checkerboard(&after), i64::from(width + GAP), 0);
+            canvas
+        }
+        ComparisonStyle::Split => {
+            let (before, after) = (on_checkerboard(&before), on_checkerboard(&after));
+            let middle = width / 2;
+            RgbaImage::from_fn(width, height, |x, y| match x {
+                x if x + 1 == middle || x == middle => SEPARATOR,
+                x if x < middle => *before.get_pixel(x, y),
+                x => *after.get_pixel(x, y),
+            })
+        }
+    };
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(composite)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to encode the comparison: {}", e)))?;
+    Ok(png)
+}
+
+/// `image` composited over a light checkerboard
+fn on_checkerboard(image: &RgbaImage) -> RgbaImage {
+    let mut canvas = RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let shade = if (x / CHECKER + y / CHECKER).is_multiple_of(2) { 0xff } else { 0xcc };
+        Rgba([shade, shade, shade, 0xff])
+    });
+    imageops::overlay(&mut canvas, image, 0, 0);
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(image: RgbaImage) -> Vec<u8> {
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_render_comparison() {
+        let original = png(RgbaImage::from_pixel(20, 10, Rgba([255, 0, 0, 255])));
+        let processed = png(RgbaImage::from_pixel(10, 5, Rgba([0, 0, 255, 255])));
+        let limits = Limits::default();
+
+        let side = render_comparison(&original, &processed, ComparisonStyle::SideBySide, &limits).unwrap();
+        let side = image::load_from_memory(&side).unwrap().to_rgba8();
+        assert_eq!(side.dimensions(), (48, 10));
+        assert_eq!(side.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(side.get_pixel(22, 5), &SEPARATOR);
+        assert_eq!(side.get_pixel(47, 9), &Rgba([0, 0, 255, 255]));
+
+        let split = render_comparison(&original, &processed, ComparisonStyle::Split, &limits).unwrap();
+        let split = image::load_from_memory(&split).unwrap().to_rgba8();
+        assert_eq!(split.dimensions(), (20, 10));
+        assert_eq!(split.get_pixel(2, 2), &Rgba([255, 0, 0, 255]));
+        assert_eq!(split.get_pixel(10, 2), &SEPARATOR);
+        assert_eq!(split.get_pixel(15, 2), &Rgba([0, 0, 255, 255]));
+    }
+}
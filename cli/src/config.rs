@@ -1,13 +1,23 @@
-use clap::ValueEnum;
+use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::limits::Limits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum StripMode {
     All,
     Safe,
     None,
 }
 
+/// Settings shared by every format, plus one section per format for the knobs
+/// that only make sense there.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct ProcessingConfig {
     /// Quantization quality 0–100 (lower = smaller file, worse quality)
     pub quality: u8,
@@ -17,14 +27,169 @@ pub struct ProcessingConfig {
     pub no_lossy: bool,
     /// Metadata strip mode
     pub strip: StripMode,
+    /// Rotate/flip the pixels upright per the EXIF orientation before
+    /// re-encoding, since the re-encoded (or stripped) image loses the tag
+    pub auto_orient: bool,
+    /// Search the lossy quality per image for the smallest output whose SSIM
+    /// against the original reaches this (0–1, e.g. 0.97); overrides `quality`
+    /// for PNG, WebP and AVIF, and for JPEG when converting to it (compress
+    /// keeps JPEGs at their own quality)
+    pub target_ssim: Option<f64>,
     /// Dry run — don't write anything
     pub dry_run: bool,
     /// Create .bak backup before overwriting
     pub backup: bool,
-    /// Extract frames from MP4 to PNG
-    pub extract_frames: bool,
-    /// Frames per second to extract (0 = all frames)
-    pub fps: f32,
+    /// Cut leading/trailing silence from WAV and MP3
+    pub trim_silence: Option<SilenceTrim>,
+    /// Worker threads for batch work (`parallel` feature); None leaves the
+    /// pool to the caller: rayon's global one, or in the CLI one sized to the batch
+    pub jobs: Option<usize>,
+    /// Run batch workers, and the ffmpeg processes they start, at low CPU
+    /// priority so a long batch leaves a shared machine usable
+    pub low_priority: bool,
+    /// Decompression-bomb and input size limits
+    pub limits: Limits,
+    /// PNG quantization and oxipng settings
+    pub png: PngOptions,
+    /// JPEG encoder settings
+    pub jpeg: JpegOptions,
+    /// WebP encoder settings
+    pub webp: WebpOptions,
+    /// AVIF encoder settings
+    pub avif: AvifOptions,
+    /// GIF frame quantization settings
+    pub gif: GifOptions,
+    /// MP3 settings
+    pub mp3: Mp3Options,
+    /// MP4 encoder and frame extraction settings
+    pub mp4: Mp4Options,
+    /// WAV delivery settings
+    pub wav: WavOptions,
+    /// Opus encoder settings for audio conversion
+    pub opus: OpusOptions,
+    /// Page selection for multi-page TIFF sources
+    pub tiff: TiffOptions,
+    /// Image selection for ICO sources
+    pub ico: IcoOptions,
+}
+
+impl ProcessingConfig {
+    /// Quality used for `format`: the section's override, else the shared `quality`
+    pub fn quality_for(&self, format: Format) -> u8 {
+        let section = match format {
+            Format::Png => self.png.quality,
+            Format::Jpeg => self.jpeg.quality,
+            Format::Webp => self.webp.quality,
+            Format::Avif => self.avif.quality,
+            Format::Gif => self.gif.quality,
+            // WebM output is re-encoded with the MP4 section's video settings
+            Format::Mp4 | Format::Webm => self.mp4.quality,
+            Format::Mp3 => self.mp3.quality,
+            Format::Wav | Format::Flac | Format::Opus | Format::Ogg => None,
+            // Read only, never encoded
+            Format::Heic | Format::Tiff | Format::Bmp | Format::Ico => None,
+        };
+        section.unwrap_or(self.quality)
+    }
+
+    /// Check every setting is in range; [`PreparerBuilder::build`](crate::PreparerBuilder::build)
+    /// and config files run this
+    pub fn validate(&self) -> Result<(), ProcessingError> {
+        if self.quality > 100 {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "quality must be 0-100, got {}",
+                self.quality
+            )));
+        }
+        if !(1..=10).contains(&self.speed) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "speed must be 1-10, got {}",
+                self.speed
+            )));
+        }
+        for format in Format::ALL {
+            let quality = self.quality_for(format);
+            if quality > 100 {
+                return Err(ProcessingError::InvalidConfig(format!(
+                    "{} quality must be 0-100, got {}",
+                    format.as_str(),
+                    quality
+                )));
+            }
+        }
+        if self.jobs == Some(0) {
+            return Err(ProcessingError::InvalidConfig("jobs must be at least 1".to_string()));
+        }
+        if self.target_ssim.is_some_and(|target| !(target > 0.0 && target <= 1.0)) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "target SSIM must be above 0 and at most 1, got {}",
+                self.target_ssim.unwrap_or_default()
+            )));
+        }
+        if matches!(self.mp4.scale, Some((0, _) | (_, 0))) || self.mp4.max_height == Some(0) {
+            return Err(ProcessingError::InvalidConfig("MP4 scale must be non-zero".to_string()));
+        }
+        if self.mp3.bitrate.is_some_and(|kbps| !(8..=320).contains(&kbps)) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "MP3 bitrate must be 8-320 kbit/s, got {}",
+                self.mp3.bitrate.unwrap_or_default()
+            )));
+        }
+        if self.wav.sample_rate.is_some_and(|rate| !(8_000..=384_000).contains(&rate)) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "WAV sample rate must be 8000-384000 Hz, got {}",
+                self.wav.sample_rate.unwrap_or_default()
+            )));
+        }
+        if self.wav.bit_depth.is_some_and(|bits| ![8, 16, 24].contains(&bits)) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "WAV bit depth must be 8, 16 or 24, got {}",
+                self.wav.bit_depth.unwrap_or_default()
+            )));
+        }
+        if !(6..=510).contains(&self.opus.bitrate) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "Opus bitrate must be 6-510 kbit/s, got {}",
+                self.opus.bitrate
+            )));
+        }
+        if self.tiff.page == 0 {
+            return Err(ProcessingError::InvalidConfig("TIFF pages are numbered from 1".to_string()));
+        }
+        if self.ico.size.is_some_and(|size| !(1..=256).contains(&size)) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "ICO size must be 1-256 pixels, got {}",
+                self.ico.size.unwrap_or_default()
+            )));
+        }
+        if self.mp3.cover_art.is_some_and(|cover| cover.max_size == 0) {
+            return Err(ProcessingError::InvalidConfig("cover art size must be non-zero".to_string()));
+        }
+        if self.trim_silence.is_some_and(|trim| trim.threshold_db.is_nan() || trim.threshold_db > 0.0) {
+            return Err(ProcessingError::InvalidConfig(
+                "silence threshold must be a level in dBFS at or below 0".to_string(),
+            ));
+        }
+        if !(2..=256).contains(&self.png.max_colors) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "PNG max colors must be 2-256, got {}",
+                self.png.max_colors
+            )));
+        }
+        if self.png.preset > 6 {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "PNG preset must be 0-6, got {}",
+                self.png.preset
+            )));
+        }
+        if self.png.deflate_level.is_some_and(|level| level > 12) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "PNG deflate level must be 0-12, got {}",
+                self.png.deflate_level.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl Default for ProcessingConfig {
@@ -34,10 +199,489 @@ impl Default for ProcessingConfig {
             speed: 3,
             no_lossy: false,
             strip: StripMode::All,
+            auto_orient: true,
+            target_ssim: None,
             dry_run: false,
             backup: false,
+            trim_silence: None,
+            jobs: None,
+            low_priority: false,
+            limits: Limits::default(),
+            png: PngOptions::default(),
+            jpeg: JpegOptions::default(),
+            webp: WebpOptions::default(),
+            avif: AvifOptions::default(),
+            gif: GifOptions::default(),
+            mp3: Mp3Options::default(),
+            mp4: Mp4Options::default(),
+            wav: WavOptions::default(),
+            opus: OpusOptions::default(),
+            tiff: TiffOptions::default(),
+            ico: IcoOptions::default(),
+        }
+    }
+}
+
+/// PNG settings. The oxipng defaults reproduce preset 4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct PngOptions {
+    /// Quantization quality override 0–100
+    pub quality: Option<u8>,
+    /// Largest palette imagequant may produce, 2–256
+    pub max_colors: u32,
+    /// oxipng preset 0–6 the other options are applied on top of
+    pub preset: u8,
+    /// libdeflate level 0–12; None keeps the preset's level
+    pub deflate_level: Option<u8>,
+    /// Compress with Zopfli: much slower, usually a few percent smaller
+    pub zopfli: bool,
+    /// Row filter strategies to try; empty keeps the preset's set
+    pub filters: Vec<PngFilter>,
+    /// Some(true) = Adam7 interlace, Some(false) = de-interlace, None = leave as is
+    pub interlace: Option<bool>,
+    /// Allow changing the color of fully transparent pixels
+    pub optimize_alpha: bool,
+    /// Ancillary chunks kept whatever the strip mode, e.g. `*b"iCCP"`
+    #[cfg_attr(feature = "serde", serde(with = "chunk_names"))]
+    pub keep_chunks: Vec<[u8; 4]>,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            quality: None,
+            max_colors: 256,
+            preset: 4,
+            deflate_level: None,
+            zopfli: false,
+            filters: Vec::new(),
+            interlace: Some(false),
+            optimize_alpha: false,
+            keep_chunks: Vec::new(),
+        }
+    }
+}
+
+/// JPEG encoder settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct JpegOptions {
+    /// Encoder quality override 0–100
+    pub quality: Option<u8>,
+}
+
+/// WebP encoder settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct WebpOptions {
+    /// Encoder quality override 0–100
+    pub quality: Option<u8>,
+}
+
+/// AVIF encoder settings. The shared `speed` picks the rav1e preset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct AvifOptions {
+    /// Encoder quality override 0–100
+    pub quality: Option<u8>,
+}
+
+/// GIF settings. Frames are quantized one by one, each getting its own palette.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct GifOptions {
+    /// Quantization quality override 0–100
+    pub quality: Option<u8>,
+    /// Largest palette per frame (2–256)
+    pub max_colors: u32,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self { quality: None, max_colors: 256 }
+    }
+}
+
+/// MP3 settings. Tag stripping is driven by the shared `strip` mode; the audio
+/// is only re-encoded (with ffmpeg's LAME encoder) when `bitrate` or `vbr` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Mp3Options {
+    /// Quality override 0–100 for VBR re-encoding
+    pub quality: Option<u8>,
+    /// Re-encode at this constant bitrate in kbit/s (8–320)
+    pub bitrate: Option<u32>,
+    /// Re-encode as VBR with the LAME quality mapped from `quality`; ignored with `bitrate`
+    pub vbr: bool,
+    /// Keep embedded cover art in `StripMode::Safe`, downscaled and recompressed;
+    /// None drops it with the other unsafe frames
+    pub cover_art: Option<CoverArt>,
+    /// Measure EBU R128 loudness with ffmpeg and write ReplayGain TXXX and RVA2 frames
+    pub replaygain: bool,
+    /// Zero bytes reserved after a rewritten ID3v2 tag so later tag edits need not
+    /// move the audio. None writes no padding and leaves tags that are kept as is
+    /// alone; Some(0) also rewrites kept tags to drop their padding.
+    pub id3_padding: Option<u32>,
+}
+
+impl Mp3Options {
+    /// True when the audio stream should be re-encoded rather than only stripped
+    pub fn reencodes(&self) -> bool {
+        self.bitrate.is_some() || self.vbr
+    }
+}
+
+/// WAV settings. Metadata chunks follow the shared `strip` mode; the audio is only
+/// converted (with ffmpeg) when it is a reduction: WAV is never upsampled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct WavOptions {
+    /// Downsample to this rate in Hz, e.g. 48000 for 96 kHz masters
+    pub sample_rate: Option<u32>,
+    /// Requantize to 8, 16 or 24 bits with triangular dither
+    pub bit_depth: Option<u16>,
+    /// Keep Broadcast WAV `bext` and `iXML` chunks (origination data) in every strip mode
+    pub broadcast: bool,
+}
+
+/// Opus encoder settings, used when converting audio to Opus (libopus via ffmpeg)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct OpusOptions {
+    /// Target bitrate in kbit/s (6–510); 24–32 suits speech, 96–128 music
+    pub bitrate: u32,
+}
+
+impl Default for OpusOptions {
+    fn default() -> Self {
+        Self { bitrate: 64 }
+    }
+}
+
+/// TIFF source settings, used when converting from TIFF
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct TiffOptions {
+    /// Page of a multi-page TIFF to convert, from 1
+    pub page: u32,
+}
+
+impl Default for TiffOptions {
+    fn default() -> Self {
+        Self { page: 1 }
+    }
+}
+
+/// ICO source settings, used when converting from ICO
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct IcoOptions {
+    /// Width of the icon image to convert, e.g. 32 for the 32x32 one; None
+    /// takes the largest
+    pub size: Option<u32>,
+}
+
+/// MP4 encoder and frame extraction settings
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Mp4Options {
+    /// Quality override 0–100, mapped to a CRF
+    pub quality: Option<u8>,
+    /// Video codec for re-encoding
+    pub codec: VideoCodec,
+    /// Hardware encoder family; `None` = software encoder
+    pub hwaccel: HwAccel,
+    /// Encoder CRF (0–51 for H.264/HEVC, 0–63 for VP9/AV1), taking precedence over any quality mapping
+    pub crf: Option<u8>,
+    /// Target video bitrate in bits/s; replaces CRF rate control when set
+    pub video_bitrate: Option<u64>,
+    /// Encode twice to hit `video_bitrate` more accurately
+    pub two_pass: bool,
+    /// What happens to the audio track when re-encoding
+    pub audio: AudioTrack,
+    /// AAC bitrate in kbit/s for `AudioTrack::Aac`
+    pub audio_bitrate: u32,
+    /// Keep only this part of the video when re-encoding
+    pub trim: Option<TimeRange>,
+    /// Exact output size (width, height) when re-encoding
+    pub scale: Option<(u32, u32)>,
+    /// Downscale to at most this height, keeping the aspect ratio; ignored with `scale`
+    pub max_height: Option<u32>,
+    /// Tone-map HDR input to SDR instead of refusing to re-encode it with a codec
+    /// that cannot keep HDR
+    pub force_sdr: bool,
+    /// Remux fragmented MP4 (fMP4/DASH) into a regular one in lossless mode
+    pub defragment: bool,
+    /// Extract frames from MP4 to PNG
+    pub extract_frames: bool,
+    /// Frames per second to extract (0 = all frames)
+    pub fps: f32,
+    /// Frame rate of GIF/WebP animations made from the video
+    pub animation_fps: f32,
+    /// Width of those animations, keeping the aspect ratio; None = source width
+    pub animation_width: Option<u32>,
+}
+
+impl Default for Mp4Options {
+    fn default() -> Self {
+        Self {
+            quality: None,
+            codec: VideoCodec::H264,
+            hwaccel: HwAccel::None,
+            crf: None,
+            video_bitrate: None,
+            two_pass: false,
+            audio: AudioTrack::Aac,
+            audio_bitrate: 128,
+            trim: None,
+            scale: None,
+            max_height: None,
+            force_sdr: false,
+            defragment: false,
             extract_frames: false,
             fps: 1.0,
+            animation_fps: 12.0,
+            animation_width: None,
+        }
+    }
+}
+
+/// Video codec used when re-encoding MP4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+}
+
+/// How embedded cover art is shrunk when it is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct CoverArt {
+    /// Longest side in pixels; larger art is downscaled keeping its aspect ratio
+    pub max_size: u32,
+    /// Encoding of the recompressed picture
+    pub format: CoverFormat,
+}
+
+impl Default for CoverArt {
+    fn default() -> Self {
+        Self { max_size: 600, format: CoverFormat::Jpeg }
+    }
+}
+
+/// Image format for recompressed cover art. JPEG is what every player reads;
+/// WebP is smaller but not understood everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CoverFormat {
+    #[default]
+    Jpeg,
+    Webp,
+}
+
+impl CoverFormat {
+    pub fn format(self) -> Format {
+        match self {
+            CoverFormat::Jpeg => Format::Jpeg,
+            CoverFormat::Webp => Format::Webp,
+        }
+    }
+}
+
+/// What counts as silence when trimming audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct SilenceTrim {
+    /// Level in dBFS below which audio is silent
+    pub threshold_db: f32,
+    /// Shortest leading or trailing silence that gets cut
+    pub min_duration: Duration,
+}
+
+impl Default for SilenceTrim {
+    fn default() -> Self {
+        Self {
+            threshold_db: -50.0,
+            min_duration: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Span of a video, from `start` to `end` (or the end of the file)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeRange {
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl TimeRange {
+    /// Length of the range within a video of `total` length
+    pub fn length_within(&self, total: Duration) -> Duration {
+        self.end.unwrap_or(total).min(total).saturating_sub(self.start)
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = ProcessingError;
+
+    /// `START-END` or `START-`, each as `HH:MM:SS[.mmm]`, `MM:SS` or seconds
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ProcessingError::InvalidConfig(format!("invalid time range: {}", s));
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = match start.trim() {
+            "" => Duration::ZERO,
+            start => parse_timestamp(start).ok_or_else(invalid)?,
+        };
+        let end = match end.trim() {
+            "" => None,
+            end => Some(parse_timestamp(end).ok_or_else(invalid)?),
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(ProcessingError::InvalidConfig(format!("time range ends before it starts: {}", s)));
+        }
+        Ok(TimeRange { start, end })
+    }
+}
+
+/// `HH:MM:SS[.mmm]`, `MM:SS[.mmm]` or plain seconds
+pub(crate) fn parse_timestamp(s: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in s.trim().split(':') {
+        let value: f64 = part.parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + value;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// Audio handling when re-encoding a video
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum AudioTrack {
+    /// Re-encode to AAC at `audio_bitrate`
+    Aac,
+    /// Keep the original audio stream as is
+    Copy,
+    /// Remove audio
+    Drop,
+}
+
+/// Hardware video encoder family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum HwAccel {
+    /// Software encoding (libx264, libx265, ...)
+    None,
+    /// First hardware encoder the local ffmpeg offers for the codec, else software
+    Auto,
+    /// Apple VideoToolbox
+    Videotoolbox,
+    /// NVIDIA NVENC
+    Nvenc,
+    /// Intel Quick Sync
+    Qsv,
+    /// VA-API (Linux, Intel/AMD)
+    Vaapi,
+}
+
+/// PNG row filter strategy tried by oxipng
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    MinSum,
+    Entropy,
+    Bigrams,
+    BigEnt,
+    Brute,
+}
+
+impl FromStr for PngFilter {
+    type Err = ProcessingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(PngFilter::None),
+            "sub" => Ok(PngFilter::Sub),
+            "up" => Ok(PngFilter::Up),
+            "average" | "avg" => Ok(PngFilter::Average),
+            "paeth" => Ok(PngFilter::Paeth),
+            "minsum" => Ok(PngFilter::MinSum),
+            "entropy" => Ok(PngFilter::Entropy),
+            "bigrams" => Ok(PngFilter::Bigrams),
+            "bigent" => Ok(PngFilter::BigEnt),
+            "brute" => Ok(PngFilter::Brute),
+            _ => Err(ProcessingError::InvalidConfig(format!("unknown PNG filter: {}", s))),
         }
     }
 }
+
+/// Chunk names as strings (`"iCCP"`) rather than byte arrays in serialized configs
+#[cfg(feature = "serde")]
+mod chunk_names {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(chunks: &[[u8; 4]], serializer: S) -> Result<S::Ok, S::Error> {
+        chunks
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 4]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|name| {
+                <[u8; 4]>::try_from(name.as_bytes())
+                    .map_err(|_| D::Error::custom(format!("'{}' is not a 4-letter PNG chunk name", name)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_range() {
+        let range: TimeRange = "00:00:05-00:01:30".parse().unwrap();
+        assert_eq!(range.start, Duration::from_secs(5));
+        assert_eq!(range.end, Some(Duration::from_secs(90)));
+
+        let open: TimeRange = "1:00.5-".parse().unwrap();
+        assert_eq!(open.start, Duration::from_millis(60_500));
+        assert_eq!(open.end, None);
+        assert_eq!(open.length_within(Duration::from_secs(100)), Duration::from_millis(39_500));
+
+        assert!("90-5".parse::<TimeRange>().is_err());
+        assert!("5".parse::<TimeRange>().is_err());
+        assert!("a-b".parse::<TimeRange>().is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! `image-preparer.toml`: default settings shared by the CLI and the server.
+//! The file holds [`ProcessingConfig`] fields (`quality`, `speed`, `no_lossy`,
+//! `strip`, per-format sections such as `[png]` or `[webp]`, ...) plus
+//! `output`, the directory commands write to when the command line names none,
+//! and `[extensions]`, extra file extensions read as a known format:
+//!
+//! ```toml
+//! quality = 75
+//! strip = "safe"
+//! output = "dist"
+//!
+//! [webp]
+//! quality = 70
+//!
+//! [png]
+//! max_colors = 128
+//!
+//! [extensions]
+//! jfif = "jpg"
+//! ```
+//!
+//! Settings the file leaves out keep their defaults, or with
+//! [`ConfigFile::apply_to`] the values of a base config: the CLI lays the file
+//! over the config its flags built and then restores the flags actually given
+//! on the command line. The server reads the file at startup.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::ProcessingConfig;
+use crate::error::ProcessingError;
+use crate::format::{parse_extension_mapping, Format};
+
+/// Name the CLI and the server look for in the working directory
+pub const CONFIG_FILE_NAME: &str = "image-preparer.toml";
+
+/// Environment variable pointing the server at a config file elsewhere
+pub const CONFIG_ENV: &str = "IP_CONFIG";
+
+/// Top-level keys a config file may set. `dry_run` is left to the command line.
+const KEYS: &[&str] = &[
+    "output",
+    "extensions",
+    "quality",
+    "speed",
+    "no_lossy",
+    "strip",
+    "auto_orient",
+    "target_ssim",
+    "backup",
+    "trim_silence",
+    "jobs",
+    "low_priority",
+    "limits",
+    "png",
+    "jpeg",
+    "webp",
+    "avif",
+    "gif",
+    "mp3",
+    "mp4",
+    "wav",
+    "opus",
+    "tiff",
+    "ico",
+];
+
+/// A parsed and validated config file
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    /// Where the file was read from; empty for [`ConfigFile::parse`]
+    pub path: PathBuf,
+    /// Output file or directory for commands given none. [`ConfigFile::load`]
+    /// resolves a relative path against the file's directory.
+    pub output: Option<PathBuf>,
+    /// `[extensions]`: extension (without the dot) and the format files ending in it
+    /// are read as, for [`crate::format::map_extension`]
+    pub extensions: Vec<(String, Format)>,
+    /// The file's settings over the defaults
+    pub config: ProcessingConfig,
+    /// The settings as written, for [`ConfigFile::apply_to`]
+    settings: toml::Table,
+}
+
+impl ConfigFile {
+    /// Read, parse and validate `path`
+    pub fn load(path: &Path) -> Result<Self, ProcessingError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|source| ProcessingError::ReadFile { path: path.to_path_buf(), source })?;
+        let mut file = Self::parse(&text).map_err(|e| match e {
+            ProcessingError::InvalidConfig(reason) => {
+                ProcessingError::InvalidConfig(format!("{}: {}", path.display(), reason))
+            }
+            e => e,
+        })?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        file.output = file.output.map(|output| dir.join(output));
+        file.path = path.to_path_buf();
+        Ok(file)
+    }
+
+    /// [`CONFIG_FILE_NAME`] in `dir`, if there is one
+    pub fn discover(dir: &Path) -> Result<Option<Self>, ProcessingError> {
+        let path = dir.join(CONFIG_FILE_NAME);
+        if path.is_file() {
+            Self::load(&path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parse and validate TOML; unknown top-level keys are rejected so typos
+    /// do not go unnoticed
+    pub fn parse(text: &str) -> Result<Self, ProcessingError> {
+        let mut settings: toml::Table = text.parse().map_err(invalid)?;
+        if let Some(key) = settings.keys().find(|key| !KEYS.contains(&key.as_str())) {
+            return Err(ProcessingError::InvalidConfig(format!("unknown setting '{}'", key)));
+        }
+        let output = match settings.remove("output") {
+            Some(toml::Value::String(output)) => Some(PathBuf::from(output)),
+            Some(_) => return Err(ProcessingError::InvalidConfig("output must be a path string".to_string())),
+            None => None,
+        };
+        let extensions = match settings.remove("extensions") {
+            Some(toml::Value::Table(table)) => table
+                .iter()
+                .map(|(extension, format)| match format {
+                    toml::Value::String(format) => parse_extension_mapping(&format!("{}={}", extension, format)),
+                    _ => Err(ProcessingError::InvalidConfig(format!("extensions.{} must be a format name", extension))),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(ProcessingError::InvalidConfig("extensions must be a table".to_string())),
+            None => Vec::new(),
+        };
+        let config = ProcessingConfig::default();
+        let mut file = Self { path: PathBuf::new(), output, extensions, config, settings };
+        file.config = file.apply_to(&ProcessingConfig::default())?;
+        Ok(file)
+    }
+
+    /// `base` with the file's settings on top, validated. Format sections are
+    /// merged key by key, so `[png] max_colors` leaves `base`'s other PNG settings.
+    pub fn apply_to(&self, base: &ProcessingConfig) -> Result<ProcessingConfig, ProcessingError> {
+        let mut table = toml::Table::try_from(base)
+            .map_err(|e| ProcessingError::InvalidConfig(format!("cannot merge settings: {}", e)))?;
+        merge_tables(&mut table, &self.settings);
+        let config: ProcessingConfig = table.try_into().map_err(invalid)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Copy `from` into `into`, descending into tables both have
+fn merge_tables(into: &mut toml::Table, from: &toml::Table) {
+    for (key, value) in from {
+        match (into.get_mut(key), value) {
+            (Some(toml::Value::Table(into)), toml::Value::Table(from)) => merge_tables(into, from),
+            _ => {
+                into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn invalid(e: toml::de::Error) -> ProcessingError {
+    ProcessingError::InvalidConfig(e.to_string().trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StripMode;
+    use crate::format::Format;
+
+    #[test]
+    fn test_parse() {
+        let file = ConfigFile::parse(
+            "quality = 70\nstrip = \"safe\"\noutput = \"dist\"\n\n[webp]\nquality = 60\n\n[png]\nmax_colors = 64\n",
+        )
+        .unwrap();
+        assert_eq!(file.output.as_deref(), Some(Path::new("dist")));
+        assert_eq!(file.config.quality, 70);
+        assert_eq!(file.config.strip, StripMode::Safe);
+        assert_eq!(file.config.quality_for(Format::Webp), 60);
+        assert_eq!(file.config.png.max_colors, 64);
+        assert_eq!(file.config.speed, ProcessingConfig::default().speed);
+
+        let mut base = ProcessingConfig { speed: 8, ..ProcessingConfig::default() };
+        base.png.zopfli = true;
+        let applied = file.apply_to(&base).unwrap();
+        assert_eq!((applied.quality, applied.speed), (70, 8));
+        assert_eq!(applied.png.max_colors, 64);
+        assert!(applied.png.zopfli);
+
+        assert!(ConfigFile::parse("qualty = 70").unwrap_err().to_string().contains("unknown setting 'qualty'"));
+        assert!(ConfigFile::parse("quality = 170").is_err());
+        assert!(ConfigFile::parse("strip = \"some\"").is_err());
+    }
+
+    #[test]
+    fn test_load_and_discover() {
+        let dir = std::env::temp_dir().join(format!("ip_config_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(ConfigFile::discover(&dir).unwrap().is_none());
+
+        let path = dir.join(CONFIG_FILE_NAME);
+        std::fs::write(&path, "quality = 65\noutput = \"dist\"\n[extensions]\njfif = \"jpg\"\n").unwrap();
+        let file = ConfigFile::discover(&dir).unwrap().unwrap();
+        assert_eq!(file.path, path);
+        assert_eq!(file.config.quality, 65);
+        // A relative output is resolved against the file's directory
+        assert_eq!(file.output, Some(dir.join("dist")));
+        assert_eq!(file.extensions, [("jfif".to_string(), Format::Jpeg)]);
+
+        // Errors name the file
+        std::fs::write(&path, "speed = 99\n").unwrap();
+        let error = ConfigFile::load(&path).unwrap_err().to_string();
+        assert!(error.contains(&path.display().to_string()), "{}", error);
+        assert!(ConfigFile::load(&dir.join("missing.toml")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extensions() {
+        let file = ConfigFile::parse("quality = 70\n\n[extensions]\njfif = \"jpg\"\n\".PNGX\" = \"png\"\n").unwrap();
+        assert_eq!(file.extensions, [("PNGX".to_string(), Format::Png), ("jfif".to_string(), Format::Jpeg)]);
+        assert_eq!(file.config.quality, 70);
+        assert!(ConfigFile::parse("").unwrap().extensions.is_empty());
+
+        assert!(ConfigFile::parse("[extensions]\njfif = \"psd\"\n").is_err());
+        assert!(ConfigFile::parse("[extensions]\njfif = 1\n").unwrap_err().to_string().contains("extensions.jfif"));
+        assert!(ConfigFile::parse("extensions = \"jfif=jpg\"").is_err());
+    }
+}
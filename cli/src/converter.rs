@@ -1,52 +1,34 @@
-use image::{GenericImageView, ImageFormat as ImgFormat, DynamicImage};
+use image::metadata::Orientation;
+use image::{ImageFormat as ImgFormat, DynamicImage};
 use std::io::Cursor;
 
-use crate::config::ProcessingConfig;
+use crate::config::{ProcessingConfig, StripMode};
 use crate::error::ProcessingError;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ConvertFormat {
-    Png,
-    Jpg,
-    Webp,
-}
-
-impl ConvertFormat {
-    pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_lowercase().as_str() {
-            "png" => Some(ConvertFormat::Png),
-            "jpg" | "jpeg" => Some(ConvertFormat::Jpg),
-            "webp" => Some(ConvertFormat::Webp),
-            _ => None,
-        }
-    }
-
-    pub fn extension(&self) -> &'static str {
-        match self {
-            ConvertFormat::Png => "png",
-            ConvertFormat::Jpg => "jpg",
-            ConvertFormat::Webp => "webp",
-        }
-    }
-
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            ConvertFormat::Png => "PNG",
-            ConvertFormat::Jpg => "JPEG",
-            ConvertFormat::Webp => "WebP",
-        }
-    }
-}
+use crate::format::Format;
+use crate::limits::{decode_image, Limits};
+use crate::processor::jpeg::{estimate_quality, optimize_huffman, strip_jpeg_metadata};
+use crate::processor::png;
+#[cfg(feature = "avif")]
+use crate::processor::avif::encode_avif;
+#[cfg(feature = "webp")]
+use crate::processor::webp::{self, encode_webp};
+use crate::progress::{Progress, Stage};
+use crate::tune::{tune_quality, with_quality, SsimReference};
 
 /// Convert image from one format to another
 pub fn convert_image(
     input: &[u8],
-    target_format: ConvertFormat,
+    target_format: Format,
     config: &ProcessingConfig,
 ) -> Result<Vec<u8>, ProcessingError> {
-    // Load image (supports PNG, JPG, WebP automatically)
-    let img = image::load_from_memory(input)
-        .map_err(|e| ProcessingError::Decode(format!("Failed to load image: {}", e)))?;
+    if target_format == Format::Jpeg {
+        if let Some(stripped) = keep_jpeg(input, config)? {
+            return Ok(stripped);
+        }
+    }
+
+    // Load image (PNG, JPG, WebP, TIFF, BMP and ICO directly, AVIF and HEIC through ffmpeg)
+    let img = decode_source(input, config)?;
 
     log::debug!(
         "Converting image: {}x{} pixels to {}",
@@ -55,12 +37,7 @@ pub fn convert_image(
         target_format.as_str()
     );
 
-    // Convert based on target format
-    let output = match target_format {
-        ConvertFormat::Png => convert_to_png(&img, config)?,
-        ConvertFormat::Jpg => convert_to_jpg(&img, config)?,
-        ConvertFormat::Webp => convert_to_webp(&img, config)?,
-    };
+    let output = encode_image(&img, target_format, config)?;
 
     log::debug!(
         "Conversion complete: {} bytes ({})",
@@ -71,6 +48,166 @@ pub fn convert_image(
     Ok(output)
 }
 
+/// Decode `input` once and encode it as `target_format` with that format's
+/// optimizer: quantize + oxipng for PNG, the WebP compressor for WebP. Unlike
+/// [`convert_image`] followed by compressing, the pixels are never decoded
+/// twice or re-encoded lossily twice. Also returns the lossy quality used.
+pub fn transcode_image(
+    input: &[u8],
+    target_format: Format,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    progress.start(Stage::Decode);
+    let img = decode_source(input, config)?;
+    progress.finish(Stage::Decode);
+
+    compress_decoded(&img, target_format, config, progress)
+}
+
+/// Decode a conversion source. A multi-page TIFF decodes the page picked by
+/// `config.tiff` and an ICO the image picked by `config.ico`; decoders only
+/// read the first page and the largest icon image.
+pub(crate) fn decode_source(input: &[u8], config: &ProcessingConfig) -> Result<DynamicImage, ProcessingError> {
+    match Format::from_bytes(input) {
+        Some(Format::Tiff) => {
+            let pages = crate::tiff::pages(input).len();
+            if pages > 1 && config.tiff.page == 1 {
+                log::warn!("TIFF has {} pages, converting page 1 (pick another with --page)", pages);
+            }
+            let page = crate::tiff::select_page(input, config.tiff.page)?;
+            let img = decode_image(&page, Some(ImgFormat::Tiff), &config.limits)?;
+            Ok(auto_orient(img, &page, config))
+        }
+        Some(Format::Ico) => {
+            let image = crate::ico::select(input, config.ico.size)?;
+            decode_image(&image, None, &config.limits)
+        }
+        _ => decode_image(input, None, &config.limits).map(|img| auto_orient(img, input, config)),
+    }
+}
+
+/// `img` rotated/flipped upright per the EXIF orientation of `source`, the data
+/// it was decoded from: encoders don't write the tag, so a viewer would show the
+/// stored pixels sideways. Returned as is with `config.auto_orient` off.
+pub(crate) fn auto_orient(mut img: DynamicImage, source: &[u8], config: &ProcessingConfig) -> DynamicImage {
+    if !config.auto_orient {
+        return img;
+    }
+    let orientation = Format::from_bytes(source)
+        .and_then(|format| crate::exif::orientation(source, format))
+        .and_then(|value| Orientation::from_exif(u8::try_from(value).ok()?));
+    if let Some(orientation) = orientation.filter(|orientation| *orientation != Orientation::NoTransforms) {
+        log::debug!("Applying EXIF orientation {:?}", orientation);
+        img.apply_orientation(orientation);
+    }
+    img
+}
+
+/// The optimizing half of [`transcode_image`], for an image already decoded
+/// (and possibly transformed)
+pub(crate) fn compress_decoded(
+    img: &DynamicImage,
+    target_format: Format,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    match target_format {
+        Format::Png => png::compress_image(img, config, progress),
+        #[cfg(feature = "webp")]
+        Format::Webp => webp::compress_image(img, config, progress),
+        _ => {
+            progress.start(Stage::Encode);
+            let encoded = encode_image_with_quality(img, target_format, config)?;
+            progress.finish(Stage::Encode);
+            Ok(encoded)
+        }
+    }
+}
+
+/// A JPEG already saved at or below the requested quality, with only its
+/// metadata stripped: re-encoding it would cost quality and rarely save bytes.
+/// None when the input should be re-encoded.
+fn keep_jpeg(input: &[u8], config: &ProcessingConfig) -> Result<Option<Vec<u8>>, ProcessingError> {
+    if config.no_lossy || config.target_ssim.is_some() {
+        return Ok(None);
+    }
+    let Some(quality) = estimate_quality(input) else {
+        return Ok(None);
+    };
+    // Stripping drops the orientation tag: only re-encoding can keep the image upright
+    if config.auto_orient && crate::exif::orientation(input, Format::Jpeg).is_some_and(|orientation| orientation != 1) {
+        return Ok(None);
+    }
+    let requested = config.quality_for(Format::Jpeg);
+    if quality > requested {
+        return Ok(None);
+    }
+    config.limits.check_input(input.len())?;
+    // Kept without decoding, so hold the header to the pixel limits a decode would
+    let header = image::ImageReader::with_format(Cursor::new(input), ImgFormat::Jpeg);
+    if let Ok((width, height)) = header.into_dimensions() {
+        config.limits.check_dimensions(width, height)?;
+    }
+    log::debug!("JPEG already at quality ~{} (requested {}), not re-encoding", quality, requested);
+    strip_jpeg_metadata(input, StripMode::All).map(Some)
+}
+
+/// Encode an already decoded image as PNG, JPEG, WebP or AVIF
+pub(crate) fn encode_image(
+    img: &DynamicImage,
+    target_format: Format,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    encode_image_with_quality(img, target_format, config).map(|(data, _)| data)
+}
+
+/// [`encode_image`], also returning the lossy quality used: the configured one,
+/// or the one found for `config.target_ssim`. None for lossless output.
+pub(crate) fn encode_image_with_quality(
+    img: &DynamicImage,
+    target_format: Format,
+    config: &ProcessingConfig,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    let lossy = encodes_lossy(target_format, config);
+    match config.target_ssim {
+        Some(target) if lossy => {
+            let (data, quality) = tune_quality(&SsimReference::new(img), target, |quality| {
+                let data = encode_at(img, target_format, &with_quality(config, target_format, quality))?;
+                let decoded = decode_image(&data, None, &Limits::unlimited())?;
+                Ok((data, decoded))
+            })?;
+            Ok((data, Some(quality)))
+        }
+        _ => Ok((encode_at(img, target_format, config)?, lossy.then(|| config.quality_for(target_format)))),
+    }
+}
+
+/// Whether encoding to `target_format` is lossy: JPEG and AVIF unless `no_lossy`,
+/// WebP only with libwebp (the pure-Rust encoder is lossless)
+pub(crate) fn encodes_lossy(target_format: Format, config: &ProcessingConfig) -> bool {
+    !config.no_lossy
+        && (matches!(target_format, Format::Jpeg | Format::Avif)
+            || (target_format == Format::Webp && cfg!(feature = "libwebp")))
+}
+
+fn encode_at(img: &DynamicImage, target_format: Format, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    match target_format {
+        Format::Png => convert_to_png(img, config),
+        Format::Jpeg => convert_to_jpg(img, config),
+        #[cfg(feature = "webp")]
+        Format::Webp => convert_to_webp(img, config),
+        #[cfg(feature = "avif")]
+        Format::Avif => encode_avif(img, config),
+        #[cfg(feature = "gif")]
+        Format::Gif => crate::processor::gif::encode_gif(img, config),
+        other => Err(ProcessingError::UnsupportedFormat(format!(
+            "cannot convert an image to {}",
+            other.as_str()
+        ))),
+    }
+}
+
 /// Convert to PNG format
 fn convert_to_png(img: &DynamicImage, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
     let mut output = Vec::new();
@@ -101,7 +238,7 @@ fn convert_to_jpg(img: &DynamicImage, config: &ProcessingConfig) -> Result<Vec<u
     // Create JPEG encoder with quality
     let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
         &mut cursor,
-        config.quality,
+        config.quality_for(Format::Jpeg),
     );
 
     encoder
@@ -113,21 +250,61 @@ fn convert_to_jpg(img: &DynamicImage, config: &ProcessingConfig) -> Result<Vec<u
         )
         .map_err(|e| ProcessingError::Encode(format!("Failed to encode JPEG: {}", e)))?;
 
-    Ok(output)
+    // The encoder writes the standard Huffman tables
+    Ok(optimize_huffman(&output).unwrap_or(output))
 }
 
 /// Convert to WebP format
+#[cfg(feature = "webp")]
 fn convert_to_webp(img: &DynamicImage, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
     let rgba = img.to_rgba8();
-    let (width, height) = img.dimensions();
+    let (width, height) = rgba.dimensions();
 
-    let encoder = webp::Encoder::from_rgba(rgba.as_raw(), width, height);
+    encode_webp(rgba.as_raw(), width, height, config)
+}
 
-    let encoded = if config.no_lossy {
-        encoder.encode_lossless()
-    } else {
-        encoder.encode(config.quality as f32)
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(encoded.to_vec())
+    #[test]
+    fn test_keep_jpeg_checks_dimensions() {
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 50);
+        image::RgbImage::from_pixel(64, 64, image::Rgb([90, 120, 200])).write_with_encoder(encoder).unwrap();
+
+        let config = ProcessingConfig::default();
+        assert!(keep_jpeg(&jpeg, &config).unwrap().is_some());
+
+        let mut limited = ProcessingConfig::default();
+        limited.limits.max_pixels = Some(1000);
+        assert!(matches!(keep_jpeg(&jpeg, &limited), Err(ProcessingError::LimitExceeded(_))));
+    }
+
+    /// A 32x16 JPEG whose EXIF orientation is `orientation`
+    fn tagged_jpeg(orientation: u16) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90);
+        image::RgbImage::from_fn(32, 16, |x, _| image::Rgb([(x * 8) as u8, 0, 0])).write_with_encoder(encoder).unwrap();
+        let tiff = crate::exif::orientation_block(orientation);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&tiff);
+        tagged.extend_from_slice(&jpeg[2..]);
+        tagged
+    }
+
+    #[test]
+    fn test_convert_auto_orient() {
+        let dimensions = |data: &[u8]| image::load_from_memory(data).map(|img| (img.width(), img.height())).unwrap();
+        let config = ProcessingConfig::default();
+        assert_eq!(dimensions(&convert_image(&tagged_jpeg(6), Format::Png, &config).unwrap()), (16, 32));
+
+        let upright = ProcessingConfig { auto_orient: false, ..ProcessingConfig::default() };
+        assert_eq!(dimensions(&convert_image(&tagged_jpeg(6), Format::Png, &upright).unwrap()), (32, 16));
+        // 262 is 6 in its low byte, but no orientation
+        assert_eq!(dimensions(&convert_image(&tagged_jpeg(262), Format::Png, &config).unwrap()), (32, 16));
+    }
 }
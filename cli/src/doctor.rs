@@ -0,0 +1,179 @@
+//! `image_preparer doctor`: checks the environment (ffmpeg, ffprobe and their
+//! encoders, a writable temp directory) and lists which operations work with it,
+//! so a missing encoder shows up before a batch fails halfway through.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::ffmpeg::{self, FfmpegInfo, FFMPEG_ENV};
+use crate::term::{self, Color};
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Something optional is missing; the operations it names are unavailable
+    Warn,
+    /// Nothing that writes files will work
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status, detail: detail.into() }
+    }
+}
+
+/// Operations that go through ffmpeg and the encoders each one needs
+const FFMPEG_OPERATIONS: [(&str, &[&str]); 9] = [
+    ("MP4 compression (H.264, AAC)", &["libx264", "aac"]),
+    ("MP4 compression with --video-codec hevc", &["libx265"]),
+    ("MP4 compression with --video-codec vp9, MP4 to WebM", &["libvpx-vp9"]),
+    ("MP4 compression with --video-codec av1", &["libaom-av1"]),
+    ("MP4 to GIF", &["gif"]),
+    ("MP4 to animated WebP", &["libwebp"]),
+    ("MP3 re-encoding (--audio-bitrate, --mp3-bitrate, --mp3-vbr)", &["libmp3lame"]),
+    ("WAV to FLAC", &["flac"]),
+    ("Conversion to Opus", &["libopus"]),
+];
+
+/// Every check, in the order they are printed
+pub fn run_checks() -> Vec<Check> {
+    let info = ffmpeg::probe();
+    let mut checks = vec![ffmpeg_check(info), ffprobe_check(), temp_dir_check()];
+    checks.push(Check::new(
+        "Built in",
+        CheckStatus::Ok,
+        "PNG and WebP compression, PNG/JPEG/WebP conversion, metadata stripping for PNG, WebP, MP3 and WAV",
+    ));
+    checks.push(lossy_webp_check());
+    checks.extend(FFMPEG_OPERATIONS.iter().map(|&(operation, encoders)| operation_check(info, operation, encoders)));
+    checks
+}
+
+fn ffmpeg_check(info: Option<&FfmpegInfo>) -> Check {
+    match info {
+        Some(info) => Check::new(
+            "ffmpeg",
+            CheckStatus::Ok,
+            format!("{} at {} ({} encoders)", info.version, info.path.display(), info.encoders.len()),
+        ),
+        None => Check::new(
+            "ffmpeg",
+            CheckStatus::Warn,
+            format!(
+                "not found at {}: video, audio conversion and MP3 re-encoding are unavailable. \
+                 Install ffmpeg or point to it with --ffmpeg-path or {}",
+                ffmpeg::binary().display(),
+                FFMPEG_ENV
+            ),
+        ),
+    }
+}
+
+fn ffprobe_check() -> Check {
+    let binary = ffmpeg::ffprobe_binary();
+    let output = Command::new(&binary).arg("-version").output().ok().filter(|output| output.status.success());
+    match output {
+        Some(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let version = stdout.lines().next().and_then(|line| line.split_whitespace().nth(2)).unwrap_or("unknown");
+            Check::new("ffprobe", CheckStatus::Ok, format!("{} at {}", version, binary.display()))
+        }
+        None => Check::new(
+            "ffprobe",
+            CheckStatus::Warn,
+            format!(
+                "not found at {}: inspect shows less about MP4 files and converted audio loses its tags \
+                 with --strip safe",
+                binary.display()
+            ),
+        ),
+    }
+}
+
+/// ffmpeg runs read and write their files in the temp directory
+fn temp_dir_check() -> Check {
+    let dir = std::env::temp_dir();
+    let probe: PathBuf = dir.join(format!("image_preparer_doctor_{}", std::process::id()));
+    match std::fs::write(&probe, b"ok") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            Check::new("Temp directory", CheckStatus::Ok, format!("{} is writable", dir.display()))
+        }
+        Err(e) => Check::new(
+            "Temp directory",
+            CheckStatus::Fail,
+            format!("cannot write to {}: {}. Set TMPDIR to a writable directory", dir.display(), e),
+        ),
+    }
+}
+
+fn lossy_webp_check() -> Check {
+    if cfg!(feature = "libwebp") {
+        Check::new("Lossy WebP", CheckStatus::Ok, "built with libwebp")
+    } else {
+        Check::new("Lossy WebP", CheckStatus::Warn, "built without the libwebp feature: WebP is encoded losslessly")
+    }
+}
+
+/// Whether `operation` can run with `info`'s ffmpeg: Ok when every encoder is there
+fn operation_check(info: Option<&FfmpegInfo>, operation: &str, encoders: &[&str]) -> Check {
+    let Some(info) = info else {
+        return Check::new(operation, CheckStatus::Warn, "needs ffmpeg");
+    };
+    // An unreadable encoder list is not proof anything is missing; encodes check again
+    if info.encoders.is_empty() {
+        return Check::new(operation, CheckStatus::Ok, "encoder list unavailable, assumed present");
+    }
+    let missing: Vec<&str> = encoders.iter().copied().filter(|encoder| !info.has_encoder(encoder)).collect();
+    if missing.is_empty() {
+        Check::new(operation, CheckStatus::Ok, encoders.join(", "))
+    } else {
+        Check::new(operation, CheckStatus::Warn, format!("ffmpeg lacks {}", missing.join(", ")))
+    }
+}
+
+/// One line per check with a colored status tag, then a count of problems
+pub fn print_checks(checks: &[Check]) {
+    term::heading("image_preparer doctor");
+    let width = checks.iter().map(|check| check.name.chars().count()).max().unwrap_or(0);
+    for check in checks {
+        let tag = match check.status {
+            CheckStatus::Ok => term::paint("[ OK ]", Color::Green),
+            CheckStatus::Warn => term::paint("[WARN]", Color::Yellow),
+            CheckStatus::Fail => term::paint("[FAIL]", Color::Red),
+        };
+        println!("{} {:<width$}  {}", tag, check.name, check.detail);
+    }
+
+    let warnings = checks.iter().filter(|check| check.status == CheckStatus::Warn).count();
+    let failures = checks.iter().filter(|check| check.status == CheckStatus::Fail).count();
+    println!("{}", term::rule());
+    println!("{} warning(s), {} failure(s)", warnings, failures);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_check() {
+        let info = FfmpegInfo {
+            path: PathBuf::from("ffmpeg"),
+            version: "6.1".to_string(),
+            encoders: vec!["libx264".to_string(), "gif".to_string()],
+        };
+        assert_eq!(operation_check(Some(&info), "MP4", &["libx264"]).status, CheckStatus::Ok);
+        let check = operation_check(Some(&info), "MP4", &["libx264", "aac"]);
+        assert_eq!((check.status, check.detail.as_str()), (CheckStatus::Warn, "ffmpeg lacks aac"));
+        assert_eq!(operation_check(None, "MP4", &["libx264"]).status, CheckStatus::Warn);
+    }
+}
@@ -6,6 +6,9 @@ pub enum ProcessingError {
     #[error("unsupported format: {0}")]
     UnsupportedFormat(String),
 
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
     #[error("failed to read file {path}: {source}")]
     ReadFile {
         path: PathBuf,
@@ -30,6 +33,142 @@ pub enum ProcessingError {
     #[error("optimization failed: {0}")]
     Optimize(String),
 
+    #[error("ffmpeg not found - install it to process video")]
+    FfmpegMissing,
+
+    #[error("ffmpeg has no {0} encoder - use another codec or an ffmpeg build that includes it")]
+    FfmpegEncoderMissing(String),
+
+    #[error("ffmpeg failed ({}): {stderr}", describe_exit(.exit_code))]
+    FfmpegFailed {
+        /// None when ffmpeg was killed by a signal
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+
+    #[error("ffmpeg timed out after {0:?} and was killed")]
+    FfmpegTimedOut(std::time::Duration),
+
+    #[error("corrupt {format} input{}: {reason}", describe_offset(.offset))]
+    CorruptInput {
+        format: String,
+        /// Byte offset of the bad structure, when known
+        offset: Option<u64>,
+        reason: String,
+    },
+
+    #[error("limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("processing cancelled")]
+    Cancelled,
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "fs")]
     #[error("directory walk error: {0}")]
-    WalkDir(#[from] walkdir::Error),
+    WalkDir(#[from] ignore::Error),
+}
+
+impl ProcessingError {
+    /// Stable machine-readable code, e.g. for JSON reports and API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessingError::UnsupportedFormat(_) => "unsupported_format",
+            ProcessingError::InvalidConfig(_) => "invalid_config",
+            ProcessingError::ReadFile { .. } => "read_file",
+            ProcessingError::WriteFile { .. } => "write_file",
+            ProcessingError::Decode(_) => "decode",
+            ProcessingError::Quantize(_) => "quantize",
+            ProcessingError::Encode(_) => "encode",
+            ProcessingError::Optimize(_) => "optimize",
+            ProcessingError::FfmpegMissing => "ffmpeg_missing",
+            ProcessingError::FfmpegEncoderMissing(_) => "ffmpeg_encoder_missing",
+            ProcessingError::FfmpegFailed { .. } => "ffmpeg_failed",
+            ProcessingError::FfmpegTimedOut(_) => "ffmpeg_timed_out",
+            ProcessingError::CorruptInput { .. } => "corrupt_input",
+            ProcessingError::LimitExceeded(_) => "limit_exceeded",
+            ProcessingError::Cancelled => "cancelled",
+            ProcessingError::Io(_) => "io",
+            #[cfg(feature = "fs")]
+            ProcessingError::WalkDir(_) => "walk_dir",
+        }
+    }
+
+    /// Whether running the same job again may succeed. Bad input and bad
+    /// configuration are fatal; I/O hiccups and a killed ffmpeg are not.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProcessingError::ReadFile { .. }
+                | ProcessingError::WriteFile { .. }
+                | ProcessingError::Io(_)
+                | ProcessingError::FfmpegFailed { exit_code: None, .. }
+        )
+    }
+
+    /// I/O failures that usually clear up within seconds, so the same read or
+    /// write is worth repeating in place: timeouts, dropped connections and
+    /// locks held by sync clients on network shares and cloud folders. A missing
+    /// file or denied permission is not.
+    pub fn is_transient(&self) -> bool {
+        use std::io::ErrorKind::*;
+
+        let source = match self {
+            ProcessingError::ReadFile { source, .. }
+            | ProcessingError::WriteFile { source, .. }
+            | ProcessingError::Io(source) => source,
+            _ => return false,
+        };
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION: another process has the file open
+        if cfg!(windows) && matches!(source.raw_os_error(), Some(32 | 33)) {
+            return true;
+        }
+        matches!(
+            source.kind(),
+            Interrupted
+                | TimedOut
+                | WouldBlock
+                | ResourceBusy
+                | ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | NetworkDown
+                | NetworkUnreachable
+                | HostUnreachable
+                | StaleNetworkFileHandle
+        )
+    }
+}
+
+fn describe_exit(exit_code: &Option<i32>) -> String {
+    match exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => "killed by signal".to_string(),
+    }
+}
+
+fn describe_offset(offset: &Option<u64>) -> String {
+    match offset {
+        Some(offset) => format!(" at byte {}", offset),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn test_transient_io_errors() {
+        let read = |kind| ProcessingError::ReadFile { path: PathBuf::from("a.png"), source: Error::from(kind) };
+        assert!(read(ErrorKind::TimedOut).is_transient());
+        assert!(read(ErrorKind::StaleNetworkFileHandle).is_transient());
+        assert!(!read(ErrorKind::NotFound).is_transient());
+        assert!(!read(ErrorKind::PermissionDenied).is_transient());
+        assert!(!ProcessingError::Decode("bad".to_string()).is_transient());
+    }
 }
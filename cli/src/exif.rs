@@ -0,0 +1,678 @@
+//! Reading EXIF data embedded in images: the capture date, orientation and GPS
+//! position, found in the JPEG APP1 segment, the PNG `eXIf` chunk, the WebP
+//! `EXIF` chunk or a TIFF file's own IFD0. Also the tag listing `inspect` shows
+//! for JPEG and the rewrite `--strip safe` gives JPEG EXIF blocks.
+//!
+//! EXIF is a TIFF structure: a byte-order mark, then image file directories
+//! (IFDs) of 12-byte entries. Only what the callers need is parsed; unknown or
+//! malformed data reads as "no value" rather than an error.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::format::Format;
+use crate::inspect::{Entry as ReportEntry, EntryClass, Section};
+use crate::processor::{jpeg, png};
+#[cfg(feature = "webp")]
+use crate::processor::webp;
+
+/// IFD0 tag pointing to the EXIF sub-IFD
+const EXIF_IFD_POINTER: u16 = 0x8769;
+/// IFD0 tag pointing to the GPS sub-IFD
+const GPS_IFD_POINTER: u16 = 0x8825;
+/// EXIF sub-IFD tag pointing to the interoperability IFD
+const INTEROP_IFD_POINTER: u16 = 0xA005;
+const MAKE: u16 = 0x010F;
+const MODEL: u16 = 0x0110;
+const DATE_TIME_DIGITIZED: u16 = 0x9004;
+const BODY_SERIAL_NUMBER: u16 = 0xA431;
+const LENS_MODEL: u16 = 0xA434;
+/// IFD0 last-modified date, used when the sub-IFD has no DateTimeOriginal
+const DATE_TIME: u16 = 0x0132;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+/// IFD0 display orientation, 1 (upright) to 8
+const ORIENTATION: u16 = 0x0112;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+/// EXIF sub-IFD tags `--strip safe` removes along with the GPS directory: those
+/// naming the camera, lens or owner. Maker notes go too: they often hold the
+/// serial number, and their internal offsets break once the block is rebuilt.
+const PRIVATE_EXIF_TAGS: [u16; 4] = [
+    0x927C, // MakerNote
+    0xA430, // CameraOwnerName
+    0xA431, // BodySerialNumber
+    0xA435, // LensSerialNumber
+];
+
+/// Calendar date a photo was taken (or a file modified)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CaptureDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl CaptureDate {
+    /// UTC date of `time`; None before 1970
+    pub fn from_system_time(time: SystemTime) -> Option<Self> {
+        let days = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 86_400;
+        Some(civil_from_days(days))
+    }
+}
+
+/// Date from `DateTimeOriginal`, falling back to the IFD0 `DateTime`. None when
+/// `data` has no EXIF block or neither tag holds a valid date.
+pub fn capture_date(data: &[u8], format: Format) -> Option<CaptureDate> {
+    let tiff = exif_block(data, format)?;
+    let reader = Tiff::new(tiff)?;
+    let ifd0 = reader.u32(4)? as usize;
+
+    let original = reader
+        .find(ifd0, EXIF_IFD_POINTER)
+        .filter(|entry| entry.kind == TYPE_LONG)
+        .and_then(|entry| reader.find(reader.u32(entry.value)? as usize, DATE_TIME_ORIGINAL))
+        .and_then(|entry| parse_date(reader.ascii(&entry)?));
+    original.or_else(|| parse_date(reader.ascii(&reader.find(ifd0, DATE_TIME)?)?))
+}
+
+/// IFD0 `Orientation`, 1–8: how the stored pixels are rotated or flipped for
+/// display. None when `data` has no EXIF block or no valid tag.
+pub fn orientation(data: &[u8], format: Format) -> Option<u16> {
+    let reader = Tiff::new(exif_block(data, format)?)?;
+    let entry = reader.find(reader.u32(4)? as usize, ORIENTATION).filter(|entry| entry.kind == TYPE_SHORT)?;
+    reader.u16(entry.value).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Where a photo was taken, in decimal degrees; south and west are negative
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl fmt::Display for GpsPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)
+    }
+}
+
+/// `GPSLatitude`/`GPSLongitude` with their N/S and E/W references. None when
+/// `data` has no EXIF block, no GPS directory or an incomplete position.
+pub fn gps_position(data: &[u8], format: Format) -> Option<GpsPosition> {
+    position(&Tiff::new(exif_block(data, format)?)?)
+}
+
+fn position(reader: &Tiff) -> Option<GpsPosition> {
+    let ifd0 = reader.u32(4)? as usize;
+    let gps = reader.u32(reader.find(ifd0, GPS_IFD_POINTER)?.value)? as usize;
+    // Degrees, minutes and seconds as three rationals; the reference is one letter
+    let coordinate = |reference: u16, tag: u16, negative: &str| {
+        let entry = reader.find(gps, tag).filter(|entry| entry.kind == TYPE_RATIONAL && entry.count == 3)?;
+        let start = reader.u32(entry.value)? as usize;
+        let rational = |i: usize| {
+            let denominator = reader.u32(start + 8 * i + 4).filter(|&d| d != 0)?;
+            Some(f64::from(reader.u32(start + 8 * i)?) / f64::from(denominator))
+        };
+        let degrees = rational(0)? + rational(1)? / 60.0 + rational(2)? / 3600.0;
+        let reference = reader.ascii(&reader.find(gps, reference)?)?;
+        Some(if reference.starts_with(negative) { -degrees } else { degrees })
+    };
+    Some(GpsPosition { latitude: coordinate(0x0001, 0x0002, "S")?, longitude: coordinate(0x0003, 0x0004, "W")? })
+}
+
+/// `inspect` section for an EXIF block: camera, lens, dates, serial number and
+/// position up front, then every tag of IFD0 and the EXIF, GPS and
+/// interoperability directories, marked by whether `--strip safe` keeps it.
+/// None when `tiff` is not a TIFF structure.
+pub(crate) fn exif_section(tiff: &[u8]) -> Option<Section> {
+    let reader = Tiff::new(tiff)?;
+    let ifd0 = reader.u32(4)? as usize;
+    let directories = directories(&reader, ifd0);
+    let text = |directory: Directory, tag: u16| {
+        let ifd = directories.iter().find(|(d, _)| *d == directory)?.1;
+        reader.ascii(&reader.find(ifd, tag)?).map(str::trim).filter(|text| !text.is_empty())
+    };
+
+    let mut section = Section::new("EXIF");
+    let camera = match (text(Directory::Image, MAKE), text(Directory::Image, MODEL)) {
+        // Most models already start with the make
+        (Some(make), Some(model)) if !model.starts_with(make) => Some(format!("{} {}", make, model)),
+        (make, model) => model.or(make).map(String::from),
+    };
+    if let Some(camera) = camera {
+        section.field("Camera", camera);
+    }
+    let labelled = [
+        ("Lens", Directory::Exif, LENS_MODEL),
+        ("Taken", Directory::Exif, DATE_TIME_ORIGINAL),
+        ("Digitized", Directory::Exif, DATE_TIME_DIGITIZED),
+        ("Modified", Directory::Image, DATE_TIME),
+        ("Serial number", Directory::Exif, BODY_SERIAL_NUMBER),
+    ];
+    for (label, directory, tag) in labelled {
+        if let Some(value) = text(directory, tag) {
+            section.field(label, value);
+        }
+    }
+    if let Some(position) = position(&reader) {
+        section.field("GPS position", position);
+    }
+
+    for &(directory, ifd) in &directories {
+        for (tag, entry) in reader.entries(ifd) {
+            let name = tag_name(directory, tag).map_or_else(|| format!("Tag 0x{:04X}", tag), String::from);
+            let mut item = ReportEntry::new(name);
+            item.description = Some(directory_name(directory).to_string());
+            let removed = removed_by_safe(directory, tag);
+            item.class = Some(if removed { EntryClass::Unsafe } else { EntryClass::Safe });
+            item.size = Some(value_size(&entry));
+            item.field("Value", describe_value(&reader, &entry));
+            match (directory, tag) {
+                (Directory::Gps, 0x0002 | 0x0004) => item.warnings.push("Location of the capture".to_string()),
+                (Directory::Exif, 0xA431 | 0xA435) => item.warnings.push("Identifies the device".to_string()),
+                _ => {}
+            }
+            section.entries.push(item);
+        }
+    }
+    let removed = section.entries.iter().filter(|entry| entry.class == Some(EntryClass::Unsafe)).count();
+    section.summary = Some(format!("{} tags, {} removed by --strip safe", section.entries.len(), removed));
+    Some(section)
+}
+
+/// `tiff` rebuilt without what tells where a photo was taken or with which
+/// device: the GPS directory and [`PRIVATE_EXIF_TAGS`]. IFD0, the EXIF directory
+/// and its interoperability directory keep every other tag, so orientation and
+/// color space survive. The thumbnail (IFD1) is dropped as well, since it can
+/// show what a crop removed. None when the TIFF structure cannot be read.
+pub(crate) fn strip_private(tiff: &[u8]) -> Option<Vec<u8>> {
+    let reader = Tiff::new(tiff)?;
+    let ifd0 = reader.u32(4)? as usize;
+    let mut writer = IfdWriter { reader, out: tiff[..4].to_vec() };
+    writer.out.extend_from_slice(&[0; 4]);
+    let offset = writer.copy_ifd(ifd0, Directory::Image)?;
+    writer.put_u32(4, offset);
+    Some(writer.out)
+}
+
+/// Whether `--strip safe` drops a tag from a JPEG's EXIF block
+fn removed_by_safe(directory: Directory, tag: u16) -> bool {
+    match directory {
+        Directory::Image => tag == GPS_IFD_POINTER,
+        Directory::Exif => PRIVATE_EXIF_TAGS.contains(&tag),
+        Directory::Gps => true,
+        Directory::Interop => false,
+    }
+}
+
+/// IFD0 and the sub-IFDs reachable from it, with their offsets
+fn directories(reader: &Tiff, ifd0: usize) -> Vec<(Directory, usize)> {
+    let pointer = |ifd: usize, tag: u16| {
+        let entry = reader.find(ifd, tag)?;
+        reader.u32(entry.value).map(|offset| offset as usize)
+    };
+    let mut directories = vec![(Directory::Image, ifd0)];
+    if let Some(exif) = pointer(ifd0, EXIF_IFD_POINTER) {
+        directories.push((Directory::Exif, exif));
+        if let Some(interop) = pointer(exif, INTEROP_IFD_POINTER) {
+            directories.push((Directory::Interop, interop));
+        }
+    }
+    if let Some(gps) = pointer(ifd0, GPS_IFD_POINTER) {
+        directories.push((Directory::Gps, gps));
+    }
+    directories
+}
+
+fn directory_name(directory: Directory) -> &'static str {
+    match directory {
+        Directory::Image => "IFD0",
+        Directory::Exif => "EXIF",
+        Directory::Gps => "GPS",
+        Directory::Interop => "Interoperability",
+    }
+}
+
+/// Builds a TIFF structure by copying directories out of another, in its byte order
+struct IfdWriter<'a> {
+    reader: Tiff<'a>,
+    out: Vec<u8>,
+}
+
+impl IfdWriter<'_> {
+    fn put_u16(&mut self, at: usize, value: u16) {
+        let bytes = if self.reader.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.out[at..at + 2].copy_from_slice(&bytes);
+    }
+
+    fn put_u32(&mut self, at: usize, value: u32) {
+        let bytes = if self.reader.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.out[at..at + 4].copy_from_slice(&bytes);
+    }
+
+    /// Offset of the next write, on a word boundary as TIFF requires
+    fn aligned_end(&mut self) -> usize {
+        if self.out.len() % 2 == 1 {
+            self.out.push(0);
+        }
+        self.out.len()
+    }
+
+    /// Copy the IFD at `ifd`, less the tags safe stripping removes, with its
+    /// out-of-line values and sub-IFDs after it. Returns its new offset; the
+    /// copy has no next IFD.
+    fn copy_ifd(&mut self, ifd: usize, directory: Directory) -> Option<u32> {
+        let data = self.reader.data;
+        let entries: Vec<(u16, Entry)> =
+            self.reader.entries(ifd).filter(|(tag, _)| !removed_by_safe(directory, *tag)).collect();
+        let start = self.aligned_end();
+        self.out.resize(start + 2 + 12 * entries.len() + 4, 0);
+        self.put_u16(start, entries.len() as u16);
+
+        for (index, (tag, entry)) in entries.iter().enumerate() {
+            let at = start + 2 + 12 * index;
+            // Tag, type and count as they were
+            self.out[at..at + 8].copy_from_slice(data.get(entry.value - 8..entry.value)?);
+            let sub = match (directory, *tag) {
+                (Directory::Image, EXIF_IFD_POINTER) => Some(Directory::Exif),
+                (Directory::Exif, INTEROP_IFD_POINTER) => Some(Directory::Interop),
+                _ => None,
+            };
+            if let Some(sub) = sub {
+                let offset = self.copy_ifd(self.reader.u32(entry.value)? as usize, sub)?;
+                self.put_u32(at + 8, offset);
+            } else if value_size(entry) <= 4 {
+                self.out[at + 8..at + 12].copy_from_slice(data.get(entry.value..entry.value + 4)?);
+            } else {
+                let source = self.reader.u32(entry.value)? as usize;
+                let value = data.get(source..source.checked_add(value_size(entry) as usize)?)?;
+                let offset = self.aligned_end();
+                self.out.extend_from_slice(value);
+                self.put_u32(at + 8, offset as u32);
+            }
+        }
+        Some(start as u32)
+    }
+}
+
+/// Smallest EXIF TIFF that still tells viewers how to display the image: IFD0
+/// with only `Orientation`
+pub(crate) fn orientation_block(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0*".to_vec();
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&ORIENTATION.to_be_bytes());
+    tiff.extend_from_slice(&TYPE_SHORT.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0; 2]);
+    // No next IFD
+    tiff.extend_from_slice(&[0; 4]);
+    tiff
+}
+
+/// The TIFF data of the EXIF block in `data`
+fn exif_block(data: &[u8], format: Format) -> Option<&[u8]> {
+    match format {
+        Format::Jpeg => jpeg::exif_payload(data),
+        Format::Png => png::find_chunk(data, b"eXIf"),
+        // Some writers keep the JPEG-style prefix
+        #[cfg(feature = "webp")]
+        Format::Webp => webp::find_chunk(data, b"EXIF").map(|chunk| chunk.strip_prefix(b"Exif\0\0").unwrap_or(chunk)),
+        Format::Tiff => Some(data),
+        _ => None,
+    }
+}
+
+/// An IFD entry; `value` is the offset of the 4-byte value field
+pub(crate) struct Entry {
+    pub(crate) kind: u16,
+    pub(crate) count: u32,
+    pub(crate) value: usize,
+}
+
+/// Classic (32-bit offset) TIFF structure, shared with [`crate::tiff`]
+pub(crate) struct Tiff<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Self { data, big_endian })
+    }
+
+    pub(crate) fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = [*self.data.get(offset)?, *self.data.get(offset + 1)?];
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    pub(crate) fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    /// Entry `tag` of the IFD at `ifd`
+    pub(crate) fn find(&self, ifd: usize, tag: u16) -> Option<Entry> {
+        self.entries(ifd).find(|(entry_tag, _)| *entry_tag == tag).map(|(_, entry)| entry)
+    }
+
+    /// Tags and entries of the IFD at `ifd`, stopping at the end of the data
+    pub(crate) fn entries(&self, ifd: usize) -> impl Iterator<Item = (u16, Entry)> + '_ {
+        let count = self.u16(ifd).map_or(0, usize::from);
+        (0..count).map_while(move |i| {
+            let entry = ifd + 2 + 12 * i;
+            let kind = self.u16(entry + 2)?;
+            Some((self.u16(entry)?, Entry { kind, count: self.u32(entry + 4)?, value: entry + 8 }))
+        })
+    }
+
+    /// Offset of the IFD after the one at `ifd`; None at the end of the chain
+    pub(crate) fn next_ifd(&self, ifd: usize) -> Option<usize> {
+        let count = usize::from(self.u16(ifd)?);
+        let next = self.u32(ifd + 2 + 12 * count)? as usize;
+        (next != 0).then_some(next)
+    }
+
+    /// ASCII value without its NUL terminator; values over 4 bytes live at an offset
+    pub(crate) fn ascii(&self, entry: &Entry) -> Option<&'a str> {
+        if entry.kind != TYPE_ASCII {
+            return None;
+        }
+        let len = entry.count as usize;
+        let start = if len <= 4 { entry.value } else { self.u32(entry.value)? as usize };
+        let bytes = self.data.get(start..start.checked_add(len)?)?;
+        std::str::from_utf8(bytes).ok().map(|text| text.trim_end_matches('\0'))
+    }
+}
+
+/// Which directory a tag sits in, for naming it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Directory {
+    /// IFD0: the image (or a TIFF page)
+    Image,
+    Exif,
+    Gps,
+    /// Interoperability directory, which says which color space rules the file follows
+    Interop,
+}
+
+/// Bytes of an entry's value (`count` times the type's size)
+pub(crate) fn value_size(entry: &Entry) -> u64 {
+    let unit = match entry.kind {
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    };
+    entry.count as u64 * unit
+}
+
+/// Where an entry's value starts: in the entry when it fits in 4 bytes, else at the offset stored there
+fn value_offset(tiff: &Tiff, entry: &Entry) -> Option<usize> {
+    if value_size(entry) <= 4 {
+        Some(entry.value)
+    } else {
+        tiff.u32(entry.value).map(|offset| offset as usize)
+    }
+}
+
+/// Display text for a value: text for ASCII, up to 8 numbers or 4 rationals,
+/// else the value's size
+pub(crate) fn describe_value(tiff: &Tiff, entry: &Entry) -> String {
+    if let Some(text) = tiff.ascii(entry) {
+        return text.to_string();
+    }
+    let Some(start) = value_offset(tiff, entry) else {
+        return format!("{} bytes (out of range)", value_size(entry));
+    };
+    let count = entry.count as usize;
+    let values: Option<Vec<String>> = match entry.kind {
+        3 if count <= 8 => (0..count).map(|i| tiff.u16(start + 2 * i).map(|v| v.to_string())).collect(),
+        4 if count <= 8 => (0..count).map(|i| tiff.u32(start + 4 * i).map(|v| v.to_string())).collect(),
+        5 if count <= 4 => (0..count)
+            .map(|i| Some(format!("{}/{}", tiff.u32(start + 8 * i)?, tiff.u32(start + 8 * i + 4)?)))
+            .collect(),
+        _ => None,
+    };
+    match values {
+        Some(values) => values.join(", "),
+        None if matches!(entry.kind, 3..=5) => format!("{} values", count),
+        None => format!("{} bytes", value_size(entry)),
+    }
+}
+
+/// Name of the common tags of each directory
+pub(crate) fn tag_name(directory: Directory, tag: u16) -> Option<&'static str> {
+    let name = match (directory, tag) {
+        (Directory::Gps, 0x0000) => "GPSVersionID",
+        (Directory::Gps, 0x0001) => "GPSLatitudeRef",
+        (Directory::Gps, 0x0002) => "GPSLatitude",
+        (Directory::Gps, 0x0003) => "GPSLongitudeRef",
+        (Directory::Gps, 0x0004) => "GPSLongitude",
+        (Directory::Gps, 0x0005) => "GPSAltitudeRef",
+        (Directory::Gps, 0x0006) => "GPSAltitude",
+        (Directory::Gps, 0x0007) => "GPSTimeStamp",
+        (Directory::Gps, 0x0010) => "GPSImgDirectionRef",
+        (Directory::Gps, 0x0011) => "GPSImgDirection",
+        (Directory::Gps, 0x0012) => "GPSMapDatum",
+        (Directory::Gps, 0x001B) => "GPSProcessingMethod",
+        (Directory::Gps, 0x001D) => "GPSDateStamp",
+        (Directory::Gps, _) => return None,
+        (Directory::Interop, 0x0001) => "InteroperabilityIndex",
+        (Directory::Interop, 0x0002) => "InteroperabilityVersion",
+        (Directory::Interop, _) => return None,
+        (_, 0x00FE) => "NewSubfileType",
+        (_, 0x0100) => "ImageWidth",
+        (_, 0x0101) => "ImageLength",
+        (_, 0x0102) => "BitsPerSample",
+        (_, 0x0103) => "Compression",
+        (_, 0x0106) => "PhotometricInterpretation",
+        (_, 0x010D) => "DocumentName",
+        (_, 0x010E) => "ImageDescription",
+        (_, 0x010F) => "Make",
+        (_, 0x0110) => "Model",
+        (_, 0x0111) => "StripOffsets",
+        (_, 0x0112) => "Orientation",
+        (_, 0x0115) => "SamplesPerPixel",
+        (_, 0x0116) => "RowsPerStrip",
+        (_, 0x0117) => "StripByteCounts",
+        (_, 0x011A) => "XResolution",
+        (_, 0x011B) => "YResolution",
+        (_, 0x011C) => "PlanarConfiguration",
+        (_, 0x0128) => "ResolutionUnit",
+        (_, 0x0129) => "PageNumber",
+        (_, 0x0131) => "Software",
+        (_, 0x0132) => "DateTime",
+        (_, 0x013B) => "Artist",
+        (_, 0x013C) => "HostComputer",
+        (_, 0x013D) => "Predictor",
+        (_, 0x0140) => "ColorMap",
+        (_, 0x0142) => "TileWidth",
+        (_, 0x0143) => "TileLength",
+        (_, 0x0144) => "TileOffsets",
+        (_, 0x0145) => "TileByteCounts",
+        (_, 0x0152) => "ExtraSamples",
+        (_, 0x0153) => "SampleFormat",
+        (_, 0x0201) => "JPEGInterchangeFormat",
+        (_, 0x0202) => "JPEGInterchangeFormatLength",
+        (_, 0x0213) => "YCbCrPositioning",
+        (_, 0x02BC) => "XMP",
+        (_, 0x8298) => "Copyright",
+        (_, 0x829A) => "ExposureTime",
+        (_, 0x829D) => "FNumber",
+        (_, 0x83BB) => "IPTC",
+        (_, 0x8649) => "Photoshop",
+        (_, 0x8769) => "ExifIFD",
+        (_, 0x8773) => "ICCProfile",
+        (_, 0x8825) => "GPSIFD",
+        (_, 0x8822) => "ExposureProgram",
+        (_, 0x8827) => "ISOSpeedRatings",
+        (_, 0x9000) => "ExifVersion",
+        (_, 0x9003) => "DateTimeOriginal",
+        (_, 0x9004) => "DateTimeDigitized",
+        (_, 0x9010) => "OffsetTime",
+        (_, 0x9011) => "OffsetTimeOriginal",
+        (_, 0x9101) => "ComponentsConfiguration",
+        (_, 0x9209) => "Flash",
+        (_, 0x920A) => "FocalLength",
+        (_, 0x927C) => "MakerNote",
+        (_, 0x9286) => "UserComment",
+        (_, 0xA000) => "FlashpixVersion",
+        (_, 0xA001) => "ColorSpace",
+        (_, 0xA002) => "PixelXDimension",
+        (_, 0xA003) => "PixelYDimension",
+        (_, 0xA005) => "InteroperabilityIFD",
+        (_, 0xA420) => "ImageUniqueID",
+        (_, 0xA430) => "CameraOwnerName",
+        (_, 0xA431) => "BodySerialNumber",
+        (_, 0xA433) => "LensMake",
+        (_, 0xA434) => "LensModel",
+        (_, 0xA435) => "LensSerialNumber",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// `YYYY:MM:DD HH:MM:SS`; cameras without a clock write zeros or blanks
+fn parse_date(text: &str) -> Option<CaptureDate> {
+    let mut parts = text.get(..10)?.split(':');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(CaptureDate { year, month, day })
+}
+
+/// Gregorian date `days` after 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_from_days(days: u64) -> CaptureDate {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = (year_of_era + era * 400 + u64::from(month <= 2)) as u16;
+    CaptureDate { year, month, day }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little-endian TIFF with DateTime in IFD0 and DateTimeOriginal in the EXIF sub-IFD
+    fn tiff(original: &[u8; 20]) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 at 8: two entries, then no next IFD; data from 38
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend_from_slice(&[0x32, 0x01, 2, 0, 20, 0, 0, 0, 38, 0, 0, 0]);
+        tiff.extend_from_slice(&[0x69, 0x87, 4, 0, 1, 0, 0, 0, 58, 0, 0, 0]);
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"2019:01:01 00:00:00\0");
+        // EXIF IFD at 58: one entry, value at 76
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&[0x03, 0x90, 2, 0, 20, 0, 0, 0, 76, 0, 0, 0]);
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(original);
+        tiff
+    }
+
+    /// Little-endian TIFF: IFD0 with Make, Orientation 6 and the EXIF and GPS
+    /// pointers; EXIF with ColorSpace and a serial number; GPS with a position
+    fn private_tiff() -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            [&tag.to_le_bytes()[..], &kind.to_le_bytes(), &count.to_le_bytes(), &value.to_le_bytes()].concat()
+        };
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 at 8, Make at 62
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend(entry(MAKE, TYPE_ASCII, 6, 62));
+        tiff.extend(entry(ORIENTATION, TYPE_SHORT, 1, 6));
+        tiff.extend(entry(EXIF_IFD_POINTER, TYPE_LONG, 1, 68));
+        tiff.extend(entry(GPS_IFD_POINTER, TYPE_LONG, 1, 104));
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"Canon\0");
+        // EXIF at 68, serial number at 98
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend(entry(0xA001, TYPE_SHORT, 1, 1));
+        tiff.extend(entry(BODY_SERIAL_NUMBER, TYPE_ASCII, 6, 98));
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"12345\0");
+        // GPS at 104: 48°51'30" N, 2°17'40.5" W at 158 and 182
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend(entry(0x0001, TYPE_ASCII, 2, u32::from_le_bytes(*b"N\0\0\0")));
+        tiff.extend(entry(0x0002, TYPE_RATIONAL, 3, 158));
+        tiff.extend(entry(0x0003, TYPE_ASCII, 2, u32::from_le_bytes(*b"W\0\0\0")));
+        tiff.extend(entry(0x0004, TYPE_RATIONAL, 3, 182));
+        tiff.extend_from_slice(&[0; 4]);
+        for value in [48u32, 1, 51, 1, 3000, 100, 2, 1, 17, 1, 4050, 100] {
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        tiff
+    }
+
+    #[test]
+    fn test_strip_private() {
+        let tiff = private_tiff();
+        let position = gps_position(&tiff, Format::Tiff).unwrap();
+        assert_eq!(position.to_string(), "48.858333, -2.294583");
+
+        let section = exif_section(&tiff).unwrap();
+        let field = |label: &str| section.fields.iter().find(|field| field.label == label).map(|f| f.value.clone());
+        assert_eq!(field("Camera").as_deref(), Some("Canon"));
+        assert_eq!(field("Serial number").as_deref(), Some("12345"));
+        assert_eq!(field("GPS position").as_deref(), Some("48.858333, -2.294583"));
+        assert_eq!(section.summary.as_deref(), Some("10 tags, 6 removed by --strip safe"));
+
+        let stripped = strip_private(&tiff).unwrap();
+        let reader = Tiff::new(&stripped).unwrap();
+        let ifd0 = reader.u32(4).unwrap() as usize;
+        assert_eq!(gps_position(&stripped, Format::Tiff), None);
+        assert_eq!(orientation(&stripped, Format::Tiff), Some(6));
+        assert_eq!(reader.ascii(&reader.find(ifd0, MAKE).unwrap()), Some("Canon"));
+        let exif = reader.u32(reader.find(ifd0, EXIF_IFD_POINTER).unwrap().value).unwrap() as usize;
+        assert_eq!(reader.entries(exif).map(|(tag, _)| tag).collect::<Vec<_>>(), [0xA001]);
+        assert_eq!(reader.u16(reader.find(exif, 0xA001).unwrap().value), Some(1));
+        assert_eq!(strip_private(b"not a tiff"), None);
+    }
+
+    #[test]
+    fn test_capture_date() {
+        let image = image::RgbImage::new(8, 8);
+        let mut plain = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut plain).encode_image(&image).unwrap();
+        let with_exif = |tiff: Vec<u8>| {
+            let mut jpeg = plain[..2].to_vec();
+            jpeg.extend_from_slice(&[0xFF, 0xE1]);
+            jpeg.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+            jpeg.extend_from_slice(b"Exif\0\0");
+            jpeg.extend_from_slice(&tiff);
+            jpeg.extend_from_slice(&plain[2..]);
+            jpeg
+        };
+
+        let jpeg = with_exif(tiff(b"2023:07:14 18:02:55\0"));
+        assert_eq!(capture_date(&jpeg, Format::Jpeg), Some(CaptureDate { year: 2023, month: 7, day: 14 }));
+        // Blank DateTimeOriginal: IFD0 DateTime instead
+        let jpeg = with_exif(tiff(b"0000:00:00 00:00:00\0"));
+        assert_eq!(capture_date(&jpeg, Format::Jpeg), Some(CaptureDate { year: 2019, month: 1, day: 1 }));
+        assert_eq!(capture_date(&plain, Format::Jpeg), None);
+
+        let leap_day = UNIX_EPOCH + std::time::Duration::from_secs(951_782_400);
+        assert_eq!(CaptureDate::from_system_time(leap_day), Some(CaptureDate { year: 2000, month: 2, day: 29 }));
+    }
+}
@@ -0,0 +1,225 @@
+//! `image_preparer explain`: what `compress` would do to each file, worked out
+//! without encoding or writing anything. Shows the processor or chain that takes
+//! the file, the settings in effect for its format once per-format overrides are
+//! applied, whether ffmpeg runs, which metadata blocks the strip mode removes and
+//! why a file would be left alone.
+
+use std::path::{Path, PathBuf};
+
+use crate::chain::{Step, StripStep};
+use crate::config::{ProcessingConfig, SilenceTrim, StripMode};
+use crate::format::Format;
+use crate::metadata::{metadata_blocks, removed_blocks, MetadataBlock};
+use crate::pipeline::Pipeline;
+use crate::processor::{is_ffmpeg_available, Capabilities};
+use crate::progress::Progress;
+use crate::report::format_size;
+use crate::term::{self, Color};
+
+/// What takes a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Handler {
+    /// The processor registered for the format
+    Processor(Capabilities),
+    /// A chain set for the format, by step name
+    Chain(Vec<String>),
+    /// Nothing handles the format in this build
+    None,
+}
+
+/// What `compress` would do to one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePlan {
+    pub path: PathBuf,
+    pub format: Format,
+    pub handler: Handler,
+    /// What the processor does with these settings, e.g. "strip tags; audio frames copied untouched"
+    pub action: String,
+    /// Settings in effect for the format, per-format overrides applied
+    pub settings: Vec<(&'static str, String)>,
+    /// What ffmpeg would run for; empty when it is not needed
+    pub ffmpeg: Vec<&'static str>,
+    /// Whether ffmpeg was found (always false without the `ffmpeg` feature)
+    pub ffmpeg_found: bool,
+    /// Metadata blocks the strip mode removes; chains are not previewed
+    pub stripped: Vec<MetadataBlock>,
+    /// Metadata blocks that stay
+    pub kept: Vec<MetadataBlock>,
+    /// Why the file would be left alone or fail, if it would
+    pub skip: Option<String>,
+}
+
+/// Plan for `data`, read from `path`. Only the metadata strip is tried out, in
+/// memory; nothing is encoded and ffmpeg is not run.
+pub fn explain(pipeline: &Pipeline, path: &Path, data: &[u8], format: Format, config: &ProcessingConfig) -> FilePlan {
+    let mut plan = FilePlan {
+        path: path.to_path_buf(),
+        format,
+        handler: Handler::None,
+        action: String::new(),
+        settings: Vec::new(),
+        ffmpeg: Vec::new(),
+        ffmpeg_found: is_ffmpeg_available(),
+        stripped: Vec::new(),
+        kept: Vec::new(),
+        skip: None,
+    };
+
+    if let Some(chain) = pipeline.chain(format) {
+        let steps: Vec<String> = chain.step_names().into_iter().map(String::from).collect();
+        plan.action = format!("run the chain {}", steps.join(" → "));
+        plan.settings.push(("quality", quality(config, format)));
+        plan.settings.push(("strip", strip_name(config.strip).to_string()));
+        plan.handler = Handler::Chain(steps);
+    } else if let Some(capabilities) = pipeline.capabilities(format) {
+        plan.handler = Handler::Processor(capabilities);
+        // The processor's own decisions for this input and config
+        let decided = pipeline.plan(format, data, config).unwrap_or_default();
+        plan.action = decided.action;
+        plan.settings = decided.settings;
+        plan.settings.push(("strip", strip_name(config.strip).to_string()));
+        plan.ffmpeg = decided.ffmpeg;
+        plan.skip = decided.skip;
+        preview_strip(&mut plan, data, config);
+    } else {
+        plan.action = "none".to_string();
+        plan.skip = Some(format!("no processor for {} files", format.as_str()));
+        return plan;
+    }
+
+    if let Err(e) = config.limits.check_input(data.len()) {
+        plan.skip = Some(e.to_string());
+    }
+    plan
+}
+
+/// Run only the metadata strip to see which blocks it removes. A strip that
+/// fails here would fail the real run too.
+fn preview_strip(plan: &mut FilePlan, data: &[u8], config: &ProcessingConfig) {
+    let before = metadata_blocks(data, plan.format);
+    if config.strip == StripMode::None || before.is_empty() {
+        plan.kept = before;
+        return;
+    }
+
+    // The strip alone: re-encoding, ReplayGain and silence trimming are ffmpeg runs
+    let mut strip_config = config.clone();
+    strip_config.mp3.bitrate = None;
+    strip_config.mp3.vbr = false;
+    strip_config.mp3.replaygain = false;
+    strip_config.trim_silence = None;
+    match StripStep.apply(data, plan.format, &strip_config, &Progress::none()) {
+        Ok((stripped, format)) => {
+            let after = metadata_blocks(&stripped, format);
+            plan.stripped = removed_blocks(&before, &after);
+            plan.kept = after;
+        }
+        Err(e) => plan.skip = Some(format!("would fail: {}", e)),
+    }
+}
+
+/// The quality setting for `format`, or the SSIM target when that tunes it
+pub(crate) fn quality(config: &ProcessingConfig, format: Format) -> String {
+    match config.target_ssim {
+        // compress keeps JPEGs at their own quality
+        Some(ssim) if matches!(format, Format::Png | Format::Webp | Format::Avif) => {
+            format!("lowest reaching SSIM {}", ssim)
+        }
+        _ => config.quality_for(format).to_string(),
+    }
+}
+
+fn strip_name(strip: StripMode) -> &'static str {
+    match strip {
+        StripMode::All => "all",
+        StripMode::Safe => "safe",
+        StripMode::None => "none",
+    }
+}
+
+pub(crate) fn silence(trim: &SilenceTrim) -> String {
+    format!("below {} dB for {:.1}s or longer", trim.threshold_db, trim.min_duration.as_secs_f64())
+}
+
+/// `name (size), ...` or `none`
+fn block_list(blocks: &[MetadataBlock]) -> String {
+    if blocks.is_empty() {
+        return "none".to_string();
+    }
+    let names: Vec<String> =
+        blocks.iter().map(|block| format!("{} ({})", block.name, format_size(block.size))).collect();
+    names.join(", ")
+}
+
+pub fn print_plan(plan: &FilePlan) {
+    term::section(&plan.path.display().to_string());
+    term::field(2, "Format", plan.format.as_str());
+    let handler = match &plan.handler {
+        Handler::Processor(_) => format!("{} processor", plan.format.as_str()),
+        Handler::Chain(steps) => format!("chain ({})", steps.join(", ")),
+        Handler::None => term::paint("nothing", Color::Yellow),
+    };
+    term::field(2, "Handled by", handler);
+    term::field(2, "Action", &plan.action);
+    for (name, value) in &plan.settings {
+        term::field(4, name, value);
+    }
+
+    let ffmpeg = match (plan.ffmpeg.is_empty(), plan.ffmpeg_found) {
+        (true, _) => "not used".to_string(),
+        (false, true) => format!("used for {}", plan.ffmpeg.join(", ")),
+        (false, false) => term::paint(format!("needed for {}, but not found", plan.ffmpeg.join(", ")), Color::Yellow),
+    };
+    term::field(2, "ffmpeg", ffmpeg);
+
+    if matches!(plan.handler, Handler::Processor(_)) {
+        term::field(2, "Strips", block_list(&plan.stripped));
+        term::field(2, "Keeps", block_list(&plan.kept));
+    }
+    match &plan.skip {
+        Some(reason) => term::field(2, "Result", term::paint(format!("skipped: {}", reason), Color::Yellow)),
+        None => term::field(2, "Result", term::paint("processed", Color::Green)),
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1x1 PNG with a tEXt chunk and an sRGB chunk
+    fn png_with_text() -> Vec<u8> {
+        let mut png = Vec::new();
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255]));
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let png = crate::processor::png::replace_chunk(&png, b"tEXt", b"Author\0someone");
+        crate::processor::png::replace_chunk(&png, b"sRGB", &[0])
+    }
+
+    #[test]
+    fn test_explain_png() {
+        let pipeline = Pipeline::with_default_processors();
+        let config = ProcessingConfig { strip: StripMode::Safe, no_lossy: true, ..ProcessingConfig::default() };
+        let plan = explain(&pipeline, Path::new("a.png"), &png_with_text(), Format::Png, &config);
+
+        assert!(matches!(plan.handler, Handler::Processor(_)));
+        assert_eq!(plan.action, "optimize losslessly with oxipng");
+        assert!(plan.ffmpeg.is_empty());
+        let names = |blocks: &[MetadataBlock]| blocks.iter().map(|b| b.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&plan.stripped), ["tEXt"]);
+        assert_eq!(names(&plan.kept), ["sRGB"]);
+        assert_eq!(plan.skip, None);
+
+        // --max-pixels, as the processor checks it
+        let limits = crate::Limits { max_pixels: Some(0), ..crate::Limits::default() };
+        let config = ProcessingConfig { limits, ..config };
+        let plan = explain(&pipeline, Path::new("a.png"), &png_with_text(), Format::Png, &config);
+        assert!(plan.skip.unwrap().contains("limit is 0"));
+
+        let plan = explain(&pipeline, Path::new("a.flac"), b"", Format::Flac, &config);
+        assert_eq!(plan.handler, Handler::None);
+        assert!(plan.skip.is_some());
+    }
+}
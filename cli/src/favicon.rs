@@ -0,0 +1,260 @@
+//! Favicon sets: the icons browsers, iOS and Android look for, made from one
+//! logo, plus the web app manifest and the `<link>` tags that announce them.
+//!
+//! Every PNG goes through the PNG pipeline (quantization and oxipng), and
+//! `favicon.ico` holds PNG-compressed 16, 32 and 48 px images.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::config::ProcessingConfig;
+use crate::converter::encode_image;
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::limits::decode_image;
+use crate::processor::png::PngProcessor;
+use crate::processor::ImageProcessor;
+
+/// PNG icons on a transparent background: file name and edge length
+const PNG_ICONS: [(&str, u32); 4] = [
+    ("favicon-16x16.png", 16),
+    ("favicon-32x32.png", 32),
+    ("android-chrome-192x192.png", 192),
+    ("android-chrome-512x512.png", 512),
+];
+/// iOS fills transparency with black, so this one sits on the background color
+const APPLE_TOUCH_ICON: (&str, u32) = ("apple-touch-icon.png", 180);
+/// Android crops maskable icons to a shape inside the central 80% circle
+const MASKABLE_ICON: (&str, u32) = ("maskable-icon-512x512.png", 512);
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+/// Share of the maskable icon's edge the logo may fill
+const MASKABLE_SAFE_ZONE: f32 = 0.8;
+
+/// Name of the web app manifest in the set
+pub const MANIFEST_NAME: &str = "site.webmanifest";
+
+/// Colors and names used in the set
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaviconOptions {
+    /// Behind the Apple touch icon and the maskable icon, and the manifest's
+    /// `background_color` / `theme_color`
+    pub background: Rgba<u8>,
+    /// App name in the manifest
+    pub name: String,
+    /// URL path the files are served from, e.g. `/` or `/static/icons/`
+    pub base_url: String,
+}
+
+impl Default for FaviconOptions {
+    fn default() -> Self {
+        Self {
+            background: Rgba([0xff, 0xff, 0xff, 0xff]),
+            name: String::new(),
+            base_url: "/".to_string(),
+        }
+    }
+}
+
+/// One file of the set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaviconFile {
+    pub name: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Render the whole set from `input` (PNG, JPEG or WebP): `favicon.ico`, the PNG
+/// icons and [`MANIFEST_NAME`]. A non-square logo is centered on a square canvas.
+pub fn render_favicons(
+    input: &[u8],
+    options: &FaviconOptions,
+    config: &ProcessingConfig,
+) -> Result<Vec<FaviconFile>, ProcessingError> {
+    let logo = square(&decode_image(input, None, &config.limits)?.to_rgba8());
+    if logo.width() < 512 {
+        log::warn!("Logo is {} px wide; icons up to 512 px are upscaled from it", logo.width());
+    }
+    let optimized = |image: RgbaImage| -> Result<Vec<u8>, ProcessingError> {
+        let png = encode_image(&DynamicImage::ImageRgba8(image), Format::Png, config)?;
+        PngProcessor.process(&png, config)
+    };
+
+    let ico_images = ICO_SIZES
+        .iter()
+        .map(|&size| Ok((size, optimized(resize(&logo, size))?)))
+        .collect::<Result<Vec<_>, ProcessingError>>()?;
+    let mut files = vec![FaviconFile { name: "favicon.ico", data: ico(&ico_images) }];
+
+    for (name, size) in PNG_ICONS {
+        files.push(FaviconFile { name, data: optimized(resize(&logo, size))? });
+    }
+    let (name, size) = APPLE_TOUCH_ICON;
+    files.push(FaviconFile { name, data: optimized(on_background(&logo, size, 1.0, options.background))? });
+    let (name, size) = MASKABLE_ICON;
+    files.push(FaviconFile {
+        name,
+        data: optimized(on_background(&logo, size, MASKABLE_SAFE_ZONE, options.background))?,
+    });
+    files.push(FaviconFile { name: MANIFEST_NAME, data: web_manifest(options).into_bytes() });
+    Ok(files)
+}
+
+/// `<link>` tags for the set, ready to paste into `<head>`
+pub fn link_tags(options: &FaviconOptions) -> String {
+    let base = html_attribute(&base_url(options));
+    format!(
+        "<link rel=\"icon\" href=\"{base}favicon.ico\" sizes=\"16x16 32x32 48x48\">\n\
+         <link rel=\"icon\" type=\"image/png\" sizes=\"32x32\" href=\"{base}favicon-32x32.png\">\n\
+         <link rel=\"icon\" type=\"image/png\" sizes=\"16x16\" href=\"{base}favicon-16x16.png\">\n\
+         <link rel=\"apple-touch-icon\" href=\"{base}{}\">\n\
+         <link rel=\"manifest\" href=\"{base}{}\">\n\
+         <meta name=\"theme-color\" content=\"{}\">\n",
+        APPLE_TOUCH_ICON.0,
+        MANIFEST_NAME,
+        hex(options.background),
+    )
+}
+
+/// `site.webmanifest` listing the Android and maskable icons
+fn web_manifest(options: &FaviconOptions) -> String {
+    let base = base_url(options);
+    let name = json_string(&options.name);
+    let color = hex(options.background);
+    let icon = |(file, size): (&str, u32), purpose: &str| {
+        let src = json_string(&format!("{base}{file}"));
+        format!("    {{ \"src\": {src}, \"sizes\": \"{size}x{size}\", \"type\": \"image/png\"{purpose} }}")
+    };
+    let icons = [
+        icon(PNG_ICONS[2], ""),
+        icon(PNG_ICONS[3], ""),
+        icon(MASKABLE_ICON, ", \"purpose\": \"maskable\""),
+    ];
+    format!(
+        "{{\n  \"name\": {name},\n  \"short_name\": {name},\n  \"icons\": [\n{}\n  ],\n  \
+         \"theme_color\": \"{color}\",\n  \"background_color\": \"{color}\",\n  \"display\": \"standalone\"\n}}\n",
+        icons.join(",\n")
+    )
+}
+
+/// `base_url` with exactly one trailing slash, unescaped
+fn base_url(options: &FaviconOptions) -> String {
+    format!("{}/", options.base_url.trim_end_matches('/'))
+}
+
+/// `text` escaped for a double-quoted HTML attribute
+fn html_attribute(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+/// `#rrggbb`; the manifest and theme-color ignore alpha
+fn hex(color: Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Center `image` on a transparent square canvas
+fn square(image: &RgbaImage) -> RgbaImage {
+    let edge = image.width().max(image.height());
+    if image.width() == image.height() {
+        return image.clone();
+    }
+    let mut canvas = RgbaImage::new(edge, edge);
+    let (x, y) = ((edge - image.width()) / 2, (edge - image.height()) / 2);
+    imageops::overlay(&mut canvas, image, i64::from(x), i64::from(y));
+    canvas
+}
+
+fn resize(logo: &RgbaImage, size: u32) -> RgbaImage {
+    imageops::resize(logo, size, size, FilterType::Lanczos3)
+}
+
+/// `logo` scaled to `scale` of a `size` px square filled with `background`
+fn on_background(logo: &RgbaImage, size: u32, scale: f32, background: Rgba<u8>) -> RgbaImage {
+    let inner = ((size as f32 * scale).round() as u32).clamp(1, size);
+    let mut canvas = RgbaImage::from_pixel(size, size, background);
+    let offset = i64::from((size - inner) / 2);
+    imageops::overlay(&mut canvas, &resize(logo, inner), offset, offset);
+    canvas
+}
+
+/// ICO container holding PNG-compressed images, smallest first
+fn ico(images: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut ico = vec![0, 0, 1, 0];
+    ico.extend_from_slice(&(images.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * images.len();
+    for (size, png) in images {
+        // 0 stands for 256
+        let edge = if *size >= 256 { 0 } else { *size as u8 };
+        ico.extend_from_slice(&[edge, edge, 0, 0]);
+        ico.extend_from_slice(&1u16.to_le_bytes()); // color planes
+        ico.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+        ico.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        ico.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += png.len();
+    }
+    for (_, png) in images {
+        ico.extend_from_slice(png);
+    }
+    ico
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_favicons() {
+        let logo = RgbaImage::from_fn(600, 300, |x, _| Rgba([(x % 256) as u8, 40, 200, 255]));
+        let mut png = Vec::new();
+        logo.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let options = FaviconOptions { name: "My \"App\"".to_string(), ..FaviconOptions::default() };
+
+        let files = render_favicons(&png, &options, &ProcessingConfig::default()).unwrap();
+        let file = |name: &str| &files.iter().find(|f| f.name == name).unwrap().data;
+
+        let icon = image::load_from_memory(file("android-chrome-192x192.png")).unwrap();
+        assert_eq!((icon.width(), icon.height()), (192, 192));
+        // Letterboxed: transparent above the logo
+        assert_eq!(icon.to_rgba8().get_pixel(96, 2)[3], 0);
+        // Opaque on the background color
+        let touch = image::load_from_memory(file("apple-touch-icon.png")).unwrap().to_rgba8();
+        assert_eq!(touch.get_pixel(90, 2), &Rgba([255, 255, 255, 255]));
+
+        let ico = file("favicon.ico");
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 3, 0]);
+        // Third directory entry: 48 px, data at the recorded offset
+        let entry = &ico[6 + 32..6 + 48];
+        assert_eq!(entry[0], 48);
+        let (len, offset) = (
+            u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize,
+            u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize,
+        );
+        assert_eq!(offset + len, ico.len());
+        assert_eq!(image::load_from_memory(&ico[offset..]).unwrap().width(), 48);
+
+        let manifest = String::from_utf8(file(MANIFEST_NAME).clone()).unwrap();
+        assert!(manifest.contains(r#""name": "My \"App\"""#));
+        assert!(manifest.contains(r#""purpose": "maskable""#));
+        assert!(link_tags(&options).contains(r#"href="/apple-touch-icon.png""#));
+    }
+
+    #[test]
+    fn test_base_url_escaping() {
+        let base_url = "https://cdn.test/i?v=2&t=\"x\"".to_string();
+        let options = FaviconOptions { base_url, ..FaviconOptions::default() };
+        assert!(link_tags(&options).contains(r#"href="https://cdn.test/i?v=2&amp;t=&quot;x&quot;/favicon.ico""#));
+        let manifest = web_manifest(&options);
+        assert!(manifest.contains(r#""src": "https://cdn.test/i?v=2&t=\"x\"/android-chrome-192x192.png""#));
+    }
+}
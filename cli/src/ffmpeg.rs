@@ -0,0 +1,787 @@
+//! Locating the ffmpeg binary and probing what it can do.
+//!
+//! The binary is, in order of precedence, the path given to [`set_binary`],
+//! the `IP_FFMPEG` environment variable, or `ffmpeg` on `PATH`. The probe
+//! (version and encoder list) runs once per process and is shared by every
+//! video and audio code path. `ffprobe` is looked up next to that binary.
+//!
+//! Encodes go through [`run`], which caps how many ffmpeg processes run at once
+//! independently of the rayon pool ([`set_max_jobs`]), kills a run that exceeds
+//! [`set_timeout`], and kills everything still running on [`cancel_all`].
+
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::config::SilenceTrim;
+use crate::error::ProcessingError;
+
+/// Environment variable naming the ffmpeg binary
+pub const FFMPEG_ENV: &str = "IP_FFMPEG";
+
+static BINARY: OnceLock<PathBuf> = OnceLock::new();
+static PROBE: OnceLock<Option<FfmpegInfo>> = OnceLock::new();
+
+/// Default cap on concurrent ffmpeg processes: each already uses several cores
+pub const DEFAULT_MAX_JOBS: usize = 2;
+
+static MAX_JOBS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_JOBS);
+/// Seconds, 0 for no timeout
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static SLOTS: Slots = Slots { running: Mutex::new(0), freed: Condvar::new() };
+static CHILDREN: Mutex<Vec<Arc<Mutex<Child>>>> = Mutex::new(Vec::new());
+
+/// What the local ffmpeg reported about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegInfo {
+    pub path: PathBuf,
+    /// Version string from `ffmpeg -version`, e.g. `6.1.1`
+    pub version: String,
+    /// Encoder names from `ffmpeg -encoders`; empty if the list could not be read
+    pub encoders: Vec<String>,
+}
+
+impl FfmpegInfo {
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.iter().any(|encoder| encoder == name)
+    }
+}
+
+/// Use `path` as the ffmpeg binary. Must be called before the first video is
+/// processed; returns false if the binary was already resolved.
+pub fn set_binary(path: impl Into<PathBuf>) -> bool {
+    BINARY.set(path.into()).is_ok()
+}
+
+/// The ffmpeg binary every command is spawned with
+pub fn binary() -> &'static Path {
+    BINARY.get_or_init(|| {
+        std::env::var_os(FFMPEG_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("ffmpeg"))
+    })
+}
+
+/// A [`Command`] for the configured ffmpeg binary
+pub fn command() -> Command {
+    Command::new(binary())
+}
+
+/// Run at most `jobs` ffmpeg processes at once (0 for no cap); callers beyond
+/// that wait for a slot. Applies to runs started after the call.
+pub fn set_max_jobs(jobs: usize) {
+    MAX_JOBS.store(jobs, Ordering::Relaxed);
+}
+
+/// The cap set by [`set_max_jobs`] (0 for none)
+pub fn max_jobs() -> usize {
+    MAX_JOBS.load(Ordering::Relaxed)
+}
+
+/// Kill an ffmpeg run that takes longer than `timeout` and fail it with
+/// [`ProcessingError::FfmpegTimedOut`]; None waits forever (the default)
+pub fn set_timeout(timeout: Option<Duration>) {
+    TIMEOUT_SECS.store(timeout.map_or(0, |timeout| timeout.as_secs().max(1)), Ordering::Relaxed);
+}
+
+fn timeout() -> Option<Duration> {
+    match TIMEOUT_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Kill every running ffmpeg process and refuse to start new ones; their runs
+/// fail with [`ProcessingError::Cancelled`]
+pub fn cancel_all() {
+    CANCELLED.store(true, Ordering::SeqCst);
+    SLOTS.freed.notify_all();
+    for child in CHILDREN.lock().unwrap().iter() {
+        let _ = child.lock().unwrap().kill();
+    }
+}
+
+fn check_cancelled() -> Result<(), ProcessingError> {
+    if CANCELLED.load(Ordering::SeqCst) {
+        Err(ProcessingError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Counting semaphore for [`set_max_jobs`]
+struct Slots {
+    running: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A claimed ffmpeg slot, given back on drop
+pub(crate) struct Slot;
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *SLOTS.running.lock().unwrap() -= 1;
+        SLOTS.freed.notify_one();
+    }
+}
+
+/// Wait for a free ffmpeg slot
+pub(crate) fn acquire_slot() -> Result<Slot, ProcessingError> {
+    let mut running = SLOTS.running.lock().unwrap();
+    loop {
+        check_cancelled()?;
+        let max = MAX_JOBS.load(Ordering::Relaxed);
+        if max == 0 || *running < max {
+            *running += 1;
+            return Ok(Slot);
+        }
+        running = SLOTS.freed.wait(running).unwrap();
+    }
+}
+
+/// A running child listed for [`cancel_all`] until dropped
+struct Registered(Arc<Mutex<Child>>);
+
+impl Registered {
+    fn new(child: Child) -> Self {
+        let child = Arc::new(Mutex::new(child));
+        CHILDREN.lock().unwrap().push(child.clone());
+        Self(child)
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        CHILDREN.lock().unwrap().retain(|child| !Arc::ptr_eq(child, &self.0));
+    }
+}
+
+/// Run `cmd` like [`Command::output`], within the concurrency cap and timeout
+pub(crate) fn run(cmd: &mut Command) -> Result<Output, ProcessingError> {
+    run_with_lines(cmd, |_| {})
+}
+
+/// [`run`], also handing each line ffmpeg writes to stdout to `on_line` as it
+/// arrives (for `-progress pipe:1`)
+pub(crate) fn run_with_lines(cmd: &mut Command, mut on_line: impl FnMut(&str)) -> Result<Output, ProcessingError> {
+    let (mut output, stdout) = run_with_stdout(cmd, |pipe| {
+        let mut stdout = Vec::new();
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            if let Ok(text) = std::str::from_utf8(&line) {
+                on_line(text.trim_end());
+            }
+            stdout.append(&mut line);
+        }
+        Ok(stdout)
+    })?;
+    output.stdout = stdout;
+    Ok(output)
+}
+
+/// [`run`], handing ffmpeg's stdout to `read` while it runs (for `pipe:1`
+/// outputs consumed as a stream). The returned [`Output`] has no stdout. When
+/// `read` fails a still running ffmpeg is killed; if ffmpeg itself failed, its
+/// error wins.
+pub(crate) fn run_with_stdout<T>(
+    cmd: &mut Command,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, ProcessingError>,
+) -> Result<(Output, T), ProcessingError> {
+    run_within(cmd, timeout(), read)
+}
+
+/// [`run_with_stdout`] killing ffmpeg after `limit` rather than the [`set_timeout`] one
+fn run_within<T>(
+    cmd: &mut Command,
+    limit: Option<Duration>,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, ProcessingError>,
+) -> Result<(Output, T), ProcessingError> {
+    let _slot = acquire_slot()?;
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    // Drain stderr on its own thread so a chatty ffmpeg can't block on a full pipe
+    let stderr_pipe = child.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = stderr_pipe {
+            let _ = pipe.read_to_end(&mut stderr);
+        }
+        stderr
+    });
+    let stdout_pipe = child.stdout.take();
+    let child = Registered::new(child);
+    // cancel_all may have run between the slot check and the registration
+    if let Err(e) = check_cancelled() {
+        let _ = child.0.lock().unwrap().kill();
+        return Err(e);
+    }
+
+    // The watchdog kills the child unless the run finishes (dropping `done`) in time
+    let (done, finished) = mpsc::channel::<()>();
+    let timed_out = Arc::new(AtomicBool::new(false));
+    if let Some(limit) = limit {
+        let child = child.0.clone();
+        let timed_out = timed_out.clone();
+        std::thread::spawn(move || {
+            if finished.recv_timeout(limit) == Err(mpsc::RecvTimeoutError::Timeout) {
+                log::warn!("ffmpeg still running after {:?}, killing it", limit);
+                timed_out.store(true, Ordering::SeqCst);
+                let _ = child.lock().unwrap().kill();
+            }
+        });
+    }
+
+    let value = match stdout_pipe {
+        Some(mut pipe) => read(&mut pipe),
+        None => read(&mut io::empty()),
+    };
+    // Stop an ffmpeg still writing; one that already exited explains the failure better
+    let killed = value.is_err() && {
+        let mut child = child.0.lock().unwrap();
+        matches!(child.try_wait(), Ok(None)) && child.kill().is_ok()
+    };
+
+    // stdout is closed, so the child has exited or is about to; poll rather than
+    // block in wait() so the watchdog and cancel_all can still take the lock
+    let status = loop {
+        let status = child.0.lock().unwrap().try_wait();
+        match status {
+            Ok(Some(status)) => break status,
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(ProcessingError::Encode(format!("Failed to wait for ffmpeg: {}", e))),
+        }
+    };
+    drop(done);
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    if timed_out.load(Ordering::SeqCst) {
+        return Err(ProcessingError::FfmpegTimedOut(limit.unwrap_or_default()));
+    }
+    check_cancelled()?;
+    let output = Output { status, stdout: Vec::new(), stderr };
+    match value {
+        Ok(value) => Ok((output, value)),
+        Err(_) if !killed && !output.status.success() => Err(ffmpeg_failed(&output)),
+        Err(e) => Err(e),
+    }
+}
+
+/// [`run`] for tokio: waits for a slot on the blocking pool and kills the child
+/// when the future is dropped or times out
+#[cfg(all(feature = "async", feature = "mp4"))]
+pub(crate) async fn run_async(cmd: &mut tokio::process::Command) -> Result<Output, ProcessingError> {
+    let _slot = tokio::task::spawn_blocking(acquire_slot)
+        .await
+        .map_err(|_| ProcessingError::Cancelled)??;
+    let output = cmd.kill_on_drop(true).output();
+    let output = match timeout() {
+        Some(limit) => tokio::time::timeout(limit, output)
+            .await
+            .map_err(|_| ProcessingError::FfmpegTimedOut(limit))?,
+        None => output.await,
+    };
+    let output = output.map_err(spawn_error)?;
+    check_cancelled()?;
+    Ok(output)
+}
+
+/// `ffprobe` next to the configured ffmpeg binary, or on `PATH` when ffmpeg is
+/// a bare command name
+pub fn ffprobe_binary() -> PathBuf {
+    let path = binary();
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if path.parent().is_some_and(|dir| !dir.as_os_str().is_empty()) => {
+            let probe = name.replacen("ffmpeg", "ffprobe", 1);
+            if probe == name {
+                path.with_file_name("ffprobe")
+            } else {
+                path.with_file_name(OsString::from(probe))
+            }
+        }
+        _ => PathBuf::from("ffprobe"),
+    }
+}
+
+/// Version and encoders of the configured ffmpeg, probed once per process;
+/// `None` when it cannot be run.
+pub fn probe() -> Option<&'static FfmpegInfo> {
+    PROBE
+        .get_or_init(|| {
+            let output = command().arg("-version").output().ok().filter(|o| o.status.success())?;
+            let version = parse_version(&String::from_utf8_lossy(&output.stdout)).unwrap_or_default();
+            let encoders = command()
+                .args(["-hide_banner", "-encoders"])
+                .output()
+                .map(|output| parse_encoders(&String::from_utf8_lossy(&output.stdout)))
+                .unwrap_or_default();
+            let info = FfmpegInfo {
+                path: binary().to_path_buf(),
+                version,
+                encoders,
+            };
+            log::debug!(
+                "ffmpeg {} at {} ({} encoders)",
+                info.version,
+                info.path.display(),
+                info.encoders.len()
+            );
+            Some(info)
+        })
+        .as_ref()
+}
+
+/// Container and stream details reported by ffprobe
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeInfo {
+    /// e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+    pub format_name: String,
+    pub duration: Option<f64>,
+    /// Overall bitrate in bits/s
+    pub bit_rate: Option<u64>,
+    /// Container-level tags (`major_brand`, `encoder`, `creation_time`, ...)
+    pub tags: Vec<(String, String)>,
+    pub streams: Vec<ProbeStream>,
+    pub chapters: Vec<ProbeChapter>,
+}
+
+/// One stream as reported by ffprobe; fields the stream lacks are `None`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeStream {
+    pub index: u32,
+    /// `video`, `audio`, `subtitle`, `data`, ...
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    /// e.g. `High`, `Main 10`, `LC`
+    pub profile: Option<String>,
+    /// Codec level as reported (H.264 `40` means level 4.0)
+    pub level: Option<i64>,
+    pub pix_fmt: Option<String>,
+    pub color_space: Option<String>,
+    pub color_range: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_primaries: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub bit_rate: Option<u64>,
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeChapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// Run ffprobe on a file. Fails with [`ProcessingError::FfmpegMissing`] when
+/// ffprobe cannot be started.
+pub fn ffprobe(path: &Path) -> Result<ProbeInfo, ProcessingError> {
+    let output = Command::new(ffprobe_binary())
+        .args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams", "-show_chapters"])
+        .arg(path)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ProcessingError::FfmpegMissing,
+            _ => ProcessingError::Encode(format!("Failed to execute ffprobe: {}", e)),
+        })?;
+    if !output.status.success() {
+        return Err(ProcessingError::FfmpegFailed {
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    parse_probe(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `ffprobe -print_format json` output. ffprobe prints most numbers as strings.
+fn parse_probe(json: &str) -> Result<ProbeInfo, ProcessingError> {
+    let root: Value = serde_json::from_str(json).map_err(|e| ProcessingError::CorruptInput {
+        format: "ffprobe".to_string(),
+        offset: None,
+        reason: e.to_string(),
+    })?;
+    let format = &root["format"];
+
+    let streams = root["streams"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|stream| ProbeStream {
+            index: number(&stream["index"]).unwrap_or_default() as u32,
+            codec_type: text(&stream["codec_type"]).unwrap_or_default(),
+            codec_name: text(&stream["codec_name"]),
+            profile: text(&stream["profile"]),
+            level: stream["level"].as_i64().filter(|level| *level >= 0),
+            pix_fmt: text(&stream["pix_fmt"]),
+            color_space: text(&stream["color_space"]),
+            color_range: text(&stream["color_range"]),
+            color_transfer: text(&stream["color_transfer"]),
+            color_primaries: text(&stream["color_primaries"]),
+            width: number(&stream["width"]).map(|n| n as u32),
+            height: number(&stream["height"]).map(|n| n as u32),
+            sample_rate: number(&stream["sample_rate"]).map(|n| n as u32),
+            channels: number(&stream["channels"]).map(|n| n as u32),
+            bit_rate: number(&stream["bit_rate"]).map(|n| n as u64),
+            tags: tags(&stream["tags"]),
+        })
+        .collect();
+
+    let chapters = root["chapters"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|chapter| ProbeChapter {
+            start: number(&chapter["start_time"]).unwrap_or_default(),
+            end: number(&chapter["end_time"]).unwrap_or_default(),
+            title: text(&chapter["tags"]["title"]),
+        })
+        .collect();
+
+    Ok(ProbeInfo {
+        format_name: text(&format["format_name"]).unwrap_or_default(),
+        duration: number(&format["duration"]),
+        bit_rate: number(&format["bit_rate"]).map(|n| n as u64),
+        tags: tags(&format["tags"]),
+        streams,
+        chapters,
+    })
+}
+
+fn text(value: &Value) -> Option<String> {
+    value.as_str().filter(|s| !s.is_empty() && *s != "unknown").map(String::from)
+}
+
+/// A JSON number or a numeric string
+fn number(value: &Value) -> Option<f64> {
+    value.as_f64().or_else(|| value.as_str()?.parse().ok())
+}
+
+fn tags(value: &Value) -> Vec<(String, String)> {
+    value
+        .as_object()
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Map a failure to start ffmpeg, telling a missing binary apart from other errors
+pub(crate) fn spawn_error(e: io::Error) -> ProcessingError {
+    if e.kind() == io::ErrorKind::NotFound {
+        ProcessingError::FfmpegMissing
+    } else {
+        ProcessingError::Encode(format!("Failed to execute ffmpeg: {}", e))
+    }
+}
+
+pub(crate) fn ffmpeg_failed(output: &std::process::Output) -> ProcessingError {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    log::error!("ffmpeg failed: {}", stderr);
+    ProcessingError::FfmpegFailed {
+        exit_code: output.status.code(),
+        stderr: stderr.trim().to_string(),
+    }
+}
+
+/// Temporary input/output files for one ffmpeg run, removed on drop
+pub(crate) struct FfmpegTempFiles {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Prefix of the two-pass statistics files
+    pub passlog: PathBuf,
+}
+
+impl FfmpegTempFiles {
+    /// Reserve unique temp paths (safe for concurrent runs in one process)
+    #[cfg(feature = "mp4")]
+    pub fn new() -> Self {
+        Self::with_output_extension("mp4")
+    }
+
+    /// Like [`FfmpegTempFiles::new`], with an output extension that selects ffmpeg's muxer
+    #[cfg(feature = "mp4")]
+    pub fn with_output_extension(extension: &str) -> Self {
+        Self::with_extensions("mp4", extension)
+    }
+
+    /// Temp paths for a non-MP4 input, e.g. `("mp3", "mp3")`
+    pub fn with_extensions(input: &str, output: &str) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_dir = std::env::temp_dir();
+        let pid = std::process::id();
+        Self {
+            input: temp_dir.join(format!("input_{}_{}.{}", pid, id, input)),
+            output: temp_dir.join(format!("output_{}_{}.{}", pid, id, output)),
+            passlog: temp_dir.join(format!("passlog_{}_{}", pid, id)),
+        }
+    }
+}
+
+impl Drop for FfmpegTempFiles {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.input);
+        let _ = std::fs::remove_file(&self.output);
+        // x264 keeps its macroblock tree next to the log, x265 its own stats and cutree;
+        // both write `.temp` files first, left behind when a pass fails
+        for suffix in ["-0.log", "-0.log.mbtree", "-x265.log", "-x265.log.cutree"] {
+            for partial in ["", ".temp"] {
+                let mut path = self.passlog.clone().into_os_string();
+                path.push(suffix);
+                path.push(partial);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Encoders built into the local ffmpeg; empty when it cannot be probed
+pub(crate) fn ffmpeg_encoders() -> &'static [String] {
+    probe().map(|info| info.encoders.as_slice()).unwrap_or_default()
+}
+
+/// Fail early with a clear error if the local ffmpeg lacks `encoder`
+pub(crate) fn ensure_encoder(encoder: &str) -> Result<(), ProcessingError> {
+    let encoders = ffmpeg_encoders();
+    // An unreadable list is not proof the encoder is missing; let ffmpeg decide
+    if encoders.is_empty() || encoders.iter().any(|name| name == encoder) {
+        Ok(())
+    } else {
+        Err(ProcessingError::FfmpegEncoderMissing(encoder.to_string()))
+    }
+}
+
+/// Turn a finished ffmpeg run into the compressed bytes
+pub(crate) fn read_ffmpeg_result(
+    output: std::process::Output,
+    temp: &FfmpegTempFiles,
+    input_len: usize,
+) -> Result<Vec<u8>, ProcessingError> {
+    if !output.status.success() {
+        return Err(ffmpeg_failed(&output));
+    }
+
+    let result = std::fs::read(&temp.output)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to read ffmpeg output: {}", e)))?;
+
+    log::debug!("ffmpeg completed: {} -> {} bytes ({:.1}% reduction)",
+               input_len,
+               result.len(),
+               (1.0 - result.len() as f64 / input_len as f64) * 100.0);
+
+    Ok(result)
+}
+
+/// A stretch of silence found by ffmpeg's `silencedetect`, in seconds; `end` is
+/// None when it lasts to the end of the stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Silence {
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+/// Silences of at least `trim.min_duration` below `trim.threshold_db` in the
+/// first audio stream of `path`
+pub fn detect_silence(path: &Path, trim: &SilenceTrim) -> Result<Vec<Silence>, ProcessingError> {
+    let filter = format!("silencedetect=noise={}dB:d={}", trim.threshold_db, trim.min_duration.as_secs_f64());
+    let output = run(command()
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af"])
+        .arg(filter)
+        .args(["-f", "null", "-"]))?;
+    if !output.status.success() {
+        return Err(ffmpeg_failed(&output));
+    }
+    Ok(parse_silences(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// `silence_start: 0` / `silence_end: 1.5 | silence_duration: 1.5` log lines
+fn parse_silences(log: &str) -> Vec<Silence> {
+    let seconds = |line: &str, key: &str| -> Option<f64> {
+        line.split_once(key)?.1.split_whitespace().next()?.parse().ok()
+    };
+    let mut silences: Vec<Silence> = Vec::new();
+    for line in log.lines() {
+        if let Some(start) = seconds(line, "silence_start:") {
+            silences.push(Silence { start, end: None });
+        } else if let Some(end) = seconds(line, "silence_end:") {
+            if let Some(last) = silences.last_mut() {
+                last.end = Some(end);
+            }
+        }
+    }
+    silences
+}
+
+/// EBU R128 measurement of a whole stream from ffmpeg's `ebur128` filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBFS
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// ReplayGain 2.0 track gain in dB: the distance to its -18 LUFS reference
+    pub fn replaygain(&self) -> f64 {
+        -18.0 - self.integrated
+    }
+
+    /// True peak as a linear sample value (1.0 = full scale)
+    pub fn peak_amplitude(&self) -> f64 {
+        10f64.powf(self.true_peak / 20.0)
+    }
+}
+
+/// Integrated loudness and true peak of the first audio stream of `path`
+pub fn measure_loudness(path: &Path) -> Result<Loudness, ProcessingError> {
+    let output = run(command()
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af", "ebur128=peak=true", "-f", "null", "-"]))?;
+    if !output.status.success() {
+        return Err(ffmpeg_failed(&output));
+    }
+    parse_loudness(&String::from_utf8_lossy(&output.stderr))
+        .ok_or_else(|| ProcessingError::Encode("ffmpeg printed no loudness summary".to_string()))
+}
+
+/// The `Summary:` block ebur128 logs at the end: `I: -16.5 LUFS` ... `Peak: -0.4 dBFS`
+fn parse_loudness(log: &str) -> Option<Loudness> {
+    let summary = &log[log.rfind("Summary:")?..];
+    let value = |key: &str| -> Option<f64> { summary.split_once(key)?.1.split_whitespace().next()?.parse().ok() };
+    Some(Loudness { integrated: value("I:")?, true_peak: value("Peak:")? })
+}
+
+/// Version from the first line of `ffmpeg -version`: `ffmpeg version 6.1.1 Copyright ...`
+fn parse_version(output: &str) -> Option<String> {
+    let mut words = output.lines().next()?.split_whitespace();
+    words.find(|word| *word == "version")?;
+    words.next().map(String::from)
+}
+
+/// Encoder names from `ffmpeg -encoders` lines such as ` V....D libx264   H.264 ...`
+pub(crate) fn parse_encoders(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("------"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_and_encoders() {
+        let banner = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc 13\n";
+        assert_eq!(parse_version(banner).as_deref(), Some("6.1.1-3ubuntu5"));
+        assert_eq!(parse_version("garbage"), None);
+
+        let listing = concat!(
+            "Encoders:\n V..... = Video\n ------\n",
+            " V....D libx264              libx264 H.264\n A....D aac                  AAC\n",
+        );
+        assert_eq!(parse_encoders(listing), ["libx264", "aac"]);
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        let json = r#"{
+            "streams": [
+                {"index": 0, "codec_name": "h264", "profile": "High", "codec_type": "video", "width": 1920,
+                 "height": 1080, "pix_fmt": "yuv420p", "level": 40, "color_range": "tv", "color_space": "bt709",
+                 "bit_rate": "4500000", "tags": {"language": "und"}},
+                {"index": 1, "codec_name": "aac", "profile": "LC", "codec_type": "audio", "sample_rate": "48000",
+                 "channels": 2, "level": -99, "bit_rate": "128000"}
+            ],
+            "chapters": [{"id": 0, "start_time": "0.000000", "end_time": "12.500000", "tags": {"title": "Intro"}}],
+            "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "60.000000", "bit_rate": "4630000",
+                       "tags": {"major_brand": "isom"}}
+        }"#;
+        let info = parse_probe(json).unwrap();
+        assert_eq!(info.duration, Some(60.0));
+        assert_eq!(info.bit_rate, Some(4_630_000));
+        assert_eq!(info.tags, [("major_brand".to_string(), "isom".to_string())]);
+
+        let video = &info.streams[0];
+        assert_eq!(video.profile.as_deref(), Some("High"));
+        assert_eq!(video.level, Some(40));
+        assert_eq!(video.color_space.as_deref(), Some("bt709"));
+        assert_eq!(video.bit_rate, Some(4_500_000));
+
+        let audio = &info.streams[1];
+        assert_eq!(audio.level, None);
+        assert_eq!((audio.sample_rate, audio.channels), (Some(48_000), Some(2)));
+
+        assert_eq!(info.chapters[0].end, 12.5);
+        assert_eq!(info.chapters[0].title.as_deref(), Some("Intro"));
+        assert!(parse_probe("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_loudness() {
+        let log = "[Parsed_ebur128_0 @ 0x55] t: 9.9 TARGET:-23 LUFS M: -15.1 S: -16.0 I: -16.2 LUFS\n\
+                   [Parsed_ebur128_0 @ 0x55] Summary:\n\n  Integrated loudness:\n    I:         -16.5 LUFS\n\
+                   \x20   Threshold: -27.0 LUFS\n\n  True peak:\n    Peak:        -0.4 dBFS\n";
+        let loudness = parse_loudness(log).unwrap();
+        assert_eq!(loudness, Loudness { integrated: -16.5, true_peak: -0.4 });
+        assert!((loudness.replaygain() + 1.5).abs() < 1e-9);
+        assert_eq!(parse_loudness("no summary"), None);
+    }
+
+    #[test]
+    fn test_parse_silences() {
+        let log = "[silencedetect @ 0x5581] silence_start: 0\n\
+                   [silencedetect @ 0x5581] silence_end: 1.25 | silence_duration: 1.25\n\
+                   size=N/A time=00:00:09.00 bitrate=N/A\n\
+                   [silencedetect @ 0x5581] silence_start: 8.5\n";
+        assert_eq!(
+            parse_silences(log),
+            [Silence { start: 0.0, end: Some(1.25) }, Silence { start: 8.5, end: None }]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_collects_lines_and_times_out() {
+        let mut lines = Vec::new();
+        let output = run_with_lines(Command::new("sh").args(["-c", "echo a; echo b >&2; echo c"]), |line| {
+            lines.push(line.to_string())
+        })
+        .unwrap();
+        assert_eq!(lines, ["a", "c"]);
+        assert_eq!(output.stdout, b"a\nc\n");
+        assert_eq!(output.stderr, b"b\n");
+
+        let mut sleep = Command::new("sh");
+        sleep.args(["-c", "exec sleep 10"]);
+        let result = run_within(&mut sleep, Some(Duration::from_secs(1)), |_| Ok(()));
+        assert!(matches!(result, Err(ProcessingError::FfmpegTimedOut(_))));
+    }
+}
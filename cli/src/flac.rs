@@ -0,0 +1,181 @@
+//! FLAC metadata blocks, for reading and rewriting the Vorbis comment block
+//! (`meta`). Audio frames and every other block (STREAMINFO, PICTURE, padding,
+//! ...) are copied as they are.
+
+use crate::error::ProcessingError;
+
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+/// Block lengths are 24-bit
+const MAX_BLOCK_LEN: usize = (1 << 24) - 1;
+const VENDOR: &str = concat!("image_preparer ", env!("CARGO_PKG_VERSION"));
+
+/// A metadata block: its type and where its body sits in the file
+struct Block {
+    kind: u8,
+    start: usize,
+    end: usize,
+}
+
+/// The metadata blocks of `data` and the offset the audio frames start at
+fn blocks(data: &[u8]) -> Result<(Vec<Block>, usize), ProcessingError> {
+    if !data.starts_with(b"fLaC") {
+        return Err(corrupt("missing fLaC signature"));
+    }
+    let mut blocks = Vec::new();
+    let mut pos = 4;
+    loop {
+        let header = data.get(pos..pos + 4).ok_or_else(|| corrupt("truncated metadata block header"))?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let (start, end) = (pos + 4, pos + 4 + len);
+        if end > data.len() {
+            return Err(corrupt("metadata block runs past the end of the file"));
+        }
+        blocks.push(Block { kind: header[0] & 0x7F, start, end });
+        pos = end;
+        if header[0] & 0x80 != 0 {
+            return Ok((blocks, pos));
+        }
+    }
+}
+
+/// Vorbis comments as `(field, value)` in file order; empty without a comment block
+pub fn comments(data: &[u8]) -> Result<Vec<(String, String)>, ProcessingError> {
+    let (blocks, _) = blocks(data)?;
+    let Some(block) = blocks.iter().find(|block| block.kind == VORBIS_COMMENT) else {
+        return Ok(Vec::new());
+    };
+    let (_, fields) = parse_comment(&data[block.start..block.end])?;
+    Ok(fields
+        .into_iter()
+        .map(|field| match field.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (field, String::new()),
+        })
+        .collect())
+}
+
+/// `data` with the comments of the fields in `delete` and `set` removed (names
+/// compare case-insensitively) and one comment per `set` entry added. The comment
+/// block keeps its vendor string and its place; a file without one gets it after
+/// STREAMINFO.
+pub fn with_comments(data: &[u8], set: &[(String, String)], delete: &[String]) -> Result<Vec<u8>, ProcessingError> {
+    let (blocks, audio) = blocks(data)?;
+    let existing = blocks.iter().find(|block| block.kind == VORBIS_COMMENT);
+    let (vendor, mut fields) = match existing {
+        Some(block) => parse_comment(&data[block.start..block.end])?,
+        None => (VENDOR.to_string(), Vec::new()),
+    };
+    let replaced = |field: &String| {
+        let key = field.split_once('=').map_or(field.as_str(), |(key, _)| key);
+        set.iter().map(|(name, _)| name).chain(delete).any(|name| name.eq_ignore_ascii_case(key))
+    };
+    fields.retain(|field| !replaced(field));
+    fields.extend(set.iter().map(|(key, value)| format!("{}={}", key, value)));
+    let comment = build_comment(&vendor, &fields)?;
+
+    let mut bodies: Vec<(u8, &[u8])> = Vec::with_capacity(blocks.len() + 1);
+    for block in &blocks {
+        match block.kind {
+            VORBIS_COMMENT if existing.is_some_and(|first| std::ptr::eq(block, first)) => {
+                bodies.push((VORBIS_COMMENT, &comment))
+            }
+            // Only one comment block is allowed
+            VORBIS_COMMENT => {}
+            kind => bodies.push((kind, &data[block.start..block.end])),
+        }
+        if block.kind == STREAMINFO && existing.is_none() {
+            bodies.push((VORBIS_COMMENT, &comment));
+        }
+    }
+
+    let mut output = Vec::with_capacity(data.len() + comment.len());
+    output.extend_from_slice(b"fLaC");
+    for (index, (kind, body)) in bodies.iter().enumerate() {
+        let last = if index + 1 == bodies.len() { 0x80 } else { 0 };
+        output.push(last | kind);
+        output.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        output.extend_from_slice(body);
+    }
+    output.extend_from_slice(&data[audio..]);
+    Ok(output)
+}
+
+/// Vendor string and `FIELD=value` comments of a comment block body
+fn parse_comment(body: &[u8]) -> Result<(String, Vec<String>), ProcessingError> {
+    let mut pos = 0;
+    let vendor = read_string(body, &mut pos)?;
+    let count = read_u32(body, &mut pos)? as usize;
+    // The count comes from the file: don't let it size the allocation
+    let mut fields = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        fields.push(read_string(body, &mut pos)?);
+    }
+    Ok((vendor, fields))
+}
+
+/// A comment block body: the vendor string and the comments, lengths little-endian
+fn build_comment(vendor: &str, fields: &[String]) -> Result<Vec<u8>, ProcessingError> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    body.extend_from_slice(vendor.as_bytes());
+    body.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    for field in fields {
+        body.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        body.extend_from_slice(field.as_bytes());
+    }
+    if body.len() > MAX_BLOCK_LEN {
+        return Err(corrupt("Vorbis comment block would exceed 16 MiB"));
+    }
+    Ok(body)
+}
+
+fn read_u32(body: &[u8], pos: &mut usize) -> Result<u32, ProcessingError> {
+    let bytes = body.get(*pos..*pos + 4).ok_or_else(|| corrupt("truncated Vorbis comment"))?;
+    *pos += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_string(body: &[u8], pos: &mut usize) -> Result<String, ProcessingError> {
+    let len = read_u32(body, pos)? as usize;
+    let text = body.get(*pos..*pos + len).ok_or_else(|| corrupt("truncated Vorbis comment"))?;
+    *pos += len;
+    Ok(String::from_utf8_lossy(text).into_owned())
+}
+
+fn corrupt(reason: &str) -> ProcessingError {
+    ProcessingError::CorruptInput {
+        format: "flac".to_string(),
+        offset: None,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `fLaC`, a zeroed STREAMINFO marked last, and two bytes standing in for frames
+    fn sample() -> Vec<u8> {
+        let mut data = b"fLaC\x80\0\0\x22".to_vec();
+        data.extend_from_slice(&[0; 34]);
+        data.extend_from_slice(&[0xFF, 0xF8]);
+        data
+    }
+
+    #[test]
+    fn test_with_comments() {
+        let set = vec![("TITLE".to_string(), "One".to_string()), ("ARTIST".to_string(), "Band".to_string())];
+        let tagged = with_comments(&sample(), &set, &[]).unwrap();
+        assert_eq!(comments(&tagged).unwrap(), set);
+        // STREAMINFO lost its last flag to the new comment block; the frames follow it
+        assert_eq!(tagged[4], STREAMINFO);
+        assert!(tagged.ends_with(&[0xFF, 0xF8]));
+
+        let retitled = vec![("title".to_string(), "Two".to_string())];
+        let edited = with_comments(&tagged, &retitled, &["Artist".to_string()]).unwrap();
+        assert_eq!(comments(&edited).unwrap(), retitled);
+        assert_eq!(comments(&sample()).unwrap(), Vec::new());
+        assert!(comments(b"RIFF").is_err());
+    }
+}
@@ -1,31 +1,374 @@
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::RwLock;
 
+use crate::error::ProcessingError;
+
+/// Extensions added with [`map_extension`], lowercase without the dot
+static EXTRA_EXTENSIONS: RwLock<Vec<(String, Format)>> = RwLock::new(Vec::new());
+
+/// Treat files ending in `.extension` as `format` from now on, process-wide:
+/// `jfif` as JPEG, or a tool's own screenshot extension as PNG. Mappings take
+/// precedence over the built-in extensions, and a later mapping of the same
+/// extension replaces the earlier one.
+pub fn map_extension(extension: &str, format: Format) {
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    let mut extra = EXTRA_EXTENSIONS.write().unwrap_or_else(|e| e.into_inner());
+    extra.retain(|(mapped, _)| *mapped != extension);
+    extra.push((extension, format));
+}
+
+/// `EXT=FORMAT`, e.g. `jfif=jpg`, as taken by `--map-extension`
+pub fn parse_extension_mapping(s: &str) -> Result<(String, Format), ProcessingError> {
+    let invalid =
+        || ProcessingError::InvalidConfig(format!("'{}' is not an extension mapping, expected EXT=FORMAT", s));
+    let (extension, format) = s.split_once('=').ok_or_else(invalid)?;
+    let extension = extension.trim().trim_start_matches('.');
+    if extension.is_empty() || extension.contains(['/', '\\']) {
+        return Err(invalid());
+    }
+    Ok((extension.to_string(), format.trim().parse()?))
+}
+
+/// Every file format the crate knows about, for processing, conversion and detection.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum ImageFormat {
+pub enum Format {
     Png,
-    Mp3,
+    Jpeg,
     Webp,
+    Avif,
+    Gif,
+    Mp3,
     Mp4,
+    Webm,
+    Wav,
+    Flac,
+    /// Opus audio in an Ogg container
+    Opus,
+    /// Vorbis audio in an Ogg container
+    Ogg,
+    /// HEVC-coded HEIF, as iPhones write photos; read for conversion and
+    /// inspection only
+    Heic,
+    /// Baseline TIFF, often multi-page scans; read for conversion and
+    /// inspection only
+    Tiff,
+    /// Windows bitmap; read for conversion only
+    Bmp,
+    /// Windows icon holding one or more PNG or BMP images; read for conversion
+    /// and inspection only
+    Ico,
 }
 
-impl ImageFormat {
-    pub fn from_path(path: &Path) -> Option<Self> {
-        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+impl Format {
+    pub const ALL: [Format; 16] = [
+        Format::Png,
+        Format::Jpeg,
+        Format::Webp,
+        Format::Avif,
+        Format::Gif,
+        Format::Mp3,
+        Format::Mp4,
+        Format::Webm,
+        Format::Wav,
+        Format::Flac,
+        Format::Opus,
+        Format::Ogg,
+        Format::Heic,
+        Format::Tiff,
+        Format::Bmp,
+        Format::Ico,
+    ];
+
+    /// Still-image formats, which are the valid conversion targets
+    #[cfg(feature = "avif")]
+    pub const IMAGES: [Format; 4] = [Format::Png, Format::Jpeg, Format::Webp, Format::Avif];
+
+    /// Still-image formats, which are the valid conversion targets; AVIF needs the `avif` feature
+    #[cfg(not(feature = "avif"))]
+    pub const IMAGES: [Format; 3] = [Format::Png, Format::Jpeg, Format::Webp];
+
+    /// Still-image formats that convert to the [`IMAGES`](Format::IMAGES)
+    /// targets; HEIC, TIFF, BMP and ICO are decoded but never written
+    pub const IMAGE_SOURCES: [Format; 8] = [
+        Format::Png,
+        Format::Jpeg,
+        Format::Webp,
+        Format::Avif,
+        Format::Heic,
+        Format::Tiff,
+        Format::Bmp,
+        Format::Ico,
+    ];
+
+    /// Animated formats a video can be converted to
+    pub const ANIMATIONS: [Format; 2] = [Format::Gif, Format::Webp];
+
+    /// Lossless audio formats, convertible into each other
+    pub const LOSSLESS_AUDIO: [Format; 2] = [Format::Wav, Format::Flac];
+
+    /// Audio formats that can be converted to Opus
+    pub const OPUS_SOURCES: [Format; 3] = [Format::Wav, Format::Flac, Format::Mp3];
+
+    /// Format for a file extension (any case, without the dot), including those added with [`map_extension`]
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let ext = ext.to_ascii_lowercase();
+        let extra = EXTRA_EXTENSIONS.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(&(_, format)) = extra.iter().find(|(mapped, _)| *mapped == ext) {
+            return Some(format);
+        }
         match ext.as_str() {
-            "png" => Some(ImageFormat::Png),
-            "mp3" => Some(ImageFormat::Mp3),
-            "webp" => Some(ImageFormat::Webp),
-            "mp4" | "m4v" | "m4a" => Some(ImageFormat::Mp4),
+            "png" => Some(Format::Png),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "webp" => Some(Format::Webp),
+            "avif" => Some(Format::Avif),
+            "gif" => Some(Format::Gif),
+            "mp3" => Some(Format::Mp3),
+            "mp4" | "m4v" | "m4a" => Some(Format::Mp4),
+            "webm" => Some(Format::Webm),
+            "wav" | "wave" => Some(Format::Wav),
+            "flac" => Some(Format::Flac),
+            "opus" => Some(Format::Opus),
+            "ogg" | "oga" => Some(Format::Ogg),
+            "heic" | "heif" | "hif" => Some(Format::Heic),
+            "tif" | "tiff" => Some(Format::Tiff),
+            "bmp" | "dib" => Some(Format::Bmp),
+            "ico" => Some(Format::Ico),
             _ => None,
         }
     }
 
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(path.extension()?.to_str()?)
+    }
+
+    /// Detect the format from the leading magic bytes
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Format::Jpeg)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(Format::Webp)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+            Some(Format::Wav)
+        } else if data.starts_with(b"fLaC") {
+            Some(Format::Flac)
+        } else if data.starts_with(b"OggS") && data.get(28..36) == Some(b"OpusHead") {
+            Some(Format::Opus)
+        } else if data.starts_with(b"OggS") && data.get(28..35) == Some(b"\x01vorbis") {
+            Some(Format::Ogg)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(Format::Gif)
+        } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+            Some(Format::Tiff)
+        } else if has_brand(data, &[b"avif", b"avis"]) {
+            Some(Format::Avif)
+        } else if has_brand(data, &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1"]) {
+            Some(Format::Heic)
+        } else if data.len() >= 8 && &data[4..8] == b"ftyp" {
+            Some(Format::Mp4)
+        } else if data.starts_with(b"BM") && data.get(6..10) == Some(&[0; 4]) {
+            // File header with its reserved fields zero
+            Some(Format::Bmp)
+        } else if is_ico(data) {
+            Some(Format::Ico)
+        } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            // EBML header (Matroska family)
+            Some(Format::Webm)
+        } else if data.starts_with(b"ID3") || is_mpeg_frame(data) {
+            // ID3v2 tag, or a bare MPEG audio frame
+            Some(Format::Mp3)
+        } else {
+            None
+        }
+    }
+
+    /// Format of `data`, read from `path`: the content's when its magic bytes
+    /// name another format than the extension (a JPEG renamed to `.png`), else
+    /// the extension's, else the content's for unknown or missing extensions
+    pub fn detect(path: &Path, data: &[u8]) -> Option<Self> {
+        match (Self::from_path(path), Self::from_bytes(data)) {
+            (_, Some(content)) => Some(content),
+            (extension, None) => extension,
+        }
+    }
+
+    /// Canonical file extension, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+            Format::Avif => "avif",
+            Format::Gif => "gif",
+            Format::Mp3 => "mp3",
+            Format::Mp4 => "mp4",
+            Format::Webm => "webm",
+            Format::Wav => "wav",
+            Format::Flac => "flac",
+            Format::Opus => "opus",
+            Format::Ogg => "ogg",
+            Format::Heic => "heic",
+            Format::Tiff => "tif",
+            Format::Bmp => "bmp",
+            Format::Ico => "ico",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+            Format::Webp => "image/webp",
+            Format::Avif => "image/avif",
+            Format::Gif => "image/gif",
+            Format::Mp3 => "audio/mpeg",
+            Format::Mp4 => "video/mp4",
+            Format::Webm => "video/webm",
+            Format::Wav => "audio/wav",
+            Format::Flac => "audio/flac",
+            Format::Opus | Format::Ogg => "audio/ogg",
+            Format::Heic => "image/heic",
+            Format::Tiff => "image/tiff",
+            Format::Bmp => "image/bmp",
+            Format::Ico => "image/x-icon",
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        Self::IMAGES.contains(self)
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
-            ImageFormat::Png => "PNG",
-            ImageFormat::Mp3 => "MP3",
-            ImageFormat::Webp => "WebP",
-            ImageFormat::Mp4 => "MP4",
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Webp => "WebP",
+            Format::Avif => "AVIF",
+            Format::Gif => "GIF",
+            Format::Mp3 => "MP3",
+            Format::Mp4 => "MP4",
+            Format::Webm => "WebM",
+            Format::Wav => "WAV",
+            Format::Flac => "FLAC",
+            Format::Opus => "Opus",
+            Format::Ogg => "Vorbis",
+            Format::Heic => "HEIC",
+            Format::Tiff => "TIFF",
+            Format::Bmp => "BMP",
+            Format::Ico => "ICO",
         }
     }
 }
+
+/// An MPEG audio frame header: the 11-bit sync, then a version, layer, bitrate
+/// and sample rate that are not reserved. The sync alone also matches text
+/// such as a UTF-16 byte order mark.
+fn is_mpeg_frame(data: &[u8]) -> bool {
+    let [0xFF, version_layer, rate, ..] = *data else {
+        return false;
+    };
+    version_layer & 0xE0 == 0xE0
+        && version_layer & 0x18 != 0x08
+        && version_layer & 0x06 != 0
+        && rate & 0xF0 != 0xF0
+        && rate & 0x0C != 0x0C
+}
+
+/// An icon directory header (type 1, at least one image) whose first entry
+/// has its reserved byte zero and at most one color plane
+fn is_ico(data: &[u8]) -> bool {
+    let [0, 0, 1, 0, count_low, count_high, _, _, _, 0, planes, 0, ..] = *data else {
+        return false;
+    };
+    (count_low, count_high) != (0, 0) && planes <= 1
+}
+
+/// An ISOBMFF `ftyp` box whose major or a compatible brand is one of `brands`:
+/// `avif` (still image) or `avis` (image sequence) for AVIF, the HEVC brands
+/// or the generic `mif1`/`msf1` for HEIC. Other `ftyp` files are MP4.
+fn has_brand(data: &[u8], wanted: &[&[u8; 4]]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false;
+    }
+    let size = (u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize).clamp(16, data.len());
+    // Major brand, minor version, then compatible brands
+    let mut brands = std::iter::once(&data[8..12]).chain(data[16..size].chunks_exact(4));
+    brands.any(|brand| wanted.iter().any(|wanted| brand == *wanted))
+}
+
+/// Parses an extension-style name such as `png`, `jpeg` or `mp4`
+impl FromStr for Format {
+    type Err = ProcessingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_extension(s).ok_or_else(|| ProcessingError::UnsupportedFormat(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes() {
+        assert_eq!(Format::from_bytes(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some(Format::Png));
+        assert_eq!(Format::from_bytes(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(Format::Jpeg));
+        assert_eq!(Format::from_bytes(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::Webp));
+        assert_eq!(Format::from_bytes(b"GIF89a\x01\0"), Some(Format::Gif));
+        assert_eq!(Format::from_bytes(b"II*\0\x08\0\0\0"), Some(Format::Tiff));
+        assert_eq!(Format::from_bytes(b"MM\0*\0\0\0\x08"), Some(Format::Tiff));
+        assert_eq!(Format::from_bytes(b"BM\x36\x0c\0\0\0\0\0\0\x36\0\0\0"), Some(Format::Bmp));
+        assert_eq!(Format::from_bytes(b"\0\0\x01\0\x02\0\x10\x10\0\0\x01\0"), Some(Format::Ico));
+        assert_eq!(Format::from_bytes(b"BMP files"), None);
+        assert_eq!(Format::from_bytes(b"\0\0\0\x18ftypisom"), Some(Format::Mp4));
+        assert_eq!(Format::from_bytes(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"), Some(Format::Avif));
+        assert_eq!(Format::from_bytes(b"\0\0\0\x18ftypmif1\0\0\0\0avis"), Some(Format::Avif));
+        assert_eq!(Format::from_bytes(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic"), Some(Format::Heic));
+        assert_eq!(Format::from_bytes(b"\0\0\0\x18ftypmif1\0\0\0\0heix"), Some(Format::Heic));
+        assert_eq!(Format::from_bytes(b"ID3\x04\0"), Some(Format::Mp3));
+        assert_eq!(Format::from_bytes(&[0x1A, 0x45, 0xDF, 0xA3, 0x9F]), Some(Format::Webm));
+        assert_eq!(Format::from_bytes(b"RIFF\0\0\0\0WAVEfmt "), Some(Format::Wav));
+        assert_eq!(Format::from_bytes(b"fLaC\0\0\0\x22"), Some(Format::Flac));
+        let mut ogg = b"OggS".to_vec();
+        ogg.resize(28, 0);
+        ogg.extend_from_slice(b"OpusHead\x01\x02");
+        assert_eq!(Format::from_bytes(&ogg), Some(Format::Opus));
+        ogg.truncate(28);
+        ogg.extend_from_slice(b"\x01vorbis\0\0\0\0");
+        assert_eq!(Format::from_bytes(&ogg), Some(Format::Ogg));
+        assert_eq!(Format::from_bytes(&[0xFF, 0xFB, 0x90, 0x64]), Some(Format::Mp3));
+        assert_eq!(Format::from_bytes(b"\xFF\xFE<\0h\0"), None);
+        assert_eq!(Format::from_bytes(b"hello"), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0];
+        assert_eq!(Format::detect(Path::new("renamed.png"), &jpeg), Some(Format::Jpeg));
+        assert_eq!(Format::detect(Path::new("shot.dat"), b"\x89PNG\r\n\x1a\n"), Some(Format::Png));
+        // Content without a recognizable signature keeps the extension's format
+        assert_eq!(Format::detect(Path::new("clip.mp3"), b"\0\0\0\0"), Some(Format::Mp3));
+        assert_eq!(Format::detect(Path::new("notes.txt"), b"hello"), None);
+    }
+
+    #[test]
+    fn test_jpeg_alias() {
+        assert_eq!("jpeg".parse::<Format>().unwrap(), Format::Jpeg);
+        assert_eq!("JPG".parse::<Format>().unwrap(), Format::Jpeg);
+        assert_eq!(Format::Jpeg.extension(), "jpg");
+    }
+
+    #[test]
+    fn test_map_extension() {
+        let (extension, format) = parse_extension_mapping(".JFIF=jpeg").unwrap();
+        assert_eq!((extension.as_str(), format), ("JFIF", Format::Jpeg));
+        assert!(parse_extension_mapping("jfif").is_err());
+        assert!(parse_extension_mapping("jfif=psd").is_err());
+
+        assert_eq!(Format::from_path(Path::new("a.ipshot")), None);
+        map_extension(".IPSHOT", Format::Png);
+        assert_eq!(Format::from_path(Path::new("shots/a.ipshot")), Some(Format::Png));
+    }
+}
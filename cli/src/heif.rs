@@ -0,0 +1,731 @@
+//! The HEIF container (ISO/IEC 23008-12), shared by HEIC and AVIF: the `ftyp`
+//! brands, the `meta` box's items and the `ispe` dimensions. HEIC is only read:
+//! [`decode_heic`] pulls the primary image's HEVC tiles out of the container,
+//! has ffmpeg's HEVC decoder decode them, and joins the grid here.
+
+#[cfg(feature = "ffmpeg")]
+use image::RgbImage;
+use image::DynamicImage;
+
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::limits::Limits;
+
+/// One item of the `meta` box: a coded image, a tile, EXIF or XMP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub id: u32,
+    /// `hvc1`, `grid`, `Exif`, `mime`, ...
+    pub kind: [u8; 4],
+    pub name: String,
+    /// MIME type of `mime` items, e.g. `application/rdf+xml` for XMP
+    pub content_type: Option<String>,
+    /// Bytes of the item's extents in `iloc`
+    pub size: Option<u64>,
+}
+
+impl Item {
+    /// `EXIF` or `XMP` for metadata items, the names [`crate::metadata`] uses
+    pub fn metadata_name(&self) -> Option<&'static str> {
+        match (&self.kind, self.content_type.as_deref()) {
+            (b"Exif", _) => Some("EXIF"),
+            (b"mime", Some("application/rdf+xml")) => Some("XMP"),
+            _ => None,
+        }
+    }
+}
+
+/// Major brand and compatible brands of the `ftyp` box
+pub fn brands(data: &[u8]) -> Option<([u8; 4], Vec<[u8; 4]>)> {
+    let (kind, body) = boxes(data).next()?;
+    if &kind != b"ftyp" || body.len() < 8 {
+        return None;
+    }
+    let major = body[0..4].try_into().ok()?;
+    // Then the minor version, then compatible brands
+    let compatible = body[8..].chunks_exact(4).filter_map(|brand| brand.try_into().ok()).collect();
+    Some((major, compatible))
+}
+
+/// Width and height of the largest `ispe` property, which is the primary image
+/// (alpha planes share its size, thumbnails and grid tiles are smaller). None
+/// if the file is not an ISOBMFF image or has no `ispe`.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let iprp = boxes(meta(data)?).find(|(kind, _)| kind == b"iprp")?.1;
+    let ipco = boxes(iprp).find(|(kind, _)| kind == b"ipco")?.1;
+    boxes(ipco)
+        .filter(|(kind, body)| kind == b"ispe" && body.len() >= 12)
+        .map(|(_, body)| {
+            let width = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+            let height = u32::from_be_bytes([body[8], body[9], body[10], body[11]]);
+            (width, height)
+        })
+        .max_by_key(|&(width, height)| width as u64 * height as u64)
+}
+
+/// ID of the primary item (`pitm`)
+pub fn primary_item(data: &[u8]) -> Option<u32> {
+    let body = boxes(meta(data)?).find(|(kind, _)| kind == b"pitm")?.1;
+    let mut reader = Reader::new(body);
+    let version = reader.full_box()?;
+    reader.uint(if version == 0 { 2 } else { 4 }).map(|id| id as u32)
+}
+
+/// Items listed in `iinf`, in order, with their sizes from `iloc`. Empty if the
+/// file has no `meta` box or the item list cannot be read.
+pub fn items(data: &[u8]) -> Vec<Item> {
+    let Some(meta) = meta(data) else {
+        return Vec::new();
+    };
+    let mut items =
+        boxes(meta).find(|(kind, _)| kind == b"iinf").and_then(|(_, body)| item_infos(body)).unwrap_or_default();
+    if let Some(locations) = boxes(meta).find(|(kind, _)| kind == b"iloc").and_then(|(_, body)| item_locations(body)) {
+        for item in &mut items {
+            item.size = locations
+                .iter()
+                .find(|location| location.id == item.id)
+                .map(|location| location.extents.iter().fold(0u64, |size, &(_, len)| size.saturating_add(len)));
+        }
+    }
+    items
+}
+
+/// `inspect` for HEIC: brands, dimensions, whether EXIF and XMP are present,
+/// and the item list
+pub fn inspect_heif(data: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Heic, data.len());
+    let Some((major, compatible)) = brands(data) else {
+        report.errors.push("No ftyp box; not a HEIF file".to_string());
+        return Ok(report);
+    };
+    report.field("Major brand", brand_name(&major));
+    let compatible: Vec<String> = compatible.iter().map(brand_name).collect();
+    report.field("Compatible brands", compatible.join(", "));
+    match dimensions(data) {
+        Some((width, height)) => report.field("Image dimensions", format!("{} x {} pixels", width, height)),
+        None => report.errors.push("No ispe property; image dimensions unknown".to_string()),
+    }
+
+    let items = items(data);
+    let present = |name| if items.iter().any(|item| item.metadata_name() == Some(name)) { "yes" } else { "no" };
+    report.field("EXIF present", present("EXIF"));
+    report.field("XMP present", present("XMP"));
+    if items.is_empty() {
+        report.errors.push("Could not read the item list".to_string());
+        return Ok(report);
+    }
+
+    let primary = primary_item(data);
+    let mut section = Section::new("HEIF Items");
+    for item in &items {
+        let kind = brand_name(&item.kind);
+        let mut entry = Entry::new(format!("#{} {}", item.id, kind));
+        entry.description = Some(item_description(item, primary).to_string());
+        // Re-encoding to another format leaves Exif and XMP behind, like `--strip safe`
+        entry.class = Some(if item.metadata_name().is_some() { EntryClass::Unsafe } else { EntryClass::Safe });
+        entry.size = item.size;
+        if !item.name.is_empty() {
+            entry.field("Name", &item.name);
+        }
+        if let Some(content_type) = &item.content_type {
+            entry.field("Content type", content_type);
+        }
+        section.entries.push(entry);
+    }
+    section.summary = Some(format!("{} items", items.len()));
+    report.sections.push(section);
+    Ok(report)
+}
+
+/// The primary image's HEVC tiles, ready for a decoder: one for a plain image,
+/// a `grid`'s tiles in row-major order
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tiles {
+    /// Annex B stream with one picture per tile, each behind its own parameter sets
+    stream: Vec<u8>,
+    count: usize,
+    /// Size of each tile (its `ispe`)
+    tile_size: (u32, u32),
+    grid: Grid,
+    /// `irot` and `imir` of the primary item, in the order they apply
+    transforms: Vec<Transform>,
+}
+
+/// Tile layout of a `grid` item, whose joined tiles are cropped to `width` x `height`
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Grid {
+    rows: u32,
+    columns: u32,
+    width: u32,
+    height: u32,
+}
+
+/// A transformative property of the primary item
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transform {
+    /// `irot`: anti-clockwise quarter turns
+    Rotate(u8),
+    /// `imir`: about the vertical axis (left-right) when true, else the horizontal one
+    Mirror(bool),
+}
+
+/// Find the primary image's tiles; only HEVC images and grids of them are handled
+#[cfg(feature = "ffmpeg")]
+fn hevc_tiles(data: &[u8]) -> Result<Tiles, ProcessingError> {
+    let corrupt = |reason: &str| ProcessingError::CorruptInput {
+        format: "heic".to_string(),
+        offset: None,
+        reason: reason.to_string(),
+    };
+    let primary = primary_item(data).ok_or_else(|| corrupt("no primary item"))?;
+    let item = items(data)
+        .into_iter()
+        .find(|item| item.id == primary)
+        .ok_or_else(|| corrupt("primary item not listed"))?;
+
+    let (ids, grid) = match &item.kind {
+        b"hvc1" => (vec![primary], None),
+        b"grid" => {
+            let layout = item_data(data, primary).as_deref().and_then(parse_grid).ok_or_else(|| corrupt("bad grid"))?;
+            let ids = derived_from(data, primary).ok_or_else(|| corrupt("grid without tiles"))?;
+            if ids.len() as u64 != layout.rows as u64 * layout.columns as u64 {
+                return Err(corrupt("grid tile count does not match its rows and columns"));
+            }
+            (ids, Some(layout))
+        }
+        kind => {
+            return Err(ProcessingError::UnsupportedFormat(format!("HEIF primary image of type {}", brand_name(kind))))
+        }
+    };
+
+    let mut stream = Vec::new();
+    let mut tile_size = None;
+    for &id in &ids {
+        let properties = properties(data, id);
+        let property = |kind: &[u8; 4]| properties.iter().find(|(k, _)| k == kind).map(|&(_, body)| body);
+        let hvcc = property(b"hvcC").ok_or_else(|| corrupt("tile without hvcC"))?;
+        let size = property(b"ispe").and_then(ispe).ok_or_else(|| corrupt("tile without ispe"))?;
+        if *tile_size.get_or_insert(size) != size {
+            return Err(ProcessingError::UnsupportedFormat("HEIF grid with tiles of different sizes".to_string()));
+        }
+        let coded = item_data(data, id).ok_or_else(|| corrupt("tile data outside the file"))?;
+        annex_b(hvcc, &coded, &mut stream).ok_or_else(|| corrupt("bad HEVC NAL units"))?;
+    }
+    let tile_size = tile_size.ok_or_else(|| corrupt("grid without tiles"))?;
+    let grid = grid.unwrap_or(Grid { rows: 1, columns: 1, width: tile_size.0, height: tile_size.1 });
+
+    let transforms = properties(data, primary)
+        .into_iter()
+        .filter_map(|(kind, body)| match &kind {
+            b"irot" => body.first().map(|angle| Transform::Rotate(angle & 3)),
+            b"imir" => body.first().map(|axis| Transform::Mirror(axis & 1 == 1)),
+            _ => None,
+        })
+        .collect();
+    Ok(Tiles { stream, count: ids.len(), tile_size, grid, transforms })
+}
+
+/// Join decoded `frames` (RGB, one per tile) into the grid and apply the transforms
+#[cfg(feature = "ffmpeg")]
+fn assemble(tiles: &Tiles, frames: &[u8]) -> Result<DynamicImage, ProcessingError> {
+    let (tile_width, tile_height) = tiles.tile_size;
+    let frame_len = tile_width as usize * tile_height as usize * 3;
+    if frames.len() != frame_len * tiles.count {
+        return Err(ProcessingError::Decode(format!(
+            "expected {} decoded {}x{} tiles, got {} bytes",
+            tiles.count,
+            tile_width,
+            tile_height,
+            frames.len()
+        )));
+    }
+
+    let Grid { columns, width, height, .. } = tiles.grid;
+    let mut canvas = RgbImage::new(width, height);
+    for (index, frame) in frames.chunks_exact(frame_len).enumerate() {
+        let tile = RgbImage::from_raw(tile_width, tile_height, frame.to_vec()).expect("frame is one tile");
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        // Tiles past the output size are cropped
+        image::imageops::replace(
+            &mut canvas,
+            &tile,
+            column as i64 * tile_width as i64,
+            row as i64 * tile_height as i64,
+        );
+    }
+
+    let mut img = DynamicImage::ImageRgb8(canvas);
+    for transform in &tiles.transforms {
+        img = match transform {
+            Transform::Rotate(1) => img.rotate270(),
+            Transform::Rotate(2) => img.rotate180(),
+            Transform::Rotate(3) => img.rotate90(),
+            Transform::Rotate(_) => img,
+            Transform::Mirror(true) => img.fliph(),
+            Transform::Mirror(false) => img.flipv(),
+        };
+    }
+    Ok(img)
+}
+
+/// Decode the primary image after checking its dimensions against `limits`.
+/// The tiles go to ffmpeg as one raw HEVC stream, a picture each, and come back
+/// as RGB frames that are joined here, so grids (as iPhones write) need no
+/// particular ffmpeg version.
+#[cfg(feature = "ffmpeg")]
+pub(crate) fn decode_heic(input: &[u8], limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    use crate::ffmpeg::{self, read_ffmpeg_result, FfmpegTempFiles};
+
+    limits.check_input(input.len())?;
+    if let Some((width, height)) = dimensions(input) {
+        limits.check_dimensions(width, height)?;
+    }
+    let tiles = hevc_tiles(input)?;
+    limits.check_dimensions(tiles.grid.width, tiles.grid.height)?;
+    let (tile_width, tile_height) = tiles.tile_size;
+    limits.check_dimensions(tile_width, tile_height.saturating_mul(tiles.count as u32))?;
+
+    let temp = FfmpegTempFiles::with_extensions("hevc", "rgb");
+    std::fs::write(&temp.input, &tiles.stream)
+        .map_err(|e| ProcessingError::Decode(format!("Failed to write temp input: {}", e)))?;
+
+    let mut cmd = ffmpeg::command();
+    cmd.args(["-f", "hevc", "-i"]).arg(&temp.input);
+    cmd.args(["-y", "-vsync", "passthrough", "-f", "rawvideo", "-pix_fmt", "rgb24"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    let frames = read_ffmpeg_result(output, &temp, tiles.stream.len())?;
+    assemble(&tiles, &frames)
+}
+
+/// Without the `ffmpeg` feature there is no HEVC decoder
+#[cfg(not(feature = "ffmpeg"))]
+pub(crate) fn decode_heic(_input: &[u8], _limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Body of the top-level `meta` box, after its version and flags
+fn meta(data: &[u8]) -> Option<&[u8]> {
+    boxes(data).find(|(kind, _)| kind == b"meta")?.1.get(4..)
+}
+
+/// The bytes of item `id`, from the file or the `idat` box
+#[cfg(feature = "ffmpeg")]
+fn item_data(data: &[u8], id: u32) -> Option<Vec<u8>> {
+    let meta = meta(data)?;
+    let locations = boxes(meta).find(|(kind, _)| kind == b"iloc").and_then(|(_, body)| item_locations(body))?;
+    let location = locations.into_iter().find(|location| location.id == id)?;
+    let source = match location.method {
+        0 => data,
+        1 => boxes(meta).find(|(kind, _)| kind == b"idat")?.1,
+        _ => return None,
+    };
+
+    let mut bytes = Vec::new();
+    for (offset, len) in location.extents {
+        let start = usize::try_from(offset).ok()?;
+        // A length of 0 runs to the end of the source
+        let end = if len == 0 { source.len() } else { start.checked_add(usize::try_from(len).ok()?)? };
+        bytes.extend_from_slice(source.get(start..end)?);
+    }
+    Some(bytes)
+}
+
+/// Properties `ipma` associates with item `id`, in association order
+#[cfg(feature = "ffmpeg")]
+fn properties(data: &[u8], id: u32) -> Vec<([u8; 4], &[u8])> {
+    let Some((_, iprp)) = meta(data).and_then(|meta| boxes(meta).find(|(kind, _)| kind == b"iprp")) else {
+        return Vec::new();
+    };
+    let ipco: Vec<_> =
+        boxes(iprp).find(|(kind, _)| kind == b"ipco").map(|(_, body)| boxes(body).collect()).unwrap_or_default();
+    boxes(iprp)
+        .filter(|(kind, _)| kind == b"ipma")
+        .filter_map(|(_, body)| associations(body, id))
+        .flatten()
+        // Index 0 means no property
+        .filter_map(|index| ipco.get(index.checked_sub(1)?).copied())
+        .collect()
+}
+
+/// `ipma`: the 1-based `ipco` indices of item `id`'s properties
+#[cfg(feature = "ffmpeg")]
+fn associations(body: &[u8], id: u32) -> Option<Vec<usize>> {
+    let mut reader = Reader::new(body);
+    let header = reader.bytes(4)?;
+    let id_size = if header[0] < 1 { 2 } else { 4 };
+    // Flag 1: 15-bit indices instead of 7-bit, after the essential bit
+    let wide = header[3] & 1 == 1;
+    for _ in 0..reader.uint(4)? {
+        let item = reader.uint(id_size)?;
+        let indices = (0..reader.uint(1)?)
+            .map(|_| match wide {
+                true => reader.uint(2).map(|index| (index & 0x7FFF) as usize),
+                false => reader.uint(1).map(|index| (index & 0x7F) as usize),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if item == id as u64 {
+            return Some(indices);
+        }
+    }
+    None
+}
+
+/// Items that item `id` is derived from (`dimg` in `iref`), in order: a grid's tiles
+#[cfg(feature = "ffmpeg")]
+fn derived_from(data: &[u8], id: u32) -> Option<Vec<u32>> {
+    let body = boxes(meta(data)?).find(|(kind, _)| kind == b"iref")?.1;
+    let mut reader = Reader::new(body);
+    let id_size = if reader.full_box()? == 0 { 2 } else { 4 };
+    boxes(reader.rest()).filter(|(kind, _)| kind == b"dimg").find_map(|(_, body)| {
+        let mut reader = Reader::new(body);
+        if reader.uint(id_size)? != id as u64 {
+            return None;
+        }
+        (0..reader.uint(2)?).map(|_| reader.uint(id_size).map(|tile| tile as u32)).collect()
+    })
+}
+
+/// The data of a `grid` item: version, flags (1: 32-bit sizes), rows and columns
+/// minus one, output width and height
+#[cfg(feature = "ffmpeg")]
+fn parse_grid(data: &[u8]) -> Option<Grid> {
+    let mut reader = Reader::new(data);
+    let header = reader.bytes(2)?;
+    let size = if header[1] & 1 == 1 { 4 } else { 2 };
+    let rows = reader.uint(1)? as u32 + 1;
+    let columns = reader.uint(1)? as u32 + 1;
+    Some(Grid { rows, columns, width: reader.uint(size)? as u32, height: reader.uint(size)? as u32 })
+}
+
+/// Width and height of an `ispe` body
+#[cfg(feature = "ffmpeg")]
+fn ispe(body: &[u8]) -> Option<(u32, u32)> {
+    let mut reader = Reader::new(body);
+    reader.full_box()?;
+    Some((reader.uint(4)? as u32, reader.uint(4)? as u32))
+}
+
+/// Append one coded image to an Annex B stream: the parameter sets from its
+/// `hvcC`, then its length-prefixed NAL units, each behind a start code
+#[cfg(feature = "ffmpeg")]
+fn annex_b(hvcc: &[u8], coded: &[u8], stream: &mut Vec<u8>) -> Option<()> {
+    const START_CODE: [u8; 4] = [0, 0, 0, 1];
+    let length_size = (*hvcc.get(21)? & 3) as usize + 1;
+    let mut reader = Reader::new(hvcc.get(22..)?);
+    for _ in 0..reader.uint(1)? {
+        reader.uint(1)?; // array completeness, NAL unit type
+        for _ in 0..reader.uint(2)? {
+            let len = reader.uint(2)? as usize;
+            stream.extend_from_slice(&START_CODE);
+            stream.extend_from_slice(reader.bytes(len)?);
+        }
+    }
+
+    let mut reader = Reader::new(coded);
+    while !reader.rest().is_empty() {
+        let len = reader.uint(length_size)? as usize;
+        stream.extend_from_slice(&START_CODE);
+        stream.extend_from_slice(reader.bytes(len)?);
+    }
+    Some(())
+}
+
+/// `iinf`: an entry count, then one `infe` box per item
+fn item_infos(body: &[u8]) -> Option<Vec<Item>> {
+    let mut reader = Reader::new(body);
+    let version = reader.full_box()?;
+    reader.uint(if version == 0 { 2 } else { 4 })?;
+    Some(boxes(reader.rest()).filter(|(kind, _)| kind == b"infe").filter_map(|(_, body)| item_info(body)).collect())
+}
+
+/// `infe` version 2 or 3; earlier versions predate item types and are not
+/// written by HEIF encoders
+fn item_info(body: &[u8]) -> Option<Item> {
+    let mut reader = Reader::new(body);
+    let version = reader.full_box()?;
+    if version < 2 {
+        return None;
+    }
+    let id = reader.uint(if version == 2 { 2 } else { 4 })? as u32;
+    reader.uint(2)?; // protection index
+    let kind = reader.bytes(4)?.try_into().ok()?;
+    let name = reader.string().unwrap_or_default();
+    let content_type = if &kind == b"mime" { reader.string() } else { None };
+    Some(Item { id, kind, name, content_type, size: None })
+}
+
+/// Where an item's bytes are, from `iloc`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    id: u32,
+    /// 0: offsets into the file, 1: into the `idat` box
+    method: u64,
+    /// Offset (base offset included) and length of each extent
+    extents: Vec<(u64, u64)>,
+}
+
+/// `iloc`: every item's location
+fn item_locations(body: &[u8]) -> Option<Vec<Location>> {
+    let mut reader = Reader::new(body);
+    let version = reader.full_box()?;
+    if version > 2 {
+        return None;
+    }
+    let sizes = reader.bytes(2)?;
+    let (offset_size, length_size) = ((sizes[0] >> 4) as usize, (sizes[0] & 0xF) as usize);
+    let base_offset_size = (sizes[1] >> 4) as usize;
+    let index_size = if version > 0 { (sizes[1] & 0xF) as usize } else { 0 };
+    let id_size = if version < 2 { 2 } else { 4 };
+
+    let count = reader.uint(id_size)?;
+    let mut locations = Vec::new();
+    for _ in 0..count {
+        let id = reader.uint(id_size)? as u32;
+        // Low four bits of the field after the ID
+        let method = if version > 0 { reader.uint(2)? & 0xF } else { 0 };
+        reader.uint(2)?; // data reference index
+        let base_offset = reader.uint(base_offset_size)?;
+        let mut extents = Vec::new();
+        for _ in 0..reader.uint(2)? {
+            reader.uint(index_size)?;
+            let offset = base_offset.saturating_add(reader.uint(offset_size)?);
+            extents.push((offset, reader.uint(length_size)?));
+        }
+        locations.push(Location { id, method, extents });
+    }
+    Some(locations)
+}
+
+/// What an item holds, by type
+fn item_description(item: &Item, primary: Option<u32>) -> &'static str {
+    if primary == Some(item.id) {
+        return "Primary image";
+    }
+    match (&item.kind, item.metadata_name()) {
+        (_, Some("EXIF")) => "EXIF metadata",
+        (_, Some(_)) => "XMP metadata",
+        (b"hvc1", _) => "HEVC coded image",
+        (b"av01", _) => "AV1 coded image",
+        (b"grid", _) => "Grid of tiles",
+        (b"iden", _) => "Derived image (identity)",
+        (b"iovl", _) => "Derived image (overlay)",
+        (b"jpeg", _) => "JPEG coded image",
+        (b"mime", _) => "MIME content",
+        _ => "Item",
+    }
+}
+
+/// A brand or item type as text
+fn brand_name(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).trim_end().to_string()
+}
+
+/// Type and body of each box in `data`, stopping at the first malformed one
+pub(crate) fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (start, size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => (pos + 8, data.len() - pos),
+            1 => {
+                let large = data.get(pos + 8..pos + 16)?;
+                (pos + 16, usize::try_from(u64::from_be_bytes(large.try_into().ok()?)).ok()?)
+            }
+            size => (pos + 8, size as usize),
+        };
+        let end = pos.checked_add(size)?;
+        let body = data.get(start..end)?;
+        pos = end;
+        Some((kind, body))
+    })
+}
+
+/// Big-endian fields of a box body, read front to back
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(..len)?;
+        self.data = &self.data[len..];
+        Some(bytes)
+    }
+
+    /// An unsigned integer of `size` bytes (0 to 8; 0 reads nothing and gives 0)
+    fn uint(&mut self, size: usize) -> Option<u64> {
+        if size > 8 {
+            return None;
+        }
+        Some(self.bytes(size)?.iter().fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+
+    /// A full box's version; its flags are skipped
+    fn full_box(&mut self) -> Option<u8> {
+        Some(self.bytes(4)?[0])
+    }
+
+    /// A NUL-terminated UTF-8 string
+    fn string(&mut self) -> Option<String> {
+        let len = self.data.iter().position(|&byte| byte == 0)?;
+        let text = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.bytes(1)?;
+        Some(text)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn infe(id: u16, kind: &[u8; 4], tail: &[u8]) -> Vec<u8> {
+        let mut body = vec![2, 0, 0, 0];
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(kind);
+        body.extend_from_slice(tail);
+        boxed(b"infe", &body)
+    }
+
+    /// A minimal HEIC: ftyp, then meta with a primary image, EXIF and XMP
+    fn heic() -> Vec<u8> {
+        let mut iinf = vec![0, 0, 0, 0, 0, 3];
+        iinf.extend(infe(1, b"hvc1", b"\0"));
+        iinf.extend(infe(2, b"Exif", b"\0"));
+        iinf.extend(infe(3, b"mime", b"XMP\0application/rdf+xml\0"));
+
+        // Version 1, 4-byte offsets and lengths, no base offset or index
+        let mut iloc = vec![1, 0, 0, 0, 0x44, 0x00, 0, 3];
+        for (id, length) in [(1u16, 5000u32), (2, 120), (3, 900)] {
+            iloc.extend_from_slice(&id.to_be_bytes());
+            iloc.extend_from_slice(&[0, 0, 0, 0, 0, 1]);
+            iloc.extend_from_slice(&[0, 0, 0, 0]);
+            iloc.extend_from_slice(&length.to_be_bytes());
+        }
+
+        let mut ispe = vec![0; 4];
+        ispe.extend_from_slice(&4032u32.to_be_bytes());
+        ispe.extend_from_slice(&3024u32.to_be_bytes());
+        let iprp = boxed(b"iprp", &boxed(b"ipco", &boxed(b"ispe", &ispe)));
+
+        let mut meta = vec![0; 4];
+        meta.extend(boxed(b"pitm", &[0, 0, 0, 0, 0, 1]));
+        meta.extend(boxed(b"iinf", &iinf));
+        meta.extend(boxed(b"iloc", &iloc));
+        meta.extend(iprp);
+
+        let mut data = boxed(b"ftyp", b"heic\0\0\0\0mif1heic");
+        data.extend(boxed(b"meta", &meta));
+        data
+    }
+
+    #[test]
+    fn test_parse_items() {
+        let data = heic();
+        assert_eq!(Format::from_bytes(&data), Some(Format::Heic));
+        assert_eq!(brands(&data), Some((*b"heic", vec![*b"mif1", *b"heic"])));
+        assert_eq!(dimensions(&data), Some((4032, 3024)));
+        assert_eq!(primary_item(&data), Some(1));
+
+        let items = items(&data);
+        let summary: Vec<_> = items.iter().map(|item| (item.id, item.metadata_name(), item.size)).collect();
+        assert_eq!(summary, [(1, None, Some(5000)), (2, Some("EXIF"), Some(120)), (3, Some("XMP"), Some(900))]);
+        assert_eq!(items[2].name, "XMP");
+
+        let report = inspect_heif(&data).unwrap();
+        assert!(report.fields.iter().any(|field| field.label == "EXIF present" && field.value == "yes"));
+        assert_eq!(report.sections[0].entries[0].description.as_deref(), Some("Primary image"));
+        assert!(report.errors.is_empty());
+    }
+
+    /// A 2x2 grid of 64x64 red, green, blue and yellow HEVC tiles, cropped to 120x100
+    #[cfg(feature = "ffmpeg")]
+    const GRID: &[u8] = include_bytes!("../tests/fixtures/grid.heic");
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_hevc_tiles() {
+        let tiles = hevc_tiles(GRID).unwrap();
+        assert_eq!((tiles.count, tiles.tile_size), (4, (64, 64)));
+        assert_eq!(tiles.grid, Grid { rows: 2, columns: 2, width: 120, height: 100 });
+        assert!(tiles.transforms.is_empty());
+        // Each tile opens with a VPS behind a start code
+        let vps = [0, 0, 0, 1, 0x40, 0x01];
+        assert!(tiles.stream.starts_with(&vps));
+        assert_eq!(tiles.stream.windows(vps.len()).filter(|window| *window == vps).count(), 4);
+
+        // The synthetic HEIC's image has no hvcC
+        assert!(matches!(hevc_tiles(&heic()), Err(ProcessingError::CorruptInput { .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_assemble() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]];
+        let frames: Vec<u8> = colors.iter().flat_map(|color| color.repeat(4)).collect();
+        let mut tiles = Tiles {
+            stream: Vec::new(),
+            count: 4,
+            tile_size: (2, 2),
+            grid: Grid { rows: 2, columns: 2, width: 3, height: 3 },
+            transforms: Vec::new(),
+        };
+
+        let img = assemble(&tiles, &frames).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (3, 3));
+        let corners = [img[(1, 1)].0, img[(2, 0)].0, img[(0, 2)].0, img[(2, 2)].0];
+        assert_eq!(corners, colors);
+
+        // irot 1 turns anti-clockwise: the top-right tile ends up top-left
+        tiles.transforms = vec![Transform::Rotate(1)];
+        assert_eq!(assemble(&tiles, &frames).unwrap().to_rgb8()[(0, 0)].0, colors[1]);
+        tiles.transforms = vec![Transform::Mirror(true)];
+        assert_eq!(assemble(&tiles, &frames).unwrap().to_rgb8()[(0, 0)].0, colors[1]);
+
+        assert!(matches!(assemble(&tiles, &frames[12..]), Err(ProcessingError::Decode(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_decode_heic_grid() {
+        if !crate::processor::is_ffmpeg_available() {
+            return;
+        }
+        let img = decode_heic(GRID, &Limits::default()).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (120, 100));
+        let close = |(x, y), expected: [u8; 3]| {
+            let pixel = img[(x, y)].0;
+            assert!(pixel.iter().zip(expected).all(|(&a, b)| a.abs_diff(b) <= 12), "{:?} at {:?}", pixel, (x, y));
+        };
+        close((5, 5), [220, 40, 40]);
+        close((100, 5), [40, 200, 60]);
+        close((5, 90), [40, 60, 220]);
+        close((100, 90), [230, 220, 40]);
+
+        let limits = Limits { max_pixels: Some(10_000), ..Limits::default() };
+        assert!(matches!(decode_heic(GRID, &limits), Err(ProcessingError::LimitExceeded(_))));
+    }
+}
@@ -0,0 +1,170 @@
+//! ICO files, read for conversion and inspection. An icon holds several images
+//! (sizes and bit depths), each a PNG or a BMP without its file header. The
+//! image crate's decoder (`ico` feature) returns the largest one only, so
+//! [`select`] cuts the wanted image out: a PNG as it is, a BMP into an icon of
+//! its own.
+
+use std::borrow::Cow;
+
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::inspect::{Entry, InspectionReport, Section};
+
+const HEADER_LEN: usize = 6;
+const ENTRY_LEN: usize = 16;
+
+/// One image of the icon directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcoImage {
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel from the directory; 0 when the writer left it out
+    pub bits: u16,
+    /// PNG-compressed rather than a BMP bitmap
+    pub png: bool,
+    pub offset: usize,
+    pub size: usize,
+    /// Offset of the 16-byte directory entry
+    entry: usize,
+}
+
+/// Every image in the icon, in directory order. Entries whose data lies
+/// outside the file are left out.
+pub fn images(data: &[u8]) -> Vec<IcoImage> {
+    if !data.starts_with(&[0, 0, 1, 0]) || data.len() < HEADER_LEN {
+        return Vec::new();
+    }
+    let count = u16::from_le_bytes([data[4], data[5]]) as usize;
+    (0..count)
+        .filter_map(|i| {
+            let entry = HEADER_LEN + ENTRY_LEN * i;
+            let fields = data.get(entry..entry + ENTRY_LEN)?;
+            let u32_at = |at: usize| u32::from_le_bytes([fields[at], fields[at + 1], fields[at + 2], fields[at + 3]]);
+            let (size, offset) = (u32_at(8) as usize, u32_at(12) as usize);
+            let image = data.get(offset..offset.checked_add(size)?)?;
+            // 0 stands for 256
+            let edge = |byte: u8| if byte == 0 { 256 } else { u32::from(byte) };
+            Some(IcoImage {
+                width: edge(fields[0]),
+                height: edge(fields[1]),
+                bits: u16::from_le_bytes([fields[6], fields[7]]),
+                png: image.starts_with(b"\x89PNG\r\n\x1a\n"),
+                offset,
+                size,
+                entry,
+            })
+        })
+        .collect()
+}
+
+/// The image [`select`] takes by default: the largest, then the deepest
+pub fn largest(images: &[IcoImage]) -> Option<&IcoImage> {
+    images.iter().max_by_key(|image| (image.width * image.height, image.bits))
+}
+
+/// The image whose width is `size` (the deepest of them), or the largest
+/// image for None: the PNG itself, or an icon holding only that BMP. Either
+/// decodes with its format guessed from the content. Sizes the icon lacks are
+/// [`ProcessingError::InvalidConfig`] listing those it has.
+pub fn select(data: &[u8], size: Option<u32>) -> Result<Cow<'_, [u8]>, ProcessingError> {
+    let images = images(data);
+    let image = match size {
+        None => largest(&images),
+        Some(size) => images.iter().filter(|image| image.width == size).max_by_key(|image| image.bits),
+    };
+    let Some(image) = image else {
+        return Err(match size {
+            Some(size) => ProcessingError::InvalidConfig(format!(
+                "no {}x{} image in the ICO; it has {}",
+                size,
+                size,
+                describe_sizes(&images)
+            )),
+            None => ProcessingError::CorruptInput {
+                format: "ico".to_string(),
+                offset: None,
+                reason: "no readable image in the icon directory".to_string(),
+            },
+        });
+    };
+    let bytes = &data[image.offset..image.offset + image.size];
+    if image.png {
+        // Also PNGs without alpha, which the image crate's ICO decoder refuses
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let mut icon = vec![0, 0, 1, 0, 1, 0];
+    icon.extend_from_slice(&data[image.entry..image.entry + 12]);
+    icon.extend_from_slice(&((HEADER_LEN + ENTRY_LEN) as u32).to_le_bytes());
+    icon.extend_from_slice(bytes);
+    Ok(Cow::Owned(icon))
+}
+
+/// `16x16, 32x32, 48x48`, each size once, smallest first
+pub fn describe_sizes(images: &[IcoImage]) -> String {
+    let mut sizes: Vec<(u32, u32)> = images.iter().map(|image| (image.width, image.height)).collect();
+    sizes.sort_unstable();
+    sizes.dedup();
+    sizes.iter().map(|(width, height)| format!("{}x{}", width, height)).collect::<Vec<_>>().join(", ")
+}
+
+/// `inspect` for ICO: every embedded image with its size, bit depth and encoding
+pub fn inspect_ico(data: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Ico, data.len());
+    let images = images(data);
+    if images.is_empty() {
+        report.errors.push("No readable image in the icon directory".to_string());
+        return Ok(report);
+    }
+    report.field("Images", images.len());
+    report.field("Sizes", describe_sizes(&images));
+
+    let mut section = Section::new("Icon Images");
+    for image in &images {
+        let mut entry = Entry::new(format!("{}x{}", image.width, image.height));
+        entry.size = Some(image.size as u64);
+        entry.field("Encoding", if image.png { "PNG" } else { "BMP" });
+        if image.bits > 0 {
+            entry.field("Bit depth", format!("{} bits per pixel", image.bits));
+        }
+        entry.field("Offset", image.offset);
+        section.entries.push(entry);
+    }
+    section.summary = Some(format!("{} images; convert --ico-size picks one (default: the largest)", images.len()));
+    report.sections.push(section);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory of a 16x16 and a 32x32 image, the 32x32 a (truncated) PNG
+    fn icon() -> Vec<u8> {
+        let mut ico = vec![0, 0, 1, 0, 2, 0];
+        ico.extend_from_slice(&[16, 16, 0, 0, 1, 0, 32, 0]);
+        ico.extend_from_slice(&4u32.to_le_bytes());
+        ico.extend_from_slice(&38u32.to_le_bytes());
+        ico.extend_from_slice(&[32, 32, 0, 0, 1, 0, 32, 0]);
+        ico.extend_from_slice(&8u32.to_le_bytes());
+        ico.extend_from_slice(&42u32.to_le_bytes());
+        ico.extend_from_slice(b"\x28\0\0\0\x89PNG\r\n\x1a\n");
+        ico
+    }
+
+    #[test]
+    fn test_select() {
+        let ico = icon();
+        assert_eq!(Format::from_bytes(&ico), Some(Format::Ico));
+        let listed = images(&ico);
+        assert_eq!(listed.iter().map(|image| (image.width, image.png)).collect::<Vec<_>>(), [(16, false), (32, true)]);
+        assert_eq!(describe_sizes(&listed), "16x16, 32x32");
+
+        let small = select(&ico, Some(16)).unwrap();
+        assert_eq!(&small[..8], &[0, 0, 1, 0, 1, 0, 16, 16]);
+        assert_eq!(images(&small)[0].offset, 22);
+        assert_eq!(&small[22..], b"\x28\0\0\0");
+        assert_eq!(&*select(&ico, None).unwrap(), b"\x89PNG\r\n\x1a\n");
+        assert!(select(&ico, Some(48)).unwrap_err().to_string().contains("it has 16x16, 32x32"));
+    }
+}
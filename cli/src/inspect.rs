@@ -0,0 +1,347 @@
+//! What `inspect` shows. [`inspect`] builds an [`InspectionReport`] per file
+//! (chunks, frames, boxes and tracks with their sizes and safety) for the CLI,
+//! which prints it with [`print_report`], and for the server's `/inspect`, which
+//! returns it as JSON. [`summarize`] is the one-line variant for
+//! `inspect --summary`, printed as an aligned table.
+
+use std::fmt::Display;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::metadata::metadata_blocks;
+#[cfg(feature = "mp3")]
+use crate::processor::mp3;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4;
+use crate::processor::ogg;
+use crate::report::format_size;
+use crate::term::{self, Color};
+
+/// Metadata blocks that can identify a person, place, device or time: text
+/// chunks, EXIF, XMP, IPTC, comments and MP4 user data
+const SENSITIVE_BLOCKS: [&str; 12] =
+    ["tEXt", "zTXt", "iTXt", "eXIf", "tIME", "EXIF", "GPS", "XMP", "IPTC", "Comment", "udta", "meta"];
+
+/// Full inspection of one file: file-level fields, then sections of entries
+/// (chunks, frames, boxes, tracks). Fields hold display-ready text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InspectionReport {
+    /// `PNG`, `MP3`, ... (see [`Format::as_str`])
+    pub format: &'static str,
+    pub file_size: u64,
+    pub fields: Vec<Field>,
+    pub sections: Vec<Section>,
+    /// Parts that could not be read; the rest of the report still holds
+    pub errors: Vec<String>,
+}
+
+impl InspectionReport {
+    pub fn new(format: Format, file_size: usize) -> Self {
+        Self {
+            format: format.as_str(),
+            file_size: file_size as u64,
+            fields: Vec::new(),
+            sections: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn field(&mut self, label: impl Into<String>, value: impl Display) {
+        self.fields.push(Field::new(label, value));
+    }
+}
+
+/// `label: value`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Field {
+    pub label: String,
+    pub value: String,
+}
+
+impl Field {
+    pub fn new(label: impl Into<String>, value: impl Display) -> Self {
+        Self { label: label.into(), value: value.to_string() }
+    }
+}
+
+/// A titled part of a report, e.g. "PNG Chunks" or "Tracks"
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Section {
+    pub title: String,
+    pub fields: Vec<Field>,
+    pub entries: Vec<Entry>,
+    /// Closing line, e.g. chunk counts
+    pub summary: Option<String>,
+}
+
+impl Section {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self { title: title.into(), ..Self::default() }
+    }
+
+    pub fn field(&mut self, label: impl Into<String>, value: impl Display) {
+        self.fields.push(Field::new(label, value));
+    }
+}
+
+/// One chunk, frame, box, track or stream
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Entry {
+    pub name: String,
+    /// What the entry is, e.g. "Textual Data" for a PNG `tEXt` chunk
+    pub description: Option<String>,
+    pub class: Option<EntryClass>,
+    /// Bytes of the entry's data
+    pub size: Option<u64>,
+    pub fields: Vec<Field>,
+    /// Findings worth attention, e.g. file paths in private data
+    pub warnings: Vec<String>,
+}
+
+impl Entry {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Self::default() }
+    }
+
+    pub fn field(&mut self, label: impl Into<String>, value: impl Display) {
+        self.fields.push(Field::new(label, value));
+    }
+}
+
+/// How an entry is classified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum EntryClass {
+    /// PNG chunk every decoder must understand
+    Critical,
+    /// PNG chunk a decoder may skip
+    Ancillary,
+    /// Kept by `--strip safe`
+    Safe,
+    /// Removed by `--strip safe`
+    Unsafe,
+}
+
+/// Report for `data` in `format`; formats without an inspector (or whose
+/// Cargo feature is disabled) are [`ProcessingError::UnsupportedFormat`]
+pub fn inspect(data: &[u8], format: Format) -> Result<InspectionReport, ProcessingError> {
+    match format {
+        Format::Png => crate::processor::png::inspect_png(data),
+        Format::Jpeg => crate::processor::jpeg::inspect_jpeg(data),
+        #[cfg(feature = "webp")]
+        Format::Webp => crate::processor::webp::inspect_webp(data),
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => mp3::inspect_mp3(data),
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => mp4::inspect_mp4(data),
+        Format::Heic => crate::heif::inspect_heif(data),
+        Format::Tiff => crate::tiff::inspect_tiff(data),
+        Format::Ico => crate::ico::inspect_ico(data),
+        Format::Opus | Format::Ogg => ogg::inspect_ogg(data),
+        _ => Err(ProcessingError::UnsupportedFormat(format.as_str().to_string())),
+    }
+}
+
+/// Print `report` as the CLI's `inspect` output
+pub fn print_report(report: &InspectionReport) {
+    term::heading(&format!("{} Metadata Inspection", report.format));
+    term::field(0, "File size", format!("{} bytes ({})", report.file_size, format_size(report.file_size)));
+    for field in &report.fields {
+        term::field(0, &field.label, &field.value);
+    }
+    for error in &report.errors {
+        println!("{}", term::paint(error, Color::Red));
+    }
+    println!();
+
+    for section in &report.sections {
+        term::section(&section.title);
+        for field in &section.fields {
+            term::field(2, &field.label, &field.value);
+        }
+        if !section.fields.is_empty() {
+            println!();
+        }
+        for entry in &section.entries {
+            print_entry(entry);
+        }
+        if let Some(summary) = &section.summary {
+            println!("{}", term::rule());
+            println!("Summary: {}", summary);
+        }
+        println!();
+    }
+}
+
+fn print_entry(entry: &Entry) {
+    let class = match entry.class {
+        Some(EntryClass::Critical) => format!("{} ", term::paint("[CRITICAL]", Color::Yellow)),
+        Some(EntryClass::Ancillary) => format!("{} ", term::paint("[ANCILLARY]", Color::Dim)),
+        Some(EntryClass::Safe) => format!("{} ", term::safety(true)),
+        Some(EntryClass::Unsafe) => format!("{} ", term::safety(false)),
+        None => String::new(),
+    };
+    let description = entry.description.as_ref().map(|d| format!(" - {}", d)).unwrap_or_default();
+    println!("  {}{}{}", class, term::paint(&entry.name, Color::Bold), description);
+    if let Some(size) = entry.size {
+        term::field(6, "Size", format!("{} bytes", size));
+    }
+    for field in &entry.fields {
+        term::field(6, &field.label, &field.value);
+    }
+    for warning in &entry.warnings {
+        println!("      {}", term::paint(warning, Color::Red));
+    }
+    println!();
+}
+
+/// What `inspect --summary` shows for one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSummary {
+    pub format: Format,
+    /// Image or video dimensions
+    pub dimensions: Option<(u32, u32)>,
+    /// Audio or video duration
+    pub duration: Option<Duration>,
+    /// Bytes of metadata blocks (see [`metadata_blocks`])
+    pub metadata_bytes: u64,
+    /// Sensitive metadata blocks, or for MP3 and Ogg the ID3 frames, tags and comments `--strip safe` removes
+    pub sensitive: usize,
+}
+
+/// Summary of `data`; fields that cannot be read are left empty
+pub fn summarize(data: &[u8], format: Format) -> FileSummary {
+    let blocks = metadata_blocks(data, format);
+    let (duration, dimensions) = match format {
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => (mp3::duration(data).map(Duration::from_secs_f64), None),
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => match mp4::duration_and_dimensions(data) {
+            Some((duration, dimensions)) => (Some(duration), dimensions),
+            None => (None, None),
+        },
+        Format::Avif | Format::Heic => (None, crate::heif::dimensions(data)),
+        Format::Tiff => (None, crate::tiff::dimensions(data)),
+        Format::Ico => (None, crate::ico::largest(&crate::ico::images(data)).map(|image| (image.width, image.height))),
+        Format::Opus | Format::Ogg => (ogg::duration(data).map(Duration::from_secs_f64), None),
+        format if format.is_image() || format == Format::Bmp => {
+            let reader = image::ImageReader::new(Cursor::new(data)).with_guessed_format().ok();
+            (None, reader.and_then(|reader| reader.into_dimensions().ok()))
+        }
+        _ => (None, None),
+    };
+    let sensitive = match format {
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => mp3::unsafe_item_count(data),
+        Format::Opus | Format::Ogg => ogg::unsafe_comment_count(data),
+        _ => blocks.iter().filter(|block| SENSITIVE_BLOCKS.contains(&block.name.as_str())).count(),
+    };
+
+    FileSummary {
+        format,
+        dimensions,
+        duration,
+        metadata_bytes: blocks.iter().map(|block| block.size).sum(),
+        sensitive,
+    }
+}
+
+/// Aligned table with one row per file
+pub fn print_table(rows: &[(PathBuf, FileSummary)]) {
+    const HEADERS: [&str; 5] = ["File", "Format", "Size / length", "Metadata", "Sensitive"];
+
+    let cells: Vec<[String; 5]> = rows.iter().map(|(path, summary)| row_cells(path, summary)).collect();
+    let widths: Vec<usize> = (0..HEADERS.len())
+        .map(|column| {
+            cells.iter().map(|row| row[column].chars().count()).chain([HEADERS[column].len()]).max().unwrap_or(0)
+        })
+        .collect();
+
+    // Pad before painting so escape codes do not count toward the width
+    let line = |cells: [String; 5], paint: &dyn Fn(usize, String) -> String| {
+        let padded: Vec<String> = cells
+            .into_iter()
+            .enumerate()
+            .map(|(column, cell)| match column {
+                0 | 1 => paint(column, format!("{:<width$}", cell, width = widths[column])),
+                _ => paint(column, format!("{:>width$}", cell, width = widths[column])),
+            })
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+
+    line(HEADERS.map(String::from), &|_, cell| term::paint(cell, Color::Bold));
+    for (row, (_, summary)) in cells.into_iter().zip(rows) {
+        line(row, &|column, cell| match column {
+            4 if summary.sensitive > 0 => term::paint(cell, Color::Red),
+            4 => term::paint(cell, Color::Green),
+            _ => cell,
+        });
+    }
+
+    let flagged = rows.iter().filter(|(_, summary)| summary.sensitive > 0).count();
+    println!("{}", term::rule());
+    println!("{} files, {} with sensitive metadata", rows.len(), flagged);
+}
+
+fn row_cells(path: &Path, summary: &FileSummary) -> [String; 5] {
+    let extent = match (summary.dimensions, summary.duration) {
+        (Some((width, height)), Some(duration)) => format!("{}x{}, {:.1}s", width, height, duration.as_secs_f64()),
+        (Some((width, height)), None) => format!("{}x{}", width, height),
+        (None, Some(duration)) => format!("{:.1}s", duration.as_secs_f64()),
+        (None, None) => "-".to_string(),
+    };
+    [
+        path.display().to_string(),
+        summary.format.as_str().to_string(),
+        extent,
+        format_size(summary.metadata_bytes),
+        summary.sensitive.to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 6x4 PNG with a tEXt chunk before IEND
+    fn png_with_text() -> Vec<u8> {
+        let image = image::RgbImage::new(6, 4);
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let iend = png.len() - 12;
+        let mut text = 9u32.to_be_bytes().to_vec();
+        text.extend_from_slice(b"tEXtAuthor\0me\0\0\0\0");
+        png.splice(iend..iend, text);
+        png
+    }
+
+    #[test]
+    fn test_summarize() {
+        let png = png_with_text();
+        let summary = summarize(&png, Format::Png);
+        assert_eq!(summary.dimensions, Some((6, 4)));
+        assert_eq!((summary.metadata_bytes, summary.sensitive), (21, 1));
+        assert_eq!(summary.duration, None);
+    }
+
+    #[test]
+    fn test_inspect_png() {
+        let report = inspect(&png_with_text(), Format::Png).unwrap();
+        assert_eq!(report.fields[0], Field::new("Image dimensions", "6 x 4 pixels"));
+        let chunks = &report.sections[0].entries;
+        let text = chunks.iter().find(|entry| entry.name == "tEXt").unwrap();
+        assert_eq!((text.class, text.size), (Some(EntryClass::Ancillary), Some(9)));
+        assert_eq!(text.fields, [Field::new("Author", "me")]);
+        assert_eq!(chunks[0].class, Some(EntryClass::Critical));
+
+        assert!(matches!(inspect(b"", Format::Wav), Err(ProcessingError::UnsupportedFormat(_))));
+    }
+}
@@ -1,14 +1,31 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use walkdir::WalkDir;
+use ignore::WalkBuilder;
 
+use crate::checksum::sha256_hex;
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
+use crate::exif::{self, CaptureDate};
+use crate::format::Format;
 
-/// Collect all supported image files from the input path.
-/// If `recursive` is true, walk subdirectories.
-pub fn collect_files(input: &Path, recursive: bool) -> Result<Vec<PathBuf>, ProcessingError> {
+/// Per-directory ignore file read besides `.gitignore`, with the same syntax
+pub const IGNORE_FILE_NAME: &str = ".ipignore";
+
+/// Collect files of the given formats from the input path: by extension, or for
+/// extensions that name no format (`.dat`, none) by content, see [`sniff_format`].
+/// A single file is always returned as is. If `recursive` is true, walk subdirectories.
+/// With `ignore_files`, paths matched by `.gitignore` or `.ipignore` files (in the
+/// walked directories and their parents) are left out.
+pub fn collect_files(
+    input: &Path,
+    recursive: bool,
+    formats: &[Format],
+    ignore_files: bool,
+) -> Result<Vec<PathBuf>, ProcessingError> {
     if input.is_file() {
         return Ok(vec![input.to_path_buf()]);
     }
@@ -20,21 +37,18 @@ pub fn collect_files(input: &Path, recursive: bool) -> Result<Vec<PathBuf>, Proc
         });
     }
 
-    let max_depth = if recursive { usize::MAX } else { 1 };
-
-    let files: Result<Vec<_>, _> = WalkDir::new(input)
-        .max_depth(max_depth)
-        .into_iter()
+    let files: Result<Vec<_>, _> = walk(input, recursive, ignore_files)
         .filter_map(|entry| {
             let entry = match entry {
                 Ok(e) => e,
                 Err(e) => return Some(Err(ProcessingError::from(e))),
             };
-            if !entry.file_type().is_file() {
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
                 return None;
             }
             let path = entry.into_path();
-            if ImageFormat::from_path(&path).is_some() {
+            let format = Format::from_path(&path).or_else(|| sniff_format(&path));
+            if format.is_some_and(|f| formats.contains(&f)) {
                 Some(Ok(path))
             } else {
                 None
@@ -45,6 +59,213 @@ pub fn collect_files(input: &Path, recursive: bool) -> Result<Vec<PathBuf>, Proc
     files
 }
 
+/// Walk of `input` (one level unless `recursive`), hidden files included and
+/// symlinks not followed; `ignore_files` applies `.gitignore` and `.ipignore`
+/// rules whether or not the tree is in a git repository
+fn walk(input: &Path, recursive: bool, ignore_files: bool) -> ignore::Walk {
+    let mut builder = WalkBuilder::new(input);
+    builder.standard_filters(false).max_depth((!recursive).then_some(1));
+    if ignore_files {
+        builder.git_ignore(true).parents(true).require_git(false).add_custom_ignore_filename(IGNORE_FILE_NAME);
+    }
+    builder.build()
+}
+
+/// Order batch files are handed to the worker threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FileOrder {
+    /// Largest first, so a huge video does not start last and finish alone
+    #[default]
+    Size,
+    /// Alphabetical by path
+    Name,
+    /// Directory walk order
+    None,
+}
+
+/// Reorder collected files for scheduling. Files whose size cannot be read sort last.
+pub fn sort_files(files: &mut [PathBuf], order: FileOrder) {
+    match order {
+        FileOrder::Size => files.sort_by_cached_key(|path| {
+            std::cmp::Reverse(fs::metadata(path).map(|meta| meta.len()).unwrap_or(0))
+        }),
+        FileOrder::Name => files.sort(),
+        FileOrder::None => {}
+    }
+}
+
+/// Files with identical contents among a batch's files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// The copy that is processed: the first of the group in list order
+    pub original: PathBuf,
+    /// Other files with the same bytes, hardlinks of the original included
+    pub copies: Vec<PathBuf>,
+    /// Symlinks under the input directory that resolve to the original or a copy. Directory
+    /// walks skip symlinks, so these are never processed themselves.
+    pub links: Vec<PathBuf>,
+}
+
+/// Exact duplicates among `files` (as collected from `input`), in list order.
+/// Only files that share their size with another are read and hashed (SHA-256).
+/// Groups without copies are listed when symlinks point at their file.
+pub fn find_duplicates(
+    input: &Path,
+    recursive: bool,
+    files: &[PathBuf],
+    ignore_files: bool,
+) -> Result<Vec<DuplicateGroup>, ProcessingError> {
+    let mut by_size: HashMap<u64, usize> = HashMap::new();
+    let sizes = files
+        .iter()
+        .map(|path| {
+            let meta = fs::metadata(path).map_err(|source| ProcessingError::ReadFile { path: path.clone(), source })?;
+            let size = meta.len();
+            *by_size.entry(size).or_default() += 1;
+            Ok(size)
+        })
+        .collect::<Result<Vec<u64>, ProcessingError>>()?;
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut group_of_hash: HashMap<String, usize> = HashMap::new();
+    for (path, size) in files.iter().zip(sizes) {
+        if by_size[&size] < 2 {
+            continue;
+        }
+        let hash = sha256_hex(&read_file(path)?);
+        match group_of_hash.get(&hash) {
+            Some(&index) => groups[index].copies.push(path.clone()),
+            None => {
+                group_of_hash.insert(hash, groups.len());
+                groups.push(DuplicateGroup { original: path.clone(), copies: Vec::new(), links: Vec::new() });
+            }
+        }
+    }
+    groups.retain(|group| !group.copies.is_empty());
+
+    if input.is_dir() {
+        let canonical: HashMap<PathBuf, &PathBuf> =
+            files.iter().filter_map(|path| Some((fs::canonicalize(path).ok()?, path))).collect();
+        for entry in walk(input, recursive, ignore_files).filter_map(Result::ok) {
+            if !entry.path_is_symlink() {
+                continue;
+            }
+            let Some(target) = fs::canonicalize(entry.path()).ok().and_then(|target| canonical.get(&target).copied())
+            else {
+                continue;
+            };
+            let index = groups.iter().position(|group| group.original == *target || group.copies.contains(target));
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    groups.push(DuplicateGroup { original: target.clone(), copies: Vec::new(), links: Vec::new() });
+                    groups.len() - 1
+                }
+            };
+            groups[index].links.push(entry.into_path());
+        }
+    }
+    Ok(groups)
+}
+
+/// Put the file at `source` at `dest` too: as a hardlink, or a copy when linking
+/// fails (another filesystem, no link support). Whatever was at `dest` is replaced.
+pub fn link_or_copy(source: &Path, dest: &Path) -> Result<(), ProcessingError> {
+    let write_error = |source| ProcessingError::WriteFile { path: dest.to_path_buf(), source };
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|source| ProcessingError::WriteFile { path: parent.to_path_buf(), source })?;
+    }
+    match fs::remove_file(dest) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(write_error(e)),
+        _ => {}
+    }
+    if fs::hard_link(source, dest).is_err() {
+        fs::copy(source, dest).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// What a batch does when several inputs would be written to the same output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CollisionPolicy {
+    /// Stop before processing anything
+    #[default]
+    Error,
+    /// Add `-1`, `-2`, ... to the names of all but one of the colliding outputs
+    Rename,
+}
+
+/// Inputs that would all be written to `output`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub output: PathBuf,
+    pub sources: Vec<PathBuf>,
+}
+
+/// Outputs of a planned batch (`(input, output)` pairs) shared by several inputs.
+/// Paths are compared ignoring case, as case-insensitive filesystems would, except
+/// that files rewritten in place never collide: they already coexist.
+pub fn find_collisions(plan: &[(PathBuf, PathBuf)]) -> Vec<Collision> {
+    let mut by_output: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, (_, output)) in plan.iter().enumerate() {
+        by_output.entry(collision_key(output)).or_default().push(index);
+    }
+    let mut collisions: Vec<Collision> = by_output
+        .into_values()
+        .filter(|indices| {
+            let in_place = indices.iter().filter(|&&index| in_place(&plan[index])).count();
+            indices.len() > 1 && in_place < indices.len()
+        })
+        .map(|indices| {
+            let mut sources: Vec<PathBuf> = indices.iter().map(|&index| plan[index].0.clone()).collect();
+            sources.sort();
+            Collision { output: plan[indices[0]].1.clone(), sources }
+        })
+        .collect();
+    collisions.sort_by(|a, b| a.output.cmp(&b.output));
+    collisions
+}
+
+/// Give colliding outputs in `plan` distinct names: one input of each collision
+/// keeps the output (the one rewritten in place, else the first by path), the
+/// others get `{stem}-{n}.{ext}` with the lowest `n` not taken. Returns the
+/// number of renamed outputs.
+pub fn disambiguate(plan: &mut [(PathBuf, PathBuf)]) -> usize {
+    let collisions = find_collisions(plan);
+    let mut taken: HashSet<String> = plan.iter().map(|(_, output)| collision_key(output)).collect();
+    let mut renamed = 0;
+    for collision in collisions {
+        let key = collision_key(&collision.output);
+        let mut indices: Vec<usize> =
+            (0..plan.len()).filter(|&index| collision_key(&plan[index].1) == key).collect();
+        indices.sort_by_key(|&index| (!in_place(&plan[index]), plan[index].0.clone()));
+        for &index in &indices[1..] {
+            let output = &plan[index].1;
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let extension = output.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+            let renamed_output = (1..)
+                .map(|n| output.with_file_name(format!("{}-{}{}", stem, n, extension)))
+                .find(|candidate| !taken.contains(&collision_key(candidate)))
+                .expect("unbounded suffixes");
+            taken.insert(collision_key(&renamed_output));
+            plan[index].1 = renamed_output;
+            renamed += 1;
+        }
+    }
+    renamed
+}
+
+/// Whether `input` is rewritten in place, by the same case-insensitive comparison
+fn in_place((input, output): &(PathBuf, PathBuf)) -> bool {
+    collision_key(input) == collision_key(output)
+}
+
+fn collision_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
 /// Resolve the output path for a given input file.
 /// If `output_base` is None, return the input path (overwrite in-place).
 /// If `output_base` is a directory, mirror the relative structure.
@@ -72,6 +293,105 @@ pub fn resolve_output(
     }
 }
 
+/// Directory layout for `--organize-by-date`: a relative path with `{year}`,
+/// `{month}` and `{day}` placeholders, e.g. `{year}/{month}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateTemplate(String);
+
+/// Directory for files without a capture date or modification time
+pub const UNDATED_DIR: &str = "undated";
+
+impl std::str::FromStr for DateTemplate {
+    type Err = ProcessingError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| ProcessingError::InvalidConfig(format!("date template {:?} {}", template, reason));
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(|| invalid("has an unclosed '{'"))? + start;
+            if !matches!(&rest[start + 1..end], "year" | "month" | "day") {
+                return Err(invalid("may only use {year}, {month} and {day}"));
+            }
+            rest = &rest[end + 1..];
+        }
+        let path = Path::new(template);
+        let normal = path.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+        if template.is_empty() || path.is_absolute() || !normal {
+            return Err(invalid("must be a relative path without '.' or '..'"));
+        }
+        Ok(Self(template.to_string()))
+    }
+}
+
+impl DateTemplate {
+    /// Directory for `date` (zero-padded month and day), or [`UNDATED_DIR`]
+    pub fn render(&self, date: Option<CaptureDate>) -> PathBuf {
+        match date {
+            Some(date) => PathBuf::from(
+                self.0
+                    .replace("{year}", &date.year.to_string())
+                    .replace("{month}", &format!("{:02}", date.month))
+                    .replace("{day}", &format!("{:02}", date.day)),
+            ),
+            None => PathBuf::from(UNDATED_DIR),
+        }
+    }
+
+    /// `dir/{rendered}/{file name of output}`, dated by the EXIF capture date in
+    /// `data` (the contents of `source`), else by `source`'s modification time
+    pub fn place(&self, dir: &Path, output: &Path, source: &Path, data: &[u8]) -> PathBuf {
+        let date = Format::from_path(source).and_then(|format| exif::capture_date(data, format)).or_else(|| {
+            let modified = fs::metadata(source).and_then(|meta| meta.modified()).ok()?;
+            CaptureDate::from_system_time(modified)
+        });
+        dir.join(self.render(date)).join(output.file_name().unwrap_or_default())
+    }
+}
+
+/// How often file reads and writes are repeated when they fail with a transient
+/// error ([`ProcessingError::is_transient`]), waiting twice as long each time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub fn none() -> Self {
+        Self { retries: 0, ..Self::default() }
+    }
+
+    /// Run `op`, repeating it while it fails with a transient error and retries remain
+    pub fn run<T>(&self, mut op: impl FnMut() -> Result<T, ProcessingError>) -> Result<T, ProcessingError> {
+        let mut delay = self.initial_delay;
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    log::warn!("{}; retrying in {:?} ({}/{})", e, delay, attempt, self.retries);
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
 /// Create a .bak backup of the file if it exists.
 pub fn create_backup(path: &Path) -> Result<(), ProcessingError> {
     if path.exists() {
@@ -87,6 +407,16 @@ pub fn create_backup(path: &Path) -> Result<(), ProcessingError> {
     Ok(())
 }
 
+/// Format of the file at `path` by its first bytes, None when they match no
+/// format or the file cannot be read
+pub fn sniff_format(path: &Path) -> Option<Format> {
+    use std::io::Read;
+
+    let mut head = Vec::with_capacity(64);
+    fs::File::open(path).ok()?.take(64).read_to_end(&mut head).ok()?;
+    Format::from_bytes(&head)
+}
+
 /// Read file contents.
 pub fn read_file(path: &Path) -> Result<Vec<u8>, ProcessingError> {
     fs::read(path).map_err(|e| ProcessingError::ReadFile {
@@ -95,7 +425,15 @@ pub fn read_file(path: &Path) -> Result<Vec<u8>, ProcessingError> {
     })
 }
 
+/// Temp files [`write_file`] is still writing, for [`remove_partial_outputs`]
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
 /// Write file contents, creating parent directories as needed.
+///
+/// The data goes to a temp file next to `path` that is renamed over it once
+/// complete, so an interrupted write never leaves a truncated output (or, in
+/// place, a truncated original). A symlink is written through to its target;
+/// an existing file's permissions carry over.
 pub fn write_file(path: &Path, data: &[u8]) -> Result<(), ProcessingError> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).map_err(|e| ProcessingError::WriteFile {
@@ -103,8 +441,195 @@ pub fn write_file(path: &Path, data: &[u8]) -> Result<(), ProcessingError> {
             source: e,
         })?;
     }
-    fs::write(path, data).map_err(|e| ProcessingError::WriteFile {
-        path: path.to_path_buf(),
-        source: e,
+    let target = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    };
+    let temp = partial_path(&target);
+    PARTIAL.lock().unwrap().push(temp.clone());
+    let written = fs::write(&temp, data).and_then(|()| {
+        if let Ok(meta) = fs::metadata(&target) {
+            fs::set_permissions(&temp, meta.permissions())?;
+        }
+        fs::rename(&temp, &target)
+    });
+    PARTIAL.lock().unwrap().retain(|partial| *partial != temp);
+    written.map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        ProcessingError::WriteFile {
+            path: path.to_path_buf(),
+            source: e,
+        }
     })
 }
+
+/// `.{name}.{pid}-{n}.partial` next to `path`; no known extension, so never collected as input
+fn partial_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}-{}.partial", name, std::process::id(), id))
+}
+
+/// Delete the temp files of writes still in progress, for a process about to
+/// exit without waiting for them. Their outputs keep their previous contents.
+pub fn remove_partial_outputs() {
+    for path in PARTIAL.lock().unwrap().drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = std::env::temp_dir().join(format!("ip_duplicates_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, data) in [("a.png", "same"), ("b.png", "same"), ("c.png", "diff"), ("d.png", "longer")] {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("d.png"), dir.join("link.png")).unwrap();
+
+        let files = ["a.png", "b.png", "c.png", "d.png"].map(|name| dir.join(name));
+        let groups = find_duplicates(&dir, false, &files, false).unwrap();
+        assert_eq!((&groups[0].original, &groups[0].copies[..]), (&files[0], &files[1..2]));
+        #[cfg(unix)]
+        assert_eq!((&groups[1].original, &groups[1].links[..]), (&files[3], &[dir.join("link.png")][..]));
+
+        link_or_copy(&files[2], &files[0]).unwrap();
+        assert_eq!(fs::read(&files[0]).unwrap(), b"diff");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut plan: Vec<(PathBuf, PathBuf)> = [
+            ("in/photo.jpg", "out/photo.png"),
+            ("in/photo.jpeg", "out/photo.png"),
+            ("in/Photo.png", "out/Photo.png"),
+            ("in/photo-1.png", "out/photo-1.png"),
+            ("a.PNG", "a.PNG"),
+            ("a.png", "a.png"),
+            ("in/b.jpg", "in/b.png"),
+            ("in/b.PNG", "in/b.png"),
+        ]
+        .map(|(input, output)| (PathBuf::from(input), PathBuf::from(output)))
+        .to_vec();
+
+        let collisions = find_collisions(&plan);
+        assert_eq!(collisions.len(), 2);
+        let sources: Vec<&str> = collisions[1].sources.iter().map(|path| path.to_str().unwrap()).collect();
+        assert_eq!(sources, ["in/Photo.png", "in/photo.jpeg", "in/photo.jpg"]);
+
+        // First by path keeps the name; photo-1.png is taken. b.PNG is b.png on a
+        // case-insensitive filesystem, so it keeps its name and b.jpg moves
+        assert_eq!(disambiguate(&mut plan), 3);
+        let outputs: Vec<&str> = plan.iter().map(|(_, output)| output.to_str().unwrap()).collect();
+        assert_eq!(outputs[..4], ["out/photo-3.png", "out/photo-2.png", "out/Photo.png", "out/photo-1.png"]);
+        assert_eq!(outputs[6..], ["in/b-1.png", "in/b.png"]);
+        assert!(find_collisions(&plan).is_empty());
+    }
+
+    #[test]
+    fn test_organized_collisions() {
+        // Undated (missing) files from different directories land in the same directory
+        let template: DateTemplate = "{year}/{month}".parse().unwrap();
+        let out = Path::new("out");
+        let mut plan: Vec<(PathBuf, PathBuf)> = ["in/a/IMG_0001.jpg", "in/b/IMG_0001.jpg"]
+            .map(|source| (PathBuf::from(source), template.place(out, Path::new(source), Path::new(source), b"")))
+            .to_vec();
+        assert_eq!(plan[0].1, Path::new("out").join(UNDATED_DIR).join("IMG_0001.jpg"));
+
+        let collisions = find_collisions(&plan);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].sources, [PathBuf::from("in/a/IMG_0001.jpg"), PathBuf::from("in/b/IMG_0001.jpg")]);
+        assert_eq!(disambiguate(&mut plan), 1);
+        assert_eq!(plan[1].1, Path::new("out").join(UNDATED_DIR).join("IMG_0001-1.jpg"));
+    }
+
+    #[test]
+    fn test_collect_files_ignores() {
+        let dir = std::env::temp_dir().join(format!("ip_ignores_{}", std::process::id()));
+        for sub in ["src", "dist", "vendor"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            fs::write(dir.join(sub).join("a.png"), b"").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "dist/\n").unwrap();
+        fs::write(dir.join(IGNORE_FILE_NAME), "vendor\n").unwrap();
+
+        let files = collect_files(&dir, true, &[Format::Png], true).unwrap();
+        assert_eq!(files, [dir.join("src/a.png")]);
+        assert_eq!(collect_files(&dir, true, &[Format::Png], false).unwrap().len(), 3);
+
+        // Unknown extensions are taken by content
+        fs::write(dir.join("src/shot.dat"), b"\x89PNG\r\n\x1a\n").unwrap();
+        fs::write(dir.join("src/notes.txt"), b"hello").unwrap();
+        let mut files = collect_files(&dir.join("src"), false, &[Format::Png], true).unwrap();
+        files.sort();
+        assert_eq!(files, [dir.join("src/a.png"), dir.join("src/shot.dat")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_file_replaces() {
+        let dir = std::env::temp_dir().join(format!("ip_write_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("real.png"), b"old contents").unwrap();
+        std::os::unix::fs::symlink(dir.join("real.png"), dir.join("link.png")).unwrap();
+
+        write_file(&dir.join("link.png"), b"new").unwrap();
+        assert_eq!(fs::read(dir.join("real.png")).unwrap(), b"new");
+        assert!(fs::symlink_metadata(dir.join("link.png")).unwrap().file_type().is_symlink());
+        // Only the two files: no temp file left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        };
+        let busy = || ProcessingError::Io(std::io::Error::from(std::io::ErrorKind::Interrupted));
+
+        // A transient failure is repeated until it succeeds
+        let mut calls = 0;
+        let result = policy.run(|| {
+            calls += 1;
+            if calls < 3 { Err(busy()) } else { Ok(calls) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // ... but only `retries` times
+        let mut calls = 0;
+        let result: Result<(), _> = policy.run(|| {
+            calls += 1;
+            Err(busy())
+        });
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(calls, 3);
+
+        // Permanent errors and RetryPolicy::none() fail at once
+        let mut calls = 0;
+        let result: Result<(), _> = policy.run(|| {
+            calls += 1;
+            Err(ProcessingError::Io(std::io::Error::from(std::io::ErrorKind::NotFound)))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<(), _> = RetryPolicy::none().run(|| {
+            calls += 1;
+            Err(busy())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
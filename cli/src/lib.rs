@@ -1,9 +1,88 @@
+//! Compression, conversion and metadata stripping for PNG, JPEG, WebP, AVIF, GIF, MP3 and MP4.
+//! HEIC photos can be converted and inspected, but are not written.
+//!
+//! Most embedders only need [`Preparer`]:
+//!
+//! ```no_run
+//! use image_preparer::{Format, Preparer, StripMode};
+//!
+//! let preparer = Preparer::builder().quality(80).strip(StripMode::Safe).build()?;
+//! let data = std::fs::read("photo.png")?;
+//! let compressed = preparer.process_bytes(&data, Format::Png)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The lower-level [`pipeline::Pipeline`] and individual processors stay
+//! available for callers that need custom processor sets.
+//!
+//! WebP, AVIF, GIF, MP3, MP4 and lossy PNG sit behind the `webp`, `avif`, `gif`,
+//! `mp3`, `mp4` and `quantize` features (all on by default), so a PNG/JPEG-only
+//! build can skip imagequant, rav1e, id3, mp4 and the ffmpeg process spawning.
+
+#[cfg(all(feature = "fs", feature = "parallel"))]
+pub mod batch;
+#[cfg(feature = "fs")]
+pub mod cache;
+pub mod cancel;
+pub mod chain;
+pub mod checksum;
+#[cfg(feature = "cli")]
 pub mod cli;
+pub mod comparison;
 pub mod config;
+#[cfg(feature = "config-file")]
+pub mod config_file;
 pub mod converter;
+#[cfg(feature = "ffmpeg")]
+pub mod doctor;
 pub mod error;
+pub mod exif;
+pub mod explain;
+pub mod favicon;
+#[cfg(feature = "ffmpeg")]
+pub mod ffmpeg;
+pub mod flac;
 pub mod format;
+pub mod heif;
+pub mod ico;
+pub mod inspect;
+#[cfg(feature = "fs")]
 pub mod io;
+pub mod limits;
+#[cfg(all(feature = "fs", feature = "serde"))]
+pub mod manifest;
+pub mod marker;
+pub mod metadata;
+pub mod outcome;
+#[cfg(feature = "parallel")]
+pub mod parallel;
 pub mod pipeline;
+pub mod preparer;
 pub mod processor;
+pub mod profile;
+pub mod progress;
 pub mod report;
+pub mod responsive;
+#[cfg(feature = "serde")]
+pub mod sidecar;
+pub mod tags;
+pub mod term;
+pub mod tiff;
+pub mod transform;
+pub mod tune;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod waveform;
+
+pub use config::{
+    AudioTrack, AvifOptions, CoverArt, CoverFormat, GifOptions, HwAccel, IcoOptions, JpegOptions, Mp3Options,
+    Mp4Options, OpusOptions, PngOptions, ProcessingConfig, SilenceTrim, StripMode, TiffOptions, TimeRange, VideoCodec,
+    WavOptions, WebpOptions,
+};
+pub use error::ProcessingError;
+pub use format::Format;
+pub use limits::Limits;
+pub use outcome::ProcessOutcome;
+pub use preparer::{Preparer, PreparerBuilder};
+pub use profile::Profile;
+pub use progress::{ProgressEvent, ProgressSink};
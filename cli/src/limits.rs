@@ -0,0 +1,200 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageBuffer, ImageError, ImageReader};
+use zune_core::bytestream::ZCursor;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+use crate::error::ProcessingError;
+use crate::format::Format;
+
+/// Resource limits checked before decoding, so a tiny file claiming
+/// 100000×100000 pixels is rejected instead of allocating tens of gigabytes.
+/// `None` disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Limits {
+    /// Maximum width × height of a decoded image
+    pub max_pixels: Option<u64>,
+    /// Maximum bytes for a decoded pixel buffer
+    pub max_alloc: Option<u64>,
+    /// Maximum size of the encoded input, in bytes
+    pub max_input_size: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_pixels: Some(100_000_000),
+            max_alloc: Some(512 * 1024 * 1024),
+            max_input_size: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_pixels: None,
+            max_alloc: None,
+            max_input_size: None,
+        }
+    }
+
+    pub fn check_input(&self, len: usize) -> Result<(), ProcessingError> {
+        match self.max_input_size {
+            Some(max) if len as u64 > max => Err(ProcessingError::LimitExceeded(format!(
+                "input is {} bytes, limit is {}",
+                len, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check header dimensions against the pixel limit and the size of an RGBA8 buffer
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), ProcessingError> {
+        let pixels = width as u64 * height as u64;
+        if let Some(max) = self.max_pixels {
+            if pixels > max {
+                return Err(ProcessingError::LimitExceeded(format!(
+                    "{}x{} is {} pixels, limit is {}",
+                    width, height, pixels, max
+                )));
+            }
+        }
+        if let Some(max) = self.max_alloc {
+            if pixels * 4 > max {
+                return Err(ProcessingError::LimitExceeded(format!(
+                    "{}x{} needs {} bytes decoded, limit is {}",
+                    width,
+                    height,
+                    pixels * 4,
+                    max
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode an image after checking its header against `limits`.
+/// `format` of None guesses the format from the content.
+pub(crate) fn decode_image(
+    input: &[u8],
+    format: Option<image::ImageFormat>,
+    limits: &Limits,
+) -> Result<DynamicImage, ProcessingError> {
+    limits.check_input(input.len())?;
+
+    // The image crate has no HEIF support; HEIC is recognized by its brands
+    if format.is_none() && Format::from_bytes(input) == Some(Format::Heic) {
+        return crate::heif::decode_heic(input, limits);
+    }
+    match format.or_else(|| image::guess_format(input).ok()) {
+        Some(image::ImageFormat::Jpeg) => return decode_jpeg(input, limits),
+        #[cfg(feature = "avif")]
+        Some(image::ImageFormat::Avif) => return crate::processor::avif::decode_avif(input, limits),
+        _ => {}
+    }
+
+    check_header(input, format, limits)?;
+
+    let mut image_limits = image::Limits::no_limits();
+    image_limits.max_alloc = limits.max_alloc;
+
+    let mut reader = reader(input, format)?;
+    reader.limits(image_limits);
+    reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => ProcessingError::LimitExceeded(e.to_string()),
+        _ => ProcessingError::Decode(e.to_string()),
+    })
+}
+
+/// Check the dimensions in the header of `input` against `limits`, as decoding
+/// it would, without decoding the pixels
+pub(crate) fn check_header(
+    input: &[u8],
+    format: Option<image::ImageFormat>,
+    limits: &Limits,
+) -> Result<(), ProcessingError> {
+    let (width, height) = reader(input, format)?
+        .into_dimensions()
+        .map_err(|e| ProcessingError::Decode(e.to_string()))?;
+    limits.check_dimensions(width, height)
+}
+
+/// JPEG straight through zune-jpeg. The image crate uses the same decoder but
+/// copies the whole input for each of its two header passes; on photo batches
+/// decode dominates, so the headers are parsed once here, from the borrowed slice.
+fn decode_jpeg(input: &[u8], limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    let decode_error = |e: zune_jpeg::errors::DecodeErrors| ProcessingError::Decode(e.to_string());
+    let options = DecoderOptions::default()
+        .set_strict_mode(false)
+        .set_max_width(usize::MAX)
+        .set_max_height(usize::MAX);
+    let mut decoder = JpegDecoder::new_with_options(ZCursor::new(input), options);
+    decoder.decode_headers().map_err(decode_error)?;
+
+    let (width, height) = decoder.dimensions().expect("headers were decoded");
+    let (width, height) = (width as u32, height as u32);
+    limits.check_dimensions(width, height)?;
+
+    // Same output as the image crate: gray and RGB(A) as stored, anything else (CMYK, YCCK) as RGB
+    let colorspace = match decoder.input_colorspace().expect("headers were decoded") {
+        space @ (ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::Luma | ColorSpace::LumaA) => space,
+        _ => ColorSpace::RGB,
+    };
+    decoder.set_options(decoder.options().jpeg_set_out_colorspace(colorspace));
+    let pixels = decoder.decode().map_err(decode_error)?;
+
+    let image = match colorspace {
+        ColorSpace::Luma => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        ColorSpace::LumaA => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        ColorSpace::RGBA => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        _ => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+    };
+    image.ok_or_else(|| ProcessingError::Decode("JPEG decoder returned a truncated image".to_string()))
+}
+
+fn reader(input: &[u8], format: Option<image::ImageFormat>) -> Result<ImageReader<Cursor<&[u8]>>, ProcessingError> {
+    match format {
+        Some(format) => Ok(ImageReader::with_format(Cursor::new(input), format)),
+        None => ImageReader::new(Cursor::new(input))
+            .with_guessed_format()
+            .map_err(|e| ProcessingError::Decode(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_dimensions_rejects_bomb() {
+        let limits = Limits::default();
+        assert!(limits.check_dimensions(4000, 3000).is_ok());
+        assert!(matches!(
+            limits.check_dimensions(100_000, 100_000),
+            Err(ProcessingError::LimitExceeded(_))
+        ));
+        assert!(Limits::unlimited().check_dimensions(100_000, 100_000).is_ok());
+    }
+
+    #[test]
+    fn test_decode_jpeg_matches_image_crate() {
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_fn(48, 32, |x, y| {
+            image::Rgb([(x * 5) as u8, (y * 7) as u8, 128])
+        }));
+        let mut jpeg = Vec::new();
+        source.write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+
+        let fast = decode_image(&jpeg, None, &Limits::default()).unwrap();
+        let reference = image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!(fast.color(), reference.color());
+        assert_eq!(fast.as_bytes(), reference.as_bytes());
+
+        let tiny = Limits { max_pixels: Some(100), ..Limits::default() };
+        assert!(matches!(decode_image(&jpeg, None, &tiny), Err(ProcessingError::LimitExceeded(_))));
+    }
+}
@@ -1,190 +1,1054 @@
-use std::path::Path;
-use std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
 
-use image_preparer::cli::{Cli, Command};
-use image_preparer::config::{ProcessingConfig, StripMode};
-use image_preparer::converter::{ConvertFormat, convert_image};
-use image_preparer::format::ImageFormat;
-use image_preparer::io::{collect_files, create_backup, read_file, resolve_output, write_file};
+use image_preparer::batch::{
+    schedule, BatchFile, BatchHooks, BatchRunner, Compress, Convert, FileStatus, Operation, Resize,
+};
+use image_preparer::cli::{given_args, override_given, Cli, Command, ConvertPreset, MetaAction, ResponsiveArgs, Snippet};
+use image_preparer::chain::ChainProfile;
+use image_preparer::cache::{config_fingerprint, Digest, ProcessedCache, CACHE_FILE_NAME};
+use image_preparer::cancel::CancelToken;
+use image_preparer::checksum::{sha256_hex, ChecksumList};
+use image_preparer::comparison::{render_comparison, ComparisonStyle};
+use image_preparer::config::{ProcessingConfig, StripMode, TimeRange};
+use image_preparer::config_file::ConfigFile;
+use image_preparer::doctor::{print_checks, run_checks, CheckStatus};
+use image_preparer::error::ProcessingError;
+use image_preparer::explain::{explain, print_plan};
+use image_preparer::ffmpeg;
+use image_preparer::favicon::{link_tags, render_favicons, FaviconOptions};
+use image_preparer::format::{map_extension, Format};
+use image_preparer::inspect::{inspect, print_report, print_table, summarize};
+use image_preparer::manifest::Manifest;
+use image_preparer::marker;
+use image_preparer::io::{
+    collect_files, create_backup, disambiguate, find_collisions, find_duplicates, link_or_copy, read_file,
+    remove_partial_outputs, resolve_output, sort_files, write_file, CollisionPolicy, DateTemplate, DuplicateGroup,
+    FileOrder, RetryPolicy,
+};
+use image_preparer::outcome::ProcessOutcome;
+use image_preparer::parallel::{auto_pool_size, lower_priority, MemoryBudget, Parallelism};
 use image_preparer::pipeline::Pipeline;
-use image_preparer::processor::png::{PngProcessor, inspect_png};
-use image_preparer::processor::mp3::{Mp3Processor, inspect_mp3};
-use image_preparer::processor::webp::{WebpProcessor, inspect_webp};
-use image_preparer::processor::mp4::{Mp4Processor, inspect_mp4, extract_frames_to_png};
-use image_preparer::report::{FileResult, Report};
+use image_preparer::processor::mp4::{extract_frames, FrameExtraction, FrameSelection};
+use image_preparer::progress::{ProgressEvent, ProgressSink};
+use image_preparer::report::{format_size, savings_pct, FileResult, Report, ReportFormat};
+use image_preparer::responsive::{picture_html, render_variants, srcset_json};
+use image_preparer::sidecar::{sidecar_path, Sidecar, SidecarStatus};
+use image_preparer::tags::{self, edit_tags, read_tags, Field, TagEdit};
+use image_preparer::term::{self, Color};
+use image_preparer::transform::ResizeOptions;
+use image_preparer::watch::{WatchOptions, Watcher};
+use image_preparer::waveform::{render_waveform, WaveformOptions};
+
+/// `println!` for batch messages, on stderr when [`Batch::report_on_stdout`]
+macro_rules! say {
+    ($batch:expr, $($arg:tt)*) => {
+        if $batch.report_on_stdout() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Before anything prints: the report, the logger and the progress bars all follow NO_COLOR
+    if cli.no_color {
+        std::env::set_var("NO_COLOR", "1");
+    }
 
     // Init logging
     let log_level = if cli.verbose { "debug" } else { "warn" };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
 
-    match &cli.command {
+    // Before any worker thread or ffmpeg starts, so they all inherit it
+    if cli.nice {
+        if let Err(e) = lower_priority() {
+            log::warn!("Could not lower the priority: {}", e);
+        }
+    }
+
+    if let Some(path) = &cli.ffmpeg_path {
+        ffmpeg::set_binary(path);
+    }
+    ffmpeg::set_max_jobs(cli.ffmpeg_jobs);
+    ffmpeg::set_timeout(cli.ffmpeg_timeout.map(Duration::from_secs));
+    if cli.verbose && ffmpeg::probe().is_none() {
+        log::debug!("ffmpeg not found at {}", ffmpeg::binary().display());
+    }
+    let interrupt = CancelToken::new();
+    handle_interrupts(interrupt.clone());
+
+    let config_file = match (&cli.config, cli.no_config) {
+        (Some(path), _) => Some(ConfigFile::load(path)?),
+        (None, false) => ConfigFile::discover(Path::new("."))?,
+        (None, true) => None,
+    };
+    if let Some(file) = &config_file {
+        log::debug!("Settings from {}", file.path.display());
+    }
+    // `--map-extension` replaces a mapping of the same extension from the file
+    let file_extensions = config_file.iter().flat_map(|file| &file.extensions);
+    for (extension, format) in file_extensions.chain(&cli.map_extension) {
+        map_extension(extension, *format);
+    }
+    let given = given_args(&matches);
+    // The file's settings over the config the flags built, then the flags given again on top
+    let settings = |config: ProcessingConfig| -> Result<ProcessingConfig> {
+        match &config_file {
+            Some(file) => Ok(override_given(file.apply_to(&config)?, &config, &given)),
+            None => Ok(config),
+        }
+    };
+    let output_or_file = |output: &Option<PathBuf>| output.clone().or_else(|| config_file.as_ref()?.output.clone());
+
+    let budget = cli.max_memory.map(MemoryBudget::new);
+    let manifest = cli.manifest.as_ref().map(|path| Mutex::new(Manifest::new(path)));
+    let checksums = cli.checksums.as_ref().map(|path| Mutex::new(ChecksumList::new(path)));
+    let batch = Batch {
+        budget: budget.as_ref(),
+        order: cli.order,
+        retry: RetryPolicy { retries: cli.retries, ..RetryPolicy::default() },
+        jobs: cli.jobs.map(usize::from),
+        manifest: manifest.as_ref(),
+        checksums: checksums.as_ref(),
+        checksum_input: cli.checksum_input,
+        organize: cli.organize_by_date.as_ref(),
+        ignore_files: !cli.no_ignore,
+        collisions: cli.on_collision,
+        report_format: cli.report_format.or_else(|| {
+            let path = cli.report_file.as_deref()?;
+            Some(ReportFormat::from_path(path).unwrap_or(ReportFormat::Json))
+        }),
+        report_file: cli.report_file.as_deref(),
+        interrupt: &interrupt,
+    };
+
+    let result = match &cli.command {
         Command::Compress {
             input,
             output,
             quality,
             speed,
             no_lossy,
+            target_ssim,
             strip,
+            no_auto_orient,
             recursive,
             backup,
             dry_run,
+            limits,
+            no_cache,
+            cache_path,
+            skip_processed: _,
+            mark,
+            force,
+            save_comparison,
+            comparison_style,
+            sidecar,
+            find_duplicates,
+            dedupe_exact,
+            profile,
+            png,
+            mp3,
+            mp4,
+            wav,
+            silence,
         } => {
-            let config = cli.to_config(*quality, *speed, *no_lossy, *strip, *dry_run, *backup);
-            handle_compress(input, output.as_deref(), *recursive, &config)
+            let mut config = cli.to_config(*quality, *speed, *no_lossy, *strip, *dry_run, *backup);
+            config.auto_orient = !no_auto_orient;
+            config.target_ssim = *target_ssim;
+            config.png = png.to_options();
+            config.mp3 = mp3.to_options(mp4.audio_bitrate);
+            config.mp4 = mp4.to_options();
+            config.wav = wav.to_options();
+            config.trim_silence = silence.to_trim();
+            limits.apply(&mut config.limits);
+            let config = settings(config)?;
+            let output = output_or_file(output);
+            let resume = Resume {
+                cache_file: (!no_cache).then(|| cache_path.clone().unwrap_or_else(|| default_cache_file(input))),
+                mark: *mark,
+                force: *force,
+            };
+            let review = Review {
+                comparison: save_comparison.as_deref().map(|dir| (dir, comparison_style.unwrap_or_default())),
+                sidecar: *sidecar,
+            };
+            let duplicates = match (*dedupe_exact, *find_duplicates) {
+                (true, _) => Duplicates::Link,
+                (false, true) => Duplicates::Report,
+                (false, false) => Duplicates::Process,
+            };
+            let (output, profile) = (output.as_deref(), *profile);
+            handle_compress(input, output, *recursive, &config, profile, duplicates, &resume, &review, &batch)
+        }
+        Command::Explain {
+            input,
+            output,
+            quality,
+            speed,
+            no_lossy,
+            target_ssim,
+            strip,
+            no_auto_orient,
+            recursive,
+            no_cache,
+            cache_path,
+            skip_processed: _,
+            force,
+            profile,
+            png,
+            mp3,
+            mp4,
+            wav,
+            silence,
+        } => {
+            let mut config = cli.to_config(*quality, *speed, *no_lossy, *strip, true, false);
+            config.auto_orient = !no_auto_orient;
+            config.target_ssim = *target_ssim;
+            config.png = png.to_options();
+            config.mp3 = mp3.to_options(mp4.audio_bitrate);
+            config.mp4 = mp4.to_options();
+            config.wav = wav.to_options();
+            config.trim_silence = silence.to_trim();
+            let config = settings(config)?;
+            let output = output_or_file(output);
+            let resume = Resume {
+                cache_file: (!no_cache).then(|| cache_path.clone().unwrap_or_else(|| default_cache_file(input))),
+                mark: false,
+                force: *force,
+            };
+            handle_explain(input, output.as_deref(), *recursive, &config, *profile, &resume, &batch)
         }
         Command::Convert {
             input,
             output,
             to,
+            preset,
             quality,
             no_lossy,
+            target_ssim,
+            strip,
+            no_auto_orient,
             recursive,
             backup,
+            fps,
+            width,
+            trim,
+            audio_bitrate,
+            page,
+            ico_size,
+            limits,
+            responsive,
         } => {
-            let config = ProcessingConfig {
+            let mut config = ProcessingConfig {
                 quality: *quality,
                 speed: 3,
                 no_lossy: *no_lossy,
-                strip: StripMode::All,
+                strip: *strip,
+                auto_orient: !no_auto_orient,
                 dry_run: false,
                 backup: *backup,
-                extract_frames: false,
-                fps: 0.0,
+                target_ssim: *target_ssim,
+                ..ProcessingConfig::default()
+            };
+            config.mp4.animation_fps = *fps;
+            config.mp4.animation_width = *width;
+            config.mp4.trim = *trim;
+            config.opus.bitrate = *audio_bitrate;
+            config.tiff.page = *page;
+            config.ico.size = *ico_size;
+            limits.apply(&mut config.limits);
+            let config = settings(config)?;
+            let output = output_or_file(output);
+            match (preset, to) {
+                // Checked here: declaring the conflict in clap would disable the preset options' `requires`
+                (Some(_), Some(_)) => anyhow::bail!("--preset replaces --to; use one of them"),
+                (Some(ConvertPreset::Responsive), None) => {
+                    handle_responsive(input, output.as_deref(), *recursive, responsive, &config, &batch)
+                }
+                (None, Some(to)) => handle_convert(input, output.as_deref(), to, *recursive, &config, &batch),
+                (None, None) => anyhow::bail!("Either --to or --preset is required"),
+            }
+        }
+        Command::Resize {
+            input,
+            output,
+            size,
+            then_compress,
+            quality,
+            speed,
+            no_lossy,
+            no_auto_orient,
+            recursive,
+            backup,
+            limits,
+            png,
+        } => {
+            let mut config = cli.to_config(*quality, *speed, *no_lossy, StripMode::All, false, *backup);
+            config.auto_orient = !no_auto_orient;
+            config.png = png.to_options();
+            limits.apply(&mut config.limits);
+            let config = settings(config)?;
+            let output = output_or_file(output);
+            let options = size.to_options();
+            handle_resize(input, output.as_deref(), *recursive, &options, *then_compress, &config, &batch)
+        }
+        Command::Watch {
+            input,
+            output,
+            to,
+            quality,
+            speed,
+            no_lossy,
+            strip,
+            no_auto_orient,
+            recursive,
+            debounce,
+            ignore,
+            backup,
+            limits,
+            png,
+        } => {
+            let mut config = cli.to_config(*quality, *speed, *no_lossy, *strip, false, *backup);
+            config.auto_orient = !no_auto_orient;
+            config.png = png.to_options();
+            limits.apply(&mut config.limits);
+            let config = settings(config)?;
+            let output = output_or_file(output);
+            let target = to.as_deref().map(parse_target).transpose()?;
+            let options = WatchOptions {
+                recursive: *recursive,
+                debounce: Duration::from_millis(*debounce),
+                formats: Vec::new(),
+                ignore: ignore.clone(),
+                ignore_files: batch.ignore_files,
+                output_dir: None,
+            };
+            handle_watch(input, output.as_deref(), target, options, &config, &batch)
+        }
+        Command::Inspect { input, recursive, summary } => {
+            handle_inspect(input, *recursive, *summary, !cli.no_ignore)
+        }
+        Command::Extract { input, output, fps, every, scenes, start, end, frame_format, frame_quality, optimize } => {
+            let selection = match (every, scenes) {
+                (Some(step), _) => FrameSelection::EveryNth(*step),
+                (_, Some(threshold)) => FrameSelection::Scenes(*threshold),
+                _ => FrameSelection::Fps(*fps),
+            };
+            let options = FrameExtraction {
+                selection,
+                format: *frame_format,
+                quality: *frame_quality,
+                range: (start.is_some() || end.is_some()).then(|| TimeRange {
+                    start: start.unwrap_or_default(),
+                    end: *end,
+                }),
+                optimize: *optimize,
+                max_frames: None,
             };
-            handle_convert(input, output.as_deref(), to, *recursive, &config)
+            handle_extract(input, output, &options)
         }
-        Command::Inspect { input, recursive } => {
-            handle_inspect(input, *recursive)
+        Command::Waveform { input, output, width, height, color, background } => {
+            let options = WaveformOptions {
+                width: *width,
+                height: *height,
+                color: *color,
+                background: background.unwrap_or(WaveformOptions::default().background),
+            };
+            let output = output.clone().unwrap_or_else(|| input.with_extension("png"));
+            handle_waveform(input, &output, &options)
         }
-        Command::Extract { input, output, fps } => {
-            handle_extract(input, output, *fps)
+        Command::Favicon { input, output, background, name, base_url, quality } => {
+            let options = FaviconOptions {
+                background: *background,
+                name: name.clone(),
+                base_url: base_url.clone(),
+            };
+            let config = ProcessingConfig { quality: *quality, ..ProcessingConfig::default() };
+            handle_favicon(input, output, &options, &config)
         }
+        Command::Meta { action } => handle_meta(action),
+        Command::Doctor => handle_doctor(),
+    };
+    // 128 + SIGINT, what shells report for a process stopped by Ctrl+C
+    if interrupt.is_cancelled() && result.is_ok() {
+        std::process::exit(130);
     }
+    result
 }
 
+/// First Ctrl+C: stop starting files, kill ffmpeg and let the batch print its
+/// partial report. Second: drop the writes in progress and exit at once.
+fn handle_interrupts(interrupt: CancelToken) {
+    let installed = ctrlc::set_handler(move || {
+        if interrupt.is_cancelled() {
+            remove_partial_outputs();
+            std::process::exit(130);
+        }
+        interrupt.cancel();
+        ffmpeg::cancel_all();
+        eprintln!("\nInterrupted: finishing the files in progress (Ctrl+C again to quit now)");
+    });
+    if let Err(e) = installed {
+        log::warn!("Could not install the Ctrl+C handler: {}", e);
+    }
+}
+
+/// Scheduling and I/O settings shared by batch commands
+struct Batch<'a> {
+    budget: Option<&'a MemoryBudget>,
+    order: FileOrder,
+    retry: RetryPolicy,
+    /// `--jobs`; None sizes the pool to the batch
+    jobs: Option<usize>,
+    /// `--manifest`, filled as files finish
+    manifest: Option<&'a Mutex<Manifest>>,
+    /// `--checksums`, filled as outputs are written
+    checksums: Option<&'a Mutex<ChecksumList>>,
+    /// `--checksum-input`
+    checksum_input: bool,
+    /// `--organize-by-date`
+    organize: Option<&'a DateTemplate>,
+    /// Skip what .gitignore and .ipignore files exclude; off with `--no-ignore`
+    ignore_files: bool,
+    /// `--on-collision`
+    collisions: CollisionPolicy,
+    /// `--report-format`, or the format `--report-file` implies
+    report_format: Option<ReportFormat>,
+    /// `--report-file`
+    report_file: Option<&'a Path>,
+    /// Cancelled by Ctrl+C: workers stop picking up files
+    interrupt: &'a CancelToken,
+}
+
+impl Batch<'_> {
+    /// Whether the report goes to stdout (`--report-format` without `--report-file`),
+    /// so messages must go to stderr to keep stdout parseable
+    fn report_on_stdout(&self) -> bool {
+        self.report_format.is_some() && self.report_file.is_none()
+    }
+
+    /// Thread pool for `cpu_files` processed in process and `ffmpeg_files` handed to ffmpeg.
+    /// `--jobs` wins over the settings file's `jobs`; its `low_priority` acts like `--nice`.
+    fn pool(&self, config: &ProcessingConfig, cpu_files: usize, ffmpeg_files: usize) -> Result<Parallelism> {
+        let threads = self.jobs.or(config.jobs).unwrap_or_else(|| {
+            let cores = std::thread::available_parallelism().map_or(1, usize::from);
+            auto_pool_size(cpu_files, ffmpeg_files, cores, ffmpeg::max_jobs())
+        });
+        log::debug!("{} worker threads for {} in-process and {} ffmpeg files", threads, cpu_files, ffmpeg_files);
+        if config.low_priority {
+            return Ok(Parallelism::low_priority_threads(threads)?);
+        }
+        Ok(Parallelism::threads(threads)?)
+    }
+
+    /// Record in the manifest that `source` is now `data` at `output`
+    fn add_to_manifest(&self, source: &Path, output: &Path, data: &[u8], original_size: u64) {
+        if let Some(manifest) = self.manifest {
+            manifest.lock().unwrap().add(source, output, data, original_size);
+        }
+    }
+
+    /// Hex SHA-256 of `data`, which is now at `output`; listed for `--checksums`
+    fn checksum(&self, output: &Path, data: &[u8]) -> String {
+        let sha256 = sha256_hex(data);
+        if let Some(checksums) = self.checksums {
+            checksums.lock().unwrap().add(output, sha256.clone());
+        }
+        sha256
+    }
+
+    /// Hex SHA-256 of an input's `data`, with `--checksum-input`
+    fn input_checksum(&self, data: &[u8]) -> Option<String> {
+        self.checksum_input.then(|| sha256_hex(data))
+    }
+
+    /// Directory `--organize-by-date` sorts into: the output directory, or the
+    /// directory of a single output file. None when not organizing.
+    fn organize_dir<'p>(&self, input: &Path, output: Option<&'p Path>) -> Result<Option<&'p Path>> {
+        if self.organize.is_none() {
+            return Ok(None);
+        }
+        let output = output.context("--organize-by-date needs an output directory")?;
+        if input.is_file() && output.extension().is_some() {
+            return Ok(Some(output.parent().unwrap_or(Path::new("."))));
+        }
+        Ok(Some(output))
+    }
+
+    /// `output` moved into the date directory under `dir` for `source` (holding `data`)
+    fn organized(&self, dir: Option<&Path>, output: PathBuf, source: &Path, data: &[u8]) -> PathBuf {
+        match (self.organize, dir) {
+            (Some(template), Some(dir)) => template.place(dir, &output, source, data),
+            _ => output,
+        }
+    }
+
+    /// `output` for `source`, moved into its date directory under `dir` when organizing (which reads the file)
+    fn placed(&self, dir: Option<&Path>, source: &Path, output: PathBuf) -> Result<PathBuf> {
+        if self.organize.is_none() || dir.is_none() {
+            return Ok(output);
+        }
+        let data = self.retry.run(|| read_file(source))?;
+        Ok(self.organized(dir, output, source, &data))
+    }
+
+    /// Output of every file, before any is processed: outputs shared by several
+    /// files stop the run or are renamed, per `--on-collision`
+    fn plan_outputs(
+        &self,
+        files: &[PathBuf],
+        output_of: impl Fn(&Path) -> Result<PathBuf>,
+    ) -> Result<HashMap<PathBuf, PathBuf>> {
+        let mut plan = files.iter().map(|path| Ok((path.clone(), output_of(path)?))).collect::<Result<Vec<_>>>()?;
+        let collisions = find_collisions(&plan);
+        if collisions.is_empty() {
+            return Ok(plan.into_iter().collect());
+        }
+        match self.collisions {
+            CollisionPolicy::Error => {
+                let listing: Vec<String> = collisions
+                    .iter()
+                    .map(|collision| {
+                        let sources: Vec<String> = collision.sources.iter().map(|s| s.display().to_string()).collect();
+                        format!("  {} ← {}", collision.output.display(), sources.join(", "))
+                    })
+                    .collect();
+                anyhow::bail!(
+                    "{} output(s) would be written by more than one file:\n{}\n\
+                     Rename the inputs or pass --on-collision rename",
+                    collisions.len(),
+                    listing.join("\n")
+                );
+            }
+            CollisionPolicy::Rename => {
+                let renamed = disambiguate(&mut plan);
+                say!(self, "Renamed {} output(s) that would have overwritten another.", renamed);
+            }
+        }
+        Ok(plan.into_iter().collect())
+    }
+
+    /// Runner for `operation` with the batch's Ctrl+C token, memory budget, retries and input checksums
+    fn runner<'r>(
+        &'r self,
+        operation: &'r dyn Operation,
+        hooks: &'r dyn BatchHooks,
+        pool: Parallelism,
+    ) -> BatchRunner<'r> {
+        let mut runner = BatchRunner::new(operation)
+            .hooks(hooks)
+            .parallelism(pool)
+            .cancel_token(self.interrupt.clone())
+            .retry(self.retry)
+            .checksum_input(self.checksum_input);
+        if let Some(budget) = self.budget {
+            runner = runner.memory_budget(budget);
+        }
+        runner
+    }
+
+    /// Progress bar message once the workers are done
+    fn finish_message(&self) -> &'static str {
+        if self.interrupt.is_cancelled() {
+            "Interrupted"
+        } else {
+            "Done!"
+        }
+    }
+
+    /// The summary, or with `--report-format` alone the report in that format instead;
+    /// with `--report-file` the summary and the report written there
+    fn print_report(&self, report: &Report) -> Result<()> {
+        match (self.report_format, self.report_file) {
+            (Some(format), Some(path)) => {
+                report.print_summary();
+                write_file(path, report.render(format).as_bytes())
+                    .with_context(|| format!("Failed to write the report to {}", path.display()))?;
+                println!("Report written to {}", path.display());
+            }
+            (Some(format), None) => print!("{}", report.render(format)),
+            (None, _) => report.print_summary(),
+        }
+        Ok(())
+    }
+
+    /// After an interrupted run's summary: how many of `total` files were never finished
+    fn print_interrupted(&self, report: &Report, total: usize) {
+        if self.interrupt.is_cancelled() {
+            let left = total.saturating_sub(report.results.len());
+            say!(
+                self,
+                "{}",
+                term::paint(format!("Interrupted: {} of {} file(s) not processed.", left, total), Color::Yellow)
+            );
+        }
+    }
+
+    /// Write the manifest and the checksum list, when asked for
+    fn save_records(&self) -> Result<()> {
+        if let Some(manifest) = self.manifest {
+            manifest.lock().unwrap().save().context("Failed to write the manifest")?;
+            say!(self, "Manifest written.");
+        }
+        if let Some(checksums) = self.checksums {
+            checksums.lock().unwrap().save().context("Failed to write the checksum list")?;
+            say!(self, "Checksums written.");
+        }
+        Ok(())
+    }
+}
+
+/// Manifest and checksum list entries for each file as it is settled
+impl BatchHooks for Batch<'_> {
+    fn finished(
+        &self,
+        file: &BatchFile,
+        outcome: &mut ProcessOutcome,
+        status: FileStatus,
+        sha256: &str,
+    ) -> std::result::Result<(), ProcessingError> {
+        let data = if status == FileStatus::Written { &outcome.data[..] } else { file.data };
+        let path = status.path(file);
+        self.add_to_manifest(file.input, path, data, file.data.len() as u64);
+        if let Some(checksums) = self.checksums {
+            checksums.lock().unwrap().add(path, sha256.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Files a compress run writes besides its outputs, for reviewing the results
+struct Review<'a> {
+    /// `--save-comparison` directory and layout
+    comparison: Option<(&'a Path, ComparisonStyle)>,
+    /// `--sidecar`
+    sidecar: bool,
+}
+
+/// How a compress run recognizes and records work done by earlier runs
+struct Resume {
+    /// Cache of processed files; None with `--no-cache`
+    cache_file: Option<std::path::PathBuf>,
+    /// Embed a marker in written outputs
+    mark: bool,
+    /// Ignore markers and the cache
+    force: bool,
+}
+
+/// What a compress run does with files whose contents are identical
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Duplicates {
+    /// Process every copy without looking for duplicates
+    Process,
+    /// `--find-duplicates`: list them, then process every copy
+    Report,
+    /// `--dedupe-exact`: list them, process one copy and link its output to the others'
+    Link,
+}
+
+/// What compress adds to each file of a batch: skipping what earlier runs did
+/// (markers, the cache), embedding markers, comparisons and sidecars
+struct CompressHooks<'a> {
+    batch: &'a Batch<'a>,
+    config: &'a ProcessingConfig,
+    /// The input argument, which comparison paths are relative to
+    input: &'a Path,
+    resume: &'a Resume,
+    review: &'a Review<'a>,
+    cache: Option<Mutex<ProcessedCache>>,
+    fingerprint: u64,
+}
+
+impl BatchHooks for CompressHooks<'_> {
+    fn skip(&self, file: &BatchFile) -> std::result::Result<Option<FileResult>, ProcessingError> {
+        if self.resume.force {
+            return Ok(None);
+        }
+        // A marker only says the input itself is done, so it skips in-place runs only
+        let marked = file.output == file.input
+            && Format::from_path(file.input)
+                .is_some_and(|format| marker::read_marker(file.data, format) == Some(self.fingerprint));
+        let cached = self.cache.as_ref().is_some_and(|cache| {
+            cache.lock().unwrap().is_processed(file.output, Digest::of(file.data), self.fingerprint)
+        });
+        if !marked && !cached {
+            return Ok(None);
+        }
+        let original_size = file.data.len() as u64;
+        let batch = self.batch;
+        let recorded = batch.manifest.is_some() || batch.checksums.is_some();
+        let output_sha256 = if file.output == file.input {
+            batch.add_to_manifest(file.input, file.output, file.data, original_size);
+            Some(batch.checksum(file.output, file.data))
+        } else if let (true, Ok(written)) = (recorded, std::fs::read(file.output)) {
+            batch.add_to_manifest(file.input, file.output, &written, original_size);
+            Some(batch.checksum(file.output, &written))
+        } else {
+            None
+        };
+        let result = FileResult::new(file.input.to_path_buf(), original_size, original_size);
+        Ok(Some(FileResult { unchanged: true, cached: true, output_sha256, ..result }))
+    }
+
+    fn processed(&self, _file: &BatchFile, outcome: &mut ProcessOutcome) -> std::result::Result<(), ProcessingError> {
+        if self.resume.mark && !outcome.is_unchanged() && marker::supports(outcome.format) {
+            outcome.data = marker::embed_marker(&outcome.data, outcome.format, self.fingerprint, self.config)?.into();
+        }
+        Ok(())
+    }
+
+    fn before_write(&self, file: &BatchFile, outcome: &mut ProcessOutcome) {
+        if let Some((dir, style)) = self.review.comparison.filter(|_| outcome.format.is_image()) {
+            let path = comparison_path(dir, file.input, self.input);
+            let saved = render_comparison(file.data, &outcome.data, style, &self.config.limits)
+                .and_then(|composite| self.batch.retry.run(|| write_file(&path, &composite)));
+            if let Err(e) = saved {
+                outcome.warnings.push(format!("comparison not saved: {}", e));
+            }
+        }
+    }
+
+    fn finished(
+        &self,
+        file: &BatchFile,
+        outcome: &mut ProcessOutcome,
+        status: FileStatus,
+        sha256: &str,
+    ) -> std::result::Result<(), ProcessingError> {
+        // What ends up at the output path, for the cache
+        if let Some(cache) = &self.cache {
+            let written = if status == FileStatus::Written { &outcome.data[..] } else { file.data };
+            cache.lock().unwrap().record(file.output, Digest::of(file.data), Digest::of(written), self.fingerprint);
+        }
+        self.batch.finished(file, outcome, status, sha256)?;
+        if self.review.sidecar && !self.config.dry_run {
+            let status = match status {
+                FileStatus::Written => SidecarStatus::Written,
+                FileStatus::Unchanged => SidecarStatus::Unchanged,
+                FileStatus::Skipped => SidecarStatus::Skipped,
+            };
+            let original_size = file.data.len() as u64;
+            let json = Sidecar::new(file.input, file.output, original_size, outcome, status, self.config)
+                .checksums(sha256, file.input_sha256)
+                .to_json();
+            // The output is already written; a missing sidecar does not undo that
+            if let Err(e) = self.batch.retry.run(|| write_file(&sidecar_path(file.output), json.as_bytes())) {
+                outcome.warnings.push(format!("sidecar not written: {}", e));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Each group of options is already bundled; the profile and duplicate handling are the odd ones out
+#[allow(clippy::too_many_arguments)]
 fn handle_compress(
     input: &Path,
     output: Option<&Path>,
     recursive: bool,
     config: &ProcessingConfig,
+    profile: Option<ChainProfile>,
+    duplicates: Duplicates,
+    resume: &Resume,
+    review: &Review,
+    batch: &Batch,
 ) -> Result<()> {
-    // Build pipeline
-    let mut pipeline = Pipeline::new();
-    pipeline.register(Box::new(PngProcessor));
-    pipeline.register(Box::new(Mp3Processor));
-    pipeline.register(Box::new(WebpProcessor));
-    pipeline.register(Box::new(Mp4Processor));
+    let mut pipeline = Pipeline::with_default_processors();
+    pipeline.set_cancel_token(batch.interrupt.clone());
+    let mut compress = Compress::new(pipeline);
+    if let Some(profile) = profile {
+        compress = compress.with_profile(profile);
+    }
 
     // Collect files
-    let files = collect_files(input, recursive)
+    let mut files = collect_files(input, recursive, &compress.sources(), batch.ignore_files)
         .context("Failed to collect input files")?;
+    sort_files(&mut files, batch.order);
 
     if files.is_empty() {
-        println!("No supported files found.");
+        say!(batch, "No supported files found.");
         return Ok(());
     }
 
-    println!("Found {} file(s) to process.", files.len());
+    say!(batch, "Found {} file(s) to process.", files.len());
+    let organize_dir = batch.organize_dir(input, output)?;
+    // Copies skipped by --dedupe-exact included: they get outputs too
+    let output_of = |path: &Path| batch.placed(organize_dir, path, compress.output(path, input, output));
+    let plan = batch.plan_outputs(&files, output_of)?;
 
-    if config.dry_run {
-        println!("[dry-run] Would process:");
+    let groups = match duplicates {
+        Duplicates::Process => Vec::new(),
+        Duplicates::Report | Duplicates::Link => {
+            find_duplicates(input, recursive, &files, batch.ignore_files).context("Failed to compare input files")?
+        }
+    };
+    print_duplicates(&groups, duplicates == Duplicates::Link, batch);
+    if duplicates == Duplicates::Link {
+        let copies: HashSet<&PathBuf> = groups.iter().flat_map(|group| &group.copies).collect();
+        files.retain(|path| !copies.contains(path));
+    }
+
+    // With comparisons to save, a dry run processes everything and only skips the writes
+    if config.dry_run && review.comparison.is_none() {
+        say!(batch, "[dry-run] Would process:");
         for f in &files {
-            let out = resolve_output(f, input, output);
-            println!("  {} → {}", f.display(), out.display());
+            say!(batch, "  {} → {}", f.display(), plan[f].display());
+        }
+        if duplicates == Duplicates::Link {
+            for group in &groups {
+                for copy in &group.copies {
+                    say!(batch, "  {} → {} (linked)", copy.display(), plan[copy].display());
+                }
+            }
         }
         return Ok(());
     }
 
-    // Progress bar
-    let pb = ProgressBar::new(files.len() as u64);
+    let (pb, progress) = progress_bar(files.len(), |name, saved_pct| format!("{} ({:.1}%)", name, saved_pct));
+    // Report per-file stages on the progress bar
+    let stage_pb = pb.clone();
+    compress.pipeline_mut().set_progress_sink(Arc::new(move |event: &ProgressEvent| {
+        if let ProgressEvent::Stage { path, stage, percent } = *event {
+            let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+            stage_pb.set_message(format!("{} [{} {:.0}%]", name, stage, percent));
+        }
+    }));
+
+    let cache = resume
+        .cache_file
+        .as_deref()
+        .filter(|_| !config.dry_run)
+        .map(ProcessedCache::load)
+        .transpose()
+        .context("Failed to read the processed-file cache")?
+        .map(Mutex::new);
+    let hooks = CompressHooks { batch, config, input, resume, review, cache, fingerprint: config_fingerprint(config) };
+    let ffmpeg_files = files
+        .iter()
+        .filter_map(|path| Format::from_path(path).and_then(|format| compress.pipeline().capabilities(format)))
+        .filter(|caps| caps.needs_ffmpeg)
+        .count();
+    let pool = batch.pool(config, files.len() - ffmpeg_files, ffmpeg_files)?;
+    let mut report = batch.runner(&compress, &hooks, pool).progress_sink(progress).run(&files, &plan, config);
+
+    pb.finish_with_message(batch.finish_message());
+    // An interrupted run links nothing: the copies keep their previous outputs
+    if duplicates == Duplicates::Link && !config.dry_run && !batch.interrupt.is_cancelled() {
+        link_duplicates(&groups, &plan, &mut report, config, batch)?;
+    }
+    batch.print_report(&report)?;
+    batch.print_interrupted(&report, files.len());
+
+    // The outputs are written either way; a lost cache only means redoing them next time
+    if let Some(cache) = hooks.cache {
+        if let Err(e) = cache.into_inner().unwrap().save() {
+            log::warn!("Failed to write the processed-file cache: {}", e);
+        }
+    }
+    if config.dry_run {
+        say!(batch, "[dry-run] Only comparisons were written.");
+        return Ok(());
+    }
+    batch.save_records()
+}
+
+/// Give each copy in `groups` its original's output, when that was written this run,
+/// and list every copy in `report` as a duplicate of its original
+fn link_duplicates(
+    groups: &[DuplicateGroup],
+    plan: &HashMap<PathBuf, PathBuf>,
+    report: &mut Report,
+    config: &ProcessingConfig,
+    batch: &Batch,
+) -> Result<()> {
+    // Originals whose output was written this run; other copies stay as they are, like their original
+    let written: HashMap<PathBuf, Option<String>> = report
+        .results
+        .iter()
+        .filter(|result| result.error.is_none() && !result.skipped && !result.unchanged)
+        .map(|result| (result.path.clone(), result.output_sha256.clone()))
+        .collect();
+    let mut linked = 0;
+    for group in groups {
+        let output_sha256 = written.get(&group.original);
+        for copy in &group.copies {
+            let original_size = std::fs::metadata(copy).map_or(0, |meta| meta.len());
+            let mut result = FileResult {
+                skipped: true,
+                duplicate_of: Some(group.original.clone()),
+                ..FileResult::new(copy.clone(), original_size, original_size)
+            };
+            if let Some(output_sha256) = output_sha256 {
+                let (source, dest) = (&plan[&group.original], &plan[copy]);
+                if config.backup {
+                    batch.retry.run(|| create_backup(dest))?;
+                }
+                batch.retry.run(|| link_or_copy(source, dest))?;
+                if batch.manifest.is_some() || batch.checksums.is_some() {
+                    let data = batch.retry.run(|| read_file(dest))?;
+                    batch.add_to_manifest(copy, dest, &data, original_size);
+                    batch.checksum(dest, &data);
+                }
+                result.compressed_size = std::fs::metadata(dest).map_or(original_size, |meta| meta.len());
+                result.output_sha256 = output_sha256.clone();
+                linked += 1;
+            }
+            report.add(result);
+        }
+    }
+    if linked > 0 {
+        say!(batch, "Linked {} duplicate(s) to their processed copy.", linked);
+    }
+    Ok(())
+}
+
+/// Batch progress bar over `files` files, and a sink moving it on as each finishes;
+/// `message` describes a finished file from its name and the percentage it saved
+fn progress_bar(
+    files: usize,
+    message: impl Fn(&str, f64) -> String + Send + Sync + 'static,
+) -> (ProgressBar, Arc<dyn ProgressSink>) {
+    let pb = ProgressBar::new(files as u64);
     pb.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
             .unwrap()
             .progress_chars("█▓░"),
     );
+    let sink_pb = pb.clone();
+    let sink = Arc::new(move |event: &ProgressEvent| match *event {
+        ProgressEvent::FileDone { path, original_size, output_size, .. } => {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let saved_pct = savings_pct(original_size, output_size);
+            sink_pb.set_message(message(&name, saved_pct));
+            sink_pb.inc(1);
+        }
+        ProgressEvent::FileFailed { .. } => sink_pb.inc(1),
+        _ => {}
+    });
+    (pb, sink)
+}
 
-    let report = Mutex::new(Report::new());
+/// Whether `e` is a file stopped by Ctrl+C rather than a failure
+fn is_cancellation(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<ProcessingError>(), Some(ProcessingError::Cancelled))
+}
 
-    // Process files in parallel
-    files.par_iter().for_each(|input_path| {
-        let output_path = resolve_output(input_path, input, output);
+/// List identical files found before processing: each group's original, its
+/// copies and symlinks pointing into the group
+fn print_duplicates(groups: &[DuplicateGroup], linking: bool, batch: &Batch) {
+    if groups.is_empty() {
+        return;
+    }
+    let copies: usize = groups.iter().map(|group| group.copies.len()).sum();
+    let links: usize = groups.iter().map(|group| group.links.len()).sum();
+    let heading = format!("{} exact duplicate(s), {} symlink(s) to inputs:", copies, links);
+    say!(batch, "{}", term::paint(heading, Color::Bold));
+    for group in groups {
+        say!(batch, "  {}", group.original.display());
+        for copy in &group.copies {
+            say!(batch, "    {} {}", term::paint("=", Color::Yellow), copy.display());
+        }
+        for link in &group.links {
+            say!(batch, "    {} {} (symlink)", term::paint("←", Color::Dim), link.display());
+        }
+    }
+    if linking {
+        say!(batch, "Copies are not processed; they get a link to their original's output.");
+    } else if copies > 0 {
+        say!(batch, "Run with --dedupe-exact to process one copy of each and link the rest.");
+    }
+}
 
-        let result = (|| -> std::result::Result<FileResult, anyhow::Error> {
-            let data = read_file(input_path)?;
-            let original_size = data.len() as u64;
+/// `dir/{input_path relative to input}.compare.png`
+fn comparison_path(dir: &Path, input_path: &Path, input: &Path) -> PathBuf {
+    let relative = if input.is_file() {
+        Path::new(input_path.file_name().unwrap_or_default())
+    } else {
+        input_path.strip_prefix(input).unwrap_or(input_path)
+    };
+    let name = relative.file_name().unwrap_or_default().to_string_lossy();
+    dir.join(relative).with_file_name(format!("{}.compare.png", name))
+}
 
-            let compressed = pipeline.process_file(input_path, &data, config)?;
-            let compressed_size = compressed.len() as u64;
+/// `.image_preparer_cache` in the input directory (next to a single input file)
+fn default_cache_file(input: &Path) -> std::path::PathBuf {
+    let dir = if input.is_dir() { input } else { input.parent().unwrap_or(Path::new(".")) };
+    dir.join(CACHE_FILE_NAME)
+}
 
-            // Skip if compressed is larger
-            if compressed_size >= original_size {
-                log::debug!(
-                    "Skipping {} — compressed ({}) >= original ({})",
-                    input_path.display(),
-                    compressed_size,
-                    original_size
-                );
-                return Ok(FileResult {
-                    path: input_path.clone(),
-                    original_size,
-                    compressed_size: original_size,
-                    skipped: true,
-                    error: None,
-                });
-            }
+fn handle_explain(
+    input: &Path,
+    output: Option<&Path>,
+    recursive: bool,
+    config: &ProcessingConfig,
+    profile: Option<ChainProfile>,
+    resume: &Resume,
+    batch: &Batch,
+) -> Result<()> {
+    let mut pipeline = Pipeline::with_default_processors();
+    if let Some(profile) = profile {
+        pipeline.set_chain(profile.source(), profile.chain());
+    }
 
-            if config.backup {
-                create_backup(&output_path)?;
-            }
-            write_file(&output_path, &compressed)?;
+    // Every known format, so files compress would pass over are explained too
+    let mut files = collect_files(input, recursive, &Format::ALL, batch.ignore_files)
+        .context("Failed to collect input files")?;
+    sort_files(&mut files, FileOrder::Name);
+    if files.is_empty() {
+        println!("No supported files found.");
+        return Ok(());
+    }
 
-            Ok(FileResult {
-                path: input_path.clone(),
-                original_size,
-                compressed_size,
-                skipped: false,
-                error: None,
-            })
-        })();
+    let cache = match &resume.cache_file {
+        Some(path) => Some(ProcessedCache::load(path).context("Failed to read the processed-file cache")?),
+        None => None,
+    };
+    let fingerprint = config_fingerprint(config);
+    let mut skipped = 0;
+    for path in &files {
+        let data = read_file(path)?;
+        let Some(format) = Format::detect(path, &data) else {
+            continue;
+        };
+        let mut plan = explain(&pipeline, path, &data, format, config);
 
-        match result {
-            Ok(file_result) => {
-                if !file_result.skipped {
-                    pb.set_message(format!(
-                        "{} ({:.1}%)",
-                        input_path.file_name().unwrap().to_string_lossy(),
-                        file_result.savings_pct()
-                    ));
-                }
-                report.lock().unwrap().add(file_result);
-            }
-            Err(e) => {
-                log::error!("Error processing {}: {}", input_path.display(), e);
-                report.lock().unwrap().add(FileResult {
-                    path: input_path.clone(),
-                    original_size: 0,
-                    compressed_size: 0,
-                    skipped: false,
-                    error: Some(e.to_string()),
-                });
+        // The same checks handle_compress makes before processing
+        let mut output_path = resolve_output(path, input, output);
+        if let Some(profile) = profile.filter(|profile| profile.source() == format) {
+            output_path = output_path.with_extension(profile.target().extension());
+        }
+        if plan.skip.is_none() && !resume.force {
+            if output_path == *path && marker::read_marker(&data, format) == Some(fingerprint) {
+                plan.skip = Some("already processed with these settings (marker)".to_string());
+            } else if cache
+                .as_ref()
+                .is_some_and(|cache| cache.is_processed(&output_path, Digest::of(&data), fingerprint))
+            {
+                plan.skip = Some("already processed with these settings (cache; --no-cache processes it)".to_string());
             }
         }
+        if plan.skip.is_some() {
+            skipped += 1;
+        }
+        print_plan(&plan);
+    }
 
-        pb.inc(1);
-    });
-
-    pb.finish_with_message("Done!");
-    report.lock().unwrap().print_summary();
-
+    println!("{}", term::rule());
+    let processed = files.len() - skipped;
+    println!("{} file(s): {} would be processed, {} skipped. Nothing was written.", files.len(), processed, skipped);
     Ok(())
 }
 
@@ -194,19 +1058,82 @@ fn handle_convert(
     target_format_str: &str,
     recursive: bool,
     config: &ProcessingConfig,
+    batch: &Batch,
 ) -> Result<()> {
-    let target_format = ConvertFormat::from_str(target_format_str)
-        .ok_or_else(|| anyhow::anyhow!("Invalid target format: {}. Use: png, jpg, jpeg, webp", target_format_str))?;
+    let convert = Convert(parse_target(target_format_str)?);
+    let target_format = convert.0;
+    let mut files = collect_files(input, recursive, &convert.sources(), batch.ignore_files)
+        .context("Failed to collect input files")?;
+    sort_files(&mut files, batch.order);
+
+    if files.is_empty() {
+        say!(batch, "No supported files found.");
+        return Ok(());
+    }
+
+    say!(batch, "Converting {} file(s) to {}...", files.len(), target_format.as_str());
+
+    let (pb, progress) = progress_bar(files.len(), move |name, _| format!("{} → {}", name, target_format.as_str()));
+    let organize_dir = batch.organize_dir(input, output)?;
+    let output_of = |input_path: &Path| {
+        // Organized outputs keep the input's name, whatever kind of path `output` is
+        if organize_dir.is_some() {
+            return batch.placed(organize_dir, input_path, input_path.with_extension(target_format.extension()));
+        }
+        Ok(convert.output(input_path, input, output))
+    };
+    let plan = batch.plan_outputs(&files, output_of)?;
+    // Images are converted in process; videos and audio go through ffmpeg
+    let image_files = files.iter().filter(|path| Format::from_path(path).is_some_and(|f| f.is_image())).count();
+    let pool = batch.pool(config, image_files, files.len() - image_files)?;
+    let report = batch.runner(&convert, batch, pool).progress_sink(progress).run(&files, &plan, config);
+
+    pb.finish_with_message(batch.finish_message());
+    batch.print_report(&report)?;
+    batch.print_interrupted(&report, files.len());
 
-    let files = collect_files(input, recursive)
+    batch.save_records()
+}
+
+/// `--to` of convert and watch, checked against the formats convert can produce
+fn parse_target(target: &str) -> Result<Format> {
+    target
+        .parse::<Format>()
+        .ok()
+        .filter(|format| {
+            format.is_image()
+                || Format::ANIMATIONS.contains(format)
+                || Format::LOSSLESS_AUDIO.contains(format)
+                || matches!(format, Format::Webm | Format::Opus)
+        })
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid target format: {}. Use: png, jpg, jpeg, webp, avif, gif, webm, flac, wav, opus",
+                target
+            )
+        })
+}
+
+/// `convert --preset responsive`: every image at several widths and formats,
+/// written where the converted file would go, plus a srcset snippet
+fn handle_responsive(
+    input: &Path,
+    output: Option<&Path>,
+    recursive: bool,
+    args: &ResponsiveArgs,
+    config: &ProcessingConfig,
+    batch: &Batch,
+) -> Result<()> {
+    let mut files = collect_files(input, recursive, &Format::IMAGES, batch.ignore_files)
         .context("Failed to collect input files")?;
+    sort_files(&mut files, batch.order);
 
     if files.is_empty() {
-        println!("No supported files found.");
+        say!(batch, "No supported files found.");
         return Ok(());
     }
 
-    println!("Converting {} file(s) to {}...", files.len(), target_format.as_str());
+    say!(batch, "Generating responsive sets for {} image(s)...", files.len());
 
     let pb = ProgressBar::new(files.len() as u64);
     pb.set_style(
@@ -216,74 +1143,260 @@ fn handle_convert(
             .progress_chars("█▓░"),
     );
 
+    // Variants and snippets are named after the stem, so inputs differing only in extension collide
+    let plan = batch.plan_outputs(&files, |path| Ok(resolve_output(path, input, output).with_extension("srcset")))?;
     let report = Mutex::new(Report::new());
+    let pool = batch.pool(config, files.len(), 0)?;
 
-    files.par_iter().for_each(|input_path| {
+    schedule(&files, &pool, batch.budget, batch.interrupt, |input_path, _| {
+        let started = Instant::now();
         let result = (|| -> std::result::Result<FileResult, anyhow::Error> {
-            let data = read_file(input_path)?;
+            let data = batch.retry.run(|| read_file(input_path))?;
             let original_size = data.len() as u64;
 
-            let converted = convert_image(&data, target_format, config)?;
-            let converted_size = converted.len() as u64;
+            let source = Format::detect(input_path, &data).unwrap_or(Format::Png);
+            let options = args.to_options(source);
+            let variants = render_variants(&data, &options, config)?;
 
-            // Determine output path with new extension
-            let output_path = if let Some(output_dir) = output {
-                if output_dir.is_dir() {
-                    let file_name = input_path.file_stem().unwrap();
-                    output_dir.join(format!("{}.{}", file_name.to_string_lossy(), target_format.extension()))
-                } else {
-                    output_dir.to_path_buf()
-                }
-            } else {
-                input_path.with_extension(target_format.extension())
+            let target = &plan[input_path];
+            let dir = target.parent().unwrap_or(Path::new(""));
+            let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+            for variant in &variants {
+                let path = dir.join(variant.file_name(&stem));
+                batch.retry.run(|| write_file(&path, &variant.data))?;
+                batch.checksum(&path, &variant.data);
+            }
+            let (snippet, extension) = match args.snippet.unwrap_or(Snippet::Html) {
+                Snippet::Html => (picture_html(&stem, &variants, &options.sizes), "html"),
+                Snippet::Json => (srcset_json(&stem, &variants, &options.sizes), "json"),
             };
+            let snippet_path = dir.join(format!("{}.srcset.{}", stem, extension));
+            batch.retry.run(|| write_file(&snippet_path, snippet.as_bytes()))?;
+            batch.checksum(&snippet_path, snippet.as_bytes());
 
-            if config.backup && output_path.exists() {
-                create_backup(&output_path)?;
+            // The manifest and the result point at the <img> fallback: the widest variant in
+            // the last format, which comes last (variants are grouped by format, narrowest first)
+            let output_sha256 = variants.last().map(|fallback| sha256_hex(&fallback.data));
+            if let Some(fallback) = variants.last() {
+                batch.add_to_manifest(input_path, &dir.join(fallback.file_name(&stem)), &fallback.data, original_size);
             }
-            write_file(&output_path, &converted)?;
+            // What a full-width visitor downloads: the widest variant in the preferred format
+            let downloaded = variants
+                .iter()
+                .filter(|v| v.format == variants[0].format)
+                .max_by_key(|v| v.width)
+                .map_or(0, |v| v.data.len() as u64);
 
             Ok(FileResult {
-                path: input_path.clone(),
-                original_size,
-                compressed_size: converted_size,
-                skipped: false,
-                error: None,
+                output_sha256,
+                input_sha256: batch.input_checksum(&data),
+                ..FileResult::new(input_path.clone(), original_size, downloaded)
             })
         })();
 
         match result {
-            Ok(file_result) => {
+            Ok(mut file_result) => {
+                file_result.duration = Some(started.elapsed());
                 pb.set_message(format!(
-                    "{} → {}",
+                    "{} ({:.1}%)",
                     input_path.file_name().unwrap().to_string_lossy(),
-                    target_format.as_str()
+                    file_result.savings_pct()
                 ));
                 report.lock().unwrap().add(file_result);
             }
+            // Stopped by Ctrl+C: counted as not processed, not as failed
+            Err(e) if is_cancellation(&e) => log::debug!("Cancelled {}", input_path.display()),
             Err(e) => {
-                log::error!("Error converting {}: {}", input_path.display(), e);
-                report.lock().unwrap().add(FileResult {
-                    path: input_path.clone(),
-                    original_size: 0,
-                    compressed_size: 0,
-                    skipped: false,
-                    error: Some(e.to_string()),
-                });
+                log::error!("Error generating variants of {}: {}", input_path.display(), e);
+                let result = FileResult::failed(input_path.clone(), e.to_string(), Some(started.elapsed()));
+                report.lock().unwrap().add(result);
             }
         }
 
         pb.inc(1);
     });
 
-    pb.finish_with_message("Done!");
-    report.lock().unwrap().print_summary();
+    pb.finish_with_message(batch.finish_message());
+    batch.print_report(&report.lock().unwrap())?;
+    batch.print_interrupted(&report.lock().unwrap(), files.len());
 
-    Ok(())
+    batch.save_records()
+}
+
+/// `resize`: every image scaled and re-encoded in its own format, through the
+/// format's optimizer with `then_compress`
+fn handle_resize(
+    input: &Path,
+    output: Option<&Path>,
+    recursive: bool,
+    options: &ResizeOptions,
+    then_compress: bool,
+    config: &ProcessingConfig,
+    batch: &Batch,
+) -> Result<()> {
+    let mut files = collect_files(input, recursive, &Format::IMAGES, batch.ignore_files)
+        .context("Failed to collect input files")?;
+    sort_files(&mut files, batch.order);
+
+    if files.is_empty() {
+        say!(batch, "No supported files found.");
+        return Ok(());
+    }
+
+    say!(batch, "Resizing {} image(s)...", files.len());
+
+    let resize = Resize { options: *options, compress: then_compress };
+    let (pb, progress) = progress_bar(files.len(), |name, saved_pct| format!("{} ({:.1}%)", name, saved_pct));
+    let organize_dir = batch.organize_dir(input, output)?;
+    let plan =
+        batch.plan_outputs(&files, |path| batch.placed(organize_dir, path, resize.output(path, input, output)))?;
+    let pool = batch.pool(config, files.len(), 0)?;
+    let report = batch.runner(&resize, batch, pool).progress_sink(progress).run(&files, &plan, config);
+
+    pb.finish_with_message(batch.finish_message());
+    batch.print_report(&report)?;
+    batch.print_interrupted(&report, files.len());
+
+    batch.save_records()
 }
 
-fn handle_inspect(input: &Path, recursive: bool) -> Result<()> {
-    let files = collect_files(input, recursive)
+/// `watch`: compress `input`'s files, or convert them to `target`, as they settle
+/// after changing, until Ctrl+C. `options.formats` and `options.output_dir` are
+/// filled in here.
+fn handle_watch(
+    input: &Path,
+    output: Option<&Path>,
+    target: Option<Format>,
+    mut options: WatchOptions,
+    config: &ProcessingConfig,
+    batch: &Batch,
+) -> Result<()> {
+    let mut pipeline = Pipeline::with_default_processors();
+    pipeline.set_cancel_token(batch.interrupt.clone());
+    // Created now so its canonical path is known: outputs inside the watched directory are never inputs
+    let output = output
+        .map(|dir| {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+            dir.canonicalize().with_context(|| format!("Failed to resolve {}", dir.display()))
+        })
+        .transpose()?;
+    options.formats = match target {
+        Some(target) => Convert(target).sources(),
+        None => pipeline.supported_formats(),
+    };
+    options.output_dir = output.clone();
+    let mut watcher = Watcher::new(input, options).context("Failed to start watching")?;
+    let root = watcher.root().to_path_buf();
+
+    let action = target.map_or("compressed".to_string(), |target| format!("converted to {}", target.as_str()));
+    println!("Watching {} (Ctrl+C to stop): new and changed files are {}.", root.display(), action);
+
+    let mut report = Report::new();
+    while let Some(paths) = watcher.next_batch(batch.interrupt) {
+        for input_path in paths {
+            // Gone again: a rename's old name, or a file deleted right after it was written
+            let Ok(data) = read_file(&input_path) else {
+                continue;
+            };
+            // Our own output, or a file touched without changing since it was handled
+            if watcher.is_known(&input_path, &data) {
+                continue;
+            }
+            let name = input_path.strip_prefix(&root).unwrap_or(&input_path).display().to_string();
+            let original_size = data.len() as u64;
+            let output_path = resolve_output(&input_path, &root, output.as_deref());
+            let output_path = match target {
+                Some(target) => output_path.with_extension(target.extension()),
+                None => output_path,
+            };
+
+            let started = Instant::now();
+            let result = (|| -> Result<Option<Vec<u8>>> {
+                let written = match target {
+                    Some(target) => Some(Convert(target).process(&input_path, &data, config)?.data.into_owned()),
+                    None => {
+                        let outcome = pipeline.process_file_with_stats(&input_path, &data, config)?;
+                        (!outcome.is_unchanged() && (outcome.data.len() as u64) < original_size)
+                            .then(|| outcome.data.into_owned())
+                    }
+                };
+                if let Some(written) = &written {
+                    if config.backup {
+                        batch.retry.run(|| create_backup(&output_path))?;
+                    }
+                    batch.retry.run(|| write_file(&output_path, written))?;
+                }
+                Ok(written)
+            })();
+            watcher.remember(&input_path, &data);
+
+            let mut file_result = FileResult {
+                path: input_path.clone(),
+                original_size,
+                compressed_size: original_size,
+                skipped: false,
+                unchanged: false,
+                cached: false,
+                error: None,
+                metadata_removed: None,
+                bitrate: None,
+                warnings: Vec::new(),
+                output_sha256: None,
+                input_sha256: batch.input_checksum(&data),
+                duration: Some(started.elapsed()),
+                duplicate_of: None,
+            };
+            match result {
+                Ok(Some(written)) => {
+                    watcher.remember(&output_path, &written);
+                    batch.add_to_manifest(&input_path, &output_path, &written, original_size);
+                    file_result.output_sha256 = Some(batch.checksum(&output_path, &written));
+                    file_result.compressed_size = written.len() as u64;
+                    let to = output_path.strip_prefix(&root).unwrap_or(&output_path).display().to_string();
+                    let renamed = if to == name { String::new() } else { format!(" → {}", to) };
+                    println!(
+                        "{}{}: {} → {} ({})",
+                        name,
+                        renamed,
+                        format_size(original_size),
+                        format_size(file_result.compressed_size),
+                        term::savings(file_result.savings_pct())
+                    );
+                }
+                Ok(None) => {
+                    println!("{}: unchanged", name);
+                    file_result.unchanged = true;
+                }
+                Err(e) if is_cancellation(&e) => break,
+                Err(e) => {
+                    log::error!("Error processing {}: {}", input_path.display(), e);
+                    file_result.error = Some(e.to_string());
+                }
+            }
+            report.add(file_result);
+        }
+    }
+
+    if !report.results.is_empty() {
+        batch.print_report(&report)?;
+    }
+    batch.save_records()
+}
+
+fn handle_inspect(input: &Path, recursive: bool, summary: bool, ignore_files: bool) -> Result<()> {
+    let inspectable = [
+        Format::Png,
+        Format::Jpeg,
+        Format::Webp,
+        Format::Mp3,
+        Format::Mp4,
+        Format::Opus,
+        Format::Ogg,
+        Format::Heic,
+        Format::Tiff,
+        Format::Ico,
+    ];
+    let files = collect_files(input, recursive, &inspectable, ignore_files)
         .context("Failed to collect input files")?;
 
     if files.is_empty() {
@@ -291,40 +1404,56 @@ fn handle_inspect(input: &Path, recursive: bool) -> Result<()> {
         return Ok(());
     }
 
+    if summary {
+        let mut rows = Vec::with_capacity(files.len());
+        for file_path in files {
+            let data = read_file(&file_path)?;
+            let Some(format) = Format::detect(&file_path, &data) else {
+                continue;
+            };
+            rows.push((file_path, summarize(&data, format)));
+        }
+        print_table(&rows);
+        println!("Run `image_preparer inspect <file>` for the full report on one file.");
+        return Ok(());
+    }
+
     for file_path in &files {
-        println!("\nFile: {}", file_path.display());
+        println!("\n{} {}", term::paint("File:", Color::Bold), file_path.display());
         let data = read_file(file_path)?;
 
-        match ImageFormat::from_path(file_path) {
-            Some(ImageFormat::Mp3) => {
-                inspect_mp3(&data)?;
-            }
-            Some(ImageFormat::Png) => {
-                inspect_png(&data)?;
-            }
-            Some(ImageFormat::Webp) => {
-                inspect_webp(&data)?;
-            }
-            Some(ImageFormat::Mp4) => {
-                inspect_mp4(&data)?;
-            }
-            None => {
-                println!("  Unsupported file format");
-            }
+        match Format::detect(file_path, &data).map(|format| inspect(&data, format)) {
+            Some(Ok(report)) => print_report(&report),
+            Some(Err(ProcessingError::UnsupportedFormat(_))) | None => println!("  Unsupported file format"),
+            Some(Err(e)) => return Err(e.into()),
         }
     }
 
     Ok(())
 }
 
-fn handle_extract(input: &Path, output: &Path, fps: f32) -> Result<()> {
-    if !matches!(ImageFormat::from_path(input), Some(ImageFormat::Mp4)) {
+fn handle_extract(input: &Path, output: &Path, options: &FrameExtraction) -> Result<()> {
+    if !matches!(Format::from_path(input), Some(Format::Mp4)) {
         anyhow::bail!("Frame extraction only supports MP4 files");
     }
 
-    println!("Extracting frames at {} fps...", fps);
+    match options.selection {
+        FrameSelection::Fps(fps) => println!("Extracting {} frames at {} fps...", options.format.as_str(), fps),
+        FrameSelection::EveryNth(step) => {
+            println!("Extracting 1 of every {} frames as {}...", step, options.format.as_str())
+        }
+        FrameSelection::Scenes(threshold) => println!(
+            "Extracting one {} frame per scene (threshold {})...",
+            options.format.as_str(),
+            threshold
+        ),
+    }
 
-    match extract_frames_to_png(input, output, fps) {
+    match extract_frames(input, output, options) {
+        Ok(count) if options.optimize => {
+            println!("✓ Extracted and optimized {} frames", count);
+            Ok(())
+        }
         Ok(count) => {
             println!("✓ Extracted {} frames", count);
             Ok(())
@@ -334,3 +1463,108 @@ fn handle_extract(input: &Path, output: &Path, fps: f32) -> Result<()> {
         }
     }
 }
+
+fn handle_favicon(input: &Path, output: &Path, options: &FaviconOptions, config: &ProcessingConfig) -> Result<()> {
+    if !Format::from_path(input).is_some_and(|format| format.is_image()) {
+        anyhow::bail!("Favicons can be made from PNG, JPEG, WebP and AVIF images");
+    }
+
+    let data = read_file(input)?;
+    let files = render_favicons(&data, options, config)
+        .with_context(|| format!("Failed to render favicons from {}", input.display()))?;
+    for file in &files {
+        write_file(&output.join(file.name), &file.data)?;
+        println!("✓ {} ({} bytes)", file.name, file.data.len());
+    }
+    let tags = link_tags(options);
+    write_file(&output.join("favicon.html"), tags.as_bytes())?;
+    println!("\nPaste into <head> (also saved as favicon.html):\n\n{}", tags);
+    Ok(())
+}
+
+fn handle_meta(action: &MetaAction) -> Result<()> {
+    let (input, output, edit) = match action {
+        MetaAction::Get { input } => {
+            let (data, format) = read_tagged(input)?;
+            let tags = read_tags(&data, format).with_context(|| format!("Failed to read tags of {}", input.display()))?;
+            if tags.is_empty() {
+                println!("No tags.");
+            }
+            for (key, value) in tags {
+                println!("{}: {}", key, value);
+            }
+            return Ok(());
+        }
+        MetaAction::Set { input, output, title, artist, album, year, genre, comment, track, png_text } => {
+            let fields = [title, artist, album, year, genre, comment, track];
+            let mut set: Vec<(String, String)> = Field::ALL
+                .into_iter()
+                .zip(fields)
+                .filter_map(|(field, value)| Some((field.as_str().to_string(), value.clone()?)))
+                .collect();
+            set.extend(png_text.iter().cloned());
+            (input, output, TagEdit { set, delete: Vec::new() })
+        }
+        MetaAction::Delete { input, keys, output } => {
+            (input, output, TagEdit { set: Vec::new(), delete: keys.clone() })
+        }
+    };
+    if edit.is_empty() {
+        let options = "--title, --artist, --album, --year, --genre, --comment, --track or --png-text";
+        anyhow::bail!("Nothing to set: give {}", options);
+    }
+
+    let (data, format) = read_tagged(input)?;
+    if format != Format::Png && matches!(action, MetaAction::Set { png_text, .. } if !png_text.is_empty()) {
+        anyhow::bail!("--png-text only applies to PNG files");
+    }
+    let tagged =
+        edit_tags(&data, format, &edit).with_context(|| format!("Failed to edit tags of {}", input.display()))?;
+    let output = output.as_deref().unwrap_or(input);
+    if !edit.set.is_empty() {
+        write_file(output, &tagged)?;
+        println!("✓ {} tag(s) set in {}", edit.set.len(), output.display());
+        return Ok(());
+    }
+    // Count what the rewrite actually removed: keys can match nothing, or several values
+    let count = |data: &[u8]| read_tags(data, format).map(|tags| tags.len());
+    let removed = count(&data)?.saturating_sub(count(&tagged)?);
+    if removed == 0 {
+        anyhow::bail!("No tags matching {} in {}", edit.delete.join(", "), input.display());
+    }
+    write_file(output, &tagged)?;
+    println!("✓ {} tag(s) deleted from {}", removed, output.display());
+    Ok(())
+}
+
+/// Contents and format of a file whose tags `meta` can edit
+fn read_tagged(input: &Path) -> Result<(Vec<u8>, Format)> {
+    let data = read_file(input)?;
+    match Format::detect(input, &data) {
+        Some(format) if tags::FORMATS.contains(&format) => Ok((data, format)),
+        _ => anyhow::bail!("Tags can be edited in MP3, FLAC, MP4 and PNG files"),
+    }
+}
+
+fn handle_doctor() -> Result<()> {
+    let checks = run_checks();
+    print_checks(&checks);
+    let failures = checks.iter().filter(|check| check.status == CheckStatus::Fail).count();
+    if failures > 0 {
+        anyhow::bail!("{} check(s) failed", failures);
+    }
+    Ok(())
+}
+
+fn handle_waveform(input: &Path, output: &Path, options: &WaveformOptions) -> Result<()> {
+    let format = Format::from_path(input)
+        .filter(|format| matches!(format, Format::Mp3 | Format::Wav | Format::Flac | Format::Opus | Format::Ogg))
+        .ok_or_else(|| anyhow::anyhow!("Waveforms can be drawn for MP3, WAV, FLAC, Opus and Vorbis files"))?;
+
+    let data = read_file(input)?;
+    let png = render_waveform(&data, format, options, &ProcessingConfig::default())
+        .with_context(|| format!("Failed to render waveform of {}", input.display()))?;
+    write_file(output, &png)?;
+    println!("✓ Wrote {}x{} waveform to {} ({} bytes)", options.width, options.height, output.display(), png.len());
+    Ok(())
+}
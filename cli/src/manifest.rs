@@ -0,0 +1,145 @@
+//! Build manifest for web bundlers: which file each source became, with a
+//! content hash for cache-busting, byte sizes and image dimensions, so Vite,
+//! Webpack or Hugo pipelines can wire optimized assets without re-hashing them.
+//!
+//! The manifest is a JSON object keyed by source path. Paths under the
+//! manifest's directory are written relative to it, with `/` separators:
+//!
+//! ```json
+//! {
+//!   "src/logo.png": {
+//!     "output": "dist/logo.png",
+//!     "hash": "a3f1c09e5b7d2e48",
+//!     "sha256": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+//!     "size": 5120,
+//!     "original_size": 18432,
+//!     "width": 256,
+//!     "height": 256
+//!   }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::cache::content_hash;
+use crate::checksum::sha256_hex;
+use crate::error::ProcessingError;
+use crate::format::Format;
+
+/// What one source file became
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    /// File to reference: the written output, or the source itself when nothing was written
+    pub output: String,
+    /// Hex content hash of `output`, stable across runs for the same bytes
+    pub hash: String,
+    /// Hex SHA-256 of `output`, for verifying the deployed file
+    pub sha256: String,
+    pub size: u64,
+    pub original_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// Manifest collected over a batch run and written once at the end
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    /// Absolute directory of the manifest; paths below it are written relative to it
+    base: Option<PathBuf>,
+    entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let base = std::path::absolute(&path).ok().and_then(|path| path.parent().map(Path::to_path_buf));
+        Self { path, base, entries: BTreeMap::new() }
+    }
+
+    /// Record that `source` (originally `original_size` bytes) is now `data` at `output`
+    pub fn add(&mut self, source: &Path, output: &Path, data: &[u8], original_size: u64) {
+        let (width, height) = dimensions(data, output).unzip();
+        let entry = ManifestEntry {
+            output: self.display(output),
+            hash: format!("{:016x}", content_hash(data)),
+            sha256: sha256_hex(data),
+            size: data.len() as u64,
+            original_size,
+            width,
+            height,
+        };
+        self.entries.insert(self.display(source), entry);
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, ManifestEntry> {
+        &self.entries
+    }
+
+    /// Write the manifest as pretty-printed JSON, replacing any previous one
+    pub fn save(&self) -> Result<(), ProcessingError> {
+        let json = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| ProcessingError::Encode(format!("Failed to serialize the manifest: {}", e)))?;
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|source| ProcessingError::WriteFile { path: dir.to_path_buf(), source })?;
+        }
+        std::fs::write(&self.path, json + "\n")
+            .map_err(|source| ProcessingError::WriteFile { path: self.path.clone(), source })
+    }
+
+    /// `path` relative to the manifest's directory when below it, with `/` separators
+    fn display(&self, path: &Path) -> String {
+        let relative = self.base.as_deref().and_then(|base| {
+            let absolute = std::path::absolute(path).ok()?;
+            absolute.strip_prefix(base).ok().map(Path::to_path_buf)
+        });
+        let path = relative.as_deref().unwrap_or(path);
+        path.to_string_lossy().replace('\\', "/")
+    }
+}
+
+/// Width and height from the image header; None for audio, video and unreadable data
+fn dimensions(data: &[u8], path: &Path) -> Option<(u32, u32)> {
+    if !Format::from_path(path).is_some_and(|format| format.is_image()) {
+        return None;
+    }
+    image::ImageReader::new(Cursor::new(data)).with_guessed_format().ok()?.into_dimensions().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_entries() {
+        let dir = std::env::temp_dir().join(format!("ip_manifest_{}", std::process::id()));
+        let mut png = Vec::new();
+        image::RgbImage::new(3, 2).write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        let mut manifest = Manifest::new(dir.join("manifest.json"));
+        manifest.add(&dir.join("src/a.png"), &dir.join("dist/a.png"), &png, 1000);
+        manifest.add(&dir.join("src/b.mp3"), &dir.join("src/b.mp3"), b"ID3", 3);
+
+        let a = &manifest.entries()["src/a.png"];
+        assert_eq!(a.output, "dist/a.png");
+        assert_eq!((a.width, a.height), (Some(3), Some(2)));
+        assert_eq!((a.size, a.original_size), (png.len() as u64, 1000));
+        assert_eq!(a.hash, format!("{:016x}", content_hash(&png)));
+        assert_eq!(a.sha256, sha256_hex(&png));
+        assert_eq!(manifest.entries()["src/b.mp3"].width, None);
+
+        manifest.save().unwrap();
+        let saved = std::fs::read(dir.join("manifest.json")).unwrap();
+        let saved: serde_json::Value = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(saved["src/a.png"]["height"], 2);
+        assert!(saved["src/b.mp3"].get("width").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
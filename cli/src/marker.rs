@@ -0,0 +1,108 @@
+//! Marker embedded in written outputs, so later runs recognize a file as already
+//! optimized with the same settings even after it was copied or moved.
+//!
+//! The marker is the settings fingerprint (`cache::config_fingerprint`
+//! for the CLI) in a place players and viewers ignore: a private `ipMk` chunk in
+//! PNG, a TXXX frame in MP3 and a trailing `free` box in MP4.
+
+use crate::config::ProcessingConfig;
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::processor::png;
+#[cfg(feature = "mp3")]
+use crate::processor::mp3;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4;
+
+/// Private, ancillary, safe-to-copy PNG chunk holding the marker
+const PNG_CHUNK: &[u8; 4] = b"ipMk";
+/// TXXX description of the MP3 marker and prefix of the MP4 `free` box body
+#[cfg(any(feature = "mp3", feature = "mp4"))]
+const MARKER_NAME: &str = "image-preparer";
+
+/// Formats a marker can be embedded in
+pub fn supports(format: Format) -> bool {
+    format == Format::Png
+        || (format == Format::Mp3 && cfg!(feature = "mp3"))
+        || (format == Format::Mp4 && cfg!(feature = "mp4"))
+}
+
+/// Fingerprint of the settings `data` was produced with, if it carries a marker
+pub fn read_marker(data: &[u8], format: Format) -> Option<u64> {
+    let value = match format {
+        Format::Png => png::find_chunk(data, PNG_CHUNK)?.to_vec(),
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => mp3::extended_text(data, MARKER_NAME)?.into_bytes(),
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => mp4::trailing_free_box(data)?.strip_prefix(free_box_prefix().as_slice())?.to_vec(),
+        _ => return None,
+    };
+    u64::from_str_radix(std::str::from_utf8(&value).ok()?, 16).ok()
+}
+
+/// `data` with its marker set to `fingerprint`, replacing an earlier one.
+/// Formats without a marker location are returned unchanged.
+#[cfg_attr(not(feature = "mp3"), allow(unused_variables))]
+pub fn embed_marker(
+    data: &[u8],
+    format: Format,
+    fingerprint: u64,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    let value = format!("{:016x}", fingerprint);
+    Ok(match format {
+        Format::Png => png::replace_chunk(data, PNG_CHUNK, value.as_bytes()),
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => mp3::with_extended_text(data, MARKER_NAME, &value, config)?,
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => {
+            let body = [free_box_prefix().as_slice(), value.as_bytes()].concat();
+            mp4::with_trailing_free_box(data, &body, &free_box_prefix())
+        }
+        _ => data.to_vec(),
+    })
+}
+
+#[cfg(feature = "mp4")]
+fn free_box_prefix() -> Vec<u8> {
+    format!("{}:", MARKER_NAME).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(data: &[u8], format: Format) {
+        let config = ProcessingConfig::default();
+        assert_eq!(read_marker(data, format), None);
+        let marked = embed_marker(data, format, 0xfeed, &config).unwrap();
+        assert_eq!(read_marker(&marked, format), Some(0xfeed), "{:?}", format);
+        // Re-marking replaces rather than stacks
+        let remarked = embed_marker(&marked, format, 0xbeef, &config).unwrap();
+        assert_eq!(read_marker(&remarked, format), Some(0xbeef));
+        assert_eq!(remarked.len(), marked.len());
+    }
+
+    #[test]
+    fn test_marker_round_trip_png() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(2, 2)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert_round_trip(&png, Format::Png);
+    }
+
+    #[test]
+    #[cfg(feature = "mp4")]
+    fn test_marker_round_trip_mp4() {
+        let mut mp4 = 24u32.to_be_bytes().to_vec();
+        mp4.extend_from_slice(b"ftypisom\0\0\0\0isommp41");
+        assert_round_trip(&mp4, Format::Mp4);
+    }
+
+    #[test]
+    #[cfg(feature = "mp3")]
+    fn test_marker_round_trip_mp3() {
+        assert_round_trip(&[0xFF, 0xFB, 0x90, 0x00].repeat(4), Format::Mp3);
+    }
+}
@@ -0,0 +1,116 @@
+//! Listing the metadata a file carries, block by block: PNG ancillary chunks,
+//! WebP EXIF/XMP/ICC chunks, JPEG APPn segments and comments, GIF comment and
+//! application extensions, MP3 tags, MP4 `udta`/`meta` boxes, Ogg comment
+//! fields, HEIC Exif/XMP items. Used to report what stripping would remove without comparing whole files.
+
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::processor::{jpeg, ogg, png};
+#[cfg(feature = "gif")]
+use crate::processor::gif;
+#[cfg(feature = "mp3")]
+use crate::processor::mp3;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4;
+#[cfg(feature = "webp")]
+use crate::processor::webp;
+
+/// One metadata chunk, segment or tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetadataBlock {
+    /// Chunk type, segment or tag name (`tEXt`, `EXIF`, `ID3v2`, ...)
+    pub name: String,
+    /// Bytes including the block's own header
+    pub size: u64,
+}
+
+/// Metadata blocks in `data`, in file order. Empty for formats without
+/// block-level metadata (WAV, ...) and for unparseable input.
+pub fn metadata_blocks(data: &[u8], format: Format) -> Vec<MetadataBlock> {
+    let named = |(name, size): (String, u64)| MetadataBlock { name, size };
+
+    match format {
+        Format::Png => png::metadata_chunks(data).into_iter().map(chunk).collect(),
+        #[cfg(feature = "webp")]
+        Format::Webp => webp::metadata_chunks(data).into_iter().map(chunk).collect(),
+        Format::Jpeg => jpeg::metadata_segments(data).into_iter().map(named).collect(),
+        #[cfg(feature = "gif")]
+        Format::Gif => gif::metadata_extensions(data).into_iter().map(named).collect(),
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => mp3::metadata_tags(data).into_iter().map(named).collect(),
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => mp4::metadata_boxes(data).into_iter().map(chunk).collect(),
+        Format::Opus | Format::Ogg => ogg::metadata_comments(data).into_iter().map(named).collect(),
+        Format::Heic => crate::heif::items(data)
+            .into_iter()
+            .filter_map(|item| Some((item.metadata_name()?.to_string(), item.size?)))
+            .map(named)
+            .collect(),
+        Format::Tiff => crate::tiff::metadata_blocks(data).into_iter().map(named).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// [`metadata_blocks`] of the file at `path`. Only an MP4's `moov` box is read
+/// into memory, so its media can be any size; other formats are read whole.
+pub fn metadata_blocks_in_file(path: &std::path::Path, format: Format) -> Result<Vec<MetadataBlock>, ProcessingError> {
+    #[cfg(feature = "mp4")]
+    if format == Format::Mp4 {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let boxes = mp4::metadata_boxes_in(&mut file)?;
+        return Ok(boxes.into_iter().map(chunk).collect());
+    }
+    Ok(metadata_blocks(&std::fs::read(path)?, format))
+}
+
+fn chunk((kind, size): ([u8; 4], u64)) -> MetadataBlock {
+    MetadataBlock { name: String::from_utf8_lossy(&kind).trim_end().to_string(), size }
+}
+
+/// Blocks of `before` that `after` no longer has. Blocks are matched by name,
+/// so of three `tEXt` chunks of which one survives, two are reported.
+pub fn removed_blocks(before: &[MetadataBlock], after: &[MetadataBlock]) -> Vec<MetadataBlock> {
+    let mut remaining: Vec<&str> = after.iter().map(|block| block.name.as_str()).collect();
+    before
+        .iter()
+        .filter(|block| match remaining.iter().position(|name| *name == block.name) {
+            Some(index) => {
+                remaining.swap_remove(index);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(body);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn test_metadata_blocks() {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(chunk(b"IHDR", &[0; 13]));
+        data.extend(chunk(b"tEXt", b"Author\0me"));
+        data.extend(chunk(b"tRNS", &[0; 2]));
+        data.extend(chunk(b"tEXt", b"Title\0x"));
+        data.extend(chunk(b"IEND", &[]));
+
+        let blocks = metadata_blocks(&data, Format::Png);
+        let names: Vec<_> = blocks.iter().map(|block| (block.name.as_str(), block.size)).collect();
+        assert_eq!(names, [("tEXt", 21), ("tEXt", 19)]);
+        assert!(metadata_blocks(&data, Format::Wav).is_empty());
+
+        assert_eq!(removed_blocks(&blocks, &blocks[..1]), blocks[1..]);
+    }
+}
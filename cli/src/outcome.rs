@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::format::Format;
+use crate::progress::Stage;
+
+/// Processed bytes plus what happened while producing them.
+///
+/// `data` borrows the input when processing left it untouched, so callers can
+/// skip rewriting identical files (see [`ProcessOutcome::is_unchanged`]).
+#[derive(Debug, Clone)]
+pub struct ProcessOutcome<'a> {
+    pub data: Cow<'a, [u8]>,
+    /// Output format (differs from the input when a chain converts)
+    pub format: Format,
+    /// Bytes of metadata removed, if the processor can tell
+    pub metadata_removed: Option<u64>,
+    /// Lossy quality actually applied, None for lossless / strip-only runs
+    pub quality: Option<u8>,
+    /// Image or video dimensions (width, height)
+    pub dimensions: Option<(u32, u32)>,
+    /// Average bitrate of the output in bits/s: of the audio, or of the video stream alone for video
+    pub bitrate: Option<u64>,
+    /// Time spent per stage, in the order stages finished
+    pub timings: Vec<(Stage, Duration)>,
+    /// Non-fatal issues worth surfacing to the user
+    pub warnings: Vec<String>,
+    /// Set by [`ProcessOutcome::into_owned`] for a pass-through it had to copy
+    unchanged: bool,
+}
+
+impl<'a> ProcessOutcome<'a> {
+    pub fn new(data: Vec<u8>, format: Format) -> Self {
+        Self::with_data(Cow::Owned(data), format)
+    }
+
+    /// Pass-through outcome for input that needed no changes
+    pub fn unchanged(input: &'a [u8], format: Format) -> Self {
+        Self::with_data(Cow::Borrowed(input), format)
+    }
+
+    fn with_data(data: Cow<'a, [u8]>, format: Format) -> Self {
+        Self {
+            data,
+            format,
+            metadata_removed: None,
+            quality: None,
+            dimensions: None,
+            bitrate: None,
+            timings: Vec::new(),
+            warnings: Vec::new(),
+            unchanged: false,
+        }
+    }
+
+    /// True when the output is the input, byte for byte
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self.data, Cow::Borrowed(_)) || self.unchanged
+    }
+
+    /// Detach from the input, copying it if the outcome was a pass-through.
+    /// A copied pass-through still reports [`ProcessOutcome::is_unchanged`].
+    pub fn into_owned(self) -> ProcessOutcome<'static> {
+        ProcessOutcome {
+            unchanged: self.is_unchanged(),
+            data: Cow::Owned(self.data.into_owned()),
+            format: self.format,
+            metadata_removed: self.metadata_removed,
+            quality: self.quality,
+            dimensions: self.dimensions,
+            bitrate: self.bitrate,
+            timings: self.timings,
+            warnings: self.warnings,
+        }
+    }
+
+    /// Total time across all recorded stages
+    pub fn total_time(&self) -> Duration {
+        self.timings.iter().map(|(_, d)| *d).sum()
+    }
+}
+
+/// Collects stage durations from progress start/finish reports.
+#[derive(Debug, Default)]
+pub struct StageTimer {
+    started: Mutex<HashMap<Stage, Instant>>,
+    finished: Mutex<Vec<(Stage, Duration)>>,
+}
+
+impl StageTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&self, stage: Stage, percent: f32) {
+        // `Instant::now` panics on wasm32-unknown-unknown
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+        if percent <= 0.0 {
+            self.started.lock().unwrap().insert(stage, Instant::now());
+        } else if percent >= 100.0 {
+            if let Some(start) = self.started.lock().unwrap().remove(&stage) {
+                self.finished.lock().unwrap().push((stage, start.elapsed()));
+            }
+        }
+    }
+
+    pub fn into_timings(self) -> Vec<(Stage, Duration)> {
+        self.finished.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_timer_records_finished_stages() {
+        let timer = StageTimer::new();
+        timer.record(Stage::Decode, 0.0);
+        timer.record(Stage::Decode, 50.0);
+        timer.record(Stage::Decode, 100.0);
+        timer.record(Stage::Encode, 0.0);
+
+        let timings = timer.into_timings();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].0, Stage::Decode);
+    }
+
+    #[test]
+    fn test_into_owned_keeps_unchanged() {
+        let input = [1, 2, 3];
+        assert!(ProcessOutcome::unchanged(&input, Format::Png).into_owned().is_unchanged());
+        assert!(!ProcessOutcome::new(input.to_vec(), Format::Png).into_owned().is_unchanged());
+    }
+}
@@ -0,0 +1,273 @@
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::config::ProcessingConfig;
+use crate::error::ProcessingError;
+
+/// Where batch work runs. imagequant and oxipng spread their own work over the
+/// same pool, so a bounded pool bounds the optimizer's total CPU use.
+#[derive(Debug, Clone, Default)]
+pub enum Parallelism {
+    /// rayon's global pool (one thread per core)
+    #[default]
+    Global,
+    /// A pool owned by the embedding application
+    Pool(Arc<ThreadPool>),
+}
+
+impl Parallelism {
+    /// Dedicated pool with at most `threads` workers
+    pub fn threads(threads: usize) -> Result<Self, ProcessingError> {
+        Self::build(threads, false)
+    }
+
+    /// Dedicated pool like [`Parallelism::threads`] whose workers lower their
+    /// own priority ([`lower_priority`]) as they start, which the ffmpeg
+    /// processes they spawn inherit. The calling thread keeps its priority.
+    pub fn low_priority_threads(threads: usize) -> Result<Self, ProcessingError> {
+        Self::build(threads, true)
+    }
+
+    /// The pool `config.jobs` and `config.low_priority` ask for (one worker per
+    /// core when only `low_priority` is set), or None when neither is set
+    pub fn from_config(config: &ProcessingConfig) -> Result<Option<Self>, ProcessingError> {
+        if config.jobs.is_none() && !config.low_priority {
+            return Ok(None);
+        }
+        let threads = config.jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+        Self::build(threads, config.low_priority).map(Some)
+    }
+
+    fn build(threads: usize, low_priority: bool) -> Result<Self, ProcessingError> {
+        let mut builder =
+            ThreadPoolBuilder::new().num_threads(threads).thread_name(|i| format!("image-preparer-{}", i));
+        if low_priority {
+            builder = builder.start_handler(|_| {
+                if let Err(e) = lower_priority() {
+                    log::debug!("Could not lower a worker's priority: {}", e);
+                }
+            });
+        }
+        let pool =
+            builder.build().map_err(|e| ProcessingError::InvalidConfig(format!("cannot build thread pool: {}", e)))?;
+        Ok(Parallelism::Pool(Arc::new(pool)))
+    }
+
+    /// Run `op` inside this pool; rayon calls made by `op` stay in it.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match self {
+            Parallelism::Global => op(),
+            Parallelism::Pool(pool) => pool.install(op),
+        }
+    }
+
+    /// Run `op` on the calling thread with a scope whose spawned jobs run in this pool
+    pub fn in_place_scope<'scope, R>(&self, op: impl FnOnce(&rayon::Scope<'scope>) -> R) -> R {
+        match self {
+            Parallelism::Global => rayon::in_place_scope(op),
+            Parallelism::Pool(pool) => pool.in_place_scope(op),
+        }
+    }
+}
+
+/// Pool size for a batch of `cpu_files` encoded in process and `ffmpeg_files`
+/// handed to ffmpeg, on `cores` cores with at most `ffmpeg_slots` ffmpeg
+/// processes at once (0 = no cap).
+///
+/// In-process work wants a thread per core. A thread waiting on ffmpeg uses no
+/// CPU, but ffmpeg keeps several cores busy itself, so the in-process share of
+/// the cores shrinks with the share of video files, and each ffmpeg slot gets
+/// one extra thread to wait in.
+pub fn auto_pool_size(cpu_files: usize, ffmpeg_files: usize, cores: usize, ffmpeg_slots: usize) -> usize {
+    let cores = cores.max(1);
+    let total = cpu_files + ffmpeg_files;
+    if total == 0 {
+        return cores;
+    }
+    let cpu_threads = (cores * cpu_files).div_ceil(total);
+    let slots = if ffmpeg_slots == 0 { cores } else { ffmpeg_slots };
+    (cpu_threads + ffmpeg_files.min(slots)).max(1)
+}
+
+/// Lower the CPU priority (nice 10) of the calling thread and of every thread
+/// and process it starts afterwards, so a long batch leaves the machine
+/// responsive. Call it before the thread pool and ffmpeg are started.
+pub fn lower_priority() -> Result<(), ProcessingError> {
+    #[cfg(unix)]
+    {
+        // SAFETY: setpriority takes plain integers and touches no memory of ours
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) } != 0 {
+            return Err(ProcessingError::Io(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        Err(ProcessingError::InvalidConfig("lowering the priority is only supported on Unix".to_string()))
+    }
+}
+
+/// Byte budget shared by concurrent jobs, so a batch of large files does not
+/// hold more than `limit` bytes in memory at once. A job asks for its bytes
+/// before loading anything and blocks until they fit.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Reserve `bytes` until the guard is dropped, blocking until they fit. A job
+    /// larger than the whole budget waits until nothing else is in flight and then
+    /// runs alone. Reserve before handing a job to a pool, not inside it: a rayon
+    /// worker waiting here for its own job's reservation to go away would wait
+    /// forever, as does one that took another job while that job waits on
+    /// parallel work (see `BatchRunner`).
+    pub fn acquire(&self, bytes: u64) -> BudgetGuard<'_> {
+        let mut used = self.used.lock().unwrap();
+        while !self.fits(*used, bytes) {
+            used = self.freed.wait(used).unwrap();
+        }
+        self.reserve(&mut used, bytes)
+    }
+
+    /// [`MemoryBudget::acquire`] without waiting: None when `bytes` do not fit yet
+    pub fn try_acquire(&self, bytes: u64) -> Option<BudgetGuard<'_>> {
+        let mut used = self.used.lock().unwrap();
+        self.fits(*used, bytes).then(|| self.reserve(&mut used, bytes))
+    }
+
+    fn fits(&self, used: u64, bytes: u64) -> bool {
+        used == 0 || used + bytes <= self.limit
+    }
+
+    fn reserve(&self, used: &mut u64, bytes: u64) -> BudgetGuard<'_> {
+        *used += bytes;
+        BudgetGuard { budget: self, bytes, thread: PhantomData }
+    }
+
+    /// Bytes currently reserved
+    pub fn in_use(&self) -> u64 {
+        *self.used.lock().unwrap()
+    }
+}
+
+/// Reservation from a [`MemoryBudget`], released on drop by the thread that took it
+#[derive(Debug)]
+pub struct BudgetGuard<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+    /// Not `Send`: a reservation belongs to the code that waited for it
+    thread: PhantomData<*const ()>,
+}
+
+impl Drop for BudgetGuard<'_> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap() -= self.bytes;
+        self.budget.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_auto_pool_size() {
+        // Images only: one thread per core
+        assert_eq!(auto_pool_size(100, 0, 8, 2), 8);
+        // Videos only: one thread per ffmpeg slot, or per video when fewer
+        assert_eq!(auto_pool_size(0, 100, 8, 2), 2);
+        assert_eq!(auto_pool_size(0, 1, 8, 2), 1);
+        assert_eq!(auto_pool_size(0, 100, 8, 0), 8);
+        // Half and half: half the cores for images plus the ffmpeg slots
+        assert_eq!(auto_pool_size(50, 50, 8, 2), 6);
+        assert_eq!(auto_pool_size(0, 0, 8, 2), 8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_low_priority_pool() {
+        assert!(Parallelism::from_config(&ProcessingConfig::default()).unwrap().is_none());
+        let config = ProcessingConfig { jobs: Some(2), low_priority: true, ..ProcessingConfig::default() };
+        let pool = Parallelism::from_config(&config).unwrap().unwrap();
+        // SAFETY: getpriority only reads the calling thread's nice value
+        let nice = pool.install(|| unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) });
+        assert!(nice >= 10);
+        assert!(matches!(pool, Parallelism::Pool(pool) if pool.current_num_threads() == 2));
+    }
+
+    #[test]
+    fn test_memory_budget_caps_in_flight_bytes() {
+        let budget = MemoryBudget::new(100);
+        let peak = Mutex::new(0);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        pool.install(|| {
+            (0..32u64).into_par_iter().for_each(|i| {
+                // Every fourth job is larger than the whole budget
+                let bytes = if i % 4 == 0 { 150 } else { 30 };
+                let _guard = budget.acquire(bytes);
+                let in_use = budget.in_use();
+                assert!(in_use <= 100 || in_use == bytes);
+                let mut peak = peak.lock().unwrap();
+                *peak = (*peak).max(in_use);
+            })
+        });
+        assert_eq!(budget.in_use(), 0);
+        assert!(*peak.lock().unwrap() <= 150);
+    }
+
+    /// Runs `job` on its own thread: a deadlock fails the test instead of hanging it
+    fn finishes(job: impl FnOnce() + Send + 'static) -> bool {
+        let (done, finished) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            job();
+            done.send(()).unwrap();
+        });
+        finished.recv_timeout(std::time::Duration::from_secs(10)).is_ok()
+    }
+
+    #[test]
+    fn test_memory_budget_deadlocks() {
+        // Larger than the whole budget: it would never fit, so it waits for the others and runs alone
+        let budget = Arc::new(MemoryBudget::new(100));
+        let held = budget.acquire(60);
+        let waiting = Arc::clone(&budget);
+        let (started, running) = std::sync::mpsc::channel();
+        let big = std::thread::spawn(move || {
+            let _guard = waiting.acquire(150);
+            started.send(waiting.in_use()).unwrap();
+        });
+        assert!(running.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+        drop(held);
+        assert_eq!(running.recv_timeout(std::time::Duration::from_secs(10)), Ok(150));
+        big.join().unwrap();
+        assert!(finishes(move || drop(budget.acquire(150))));
+    }
+
+    #[test]
+    fn test_memory_budget_try_acquire() {
+        let budget = MemoryBudget::new(100);
+        let held = budget.try_acquire(80).unwrap();
+        assert!(budget.try_acquire(50).is_none());
+        let small = budget.try_acquire(20).unwrap();
+        drop(held);
+        // Larger than the whole budget: only once nothing else is reserved
+        assert!(budget.try_acquire(150).is_none());
+        drop(small);
+        assert_eq!(budget.try_acquire(150).map(|_| budget.in_use()), Some(150));
+        assert_eq!(budget.in_use(), 0);
+    }
+}
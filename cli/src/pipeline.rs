@@ -1,52 +1,547 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::Path;
+#[cfg(feature = "async")]
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use crate::cancel::CancelToken;
+use crate::chain::Chain;
 use crate::config::ProcessingConfig;
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
-use crate::processor::ImageProcessor;
+use crate::format::Format;
+use crate::outcome::{ProcessOutcome, StageTimer};
+#[cfg(feature = "parallel")]
+use crate::parallel::Parallelism;
+#[cfg(feature = "avif")]
+use crate::processor::avif::AvifProcessor;
+#[cfg(feature = "gif")]
+use crate::processor::gif::GifProcessor;
+use crate::processor::jpeg::JpegProcessor;
+#[cfg(feature = "mp3")]
+use crate::processor::mp3::Mp3Processor;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4::Mp4Processor;
+use crate::processor::png::PngProcessor;
+use crate::processor::ogg::OggProcessor;
+use crate::processor::wav::WavProcessor;
+#[cfg(feature = "webp")]
+use crate::processor::webp::WebpProcessor;
+use crate::processor::{Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, ProgressEvent, ProgressSink, Stage, StageCallback};
 
 pub struct Pipeline {
     processors: Vec<Box<dyn ImageProcessor>>,
+    chains: HashMap<Format, Chain>,
+    on_stage: Option<StageCallback>,
+    progress: Option<Arc<dyn ProgressSink>>,
+    cancel: CancelToken,
+    #[cfg(feature = "parallel")]
+    parallelism: Parallelism,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pipeline {
     pub fn new() -> Self {
         Self {
             processors: Vec::new(),
+            chains: HashMap::new(),
+            on_stage: None,
+            progress: None,
+            cancel: CancelToken::new(),
+            #[cfg(feature = "parallel")]
+            parallelism: Parallelism::default(),
         }
     }
 
+    /// Pipeline with every built-in processor registered (PNG, JPEG, MP3, WebP, AVIF, GIF, MP4, WAV, Ogg),
+    /// less those whose Cargo feature is disabled.
+    pub fn with_default_processors() -> Self {
+        let mut pipeline = Self::new();
+        pipeline.register(Box::new(PngProcessor));
+        pipeline.register(Box::new(JpegProcessor));
+        #[cfg(feature = "mp3")]
+        pipeline.register(Box::new(Mp3Processor));
+        #[cfg(feature = "webp")]
+        pipeline.register(Box::new(WebpProcessor));
+        #[cfg(feature = "avif")]
+        pipeline.register(Box::new(AvifProcessor));
+        #[cfg(feature = "gif")]
+        pipeline.register(Box::new(GifProcessor));
+        #[cfg(feature = "mp4")]
+        pipeline.register(Box::new(Mp4Processor));
+        pipeline.register(Box::new(WavProcessor));
+        pipeline.register(Box::new(OggProcessor));
+        pipeline
+    }
+
     pub fn register(&mut self, processor: Box<dyn ImageProcessor>) {
         self.processors.push(processor);
     }
 
+    /// Run `chain` for `format` instead of the single registered processor.
+    /// Chains may change the output format; use [`Pipeline::process_bytes_with_format`]
+    /// to learn the final format.
+    pub fn set_chain(&mut self, format: Format, chain: Chain) {
+        self.chains.insert(format, chain);
+    }
+
+    /// Chain set for `format`, if any
+    pub fn chain(&self, format: Format) -> Option<&Chain> {
+        self.chains.get(&format)
+    }
+
+    /// Register a callback invoked as `(file, stage, percent)` while files are processed.
+    /// `process_bytes` has no file name and reports an empty path.
+    pub fn on_stage(&mut self, callback: impl Fn(&Path, Stage, f32) + Send + Sync + 'static) {
+        self.on_stage = Some(Arc::new(callback));
+    }
+
+    /// Send [`ProgressEvent`]s to `sink`: each file's start, stages, result and
+    /// bytes saved, plus the start and totals of [`Pipeline::process_batch`].
+    /// Stages still go to the [`Pipeline::on_stage`] callback too.
+    pub fn set_progress_sink(&mut self, sink: Arc<dyn ProgressSink>) {
+        self.progress = Some(sink);
+    }
+
+    /// Fail files started after `token` is cancelled with [`ProcessingError::Cancelled`];
+    /// files already running finish.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    /// Run batch work in `parallelism` instead of rayon's global pool.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.parallelism = parallelism;
+    }
+
+    /// Run `op` in this pipeline's thread pool, e.g. around a caller's own `par_iter`.
+    #[cfg(feature = "parallel")]
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.parallelism.install(op)
+    }
+
+    /// Keep only processors whose capabilities match, e.g.
+    /// `pipeline.retain(|caps| !caps.needs_ffmpeg)` when ffmpeg is missing.
+    pub fn retain(&mut self, keep: impl Fn(&Capabilities) -> bool) {
+        self.processors.retain(|p| keep(&p.capabilities()));
+    }
+
+    /// Formats handled by the registered processors, in registration order,
+    /// then formats only a chain handles.
+    pub fn supported_formats(&self) -> Vec<Format> {
+        let mut formats: Vec<Format> = self
+            .processors
+            .iter()
+            .flat_map(|p| p.supported_formats().iter().copied())
+            .collect();
+        let mut chained: Vec<Format> = self.chains.keys().filter(|f| !formats.contains(f)).copied().collect();
+        chained.sort_by_key(|format| format.as_str());
+        formats.extend(chained);
+        formats
+    }
+
+    /// Capabilities of the processor that would handle `format`, if any.
+    pub fn capabilities(&self, format: Format) -> Option<Capabilities> {
+        self.find_processor(format).map(|p| p.capabilities())
+    }
+
+    /// What the processor for `format` would do to `input`, if there is one.
+    pub fn plan(&self, format: Format, input: &[u8], config: &ProcessingConfig) -> Option<ProcessorPlan> {
+        self.find_processor(format).map(|p| p.plan(input, config))
+    }
+
     /// Find a processor that supports the given format.
-    fn find_processor(&self, format: ImageFormat) -> Option<&dyn ImageProcessor> {
+    fn find_processor(&self, format: Format) -> Option<&dyn ImageProcessor> {
         self.processors
             .iter()
             .find(|p| p.supported_formats().contains(&format))
             .map(|p| p.as_ref())
     }
 
-    /// Process a single file's bytes, given its path (for format detection).
+    /// Process a single file's bytes, given its path (for format detection;
+    /// the content is sniffed when the extension is missing or unknown).
     pub fn process_file(
         &self,
         path: &Path,
         data: &[u8],
         config: &ProcessingConfig,
     ) -> Result<Vec<u8>, ProcessingError> {
-        let format = ImageFormat::from_path(path).ok_or_else(|| {
-            ProcessingError::UnsupportedFormat(
-                path.extension()
-                    .map(|e| e.to_string_lossy().into_owned())
-                    .unwrap_or_else(|| "unknown".into()),
-            )
-        })?;
+        self.process_file_with_stats(path, data, config).map(|outcome| outcome.data.into_owned())
+    }
+
+    /// Like [`Pipeline::process_file`], also reporting metadata savings,
+    /// quality, dimensions, stage timings and warnings.
+    pub fn process_file_with_stats<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.run(path, data, detect_format(path, data)?, config)
+    }
+
+    /// Process raw bytes whose format is already known.
+    pub fn process_bytes(
+        &self,
+        data: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.run(Path::new(""), data, format, config).map(|outcome| outcome.data.into_owned())
+    }
+
+    /// Like [`Pipeline::process_bytes`], returning the full [`ProcessOutcome`].
+    pub fn process_bytes_with_stats<'a>(
+        &self,
+        data: &'a [u8],
+        format: Format,
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.run(Path::new(""), data, format, config)
+    }
+
+    /// Like [`Pipeline::process_bytes`], also returning the output format
+    /// (which differs from `format` when a chain converts).
+    pub fn process_bytes_with_format(
+        &self,
+        data: &[u8],
+        format: Format,
+        config: &ProcessingConfig,
+    ) -> Result<(Vec<u8>, Format), ProcessingError> {
+        self.run(Path::new(""), data, format, config)
+            .map(|outcome| (outcome.data.into_owned(), outcome.format))
+    }
+
+    /// Process several files, in parallel when the `parallel` feature is on.
+    /// Results come back in input order.
+    pub fn process_batch<'a>(
+        &self,
+        inputs: &[(&Path, &'a [u8])],
+        config: &ProcessingConfig,
+    ) -> Vec<Result<ProcessOutcome<'a>, ProcessingError>> {
+        let process = |&(path, data): &(&Path, &'a [u8])| self.process_file_with_stats(path, data, config);
+        if let Some(sink) = &self.progress {
+            sink.event(&ProgressEvent::BatchStarted { files: inputs.len() });
+        }
+
+        #[cfg(feature = "parallel")]
+        let results: Vec<_> = {
+            use rayon::prelude::*;
+            self.install(|| inputs.par_iter().map(process).collect())
+        };
+        #[cfg(not(feature = "parallel"))]
+        let results: Vec<_> = inputs.iter().map(process).collect();
+
+        if let Some(sink) = &self.progress {
+            let failed = results.iter().filter(|result| result.is_err()).count();
+            let bytes_saved = results
+                .iter()
+                .zip(inputs)
+                .filter_map(|(result, (_, data))| Some(saved(data, result.as_ref().ok()?)))
+                .sum();
+            sink.event(&ProgressEvent::BatchDone { processed: results.len() - failed, failed, bytes_saved });
+        }
+        results
+    }
+
+    fn run<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        format: Format,
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.file_started(path, data);
+        let result = self.run_file(path, data, format, config);
+        self.file_finished(path, data, &result);
+        result
+    }
+
+    fn file_started(&self, path: &Path, data: &[u8]) {
+        if let Some(sink) = &self.progress {
+            sink.event(&ProgressEvent::FileStarted { path, size: data.len() as u64 });
+        }
+    }
+
+    fn file_finished(&self, path: &Path, data: &[u8], result: &Result<ProcessOutcome, ProcessingError>) {
+        let Some(sink) = &self.progress else {
+            return;
+        };
+        match result {
+            Ok(outcome) => sink.event(&ProgressEvent::FileDone {
+                path,
+                original_size: data.len() as u64,
+                output_size: outcome.data.len() as u64,
+                bytes_saved: saved(data, outcome),
+            }),
+            Err(error) => sink.event(&ProgressEvent::FileFailed { path, error }),
+        }
+    }
+
+    fn run_file<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        format: Format,
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.cancel.check()?;
+        config.limits.check_input(data.len())?;
+
+        let timer = StageTimer::new();
+        let progress =
+            Progress::new(path, self.on_stage.as_ref()).with_sink(self.progress.as_deref()).with_timer(&timer);
+
+        let outcome = if let Some(chain) = self.chains.get(&format) {
+            let (output, output_format) = chain.run(data, format, config, &progress)?;
+            ProcessOutcome::new(output, output_format)
+        } else {
+            let processor = self.find_processor(format).ok_or_else(|| {
+                ProcessingError::UnsupportedFormat(format.as_str().to_string())
+            })?;
+            processor.process_with_stats(data, config, &progress)?
+        };
+        Ok(settle(data, format, outcome, timer))
+    }
+
+    /// Whether [`Pipeline::process_stream`] streams `format` under `config`
+    /// instead of reading the whole input first
+    pub fn supports_streaming(&self, format: Format, config: &ProcessingConfig) -> bool {
+        !self.chains.contains_key(&format)
+            && self.find_processor(format).is_some_and(|processor| processor.supports_streaming(config))
+    }
+
+    /// Process from a reader into a writer. Formats that support streaming
+    /// (see [`ImageProcessor::supports_streaming`]) never hold the whole file in memory.
+    /// Reading more than `config.limits.max_input_size` fails with `LimitExceeded`.
+    pub fn process_stream(
+        &self,
+        format: Format,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        self.cancel.check()?;
+        let max = config.limits.max_input_size.unwrap_or(u64::MAX);
+        let mut input = CountingReader { inner: input, read: 0, max };
+        let result = self.stream(format, &mut input, output, config);
+        // Whatever the processor made of the cut-off input, the limit is what failed
+        config.limits.check_input(input.read as usize)?;
+        result
+    }
+
+    fn stream(
+        &self,
+        format: Format,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        if self.chains.contains_key(&format) {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            let outcome = self.run(Path::new(""), &data, format, config)?;
+            output.write_all(&outcome.data)?;
+            return Ok(());
+        }
 
         let processor = self.find_processor(format).ok_or_else(|| {
             ProcessingError::UnsupportedFormat(format.as_str().to_string())
         })?;
 
-        processor.process(data, config)
+        processor.process_stream(input, output, config)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Pipeline {
+    /// Async variant of [`Pipeline::process_file`].
+    ///
+    /// CPU-bound processors run on tokio's blocking pool; MP4 runs ffmpeg via
+    /// `tokio::process`. Must be called from within a tokio runtime.
+    pub async fn process_file_async(
+        self: Arc<Self>,
+        path: &Path,
+        data: Vec<u8>,
+        config: ProcessingConfig,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        let format = detect_format(path, &data)?;
+        self.run_async(path.to_path_buf(), data, format, config).await.map(|outcome| outcome.data.into_owned())
+    }
+
+    /// Async variant of [`Pipeline::process_bytes`].
+    pub async fn process_bytes_async(
+        self: Arc<Self>,
+        data: Vec<u8>,
+        format: Format,
+        config: ProcessingConfig,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.run_async(PathBuf::new(), data, format, config).await.map(|outcome| outcome.data.into_owned())
+    }
+
+    /// Async variant of [`Pipeline::process_bytes_with_stats`].
+    pub async fn process_bytes_with_stats_async(
+        self: Arc<Self>,
+        data: Vec<u8>,
+        format: Format,
+        config: ProcessingConfig,
+    ) -> Result<ProcessOutcome<'static>, ProcessingError> {
+        self.run_async(PathBuf::new(), data, format, config).await
+    }
+
+    async fn run_async(
+        self: Arc<Self>,
+        path: PathBuf,
+        data: Vec<u8>,
+        format: Format,
+        config: ProcessingConfig,
+    ) -> Result<ProcessOutcome<'static>, ProcessingError> {
+        if self.find_processor(format).is_none() && !self.chains.contains_key(&format) {
+            return Err(ProcessingError::UnsupportedFormat(format.as_str().to_string()));
+        }
+
+        // Lossless MP4 is a pure-Rust remux and runs on the blocking pool like everything else
+        #[cfg(feature = "mp4")]
+        if format == Format::Mp4 && !config.no_lossy && !self.chains.contains_key(&format) {
+            self.file_started(&path, &data);
+            let result = self.run_mp4_async(&path, &data, &config).await;
+            self.file_finished(&path, &data, &result);
+            return result.map(ProcessOutcome::into_owned);
+        }
+
+        tokio::task::spawn_blocking(move || self.run(&path, &data, format, &config).map(ProcessOutcome::into_owned))
+            .await
+            .map_err(|e| {
+                if e.is_cancelled() {
+                    ProcessingError::Cancelled
+                } else {
+                    ProcessingError::Encode(format!("processing task failed: {}", e))
+                }
+            })?
+    }
+
+    /// [`Pipeline::run_file`] for lossy MP4, with ffmpeg run through `tokio::process`
+    #[cfg(feature = "mp4")]
+    async fn run_mp4_async<'a>(
+        &self,
+        path: &Path,
+        data: &'a [u8],
+        config: &ProcessingConfig,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.cancel.check()?;
+        config.limits.check_input(data.len())?;
+
+        let timer = StageTimer::new();
+        let progress =
+            Progress::new(path, self.on_stage.as_ref()).with_sink(self.progress.as_deref()).with_timer(&timer);
+        let outcome = crate::processor::mp4::compress_mp4_async(data, config, &progress).await?;
+        Ok(settle(data, Format::Mp4, outcome, timer))
+    }
+}
+
+/// Reader that counts the bytes read through it and fails once they pass `max`,
+/// so [`Pipeline::process_stream`] stops an input over the limit right there
+struct CountingReader<'a> {
+    inner: &'a mut dyn Read,
+    read: u64,
+    max: u64,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        if self.read > self.max {
+            return Err(std::io::Error::other(format!("input is over {} bytes", self.max)));
+        }
+        Ok(read)
+    }
+}
+
+/// Finish a processor's `outcome` for `data`: report identical bytes as unchanged
+/// and attach the stage timings
+fn settle<'a>(
+    data: &'a [u8],
+    format: Format,
+    mut outcome: ProcessOutcome<'a>,
+    timer: StageTimer,
+) -> ProcessOutcome<'a> {
+    // Processors that re-encode can still land on identical bytes
+    if !outcome.is_unchanged() && outcome.format == format && *outcome.data == *data {
+        outcome.data = Cow::Borrowed(data);
+    }
+    outcome.timings = timer.into_timings();
+    outcome
+}
+
+/// Bytes `outcome` saved on `input`; 0 when it is not smaller
+fn saved(input: &[u8], outcome: &ProcessOutcome) -> u64 {
+    (input.len() as u64).saturating_sub(outcome.data.len() as u64)
+}
+
+/// Format per [`Format::detect`]: the content's when it contradicts the extension
+/// (logged), else the extension's, else the content's
+fn detect_format(path: &Path, data: &[u8]) -> Result<Format, ProcessingError> {
+    let format = Format::detect(path, data).ok_or_else(|| {
+        ProcessingError::UnsupportedFormat(
+            path.extension()
+                .map(|e| e.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "unknown".into()),
+        )
+    })?;
+    if Format::from_path(path).is_some_and(|extension| extension != format) {
+        log::warn!("{} holds {} data despite its extension; processing it as such", path.display(), format.as_str());
+    }
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mono 8-bit WAV holding `samples` bytes of silence
+    fn wav(samples: usize) -> Vec<u8> {
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(36 + samples as u32).to_le_bytes());
+        data.extend_from_slice(b"WAVEfmt ");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 1, 0]);
+        data.extend_from_slice(&8000u32.to_le_bytes());
+        data.extend_from_slice(&8000u32.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 8, 0]);
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&(samples as u32).to_le_bytes());
+        data.resize(data.len() + samples, 128);
+        data
+    }
+
+    #[test]
+    fn test_process_stream_checks() {
+        let mut pipeline = Pipeline::with_default_processors();
+        let input = wav(1000);
+        let config = ProcessingConfig { no_lossy: true, ..ProcessingConfig::default() };
+        assert!(pipeline.supports_streaming(Format::Wav, &config));
+        let mut output = Vec::new();
+        pipeline.process_stream(Format::Wav, &mut input.as_slice(), &mut output, &config).unwrap();
+        assert_eq!(output, input);
+
+        let mut limited = config.clone();
+        limited.limits.max_input_size = Some(500);
+        let result = pipeline.process_stream(Format::Wav, &mut input.as_slice(), &mut Vec::new(), &limited);
+        assert!(matches!(result, Err(ProcessingError::LimitExceeded(_))));
+
+        let token = CancelToken::new();
+        token.cancel();
+        pipeline.set_cancel_token(token);
+        let result = pipeline.process_stream(Format::Wav, &mut input.as_slice(), &mut Vec::new(), &config);
+        assert!(matches!(result, Err(ProcessingError::Cancelled)));
     }
 }
@@ -0,0 +1,260 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::{
+    AvifOptions, GifOptions, IcoOptions, JpegOptions, Mp3Options, Mp4Options, OpusOptions, PngOptions, ProcessingConfig,
+    SilenceTrim, StripMode, TiffOptions, WavOptions, WebpOptions,
+};
+use crate::error::ProcessingError;
+use crate::format::Format;
+#[cfg(feature = "fs")]
+use crate::io::read_file;
+use crate::outcome::ProcessOutcome;
+#[cfg(feature = "parallel")]
+use crate::parallel::Parallelism;
+use crate::pipeline::Pipeline;
+use crate::progress::ProgressSink;
+
+/// Ready-to-use compressor with all built-in processors registered.
+///
+/// Built with [`Preparer::builder`]; the configuration is validated once and
+/// reused for every call.
+///
+/// ```no_run
+/// use image_preparer::{Format, Preparer, StripMode};
+///
+/// let preparer = Preparer::builder()
+///     .quality(80)
+///     .strip(StripMode::Safe)
+///     .build()?;
+///
+/// let data = std::fs::read("photo.png")?;
+/// let compressed = preparer.process_bytes(&data, Format::Png)?;
+/// std::fs::write("photo.min.png", compressed)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Preparer {
+    pipeline: Arc<Pipeline>,
+    config: ProcessingConfig,
+}
+
+impl Preparer {
+    pub fn builder() -> PreparerBuilder {
+        PreparerBuilder::new()
+    }
+
+    /// The validated configuration used for every call.
+    pub fn config(&self) -> &ProcessingConfig {
+        &self.config
+    }
+
+    /// Compress bytes of a known format.
+    pub fn process_bytes(&self, data: &[u8], format: Format) -> Result<Vec<u8>, ProcessingError> {
+        self.pipeline.process_bytes(data, format, &self.config)
+    }
+
+    /// Like [`Preparer::process_bytes`], also reporting what was done to the file.
+    pub fn process_bytes_with_stats<'a>(
+        &self,
+        data: &'a [u8],
+        format: Format,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        self.pipeline.process_bytes_with_stats(data, format, &self.config)
+    }
+
+    #[cfg(feature = "fs")]
+    /// Read a file from disk and compress it, detecting the format from its extension.
+    pub fn process_file(&self, path: &Path) -> Result<Vec<u8>, ProcessingError> {
+        let data = read_file(path)?;
+        self.pipeline.process_file(path, &data, &self.config)
+    }
+
+    /// Compress several files' bytes; see [`Pipeline::process_batch`].
+    pub fn process_batch<'a>(&self, inputs: &[(&Path, &'a [u8])]) -> Vec<Result<ProcessOutcome<'a>, ProcessingError>> {
+        self.pipeline.process_batch(inputs, &self.config)
+    }
+
+    /// Compress from a reader into a writer; MP3 and lossless MP4 stream without
+    /// buffering the whole input.
+    pub fn process_stream(
+        &self,
+        format: Format,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), ProcessingError> {
+        self.pipeline.process_stream(format, input, output, &self.config)
+    }
+
+    /// Async variant of [`Preparer::process_bytes`]; see [`Pipeline::process_bytes_async`].
+    #[cfg(feature = "async")]
+    pub async fn process_bytes_async(
+        &self,
+        data: Vec<u8>,
+        format: Format,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        Arc::clone(&self.pipeline)
+            .process_bytes_async(data, format, self.config.clone())
+            .await
+    }
+}
+
+/// Builder for [`Preparer`]. Unset options fall back to [`ProcessingConfig::default`].
+#[derive(Debug, Clone, Default)]
+pub struct PreparerBuilder {
+    config: ProcessingConfig,
+    progress: Option<Arc<dyn ProgressSink>>,
+    #[cfg(feature = "parallel")]
+    parallelism: Parallelism,
+}
+
+impl PreparerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Quantization / encoder quality 0–100
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.config.quality = quality;
+        self
+    }
+
+    /// Speed 1–10 (1 = slowest/best, 10 = fastest/worst)
+    pub fn speed(mut self, speed: i32) -> Self {
+        self.config.speed = speed;
+        self
+    }
+
+    /// Skip lossy steps, only optimize losslessly and strip metadata
+    pub fn no_lossy(mut self, no_lossy: bool) -> Self {
+        self.config.no_lossy = no_lossy;
+        self
+    }
+
+    /// Metadata strip mode
+    pub fn strip(mut self, strip: StripMode) -> Self {
+        self.config.strip = strip;
+        self
+    }
+
+    /// Tune the lossy quality per image to reach this SSIM (0–1) against the original
+    pub fn target_ssim(mut self, target: f64) -> Self {
+        self.config.target_ssim = Some(target);
+        self
+    }
+
+    /// PNG quantization and oxipng settings
+    pub fn png(mut self, png: PngOptions) -> Self {
+        self.config.png = png;
+        self
+    }
+
+    /// JPEG encoder settings
+    pub fn jpeg(mut self, jpeg: JpegOptions) -> Self {
+        self.config.jpeg = jpeg;
+        self
+    }
+
+    /// WebP encoder settings
+    pub fn webp(mut self, webp: WebpOptions) -> Self {
+        self.config.webp = webp;
+        self
+    }
+
+    /// AVIF encoder settings
+    pub fn avif(mut self, avif: AvifOptions) -> Self {
+        self.config.avif = avif;
+        self
+    }
+
+    /// GIF frame quantization settings
+    pub fn gif(mut self, gif: GifOptions) -> Self {
+        self.config.gif = gif;
+        self
+    }
+
+    /// MP3 re-encoding settings
+    pub fn mp3(mut self, mp3: Mp3Options) -> Self {
+        self.config.mp3 = mp3;
+        self
+    }
+
+    /// MP4 encoder and frame extraction settings
+    pub fn mp4(mut self, mp4: Mp4Options) -> Self {
+        self.config.mp4 = mp4;
+        self
+    }
+
+    /// WAV downsampling and bit-depth settings
+    pub fn wav(mut self, wav: WavOptions) -> Self {
+        self.config.wav = wav;
+        self
+    }
+
+    /// Opus bitrate for audio conversion
+    pub fn opus(mut self, opus: OpusOptions) -> Self {
+        self.config.opus = opus;
+        self
+    }
+
+    /// Page converted from multi-page TIFF sources
+    pub fn tiff(mut self, tiff: TiffOptions) -> Self {
+        self.config.tiff = tiff;
+        self
+    }
+
+    /// Which image of ICO sources is converted
+    pub fn ico(mut self, ico: IcoOptions) -> Self {
+        self.config.ico = ico;
+        self
+    }
+
+    /// Cut leading/trailing silence from WAV and MP3
+    pub fn trim_silence(mut self, trim: SilenceTrim) -> Self {
+        self.config.trim_silence = Some(trim);
+        self
+    }
+
+    /// Start from an existing configuration instead of the defaults.
+    pub fn config(mut self, config: ProcessingConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Run batches in the caller's pool (or a dedicated one from
+    /// [`Parallelism::threads`]) instead of rayon's global pool. Takes
+    /// precedence over the config's `jobs` and `low_priority`.
+    #[cfg(feature = "parallel")]
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Report each file's start, stages and result, and batch totals, to `sink`
+    /// (see [`Pipeline::set_progress_sink`])
+    pub fn progress_sink(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(sink);
+        self
+    }
+
+    /// Validate the configuration and register the built-in processors.
+    pub fn build(self) -> Result<Preparer, ProcessingError> {
+        self.config.validate()?;
+
+        #[allow(unused_mut)]
+        let mut pipeline = Pipeline::with_default_processors();
+        if let Some(sink) = self.progress {
+            pipeline.set_progress_sink(sink);
+        }
+        #[cfg(feature = "parallel")]
+        match (self.parallelism, Parallelism::from_config(&self.config)?) {
+            (Parallelism::Global, Some(configured)) => pipeline.set_parallelism(configured),
+            (parallelism, _) => pipeline.set_parallelism(parallelism),
+        }
+
+        Ok(Preparer {
+            pipeline: Arc::new(pipeline),
+            config: self.config,
+        })
+    }
+}
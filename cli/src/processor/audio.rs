@@ -0,0 +1,244 @@
+//! Audio conversion done by ffmpeg: lossless between WAV and FLAC, lossy from
+//! WAV, FLAC or MP3 to Opus.
+
+#[cfg(feature = "ffmpeg")]
+use std::path::Path;
+
+use crate::config::ProcessingConfig;
+#[cfg(feature = "ffmpeg")]
+use crate::config::StripMode;
+use crate::error::ProcessingError;
+#[cfg(feature = "ffmpeg")]
+use crate::ffmpeg::{self, ensure_encoder, read_ffmpeg_result, FfmpegTempFiles};
+use crate::format::Format;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Tags kept by `StripMode::Safe`, under ffmpeg's generic names (WAV `INFO`
+/// and FLAC Vorbis comments both map onto these)
+#[cfg(feature = "ffmpeg")]
+const SAFE_TAGS: [&str; 6] = ["title", "artist", "album", "date", "genre", "track"];
+
+/// Sample layout of an uncompressed or losslessly compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// IEEE float samples instead of integers
+    pub float: bool,
+    /// Plain integer or float samples; false for ADPCM, µ-law and other WAV codecs
+    pub pcm: bool,
+}
+
+/// Read the `fmt ` chunk of a RIFF/WAVE file
+pub fn wav_format(data: &[u8]) -> Option<PcmFormat> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        if &data[pos..pos + 4] == b"fmt " {
+            let body = data.get(pos + 8..pos + 8 + size)?;
+            if body.len() < 16 {
+                return None;
+            }
+            let mut tag = u16::from_le_bytes([body[0], body[1]]);
+            // WAVE_FORMAT_EXTENSIBLE keeps the real tag in the first bytes of the SubFormat GUID
+            if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+                tag = u16::from_le_bytes([body[24], body[25]]);
+            }
+            return Some(PcmFormat {
+                channels: u16::from_le_bytes([body[2], body[3]]),
+                sample_rate: u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+                bits_per_sample: u16::from_le_bytes([body[14], body[15]]),
+                float: tag == WAVE_FORMAT_IEEE_FLOAT,
+                pcm: tag == WAVE_FORMAT_PCM || tag == WAVE_FORMAT_IEEE_FLOAT,
+            });
+        }
+        // Chunks are padded to an even length
+        pos = pos.checked_add(8 + size + (size & 1))?;
+    }
+    None
+}
+
+/// Read the STREAMINFO block of a FLAC file
+pub fn flac_format(data: &[u8]) -> Option<PcmFormat> {
+    // STREAMINFO is always the first metadata block
+    if !data.starts_with(b"fLaC") || data.get(4)? & 0x7F != 0 {
+        return None;
+    }
+    let info = data.get(8..26)?;
+    // Bytes 10..14: 20 bits sample rate, 3 bits channels - 1, 5 bits bits per sample - 1
+    let sample_rate = (u32::from(info[10]) << 12) | (u32::from(info[11]) << 4) | (u32::from(info[12]) >> 4);
+    Some(PcmFormat {
+        channels: u16::from((info[12] >> 1) & 0x07) + 1,
+        sample_rate,
+        bits_per_sample: ((u16::from(info[12] & 0x01) << 4) | u16::from(info[13] >> 4)) + 1,
+        float: false,
+        pcm: true,
+    })
+}
+
+/// Convert WAV to FLAC (compression level 8), FLAC back to PCM WAV at the
+/// source bit depth, or WAV/FLAC/MP3 to Opus at `config.opus.bitrate`. Tags
+/// follow `config.strip`; embedded pictures are dropped since WAV cannot carry them.
+#[cfg(feature = "ffmpeg")]
+pub fn convert_audio(input: &[u8], target: Format, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let (source, codec_args) = match target {
+        Format::Flac => {
+            let pcm = wav_format(input).ok_or_else(|| corrupt("wav", "no fmt chunk"))?;
+            if pcm.float {
+                return Err(ProcessingError::UnsupportedFormat(
+                    "floating-point WAV cannot be stored losslessly as FLAC".to_string(),
+                ));
+            }
+            ensure_encoder("flac")?;
+            (Format::Wav, ["-c:a", "flac", "-compression_level", "8"].map(String::from).to_vec())
+        }
+        Format::Wav => {
+            let pcm = flac_format(input).ok_or_else(|| corrupt("flac", "no STREAMINFO block"))?;
+            (Format::Flac, vec!["-c:a".to_string(), pcm_codec(pcm.bits_per_sample).to_string()])
+        }
+        Format::Opus => {
+            let source = Format::from_bytes(input)
+                .filter(|format| Format::OPUS_SOURCES.contains(format))
+                .ok_or_else(|| ProcessingError::UnsupportedFormat("only WAV, FLAC and MP3 convert to Opus".into()))?;
+            ensure_encoder("libopus")?;
+            (source, opus_args(config.opus.bitrate))
+        }
+        other => {
+            return Err(ProcessingError::UnsupportedFormat(format!(
+                "cannot convert audio to {}",
+                other.as_str()
+            )))
+        }
+    };
+
+    let temp = FfmpegTempFiles::with_extensions(source.extension(), target.extension());
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0:a"]);
+    cmd.args(codec_args);
+    cmd.args(metadata_args(&temp.input, config.strip));
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    read_ffmpeg_result(output, &temp, input.len())
+}
+
+/// Without the `ffmpeg` feature there is nothing to convert audio with
+#[cfg(not(feature = "ffmpeg"))]
+pub fn convert_audio(_input: &[u8], _target: Format, _config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// libopus arguments for VBR at `bitrate` kbit/s
+#[cfg(feature = "ffmpeg")]
+fn opus_args(bitrate: u32) -> Vec<String> {
+    // The VoIP mode keeps speech intelligible at the low bitrates it is converted at
+    let application = if bitrate <= 32 { "voip" } else { "audio" };
+    let mut args = ["-c:a", "libopus", "-b:a"].map(String::from).to_vec();
+    args.push(format!("{}k", bitrate));
+    args.extend(["-vbr", "on", "-application", application].map(String::from));
+    args
+}
+
+/// Smallest PCM WAV codec that holds `bits` without rounding (8-bit WAV is unsigned)
+#[cfg(feature = "ffmpeg")]
+pub(crate) fn pcm_codec(bits: u16) -> &'static str {
+    match bits {
+        0..=8 => "pcm_u8",
+        9..=16 => "pcm_s16le",
+        17..=24 => "pcm_s24le",
+        _ => "pcm_s32le",
+    }
+}
+
+/// Tag handling for `strip`. Safe mode re-adds the basic tags ffprobe finds;
+/// without ffprobe it drops them all.
+#[cfg(feature = "ffmpeg")]
+fn metadata_args(input: &Path, strip: StripMode) -> Vec<String> {
+    match strip {
+        StripMode::None => Vec::new(),
+        StripMode::All => ["-map_metadata", "-1", "-fflags", "+bitexact"].map(String::from).to_vec(),
+        StripMode::Safe => {
+            let mut args = vec!["-map_metadata".to_string(), "-1".to_string()];
+            match ffmpeg::ffprobe(input) {
+                Ok(info) => {
+                    for (key, value) in &info.tags {
+                        let key = key.to_ascii_lowercase();
+                        if SAFE_TAGS.contains(&key.as_str()) {
+                            args.push("-metadata".to_string());
+                            args.push(format!("{}={}", key, value));
+                        }
+                    }
+                }
+                Err(e) => log::debug!("Could not read tags, dropping them all: {}", e),
+            }
+            args
+        }
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+fn corrupt(format: &str, reason: &str) -> ProcessingError {
+    ProcessingError::CorruptInput {
+        format: format.to_string(),
+        offset: None,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_pcm_formats() {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        wav.extend_from_slice(b"fmt \x10\0\0\0");
+        wav.extend_from_slice(&[1, 0, 2, 0]);
+        wav.extend_from_slice(&96_000u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 6]);
+        wav.extend_from_slice(&24u16.to_le_bytes());
+        let format = wav_format(&wav).unwrap();
+        assert_eq!((format.channels, format.sample_rate, format.bits_per_sample), (2, 96_000, 24));
+        assert!(format.pcm && !format.float);
+
+        // 44.1 kHz, stereo, 16-bit
+        let mut flac = b"fLaC\x80\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        flac.extend_from_slice(&[0x0A, 0xC4, 0x42, 0xF0, 0, 0, 0, 0]);
+        let format = flac_format(&flac).unwrap();
+        assert_eq!((format.channels, format.sample_rate, format.bits_per_sample), (2, 44_100, 16));
+        assert_eq!(flac_format(b"fLaC"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_opus_args() {
+        assert_eq!(opus_args(96), ["-c:a", "libopus", "-b:a", "96k", "-vbr", "on", "-application", "audio"]);
+        // Speech bitrates switch to the VoIP mode
+        assert_eq!(opus_args(32)[7], "voip");
+        assert_eq!(opus_args(33)[7], "audio");
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_opus_rejects_other_sources() {
+        let mut png = Vec::new();
+        image::GrayImage::new(2, 2).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let err = convert_audio(&png, Format::Opus, &ProcessingConfig::default()).unwrap_err();
+        assert!(matches!(err, ProcessingError::UnsupportedFormat(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,174 @@
+use image::DynamicImage;
+
+use crate::config::{ProcessingConfig, StripMode};
+use crate::converter::encode_image_with_quality;
+use crate::error::ProcessingError;
+use crate::explain;
+use crate::format::Format;
+pub use crate::heif::dimensions;
+use crate::limits::{decode_image, Limits};
+use crate::outcome::ProcessOutcome;
+use crate::processor::{is_ffmpeg_available, Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
+
+/// Re-encodes AVIF with rav1e (through ravif). Decoding goes through ffmpeg, so
+/// without it AVIF files fail; the new file is kept only if smaller.
+pub struct AvifProcessor;
+
+impl ImageProcessor for AvifProcessor {
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Avif]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: true,
+            needs_ffmpeg: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let dimensions = dimensions(input);
+        // Every re-encode is lossy, and the container is not rewritten on its own
+        if config.no_lossy {
+            let mut outcome = ProcessOutcome::unchanged(input, Format::Avif);
+            outcome.dimensions = dimensions;
+            outcome.warnings.push("AVIF is only re-encoded lossily; --no-lossy leaves it as is".to_string());
+            return Ok(outcome);
+        }
+
+        progress.start(Stage::Decode);
+        let img = decode_image(input, Some(image::ImageFormat::Avif), &config.limits)?;
+        progress.finish(Stage::Decode);
+
+        progress.start(Stage::Encode);
+        let (data, quality) = encode_image_with_quality(&img, Format::Avif, config)?;
+        progress.finish(Stage::Encode);
+
+        let mut outcome = if data.len() < input.len() {
+            ProcessOutcome::new(data, Format::Avif)
+        } else {
+            log::debug!("Re-encoded AVIF is not smaller ({} >= {} bytes), keeping the input", data.len(), input.len());
+            ProcessOutcome::unchanged(input, Format::Avif)
+        };
+        if !outcome.is_unchanged() {
+            outcome.quality = quality;
+            if config.strip == StripMode::None {
+                outcome.warnings.push("re-encoding AVIF drops Exif/XMP even with --strip none".to_string());
+            }
+        }
+        outcome.dimensions = dimensions.or(Some((img.width(), img.height())));
+        Ok(outcome)
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        if config.no_lossy {
+            return ProcessorPlan::new("none: AVIF is only re-encoded lossily");
+        }
+        let mut plan = ProcessorPlan::new("decode with ffmpeg, re-encode with rav1e, kept if smaller");
+        plan.ffmpeg.push("decoding");
+        plan.setting("quality", explain::quality(config, Format::Avif));
+        plan.setting("speed", config.speed);
+        plan.skip = match check_limits(input, &config.limits) {
+            Err(e) => Some(e.to_string()),
+            Ok(()) if !is_ffmpeg_available() => Some("ffmpeg not found, AVIF cannot be decoded".to_string()),
+            Ok(()) => None,
+        };
+        plan
+    }
+}
+
+/// Check the input size and the `ispe` dimensions against `limits` before decoding
+fn check_limits(input: &[u8], limits: &Limits) -> Result<(), ProcessingError> {
+    limits.check_input(input.len())?;
+    match dimensions(input) {
+        Some((width, height)) => limits.check_dimensions(width, height),
+        None => Ok(()),
+    }
+}
+
+/// Encode with rav1e at `config.quality_for(Avif)`, or at 100 with `no_lossy`
+/// (rav1e has no lossless mode; 100 is the closest). `config.speed` maps straight
+/// onto rav1e's presets, which use the same 1 (slowest/best) to 10 scale; alpha
+/// is encoded at the same quality and left out when the image is opaque.
+pub(crate) fn encode_avif(img: &DynamicImage, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let pixels: Vec<ravif::RGBA8> =
+        rgba.as_raw().chunks_exact(4).map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3])).collect();
+
+    let quality = if config.no_lossy { 100 } else { config.quality_for(Format::Avif).clamp(1, 100) } as f32;
+    let encoded = ravif::Encoder::new()
+        .with_quality(quality)
+        .with_alpha_quality(quality)
+        .with_speed(config.speed.clamp(1, 10) as u8)
+        .encode_rgba(ravif::Img::new(&pixels[..], width as usize, height as usize))
+        .map_err(|e| ProcessingError::Encode(format!("Failed to encode AVIF: {}", e)))?;
+    Ok(encoded.avif_file)
+}
+
+/// Decode the primary image with ffmpeg (its AV1 decoders), after checking the
+/// `ispe` dimensions against `limits`. Image sequences give their first frame.
+#[cfg(feature = "ffmpeg")]
+pub(crate) fn decode_avif(input: &[u8], limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    use crate::ffmpeg::{self, read_ffmpeg_result, FfmpegTempFiles};
+
+    check_limits(input, limits)?;
+
+    let temp = FfmpegTempFiles::with_extensions("avif", "png");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Decode(format!("Failed to write temp input: {}", e)))?;
+
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0:v:0", "-frames:v", "1", "-update", "1", "-c:v", "png"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    let png = read_ffmpeg_result(output, &temp, input.len())?;
+    decode_image(&png, Some(image::ImageFormat::Png), limits)
+}
+
+/// Without the `ffmpeg` feature there is no AV1 decoder
+#[cfg(not(feature = "ffmpeg"))]
+pub(crate) fn decode_avif(_input: &[u8], _limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_read_dimensions() {
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(40, 24, |x, y| {
+            image::Rgba([(x * 6) as u8, (y * 10) as u8, 90, if x < 20 { 255 } else { 128 }])
+        }));
+        let config = ProcessingConfig { speed: 10, ..ProcessingConfig::default() };
+        let avif = encode_avif(&img, &config).unwrap();
+
+        assert_eq!(Format::from_bytes(&avif), Some(Format::Avif));
+        assert_eq!(dimensions(&avif), Some((40, 24)));
+        assert_eq!(dimensions(b"\0\0\0\x10ftypavif\0\0\0\0"), None);
+    }
+}
@@ -0,0 +1,570 @@
+//! GIF: comment and application extensions are stripped per `StripMode` and
+//! repeated frames merged on the block stream, without decoding; with the
+//! `quantize` feature every frame is then re-quantized with its own palette.
+
+use std::borrow::Cow;
+
+use crate::config::{ProcessingConfig, StripMode};
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::outcome::ProcessOutcome;
+use crate::processor::{quantizes, Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
+
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2C;
+const TRAILER: u8 = 0x3B;
+const CONTROL: u8 = 0xF9;
+const COMMENT: u8 = 0xFE;
+const APPLICATION: u8 = 0xFF;
+
+/// Application extensions holding the loop count, always kept
+const LOOP_APPLICATIONS: [&[u8; 11]; 2] = [b"NETSCAPE2.0", b"ANIMEXTS1.0"];
+const ICC_APPLICATION: &[u8; 11] = b"ICCRGBG1012";
+const XMP_APPLICATION: &[u8; 11] = b"XMP DataXMP";
+
+pub struct GifProcessor;
+
+impl ImageProcessor for GifProcessor {
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Gif]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: cfg!(feature = "quantize"),
+            ..Capabilities::default()
+        }
+    }
+
+    fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let stream = parse_within_limits(input, config)?;
+        let dimensions = (u32::from(stream.width), u32::from(stream.height));
+
+        progress.start(Stage::Strip);
+        let stripped = rewrite(input, &stream, config.strip);
+        progress.finish(Stage::Strip);
+        let metadata_bytes = |data: &[u8]| metadata_extensions(data).iter().map(|(_, size)| size).sum::<u64>();
+        let metadata_removed = metadata_bytes(input).saturating_sub(metadata_bytes(&stripped));
+
+        let mut data = Cow::Borrowed(input);
+        if stripped != input {
+            data = Cow::Owned(stripped);
+        }
+        let mut quality = None;
+        if quantizes(config) {
+            let (quantized, used) = quantize_frames(&data, config, progress)?;
+            if quantized.len() < data.len() {
+                data = Cow::Owned(quantized);
+                quality = Some(used);
+            } else {
+                log::debug!("quantized GIF is not smaller, keeping the original frames");
+            }
+        }
+
+        let mut outcome = match data {
+            Cow::Borrowed(_) => ProcessOutcome::unchanged(input, Format::Gif),
+            Cow::Owned(data) => ProcessOutcome::new(data, Format::Gif),
+        };
+        outcome.metadata_removed = Some(metadata_removed);
+        outcome.quality = quality;
+        outcome.dimensions = Some(dimensions);
+        Ok(outcome)
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let mut plan = if quantizes(config) {
+            let mut plan = ProcessorPlan::new(
+                "strip extensions, merge repeated frames, then quantize each frame, kept if smaller",
+            );
+            plan.setting("quality", config.quality_for(Format::Gif));
+            plan.setting("max colors", config.gif.max_colors);
+            plan.setting("speed", config.speed);
+            plan
+        } else {
+            let mut plan = ProcessorPlan::new("strip extensions and merge repeated frames; frames copied untouched");
+            if !config.no_lossy {
+                plan.action.push_str(" (built without the quantize feature)");
+            }
+            plan
+        };
+        plan.skip = parse_within_limits(input, config).err().map(|e| e.to_string());
+        plan
+    }
+}
+
+/// The block stream of `input`, once its logical screen fits `config.limits`
+fn parse_within_limits(input: &[u8], config: &ProcessingConfig) -> Result<Stream, ProcessingError> {
+    let stream = Stream::parse(input)?;
+    config.limits.check_dimensions(u32::from(stream.width), u32::from(stream.height))?;
+    Ok(stream)
+}
+
+/// Remove comment and application extensions without touching the frames. The
+/// loop count stays; Safe mode also keeps the ICC profile. Not a GIF: an error.
+pub fn strip_gif_metadata(data: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
+    let stream = Stream::parse(data)?;
+    let mut output = data[..stream.header_end].to_vec();
+    for block in stream.blocks.iter().filter(|block| !block.is_metadata(strip_mode)) {
+        output.extend_from_slice(&data[block.start..block.end]);
+    }
+    output.push(TRAILER);
+    Ok(output)
+}
+
+/// Name and size of each extension [`strip_gif_metadata`] removes in All mode, in file order
+pub(crate) fn metadata_extensions(data: &[u8]) -> Vec<(String, u64)> {
+    let Ok(stream) = Stream::parse(data) else {
+        return Vec::new();
+    };
+    stream
+        .blocks
+        .iter()
+        .filter(|block| block.is_metadata(StripMode::All))
+        .map(|block| {
+            let name = match block.kind {
+                BlockKind::Application(id) if &id == XMP_APPLICATION => "XMP".to_string(),
+                BlockKind::Application(id) if &id == ICC_APPLICATION => "ICC profile".to_string(),
+                BlockKind::Application(id) => String::from_utf8_lossy(&id[..8]).trim_end().to_string(),
+                _ => "Comment".to_string(),
+            };
+            (name, (block.end - block.start) as u64)
+        })
+        .collect()
+}
+
+/// Strip per `strip_mode` and merge repeated frames: a frame with the same image
+/// data, position and control flags as the frame before it adds nothing when
+/// that frame is not disposed, so it is dropped and its delay added to the
+/// earlier frame's.
+fn rewrite(data: &[u8], stream: &Stream, strip_mode: StripMode) -> Vec<u8> {
+    let mut output = data[..stream.header_end].to_vec();
+    // The last frame written: its control block, image block and where its delay sits in `output`
+    let mut previous: Option<(Option<Control>, &[u8], Option<usize>)> = None;
+    let mut pending_control: Option<(Control, &[u8])> = None;
+    // A kept block came between the pending control block and its image
+    let mut interrupted = false;
+
+    for block in &stream.blocks {
+        let bytes = &data[block.start..block.end];
+        match block.kind {
+            // A control block belongs to the image after it
+            BlockKind::Control => {
+                if let Some((_, control_bytes)) = pending_control.take() {
+                    output.extend_from_slice(control_bytes);
+                }
+                match Control::parse(bytes) {
+                    Some(control) => pending_control = Some((control, bytes)),
+                    None => output.extend_from_slice(bytes),
+                }
+            }
+            BlockKind::Image => {
+                let control = pending_control.take();
+                let separated = std::mem::take(&mut interrupted);
+                let repeated = !separated
+                    && previous.as_ref().is_some_and(|&(previous_control, previous_image, _)| {
+                        previous_image == bytes
+                            && previous_control.is_none_or(|c| c.disposal <= 1)
+                            && previous_control.map(Control::without_delay) == control.map(|(c, _)| c.without_delay())
+                    });
+                if repeated {
+                    let (previous_control, _, delay_at) = previous.as_mut().expect("checked above");
+                    if let (Some(kept), Some(at), Some((control, _))) = (previous_control, delay_at, control) {
+                        kept.delay = kept.delay.saturating_add(control.delay);
+                        output[*at..*at + 2].copy_from_slice(&kept.delay.to_le_bytes());
+                    }
+                    continue;
+                }
+                let delay_at = control.map(|(_, control_bytes)| {
+                    output.extend_from_slice(control_bytes);
+                    output.len() - control_bytes.len() + 4
+                });
+                output.extend_from_slice(bytes);
+                previous = Some((control.map(|(c, _)| c), bytes, delay_at));
+            }
+            _ if block.is_metadata(strip_mode) => {}
+            kind => {
+                if let Some((_, control_bytes)) = pending_control.take() {
+                    output.extend_from_slice(control_bytes);
+                    interrupted = true;
+                }
+                // Plain text draws on the canvas too
+                if kind == BlockKind::OtherExtension {
+                    previous = None;
+                }
+                output.extend_from_slice(bytes);
+            }
+        }
+    }
+    if let Some((_, control_bytes)) = pending_control {
+        output.extend_from_slice(control_bytes);
+    }
+    output.push(TRAILER);
+    output
+}
+
+/// Decode every frame, quantize it to its own palette with imagequant and encode
+/// it again, keeping position, delay and disposal. Extensions kept by the strip
+/// (loop count, ICC profile, ...) are copied over. Also returns the quality used.
+#[cfg(feature = "quantize")]
+fn quantize_frames(
+    data: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, u8), ProcessingError> {
+    let quality = config.quality_for(Format::Gif);
+    let stream = Stream::parse(data)?;
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    if let Some(max) = config.limits.max_alloc.and_then(std::num::NonZeroU64::new) {
+        options.set_memory_limit(gif::MemoryLimit::Bytes(max));
+    }
+    let mut decoder =
+        options.read_info(std::io::Cursor::new(data)).map_err(|e| ProcessingError::Decode(e.to_string()))?;
+
+    progress.start(Stage::Quantize);
+    let mut frames = Vec::new();
+    let mut encoder = gif::Encoder::new(&mut frames, stream.width, stream.height, &[])
+        .map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| ProcessingError::Decode(e.to_string()))? {
+        let quantized = quantize_frame(frame, quality, config)?;
+        encoder.write_frame(&quantized).map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    }
+    drop(encoder);
+    progress.finish(Stage::Quantize);
+
+    // The encoder wrote its own header: keep it, then the extensions the strip
+    // kept, then the frames
+    let encoded = Stream::parse(&frames)?;
+    let mut output = frames[..encoded.header_end].to_vec();
+    for block in &stream.blocks {
+        if matches!(block.kind, BlockKind::Comment | BlockKind::Application(_)) {
+            output.extend_from_slice(&data[block.start..block.end]);
+        }
+    }
+    output.extend_from_slice(&frames[encoded.header_end..]);
+    Ok((output, quality))
+}
+
+/// Without imagequant GIFs are only stripped and merged
+#[cfg(not(feature = "quantize"))]
+fn quantize_frames(
+    _data: &[u8],
+    _config: &ProcessingConfig,
+    _progress: &Progress,
+) -> Result<(Vec<u8>, u8), ProcessingError> {
+    Err(ProcessingError::Quantize("built without the quantize feature".to_string()))
+}
+
+/// A frame decoded to RGBA as an indexed frame with its own palette, at the
+/// same position and with the same delay and disposal
+#[cfg(feature = "quantize")]
+fn quantize_frame(
+    frame: &gif::Frame,
+    quality: u8,
+    config: &ProcessingConfig,
+) -> Result<gif::Frame<'static>, ProcessingError> {
+    let (width, height) = (usize::from(frame.width), usize::from(frame.height));
+    let pixels: Vec<imagequant::RGBA> =
+        frame.buffer.chunks_exact(4).map(|p| imagequant::RGBA { r: p[0], g: p[1], b: p[2], a: p[3] }).collect();
+
+    let mut attr = imagequant::new();
+    attr.set_quality(0, quality).map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    attr.set_max_colors(config.gif.max_colors).map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    attr.set_speed(config.speed).map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    let mut image = attr
+        .new_image(pixels, width, height, 0.0)
+        .map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    let mut quantization = attr.quantize(&mut image).map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    let (palette, mut indices) =
+        quantization.remapped(&mut image).map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+
+    // GIF transparency is one palette entry: map every see-through color to it
+    let transparent = palette.iter().position(|color| color.a < 128).map(|index| index as u8);
+    if let Some(transparent) = transparent {
+        for index in indices.iter_mut() {
+            if palette[usize::from(*index)].a < 128 {
+                *index = transparent;
+            }
+        }
+    }
+    Ok(gif::Frame {
+        delay: frame.delay,
+        dispose: frame.dispose,
+        transparent,
+        needs_user_input: frame.needs_user_input,
+        top: frame.top,
+        left: frame.left,
+        width: frame.width,
+        height: frame.height,
+        interlaced: false,
+        palette: Some(palette.iter().flat_map(|color| [color.r, color.g, color.b]).collect()),
+        buffer: Cow::Owned(indices),
+    })
+}
+
+/// A still image as a one-frame GIF: quantized with imagequant when the
+/// `quantize` feature is on, else with the gif crate's NeuQuant
+pub fn encode_gif(img: &image::DynamicImage, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let (Ok(width), Ok(height)) = (u16::try_from(img.width()), u16::try_from(img.height())) else {
+        return Err(ProcessingError::LimitExceeded(format!(
+            "{}x{} does not fit a GIF (65535 pixels per side at most)",
+            img.width(),
+            img.height()
+        )));
+    };
+    #[cfg(feature = "quantize")]
+    let frame = {
+        let frame = gif::Frame { width, height, buffer: Cow::Owned(img.to_rgba8().into_raw()), ..Default::default() };
+        quantize_frame(&frame, config.quality_for(Format::Gif), config)?
+    };
+    // NeuQuant speed is 1–30
+    #[cfg(not(feature = "quantize"))]
+    let frame = {
+        let mut rgba = img.to_rgba8().into_raw();
+        gif::Frame::from_rgba_speed(width, height, &mut rgba, config.speed.clamp(1, 10) * 3)
+    };
+
+    let mut output = Vec::new();
+    let mut encoder =
+        gif::Encoder::new(&mut output, width, height, &[]).map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    encoder.write_frame(&frame).map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    drop(encoder);
+    Ok(output)
+}
+
+/// The logical screen and the blocks after it, up to the trailer
+struct Stream {
+    width: u16,
+    height: u16,
+    /// End of the header, logical screen descriptor and global color table
+    header_end: usize,
+    blocks: Vec<Block>,
+}
+
+struct Block {
+    kind: BlockKind,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Control,
+    Comment,
+    /// With its 11-byte identifier and authentication code
+    Application([u8; 11]),
+    /// Plain text and unknown extensions
+    OtherExtension,
+    Image,
+}
+
+impl Block {
+    fn is_metadata(&self, strip_mode: StripMode) -> bool {
+        match self.kind {
+            _ if strip_mode == StripMode::None => false,
+            BlockKind::Comment => true,
+            BlockKind::Application(id) if LOOP_APPLICATIONS.contains(&&id) => false,
+            BlockKind::Application(id) if &id == ICC_APPLICATION => strip_mode == StripMode::All,
+            BlockKind::Application(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Stream {
+    fn parse(data: &[u8]) -> Result<Self, ProcessingError> {
+        Self::blocks(data).ok_or_else(|| ProcessingError::CorruptInput {
+            format: "GIF".to_string(),
+            offset: None,
+            reason: "not a complete GIF block stream".to_string(),
+        })
+    }
+
+    fn blocks(data: &[u8]) -> Option<Self> {
+        if !matches!(data.get(..6)?, b"GIF87a" | b"GIF89a") {
+            return None;
+        }
+        let width = u16::from_le_bytes([*data.get(6)?, *data.get(7)?]);
+        let height = u16::from_le_bytes([*data.get(8)?, *data.get(9)?]);
+        let header_end = 13 + color_table_len(*data.get(10)?);
+
+        let mut blocks = Vec::new();
+        let mut pos = header_end;
+        loop {
+            let start = pos;
+            let kind = match *data.get(pos)? {
+                EXTENSION => {
+                    let label = *data.get(pos + 1)?;
+                    pos = skip_sub_blocks(data, pos + 2)?;
+                    match label {
+                        CONTROL => BlockKind::Control,
+                        COMMENT => BlockKind::Comment,
+                        APPLICATION if data.get(start + 2) == Some(&11) => {
+                            BlockKind::Application(data.get(start + 3..start + 14)?.try_into().ok()?)
+                        }
+                        _ => BlockKind::OtherExtension,
+                    }
+                }
+                IMAGE => {
+                    let flags = *data.get(pos + 9)?;
+                    // Descriptor, local color table, LZW minimum code size
+                    pos += 10 + color_table_len(flags) + 1;
+                    pos = skip_sub_blocks(data, pos)?;
+                    BlockKind::Image
+                }
+                TRAILER => return Some(Self { width, height, header_end, blocks }),
+                _ => return None,
+            };
+            blocks.push(Block { kind, start, end: pos });
+        }
+    }
+}
+
+/// Bytes of the color table a descriptor's flags announce
+fn color_table_len(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Position after the data sub-blocks starting at `pos` and their terminator
+fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = usize::from(*data.get(pos)?);
+        pos += 1 + len;
+        if len == 0 {
+            return (pos <= data.len()).then_some(pos);
+        }
+    }
+}
+
+/// Graphic control extension fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Control {
+    /// Disposal method: 0 and 1 leave the frame on the canvas
+    disposal: u8,
+    /// User input and transparency flags
+    flags: u8,
+    delay: u16,
+    transparent: u8,
+}
+
+impl Control {
+    /// The 8-byte block: introducer, label, size 4, packed, delay, transparent index, terminator
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let &[_, _, 4, packed, delay_low, delay_high, transparent, 0] = bytes else {
+            return None;
+        };
+        let delay = u16::from_le_bytes([delay_low, delay_high]);
+        Some(Self { disposal: packed >> 2 & 0x07, flags: packed & 0x03, delay, transparent })
+    }
+
+    fn without_delay(self) -> Self {
+        Self { delay: 0, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Two-frame GIF of a 4x4 image, the second frame identical to the first, with a comment
+    fn animation() -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+            let mut encoder = gif::Encoder::new(&mut data, 4, 4, &palette).unwrap();
+            encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+            let indices: Vec<u8> = (0..16).map(|i| (i % 3) as u8).collect();
+            for _ in 0..2 {
+                let frame =
+                    gif::Frame { width: 4, height: 4, delay: 5, buffer: Cow::Borrowed(&indices), ..Default::default() };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        // Comment before the trailer
+        data.pop();
+        data.extend_from_slice(&[EXTENSION, COMMENT, 5]);
+        data.extend_from_slice(b"hello");
+        data.extend_from_slice(&[0, TRAILER]);
+        data
+    }
+
+    #[test]
+    fn test_encode_gif() {
+        let alpha = |x: u32| if x < 4 { 0 } else { 255 };
+        let image = image::RgbaImage::from_fn(24, 8, |x, _| image::Rgba([(x * 10) as u8, 80, 160, alpha(x)]));
+        let gif = encode_gif(&image::DynamicImage::ImageRgba8(image), &ProcessingConfig::default()).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(Cursor::new(&gif)).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (24, 8));
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        // The see-through columns stay see-through
+        assert_eq!(frame.buffer[3], 0);
+        assert_eq!(frame.buffer[23 * 4 + 3], 255);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strip_and_merge() {
+        let gif = animation();
+        assert_eq!(metadata_extensions(&gif), [("Comment".to_string(), 9)]);
+
+        let stripped = strip_gif_metadata(&gif, StripMode::All).unwrap();
+        assert_eq!(stripped.len(), gif.len() - 9);
+        assert_eq!(strip_gif_metadata(&gif, StripMode::None).unwrap(), gif);
+
+        let stream = Stream::parse(&gif).unwrap();
+        let merged = rewrite(&gif, &stream, StripMode::All);
+        let frames = Stream::parse(&merged).unwrap().blocks;
+        assert_eq!(frames.iter().filter(|block| block.kind == BlockKind::Image).count(), 1);
+        assert!(frames.iter().any(|block| block.kind == BlockKind::Application(*b"NETSCAPE2.0")));
+
+        let mut decoder = gif::DecodeOptions::new().read_info(Cursor::new(&merged)).unwrap();
+        assert_eq!(decoder.read_next_frame().unwrap().unwrap().delay, 10);
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[cfg(feature = "quantize")]
+    #[test]
+    fn test_quantize_frames() {
+        let config = ProcessingConfig { quality: 60, ..ProcessingConfig::default() };
+        let (quantized, quality) = quantize_frames(&animation(), &config, &Progress::none()).unwrap();
+        assert_eq!(quality, 60);
+
+        let mut decoder = gif::DecodeOptions::new().read_info(Cursor::new(&quantized)).unwrap();
+        assert_eq!(decoder.repeat(), gif::Repeat::Infinite);
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height, frame.delay), (4, 4, 5));
+            frames += 1;
+        }
+        assert_eq!(frames, 2);
+    }
+}
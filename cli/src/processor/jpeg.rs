@@ -0,0 +1,906 @@
+//! JPEG processing on the encoded stream, without decoding pixels: estimating
+//! the quality a file was saved at, dropping metadata segments and rebuilding
+//! the Huffman tables of baseline files for their own symbols. Also `inspect`
+//! for JPEG.
+
+use std::borrow::Cow;
+
+use crate::config::{ProcessingConfig, StripMode};
+use crate::converter::{auto_orient, encode_image};
+use crate::error::ProcessingError;
+use crate::exif;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::limits::{check_header, decode_image};
+use crate::outcome::ProcessOutcome;
+use crate::processor::{ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
+use crate::tune::with_quality;
+
+/// Luminance quantization table from Annex K of the JPEG standard (quality 50)
+const STANDARD_LUMA: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51,
+    87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const DHT: u8 = 0xC4;
+const RST0: u8 = 0xD0;
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DQT: u8 = 0xDB;
+const DRI: u8 = 0xDD;
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const APP14: u8 = 0xEE;
+const COM: u8 = 0xFE;
+
+/// A marker segment before the scan data: `start..end` covers the marker and its payload
+struct Segment {
+    marker: u8,
+    start: usize,
+    end: usize,
+}
+
+/// Marker segments up to (not including) the first SOS. None if `data` is not a JPEG.
+fn header_segments(data: &[u8]) -> Option<(Vec<Segment>, usize)> {
+    if data.get(..2)? != [0xFF, SOI] {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        // Fill bytes before a marker
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == SOS {
+            return Some((segments, pos));
+        }
+        let length = usize::from(u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]));
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return None;
+        }
+        segments.push(Segment { marker, start: pos, end });
+        pos = end;
+    }
+}
+
+/// The quality 1–100 an IJG-compatible encoder (libjpeg, most cameras' "quality"
+/// settings, the image crate) would have used for the luminance quantization
+/// table. None without a JPEG header or a luminance table.
+pub fn estimate_quality(data: &[u8]) -> Option<u8> {
+    let (segments, _) = header_segments(data)?;
+    let luma = segments.iter().filter(|s| s.marker == DQT).find_map(|s| luma_table_sum(&data[s.start + 4..s.end]))?;
+
+    // Table sums fall as quality rises; pick the closest
+    (1..=100u8).min_by_key(|&quality| scaled_table_sum(quality).abs_diff(luma))
+}
+
+/// Sum of the entries of table 0 in a DQT payload, which may hold several tables
+fn luma_table_sum(mut payload: &[u8]) -> Option<u32> {
+    while let Some((&info, rest)) = payload.split_first() {
+        let (precision, id) = (info >> 4, info & 0x0F);
+        let size = if precision == 0 { 64 } else { 128 };
+        let table = rest.get(..size)?;
+        if id == 0 {
+            return Some(if precision == 0 {
+                table.iter().map(|&q| u32::from(q)).sum()
+            } else {
+                table.chunks_exact(2).map(|q| u32::from(u16::from_be_bytes([q[0], q[1]]))).sum()
+            });
+        }
+        payload = &rest[size..];
+    }
+    None
+}
+
+/// Sum of the standard luminance table scaled to `quality` the way libjpeg does
+fn scaled_table_sum(quality: u8) -> u32 {
+    let scale = if quality < 50 { 5000 / u32::from(quality) } else { 200 - 2 * u32::from(quality) };
+    STANDARD_LUMA.iter().map(|&q| ((u32::from(q) * scale + 50) / 100).clamp(1, 255)).sum()
+}
+
+/// TIFF data of the EXIF (APP1 `Exif\0\0`) segment, if any
+pub(crate) fn exif_payload(data: &[u8]) -> Option<&[u8]> {
+    let (segments, _) = header_segments(data)?;
+    segments
+        .iter()
+        .filter(|s| s.marker == APP1)
+        .find_map(|s| data[s.start + 4..s.end].strip_prefix(b"Exif\0\0"))
+}
+
+/// Name and size of each segment [`strip_jpeg_metadata`] removes in All mode, in file order
+pub(crate) fn metadata_segments(data: &[u8]) -> Vec<(String, u64)> {
+    let Some((segments, _)) = header_segments(data) else {
+        return Vec::new();
+    };
+    segments
+        .iter()
+        .filter(|s| matches!(s.marker, 0xE1..=0xED | 0xEF | COM))
+        .map(|s| (segment_name(s.marker, &data[s.start + 4..s.end]), (s.end - s.start) as u64))
+        .collect()
+}
+
+/// Display name of a marker segment, by its payload for APPn segments
+fn segment_name(marker: u8, payload: &[u8]) -> String {
+    match marker {
+        APP0 if payload.starts_with(b"JFIF\0") => "JFIF".to_string(),
+        APP1 if payload.starts_with(b"Exif\0") => "EXIF".to_string(),
+        APP1 if payload.starts_with(b"http://ns.adobe.com/xap/") => "XMP".to_string(),
+        APP2 if payload.starts_with(b"ICC_PROFILE\0") => "ICC profile".to_string(),
+        0xED => "IPTC".to_string(),
+        APP14 if payload.starts_with(b"Adobe") => "Adobe".to_string(),
+        COM => "Comment".to_string(),
+        APP0..=0xEF => format!("APP{}", marker - APP0),
+        DHT => "DHT".to_string(),
+        DQT => "DQT".to_string(),
+        DRI => "DRI".to_string(),
+        0xC0..=0xCF => format!("SOF{}", marker - SOF0),
+        marker => format!("0x{:02X}", marker),
+    }
+}
+
+/// `inspect` for JPEG: dimensions and estimated quality, every marker segment
+/// before the image data, then the EXIF tags with camera, dates and position
+pub fn inspect_jpeg(data: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Jpeg, data.len());
+    match image::ImageReader::with_format(std::io::Cursor::new(data), image::ImageFormat::Jpeg).into_dimensions() {
+        Ok((width, height)) => report.field("Image dimensions", format!("{} x {} pixels", width, height)),
+        Err(e) => report.errors.push(format!("Could not read JPEG dimensions: {}", e)),
+    }
+    if let Some(quality) = estimate_quality(data) {
+        report.field("Estimated quality", quality);
+    }
+    let Some((segments, _)) = header_segments(data) else {
+        report.errors.push("No valid marker segments before the image data".to_string());
+        return Ok(report);
+    };
+
+    let mut section = Section::new("JPEG Segments");
+    for segment in &segments {
+        let payload = &data[segment.start + 4..segment.end];
+        let mut entry = Entry::new(segment_name(segment.marker, payload));
+        entry.size = Some((segment.end - segment.start) as u64);
+        // What `--strip safe` does; image structure segments are not metadata
+        entry.class = match segment.marker {
+            APP1 if payload.starts_with(b"Exif\0\0") => Some(EntryClass::Safe),
+            APP2 => Some(EntryClass::Safe),
+            0xE1..=0xED | 0xEF | COM => Some(EntryClass::Unsafe),
+            _ => None,
+        };
+        if entry.class == Some(EntryClass::Safe) && segment.marker == APP1 {
+            entry.description = Some("GPS and serial-number tags removed by --strip safe".to_string());
+        }
+        section.entries.push(entry);
+    }
+    let metadata = section.entries.iter().filter(|entry| entry.class.is_some()).count();
+    section.summary = Some(format!("{} segments, {} metadata", segments.len(), metadata));
+    report.sections.push(section);
+
+    if let Some(tiff) = exif_payload(data) {
+        match exif::exif_section(tiff) {
+            Some(section) => report.sections.push(section),
+            None => report.errors.push("Could not read the EXIF block".to_string()),
+        }
+    }
+    Ok(report)
+}
+
+/// Strips metadata segments and rebuilds the Huffman tables of baseline JPEGs,
+/// leaving the compressed coefficients (and so the pixels) exactly as they were
+pub struct JpegProcessor;
+
+impl ImageProcessor for JpegProcessor {
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Jpeg]
+    }
+
+    fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let mut data = Cow::Borrowed(input);
+        if config.strip != StripMode::None {
+            progress.start(Stage::Strip);
+            data = Cow::Owned(strip_jpeg_metadata(input, config.strip)?);
+            progress.finish(Stage::Strip);
+        }
+        let metadata_removed = (input.len() as u64).saturating_sub(data.len() as u64);
+
+        let mut warnings = Vec::new();
+        let mut quality = None;
+        if reorients(input, config) {
+            if config.no_lossy {
+                warnings.push("EXIF orientation dropped unapplied: rotating the pixels is lossy".to_string());
+            } else {
+                let (rotated, used) = reorient(input, &data, config, progress)?;
+                data = Cow::Owned(rotated);
+                quality = Some(used);
+            }
+        }
+
+        progress.start(Stage::Optimize);
+        if let Some(optimized) = optimize_huffman(&data) {
+            data = Cow::Owned(optimized);
+        }
+        progress.finish(Stage::Optimize);
+
+        let mut outcome = match data {
+            Cow::Owned(data) if data != input => ProcessOutcome::new(data, Format::Jpeg),
+            _ => ProcessOutcome::unchanged(input, Format::Jpeg),
+        };
+        outcome.metadata_removed = Some(metadata_removed);
+        outcome.quality = quality;
+        outcome.warnings = warnings;
+        // From the output: a rotated image swaps them
+        let reader = image::ImageReader::with_format(std::io::Cursor::new(&*outcome.data), image::ImageFormat::Jpeg);
+        outcome.dimensions = reader.into_dimensions().ok();
+        Ok(outcome)
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        if reorients(input, config) && !config.no_lossy {
+            let mut plan = ProcessorPlan::new(
+                "strip metadata segments, then re-encode upright (applying the EXIF orientation) and rebuild \
+                 the Huffman tables",
+            );
+            let quality = estimate_quality(input).map_or_else(
+                || config.quality_for(Format::Jpeg).to_string(),
+                |quality| format!("{} (the input's)", quality),
+            );
+            plan.setting("quality", quality);
+            let limits = check_header(input, Some(image::ImageFormat::Jpeg), &config.limits);
+            plan.skip = limits.err().map(|e| e.to_string());
+            return plan;
+        }
+
+        let stripped = match config.strip {
+            StripMode::None => Some(Cow::Borrowed(input)),
+            strip => strip_jpeg_metadata(input, strip).ok().map(Cow::Owned),
+        };
+        let mut plan = if stripped.is_some_and(|data| optimize_huffman(&data).is_some()) {
+            ProcessorPlan::new("strip metadata segments, then rebuild the Huffman tables losslessly")
+        } else {
+            ProcessorPlan::new("strip metadata segments; Huffman tables kept (not baseline, or already optimal)")
+        };
+        if reorients(input, config) {
+            plan.action.push_str("; EXIF orientation dropped unapplied (rotating the pixels is lossy)");
+        }
+        plan
+    }
+}
+
+fn is_upright(data: &[u8]) -> bool {
+    exif::orientation(data, Format::Jpeg).is_none_or(|orientation| orientation == 1)
+}
+
+/// Whether stripping `input` drops an orientation tag that has to be applied to
+/// the pixels first (unless `no_lossy`, which leaves the image as stored)
+fn reorients(input: &[u8], config: &ProcessingConfig) -> bool {
+    config.strip == StripMode::All && config.auto_orient && !is_upright(input)
+}
+
+/// `stripped`, which lost the orientation tag of `input`, decoded, turned the
+/// way the tag said and re-encoded at the quality `input` was saved at (the
+/// configured one when that can't be told). Also returns that quality.
+fn reorient(
+    input: &[u8],
+    stripped: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, u8), ProcessingError> {
+    progress.start(Stage::Decode);
+    let img = decode_image(stripped, Some(image::ImageFormat::Jpeg), &config.limits)?;
+    let img = auto_orient(img, input, config);
+    progress.finish(Stage::Decode);
+
+    let quality = estimate_quality(input).unwrap_or_else(|| config.quality_for(Format::Jpeg));
+    log::debug!("Re-encoding at quality ~{} to apply the EXIF orientation", quality);
+    progress.start(Stage::Encode);
+    let data = encode_image(&img, Format::Jpeg, &with_quality(config, Format::Jpeg, quality))?;
+    progress.finish(Stage::Encode);
+    Ok((data, quality))
+}
+
+/// Remove metadata segments without touching the compressed image: EXIF, XMP
+/// and other APPn segments plus comments. JFIF (APP0) and Adobe (APP14) stay
+/// because decoders need them for color. Safe mode keeps the ICC profile (APP2)
+/// and the EXIF block less its GPS directory, serial numbers and maker notes
+/// ([`exif::strip_private`]), so orientation and color space survive. An EXIF
+/// block that cannot be parsed is replaced by one holding only a non-upright
+/// orientation so the photo still displays the right way up. Not a JPEG: an error.
+pub fn strip_jpeg_metadata(data: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
+    let (segments, scan_start) = header_segments(data).ok_or_else(|| ProcessingError::CorruptInput {
+        format: "JPEG".to_string(),
+        offset: None,
+        reason: "no valid marker segments before the image data".to_string(),
+    })?;
+    if strip_mode == StripMode::None {
+        return Ok(data.to_vec());
+    }
+
+    let mut kept_exif = match strip_mode {
+        StripMode::Safe => safe_exif(data),
+        _ => None,
+    };
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    for segment in &segments {
+        let is_metadata = match segment.marker {
+            APP0 | APP14 => false,
+            APP2 => strip_mode == StripMode::All,
+            0xE1..=0xEF | COM => true,
+            _ => false,
+        };
+        if !is_metadata {
+            output.extend_from_slice(&data[segment.start..segment.end]);
+        } else if segment.marker == APP1 && data[segment.start + 4..segment.end].starts_with(b"Exif\0\0") {
+            if let Some(tiff) = kept_exif.take() {
+                output.extend_from_slice(&[0xFF, APP1]);
+                output.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+                output.extend_from_slice(b"Exif\0\0");
+                output.extend_from_slice(&tiff);
+            }
+        }
+    }
+    output.extend_from_slice(&data[scan_start..]);
+    Ok(output)
+}
+
+/// EXIF TIFF safe stripping writes back, if any
+fn safe_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let orientation = || exif::orientation(data, Format::Jpeg).filter(|&orientation| orientation != 1);
+    let tiff = exif_payload(data).and_then(exif::strip_private).or_else(|| orientation().map(exif::orientation_block))?;
+    // The segment length is 16 bits and counts itself and the `Exif\0\0` prefix
+    (tiff.len() + 8 <= usize::from(u16::MAX)).then_some(tiff)
+}
+
+/// Rewrite a baseline JPEG with Huffman tables built from its own symbol counts
+/// (what `jpegtran -optimize` does): the same coefficients in fewer bytes. The
+/// scan is decoded twice, once to count symbols and once to re-encode them, so
+/// memory stays at the size of the output. None for progressive, arithmetic-coded,
+/// multi-scan or damaged files, and when the result would not be smaller.
+pub fn optimize_huffman(data: &[u8]) -> Option<Vec<u8>> {
+    let (segments, sos) = header_segments(data)?;
+    let scan = Scan::parse(data, &segments, sos)?;
+
+    let mut counts = vec![[0u32; 257]; TABLES];
+    scan.decode(data, |event| {
+        if let ScanEvent::Symbol { table, symbol, .. } = event {
+            counts[table][usize::from(symbol)] += 1;
+        }
+    })?;
+    let tables: Vec<Option<HuffmanTable>> = counts
+        .iter()
+        .map(|counts| counts.iter().any(|&count| count > 0).then(|| HuffmanTable::optimal(counts)))
+        .collect();
+    let codes: Vec<[(u16, u8); 256]> =
+        tables.iter().map(|table| table.as_ref().map_or([(0, 0); 256], HuffmanTable::codes)).collect();
+
+    let mut writer = BitWriter::default();
+    scan.decode(data, |event| match event {
+        ScanEvent::Symbol { table, symbol, bits, length } => {
+            let (code, code_length) = codes[table][usize::from(symbol)];
+            writer.put(u32::from(code), code_length);
+            writer.put(bits, length);
+        }
+        ScanEvent::Restart(marker) => {
+            writer.pad();
+            writer.output.extend_from_slice(&[0xFF, marker]);
+        }
+    })?;
+    writer.pad();
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    for segment in segments.iter().filter(|s| s.marker != DHT) {
+        output.extend_from_slice(&data[segment.start..segment.end]);
+    }
+    // One DHT segment with every table the scan uses
+    let mut dht = Vec::new();
+    for (index, table) in tables.iter().enumerate() {
+        if let Some(table) = table {
+            dht.push(((index / 4) as u8) << 4 | (index % 4) as u8);
+            dht.extend_from_slice(&table.bits);
+            dht.extend_from_slice(&table.values);
+        }
+    }
+    output.extend_from_slice(&[0xFF, DHT]);
+    output.extend_from_slice(&(dht.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(&dht);
+    output.extend_from_slice(&data[sos..scan.data_start]);
+    output.extend_from_slice(&writer.output);
+    output.extend_from_slice(&[0xFF, EOI]);
+
+    (output.len() < data.len()).then_some(output)
+}
+
+/// DC tables 0–3, then AC tables 0–3
+const TABLES: usize = 8;
+
+/// A Huffman table as stored in DHT: the number of codes of each length 1–16,
+/// then the symbols in code order
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    bits: [u8; 16],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Code lengths from symbol counts, limited to 16 bits (Annex K.2 and K.3).
+    /// An extra symbol with count 1 takes the longest code and is dropped, so no
+    /// real code is all ones.
+    fn optimal(counts: &[u32; 257]) -> Self {
+        let mut freq: Vec<u64> = counts.iter().map(|&count| u64::from(count)).collect();
+        freq[256] = 1;
+        let mut code_size = [0usize; 257];
+        let mut others = [None::<usize>; 257];
+
+        // Least frequent symbol, the highest-numbered one on ties
+        let least = |freq: &[u64], skip: Option<usize>| {
+            (0..257).filter(|&i| freq[i] > 0 && Some(i) != skip).min_by_key(|&i| (freq[i], std::cmp::Reverse(i)))
+        };
+        while let Some(c1) = least(&freq, None) {
+            let Some(c2) = least(&freq, Some(c1)) else {
+                break;
+            };
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+            let mut c = c1;
+            code_size[c] += 1;
+            while let Some(next) = others[c] {
+                c = next;
+                code_size[c] += 1;
+            }
+            others[c] = Some(c2);
+            let mut c = c2;
+            code_size[c] += 1;
+            while let Some(next) = others[c] {
+                c = next;
+                code_size[c] += 1;
+            }
+        }
+
+        let mut bits = vec![0u32; 258];
+        for &size in code_size.iter().filter(|&&size| size > 0) {
+            bits[size] += 1;
+        }
+        // Move codes over 16 bits up the tree, pairing each with a shorter one
+        for i in (17..bits.len()).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        let longest = (1..=16).rev().find(|&i| bits[i] > 0).unwrap_or(1);
+        bits[longest] -= 1;
+
+        let mut values = Vec::new();
+        for size in 1..bits.len() {
+            values.extend((0..=255u8).filter(|&symbol| code_size[usize::from(symbol)] == size));
+        }
+        let mut table = Self { bits: [0; 16], values };
+        for (length, count) in table.bits.iter_mut().zip(&bits[1..=16]) {
+            *length = *count as u8;
+        }
+        table
+    }
+
+    /// Parse the tables of a DHT payload into `tables`
+    fn parse_segment(mut payload: &[u8], tables: &mut [Option<HuffmanTable>]) -> Option<()> {
+        while let Some((&info, rest)) = payload.split_first() {
+            let (class, id) = (usize::from(info >> 4), usize::from(info & 0x0F));
+            if class > 1 || id > 3 {
+                return None;
+            }
+            let bits: [u8; 16] = rest.get(..16)?.try_into().ok()?;
+            let count: usize = bits.iter().map(|&n| usize::from(n)).sum();
+            let values = rest.get(16..16 + count)?.to_vec();
+            tables[class * 4 + id] = Some(Self { bits, values });
+            payload = &rest[16 + count..];
+        }
+        Some(())
+    }
+
+    /// Canonical code and its length for each symbol (Annex C)
+    fn codes(&self) -> [(u16, u8); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut values = self.values.iter();
+        let mut code = 0u16;
+        for (length, &count) in (1..=16u8).zip(&self.bits) {
+            for &value in values.by_ref().take(usize::from(count)) {
+                codes[usize::from(value)] = (code, length);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    /// Tables for bit-by-bit decoding (Annex F.2.2.3). None if the lengths
+    /// describe more codes than fit.
+    fn decoder(&self) -> Option<HuffmanDecoder> {
+        let mut decoder = HuffmanDecoder { max_code: [-1; 17], offset: [0; 17], values: self.values.clone() };
+        let (mut code, mut index) = (0i32, 0i32);
+        for length in 1..=16 {
+            let count = i32::from(self.bits[length - 1]);
+            decoder.offset[length] = index - code;
+            code += count;
+            index += count;
+            if code > 1 << length {
+                return None;
+            }
+            if count > 0 {
+                decoder.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        Some(decoder)
+    }
+}
+
+struct HuffmanDecoder {
+    /// Largest code of each length, -1 when there is none
+    max_code: [i32; 17],
+    /// Index into `values` minus the first code of each length
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn decode(&self, reader: &mut BitReader) -> Option<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.bit()? as i32;
+            if code <= self.max_code[length] {
+                return self.values.get(usize::try_from(code + self.offset[length]).ok()?).copied();
+            }
+        }
+        None
+    }
+}
+
+/// What decoding the entropy-coded data produces: each Huffman symbol with the
+/// extra bits that follow it, and the restart markers between intervals
+enum ScanEvent {
+    Symbol { table: usize, symbol: u8, bits: u32, length: u8 },
+    Restart(u8),
+}
+
+/// The single scan of a baseline JPEG
+struct Scan {
+    /// Where the entropy-coded data starts, after the SOS header
+    data_start: usize,
+    mcus: usize,
+    /// DC and AC table index of each block in an MCU
+    blocks: Vec<(usize, usize)>,
+    decoders: Vec<Option<HuffmanDecoder>>,
+    restart_interval: usize,
+}
+
+impl Scan {
+    /// The scan layout from the header segments and the SOS at `sos`. None for
+    /// anything but a sequential Huffman-coded 8-bit frame.
+    fn parse(data: &[u8], segments: &[Segment], sos: usize) -> Option<Self> {
+        let mut tables = vec![None; TABLES];
+        let mut frame = None;
+        let mut restart_interval = 0;
+        for segment in segments {
+            let payload = &data[segment.start + 4..segment.end];
+            match segment.marker {
+                SOF0 | SOF1 => frame = Some(payload),
+                DHT => HuffmanTable::parse_segment(payload, &mut tables)?,
+                DRI => restart_interval = usize::from(u16::from_be_bytes(payload.get(..2)?.try_into().ok()?)),
+                // Progressive, lossless, hierarchical or arithmetic-coded
+                0xC2 | 0xC3 | 0xC5..=0xCF => return None,
+                _ => {}
+            }
+        }
+
+        // Frame: precision, height, width, then id, sampling factors and table per component
+        let frame = frame?;
+        let height = u16::from_be_bytes([*frame.get(1)?, *frame.get(2)?]);
+        let width = u16::from_be_bytes([*frame.get(3)?, *frame.get(4)?]);
+        if *frame.first()? != 8 || height == 0 || width == 0 {
+            return None;
+        }
+        let components: Vec<(u8, usize, usize)> = frame
+            .get(6..6 + 3 * usize::from(*frame.get(5)?))?
+            .chunks_exact(3)
+            .map(|c| (c[0], usize::from(c[1] >> 4), usize::from(c[1] & 0x0F)))
+            .collect();
+        if components.iter().any(|&(_, h, v)| !(1..=4).contains(&h) || !(1..=4).contains(&v)) {
+            return None;
+        }
+        let h_max = components.iter().map(|c| c.1).max()?;
+        let v_max = components.iter().map(|c| c.2).max()?;
+
+        // Scan header: components with their tables, then the spectral selection
+        let length = usize::from(u16::from_be_bytes([*data.get(sos + 2)?, *data.get(sos + 3)?]));
+        let header = data.get(sos + 4..sos + 2 + length)?;
+        let count = usize::from(*header.first()?);
+        if header.get(1 + 2 * count..)? != [0, 63, 0] {
+            return None;
+        }
+        let mut blocks = Vec::new();
+        let mut mcus = 0;
+        for selector in header[1..1 + 2 * count].chunks_exact(2) {
+            let &(_, h, v) = components.iter().find(|c| c.0 == selector[0])?;
+            let tables = (usize::from(selector[1] >> 4), 4 + usize::from(selector[1] & 0x0F));
+            if count == 1 {
+                // Non-interleaved: one block per MCU over the component's own size
+                let blocks_across = (usize::from(width) * h).div_ceil(h_max).div_ceil(8);
+                let blocks_down = (usize::from(height) * v).div_ceil(v_max).div_ceil(8);
+                mcus = blocks_across * blocks_down;
+                blocks.push(tables);
+            } else {
+                mcus = usize::from(width).div_ceil(8 * h_max) * usize::from(height).div_ceil(8 * v_max);
+                blocks.extend(std::iter::repeat_n(tables, h * v));
+            }
+        }
+        let defined = |index: usize| tables.get(index).is_some_and(Option::is_some);
+        if blocks.is_empty() || blocks.iter().any(|&(dc, ac)| dc > 3 || !defined(dc) || !defined(ac)) {
+            return None;
+        }
+
+        let decoders = tables.iter().map(|table| table.as_ref().and_then(HuffmanTable::decoder)).collect();
+        Some(Self { data_start: sos + 2 + length, mcus, blocks, decoders, restart_interval })
+    }
+
+    /// Decode every symbol of the scan into `emit`. None if the data is damaged
+    /// or anything but EOI follows the scan.
+    fn decode(&self, data: &[u8], mut emit: impl FnMut(ScanEvent)) -> Option<()> {
+        let mut reader = BitReader { data, pos: self.data_start, byte: 0, count: 0 };
+        for mcu in 0..self.mcus {
+            if self.restart_interval > 0 && mcu > 0 && mcu % self.restart_interval == 0 {
+                let expected = RST0 + ((mcu / self.restart_interval - 1) % 8) as u8;
+                if reader.marker()? != expected {
+                    return None;
+                }
+                emit(ScanEvent::Restart(expected));
+            }
+            for &(dc, ac) in &self.blocks {
+                let size = self.decoders[dc].as_ref()?.decode(&mut reader)?;
+                if size > 11 {
+                    return None;
+                }
+                emit(ScanEvent::Symbol { table: dc, symbol: size, bits: reader.bits(size)?, length: size });
+
+                let ac_decoder = self.decoders[ac].as_ref()?;
+                let mut k = 1;
+                while k < 64 {
+                    let symbol = ac_decoder.decode(&mut reader)?;
+                    let (run, size) = (symbol >> 4, symbol & 0x0F);
+                    emit(ScanEvent::Symbol { table: ac, symbol, bits: reader.bits(size)?, length: size });
+                    // End of block
+                    if size == 0 && run != 15 {
+                        break;
+                    }
+                    k += usize::from(run) + 1;
+                }
+                if k > 64 {
+                    return None;
+                }
+            }
+        }
+        (reader.marker()? == EOI && reader.pos == data.len()).then_some(())
+    }
+}
+
+/// Reads entropy-coded bits, removing the zero byte stuffed after each 0xFF
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    byte: u8,
+    /// Bits of `byte` not read yet
+    count: u8,
+}
+
+impl BitReader<'_> {
+    /// None at the end of the data or at a marker
+    fn bit(&mut self) -> Option<u32> {
+        if self.count == 0 {
+            self.byte = *self.data.get(self.pos)?;
+            if self.byte == 0xFF {
+                if *self.data.get(self.pos + 1)? != 0 {
+                    return None;
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Some(u32::from(self.byte >> self.count & 1))
+    }
+
+    fn bits(&mut self, length: u8) -> Option<u32> {
+        (0..length).try_fold(0, |value, _| Some(value << 1 | self.bit()?))
+    }
+
+    /// Skip the padding bits of the current byte and read the marker after
+    /// them, ignoring fill bytes
+    fn marker(&mut self) -> Option<u8> {
+        self.count = 0;
+        if *self.data.get(self.pos)? != 0xFF {
+            return None;
+        }
+        while self.data.get(self.pos + 1) == Some(&0xFF) {
+            self.pos += 1;
+        }
+        let marker = *self.data.get(self.pos + 1)?;
+        self.pos += 2;
+        Some(marker)
+    }
+}
+
+/// Writes entropy-coded bits, stuffing a zero byte after each 0xFF
+#[derive(Default)]
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, length: u8) {
+        self.buffer = self.buffer << length | u64::from(bits);
+        self.count += length;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.buffer >> self.count) as u8;
+            self.output.push(byte);
+            if byte == 0xFF {
+                self.output.push(0);
+            }
+        }
+        self.buffer &= (1 << self.count) - 1;
+    }
+
+    /// Fill the last byte with one bits
+    fn pad(&mut self) {
+        if self.count > 0 {
+            let length = 8 - self.count;
+            self.put((1 << length) - 1, length);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg_at(quality: u8) -> Vec<u8> {
+        let image = image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([(x * 16) as u8, (y * 16) as u8, 64]));
+        let mut data = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality).encode_image(&image).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_estimate_quality() {
+        for quality in [30, 60, 85, 95] {
+            let estimate = estimate_quality(&jpeg_at(quality)).unwrap();
+            assert!(estimate.abs_diff(quality) <= 1, "{} estimated as {}", quality, estimate);
+        }
+        assert_eq!(estimate_quality(b"\x89PNG"), None);
+    }
+
+    #[test]
+    fn test_strip_jpeg_metadata() {
+        let jpeg = jpeg_at(80);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1, 0, 8]);
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&[0xFF, COM, 0, 6]);
+        tagged.extend_from_slice(b"hiya");
+        tagged.extend_from_slice(&jpeg[2..]);
+
+        let stripped = strip_jpeg_metadata(&tagged, StripMode::All).unwrap();
+        assert_eq!(stripped.len(), jpeg.len());
+        assert_eq!(strip_jpeg_metadata(&tagged, StripMode::None).unwrap(), tagged);
+        assert!(image::load_from_memory(&stripped).is_ok());
+    }
+
+    #[test]
+    fn test_strip_keeps_orientation() {
+        let jpeg = jpeg_at(80);
+        let tiff = exif::orientation_block(6);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, APP1]);
+        tagged.extend_from_slice(&(tiff.len() as u16 + 12).to_be_bytes());
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&tiff);
+        tagged.extend_from_slice(b"junk");
+        tagged.extend_from_slice(&jpeg[2..]);
+
+        let safe = strip_jpeg_metadata(&tagged, StripMode::Safe).unwrap();
+        assert_eq!(safe.len(), tagged.len() - 4);
+        assert_eq!(exif::orientation(&safe, Format::Jpeg), Some(6));
+        let all = strip_jpeg_metadata(&tagged, StripMode::All).unwrap();
+        assert_eq!(exif::orientation(&all, Format::Jpeg), None);
+    }
+
+    #[test]
+    fn test_auto_orient() {
+        let image = image::RgbImage::from_fn(32, 16, |x, _| image::Rgb([(x * 8) as u8, 0, 0]));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90).encode_image(&image).unwrap();
+        let tiff = exif::orientation_block(6);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, APP1]);
+        tagged.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&tiff);
+        tagged.extend_from_slice(&jpeg[2..]);
+
+        let config = ProcessingConfig::default();
+        let outcome = JpegProcessor.process_with_stats(&tagged, &config, &Progress::none()).unwrap();
+        assert_eq!(outcome.dimensions, Some((16, 32)));
+        assert_eq!(outcome.quality, Some(90));
+        assert_eq!(exif::orientation(&outcome.data, Format::Jpeg), None);
+
+        let config = ProcessingConfig { auto_orient: false, ..ProcessingConfig::default() };
+        let outcome = JpegProcessor.process_with_stats(&tagged, &config, &Progress::none()).unwrap();
+        assert_eq!(outcome.dimensions, Some((32, 16)));
+        let config = ProcessingConfig { no_lossy: true, ..ProcessingConfig::default() };
+        let outcome = JpegProcessor.process_with_stats(&tagged, &config, &Progress::none()).unwrap();
+        assert_eq!((outcome.dimensions, outcome.warnings.len()), (Some((32, 16)), 1));
+    }
+
+    #[test]
+    fn test_optimize_huffman() {
+        let color = jpeg_at(85);
+        let gray = {
+            let image = image::GrayImage::from_fn(40, 24, |x, y| image::Luma([(x * y) as u8]));
+            let mut data = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, 70).encode_image(&image).unwrap();
+            data
+        };
+        for jpeg in [color, gray] {
+            let optimized = optimize_huffman(&jpeg).unwrap();
+            assert!(optimized.len() < jpeg.len());
+            let pixels = |data: &[u8]| image::load_from_memory(data).unwrap().to_rgb8().into_raw();
+            assert_eq!(pixels(&optimized), pixels(&jpeg));
+            // Already optimal
+            assert_eq!(optimize_huffman(&optimized), None);
+        }
+
+        // Truncated scan data
+        let jpeg = jpeg_at(85);
+        assert_eq!(optimize_huffman(&jpeg[..jpeg.len() - 20]), None);
+    }
+}
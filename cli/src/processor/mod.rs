@@ -1,13 +1,176 @@
 pub mod png;
+pub mod jpeg;
+#[cfg(feature = "mp3")]
 pub mod mp3;
+#[cfg(feature = "webp")]
 pub mod webp;
+#[cfg(feature = "avif")]
+pub mod avif;
+#[cfg(feature = "gif")]
+pub mod gif;
+#[cfg(feature = "mp4")]
 pub mod mp4;
+pub mod audio;
+pub mod ogg;
+pub mod wav;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::config::ProcessingConfig;
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
+use crate::format::Format;
+use crate::outcome::ProcessOutcome;
+use crate::progress::Progress;
+
+/// Check if ffmpeg is available in the system
+#[cfg(feature = "ffmpeg")]
+pub fn is_ffmpeg_available() -> bool {
+    crate::ffmpeg::probe().is_some()
+}
+
+/// Always false when built without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+pub fn is_ffmpeg_available() -> bool {
+    false
+}
+
+/// What a processor does, so callers can filter or report on processors
+/// (e.g. drop video support when ffmpeg is not installed).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Can reduce quality to save space (honours `quality` / `no_lossy`)
+    pub lossy: bool,
+    /// Shells out to the `ffmpeg` binary
+    pub needs_ffmpeg: bool,
+    /// Only removes metadata, never touches the encoded payload
+    pub strip_only: bool,
+}
+
+/// What a processor would do to one input, worked out from the decisions it
+/// makes when processing it, without encoding anything (for `explain`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessorPlan {
+    /// What happens to the file, e.g. "strip tags; audio frames copied untouched"
+    pub action: String,
+    /// Settings in effect for the format, per-format overrides applied
+    pub settings: Vec<(&'static str, String)>,
+    /// What ffmpeg would run for; empty when it is not needed
+    pub ffmpeg: Vec<&'static str>,
+    /// Why the file would be left alone or fail, if it would
+    pub skip: Option<String>,
+}
+
+impl ProcessorPlan {
+    pub fn new(action: impl Into<String>) -> Self {
+        Self { action: action.into(), ..Self::default() }
+    }
+
+    pub fn setting(&mut self, name: &'static str, value: impl ToString) {
+        self.settings.push((name, value.to_string()));
+    }
+}
+
+/// Whether PNG and GIF frames are quantized to a palette: not with `no_lossy`,
+/// nor without the `quantize` feature
+pub(crate) fn quantizes(config: &ProcessingConfig) -> bool {
+    !config.no_lossy && cfg!(feature = "quantize")
+}
 
 pub trait ImageProcessor: Send + Sync {
-    fn supported_formats(&self) -> &[ImageFormat];
+    fn supported_formats(&self) -> &[Format];
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError>;
+
+    /// Like `process`, reporting stages to `progress`. Processors that report stages
+    /// override this and implement `process` with [`Progress::none`].
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        _progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process(input, config)
+    }
+
+    /// Like `process_with_progress`, also reporting what was done. The default only
+    /// fills in the output; processors override it to add savings attribution and
+    /// to hand back the input unchanged (borrowed) when there is nothing to do.
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let data = self.process_with_progress(input, config, progress)?;
+        let format = self.supported_formats()[0];
+        Ok(ProcessOutcome::new(data, format))
+    }
+
+    /// What `process` would do to `input` with `config`, following the same
+    /// decisions; nothing is encoded and ffmpeg is not run
+    fn plan(&self, _input: &[u8], _config: &ProcessingConfig) -> ProcessorPlan {
+        ProcessorPlan::default()
+    }
+
+    /// Whether `process_stream` works incrementally instead of buffering the whole input.
+    fn supports_streaming(&self, _config: &ProcessingConfig) -> bool {
+        false
+    }
+
+    /// Process from a reader into a writer.
+    ///
+    /// The default buffers the whole input and delegates to `process`; formats that
+    /// can filter incrementally override this to keep memory bounded.
+    fn process_stream(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        process_buffered(self, input, output, config)
+    }
+}
+
+/// Temp file that streaming processors park audio in until the headers in front
+/// of it are known, removed on drop
+pub(crate) struct Spool {
+    path: PathBuf,
+    pub(crate) file: File,
+}
+
+impl Spool {
+    pub(crate) fn new(extension: &str) -> io::Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("ip_spool_{}_{}.{}", std::process::id(), id, extension));
+        let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Buffer the whole input and run `process` — the fallback for non-streaming formats.
+pub fn process_buffered<P: ImageProcessor + ?Sized>(
+    processor: &P,
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    config: &ProcessingConfig,
+) -> Result<(), ProcessingError> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let processed = processor.process(&data, config)?;
+    output.write_all(&processed)?;
+    Ok(())
 }
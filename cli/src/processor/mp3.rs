@@ -1,159 +1,242 @@
+use std::borrow::Cow;
 use std::collections::HashSet;
-use std::io::Cursor;
-
-use id3::{Tag, TagLike, Content};
-
-use crate::config::{ProcessingConfig, StripMode};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use id3::frame::Picture;
+use id3::frame::{Comment, ExtendedText};
+#[cfg(feature = "ffmpeg")]
+use id3::frame::Unknown;
+use id3::{Frame, Tag, TagLike, Content};
+use image::imageops::FilterType;
+
+#[cfg(feature = "ffmpeg")]
+use crate::config::{Mp3Options, SilenceTrim};
+use crate::config::{CoverArt, CoverFormat, ProcessingConfig, StripMode};
+use crate::converter::encode_image;
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
-use crate::processor::ImageProcessor;
+use crate::explain;
+#[cfg(feature = "ffmpeg")]
+use crate::ffmpeg::{self, ensure_encoder, read_ffmpeg_result, FfmpegTempFiles, Silence};
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::limits::decode_image;
+use crate::outcome::ProcessOutcome;
+use crate::processor::jpeg::estimate_quality;
+use crate::processor::{is_ffmpeg_available, process_buffered, Capabilities, ImageProcessor, ProcessorPlan, Spool};
+use crate::progress::{Progress, Stage};
 
 pub struct Mp3Processor;
 
-/// Display all metadata from an MP3 file
-pub fn inspect_mp3(input: &[u8]) -> Result<(), ProcessingError> {
-    println!("\n═══════════════════════════════════════════════════════");
-    println!("                  MP3 Metadata Inspection");
-    println!("═══════════════════════════════════════════════════════\n");
-
-    let file_size = input.len();
-    println!("File size: {} bytes ({:.2} KB)", file_size, file_size as f64 / 1024.0);
+/// Inspection report of an MP3 file: tag and audio sizes, the VBR header, then
+/// every ID3v2 frame classified by what `--strip safe` keeps, and the contents
+/// of chained, trailing and ID3v1 tags
+pub fn inspect_mp3(input: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Mp3, input.len());
+    let kb = |size: usize| format!("{} bytes ({:.2} KB)", size, size as f64 / 1024.0);
 
     // Check ID3v2
     let id3v2_size = detect_id3v2_size(input);
     if id3v2_size > 0 {
-        println!("ID3v2 tag: {} bytes ({:.2} KB)", id3v2_size, id3v2_size as f64 / 1024.0);
+        report.field("ID3v2 tag", kb(id3v2_size));
+        if let Some(padding) = id3v2_padding(input) {
+            report.field("ID3v2 padding", format!("{} bytes", padding));
+        }
     } else {
-        println!("ID3v2 tag: Not found");
+        report.field("ID3v2 tag", "Not found");
+    }
+    let leading = leading_id3v2_tags(input);
+    let chained = leading.get(1..).unwrap_or_default();
+    for tag in chained {
+        report.field("Chained ID3v2 tag", format!("{} bytes at offset {}", tag.len(), tag.start));
     }
 
     // Check ID3v1
     let has_v1 = has_id3v1(input);
-    if has_v1 {
-        println!("ID3v1 tag: Present (128 bytes)");
-    } else {
-        println!("ID3v1 tag: Not found");
+    report.field("ID3v1 tag", if has_v1 { "Present (128 bytes)" } else { "Not found" });
+
+    // Check APEv2 / Lyrics3
+    let trailing = trailing_tags(input);
+    for tag in trailing.iter().rev() {
+        report.field(format!("{} tag", tag.kind.as_str()), kb(tag.len()));
     }
 
-    let audio_start = id3v2_size;
-    let audio_end = if has_v1 {
-        input.len().saturating_sub(128)
-    } else {
-        input.len()
-    };
-    let audio_size = audio_end - audio_start;
-    println!("Audio data: {} bytes ({:.2} KB)\n", audio_size, audio_size as f64 / 1024.0);
+    let audio_size = audio_end(input).saturating_sub(id3v2_end(input));
+    report.field("Audio data", kb(audio_size));
+
+    match xing_header(input) {
+        Some(xing) => {
+            let encoder = xing.encoder.as_deref().map(|e| format!(" ({})", e)).unwrap_or_default();
+            let mut section = Section::new(format!("{} Header{}", xing.kind, encoder));
+            if let Some(frames) = xing.frames {
+                section.field("Frames", frames);
+            }
+            if let Some(bytes) = xing.bytes {
+                section.field("Stream size", format!("{} bytes", bytes));
+            }
+            if let Some((delay, padding)) = xing.gapless {
+                section.field("Gapless", format!("encoder delay {} samples, padding {} samples", delay, padding));
+            }
+            if let Some(duration) = xing.duration() {
+                section.field("Duration", format!("{:.3} s", duration));
+            }
+            report.field("VBR header", xing.kind);
+            report.sections.push(section);
+        }
+        None => report.field("VBR header", "Not found"),
+    }
 
-    // Parse and display ID3v2 frames
+    // ID3v2 frames
     match Tag::read_from2(&mut Cursor::new(input)) {
         Ok(tag) => {
-            let version = tag.version();
-            let version_str = match version {
+            let version_str = match tag.version() {
                 id3::Version::Id3v22 => "2.2",
                 id3::Version::Id3v23 => "2.3",
                 id3::Version::Id3v24 => "2.4",
             };
+            let mut section = Section::new(format!("ID3v{} Tag Contents", version_str));
+            section.entries = tag.frames().map(frame_entry).collect();
+            let safe_count = section.entries.iter().filter(|entry| entry.class == Some(EntryClass::Safe)).count();
+            let unsafe_count = section.entries.len() - safe_count;
+            section.summary = Some(format!("{} safe frames, {} unsafe frames", safe_count, unsafe_count));
+            report.sections.push(section);
+        }
+        Err(e) if id3v2_size > 0 => report.errors.push(format!("Could not parse ID3v2 tag: {}", e)),
+        Err(_) => {}
+    }
 
-            println!("ID3v{} Tag Contents:", version_str);
-            println!("───────────────────────────────────────────────────────");
+    for tag in chained {
+        let mut section = Section::new("Chained ID3v2 Tag Contents (merged by --strip safe)");
+        extra_id3v2(&mut section, &input[tag.clone()]);
+        report.sections.push(section);
+    }
+    for tag in trailing.iter().rev() {
+        let mut section = Section::new(format!("{} Tag Contents (removed by --strip all and safe)", tag.kind.as_str()));
+        trailing_tag(&mut section, input, tag);
+        report.sections.push(section);
+    }
 
-            let frames: Vec<_> = tag.frames().collect();
-            if frames.is_empty() {
-                println!("  (no frames found)");
-            } else {
-                println!("  Total frames: {}\n", frames.len());
-
-                let safe_frames = get_safe_frame_ids();
-
-                for frame in &frames {
-                    let frame_id = frame.id();
-                    let is_safe = safe_frames.contains(frame_id);
-                    let safety_marker = if is_safe { "[SAFE]" } else { "[UNSAFE]" };
-
-                    let frame_name = get_frame_name(frame_id);
-                    let value = format_frame_content(frame.content());
-
-                    println!("  {} {}", safety_marker, frame_name);
-                    println!("      ID: {}", frame_id);
-
-                    // Special handling for PRIV frames - display owner separately
-                    if frame_id == "PRIV" {
-                        if let Content::Private(priv_data) = frame.content() {
-                            println!("      Owner: {}", priv_data.owner_identifier);
-                            println!("      Data: {}", format_unknown_data(&priv_data.private_data));
-
-                            // Extract and list all file paths found
-                            let paths = extract_file_paths(&priv_data.private_data);
-                            if !paths.is_empty() {
-                                println!("      Found {} file path(s):", paths.len());
-                                for path in paths {
-                                    println!("        • {}", path);
-                                }
-                            }
-                        } else {
-                            println!("      Value: {}", value);
-                        }
-                    } else {
-                        println!("      Value: {}", value);
-                    }
-                    println!();
-                }
+    if has_v1 {
+        let mut section = Section::new("ID3v1 Tag Contents");
+        id3v1_fields(&mut section, input);
+        report.sections.push(section);
+    }
 
-                // Summary
-                let safe_count = frames.iter().filter(|f| safe_frames.contains(f.id())).count();
-                let unsafe_count = frames.len() - safe_count;
-                println!("───────────────────────────────────────────────────────");
-                println!("Summary: {} safe frames, {} unsafe frames", safe_count, unsafe_count);
+    Ok(report)
+}
+
+/// One ID3v2 frame: name, ID, value and whether `--strip safe` keeps it. PRIV
+/// frames show their owner, and file paths found in their data as warnings.
+fn frame_entry(frame: &Frame) -> Entry {
+    let frame_id = frame.id();
+    let safe = get_safe_frame_ids().contains(frame_id) || is_replaygain(frame);
+    let mut entry = Entry::new(get_frame_name(frame_id));
+    entry.class = Some(if safe { EntryClass::Safe } else { EntryClass::Unsafe });
+    entry.field("ID", frame_id);
+
+    match frame.content() {
+        Content::Private(priv_data) if frame_id == "PRIV" => {
+            entry.field("Owner", &priv_data.owner_identifier);
+            entry.field("Data", format_unknown_data(&priv_data.private_data));
+            let paths = extract_file_paths(&priv_data.private_data);
+            if !paths.is_empty() {
+                entry.warnings.push(format!("Found {} file path(s): {}", paths.len(), paths.join(", ")));
             }
         }
-        Err(e) => {
-            if id3v2_size > 0 {
-                println!("Could not parse ID3v2 tag: {}", e);
-            } else {
-                println!("No ID3v2 tag found");
+        content => entry.field("Value", format_frame_content(content)),
+    }
+    entry
+}
+
+/// APEv2 items (`key = value`) or Lyrics3v2 fields, as section fields
+fn trailing_tag(section: &mut Section, input: &[u8], tag: &TrailingTag) {
+    let body = &input[tag.start..tag.end];
+    match tag.kind {
+        TrailingKind::Id3v2 => extra_id3v2(section, body),
+        TrailingKind::Apev2 => {
+            for (key, value) in ape_items(body) {
+                section.field(key, value);
             }
         }
+        TrailingKind::Lyrics3v2 => {
+            // LYRICSBEGIN, then fields of a 3-letter ID and 5-digit size, then size + LYRICS200
+            let mut fields = &body[11..body.len() - 15];
+            while fields.len() >= 8 {
+                let id = String::from_utf8_lossy(&fields[..3]).into_owned();
+                let Some(len) = std::str::from_utf8(&fields[3..8]).ok().and_then(|s| s.parse::<usize>().ok()) else {
+                    break;
+                };
+                let value = fields.get(8..8 + len).unwrap_or(&fields[8..]);
+                section.field(id, format_unknown_data(value));
+                fields = &fields[(8 + len).min(fields.len())..];
+            }
+        }
+        TrailingKind::Lyrics3v1 => {
+            section.field("Lyrics", format_unknown_data(&body[11..body.len() - 9]));
+        }
     }
+}
 
-    // Display ID3v1 if present
-    if has_v1 {
-        println!("\nID3v1 Tag Contents:");
-        println!("───────────────────────────────────────────────────────");
-        display_id3v1(input);
+/// Frames of a chained or appended ID3v2 tag
+fn extra_id3v2(section: &mut Section, tag_bytes: &[u8]) {
+    match Tag::read_from2(&mut Cursor::new(tag_bytes)) {
+        Ok(tag) => section.entries.extend(tag.frames().map(frame_entry)),
+        Err(e) => section.field("Error", format!("Could not parse tag: {}", e)),
     }
+}
 
-    println!("\n═══════════════════════════════════════════════════════\n");
-
-    Ok(())
+/// Items of an APEv2 tag block; binary values are summarised by size
+fn ape_items(block: &[u8]) -> Vec<(String, String)> {
+    let footer = &block[block.len() - 32..];
+    let count = u32::from_le_bytes([footer[16], footer[17], footer[18], footer[19]]);
+    let mut pos = if block.starts_with(b"APETAGEX") { 32 } else { 0 };
+    let items_end = block.len() - 32;
+
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let Some(header) = block.get(pos..pos + 8).filter(|_| pos + 8 <= items_end) else {
+            break;
+        };
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let flags = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let Some(key_len) = block[pos + 8..items_end].iter().position(|&b| b == 0) else {
+            break;
+        };
+        let key = String::from_utf8_lossy(&block[pos + 8..pos + 8 + key_len]).to_string();
+        let value_start = pos + 8 + key_len + 1;
+        let Some(value) = block.get(value_start..value_start.saturating_add(len)) else {
+            break;
+        };
+        // Bits 1-2: 0 = UTF-8 text, 1 = binary, 2 = external locator
+        let value = match (flags >> 1) & 0x03 {
+            1 => format!("<binary, {} bytes>", value.len()),
+            _ => String::from_utf8_lossy(value).to_string(),
+        };
+        items.push((key, value));
+        pos = value_start + len;
+    }
+    items
 }
 
-/// Display ID3v1 tag contents
-fn display_id3v1(input: &[u8]) {
+/// ID3v1 tag contents
+fn id3v1_fields(section: &mut Section, input: &[u8]) {
     if input.len() < 128 {
         return;
     }
 
-    let tag_start = input.len() - 128;
-    let tag_data = &input[tag_start..];
-
-    let title_str = String::from_utf8_lossy(&tag_data[3..33]);
-    let title = title_str.trim_end_matches('\0').trim();
-    let artist_str = String::from_utf8_lossy(&tag_data[33..63]);
-    let artist = artist_str.trim_end_matches('\0').trim();
-    let album_str = String::from_utf8_lossy(&tag_data[63..93]);
-    let album = album_str.trim_end_matches('\0').trim();
-    let year_str = String::from_utf8_lossy(&tag_data[93..97]);
-    let year = year_str.trim_end_matches('\0').trim();
-    let comment_str = String::from_utf8_lossy(&tag_data[97..127]);
-    let comment = comment_str.trim_end_matches('\0').trim();
+    let tag_data = &input[input.len() - 128..];
+    let text = |range: Range<usize>| {
+        let value = String::from_utf8_lossy(&tag_data[range]).trim_end_matches('\0').trim().to_string();
+        if value.is_empty() { "(empty)".to_string() } else { value }
+    };
     let genre = tag_data[127];
 
-    println!("  Title:   {}", if title.is_empty() { "(empty)" } else { title });
-    println!("  Artist:  {}", if artist.is_empty() { "(empty)" } else { artist });
-    println!("  Album:   {}", if album.is_empty() { "(empty)" } else { album });
-    println!("  Year:    {}", if year.is_empty() { "(empty)" } else { year });
-    println!("  Comment: {}", if comment.is_empty() { "(empty)" } else { comment });
-    println!("  Genre:   {} ({})", genre, get_genre_name(genre));
+    section.field("Title", text(3..33));
+    section.field("Artist", text(33..63));
+    section.field("Album", text(63..93));
+    section.field("Year", text(93..97));
+    section.field("Comment", text(97..127));
+    section.field("Genre", format!("{} ({})", genre, get_genre_name(genre)));
 }
 
 /// Get human-readable frame name
@@ -216,7 +299,7 @@ fn format_frame_content(content: &Content) -> String {
                     priv_data.owner_identifier,
                     format_unknown_data(&priv_data.private_data))
         }
-        _ => format!("<other content type>"),
+        _ => "<other content type>".to_string(),
     }
 }
 
@@ -249,9 +332,9 @@ fn format_unknown_data(data: &[u8]) -> String {
                        text_data.contains("\\AppData\\");
 
         let warning = if has_paths {
-            " ⚠️  CONTAINS FILE PATHS"
+            " ⚠️  CONTAINS FILE PATHS".to_string()
         } else {
-            ""
+            String::new()
         };
 
         // Show full data if it contains paths, otherwise limit to 500 chars
@@ -320,12 +403,11 @@ fn extract_file_paths(data: &[u8]) -> Vec<String> {
 
                 let path = rest[..end].trim();
                 // Only include if it looks like a real path (has / and extension or is a directory)
-                if path.len() > 5 && (path.contains('.') || path.ends_with('/')) {
-                    if path.starts_with("/Users/") || path.starts_with("/home/") ||
-                       path.starts_with("/mnt/") || path.starts_with("/Volumes/") {
-                        paths.push(path.to_string());
-                        break;
-                    }
+                if path.len() > 5 && (path.contains('.') || path.ends_with('/'))
+                    && (path.starts_with("/Users/") || path.starts_with("/home/") ||
+                        path.starts_with("/mnt/") || path.starts_with("/Volumes/")) {
+                    paths.push(path.to_string());
+                    break;
                 }
             }
         }
@@ -405,46 +487,907 @@ fn get_genre_name(code: u8) -> &'static str {
 }
 
 impl ImageProcessor for Mp3Processor {
-    fn supported_formats(&self) -> &[ImageFormat] {
-        &[ImageFormat::Mp3]
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Mp3]
+    }
+
+    /// Lossy only in the sense that the audio can be re-encoded on request
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: cfg!(feature = "ffmpeg"),
+            ..Capabilities::default()
+        }
     }
 
     fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
-        match config.strip {
-            StripMode::None => {
-                log::debug!("Strip mode: None - returning original MP3 unchanged");
-                Ok(input.to_vec())
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    /// Audio frames are copied untouched unless re-encoding was asked for, so the
+    /// size difference is normally all tag data
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let mut outcome = trim_and_process(input, config, progress)?;
+        if config.mp3.replaygain {
+            if is_ffmpeg_available() {
+                outcome.data = Cow::Owned(tag_replaygain(&outcome.data, config, progress)?);
+            } else {
+                log::warn!("ffmpeg not found - ReplayGain analysis requires ffmpeg to be installed");
+                outcome.warnings.push("ffmpeg not found, ReplayGain not written".to_string());
+            }
+        }
+        Ok(outcome)
+    }
+
+    fn plan(&self, _input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let mp3 = &config.mp3;
+        let mut plan = if reencodes(config) {
+            let mut plan = ProcessorPlan::new("strip tags, then re-encode the audio with LAME, kept if smaller");
+            let bitrate = match mp3.bitrate {
+                Some(kbps) => format!("{} kbit/s CBR", kbps),
+                None => format!("VBR from quality {}", config.quality_for(Format::Mp3)),
+            };
+            plan.setting("bitrate", bitrate);
+            plan.ffmpeg.push("re-encoding");
+            plan
+        } else {
+            ProcessorPlan::new("strip tags; audio frames copied untouched")
+        };
+        if config.strip == StripMode::Safe {
+            let cover_art = match &mp3.cover_art {
+                Some(art) => format!("kept, at most {} px as {:?}", art.max_size, art.format),
+                None => "removed".to_string(),
+            };
+            plan.setting("cover art", cover_art);
+        }
+        if let Some(padding) = mp3.id3_padding {
+            plan.setting("ID3 padding", format!("{} bytes", padding));
+        }
+        if mp3.replaygain {
+            plan.setting("ReplayGain", "measured and written");
+            plan.ffmpeg.push("ReplayGain analysis");
+        }
+        if let Some(trim) = &config.trim_silence {
+            plan.setting("trim silence", explain::silence(trim));
+            plan.ffmpeg.push("silence detection");
+        }
+        plan
+    }
+
+    /// Tag stripping streams; re-encoding, silence trimming and ReplayGain need the whole file
+    fn supports_streaming(&self, config: &ProcessingConfig) -> bool {
+        !reencodes(config) && config.trim_silence.is_none() && !config.mp3.replaygain
+    }
+
+    fn process_stream(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        if self.supports_streaming(config) {
+            strip_tags_stream(input, output, config)
+        } else {
+            process_buffered(self, input, output, config)
+        }
+    }
+}
+
+/// Silence trim (when asked for and ffmpeg is there), then `process_audio`
+fn trim_and_process<'a>(
+    input: &'a [u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<ProcessOutcome<'a>, ProcessingError> {
+    #[cfg(feature = "ffmpeg")]
+    if let Some(trim) = config.trim_silence.as_ref().filter(|_| is_ffmpeg_available()) {
+        return match trim_mp3_silence(input, trim)? {
+            Some(trimmed) => Ok(process_audio(&trimmed, config, progress)?.into_owned()),
+            None => process_audio(input, config, progress),
+        };
+    }
+
+    let mut outcome = process_audio(input, config, progress)?;
+    if config.trim_silence.is_some() {
+        log::warn!("ffmpeg not found - MP3 silence detection requires ffmpeg to be installed");
+        outcome.warnings.push("ffmpeg not found, silence left untrimmed".to_string());
+    }
+    Ok(outcome)
+}
+
+/// Tag strip, plus the re-encode when one was asked for
+fn process_audio<'a>(
+    input: &'a [u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<ProcessOutcome<'a>, ProcessingError> {
+    #[cfg(feature = "ffmpeg")]
+    if reencodes(config) && is_ffmpeg_available() {
+        return reencode_with_stats(input, config, progress);
+    }
+
+    let mut outcome = strip_with_stats(input, config, progress)?;
+    if reencodes(config) {
+        log::warn!("ffmpeg not found - MP3 re-encoding requires ffmpeg to be installed");
+        outcome.warnings.push("ffmpeg not found, MP3 audio left as is".to_string());
+    }
+    Ok(outcome)
+}
+
+/// Re-encoding is opt-in via `mp3.bitrate` / `mp3.vbr` and never happens with `no_lossy`
+fn reencodes(config: &ProcessingConfig) -> bool {
+    !config.no_lossy && config.mp3.reencodes()
+}
+
+fn strip_with_stats<'a>(
+    input: &'a [u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<ProcessOutcome<'a>, ProcessingError> {
+    if config.strip == StripMode::None {
+        log::debug!("Strip mode: None - returning original MP3 unchanged");
+        let mut outcome = ProcessOutcome::unchanged(input, Format::Mp3);
+        outcome.metadata_removed = Some(0);
+        return Ok(outcome);
+    }
+
+    progress.start(Stage::Strip);
+    let data = match config.strip {
+        StripMode::None => Ok(input.to_vec()),
+        StripMode::Safe => strip_unsafe_tags(input, config),
+        StripMode::All => strip_all_tags(input),
+    }?;
+    progress.finish(Stage::Strip);
+
+    let metadata_removed = (input.len() as u64).saturating_sub(data.len() as u64);
+    let mut outcome = ProcessOutcome::new(data, Format::Mp3);
+    outcome.metadata_removed = Some(metadata_removed);
+    // Players take VBR duration and gapless info from the Xing/LAME frame
+    if let Some(xing) = xing_header(input) {
+        if xing_header(&outcome.data).is_some() {
+            log::debug!("Kept {} header frame", xing.kind);
+        } else {
+            outcome.warnings.push(format!("{} header frame was lost; duration may be misreported", xing.kind));
+        }
+    }
+    Ok(outcome)
+}
+
+/// Strip tags per `config.strip`, then swap the audio frames for a LAME re-encode.
+/// The stripped file is kept when the re-encode is not smaller.
+#[cfg(feature = "ffmpeg")]
+fn reencode_with_stats<'a>(
+    input: &'a [u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<ProcessOutcome<'a>, ProcessingError> {
+    let mut stripped = strip_with_stats(input, config, progress)?;
+    let quality = config.quality_for(Format::Mp3);
+    let audio = reencode_audio(input, &config.mp3, quality, progress)?;
+
+    // Whatever tags the strip mode kept go back around the new frames
+    let tags_len = id3v2_end(&stripped.data);
+    let trailer_len = stripped.data.len() - audio_end(&stripped.data);
+    let total = tags_len + audio.len() + trailer_len;
+    if total >= stripped.data.len() {
+        log::info!("Re-encoded MP3 is not smaller ({} >= {} bytes), keeping the original audio",
+                   total, stripped.data.len());
+        stripped.warnings.push("re-encoding did not make the MP3 smaller; audio left as is".to_string());
+        return Ok(stripped);
+    }
+
+    let mut data = Vec::with_capacity(total);
+    data.extend_from_slice(&stripped.data[..tags_len]);
+    data.extend_from_slice(&audio);
+    data.extend_from_slice(&stripped.data[stripped.data.len() - trailer_len..]);
+
+    let mut outcome = ProcessOutcome::new(data, Format::Mp3);
+    outcome.metadata_removed = stripped.metadata_removed;
+    match config.mp3.bitrate {
+        Some(kbps) => outcome.bitrate = Some(u64::from(kbps) * 1000),
+        None => outcome.quality = Some(quality),
+    }
+    Ok(outcome)
+}
+
+/// Re-encode the MPEG audio with libmp3lame, returning bare frames without tags
+#[cfg(feature = "ffmpeg")]
+fn reencode_audio(
+    input: &[u8],
+    options: &Mp3Options,
+    quality: u8,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    ensure_encoder("libmp3lame")?;
+
+    let temp = FfmpegTempFiles::with_extensions("mp3", "mp3");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    progress.start(Stage::Ffmpeg);
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0:a:0", "-c:a", "libmp3lame"]);
+    cmd.args(lame_rate_args(options, quality));
+    // Tags are taken from the stripped input instead, so ffmpeg must not write any
+    cmd.args(["-map_metadata", "-1", "-id3v2_version", "0", "-write_id3v1", "0"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    let result = read_ffmpeg_result(output, &temp, input.len())?;
+    progress.finish(Stage::Ffmpeg);
+    Ok(result)
+}
+
+/// `-b:a` for constant bitrate, else LAME's VBR scale `-q:a`
+#[cfg(feature = "ffmpeg")]
+fn lame_rate_args(options: &Mp3Options, quality: u8) -> [String; 2] {
+    match options.bitrate {
+        Some(kbps) => ["-b:a".to_string(), format!("{}k", kbps)],
+        None => ["-q:a".to_string(), vbr_level(quality).to_string()],
+    }
+}
+
+/// Quality 100–0 mapped onto LAME's VBR levels V0 (best) to V9
+#[cfg(feature = "ffmpeg")]
+fn vbr_level(quality: u8) -> u32 {
+    ((100 - u32::from(quality.min(100))) * 9 + 50) / 100
+}
+
+/// Measure the loudness of `input` and write ReplayGain 2.0 track gain/peak as
+/// TXXX frames plus an RVA2 frame, replacing any earlier values
+#[cfg(feature = "ffmpeg")]
+fn tag_replaygain(input: &[u8], config: &ProcessingConfig, progress: &Progress) -> Result<Vec<u8>, ProcessingError> {
+    let temp = FfmpegTempFiles::with_extensions("mp3", "mp3");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+    progress.start(Stage::Ffmpeg);
+    let loudness = ffmpeg::measure_loudness(&temp.input)?;
+    progress.finish(Stage::Ffmpeg);
+
+    let gain = loudness.replaygain();
+    let peak = loudness.peak_amplitude();
+    log::info!("ReplayGain: {:.1} LUFS -> track gain {:+.2} dB, peak {:.6}", loudness.integrated, gain, peak);
+
+    let mut tag = read_id3v2_or_new(input)?;
+    // TXXX frames with the same description replace each other
+    for (description, value) in [
+        ("REPLAYGAIN_TRACK_GAIN", format!("{:+.2} dB", gain)),
+        ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", peak)),
+    ] {
+        tag.add_frame(ExtendedText { description: description.to_string(), value });
+    }
+    tag.remove("RVA2");
+    let rva2 = Unknown { data: rva2_body(gain), version: id3::Version::Id3v24 };
+    tag.add_frame(Frame::with_content("RVA2", Content::Unknown(rva2)));
+
+    replace_id3v2(input, &tag, config)
+}
+
+/// Never called: `is_ffmpeg_available` is false without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+fn tag_replaygain(_input: &[u8], _config: &ProcessingConfig, _progress: &Progress) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// RVA2 body for a track gain on the master channel: identification, channel
+/// type 1, gain in 1/512 dB, and no peak (it is in the TXXX frame)
+#[cfg(feature = "ffmpeg")]
+fn rva2_body(gain: f64) -> Vec<u8> {
+    let adjustment = (gain * 512.0).round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16;
+    let mut body = b"track\0".to_vec();
+    body.push(1);
+    body.extend_from_slice(&adjustment.to_be_bytes());
+    body.push(0);
+    body
+}
+
+/// One MPEG audio frame in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MpegFrame {
+    offset: usize,
+    len: usize,
+    /// Samples per channel
+    samples: u32,
+    sample_rate: u32,
+    /// Offset of a Xing/Info tag within the frame, if it could hold one
+    side_info_end: usize,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl MpegFrame {
+    fn duration(&self) -> f64 {
+        f64::from(self.samples) / f64::from(self.sample_rate)
+    }
+}
+
+/// Parse the 4-byte MPEG audio frame header at `offset`; free-format and
+/// reserved values are rejected
+fn mpeg_frame(input: &[u8], offset: usize) -> Option<MpegFrame> {
+    const BITRATES_V1: [[u32; 14]; 3] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    ];
+    const BITRATES_V2: [[u32; 14]; 2] = [
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+
+    let header = input.get(offset..offset + 4)?;
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    // version: 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1; layer: 1 = III, 2 = II, 3 = I
+    let version = (header[1] >> 3) & 0x03;
+    let layer = (header[1] >> 1) & 0x03;
+    let bitrate_index = usize::from(header[2] >> 4);
+    let rate_index = usize::from((header[2] >> 2) & 0x03);
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let layer_index = usize::from(3 - layer); // 0 = I, 1 = II, 2 = III
+    let kbps = if mpeg1 {
+        BITRATES_V1[layer_index][bitrate_index - 1]
+    } else {
+        BITRATES_V2[layer_index.min(1)][bitrate_index - 1]
+    };
+    // MPEG 2 halves the MPEG 1 rates, MPEG 2.5 quarters them
+    let shift = if mpeg1 { 0 } else if version == 2 { 1 } else { 2 };
+    let sample_rate = [44_100, 48_000, 32_000][rate_index] >> shift;
+    let samples = match layer_index {
+        0 => 384,
+        2 if !mpeg1 => 576,
+        _ => 1152,
+    };
+    let padding = usize::from((header[2] >> 1) & 0x01);
+    let len = if layer_index == 0 {
+        (12 * kbps as usize * 1000 / sample_rate as usize + padding) * 4
+    } else {
+        samples as usize / 8 * kbps as usize * 1000 / sample_rate as usize + padding
+    };
+    let mono = header[3] >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+
+    Some(MpegFrame {
+        offset,
+        len,
+        samples,
+        sample_rate,
+        side_info_end: 4 + side_info,
+    })
+}
+
+/// Consecutive MPEG frames after the ID3v2 tag, up to the first byte that is not a frame
+#[cfg(feature = "ffmpeg")]
+fn mpeg_frames(input: &[u8]) -> Vec<MpegFrame> {
+    let mut frames = Vec::new();
+    let Some(mut offset) = first_frame(input).map(|frame| frame.offset) else {
+        return frames;
+    };
+    while let Some(frame) = mpeg_frame(input, offset).filter(|f| f.offset + f.len <= input.len()) {
+        frames.push(frame);
+        offset += frame.len;
+    }
+    frames
+}
+
+/// Offset of the Xing/Info tag if `frame` is a VBR info frame rather than audio
+fn xing_offset(input: &[u8], frame: &MpegFrame) -> Option<usize> {
+    let offset = frame.offset + frame.side_info_end;
+    matches!(input.get(offset..offset + 4)?, b"Xing" | b"Info").then_some(offset)
+}
+
+/// First MPEG frame after the ID3v2 tag, skipping padding the tag size does not
+/// cover. A sync word only counts when another frame, an ID3v1 tag or the end of
+/// the file follows it.
+fn first_frame(input: &[u8]) -> Option<MpegFrame> {
+    let start = id3v2_end(input);
+    let end = input.len().min(start.saturating_add(64 * 1024));
+    (start..end).filter_map(|offset| mpeg_frame(input, offset)).find(|frame| {
+        let next = frame.offset + frame.len;
+        next >= input.len() || mpeg_frame(input, next).is_some() || input[next..].starts_with(b"TAG")
+    })
+}
+
+/// Xing/Info header of a VBR (or LAME CBR) file, with the LAME extension that
+/// carries gapless playback info
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct XingHeader {
+    /// The frame holding the header; it carries no audio
+    frame: MpegFrame,
+    /// `Xing` for VBR, `Info` for CBR
+    kind: String,
+    /// Audio frames in the stream, excluding this one
+    frames: Option<u32>,
+    /// Stream size in bytes
+    bytes: Option<u32>,
+    /// Encoder version from the LAME extension, e.g. `LAME3.100`
+    encoder: Option<String>,
+    /// Encoder delay and end padding in samples
+    gapless: Option<(u16, u16)>,
+}
+
+impl XingHeader {
+    /// Playing time from the frame count, less encoder delay and padding
+    fn duration(&self) -> Option<f64> {
+        let samples = u64::from(self.frames?) * u64::from(self.frame.samples);
+        let (delay, padding) = self.gapless.unwrap_or_default();
+        let samples = samples.saturating_sub(u64::from(delay) + u64::from(padding));
+        Some(samples as f64 / f64::from(self.frame.sample_rate))
+    }
+}
+
+fn xing_header(input: &[u8]) -> Option<XingHeader> {
+    let frame = first_frame(input)?;
+    let offset = xing_offset(input, &frame)?;
+    let end = frame.offset + frame.len;
+    let field = |at: usize| -> Option<u32> {
+        input.get(at..(at + 4).min(end)).and_then(|b| b.try_into().ok()).map(u32::from_be_bytes)
+    };
+
+    let flags = field(offset + 4)?;
+    let mut at = offset + 8;
+    let mut optional = |flag: u32, len: usize| {
+        let present = flags & flag != 0;
+        let value = present.then(|| field(at)).flatten();
+        if present {
+            at += len;
+        }
+        value
+    };
+    let frames = optional(0x1, 4);
+    let bytes = optional(0x2, 4);
+    optional(0x4, 100); // seek table
+    optional(0x8, 4); // quality
+
+    // LAME extension: 9-byte encoder string, then delay/padding as two 12-bit values at +21
+    let encoder = input
+        .get(at..(at + 9).min(end))
+        .filter(|b| b.len() == 9 && b[0].is_ascii_alphabetic() && b.iter().all(|c| c.is_ascii_graphic() || *c == 0))
+        .map(|b| String::from_utf8_lossy(b).trim_end_matches('\0').to_string());
+    let gapless = match input.get(at + 21..at + 24) {
+        Some(b) if encoder.is_some() && at + 24 <= end => {
+            Some(((u16::from(b[0]) << 4) | (u16::from(b[1]) >> 4), (u16::from(b[1] & 0x0F) << 8) | u16::from(b[2])))
+        }
+        _ => None,
+    };
+
+    Some(XingHeader {
+        frame,
+        kind: String::from_utf8_lossy(&input[offset..offset + 4]).to_string(),
+        frames,
+        bytes,
+        encoder,
+        gapless,
+    })
+}
+
+/// Drop whole MPEG frames of leading/trailing silence (found with ffmpeg's
+/// `silencedetect`), keeping tags and the Xing/Info frame rewritten to match.
+/// Returns None when there is nothing to cut.
+#[cfg(feature = "ffmpeg")]
+fn trim_mp3_silence(input: &[u8], trim: &SilenceTrim) -> Result<Option<Vec<u8>>, ProcessingError> {
+    let frames = mpeg_frames(input);
+    let Some(first) = frames.first().copied() else {
+        return Ok(None);
+    };
+    let info = xing_offset(input, &first);
+    let audio = if info.is_some() { &frames[1..] } else { &frames[..] };
+
+    let temp = FfmpegTempFiles::with_extensions("mp3", "mp3");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+    let silences = ffmpeg::detect_silence(&temp.input, trim)?;
+
+    let total: f64 = audio.iter().map(MpegFrame::duration).sum();
+    let Some((keep_from, keep_to)) = audible_span(&silences, total) else {
+        return Ok(None);
+    };
+
+    // Frame start times; a frame of margin each side covers the decoder delay
+    let mut start = 0.0;
+    let mut kept = Vec::new();
+    for frame in audio {
+        let end = start + frame.duration();
+        let margin = frame.duration();
+        if end + margin > keep_from && start - margin < keep_to {
+            kept.push(*frame);
+        }
+        start = end;
+    }
+    if kept.len() == audio.len() || kept.is_empty() {
+        return Ok(None);
+    }
+
+    let last = frames[frames.len() - 1];
+    let mut output = Vec::with_capacity(input.len());
+    output.extend_from_slice(&input[..first.offset]);
+    let mut music = Vec::with_capacity(kept.iter().map(|f| f.len).sum());
+    for frame in &kept {
+        music.extend_from_slice(&input[frame.offset..frame.offset + frame.len]);
+    }
+    if let Some(offset) = info {
+        let mut info_frame = input[first.offset..first.offset + first.len].to_vec();
+        update_xing(&mut info_frame, offset - first.offset, &kept, &music);
+        output.extend_from_slice(&info_frame);
+    }
+    output.extend_from_slice(&music);
+    output.extend_from_slice(&input[last.offset + last.len..]);
+
+    let cut = total - kept.iter().map(MpegFrame::duration).sum::<f64>();
+    log::info!("Trimmed {:.2} s of silence ({} of {} frames)", cut, audio.len() - kept.len(), audio.len());
+    Ok(Some(output))
+}
+
+/// The audible part of a `total`-second stream: after a silence that starts at
+/// the beginning, before one that runs to the end. None if all of it is silent
+/// or there is nothing to cut.
+#[cfg(feature = "ffmpeg")]
+fn audible_span(silences: &[Silence], total: f64) -> Option<(f64, f64)> {
+    // silencedetect timestamps are approximate around the encoder delay
+    const EDGE: f64 = 0.1;
+    let lead = silences.first().filter(|s| s.start <= EDGE);
+    let tail = silences.last().filter(|s| s.end.is_none_or(|end| end >= total - EDGE));
+    if lead.is_some_and(|s| s.end.is_none_or(|end| end >= total - EDGE)) {
+        log::debug!("Audio is entirely silent, leaving it untrimmed");
+        return None;
+    }
+
+    let from = lead.and_then(|s| s.end).unwrap_or(0.0);
+    let to = tail.map_or(total, |s| s.start);
+    (from > 0.0 || to < total).then_some((from, to))
+}
+
+/// Rewrite the Xing/Info tag at `offset` in `frame` for the audio `frames` now
+/// following it, whose bytes are `music`: the frame and byte counts, the seek
+/// table, and the LAME extension's music length and CRCs
+#[cfg(feature = "ffmpeg")]
+fn update_xing(frame: &mut [u8], offset: usize, frames: &[MpegFrame], music: &[u8]) {
+    let Some(flags) = frame.get(offset + 4..offset + 8) else {
+        return;
+    };
+    let flags = u32::from_be_bytes([flags[0], flags[1], flags[2], flags[3]]);
+    let bytes = frame.len() + music.len();
+    let mut field = offset + 8;
+    for (flag, value) in [(0x1, frames.len()), (0x2, bytes)] {
+        if flags & flag != 0 {
+            if let Some(slot) = frame.get_mut(field..field + 4) {
+                slot.copy_from_slice(&(value as u32).to_be_bytes());
             }
-            StripMode::Safe => strip_unsafe_tags(input),
-            StripMode::All => strip_all_tags(input),
+            field += 4;
+        }
+    }
+    if flags & 0x4 != 0 {
+        let table = seek_table(frame.len(), frames, bytes);
+        if let Some(slot) = frame.get_mut(field..field + 100) {
+            slot.copy_from_slice(&table);
+        }
+        field += 100;
+    }
+    if flags & 0x8 != 0 {
+        field += 4;
+    }
+
+    // LAME extension: music length at +28, music CRC at +32, then the CRC of the frame up to it
+    let lame = field;
+    if frame.len() < lame + 36 || !frame[lame].is_ascii_alphabetic() {
+        return;
+    }
+    frame[lame + 28..lame + 32].copy_from_slice(&(bytes as u32).to_be_bytes());
+    frame[lame + 32..lame + 34].copy_from_slice(&crc16(music).to_be_bytes());
+    let crc = crc16(&frame[..lame + 34]);
+    frame[lame + 34..lame + 36].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// Xing seek table: for each percent of the playing time, where the frame playing
+/// then starts, in 256ths of `bytes` counted from the start of the info frame
+#[cfg(feature = "ffmpeg")]
+fn seek_table(info_len: usize, frames: &[MpegFrame], bytes: usize) -> [u8; 100] {
+    // In samples: the sample rate does not change within a stream
+    let total: u64 = frames.iter().map(|frame| u64::from(frame.samples)).sum();
+    let mut table = [0u8; 100];
+    let (mut time, mut position) = (0, info_len);
+    let mut frames = frames.iter().peekable();
+    for (percent, entry) in (0u64..).zip(table.iter_mut()) {
+        while let Some(frame) = frames.next_if(|frame| (time + u64::from(frame.samples)) * 100 <= total * percent) {
+            time += u64::from(frame.samples);
+            position += frame.len;
+        }
+        *entry = (position * 256 / bytes.max(1)).min(255) as u8;
+    }
+    table
+}
+
+/// CRC-16 as LAME computes it for its info tag (polynomial 0x8005, reflected)
+#[cfg(feature = "ffmpeg")]
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte), |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 })
+    })
+}
+
+/// Streaming tag strip: only the ID3v2 tag is held in memory, audio is copied through
+/// with a look-behind window so trailing APEv2/Lyrics3/ID3v1 tags can be dropped.
+/// Safe mode spools the audio to a temp file, since appended ID3v2 tags found at the
+/// end are merged into the tag written first.
+fn strip_tags_stream(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    config: &ProcessingConfig,
+) -> Result<(), ProcessingError> {
+    if config.strip == StripMode::None {
+        io::copy(input, output)?;
+        return Ok(());
+    }
+
+    let mut header = Vec::with_capacity(10);
+    input.take(10).read_to_end(&mut header)?;
+
+    let id3v2_size = detect_id3v2_size(&header);
+    let mut spool = match config.strip {
+        StripMode::Safe => Some(Spool::new("mp3")?),
+        _ => None,
+    };
+    let mut tail = TrailingTagFilter::new(match spool.as_mut() {
+        Some(spool) => &mut spool.file,
+        None => &mut *output,
+    });
+
+    let mut tag_bytes = Vec::new();
+    if id3v2_size > 0 {
+        tag_bytes = header;
+        input.take((id3v2_size - 10) as u64).read_to_end(&mut tag_bytes)?;
+        // Chained tags right after the first one belong to the tag block too
+        let mut peeked = Vec::with_capacity(10);
+        loop {
+            peeked.clear();
+            input.take(10).read_to_end(&mut peeked)?;
+            let size = detect_id3v2_size(&peeked);
+            if size == 0 {
+                break;
+            }
+            log::debug!("Found chained ID3v2 tag at offset {} ({} bytes)", tag_bytes.len(), size);
+            tag_bytes.extend_from_slice(&peeked);
+            input.take((size - 10) as u64).read_to_end(&mut tag_bytes)?;
+        }
+
+        if config.strip == StripMode::All {
+            log::info!("Strip mode: All - removing ID3v2 ({} bytes)", tag_bytes.len());
+        }
+        tail.write(&peeked)?;
+    } else {
+        // No tag: the bytes we peeked at are already audio
+        tail.write(&header)?;
+    }
+
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        tail.write(&buf[..n])?;
+    }
+
+    let appended = tail.finish()?;
+    let Some(mut spool) = spool else {
+        return Ok(());
+    };
+    for tag in appended {
+        log::debug!("Merging appended ID3v2 tag ({} bytes)", tag.len());
+        tag_bytes.extend_from_slice(&tag);
+    }
+    if !tag_bytes.is_empty() {
+        output.write_all(&safe_tag_bytes(&tag_bytes, config)?)?;
+    }
+    spool.file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut spool.file, output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Rebuild the ID3v2 tags in `tag_bytes`, one after another, as one keeping only
+/// safe frames; unparseable tags are kept as-is.
+fn safe_tag_bytes(tag_bytes: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let tag = match read_id3v2(tag_bytes) {
+        Ok(tag) => tag,
+        Err(e) => {
+            log::debug!("Could not parse ID3v2 tag, keeping it: {}", e);
+            return Ok(tag_bytes.to_vec());
         }
+    };
+
+    let safe_frame_ids = get_safe_frame_ids();
+    let kept: Vec<Frame> = tag.frames().filter_map(|f| safe_frame(f, &safe_frame_ids, config)).collect();
+    let unchanged = kept.len() == tag.frames().count() && kept.iter().zip(tag.frames()).all(|(new, old)| new == old);
+    if unchanged && detect_id3v2_size(tag_bytes) == tag_bytes.len() && padding_matches(tag_bytes, config) {
+        return Ok(tag_bytes.to_vec());
+    }
+
+    let mut new_tag = Tag::new();
+    for frame in kept {
+        new_tag.add_frame(frame);
+    }
+    write_tag(&new_tag, config)
+}
+
+/// Value of the TXXX frame with `description`, from the merged ID3v2 tags
+pub(crate) fn extended_text(input: &[u8], description: &str) -> Option<String> {
+    let tag = read_id3v2(input).ok()?;
+    let value = tag.extended_texts().find(|text| text.description == description)?.value.clone();
+    Some(value)
+}
+
+/// `input` with a TXXX frame `description` set to `value`, replacing an earlier one
+pub(crate) fn with_extended_text(
+    input: &[u8],
+    description: &str,
+    value: &str,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    let mut tag = read_id3v2_or_new(input)?;
+    // TXXX frames with the same description replace each other
+    tag.add_frame(ExtendedText { description: description.to_string(), value: value.to_string() });
+    replace_id3v2(input, &tag, config)
+}
+
+/// Id and text of each text frame (`T***`) and comment frame (`COMM`), from the
+/// merged ID3v2 tags
+pub fn text_frames(input: &[u8]) -> Vec<(String, String)> {
+    let Ok(tag) = read_id3v2(input) else { return Vec::new() };
+    tag.frames()
+        .filter_map(|frame| match frame.content() {
+            Content::Text(text) => Some((frame.id().to_string(), text.replace('\0', "; "))),
+            Content::Comment(comment) => Some((frame.id().to_string(), comment.text.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `input` with the frames in `delete` removed and those in `set` replaced; a
+/// `COMM` value becomes the tag's only comment
+pub fn with_text_frames(input: &[u8], set: &[(String, String)], delete: &[String]) -> Result<Vec<u8>, ProcessingError> {
+    let mut tag = read_id3v2_or_new(input)?;
+    for id in delete {
+        tag.remove(id);
+    }
+    for (id, value) in set {
+        if id == "COMM" {
+            tag.remove("COMM");
+            let comment = Comment { lang: "eng".to_string(), description: String::new(), text: value.clone() };
+            tag.add_frame(comment);
+        } else {
+            tag.set_text(id, value.as_str());
+        }
+    }
+    replace_id3v2(input, &tag, &ProcessingConfig::default())
+}
+
+/// `input` with all of its ID3v2 tags, leading and appended, replaced by `tag` at the start
+fn replace_id3v2(input: &[u8], tag: &Tag, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let mut output = write_tag(tag, config)?;
+    output.reserve(input.len());
+    // Appended tags were merged into the new one
+    output.extend_from_slice(&input[id3v2_end(input)..]);
+    let trailing = trailing_tags(input);
+    for tag in trailing.iter().rev().filter(|tag| tag.kind == TrailingKind::Id3v2) {
+        let start = output.len() - (input.len() - tag.start);
+        output.drain(start..start + tag.len());
+    }
+    Ok(output)
+}
+
+/// Serialize `tag` as ID3v2.4 followed by `config.mp3.id3_padding` zero bytes
+/// (none by default)
+fn write_tag(tag: &Tag, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let mut out = Vec::new();
+    id3::Encoder::new()
+        .version(id3::Version::Id3v24)
+        .padding(config.mp3.id3_padding.unwrap_or(0) as usize)
+        .encode(tag, &mut out)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write ID3 tag: {}", e)))?;
+    Ok(out)
+}
+
+/// False when an explicit `id3_padding` asks for a different padding than the
+/// first tag of `input` has, so the tag must be rewritten
+fn padding_matches(input: &[u8], config: &ProcessingConfig) -> bool {
+    config.mp3.id3_padding.is_none_or(|padding| id3v2_padding(input) == Some(padding as usize))
+}
+
+/// Trailing tags larger than this are left in place when streaming
+const TRAILING_WINDOW: usize = 256 * 1024;
+
+/// Writer adapter that holds back the last `TRAILING_WINDOW` bytes and drops the
+/// trailing tags found in them on `finish`, handing back the appended ID3v2 ones.
+struct TrailingTagFilter<'a> {
+    output: &'a mut dyn Write,
+    held: Vec<u8>,
+}
+
+impl<'a> TrailingTagFilter<'a> {
+    fn new(output: &'a mut dyn Write) -> Self {
+        Self { output, held: Vec::new() }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.held.extend_from_slice(data);
+        // Flush in big steps so the window is not shifted on every write
+        if self.held.len() > 2 * TRAILING_WINDOW {
+            let flush = self.held.len() - TRAILING_WINDOW;
+            self.output.write_all(&self.held[..flush])?;
+            self.held.drain(..flush);
+        }
+        Ok(())
+    }
+
+    /// Write out the audio; the appended ID3v2 tags come back in file order
+    fn finish(self) -> Result<Vec<Vec<u8>>, ProcessingError> {
+        let end = audio_end(&self.held);
+        let trailing = trailing_tags(&self.held);
+        for tag in &trailing {
+            log::info!("Removed: {} ({} bytes)", tag.kind.as_str(), tag.len());
+        }
+        if self.held.len() - end > 0 {
+            log::debug!("Dropped {} bytes of trailing tags", self.held.len() - end);
+        }
+        if has_ape_footer(&self.held[..end]) {
+            log::warn!("APEv2 tag is larger than the streaming window, left in place");
+        }
+        self.output.write_all(&self.held[..end])?;
+        self.output.flush()?;
+        let id3v2 = trailing.iter().rev().filter(|tag| tag.kind == TrailingKind::Id3v2);
+        Ok(id3v2.map(|tag| self.held[tag.start..tag.end].to_vec()).collect())
     }
 }
 
 /// Remove all ID3 tags (v1 and v2), returning only raw MPEG audio frames
 fn strip_all_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
-    let id3v2_size = detect_id3v2_size(input);
+    let leading = leading_id3v2_tags(input);
     let has_v1 = has_id3v1(input);
+    let trailing = trailing_tags(input);
 
-    let audio_start = id3v2_size;
-    let audio_end = if has_v1 {
-        input.len().saturating_sub(128)
-    } else {
-        input.len()
-    };
+    let audio_start = id3v2_end(input);
+    let audio_end = audio_end(input);
 
     if audio_start >= audio_end {
-        return Err(ProcessingError::Decode(
-            "Invalid MP3 structure: no audio data found".to_string(),
-        ));
+        return Err(ProcessingError::CorruptInput {
+            format: "mp3".to_string(),
+            offset: Some(audio_start as u64),
+            reason: "no audio data found".to_string(),
+        });
     }
 
     let audio_only = input[audio_start..audio_end].to_vec();
 
     // Logging
     let mut removed_tags = Vec::new();
-    if id3v2_size > 0 {
-        removed_tags.push(format!("ID3v2 ({} bytes)", id3v2_size));
+    for tag in &leading {
+        removed_tags.push(format!("ID3v2 ({} bytes)", tag.len()));
+    }
+    for tag in &trailing {
+        removed_tags.push(format!("{} ({} bytes)", tag.kind.as_str(), tag.len()));
     }
     if has_v1 {
         removed_tags.push("ID3v1 (128 bytes)".to_string());
@@ -453,7 +1396,7 @@ fn strip_all_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
     if !removed_tags.is_empty() {
         log::info!("Strip mode: All - removing all ID3 tags");
         log::info!("Removed: {}", removed_tags.join(", "));
-        let saved = (id3v2_size + if has_v1 { 128 } else { 0 }) as f64 / 1024.0;
+        let saved = (input.len() - audio_only.len()) as f64 / 1024.0;
         log::info!("Stripped all tags ({:.2} KB saved from metadata)", saved);
     } else {
         log::debug!("No ID3 tags found in file");
@@ -463,17 +1406,17 @@ fn strip_all_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
 }
 
 /// Remove unsafe metadata, keeping only basic tags (title, artist, album, year, genre, track)
-fn strip_unsafe_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
-    // Try to parse ID3v2 tag
-    let tag = match Tag::read_from2(&mut Cursor::new(input)) {
+fn strip_unsafe_tags(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    // Try to parse the ID3v2 tags, merging chained and appended ones
+    let tag = match read_id3v2(input) {
         Ok(tag) => tag,
         Err(e) => {
-            // No tag or parse error - check if there's ID3v1
-            if has_id3v1(input) {
-                log::info!("Found ID3v1 tag (Safe mode removes ID3v1, keeping ID3v2 safe frames only)");
-                // Remove ID3v1, return rest
-                let without_v1 = input[..input.len().saturating_sub(128)].to_vec();
-                return Ok(without_v1);
+            // No tag or parse error - check for ID3v1, APEv2 and Lyrics3 at the end
+            let end = audio_end(input);
+            if end < input.len() {
+                log::info!("Found ID3v1/APEv2/Lyrics3 tags (Safe mode removes them, keeping ID3v2 safe frames only)");
+                // Remove the trailing tags, return rest
+                return Ok(input[..end].to_vec());
             }
             log::debug!("No ID3 tags found or parse error: {}", e);
             return Ok(input.to_vec());
@@ -493,16 +1436,21 @@ fn strip_unsafe_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
     );
 
     let safe_frame_ids = get_safe_frame_ids();
+    let mut new_tag = Tag::new();
     let mut kept_frames = Vec::new();
     let mut removed_frames = Vec::new();
+    let mut covers_changed = false;
 
-    // Categorize frames
+    // Categorize frames, building the new tag from the kept ones
     for frame in tag.frames() {
         let frame_id = frame.id();
-        if safe_frame_ids.contains(frame_id) {
-            kept_frames.push(frame_id.to_string());
-        } else {
-            removed_frames.push(frame_id.to_string());
+        match safe_frame(frame, &safe_frame_ids, config) {
+            Some(kept) => {
+                covers_changed |= kept != *frame;
+                kept_frames.push(frame_id.to_string());
+                new_tag.add_frame(kept);
+            }
+            None => removed_frames.push(frame_id.to_string()),
         }
     }
 
@@ -520,46 +1468,33 @@ fn strip_unsafe_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
     }
 
     // If no frames to remove, return original
-    if removed_frames.is_empty() && !has_id3v1(input) {
+    let single_tag = leading_id3v2_tags(input).len() == 1;
+    let untouched = single_tag && padding_matches(input, config) && audio_end(input) == input.len();
+    if removed_frames.is_empty() && !covers_changed && untouched {
         log::info!("No unsafe frames to remove");
         return Ok(input.to_vec());
     }
 
-    // Create new tag with only safe frames
-    let mut new_tag = Tag::new();
-
-    for frame in tag.frames() {
-        if safe_frame_ids.contains(frame.id()) {
-            new_tag.add_frame(frame.clone());
-        }
-    }
-
-    // Get audio data (skip old ID3v2, exclude ID3v1)
-    let id3v2_size = detect_id3v2_size(input);
+    // Get audio data (skip old ID3v2 tags, exclude trailing tags and ID3v1)
+    let id3v2_size = id3v2_end(input);
     let audio_start = id3v2_size;
-    let audio_end = if has_id3v1(input) {
-        input.len().saturating_sub(128)
-    } else {
-        input.len()
-    };
+    let audio_end = audio_end(input);
 
     if audio_start >= audio_end {
-        return Err(ProcessingError::Decode(
-            "Invalid MP3 structure: no audio data found".to_string(),
-        ));
+        return Err(ProcessingError::CorruptInput {
+            format: "mp3".to_string(),
+            offset: Some(audio_start as u64),
+            reason: "no audio data found".to_string(),
+        });
     }
 
     let audio_data = &input[audio_start..audio_end];
 
     // Write new tag + audio to buffer
-    let mut output = Vec::new();
-    new_tag
-        .write_to(&mut output, id3::Version::Id3v24)
-        .map_err(|e| ProcessingError::Encode(format!("Failed to write ID3 tag: {}", e)))?;
-
+    let mut output = write_tag(&new_tag, config)?;
     output.extend_from_slice(audio_data);
 
-    let original_metadata_size = id3v2_size + if has_id3v1(input) { 128 } else { 0 };
+    let original_metadata_size = id3v2_size + (input.len() - audio_end);
     let new_tag_size = output.len() - audio_data.len();
     let saved = (original_metadata_size as isize - new_tag_size as isize) as f64 / 1024.0;
 
@@ -580,6 +1515,76 @@ fn strip_unsafe_tags(input: &[u8]) -> Result<Vec<u8>, ProcessingError> {
     Ok(output)
 }
 
+/// Safe-mode copy of `frame`: safe frames and ReplayGain values as they are, cover
+/// art shrunk when `mp3.cover_art` keeps it (untouched with `no_lossy`), None for the rest
+fn safe_frame(frame: &Frame, safe_frame_ids: &HashSet<&str>, config: &ProcessingConfig) -> Option<Frame> {
+    if safe_frame_ids.contains(frame.id()) || is_replaygain(frame) {
+        return Some(frame.clone());
+    }
+    let (Some(cover), Content::Picture(picture)) = (&config.mp3.cover_art, frame.content()) else {
+        return None;
+    };
+    if config.no_lossy {
+        return Some(frame.clone());
+    }
+    match shrink_cover(picture, cover, config) {
+        Ok(picture) => Some(Frame::with_content(frame.id(), Content::Picture(picture))),
+        Err(e) => {
+            log::warn!("Could not recompress cover art, dropping it: {}", e);
+            None
+        }
+    }
+}
+
+/// `TXXX:REPLAYGAIN_*` frames, which only hold loudness values
+fn is_replaygain(frame: &Frame) -> bool {
+    frame
+        .content()
+        .extended_text()
+        .is_some_and(|text| text.description.to_ascii_uppercase().starts_with("REPLAYGAIN_"))
+}
+
+/// Downscale cover art to fit `cover.max_size` and re-encode it. Art that already
+/// fits keeps its original bytes unless re-encoding makes it smaller.
+fn shrink_cover(picture: &Picture, cover: &CoverArt, config: &ProcessingConfig) -> Result<Picture, ProcessingError> {
+    let img = decode_image(&picture.data, None, &config.limits)?;
+    let (width, height) = (img.width(), img.height());
+    let fits = width <= cover.max_size && height <= cover.max_size;
+    // Re-encoding a JPEG that is already at or below the target quality only adds artifacts
+    if fits
+        && cover.format == CoverFormat::Jpeg
+        && estimate_quality(&picture.data).is_some_and(|quality| quality <= config.quality_for(Format::Jpeg))
+    {
+        log::debug!("Cover art already a {}x{} JPEG at or below the target quality, keeping it", width, height);
+        return Ok(picture.clone());
+    }
+    let img = if fits { img } else { img.resize(cover.max_size, cover.max_size, FilterType::Lanczos3) };
+
+    let format = cover.format.format();
+    let data = encode_image(&img, format, config)?;
+    if fits && data.len() >= picture.data.len() {
+        log::debug!("Cover art already {}x{} and re-encoding does not shrink it, keeping it", width, height);
+        return Ok(picture.clone());
+    }
+
+    log::info!(
+        "Cover art: {}x{} {:.1} KB -> {}x{} {} {:.1} KB",
+        width,
+        height,
+        picture.data.len() as f64 / 1024.0,
+        img.width(),
+        img.height(),
+        format.as_str(),
+        data.len() as f64 / 1024.0
+    );
+    Ok(Picture {
+        mime_type: format.mime_type().to_string(),
+        picture_type: picture.picture_type,
+        description: picture.description.clone(),
+        data,
+    })
+}
+
 /// Returns the set of safe frame IDs to keep in Safe mode
 fn get_safe_frame_ids() -> HashSet<&'static str> {
     [
@@ -590,6 +1595,7 @@ fn get_safe_frame_ids() -> HashSet<&'static str> {
         "TDRC", // Recording time (ID3v2.4)
         "TCON", // Genre
         "TRCK", // Track number
+        "RVA2", // Relative volume (ReplayGain)
     ]
     .iter()
     .copied()
@@ -615,8 +1621,258 @@ fn detect_id3v2_size(input: &[u8]) -> usize {
         | ((input[8] as usize) << 7)
         | (input[9] as usize);
 
-    // Total size = header (10 bytes) + tag size
-    size + 10
+    // Total size = header (10 bytes) + tag size, plus the ID3v2.4 footer if flagged
+    let footer = if input[5] & 0x10 != 0 { 10 } else { 0 };
+    size + 10 + footer
+}
+
+/// Name and size of each tag in the file: leading ID3v2 tags, trailing tags and ID3v1
+pub(crate) fn metadata_tags(input: &[u8]) -> Vec<(String, u64)> {
+    let mut tags: Vec<(String, u64)> =
+        leading_id3v2_tags(input).into_iter().map(|tag| ("ID3v2".to_string(), tag.len() as u64)).collect();
+    tags.extend(trailing_tags(input).iter().rev().map(|tag| (tag.kind.as_str().to_string(), tag.len() as u64)));
+    if has_id3v1(input) {
+        tags.push(("ID3v1".to_string(), 128));
+    }
+    tags
+}
+
+/// Playing time from the Xing/Info header, else estimated from the first
+/// frame's bitrate (exact for CBR). None without MPEG frames.
+pub(crate) fn duration(input: &[u8]) -> Option<f64> {
+    xing_header(input).and_then(|xing| xing.duration()).or_else(|| {
+        let frame = first_frame(input)?;
+        let audio = audio_end(input).saturating_sub(frame.offset);
+        Some(audio as f64 * f64::from(frame.samples) / (frame.len as f64 * f64::from(frame.sample_rate)))
+    })
+}
+
+/// ID3v2 frames `--strip safe` removes plus APEv2 and Lyrics3 tags, which can
+/// hold anything
+pub(crate) fn unsafe_item_count(input: &[u8]) -> usize {
+    let safe_frames = get_safe_frame_ids();
+    let frames = read_id3v2(input)
+        .map(|tag| tag.frames().filter(|f| !safe_frames.contains(f.id()) && !is_replaygain(f)).count())
+        .unwrap_or(0);
+    frames + trailing_tags(input).iter().filter(|tag| tag.kind != TrailingKind::Id3v2).count()
+}
+
+/// Tag formats that sit between the audio and the ID3v1 tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TrailingKind {
+    /// A second ID3v2 tag appended after the audio
+    Id3v2,
+    Apev2,
+    Lyrics3v1,
+    Lyrics3v2,
+}
+
+impl TrailingKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TrailingKind::Id3v2 => "Appended ID3v2",
+            TrailingKind::Apev2 => "APEv2",
+            TrailingKind::Lyrics3v1 => "Lyrics3v1",
+            TrailingKind::Lyrics3v2 => "Lyrics3v2",
+        }
+    }
+}
+
+/// An APEv2 or Lyrics3 block at `start..end` of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TrailingTag {
+    kind: TrailingKind,
+    start: usize,
+    end: usize,
+}
+
+impl TrailingTag {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// Appended ID3v2, APEv2 and Lyrics3 blocks before the ID3v1 tag, last first.
+/// Older taggers chain them in any order, so this walks back until none ends
+/// where it looks, never reaching into the leading ID3v2 tags.
+fn trailing_tags(input: &[u8]) -> Vec<TrailingTag> {
+    let leading_end = id3v2_end(input);
+    let seek_target = seek_tag(input);
+    let mut tags = Vec::new();
+    let mut end = if has_id3v1(input) { input.len() - 128 } else { input.len() };
+    while let Some(tag) = ape_tag_ending_at(input, end)
+        .or_else(|| lyrics3_ending_at(input, end))
+        .or_else(|| id3v2_footer_ending_at(input, end))
+        .or_else(|| seek_target.filter(|tag| tag.end == end))
+        .filter(|tag| tag.start >= leading_end)
+    {
+        end = tag.start;
+        tags.push(tag);
+    }
+    tags
+}
+
+/// ID3v2 tags chained at the start of the file: some taggers prepend a new tag
+/// without removing the old one
+fn leading_id3v2_tags(input: &[u8]) -> Vec<Range<usize>> {
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    while let Some(size) = Some(detect_id3v2_size(&input[offset..])).filter(|&size| size > 0) {
+        let end = offset.saturating_add(size).min(input.len());
+        tags.push(offset..end);
+        offset = end;
+    }
+    tags
+}
+
+/// Zero bytes after the last frame of the first ID3v2 tag, found by walking the
+/// frame headers. None without a tag or when the frames run past its end.
+fn id3v2_padding(input: &[u8]) -> Option<usize> {
+    let size = detect_id3v2_size(input);
+    if size == 0 {
+        return None;
+    }
+    let version = input[3];
+    let flags = input[5];
+    // Frames stop at the footer, if there is one
+    let end = size.min(input.len()) - if flags & 0x10 != 0 { 10 } else { 0 };
+    let synchsafe = |b: &[u8]| b.iter().fold(0usize, |size, &b| (size << 7) | usize::from(b & 0x7F));
+    let mut pos = 10;
+    if flags & 0x40 != 0 && version >= 3 {
+        let header = input.get(10..14)?;
+        // v2.3 counts the extended header without its size field, v2.4 with it
+        pos += if version == 3 { 4 + u32::from_be_bytes(header.try_into().ok()?) as usize } else { synchsafe(header) };
+    }
+    let header_len = if version == 2 { 6 } else { 10 };
+    while pos + header_len <= end && input[pos] != 0 {
+        let frame_size = match version {
+            2 => (usize::from(input[pos + 3]) << 16) | (usize::from(input[pos + 4]) << 8) | usize::from(input[pos + 5]),
+            3 => u32::from_be_bytes(input[pos + 4..pos + 8].try_into().ok()?) as usize,
+            _ => synchsafe(&input[pos + 4..pos + 8]),
+        };
+        pos = pos.checked_add(header_len + frame_size)?;
+    }
+    (pos <= end).then(|| end - pos)
+}
+
+/// Where the leading ID3v2 tags stop
+fn id3v2_end(input: &[u8]) -> usize {
+    leading_id3v2_tags(input).last().map_or(0, |tag| tag.end)
+}
+
+/// Every ID3v2 tag in the file merged into one, later tags replacing earlier
+/// frames of the same kind. Err when there is none or the first cannot be parsed.
+fn read_id3v2(input: &[u8]) -> id3::Result<Tag> {
+    let mut tag = match Tag::read_from2(&mut Cursor::new(input)) {
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) && has_appended_id3v2(input) => Tag::new(),
+        result => result?,
+    };
+    let extra = leading_id3v2_tags(input).into_iter().skip(1).chain(
+        trailing_tags(input).into_iter().rev().filter(|t| t.kind == TrailingKind::Id3v2).map(|t| t.start..t.end),
+    );
+    for range in extra {
+        match Tag::read_from2(&mut Cursor::new(&input[range.clone()])) {
+            Ok(more) => {
+                log::debug!("Merging ID3v2 tag at offset {} ({} frames)", range.start, more.frames().count());
+                for frame in more.frames() {
+                    tag.add_frame(frame.clone());
+                }
+            }
+            Err(e) => log::debug!("Could not parse ID3v2 tag at offset {}: {}", range.start, e),
+        }
+    }
+    // The merged tag is written as one, so a pointer to the next tag is stale
+    tag.remove("SEEK");
+    Ok(tag)
+}
+
+/// [`read_id3v2`] for rewriting the tag: an empty one when there is none, an error
+/// when there is one that cannot be parsed (rewriting it would lose its frames)
+fn read_id3v2_or_new(input: &[u8]) -> Result<Tag, ProcessingError> {
+    match read_id3v2(input) {
+        Ok(tag) => Ok(tag),
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(Tag::new()),
+        Err(e) => Err(ProcessingError::Decode(format!("Failed to read ID3 tag: {}", e))),
+    }
+}
+
+fn has_appended_id3v2(input: &[u8]) -> bool {
+    trailing_tags(input).iter().any(|tag| tag.kind == TrailingKind::Id3v2)
+}
+
+/// ID3v2.4 tag whose `3DI` footer ends at `end`
+fn id3v2_footer_ending_at(input: &[u8], end: usize) -> Option<TrailingTag> {
+    let footer = input.get(end.checked_sub(10)?..end)?;
+    if !footer.starts_with(b"3DI") || footer[6..10].iter().any(|&b| b & 0x80 != 0) {
+        return None;
+    }
+    let size = footer[6..10].iter().fold(0usize, |size, &b| (size << 7) | usize::from(b));
+    let start = end.checked_sub(size + 20)?;
+    input[start..].starts_with(b"ID3").then_some(TrailingTag { kind: TrailingKind::Id3v2, start, end })
+}
+
+/// Tag a SEEK frame in the first ID3v2 tag points at (offset counted from the end
+/// of that tag), when there is an ID3v2 header there
+fn seek_tag(input: &[u8]) -> Option<TrailingTag> {
+    let first_end = detect_id3v2_size(input);
+    if first_end == 0 {
+        return None;
+    }
+    let tag = Tag::read_from2(&mut Cursor::new(input)).ok()?;
+    let Content::Unknown(seek) = tag.get("SEEK")?.content() else {
+        return None;
+    };
+    let offset = u32::from_be_bytes(seek.data.get(..4)?.try_into().ok()?) as usize;
+    let start = first_end.checked_add(offset)?;
+    let size = detect_id3v2_size(input.get(start..)?);
+    (size > 0).then_some(TrailingTag { kind: TrailingKind::Id3v2, start, end: (start + size).min(input.len()) })
+}
+
+/// Where the audio stops: before any trailing ID3v2, APEv2, Lyrics3 and ID3v1 tags
+fn audio_end(input: &[u8]) -> usize {
+    match trailing_tags(input).last() {
+        Some(tag) => tag.start,
+        None if has_id3v1(input) => input.len() - 128,
+        None => input.len(),
+    }
+}
+
+fn has_ape_footer(input: &[u8]) -> bool {
+    input.len() >= 32 && input[input.len() - 32..].starts_with(b"APETAGEX")
+}
+
+/// APEv2 (or v1) tag whose 32-byte footer ends at `end`; the optional header sits
+/// before the items and is not counted in the size field
+fn ape_tag_ending_at(input: &[u8], end: usize) -> Option<TrailingTag> {
+    let footer = input.get(end.checked_sub(32)?..end)?;
+    if !footer.starts_with(b"APETAGEX") {
+        return None;
+    }
+    let size = u32::from_le_bytes(footer[12..16].try_into().ok()?) as usize;
+    let flags = u32::from_le_bytes(footer[20..24].try_into().ok()?);
+    let header = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+    let start = end.checked_sub(size.checked_add(header)?)?;
+    (size >= 32).then_some(TrailingTag { kind: TrailingKind::Apev2, start, end })
+}
+
+/// Lyrics3v2 (`LYRICSBEGIN` ... 6-digit size + `LYRICS200`) or Lyrics3v1
+/// (`LYRICSBEGIN` ... `LYRICSEND`, at most 5100 bytes of lyrics) ending at `end`
+fn lyrics3_ending_at(input: &[u8], end: usize) -> Option<TrailingTag> {
+    let marker = input.get(end.checked_sub(9)?..end)?;
+    let (kind, start) = match marker {
+        b"LYRICS200" => {
+            let digits = input.get(end.checked_sub(15)?..end - 9)?;
+            let size: usize = std::str::from_utf8(digits).ok()?.parse().ok()?;
+            (TrailingKind::Lyrics3v2, end.checked_sub(15 + size)?)
+        }
+        b"LYRICSEND" => {
+            let from = end.saturating_sub(9 + 11 + 5100);
+            let begin = input[from..end - 9].windows(11).rposition(|w| w == b"LYRICSBEGIN")?;
+            (TrailingKind::Lyrics3v1, from + begin)
+        }
+        _ => return None,
+    };
+    input[start..].starts_with(b"LYRICSBEGIN").then_some(TrailingTag { kind, start, end })
 }
 
 /// Check if the file has an ID3v1 tag at the end (last 128 bytes start with "TAG")
@@ -648,6 +1904,231 @@ mod tests {
         assert_eq!(detect_id3v2_size(&data), 110); // 10 + 100
     }
 
+    #[test]
+    fn test_with_text_frames() {
+        let mut audio = vec![0xFF, 0xFB, 0x90, 0x00];
+        audio.extend_from_slice(&[0; 413]);
+        let set = vec![("TIT2".to_string(), "Dawn".to_string()), ("COMM".to_string(), "live".to_string())];
+        let tagged = with_text_frames(&audio, &set, &[]).unwrap();
+        assert_eq!(text_frames(&tagged), set);
+        assert!(tagged.ends_with(&audio));
+
+        let retitle = [("TIT2".to_string(), "Dusk".to_string())];
+        let edited = with_text_frames(&tagged, &retitle, &["COMM".to_string()]).unwrap();
+        assert_eq!(text_frames(&edited), retitle);
+        assert!(text_frames(&audio).is_empty());
+    }
+
+    #[test]
+    fn test_read_id3v2_or_new() {
+        let audio = vec![0xFF; 300];
+        assert_eq!(read_id3v2_or_new(&audio).unwrap().frames().count(), 0);
+        // A tag of an unknown version is not silently replaced by an empty one
+        let mut data = b"ID3\x09\x00\x00\x00\x00\x00\x10".to_vec();
+        data.extend(vec![0x41; 16]);
+        data.extend(&audio);
+        assert!(matches!(read_id3v2_or_new(&data), Err(ProcessingError::Decode(_))));
+        assert!(with_text_frames(&data, &[("TIT2".to_string(), "Dawn".to_string())], &[]).is_err());
+        assert!(with_extended_text(&data, "MARK", "1", &ProcessingConfig::default()).is_err());
+        let marked = with_extended_text(&audio, "MARK", "1", &ProcessingConfig::default()).unwrap();
+        assert_eq!(extended_text(&marked, "MARK").as_deref(), Some("1"));
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_vbr_level() {
+        assert_eq!(vbr_level(100), 0);
+        assert_eq!(vbr_level(80), 2);
+        assert_eq!(vbr_level(0), 9);
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_audible_span() {
+        let lead = Silence { start: 0.0, end: Some(1.5) };
+        let tail = Silence { start: 9.0, end: None };
+        assert_eq!(audible_span(&[lead, tail], 10.0), Some((1.5, 9.0)));
+        assert_eq!(audible_span(&[Silence { start: 4.0, end: Some(5.0) }], 10.0), None);
+        assert_eq!(audible_span(&[Silence { start: 0.0, end: None }], 10.0), None);
+
+        // 128 kbit/s, 44.1 kHz, MPEG 1 Layer III, padded
+        let frame = mpeg_frame(&[0xFF, 0xFB, 0x92, 0x64], 0).unwrap();
+        assert_eq!((frame.len, frame.samples, frame.sample_rate), (418, 1152, 44_100));
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_update_xing() {
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+
+        // Xing frame with counts, seek table, quality and a LAME extension, then four frames
+        let mut info = vec![0u8; 417];
+        info[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        info[36..44].copy_from_slice(b"Xing\0\0\0\x0F");
+        info[156..165].copy_from_slice(b"LAME3.100");
+        let header = [0xFF, 0xFB, 0x90, 0x64];
+        let mut music = Vec::new();
+        let mut frames = Vec::new();
+        for i in 0..4 {
+            frames.push(mpeg_frame(&header, 0).map(|f| MpegFrame { offset: 417 + 417 * i, ..f }).unwrap());
+            music.extend(header);
+            music.extend([i as u8; 413]);
+        }
+        update_xing(&mut info, 36, &frames, &music);
+
+        let mut data = info.clone();
+        data.extend(&music);
+        let xing = xing_header(&data).unwrap();
+        assert_eq!((xing.frames, xing.bytes), (Some(4), Some(5 * 417)));
+        // A quarter of the time per frame, a fifth of the bytes
+        assert_eq!((info[52], info[53], info[77], info[102], info[151]), (51, 51, 102, 153, 204));
+        assert_eq!(u32::from_be_bytes(info[184..188].try_into().unwrap()), 5 * 417);
+        assert_eq!(u16::from_be_bytes([info[188], info[189]]), crc16(&music));
+        assert_eq!(u16::from_be_bytes([info[190], info[191]]), crc16(&info[..190]));
+    }
+
+    #[test]
+    fn test_strip_keeps_lame_header() {
+        // ID3v2.4 tag with a footer, then an Info/LAME frame and ten 128 kbit/s frames
+        let mut data = b"ID3\x04\0\x10\0\0\0\x0A".to_vec();
+        data.extend([0; 10]);
+        data.extend(b"3DI\x04\0\x10\0\0\0\x0A");
+        let mut info = vec![0u8; 417];
+        info[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        info[36..52].copy_from_slice(b"Info\0\0\0\x03\0\0\0\x0A\0\0\x10\x4A");
+        info[52..61].copy_from_slice(b"LAME3.100");
+        info[73..76].copy_from_slice(&[0x24, 0x03, 0xE8]);
+        data.extend(&info);
+        for _ in 0..10 {
+            data.extend([0xFF, 0xFB, 0x90, 0x64]);
+            data.extend([0; 413]);
+        }
+
+        let xing = xing_header(&data).unwrap();
+        assert_eq!(xing.kind, "Info");
+        assert_eq!((xing.frames, xing.bytes), (Some(10), Some(4170)));
+        assert_eq!(xing.encoder.as_deref(), Some("LAME3.100"));
+        assert_eq!(xing.gapless, Some((576, 1000)));
+
+        let stripped = strip_all_tags(&data).unwrap();
+        assert_eq!(&stripped[..417], &info[..]);
+        assert_eq!(xing_header(&stripped), Some(XingHeader { frame: MpegFrame { offset: 0, ..xing.frame }, ..xing }));
+    }
+
+    #[test]
+    fn test_strip_ape_and_lyrics3() {
+        fn ape_frame(size: u32, flags: u32) -> Vec<u8> {
+            let mut frame = b"APETAGEX".to_vec();
+            frame.extend(2000u32.to_le_bytes());
+            frame.extend(size.to_le_bytes());
+            frame.extend(1u32.to_le_bytes());
+            frame.extend(flags.to_le_bytes());
+            frame.extend([0; 8]);
+            frame
+        }
+        let audio = vec![0xFF; 300];
+        let mut item = 7u32.to_le_bytes().to_vec();
+        item.extend([0; 4]);
+        item.extend(b"Artist\0Someone");
+        let size = (item.len() + 32) as u32;
+
+        let mut data = audio.clone();
+        data.extend(ape_frame(size, 0xA000_0000));
+        data.extend(&item);
+        data.extend(ape_frame(size, 0x8000_0000));
+        data.extend(b"LYRICSBEGININD0000211000021LYRICS200");
+        let mut v1 = vec![0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        data.extend(&v1);
+
+        let tags = trailing_tags(&data);
+        let kinds: Vec<TrailingKind> = tags.iter().map(|tag| tag.kind).collect();
+        assert_eq!(kinds, [TrailingKind::Lyrics3v2, TrailingKind::Apev2]);
+        assert_eq!(audio_end(&data), audio.len());
+        let ape = &data[tags[1].start..tags[1].end];
+        assert_eq!(ape_items(ape), [("Artist".to_string(), "Someone".to_string())]);
+
+        assert_eq!(strip_all_tags(&data).unwrap(), audio);
+        let config = ProcessingConfig { strip: StripMode::Safe, ..ProcessingConfig::default() };
+        assert_eq!(strip_unsafe_tags(&data, &config).unwrap(), audio);
+        let mut output = Vec::new();
+        strip_tags_stream(&mut data.as_slice(), &mut output, &config).unwrap();
+        assert_eq!(output, audio);
+    }
+
+    #[test]
+    fn test_chained_and_appended_id3v2() {
+        fn tag_bytes(id: &str, text: &str, version: id3::Version) -> Vec<u8> {
+            let mut tag = Tag::new();
+            tag.add_frame(Frame::text(id, text));
+            let mut out = Vec::new();
+            tag.write_to(&mut out, version).unwrap();
+            out
+        }
+        let audio = vec![0xFF; 300];
+        let mut data = tag_bytes("TIT2", "Old title", id3::Version::Id3v23);
+        data.extend(tag_bytes("TIT2", "New title", id3::Version::Id3v24));
+        let leading_end = data.len();
+        data.extend(&audio);
+        // Appended v2.4 tag with a footer, before ID3v1
+        let mut appended = tag_bytes("TPE1", "Artist", id3::Version::Id3v24);
+        appended[5] |= 0x10;
+        let mut footer = appended[..10].to_vec();
+        footer[..3].copy_from_slice(b"3DI");
+        appended.extend(footer);
+        data.extend(&appended);
+        let mut v1 = vec![0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        data.extend(&v1);
+
+        assert_eq!(id3v2_end(&data), leading_end);
+        let tags = trailing_tags(&data);
+        assert_eq!(tags.len(), 1);
+        assert_eq!((tags[0].kind, tags[0].len()), (TrailingKind::Id3v2, appended.len()));
+        assert_eq!(audio_end(&data), leading_end + audio.len());
+
+        assert_eq!(strip_all_tags(&data).unwrap(), audio);
+        let config = ProcessingConfig { strip: StripMode::Safe, ..ProcessingConfig::default() };
+        let output = strip_unsafe_tags(&data, &config).unwrap();
+        let tag = Tag::read_from2(&mut Cursor::new(&output)).unwrap();
+        assert_eq!((tag.title(), tag.artist()), (Some("New title"), Some("Artist")));
+        assert_eq!(&output[detect_id3v2_size(&output)..], audio);
+
+        let mut streamed = Vec::new();
+        strip_tags_stream(&mut data.as_slice(), &mut streamed, &config).unwrap();
+        assert_eq!(streamed, output);
+
+        // An appended tag alone becomes the leading one
+        let mut data = audio.clone();
+        data.extend(&appended);
+        let mut streamed = Vec::new();
+        strip_tags_stream(&mut data.as_slice(), &mut streamed, &config).unwrap();
+        let tag = Tag::read_from2(&mut Cursor::new(&streamed)).unwrap();
+        assert_eq!((tag.title(), tag.artist()), (None, Some("Artist")));
+        assert_eq!(&streamed[detect_id3v2_size(&streamed)..], audio);
+    }
+
+    #[test]
+    fn test_id3_padding() {
+        let mut tag = Tag::new();
+        tag.set_title("Title");
+        tag.add_frame(Frame::text("TENC", "Some encoder"));
+        let mut data = Vec::new();
+        id3::Encoder::new().version(id3::Version::Id3v23).padding(100).encode(&tag, &mut data).unwrap();
+        assert_eq!(id3v2_padding(&data), Some(100));
+        data.extend(vec![0xFF; 300]);
+
+        let mut config = ProcessingConfig { strip: StripMode::Safe, ..ProcessingConfig::default() };
+        let output = strip_unsafe_tags(&data, &config).unwrap();
+        assert_eq!(id3v2_padding(&output), Some(0));
+
+        config.mp3.id3_padding = Some(4096);
+        let output = strip_unsafe_tags(&data, &config).unwrap();
+        assert_eq!(id3v2_padding(&output), Some(4096));
+        // A tag with the requested padding and only safe frames is kept byte for byte
+        assert_eq!(strip_unsafe_tags(&output, &config).unwrap(), output);
+    }
+
     #[test]
     fn test_has_id3v1_no_tag() {
         let data = vec![0xFF; 200];
@@ -664,6 +2145,61 @@ mod tests {
         assert!(has_id3v1(&data));
     }
 
+    #[test]
+    fn test_strip_tags_stream_all() {
+        let mut data = vec![b'I', b'D', b'3', 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A];
+        data.extend(vec![0; 10]); // Tag data
+        let audio = vec![0xFF; 300];
+        data.extend(&audio);
+        let mut v1 = vec![0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        data.extend(&v1);
+
+        let mut output = Vec::new();
+        let config = ProcessingConfig { strip: StripMode::All, ..ProcessingConfig::default() };
+        strip_tags_stream(&mut data.as_slice(), &mut output, &config).unwrap();
+        assert_eq!(output, audio);
+    }
+
+    #[test]
+    fn test_strip_tags_stream_none() {
+        let data = vec![0xFF; 50];
+        let mut output = Vec::new();
+        let config = ProcessingConfig { strip: StripMode::None, ..ProcessingConfig::default() };
+        strip_tags_stream(&mut data.as_slice(), &mut output, &config).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_safe_mode_shrinks_cover_art() {
+        let cover = image::DynamicImage::new_rgb8(1200, 800);
+        let mut png = Vec::new();
+        cover.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let mut tag = Tag::new();
+        tag.set_title("Song");
+        tag.add_frame(Picture {
+            mime_type: "image/png".to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data: png,
+        });
+        let mut data = Vec::new();
+        tag.write_to(&mut data, id3::Version::Id3v24).unwrap();
+        data.extend(vec![0xFF; 300]);
+
+        let mut config = ProcessingConfig { strip: StripMode::Safe, ..ProcessingConfig::default() };
+        let dropped = Tag::read_from2(&mut Cursor::new(strip_unsafe_tags(&data, &config).unwrap())).unwrap();
+        assert_eq!(dropped.pictures().count(), 0);
+
+        config.mp3.cover_art = Some(CoverArt::default());
+        let kept = Tag::read_from2(&mut Cursor::new(strip_unsafe_tags(&data, &config).unwrap())).unwrap();
+        let picture = kept.pictures().next().unwrap();
+        assert_eq!(picture.mime_type, "image/jpeg");
+        let img = image::load_from_memory(&picture.data).unwrap();
+        assert_eq!((img.width(), img.height()), (600, 400));
+        assert_eq!(kept.title(), Some("Song"));
+    }
+
     #[test]
     fn test_get_safe_frame_ids() {
         let safe = get_safe_frame_ids();
@@ -672,5 +2208,14 @@ mod tests {
         assert!(safe.contains("TALB"));
         assert!(!safe.contains("APIC"));
         assert!(!safe.contains("COMM"));
+        assert!(safe.contains("RVA2"));
+
+        let gain = Frame::from(id3::frame::ExtendedText {
+            description: "REPLAYGAIN_TRACK_GAIN".to_string(),
+            value: "-6.20 dB".to_string(),
+        });
+        assert!(is_replaygain(&gain));
+        let other = Frame::from(id3::frame::ExtendedText { description: "URL".to_string(), value: String::new() });
+        assert!(!is_replaygain(&other));
     }
 }
@@ -1,25 +1,113 @@
-use std::io::Cursor;
+use std::io::{self, Cursor, Read, Write};
+use std::time::Duration;
+#[cfg(feature = "ffmpeg")]
 use std::process::Command;
 
-use crate::config::{ProcessingConfig, StripMode};
+use crate::config::{AudioTrack, HwAccel, ProcessingConfig, StripMode, TimeRange};
+#[cfg(feature = "ffmpeg")]
+use crate::config::VideoCodec;
+#[cfg(feature = "ffmpeg")]
+use crate::ffmpeg::{
+    self, ensure_encoder, ffmpeg_encoders, ffmpeg_failed, read_ffmpeg_result, FfmpegTempFiles,
+};
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
-use crate::processor::ImageProcessor;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::outcome::ProcessOutcome;
+pub use crate::processor::is_ffmpeg_available;
+#[cfg(not(feature = "ffmpeg"))]
+use crate::processor::process_buffered;
+use crate::processor::{Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
 
 pub struct Mp4Processor;
 
+/// Which frames [`extract_frames`] picks from the video
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSelection {
+    /// N frames per second; 0 keeps every frame
+    Fps(f32),
+    /// Every Nth decoded frame, starting with the first
+    EveryNth(u32),
+    /// One frame per scene: the first frame whose scene-change score exceeds
+    /// the threshold (0.0–1.0; around 0.3 suits most footage), plus the opening frame
+    Scenes(f32),
+}
+
+/// Options for [`extract_frames`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameExtraction {
+    pub selection: FrameSelection,
+    /// PNG, JPEG or WebP
+    pub format: Format,
+    /// Quality 0–100 for JPEG and WebP frames, and for PNG frames when optimizing
+    pub quality: u8,
+    /// Only extract frames from this segment
+    pub range: Option<TimeRange>,
+    /// Stream frames out of ffmpeg and compress them in parallel as they arrive
+    /// (PNG quantization and oxipng, or our own JPEG/WebP encoders) instead of
+    /// writing ffmpeg's output as is
+    pub optimize: bool,
+    /// Stop after this many frames
+    pub max_frames: Option<u32>,
+}
+
+impl Default for FrameExtraction {
+    fn default() -> Self {
+        Self {
+            selection: FrameSelection::Fps(1.0),
+            format: Format::Png,
+            quality: 85,
+            range: None,
+            optimize: false,
+            max_frames: None,
+        }
+    }
+}
+
 /// Extract frames from MP4 video to PNG images
+#[cfg(feature = "ffmpeg")]
 pub fn extract_frames_to_png(
     input_path: &std::path::Path,
     output_dir: &std::path::Path,
     fps: f32,
+) -> Result<usize, ProcessingError> {
+    let options = FrameExtraction {
+        selection: FrameSelection::Fps(fps),
+        ..FrameExtraction::default()
+    };
+    extract_frames(input_path, output_dir, &options)
+}
+
+/// Extract frames from MP4 video as PNG, JPEG or WebP images into
+/// `{output_dir}/{video_name}_frames/`.
+#[cfg(feature = "ffmpeg")]
+pub fn extract_frames(
+    input_path: &std::path::Path,
+    output_dir: &std::path::Path,
+    options: &FrameExtraction,
 ) -> Result<usize, ProcessingError> {
     use std::fs;
 
+    let frame_format = options.format;
+    let quality = options.quality;
+    let selection = selection_args(options.selection)?;
+    if let Some(TimeRange { start, end: Some(end) }) = options.range {
+        if end <= start {
+            return Err(ProcessingError::InvalidConfig("frame range ends before it starts".to_string()));
+        }
+    }
+    if !frame_format.is_image() {
+        return Err(ProcessingError::UnsupportedFormat(format!(
+            "cannot extract frames as {}",
+            frame_format.as_str()
+        )));
+    }
     if !is_ffmpeg_available() {
-        return Err(ProcessingError::Encode(
-            "ffmpeg not found - frame extraction requires ffmpeg".to_string(),
-        ));
+        return Err(ProcessingError::FfmpegMissing);
+    }
+    if frame_format == Format::Webp {
+        ensure_encoder("libwebp")?;
     }
 
     // Create output directory for frames
@@ -33,32 +121,56 @@ pub fn extract_frames_to_png(
         .map_err(|e| ProcessingError::Encode(format!("Failed to create frames directory: {}", e)))?;
 
     // Build ffmpeg command
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = ffmpeg::command();
+    if let Some(range) = &options.range {
+        cmd.args(seek_args(range));
+    }
     cmd.arg("-i").arg(input_path);
     cmd.arg("-y"); // Overwrite output files
 
-    // Frame extraction filter
-    if fps > 0.0 {
-        // Extract N frames per second
-        cmd.arg("-vf").arg(format!("fps={}", fps));
+    cmd.args(&selection);
+    if let Some(max_frames) = options.max_frames {
+        cmd.arg("-frames:v").arg(max_frames.to_string());
     }
-    // If fps == 0, extract all frames (no filter)
 
-    // Output format
-    let output_pattern = frames_dir.join("frame_%04d.png");
+    // ffmpeg's AV1 still-image encoders are optional builds, so AVIF frames are always encoded here
+    if options.optimize || frame_format == Format::Avif {
+        // Uncompressed PNGs: they only cross a pipe before being re-encoded
+        cmd.args(["-f", "image2pipe", "-c:v", "png", "-compression_level", "0", "pipe:1"]);
+        log::debug!("Extracting frames: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+        let (output, frame_count) = ffmpeg::run_with_stdout(&mut cmd, |stream| {
+            let workers = std::thread::available_parallelism().map_or(1, usize::from);
+            optimize_frames(stream, &frames_dir, options, workers)
+        })?;
+        if !output.status.success() {
+            return Err(ffmpeg_failed(&output));
+        }
+        log::info!("Extracted and optimized {} frames to {}", frame_count, frames_dir.display());
+        return Ok(frame_count);
+    }
+
+    // Output format and quality
+    match frame_format {
+        Format::Jpeg => {
+            // mjpeg qscale: 2 (best) to 31 (worst)
+            let qscale = 2 + u32::from(100 - quality.min(100)) * 29 / 100;
+            cmd.args(["-q:v", &qscale.to_string()]);
+        }
+        Format::Webp => {
+            cmd.args(["-c:v", "libwebp", "-lossless", "0", "-q:v", &quality.min(100).to_string()]);
+        }
+        _ => {}
+    }
+    let output_pattern = frames_dir.join(format!("frame_%04d.{}", frame_format.extension()));
     cmd.arg(output_pattern);
 
     // Execute ffmpeg
     log::debug!("Extracting frames: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
 
-    let output = cmd
-        .output()
-        .map_err(|e| ProcessingError::Encode(format!("Failed to execute ffmpeg: {}", e)))?;
+    let output = ffmpeg::run(&mut cmd)?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log::error!("ffmpeg failed: {}", stderr);
-        return Err(ProcessingError::Encode(format!("ffmpeg failed: {}", stderr)));
+        return Err(ffmpeg_failed(&output));
     }
 
     // Count extracted frames
@@ -69,7 +181,7 @@ pub fn extract_frames_to_png(
             entry.path()
                 .extension()
                 .and_then(|ext| ext.to_str())
-                .map(|ext| ext == "png")
+                .map(|ext| ext == frame_format.extension())
                 .unwrap_or(false)
         })
         .count();
@@ -83,85 +195,296 @@ pub fn extract_frames_to_png(
     Ok(frame_count)
 }
 
-/// Display all metadata from an MP4 file
-pub fn inspect_mp4(input: &[u8]) -> Result<(), ProcessingError> {
-    println!("\n═══════════════════════════════════════════════════════");
-    println!("                 MP4 Metadata Inspection");
-    println!("═══════════════════════════════════════════════════════\n");
+/// Compress the PNG frames ffmpeg writes to `stream` on `workers` threads,
+/// saving them as `frame_0001.{ext}`, ... in `frames_dir`. At most one frame
+/// per worker waits in memory, so ffmpeg is throttled to the encoders' pace.
+/// The first failure stops reading and is returned.
+#[cfg(feature = "ffmpeg")]
+fn optimize_frames(
+    stream: &mut dyn Read,
+    frames_dir: &std::path::Path,
+    options: &FrameExtraction,
+    workers: usize,
+) -> Result<usize, ProcessingError> {
+    use std::sync::{mpsc, Mutex};
+
+    let config = ProcessingConfig { quality: options.quality, ..ProcessingConfig::default() };
+    let (sender, receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(workers);
+    let receiver = Mutex::new(receiver);
+    let failure = Mutex::new(None);
+
+    let frame_count = std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let next = receiver.lock().unwrap().recv();
+                let Ok((number, frame)) = next else { break };
+                // After a failure keep draining, so a send already under way
+                // can't block on a full channel with nobody receiving
+                if failure.lock().unwrap().is_some() {
+                    continue;
+                }
+                let path = frames_dir.join(format!("frame_{:04}.{}", number, options.format.extension()));
+                let written = optimize_frame(&frame, options.format, &config)
+                    .and_then(|data| {
+                        std::fs::write(&path, data).map_err(|source| ProcessingError::WriteFile { path, source })
+                    });
+                if let Err(e) = written {
+                    failure.lock().unwrap().get_or_insert(e);
+                }
+            });
+        }
+
+        let mut count = 0;
+        while failure.lock().unwrap().is_none() {
+            match read_png_frame(stream) {
+                Ok(Some(frame)) => {
+                    count += 1;
+                    if sender.send((count, frame)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    failure.lock().unwrap().get_or_insert(e);
+                    break;
+                }
+            }
+        }
+        drop(sender);
+        count
+    });
+
+    match failure.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(frame_count),
+    }
+}
+
+/// One extracted frame in its final format
+#[cfg(feature = "ffmpeg")]
+fn optimize_frame(png: &[u8], format: Format, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    match format {
+        Format::Png => crate::processor::png::PngProcessor.process(png, config),
+        format => {
+            let image = crate::limits::decode_image(png, None, &config.limits)?;
+            crate::converter::encode_image(&image, format, config)
+        }
+    }
+}
+
+/// The next image from a stream of back-to-back PNGs (ffmpeg's `image2pipe`),
+/// read chunk by chunk up to IEND; None at the end of the stream
+#[cfg(feature = "ffmpeg")]
+fn read_png_frame(stream: &mut dyn Read) -> Result<Option<Vec<u8>>, ProcessingError> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let truncated = |offset: usize, reason: &str| ProcessingError::CorruptInput {
+        format: "PNG".to_string(),
+        offset: Some(offset as u64),
+        reason: reason.to_string(),
+    };
+
+    let mut frame = vec![0u8; SIGNATURE.len()];
+    let mut filled = 0;
+    while filled < frame.len() {
+        match stream.read(&mut frame[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(truncated(filled, "stream ends inside the PNG signature")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(ProcessingError::Io(e)),
+        }
+    }
+    if frame != SIGNATURE {
+        return Err(truncated(0, "not a PNG signature"));
+    }
 
-    let file_size = input.len();
-    println!("File size: {} bytes ({:.2} MB)\n", file_size, file_size as f64 / 1024.0 / 1024.0);
+    loop {
+        let start = frame.len();
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).map_err(|_| truncated(start, "stream ends before IEND"))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        if length > 0x7FFF_FFFF {
+            return Err(truncated(start, "chunk length out of range"));
+        }
+        frame.extend_from_slice(&header);
+        // Chunk data plus CRC
+        let body = frame.len();
+        frame.resize(body + length as usize + 4, 0);
+        stream.read_exact(&mut frame[body..]).map_err(|_| truncated(start, "stream ends inside a chunk"))?;
+        if &header[4..] == b"IEND" {
+            return Ok(Some(frame));
+        }
+    }
+}
 
+/// Inspection report of an MP4 file: brands, movie header, tracks, metadata
+/// boxes and layout, plus codec details, tags and chapters from ffprobe when it
+/// is installed
+pub fn inspect_mp4(input: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Mp4, input.len());
     let mut reader = Cursor::new(input);
 
     match mp4::Mp4Reader::read_header(&mut reader, input.len() as u64) {
         Ok(mp4) => {
             // File type info
-            println!("File Type:");
-            println!("───────────────────────────────────────────────────────");
-            println!("  Major brand: {}", mp4.ftyp.major_brand);
-            println!("  Minor version: {}", mp4.ftyp.minor_version);
-            println!("  Compatible brands: {:?}\n", mp4.ftyp.compatible_brands);
+            let mut section = Section::new("File Type");
+            section.field("Major brand", mp4.ftyp.major_brand);
+            section.field("Minor version", mp4.ftyp.minor_version);
+            section.field("Compatible brands", format!("{:?}", mp4.ftyp.compatible_brands));
+            report.sections.push(section);
 
             // Movie header info
-            println!("Movie Header:");
-            println!("───────────────────────────────────────────────────────");
-            println!("  Duration: {:.2} seconds", mp4.duration().as_secs_f64());
-            println!("  Timescale: {}", mp4.timescale());
-            println!("  Fragmented: {}", mp4.is_fragmented());
-            println!("  Tracks: {}\n", mp4.tracks().len());
+            let mut section = Section::new("Movie Header");
+            section.field("Duration", format!("{:.2} seconds", mp4.duration().as_secs_f64()));
+            section.field("Timescale", mp4.timescale());
+            section.field("Fragmented", mp4.is_fragmented());
+            if mp4.is_fragmented() {
+                section.field("Fragments", mp4.moofs.len());
+                section.field("Random access index (mfra)", has_top_level_box(input, b"mfra"));
+                if let Some(duration) = fragmented_duration(&mp4) {
+                    section.field("Fragmented duration", format!("{:.2} seconds", duration.as_secs_f64()));
+                }
+            }
+            section.field("Tracks", mp4.tracks().len());
+            report.sections.push(section);
 
             // Tracks info
-            println!("Tracks:");
-            println!("───────────────────────────────────────────────────────");
+            let mut section = Section::new("Tracks");
             for track in mp4.tracks().values() {
-                println!("  Track #{}", track.track_id());
+                let mut entry = Entry::new(format!("Track #{}", track.track_id()));
 
                 if let Ok(track_type) = track.track_type() {
-                    println!("      Type: {:?}", track_type);
-                    println!("      Codec: {:?}", track.media_type());
-                    println!("      Duration: {:.2}s", track.duration().as_secs_f64());
-
-                    let bitrate = track.bitrate();
-                    println!("      Bitrate: {} kbps", bitrate / 1000);
+                    entry.field("Type", format!("{:?}", track_type));
+                    entry.field("Codec", format!("{:?}", track.media_type()));
+                    entry.field("Duration", format!("{:.2}s", track.duration().as_secs_f64()));
+                    entry.field("Bitrate", format!("{} kbps", track.bitrate() / 1000));
 
                     if track_type == mp4::TrackType::Video {
-                        println!("      Width: {}", track.width());
-                        println!("      Height: {}", track.height());
-                        let fps = track.frame_rate();
-                        println!("      Frame rate: {:.2} fps", fps);
+                        entry.field("Width", track.width());
+                        entry.field("Height", track.height());
+                        if let Some(hdr) = detect_hdr(input) {
+                            let mut extras = Vec::new();
+                            if hdr.mastering_display {
+                                extras.push("mastering display");
+                            }
+                            if hdr.content_light {
+                                extras.push("content light level");
+                            }
+                            if extras.is_empty() {
+                                entry.field("HDR", hdr.transfer.as_str());
+                            } else {
+                                entry.field("HDR", format!("{} with {}", hdr.transfer.as_str(), extras.join(", ")));
+                            }
+                        }
+                        let rotation = track_rotation(track);
+                        if rotation != 0 {
+                            let displayed = format!("{}x{}", track.height(), track.width());
+                            entry.field("Rotation", format!("{}° (displayed as {})", rotation, displayed));
+                        }
+                        entry.field("Frame rate", format!("{:.2} fps", track.frame_rate()));
                     } else if track_type == mp4::TrackType::Audio {
                         // Audio-specific info
                         if let Ok(config) = track.channel_config() {
-                            println!("      Channel config: {:?}", config);
+                            entry.field("Channel config", format!("{:?}", config));
                         }
                     }
                 }
-
-                println!();
+                section.entries.push(entry);
             }
-
-            // Metadata
-            println!("Metadata:");
-            println!("───────────────────────────────────────────────────────");
-            println!("  Note: Detailed metadata inspection requires manual box parsing");
-            println!("  The file may contain user data (udta) and metadata (meta) boxes\n");
+            report.sections.push(section);
+
+            // Metadata boxes, all removed by --strip safe and all
+            let mut section = Section::new("Metadata");
+            for (kind, size) in metadata_boxes(input) {
+                let mut entry = Entry::new(String::from_utf8_lossy(&kind));
+                entry.description = Some(if &kind == b"udta" { "User data" } else { "Metadata" }.to_string());
+                entry.class = Some(EntryClass::Unsafe);
+                entry.size = Some(size);
+                section.entries.push(entry);
+            }
+            section.summary = Some(format!("{} metadata box(es)", section.entries.len()));
+            report.sections.push(section);
 
             // File structure
-            println!("File Structure:");
-            println!("───────────────────────────────────────────────────────");
-            println!("  Fast start optimized: {}",
-                     check_fast_start(input).unwrap_or(false));
-
+            let mut section = Section::new("File Structure");
+            section.field("Fast start", check_fast_start(input).unwrap_or(false));
+            report.sections.push(section);
         }
+        Err(e) => report.errors.push(format!("Could not parse MP4 file: {}", e)),
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    report.sections.extend(ffprobe_section(input));
+
+    Ok(report)
+}
+
+/// Codec profiles, color information, tags and chapters from ffprobe, when installed
+#[cfg(feature = "ffmpeg")]
+fn ffprobe_section(input: &[u8]) -> Option<Section> {
+    let temp = FfmpegTempFiles::new();
+    std::fs::write(&temp.input, input).ok()?;
+    let info = match ffmpeg::ffprobe(&temp.input) {
+        Ok(info) => info,
+        Err(ProcessingError::FfmpegMissing) => return None,
         Err(e) => {
-            println!("Could not parse MP4 file: {}", e);
+            log::debug!("ffprobe failed: {}", e);
+            return None;
         }
+    };
+
+    let mut section = Section::new("ffprobe");
+    section.field("Container", &info.format_name);
+    if let Some(bit_rate) = info.bit_rate {
+        section.field("Bitrate", format!("{} kbps", bit_rate / 1000));
+    }
+    for (key, value) in &info.tags {
+        section.field(key, value);
     }
 
-    println!("\n═══════════════════════════════════════════════════════\n");
+    for stream in &info.streams {
+        let mut entry = Entry::new(format!("Stream #{} ({})", stream.index, stream.codec_type));
+        let codec = stream.codec_name.as_deref().unwrap_or("?");
+        match (&stream.profile, stream.level) {
+            (Some(profile), Some(level)) => entry.field("Codec", format!("{} ({}, level {})", codec, profile, level)),
+            (Some(profile), None) => entry.field("Codec", format!("{} ({})", codec, profile)),
+            _ => entry.field("Codec", codec),
+        }
+        if let Some(pix_fmt) = &stream.pix_fmt {
+            entry.field("Pixel format", pix_fmt);
+        }
+        let color: Vec<String> = [
+            ("space", &stream.color_space),
+            ("range", &stream.color_range),
+            ("transfer", &stream.color_transfer),
+            ("primaries", &stream.color_primaries),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{} {}", name, value.as_deref()?)))
+        .collect();
+        if !color.is_empty() {
+            entry.field("Color", color.join(", "));
+        }
+        if let (Some(rate), Some(channels)) = (stream.sample_rate, stream.channels) {
+            entry.field("Audio", format!("{} Hz, {} channel(s)", rate, channels));
+        }
+        if let Some(bit_rate) = stream.bit_rate {
+            entry.field("Bitrate", format!("{} kbps", bit_rate / 1000));
+        }
+        for (key, value) in &stream.tags {
+            entry.field(key, value);
+        }
+        section.entries.push(entry);
+    }
 
-    Ok(())
+    if !info.chapters.is_empty() {
+        let mut entry = Entry::new("Chapters");
+        for chapter in &info.chapters {
+            entry.field(format!("{:.2}s - {:.2}s", chapter.start, chapter.end), chapter.title.as_deref().unwrap_or(""));
+        }
+        section.entries.push(entry);
+    }
+    Some(section)
 }
 
 /// Check if MP4 has moov box before mdat (fast start)
@@ -204,161 +527,2545 @@ fn check_fast_start(input: &[u8]) -> Result<bool, ProcessingError> {
 }
 
 impl ImageProcessor for Mp4Processor {
-    fn supported_formats(&self) -> &[ImageFormat] {
-        &[ImageFormat::Mp4]
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Mp4]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: true,
+            needs_ffmpeg: true,
+            ..Capabilities::default()
+        }
     }
 
     fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
-        // Parse MP4 to validate
-        let mut reader = Cursor::new(input);
-        let mp4 = mp4::Mp4Reader::read_header(&mut reader, input.len() as u64)
-            .map_err(|e| ProcessingError::Decode(e.to_string()))?;
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let header = validate_mp4(input)?;
 
-        log::debug!("Processing MP4: {} tracks, {:.2}s duration",
-                   mp4.tracks().len(),
-                   mp4.duration().as_secs_f64());
+        if config.no_lossy {
+            // Lossless mode: strip metadata and move moov to the front, no ffmpeg needed
+            log::debug!("MP4 lossless mode: remuxing without re-encoding");
+            let mut warnings = Vec::new();
+            progress.start(Stage::Strip);
+            let data = if config.mp4.defragment && is_fragmented(input) {
+                if is_ffmpeg_available() {
+                    defragment_mp4(input, config)?
+                } else {
+                    warnings.push("ffmpeg not found, fragmented MP4 left fragmented".to_string());
+                    faststart_mp4(input, config.strip)?
+                }
+            } else {
+                faststart_mp4(input, config.strip)?
+            };
+            progress.finish(Stage::Strip);
+
+            let mut outcome = ProcessOutcome::new(data, Format::Mp4);
+            outcome.warnings = warnings;
+            outcome.metadata_removed = Some(input.len().saturating_sub(outcome.data.len()) as u64);
+            outcome.dimensions = header.dimensions;
+            outcome.bitrate = header.video_bitrate.or_else(|| average_bitrate(outcome.data.len(), header.duration));
+            if config.mp4.audio != AudioTrack::Aac {
+                outcome.warnings.push("audio options only apply when re-encoding; audio left as is".to_string());
+            }
+            if config.mp4.trim.is_some() {
+                outcome.warnings.push("trimming needs re-encoding; video left at full length".to_string());
+            }
+            return Ok(outcome);
+        }
 
-        // Check if ffmpeg is available
         if !is_ffmpeg_available() {
-            log::warn!("ffmpeg not found - MP4 compression requires ffmpeg to be installed");
-            log::warn!("Install: brew install ffmpeg (macOS) or apt install ffmpeg (Linux)");
-            return Ok(input.to_vec());
+            return Ok(ffmpeg_missing(input, header.dimensions));
         }
 
+        // Lossy mode: re-encode with compression
+        log::debug!("MP4 lossy mode: re-encoding with quality {}", config.quality_for(Format::Mp4));
+        let data = compress_mp4_with_ffmpeg(input, config, encoded_duration(config, &header), progress)?;
+        Ok(encoded_outcome(input, data, config, &header))
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let options = &config.mp4;
         if config.no_lossy {
-            // Lossless mode: only strip metadata using ffmpeg
-            log::debug!("MP4 lossless mode: stripping metadata only");
-            compress_mp4_with_ffmpeg(input, config, true)
+            let mut plan =
+                ProcessorPlan::new("remux without re-encoding: strip metadata, move the index to the front");
+            if options.defragment && is_fragmented(input) {
+                plan.ffmpeg.push("defragmenting");
+            }
+            return plan;
+        }
+
+        let mut plan = ProcessorPlan::new("re-encode with ffmpeg");
+        plan.ffmpeg.push("re-encoding");
+        plan.setting("codec", options.codec.as_str());
+        if options.hwaccel != HwAccel::None {
+            plan.setting("hardware encoder", format!("{:?}", options.hwaccel).to_lowercase());
+        }
+        match options.video_bitrate {
+            Some(bps) => {
+                let passes = if options.two_pass { ", two-pass" } else { "" };
+                plan.setting("video bitrate", format!("{} kbit/s{}", bps / 1000, passes));
+            }
+            #[cfg(feature = "ffmpeg")]
+            None => plan.setting("CRF", effective_crf(config)),
+            #[cfg(not(feature = "ffmpeg"))]
+            None => plan.setting("quality", config.quality_for(Format::Mp4)),
+        }
+        plan.setting("speed", config.speed);
+        let audio = match options.audio {
+            AudioTrack::Aac => format!("AAC {} kbit/s", options.audio_bitrate),
+            AudioTrack::Copy => "copied".to_string(),
+            AudioTrack::Drop => "removed".to_string(),
+        };
+        plan.setting("audio", audio);
+        if let Some((width, height)) = options.scale {
+            plan.setting("scale", format!("{}x{}", width, height));
+        } else if let Some(height) = options.max_height {
+            plan.setting("max height", height);
+        }
+        if let Some(trim) = &options.trim {
+            let end = trim.end.map_or("end".to_string(), |end| format!("{:.1}s", end.as_secs_f64()));
+            plan.setting("trim", format!("{:.1}s to {}", trim.start.as_secs_f64(), end));
+        }
+        if options.force_sdr {
+            plan.setting("HDR", "tone-mapped to SDR");
+        }
+        if !is_ffmpeg_available() {
+            plan.skip = Some("ffmpeg not found, the video would be left unchanged".to_string());
+        }
+        plan
+    }
+
+    /// Lossless mode strips metadata box-by-box (without the faststart move, which
+    /// needs the whole file); anything ffmpeg runs on is spooled to its temp file.
+    fn supports_streaming(&self, config: &ProcessingConfig) -> bool {
+        cfg!(feature = "ffmpeg") || (config.no_lossy && !config.mp4.defragment)
+    }
+
+    fn process_stream(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        if config.no_lossy && !config.mp4.defragment {
+            strip_mp4_stream(input, output, config.strip)
         } else {
-            // Lossy mode: re-encode with compression
-            log::debug!("MP4 lossy mode: re-encoding with quality {}", config.quality);
-            compress_mp4_with_ffmpeg(input, config, false)
+            process_spooled(input, output, config)
         }
     }
 }
 
-/// Check if ffmpeg is available in the system
-fn is_ffmpeg_available() -> bool {
-    Command::new("ffmpeg")
-        .arg("-version")
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+/// What [`Mp4Processor`] reports when ffmpeg is missing: the input, untouched
+fn ffmpeg_missing(input: &[u8], dimensions: Option<(u32, u32)>) -> ProcessOutcome<'_> {
+    log::warn!("ffmpeg not found - MP4 compression requires ffmpeg to be installed");
+    log::warn!("Install: brew install ffmpeg (macOS) or apt install ffmpeg (Linux)");
+    let mut outcome = ProcessOutcome::unchanged(input, Format::Mp4);
+    outcome.dimensions = dimensions;
+    outcome.warnings.push("ffmpeg not found, file left unchanged".to_string());
+    outcome
 }
 
-/// Compress MP4 using ffmpeg
-fn compress_mp4_with_ffmpeg(input: &[u8], config: &ProcessingConfig, lossless: bool) -> Result<Vec<u8>, ProcessingError> {
-    use std::io::Write;
-
-    // Create temporary files
-    let temp_dir = std::env::temp_dir();
-    let input_path = temp_dir.join(format!("input_{}.mp4", std::process::id()));
-    let output_path = temp_dir.join(format!("output_{}.mp4", std::process::id()));
-
-    // Write input to temp file
-    let mut input_file = std::fs::File::create(&input_path)
-        .map_err(|e| ProcessingError::Encode(format!("Failed to create temp input: {}", e)))?;
-    input_file.write_all(input)
-        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
-    drop(input_file);
+/// Length of the video a lossy encode of an input with `header` writes
+fn encoded_duration(config: &ProcessingConfig, header: &Mp4Header) -> Duration {
+    config.mp4.trim.map_or(header.duration, |trim| trim.length_within(header.duration))
+}
 
-    // Build ffmpeg command
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-i").arg(&input_path);
-    cmd.arg("-y"); // Overwrite output file
+/// What [`Mp4Processor`] reports for `data`, the lossy encode of `input` (whose
+/// header is `header`), by whichever runner produced it
+fn encoded_outcome<'a>(
+    input: &[u8],
+    mut data: Vec<u8>,
+    config: &ProcessingConfig,
+    header: &Mp4Header,
+) -> ProcessOutcome<'a> {
+    clear_stale_rotation(&mut data);
+
+    // Scaling and trimming change the video, so report what was written
+    let (duration, dimensions, video_bitrate) = match validate_mp4(&data) {
+        Ok(written) => (written.duration, written.dimensions.or(header.dimensions), written.video_bitrate),
+        Err(_) => (encoded_duration(config, header), header.dimensions, None),
+    };
+
+    let mut outcome = ProcessOutcome::new(data, Format::Mp4);
+    outcome.quality = Some(config.quality_for(Format::Mp4));
+    if let Some(hdr) = detect_hdr(input) {
+        if config.mp4.force_sdr {
+            outcome.warnings.push(format!("{} HDR tone-mapped to SDR", hdr.transfer.as_str()));
+        } else if hdr.mastering_display || hdr.content_light {
+            outcome.warnings.push("HDR kept, but mastering display / content light metadata was dropped".to_string());
+        }
+    }
+    outcome.dimensions = dimensions;
+    // The video stream's, comparable with --video-bitrate
+    outcome.bitrate = video_bitrate.or_else(|| average_bitrate(outcome.data.len(), duration));
+    outcome
+}
 
-    if lossless {
-        // Lossless: copy video/audio streams, only strip metadata
-        log::debug!("Using ffmpeg copy mode (no re-encoding)");
-        cmd.arg("-c:v").arg("copy");
-        cmd.arg("-c:a").arg("copy");
+/// Average bitrate of `len` bytes played over `duration`
+fn average_bitrate(len: usize, duration: Duration) -> Option<u64> {
+    let secs = duration.as_secs_f64();
+    (secs > 0.0).then(|| (len as f64 * 8.0 / secs) as u64)
+}
 
-        // Strip metadata based on config
-        match config.strip {
-            StripMode::All | StripMode::Safe => {
-                cmd.arg("-map_metadata").arg("-1"); // Remove all metadata
-            }
-            StripMode::None => {
-                // Keep metadata
+/// What [`validate_mp4`] read from the header
+struct Mp4Header {
+    duration: Duration,
+    /// Display size of the first video track, with its rotation applied
+    dimensions: Option<(u32, u32)>,
+    /// Clockwise display rotation of that track in degrees
+    rotation: u16,
+    /// Average bitrate of that track alone, in bits/s (None for fragmented files)
+    video_bitrate: Option<u64>,
+}
+
+/// Parse the MP4 header to make sure the input is a readable MP4
+fn validate_mp4(input: &[u8]) -> Result<Mp4Header, ProcessingError> {
+    let mut reader = Cursor::new(input);
+    let mp4 = mp4::Mp4Reader::read_header(&mut reader, input.len() as u64)
+        .map_err(|e| ProcessingError::CorruptInput {
+            format: "mp4".to_string(),
+            offset: None,
+            reason: e.to_string(),
+        })?;
+
+    log::debug!("Processing MP4: {} tracks, {:.2}s duration",
+               mp4.tracks().len(),
+               mp4.duration().as_secs_f64());
+
+    let video = mp4
+        .tracks()
+        .values()
+        .find(|t| matches!(t.track_type(), Ok(mp4::TrackType::Video)));
+    let rotation = video.map_or(0, track_rotation);
+    let dimensions = video.map(|t| match rotation {
+        90 | 270 => (t.height() as u32, t.width() as u32),
+        _ => (t.width() as u32, t.height() as u32),
+    });
+    let video_bitrate = video.map(|t| u64::from(t.bitrate())).filter(|&bitrate| bitrate > 0);
+
+    // Fragmented files often leave the movie header duration at zero
+    let duration = match mp4.duration() {
+        Duration::ZERO if mp4.is_fragmented() => fragmented_duration(&mp4).unwrap_or_default(),
+        duration => duration,
+    };
+
+    Ok(Mp4Header {
+        duration,
+        dimensions,
+        rotation,
+        video_bitrate,
+    })
+}
+
+/// True for fragmented MP4 (fMP4/DASH/CMAF): `moof` boxes at the top level
+pub fn is_fragmented(data: &[u8]) -> bool {
+    has_top_level_box(data, b"moof")
+}
+
+fn has_top_level_box(data: &[u8], kind: &[u8; 4]) -> bool {
+    let mut pos = 0u64;
+    while let Ok(Some(header)) = read_box_header(&mut &data[pos as usize..], pos) {
+        if &header.kind == kind {
+            return true;
+        }
+        match header.size {
+            Some(size) if pos + size < data.len() as u64 => pos += size,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Body of the top-level `free` box at the very end of the file
+pub(crate) fn trailing_free_box(data: &[u8]) -> Option<&[u8]> {
+    let last = child_boxes(data)?.pop()?;
+    (&last.kind == b"free").then(|| &data[last.start + last.header_len..last.end])
+}
+
+/// `data` with a `free` box holding `body` at the end, replacing a trailing
+/// `free` box whose body starts with `replace_prefix`. Appending after every
+/// other box moves nothing, so no chunk offsets change.
+pub(crate) fn with_trailing_free_box(data: &[u8], body: &[u8], replace_prefix: &[u8]) -> Vec<u8> {
+    let end = match child_boxes(data).and_then(|mut boxes| boxes.pop()) {
+        Some(last) if &last.kind == b"free" && data[last.start + last.header_len..].starts_with(replace_prefix) => {
+            last.start
+        }
+        _ => data.len(),
+    };
+    let mut output = Vec::with_capacity(end + body.len() + 8);
+    output.extend_from_slice(&data[..end]);
+    output.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    output.extend_from_slice(b"free");
+    output.extend_from_slice(body);
+    output
+}
+
+/// Duration (of the fragments, for fragmented files) and the first video
+/// track's dimensions. None when the header cannot be read.
+pub(crate) fn duration_and_dimensions(input: &[u8]) -> Option<(Duration, Option<(u32, u32)>)> {
+    let mp4 = mp4::Mp4Reader::read_header(Cursor::new(input), input.len() as u64).ok()?;
+    let duration = if mp4.is_fragmented() { fragmented_duration(&mp4) } else { None }.unwrap_or(mp4.duration());
+    let dimensions = mp4
+        .tracks()
+        .values()
+        .find(|track| track.track_type().is_ok_and(|kind| kind == mp4::TrackType::Video))
+        .map(|track| (u32::from(track.width()), u32::from(track.height())));
+    Some((duration, dimensions))
+}
+
+/// Displayed dimensions of the first video track of the MP4 at `path`, reading
+/// only its header. None when the header cannot be read.
+pub fn video_dimensions(path: &std::path::Path) -> Option<(u32, u32)> {
+    let file = std::fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mp4 = mp4::Mp4Reader::read_header(io::BufReader::new(file), size).ok()?;
+    let video = mp4.tracks().values().find(|track| matches!(track.track_type(), Ok(mp4::TrackType::Video)))?;
+    let (width, height) = (u32::from(video.width()), u32::from(video.height()));
+    Some(match track_rotation(video) {
+        90 | 270 => (height, width),
+        _ => (width, height),
+    })
+}
+
+/// Length of a fragmented file: the `mehd` fragment duration if present, else the
+/// sample durations of the first fragmented track summed over every `moof`
+fn fragmented_duration<R: Read + io::Seek>(mp4: &mp4::Mp4Reader<R>) -> Option<Duration> {
+    let mvex = mp4.moov.mvex.as_ref();
+    if let Some(mehd) = mvex.and_then(|mvex| mvex.mehd.as_ref()).filter(|mehd| mehd.fragment_duration > 0) {
+        let timescale = mp4.moov.mvhd.timescale.max(1);
+        return Some(Duration::from_secs_f64(mehd.fragment_duration as f64 / timescale as f64));
+    }
+
+    let track_id = mp4.moofs.first()?.trafs.first()?.tfhd.track_id;
+    let timescale = mp4.tracks().get(&track_id)?.timescale().max(1);
+    let trex_duration = mvex
+        .map(|mvex| &mvex.trex)
+        .filter(|trex| trex.track_id == track_id)
+        .map(|trex| trex.default_sample_duration);
+    let ticks: u64 = mp4
+        .moofs
+        .iter()
+        .flat_map(|moof| &moof.trafs)
+        .filter(|traf| traf.tfhd.track_id == track_id)
+        .filter_map(|traf| {
+            let trun = traf.trun.as_ref()?;
+            if trun.sample_durations.is_empty() {
+                let default = traf.tfhd.default_sample_duration.or(trex_duration)?;
+                Some(u64::from(default) * u64::from(trun.sample_count))
+            } else {
+                Some(trun.sample_durations.iter().map(|&d| u64::from(d)).sum())
             }
+        })
+        .sum();
+    (ticks > 0).then(|| Duration::from_secs_f64(ticks as f64 / timescale as f64))
+}
+
+/// Clockwise display rotation (0, 90, 180 or 270 degrees) encoded in the track
+/// matrix, as phones write it; other transforms count as 0
+fn track_rotation(track: &mp4::Mp4Track) -> u16 {
+    const ONE: i32 = 0x0001_0000;
+    let matrix = &track.trak.tkhd.matrix;
+    match (matrix.a, matrix.b, matrix.c, matrix.d) {
+        (0, ONE, m, 0) if m == -ONE => 90,
+        (m, 0, 0, n) if m == -ONE && n == -ONE => 180,
+        (0, m, ONE, 0) if m == -ONE => 270,
+        _ => 0,
+    }
+}
+
+/// HDR signalling found on the first video sample entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HdrInfo {
+    pub transfer: HdrTransfer,
+    /// `colr` colour primaries code (9 = BT.2020)
+    pub primaries: u16,
+    /// `colr` matrix coefficients code (9 = BT.2020 non-constant)
+    pub matrix: u16,
+    /// Static mastering display metadata (`mdcv`/`SmDm`) is present
+    pub mastering_display: bool,
+    /// Content light level (`clli`/`CoLL`) is present
+    pub content_light: bool,
+}
+
+/// HDR transfer characteristics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrTransfer {
+    /// SMPTE ST 2084 (PQ), used by HDR10 and Dolby Vision
+    Pq,
+    /// ARIB STD-B67 (HLG), used by phones and broadcast
+    Hlg,
+}
+
+impl HdrTransfer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HdrTransfer::Pq => "PQ (HDR10)",
+            HdrTransfer::Hlg => "HLG",
         }
+    }
 
-        // Fast start
-        cmd.arg("-movflags").arg("+faststart");
-    } else {
-        // Lossy: re-encode with compression
-        // Map quality (0-100) to CRF (0-51, lower is better)
-        // quality 100 -> CRF 18 (very high quality)
-        // quality 80 -> CRF 23 (good quality, default)
-        // quality 50 -> CRF 28 (medium quality)
-        // quality 0 -> CRF 35 (low quality)
-        let crf = ((100 - config.quality) as f32 * 0.33 + 18.0) as u32;
-        let crf = crf.min(35).max(18);
-
-        log::debug!("Using ffmpeg with CRF {} (quality {})", crf, config.quality);
-
-        // Video encoding
-        cmd.arg("-c:v").arg("libx264");
-        cmd.arg("-crf").arg(crf.to_string());
-
-        // Map speed (1-10) to preset
-        // speed 1 (slowest) -> veryslow
-        // speed 3 (default) -> medium
-        // speed 10 (fastest) -> ultrafast
-        let preset = match config.speed {
-            1 => "veryslow",
-            2 => "slow",
-            3 | 4 => "medium",
-            5 | 6 => "fast",
-            7 | 8 => "faster",
-            _ => "ultrafast",
+    /// ffmpeg `-color_trc` name
+    #[cfg(feature = "ffmpeg")]
+    fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            HdrTransfer::Pq => "smpte2084",
+            HdrTransfer::Hlg => "arib-std-b67",
+        }
+    }
+}
+
+/// Sample entry types of video codecs that may carry HDR
+const VIDEO_SAMPLE_ENTRIES: [&[u8; 4]; 9] =
+    [b"avc1", b"avc3", b"hvc1", b"hev1", b"dvh1", b"dvhe", b"vp09", b"av01", b"encv"];
+
+/// Bytes of a visual sample entry body before its child boxes
+const VISUAL_SAMPLE_ENTRY_LEN: usize = 78;
+
+/// Look for an `nclx` `colr` box with a PQ or HLG transfer on the video sample
+/// entry. HDR signalled only inside the bitstream is not seen here.
+pub fn detect_hdr(data: &[u8]) -> Option<HdrInfo> {
+    let moov = child_boxes(data)?.into_iter().find(|child| &child.kind == b"moov")?;
+    find_hdr(&data[moov.start + moov.header_len..moov.end])
+}
+
+fn find_hdr(body: &[u8]) -> Option<HdrInfo> {
+    for ChildBox { kind, start, header_len, end } in child_boxes(body)? {
+        let child = &body[start + header_len..end];
+        let found = match &kind {
+            k if MOOV_CONTAINERS.contains(&k) => find_hdr(child),
+            // version/flags and entry count precede the sample entries
+            b"stsd" if child.len() > 8 => child_boxes(&child[8..])?
+                .into_iter()
+                .filter(|entry| VIDEO_SAMPLE_ENTRIES.contains(&&entry.kind))
+                .find_map(|entry| {
+                    let entry_body = &child[8 + entry.start + entry.header_len..8 + entry.end];
+                    sample_entry_hdr(entry_body.get(VISUAL_SAMPLE_ENTRY_LEN..)?)
+                }),
+            _ => None,
         };
-        cmd.arg("-preset").arg(preset);
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// HDR info from the child boxes of a visual sample entry
+fn sample_entry_hdr(children: &[u8]) -> Option<HdrInfo> {
+    let children: Vec<(ChildBox, &[u8])> = child_boxes(children)?
+        .into_iter()
+        .map(|child| {
+            let body = &children[child.start + child.header_len..child.end];
+            (child, body)
+        })
+        .collect();
 
-        // Audio encoding
-        cmd.arg("-c:a").arg("aac");
-        cmd.arg("-b:a").arg("128k");
+    let (primaries, transfer, matrix) = children.iter().find_map(|(child, body)| {
+        // colour_type, primaries, transfer, matrix, full range flag
+        (&child.kind == b"colr" && body.len() >= 10 && &body[..4] == b"nclx").then(|| {
+            let code = |at: usize| u16::from_be_bytes([body[at], body[at + 1]]);
+            (code(4), code(6), code(8))
+        })
+    })?;
+    let transfer = match transfer {
+        16 => HdrTransfer::Pq,
+        18 => HdrTransfer::Hlg,
+        _ => return None,
+    };
+    let has = |kinds: [&[u8; 4]; 2]| children.iter().any(|(child, _)| kinds.contains(&&child.kind));
+
+    Some(HdrInfo {
+        transfer,
+        primaries,
+        matrix,
+        mastering_display: has([b"mdcv", b"SmDm"]),
+        content_light: has([b"clli", b"CoLL"]),
+    })
+}
 
-        // Strip metadata
-        if config.strip != StripMode::None {
-            cmd.arg("-map_metadata").arg("-1");
+/// Re-encoding turns the frames upright (see [`rotation_filter`]), so a rotation
+/// matrix left on the output would turn the video a second time. Reset every
+/// track matrix in `data` to identity in that case.
+fn clear_stale_rotation(data: &mut [u8]) {
+    let Some(children) = child_boxes(data) else {
+        return;
+    };
+    for ChildBox { kind, start, header_len, end } in children {
+        if &kind == b"moov" && reset_track_matrices(&mut data[start + header_len..end]) {
+            log::debug!("Cleared stale rotation from re-encoded MP4");
         }
+    }
+}
 
-        // Fast start
-        cmd.arg("-movflags").arg("+faststart");
+/// Identity matrix in 16.16 / 2.30 fixed point, as stored in `tkhd`
+const IDENTITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Reset `tkhd` matrices in a `moov`-tree body; true if any was not identity
+fn reset_track_matrices(body: &mut [u8]) -> bool {
+    let Some(children) = child_boxes(body) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for ChildBox { kind, start, header_len, end } in children {
+        let child = &mut body[start + header_len..end];
+        match &kind {
+            b"trak" => changed |= reset_track_matrices(child),
+            b"tkhd" if !child.is_empty() => {
+                // version/flags, times/track id/duration (20 or 32 bytes), reserved,
+                // layer, alternate group, volume, reserved (16 bytes)
+                let offset = if child[0] == 1 { 52 } else { 40 };
+                let Some(matrix) = child.get_mut(offset..offset + 36) else {
+                    continue;
+                };
+                let identity: Vec<u8> = IDENTITY_MATRIX.iter().flat_map(|v| v.to_be_bytes()).collect();
+                if matrix != identity.as_slice() {
+                    matrix.copy_from_slice(&identity);
+                    changed = true;
+                }
+            }
+            _ => {}
+        }
     }
+    changed
+}
 
-    cmd.arg(&output_path);
+/// ffmpeg arguments placed before `-i`: input seeking for the trim range, and
+/// `-noautorotate` when [`video_args`] turns a `rotation`° input itself
+#[cfg(feature = "ffmpeg")]
+fn input_args(config: &ProcessingConfig, rotation: u16) -> Vec<String> {
+    let mut args = config.mp4.trim.as_ref().map(seek_args).unwrap_or_default();
+    if rotation_filter(rotation).is_some() {
+        args.push("-noautorotate".to_string());
+    }
+    args
+}
 
-    // Execute ffmpeg
-    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+/// Filter turning frames of a video with a clockwise display `rotation` upright.
+/// Done in our own filter chain rather than by ffmpeg's autorotate so that
+/// `--video-scale` sizes are always taken as the displayed width and height.
+#[cfg(feature = "ffmpeg")]
+fn rotation_filter(rotation: u16) -> Option<&'static str> {
+    match rotation {
+        90 => Some("transpose=clock"),
+        180 => Some("hflip,vflip"),
+        270 => Some("transpose=cclock"),
+        _ => None,
+    }
+}
 
-    let output = cmd.output()
-        .map_err(|e| ProcessingError::Encode(format!("Failed to execute ffmpeg: {}", e)))?;
+/// `-ss`/`-to` input seeking for a time range
+#[cfg(feature = "ffmpeg")]
+fn seek_args(range: &TimeRange) -> Vec<String> {
+    let mut args = vec!["-ss".to_string(), format!("{:.3}", range.start.as_secs_f64())];
+    if let Some(end) = range.end {
+        args.extend(["-to".to_string(), format!("{:.3}", end.as_secs_f64())]);
+    }
+    args
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log::error!("ffmpeg failed: {}", stderr);
+/// The frame selection filter for [`extract_frames`]
+#[cfg(feature = "ffmpeg")]
+fn selection_args(selection: FrameSelection) -> Result<Vec<String>, ProcessingError> {
+    let args = match selection {
+        // Extract N frames per second
+        FrameSelection::Fps(fps) if fps > 0.0 => vec!["-vf".to_string(), format!("fps={}", fps)],
+        // fps == 0 extracts all frames (no filter)
+        FrameSelection::Fps(_) => Vec::new(),
+        FrameSelection::EveryNth(0) => {
+            return Err(ProcessingError::InvalidConfig("frame step must be at least 1".to_string()));
+        }
+        FrameSelection::EveryNth(step) => {
+            vec!["-vf".to_string(), format!("select=not(mod(n\\,{}))", step), "-vsync".into(), "vfr".into()]
+        }
+        FrameSelection::Scenes(threshold) if !(0.0..=1.0).contains(&threshold) => {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "scene threshold must be 0.0-1.0, got {}",
+                threshold
+            )));
+        }
+        FrameSelection::Scenes(threshold) => {
+            let filter = format!("select=eq(n\\,0)+gt(scene\\,{})", threshold);
+            vec!["-vf".to_string(), filter, "-vsync".into(), "vfr".into()]
+        }
+    };
+    Ok(args)
+}
 
-        // Cleanup temp files
-        let _ = std::fs::remove_file(&input_path);
-        let _ = std::fs::remove_file(&output_path);
+/// Build the ffmpeg re-encoding arguments placed between input and output paths
+#[cfg(feature = "ffmpeg")]
+fn compress_args(
+    config: &ProcessingConfig,
+    encoder: &VideoEncoder,
+    hdr: Option<&HdrPlan>,
+    rotation: u16,
+) -> Vec<String> {
+    let mut args = video_args(config, encoder, hdr, rotation);
+
+    // Audio
+    match config.mp4.audio {
+        AudioTrack::Aac => {
+            args.extend(["-c:a", "aac", "-b:a"].map(String::from));
+            args.push(format!("{}k", config.mp4.audio_bitrate));
+        }
+        AudioTrack::Copy => args.extend(["-c:a", "copy"].map(String::from)),
+        AudioTrack::Drop => args.push("-an".to_string()),
+    }
 
-        return Err(ProcessingError::Encode(format!("ffmpeg failed: {}", stderr)));
+    // Strip metadata
+    if config.strip != StripMode::None {
+        args.extend(["-map_metadata", "-1"].map(String::from));
     }
 
-    // Read output
-    let result = std::fs::read(&output_path)
-        .map_err(|e| ProcessingError::Encode(format!("Failed to read ffmpeg output: {}", e)))?;
+    // Fast start
+    args.extend(["-movflags", "+faststart"].map(String::from));
 
-    // Cleanup temp files
-    let _ = std::fs::remove_file(&input_path);
-    let _ = std::fs::remove_file(&output_path);
+    args
+}
 
-    log::debug!("ffmpeg completed: {} -> {} bytes ({:.1}% reduction)",
-               input.len(),
-               result.len(),
-               (1.0 - result.len() as f64 / input.len() as f64) * 100.0);
+/// Video stream arguments: encoder, rate control, speed, filters and HDR handling
+#[cfg(feature = "ffmpeg")]
+fn video_args(config: &ProcessingConfig, encoder: &VideoEncoder, hdr: Option<&HdrPlan>, rotation: u16) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
 
-    Ok(result)
+    if encoder.hwaccel == Some(HwAccel::Vaapi) {
+        args.extend(["-vaapi_device", VAAPI_DEVICE].map(String::from));
+    }
+
+    let codec = config.mp4.codec;
+    let quality = config.quality_for(Format::Mp4);
+    let crf = effective_crf(config);
+
+    // Video encoding: a target bitrate replaces CRF
+    args.extend(["-c:v", encoder.name].map(String::from));
+    match config.mp4.video_bitrate {
+        Some(bitrate) => {
+            log::debug!("Using {} with target bitrate {} bit/s", encoder.name, bitrate);
+            args.extend(["-b:v".to_string(), bitrate.to_string()]);
+        }
+        None if encoder.hwaccel.is_some() => {
+            log::debug!("Using {} with quality {}", encoder.name, quality);
+            args.extend(hw_quality_args(encoder, crf, quality));
+        }
+        None => {
+            log::debug!("Using {} with CRF {} (quality {})", codec.as_str(), crf, quality);
+            args.extend(["-crf".to_string(), crf.to_string()]);
+            if matches!(codec, VideoCodec::Vp9 | VideoCodec::Av1) {
+                // libvpx/libaom only run in constant-quality mode with a zero bitrate
+                args.extend(["-b:v", "0"].map(String::from));
+            }
+        }
+    }
+
+    match encoder.hwaccel {
+        Some(hwaccel) => args.extend(hw_speed_args(hwaccel, config.speed)),
+        None => args.extend(speed_args(codec, config.speed)),
+    }
+    if codec == VideoCodec::Hevc {
+        // QuickTime and iOS only play HEVC in MP4 with the hvc1 tag
+        args.extend(["-tag:v", "hvc1"].map(String::from));
+    }
+
+    // Rotating before scaling; -2 keeps the aspect ratio with an even width for
+    // libx264. VA-API encoders need the frames uploaded to the GPU.
+    let mut filters: Vec<String> = rotation_filter(rotation).map(String::from).into_iter().collect();
+    match hdr {
+        Some(HdrPlan::ToneMap) => {
+            filters.push(TONEMAP_FILTER.to_string());
+            args.extend(["-color_primaries", "bt709", "-color_trc", "bt709", "-colorspace", "bt709"].map(String::from));
+        }
+        Some(HdrPlan::Preserve(info)) => {
+            args.extend(["-pix_fmt", "yuv420p10le", "-color_primaries"].map(String::from));
+            args.push(info.primaries.to_string());
+            args.extend(["-color_trc".to_string(), info.transfer.ffmpeg_name().to_string()]);
+            args.extend(["-colorspace".to_string(), info.matrix.to_string()]);
+        }
+        None => {}
+    }
+    filters.extend(scale_filter(config));
+    if encoder.hwaccel == Some(HwAccel::Vaapi) {
+        filters.push("format=nv12,hwupload".to_string());
+    }
+    if !filters.is_empty() {
+        args.extend(["-vf".to_string(), filters.join(",")]);
+    }
+
+    args
+}
+
+/// ffmpeg software encoder for a codec
+#[cfg(feature = "ffmpeg")]
+fn software_encoder(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "libx264",
+        VideoCodec::Hevc => "libx265",
+        VideoCodec::Vp9 => "libvpx-vp9",
+        VideoCodec::Av1 => "libaom-av1",
+    }
+}
+
+/// Render node used for VA-API encoding
+#[cfg(feature = "ffmpeg")]
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Encoder chosen for a run
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VideoEncoder {
+    name: &'static str,
+    /// None for software encoders
+    hwaccel: Option<HwAccel>,
+}
+
+/// ffmpeg encoder of a hardware family for a codec, if the family supports it
+#[cfg(feature = "ffmpeg")]
+fn hw_encoder(hwaccel: HwAccel, codec: VideoCodec) -> Option<&'static str> {
+    match (hwaccel, codec) {
+        (HwAccel::Videotoolbox, VideoCodec::H264) => Some("h264_videotoolbox"),
+        (HwAccel::Videotoolbox, VideoCodec::Hevc) => Some("hevc_videotoolbox"),
+        (HwAccel::Nvenc, VideoCodec::H264) => Some("h264_nvenc"),
+        (HwAccel::Nvenc, VideoCodec::Hevc) => Some("hevc_nvenc"),
+        (HwAccel::Nvenc, VideoCodec::Av1) => Some("av1_nvenc"),
+        (HwAccel::Qsv, VideoCodec::H264) => Some("h264_qsv"),
+        (HwAccel::Qsv, VideoCodec::Hevc) => Some("hevc_qsv"),
+        (HwAccel::Qsv, VideoCodec::Vp9) => Some("vp9_qsv"),
+        (HwAccel::Qsv, VideoCodec::Av1) => Some("av1_qsv"),
+        (HwAccel::Vaapi, VideoCodec::H264) => Some("h264_vaapi"),
+        (HwAccel::Vaapi, VideoCodec::Hevc) => Some("hevc_vaapi"),
+        (HwAccel::Vaapi, VideoCodec::Vp9) => Some("vp9_vaapi"),
+        (HwAccel::Vaapi, VideoCodec::Av1) => Some("av1_vaapi"),
+        _ => None,
+    }
+}
+
+/// Pick the encoder for `config.mp4`: an explicit hardware family must be available,
+/// `Auto` takes the first available one that works (platform-native first) and falls
+/// back to software
+#[cfg(feature = "ffmpeg")]
+fn select_encoder(config: &ProcessingConfig) -> Result<VideoEncoder, ProcessingError> {
+    let codec = config.mp4.codec;
+    let software = VideoEncoder { name: software_encoder(codec), hwaccel: None };
+
+    match config.mp4.hwaccel {
+        HwAccel::None => {
+            ensure_encoder(software.name)?;
+            Ok(software)
+        }
+        HwAccel::Auto => {
+            const ORDER: [HwAccel; 4] = if cfg!(target_os = "macos") {
+                [HwAccel::Videotoolbox, HwAccel::Nvenc, HwAccel::Qsv, HwAccel::Vaapi]
+            } else {
+                [HwAccel::Nvenc, HwAccel::Qsv, HwAccel::Vaapi, HwAccel::Videotoolbox]
+            };
+            let hardware = ORDER.into_iter().find_map(|hwaccel| {
+                let encoder = VideoEncoder { name: hw_encoder(hwaccel, codec)?, hwaccel: Some(hwaccel) };
+                let available = ffmpeg_encoders().iter().any(|name| name == encoder.name);
+                (available && encoder_works(&encoder)).then_some(encoder)
+            });
+            match hardware {
+                Some(encoder) => Ok(encoder),
+                None => {
+                    log::debug!("No hardware {} encoder found, using {}", codec.as_str(), software.name);
+                    ensure_encoder(software.name)?;
+                    Ok(software)
+                }
+            }
+        }
+        hwaccel => {
+            let name = hw_encoder(hwaccel, codec).ok_or_else(|| {
+                ProcessingError::InvalidConfig(format!("{:?} cannot encode {}", hwaccel, codec.as_str()))
+            })?;
+            ensure_encoder(name)?;
+            Ok(VideoEncoder { name, hwaccel: Some(hwaccel) })
+        }
+    }
+}
+
+/// Whether `encoder` can encode here: ffmpeg lists hardware encoders it was built
+/// with even when the device is missing (nvenc without an NVIDIA GPU). Probed with
+/// a one-frame encode, once per encoder per process.
+#[cfg(feature = "ffmpeg")]
+fn encoder_works(encoder: &VideoEncoder) -> bool {
+    static PROBED: std::sync::OnceLock<std::sync::Mutex<std::collections::HashMap<&str, bool>>> =
+        std::sync::OnceLock::new();
+    let probed = PROBED.get_or_init(Default::default);
+    if let Some(&works) = probed.lock().unwrap().get(encoder.name) {
+        return works;
+    }
+
+    let vaapi = encoder.hwaccel == Some(HwAccel::Vaapi);
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-hide_banner");
+    if vaapi {
+        cmd.args(["-vaapi_device", VAAPI_DEVICE]);
+    }
+    cmd.args(["-f", "lavfi", "-i", "color=black:size=256x256", "-frames:v", "1"]);
+    if vaapi {
+        cmd.args(["-vf", "format=nv12,hwupload"]);
+    }
+    cmd.args(["-c:v", encoder.name, "-f", "null", "-"]);
+    let works = ffmpeg::run(&mut cmd).is_ok_and(|output| output.status.success());
+    if !works {
+        log::debug!("{} is listed by ffmpeg but cannot encode here", encoder.name);
+    }
+    probed.lock().unwrap().insert(encoder.name, works);
+    works
+}
+
+/// Constant-quality arguments for hardware encoders. `crf` is the software
+/// CRF for the same quality, which the QP-based encoders accept on a similar scale.
+#[cfg(feature = "ffmpeg")]
+fn hw_quality_args(encoder: &VideoEncoder, crf: u32, quality: u8) -> Vec<String> {
+    match encoder.hwaccel {
+        // VideoToolbox: 1-100, higher is better
+        Some(HwAccel::Videotoolbox) => vec!["-q:v".to_string(), quality.clamp(1, 100).to_string()],
+        Some(HwAccel::Nvenc) => vec!["-rc".to_string(), "vbr".to_string(), "-cq".to_string(), crf.to_string()],
+        Some(HwAccel::Qsv) => vec!["-global_quality".to_string(), crf.to_string()],
+        Some(HwAccel::Vaapi) => vec!["-qp".to_string(), crf.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+/// Map speed (1-10, 1 = slowest) to a hardware encoder preset, where there is one
+#[cfg(feature = "ffmpeg")]
+fn hw_speed_args(hwaccel: HwAccel, speed: i32) -> Vec<String> {
+    match hwaccel {
+        // p7 = slowest/best, p1 = fastest
+        HwAccel::Nvenc => {
+            let preset = 7 - (speed.clamp(1, 10) - 1) * 6 / 9;
+            vec!["-preset".to_string(), format!("p{}", preset)]
+        }
+        HwAccel::Qsv => {
+            let preset = match speed {
+                1 => "veryslow",
+                2 => "slower",
+                3 | 4 => "medium",
+                5 | 6 => "fast",
+                7 | 8 => "faster",
+                _ => "veryfast",
+            };
+            vec!["-preset".to_string(), preset.to_string()]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// (CRF at quality 100, CRF at quality 0, encoder maximum)
+#[cfg(feature = "ffmpeg")]
+fn codec_crf_range(codec: VideoCodec) -> (u32, u32, u32) {
+    match codec {
+        VideoCodec::H264 => (18, 35, 51),
+        // x265 at CRF 28 looks about like x264 at 23
+        VideoCodec::Hevc => (22, 40, 51),
+        VideoCodec::Vp9 => (24, 50, 63),
+        VideoCodec::Av1 => (22, 50, 63),
+    }
+}
+
+/// CRF the encoder runs at: `mp4.crf` capped to the codec's scale, else mapped from the quality
+#[cfg(feature = "ffmpeg")]
+pub(crate) fn effective_crf(config: &ProcessingConfig) -> u32 {
+    let codec = config.mp4.codec;
+    match config.mp4.crf {
+        Some(crf) => u32::from(crf).min(codec_crf_range(codec).2),
+        None => quality_to_crf(codec, config.quality_for(Format::Mp4)),
+    }
+}
+
+/// Map quality (0-100) to the codec's CRF scale (lower is better). For H.264:
+/// quality 100 -> CRF 18 (very high quality)
+/// quality 80 -> CRF 23 (good quality, default)
+/// quality 50 -> CRF 28 (medium quality)
+/// quality 0 -> CRF 35 (low quality)
+#[cfg(feature = "ffmpeg")]
+fn quality_to_crf(codec: VideoCodec, quality: u8) -> u32 {
+    let (best, worst, max) = codec_crf_range(codec);
+    let step = 0.33 * max as f32 / 51.0;
+    ((100 - quality.min(100)) as f32 * step + best as f32).clamp(best as f32, worst as f32) as u32
+}
+
+/// Map speed (1-10, 1 = slowest) to the encoder's speed setting
+#[cfg(feature = "ffmpeg")]
+fn speed_args(codec: VideoCodec, speed: i32) -> Vec<String> {
+    match codec {
+        VideoCodec::H264 | VideoCodec::Hevc => {
+            // speed 1 -> veryslow, 3 (default) -> medium, 10 -> ultrafast
+            let preset = match speed {
+                1 => "veryslow",
+                2 => "slow",
+                3 | 4 => "medium",
+                5 | 6 => "fast",
+                7 | 8 => "faster",
+                _ => "ultrafast",
+            };
+            vec!["-preset".to_string(), preset.to_string()]
+        }
+        // libvpx: -cpu-used 0-5 with the "good" deadline
+        VideoCodec::Vp9 => {
+            let cpu_used = (speed.clamp(1, 10) - 1) * 5 / 9;
+            vec!["-deadline".to_string(), "good".to_string(), "-cpu-used".to_string(), cpu_used.to_string()]
+        }
+        // libaom: -cpu-used 0-8, default speed 3 -> 4
+        VideoCodec::Av1 => {
+            let cpu_used = ((speed.clamp(1, 10) - 1) * 8 + 4) / 9 + 2;
+            vec!["-cpu-used".to_string(), cpu_used.min(8).to_string()]
+        }
+    }
+}
+
+/// `scale` filter for the configured output size, if any
+#[cfg(feature = "ffmpeg")]
+fn scale_filter(config: &ProcessingConfig) -> Option<String> {
+    match (config.mp4.scale, config.mp4.max_height) {
+        (Some((width, height)), _) => Some(format!("scale={}:{}", width, height)),
+        // Never upscale videos that are already small enough
+        (None, Some(height)) => Some(format!("scale=-2:'min(ih,{})'", height)),
+        (None, None) => None,
+    }
+}
+
+/// Compress MP4 using ffmpeg
+#[cfg(feature = "ffmpeg")]
+fn compress_mp4_with_ffmpeg(
+    input: &[u8],
+    config: &ProcessingConfig,
+    duration: Duration,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    let temp = FfmpegTempFiles::new();
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+    encode_mp4_file(&temp, input, input.len(), config, duration, progress)
+}
+
+/// Re-encode `temp.input`, `input_len` bytes long; `header` holds at least its `moov`
+#[cfg(feature = "ffmpeg")]
+fn encode_mp4_file(
+    temp: &FfmpegTempFiles,
+    header: &[u8],
+    input_len: usize,
+    config: &ProcessingConfig,
+    duration: Duration,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    let plan = EncodePlan::new(header, config)?;
+    let command = |args: &[std::ffi::OsString]| {
+        let mut cmd = ffmpeg::command();
+        cmd.args(["-progress", "pipe:1", "-nostats"]);
+        cmd.args(args);
+        log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+        cmd
+    };
+
+    progress.start(Stage::Ffmpeg);
+
+    // Two-pass: an analysis run (first half of the progress) feeds the real encode
+    let (encode, analysis, share) = plan.passes(config, temp);
+    if let Some(args) = analysis {
+        let output = run_with_progress(command(&args), duration, 0.0..share, progress)?;
+        if !output.status.success() {
+            return Err(ffmpeg_failed(&output));
+        }
+    }
+
+    let output = run_with_progress(command(&encode), duration, share..100.0, progress)?;
+    let result = read_ffmpeg_result(output, temp, input_len)?;
+    progress.finish(Stage::Ffmpeg);
+
+    Ok(result)
+}
+
+/// A lossy MP4 encode, planned once whichever runner ([`encode_mp4_file`] or
+/// `compress_mp4_async`) executes it
+#[cfg(feature = "ffmpeg")]
+struct EncodePlan {
+    encoder: VideoEncoder,
+    hdr: Option<HdrPlan>,
+    rotation: u16,
+    two_pass: bool,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl EncodePlan {
+    /// Plan the encode of an input whose `moov` is in `header`
+    fn new(header: &[u8], config: &ProcessingConfig) -> Result<Self, ProcessingError> {
+        let encoder = select_encoder(config)?;
+        let hdr = plan_hdr(header, config, &encoder)?;
+        let rotation = validate_mp4(header).map_or(0, |header| header.rotation);
+        if rotation != 0 {
+            log::debug!("Input is rotated {}°; turning the frames upright before scaling", rotation);
+        }
+        let two_pass = wants_two_pass(config, &encoder);
+        Ok(Self { encoder, hdr, rotation, two_pass })
+    }
+
+    /// ffmpeg arguments for the encode into `temp.output`, for the analysis pass
+    /// run before it (two-pass only), and where the encode's share of the
+    /// progress starts
+    fn passes(
+        &self,
+        config: &ProcessingConfig,
+        temp: &FfmpegTempFiles,
+    ) -> (Vec<std::ffi::OsString>, Option<Vec<std::ffi::OsString>>, f32) {
+        let pass = |number: Option<u8>| {
+            let compress = compress_args(config, &self.encoder, self.hdr.as_ref(), self.rotation);
+            let mut args: Vec<std::ffi::OsString> =
+                input_args(config, self.rotation).into_iter().map(Into::into).collect();
+            args.extend(["-i".into(), temp.input.clone().into_os_string()]);
+            args.push("-y".into()); // Overwrite output file
+            args.extend(compress.into_iter().map(Into::into));
+            if let Some(number) = number {
+                args.extend(pass_args(number, temp, &self.encoder));
+            }
+            args.push(if number == Some(1) { NULL_OUTPUT.into() } else { temp.output.clone().into_os_string() });
+            args
+        };
+        if self.two_pass {
+            (pass(Some(2)), Some(pass(Some(1))), 50.0)
+        } else {
+            (pass(None), None, 0.0)
+        }
+    }
+}
+
+/// How an HDR input is re-encoded
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HdrPlan {
+    /// Tone-map to 8-bit BT.709 (`force_sdr`)
+    ToneMap,
+    /// Encode 10-bit with the input's colour tags
+    Preserve(HdrInfo),
+}
+
+/// Linearize, tone-map with Hable and convert to BT.709; needs ffmpeg built with zimg
+#[cfg(feature = "ffmpeg")]
+const TONEMAP_FILTER: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+                              tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+/// Decide how to re-encode `input` if it is HDR. Software HEVC, VP9 and AV1 keep
+/// 10-bit HDR; H.264 and hardware encoders would silently flatten it, so they need
+/// `force_sdr` to tone-map instead.
+#[cfg(feature = "ffmpeg")]
+fn plan_hdr(
+    input: &[u8],
+    config: &ProcessingConfig,
+    encoder: &VideoEncoder,
+) -> Result<Option<HdrPlan>, ProcessingError> {
+    let Some(info) = detect_hdr(input) else {
+        return Ok(None);
+    };
+    if config.mp4.force_sdr {
+        return Ok(Some(HdrPlan::ToneMap));
+    }
+    if encoder.hwaccel.is_none() && config.mp4.codec != VideoCodec::H264 {
+        return Ok(Some(HdrPlan::Preserve(info)));
+    }
+    Err(ProcessingError::InvalidConfig(format!(
+        "input is {} HDR and {} would lose it; \
+         use --force-sdr to tone-map, a software hevc/vp9/av1 encode, or --no-lossy",
+        info.transfer.as_str(),
+        encoder.name
+    )))
+}
+
+/// Two-pass needs a target bitrate and a software encoder
+#[cfg(feature = "ffmpeg")]
+fn wants_two_pass(config: &ProcessingConfig, encoder: &VideoEncoder) -> bool {
+    if !config.mp4.two_pass || config.mp4.video_bitrate.is_none() {
+        return false;
+    }
+    if encoder.hwaccel.is_some() {
+        log::warn!("{} does not support two-pass encoding, using a single pass", encoder.name);
+        return false;
+    }
+    true
+}
+
+/// Where the first pass writes its (discarded) output
+#[cfg(feature = "ffmpeg")]
+const NULL_OUTPUT: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+/// Arguments for one pass of a two-pass encode; pass 1 only analyses video. x265
+/// ignores `-passlogfile` (and would leave `x265_2pass.log` in the working
+/// directory), so it gets a stats file next to it through `-x265-params`.
+#[cfg(feature = "ffmpeg")]
+fn pass_args(pass: u8, temp: &FfmpegTempFiles, encoder: &VideoEncoder) -> Vec<std::ffi::OsString> {
+    let mut args: Vec<std::ffi::OsString> = vec!["-pass".into(), pass.to_string().into(), "-passlogfile".into()];
+    args.push(temp.passlog.clone().into_os_string());
+    if encoder.name == "libx265" {
+        // Quoted: x265-params splits on ':', which a Windows path contains
+        let stats = format!("pass={}:stats='{}-x265.log'", pass, temp.passlog.display());
+        args.extend(["-x265-params".into(), stats.into()]);
+    }
+    if pass == 1 {
+        args.extend(["-an", "-f", "null"].map(Into::into));
+    }
+    args
+}
+
+/// Run ffmpeg with `-progress pipe:1`, forwarding `out_time_us` as a percentage of
+/// `duration` scaled into `span`
+#[cfg(feature = "ffmpeg")]
+fn run_with_progress(
+    mut cmd: Command,
+    duration: Duration,
+    span: std::ops::Range<f32>,
+    progress: &Progress,
+) -> Result<std::process::Output, ProcessingError> {
+    let total_us = duration.as_micros() as f64;
+    ffmpeg::run_with_lines(&mut cmd, |line| {
+        if let Some(us) = line.strip_prefix("out_time_us=").and_then(|v| v.trim().parse::<f64>().ok()) {
+            if total_us > 0.0 {
+                let fraction = (us / total_us).clamp(0.0, 1.0) as f32;
+                progress.report(Stage::Ffmpeg, span.start + fraction * (span.end - span.start));
+            }
+        }
+    })
+}
+
+/// Turn a video into an animated GIF (with a generated palette) or animated WebP,
+/// at `config.mp4.animation_fps` and `animation_width`, honouring `mp4.trim`
+#[cfg(feature = "ffmpeg")]
+pub fn video_to_animation(input: &[u8], target: Format, config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    validate_mp4(input)?;
+
+    let mut filter = format!("fps={}", config.mp4.animation_fps);
+    if let Some(width) = config.mp4.animation_width {
+        filter.push_str(&format!(",scale={}:-1:flags=lanczos", width));
+    }
+
+    let mut args: Vec<String> = Vec::new();
+    match target {
+        Format::Gif => {
+            // One palette for the whole clip, weighted towards moving areas
+            filter.push_str(
+                ",split[s0][s1];[s0]palettegen=stats_mode=diff[p];\
+                 [s1][p]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle",
+            );
+            args.extend(["-vf".to_string(), filter]);
+        }
+        Format::Webp => {
+            let encoder = if ffmpeg_encoders().iter().any(|name| name == "libwebp_anim") {
+                "libwebp_anim"
+            } else {
+                "libwebp"
+            };
+            ensure_encoder(encoder)?;
+            args.extend(["-vf".to_string(), filter, "-c:v".to_string(), encoder.to_string()]);
+            if config.no_lossy {
+                args.extend(["-lossless", "1"].map(String::from));
+            } else {
+                args.extend(["-lossless", "0", "-q:v"].map(String::from));
+                args.push(config.quality_for(Format::Webp).to_string());
+            }
+        }
+        _ => {
+            return Err(ProcessingError::UnsupportedFormat(format!(
+                "cannot convert a video to {}",
+                target.as_str()
+            )))
+        }
+    }
+    args.extend(["-loop", "0", "-an"].map(String::from));
+
+    let temp = FfmpegTempFiles::with_output_extension(target.extension());
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    // ffmpeg's autorotate turns the frames before the animation scale
+    let mut cmd = ffmpeg::command();
+    cmd.args(input_args(config, 0));
+    cmd.arg("-i").arg(&temp.input);
+    cmd.arg("-y");
+    cmd.args(&args);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    read_ffmpeg_result(output, &temp, input.len())
+}
+
+/// Video codec of WebM output: AV1 when asked for, else VP9, since WebM cannot
+/// hold the MP4-only H.264 and HEVC
+#[cfg(feature = "ffmpeg")]
+fn webm_codec(codec: VideoCodec) -> VideoCodec {
+    match codec {
+        VideoCodec::Av1 => VideoCodec::Av1,
+        VideoCodec::H264 | VideoCodec::Hevc | VideoCodec::Vp9 => VideoCodec::Vp9,
+    }
+}
+
+/// Re-encode a video as WebM: VP9 (or AV1, see [`webm_codec`]) video using the
+/// `mp4` section's quality, CRF, bitrate, scale and trim settings, and Opus audio
+/// at `mp4.audio_bitrate`
+#[cfg(feature = "ffmpeg")]
+pub fn video_to_webm(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    validate_mp4(input)?;
+
+    let mut webm = config.clone();
+    webm.mp4.codec = webm_codec(config.mp4.codec);
+    if webm.mp4.codec != config.mp4.codec && config.mp4.codec != VideoCodec::H264 {
+        log::warn!("WebM cannot hold {}; encoding VP9", config.mp4.codec.as_str());
+    }
+    let encoder = VideoEncoder {
+        name: software_encoder(webm.mp4.codec),
+        hwaccel: None,
+    };
+    ensure_encoder(encoder.name)?;
+    let hdr = plan_hdr(input, &webm, &encoder)?;
+
+    // WebM has no track matrix to reset afterwards, so ffmpeg's autorotate turns
+    // the frames (before the scale) and drops the rotation itself
+    let mut args = video_args(&webm, &encoder, hdr.as_ref(), 0);
+    match config.mp4.audio {
+        // WebM carries Opus or Vorbis, so MP4 audio (usually AAC) is always re-encoded
+        AudioTrack::Aac | AudioTrack::Copy => {
+            ensure_encoder("libopus")?;
+            args.extend(["-c:a", "libopus", "-b:a"].map(String::from));
+            args.push(format!("{}k", config.mp4.audio_bitrate));
+        }
+        AudioTrack::Drop => args.push("-an".to_string()),
+    }
+    if config.strip != StripMode::None {
+        args.extend(["-map_metadata", "-1"].map(String::from));
+    }
+
+    let temp = FfmpegTempFiles::with_output_extension(Format::Webm.extension());
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    let mut cmd = ffmpeg::command();
+    cmd.args(input_args(config, 0));
+    cmd.arg("-i").arg(&temp.input);
+    cmd.arg("-y");
+    cmd.args(&args);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    read_ffmpeg_result(output, &temp, input.len())
+}
+
+/// Remux a fragmented MP4 into a regular one with `moov` up front, without
+/// re-encoding (`ffmpeg -c copy`)
+#[cfg(feature = "ffmpeg")]
+fn defragment_mp4(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    let temp = FfmpegTempFiles::new();
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+    defragment_mp4_file(&temp, input.len(), config)
+}
+
+/// [`defragment_mp4`] of `temp.input`, `input_len` bytes long
+#[cfg(feature = "ffmpeg")]
+fn defragment_mp4_file(
+    temp: &FfmpegTempFiles,
+    input_len: usize,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0", "-c", "copy"]);
+    if config.strip != StripMode::None {
+        cmd.args(["-map_metadata", "-1"]);
+    }
+    cmd.args(["-movflags", "+faststart"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    read_ffmpeg_result(output, temp, input_len)
+}
+
+/// [`Mp4Processor::process_stream`] for what ffmpeg may have to run on: the input is
+/// copied to ffmpeg's temp file and only its boxes other than `mdat` are read back
+/// into memory, to validate it and plan the encode
+#[cfg(feature = "ffmpeg")]
+fn process_spooled(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    config: &ProcessingConfig,
+) -> Result<(), ProcessingError> {
+    let temp = FfmpegTempFiles::new();
+    let input_len = io::copy(input, &mut std::fs::File::create(&temp.input)?)? as usize;
+    let header = read_without_media(&temp.input)?;
+    let Mp4Header { duration, .. } = validate_mp4(&header)?;
+    let spooled = || std::fs::File::open(&temp.input);
+
+    if config.no_lossy {
+        // Only `defragment` gets here; the faststart move would need the whole file
+        if is_fragmented(&header) && is_ffmpeg_available() {
+            output.write_all(&defragment_mp4_file(&temp, input_len, config)?)?;
+            return Ok(());
+        }
+        return strip_mp4_stream(&mut io::BufReader::new(spooled()?), output, config.strip);
+    }
+    if !is_ffmpeg_available() {
+        log::warn!("ffmpeg not found - MP4 compression requires ffmpeg to be installed");
+        io::copy(&mut spooled()?, output)?;
+        return Ok(());
+    }
+
+    let duration = config.mp4.trim.map_or(duration, |trim| trim.length_within(duration));
+    let mut data = encode_mp4_file(&temp, &header, input_len, config, duration, &Progress::none())?;
+    clear_stale_rotation(&mut data);
+    output.write_all(&data)?;
+    Ok(())
+}
+
+/// Every top-level box of the file at `path` except `mdat`, which is seeked over
+#[cfg(feature = "ffmpeg")]
+fn read_without_media(path: &std::path::Path) -> Result<Vec<u8>, ProcessingError> {
+    let mut file = io::BufReader::new(std::fs::File::open(path)?);
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+    while let Some(BoxHeader { kind, raw, size }) = read_box_header(&mut file, offset)? {
+        let body_len = size.map(|size| size - raw.len() as u64);
+        match body_len {
+            Some(len) if &kind == b"mdat" => {
+                let len = i64::try_from(len).map_err(|_| corrupt_mp4(offset, "mdat too large".to_string()))?;
+                file.seek_relative(len)?;
+            }
+            None if &kind == b"mdat" => break,
+            _ => {
+                boxes.extend_from_slice(&raw);
+                read_body(&mut file, body_len, offset, &mut boxes)?;
+            }
+        }
+        match size {
+            Some(size) => offset += size,
+            None => break,
+        }
+    }
+    Ok(boxes)
+}
+
+/// Without the `ffmpeg` feature nothing is spooled; only `supports_streaming` inputs are streamed
+#[cfg(not(feature = "ffmpeg"))]
+fn process_spooled(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    config: &ProcessingConfig,
+) -> Result<(), ProcessingError> {
+    process_buffered(&Mp4Processor, input, output, config)
+}
+
+/// Never called: `is_ffmpeg_available` is false without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+fn defragment_mp4(_input: &[u8], _config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Without the `ffmpeg` feature there is nothing to encode WebM with
+#[cfg(not(feature = "ffmpeg"))]
+pub fn video_to_webm(_input: &[u8], _config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Without the `ffmpeg` feature there is nothing to encode animations with
+#[cfg(not(feature = "ffmpeg"))]
+pub fn video_to_animation(
+    _input: &[u8],
+    _target: Format,
+    _config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Never called: `is_ffmpeg_available` is false without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+fn compress_mp4_with_ffmpeg(
+    _input: &[u8],
+    _config: &ProcessingConfig,
+    _duration: Duration,
+    _progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Top-level boxes dropped by the streaming metadata strip
+const STRIPPED_TOP_LEVEL: [&[u8; 4]; 4] = [b"udta", b"meta", b"free", b"skip"];
+
+/// Boxes inside `moov` that carry metadata rather than media structure
+const STRIPPED_IN_MOOV: [&[u8; 4]; 2] = [b"udta", b"meta"];
+
+/// Boxes inside `moov` whose children are filtered and searched for chunk offsets
+const MOOV_CONTAINERS: [&[u8; 4]; 7] = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"edts", b"dinf"];
+
+/// Streaming MP4 metadata strip without re-encoding.
+///
+/// Top-level boxes are copied one by one; only `moov` (and any small boxes between a
+/// leading `moov` and the first `mdat`) is held in memory, so `stco`/`co64` chunk
+/// offsets can be shifted by the bytes removed in front of the media data. Once
+/// `moov` is written nothing else is removed, which keeps those offsets valid.
+pub fn strip_mp4_stream(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    strip_mode: StripMode,
+) -> Result<(), ProcessingError> {
+    if strip_mode == StripMode::None {
+        io::copy(input, output)?;
+        return Ok(());
+    }
+
+    let mut offset = 0u64;
+    // (original offset, bytes removed) for every removal
+    let mut removed: Vec<(u64, u64)> = Vec::new();
+    // `moov` seen before any `mdat`, and the boxes following it, wait for the first `mdat`
+    let mut pending_moov: Option<Vec<u8>> = None;
+    let mut held: Vec<u8> = Vec::new();
+    let mut moov_written = false;
+    let mut mdat_seen = false;
+
+    while let Some(BoxHeader { kind, raw: header, size: box_size }) = read_box_header(input, offset)? {
+        let header_len = header.len() as u64;
+        let body_len = box_size.map(|size| size - header_len);
+
+        if !moov_written && STRIPPED_TOP_LEVEL.contains(&&kind) {
+            if let Some(len) = body_len {
+                io::copy(&mut input.take(len), &mut io::sink())?;
+                log::debug!("Stripping MP4 box: {}", String::from_utf8_lossy(&kind));
+                removed.push((offset, header_len + len));
+                offset += header_len + len;
+                continue;
+            }
+        }
+
+        if &kind == b"moov" {
+            let mut moov = header;
+            read_body(input, body_len, offset, &mut moov)?;
+            let filtered = filter_container(&moov);
+            if filtered.len() < moov.len() {
+                removed.push((offset, (moov.len() - filtered.len()) as u64));
+            }
+            offset += moov.len() as u64;
+
+            if mdat_seen {
+                // moov after the media data: every removal in front of it is known
+                write_moov(output, filtered, &removed)?;
+                moov_written = true;
+            } else {
+                pending_moov = Some(filtered);
+            }
+            continue;
+        }
+
+        if &kind == b"mdat" && !mdat_seen {
+            mdat_seen = true;
+            if let Some(moov) = pending_moov.take() {
+                write_moov(output, moov, &removed)?;
+                output.write_all(&held)?;
+                held.clear();
+                moov_written = true;
+            }
+        }
+
+        if matches!(&kind, b"moof" | b"mfra") && !removed.is_empty() {
+            // Fragments may point at absolute file offsets, which moved
+            let mut fragment = header;
+            read_body(input, body_len, offset, &mut fragment)?;
+            let header_len = box_header_len(&fragment);
+            patch_fragment_offsets(&kind, &mut fragment[header_len..], &|at| shift_by_removed(at, &removed));
+            offset += fragment.len() as u64;
+            if pending_moov.is_some() {
+                held.extend_from_slice(&fragment);
+            } else {
+                output.write_all(&fragment)?;
+            }
+            continue;
+        }
+
+        if pending_moov.is_some() {
+            held.extend_from_slice(&header);
+            read_body(input, body_len, offset, &mut held)?;
+        } else {
+            output.write_all(&header)?;
+            match body_len {
+                Some(len) => {
+                    io::copy(&mut input.take(len), output)?;
+                }
+                None => {
+                    io::copy(input, output)?;
+                }
+            }
+        }
+        offset += box_size.unwrap_or(0);
+    }
+
+    if let Some(moov) = pending_moov.take() {
+        write_moov(output, moov, &removed)?;
+        output.write_all(&held)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+fn corrupt_mp4(offset: u64, reason: String) -> ProcessingError {
+    ProcessingError::CorruptInput {
+        format: "mp4".to_string(),
+        offset: Some(offset),
+        reason,
+    }
+}
+
+/// Top-level box header as read from a stream
+struct BoxHeader {
+    kind: [u8; 4],
+    /// Raw header bytes (8, or 16 with a 64-bit size)
+    raw: Vec<u8>,
+    /// Total box size, or None if the box runs to the end of the stream
+    size: Option<u64>,
+}
+
+/// Read a top-level box header starting at `offset`. Returns `None` at a clean end of stream.
+fn read_box_header(input: &mut dyn Read, offset: u64) -> Result<Option<BoxHeader>, ProcessingError> {
+    let mut header = Vec::with_capacity(16);
+    input.take(8).read_to_end(&mut header)?;
+    if header.is_empty() {
+        return Ok(None);
+    }
+    if header.len() < 8 {
+        return Err(corrupt_mp4(offset, "truncated box header".to_string()));
+    }
+
+    let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let size = match size32 {
+        0 => None,
+        1 => {
+            input.take(8).read_to_end(&mut header)?;
+            if header.len() < 16 {
+                return Err(corrupt_mp4(offset, "truncated box header".to_string()));
+            }
+            let large = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            Some(large)
+        }
+        size => Some(size),
+    };
+
+    if let Some(size) = size {
+        if size < header.len() as u64 {
+            return Err(corrupt_mp4(
+                offset,
+                format!("invalid box size {} for {}", size, String::from_utf8_lossy(&kind)),
+            ));
+        }
+    }
+
+    Ok(Some(BoxHeader { kind, raw: header, size }))
+}
+
+/// Append a box body (or the rest of the stream if `len` is None) to `buf`;
+/// `offset` is where the box starts, for error reporting
+fn read_body(input: &mut dyn Read, len: Option<u64>, offset: u64, buf: &mut Vec<u8>) -> Result<(), ProcessingError> {
+    match len {
+        Some(len) => {
+            let read = input.take(len).read_to_end(buf)?;
+            if (read as u64) < len {
+                return Err(corrupt_mp4(offset, "truncated box".to_string()));
+            }
+        }
+        None => {
+            input.read_to_end(buf)?;
+        }
+    }
+    Ok(())
+}
+
+/// Patch chunk offsets in a filtered `moov` and write it out
+fn write_moov(output: &mut dyn Write, mut moov: Vec<u8>, removed: &[(u64, u64)]) -> Result<(), ProcessingError> {
+    if !removed.is_empty() {
+        let header_len = box_header_len(&moov);
+        patch_chunk_offsets(&mut moov[header_len..], &|offset| shift_by_removed(offset, removed));
+    }
+    output.write_all(&moov)?;
+    Ok(())
+}
+
+/// Where an original file offset ends up after the `(offset, len)` removals
+fn shift_by_removed(offset: u64, removed: &[(u64, u64)]) -> u64 {
+    let delta: u64 = removed.iter().filter(|(at, _)| *at < offset).map(|(_, len)| len).sum();
+    offset.saturating_sub(delta)
+}
+
+/// Map the absolute file offsets in a `moof` (`tfhd` base data offset) or `mfra`
+/// (`tfra` moof offsets) body through `shift`. Offsets relative to the `moof`
+/// (default-base-is-moof) need no change.
+fn patch_fragment_offsets(kind: &[u8; 4], body: &mut [u8], shift: &dyn Fn(u64) -> u64) {
+    let Some(children) = child_boxes(body) else {
+        return;
+    };
+
+    for ChildBox { kind: child_kind, start, header_len, end } in children {
+        let child = &mut body[start + header_len..end];
+        match (kind, &child_kind) {
+            (b"moof", b"traf") => patch_fragment_offsets(&child_kind, child, shift),
+            // version/flags, track id, then base_data_offset when flag 0x1 is set
+            (b"traf", b"tfhd") if child.len() >= 16 && child[3] & 0x01 != 0 => {
+                let old = u64::from_be_bytes(child[8..16].try_into().unwrap());
+                child[8..16].copy_from_slice(&shift(old).to_be_bytes());
+            }
+            (b"mfra", b"tfra") if child.len() >= 16 => {
+                let wide = child[0] == 1;
+                let sizes = child[11];
+                let count = u32::from_be_bytes([child[12], child[13], child[14], child[15]]) as usize;
+                let field = if wide { 8 } else { 4 };
+                // time, moof_offset, then traf/trun/sample numbers of 1-4 bytes each
+                let entry_len = 2 * field
+                    + ((sizes >> 4) & 3) as usize
+                    + ((sizes >> 2) & 3) as usize
+                    + (sizes & 3) as usize
+                    + 3;
+                for entry in child[16..].chunks_exact_mut(entry_len).take(count) {
+                    let at = &mut entry[field..2 * field];
+                    if wide {
+                        let old = u64::from_be_bytes(at[..8].try_into().unwrap());
+                        at.copy_from_slice(&shift(old).to_be_bytes());
+                    } else {
+                        let old = u32::from_be_bytes([at[0], at[1], at[2], at[3]]) as u64;
+                        at.copy_from_slice(&(shift(old) as u32).to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Lossless MP4 optimization without ffmpeg: strip metadata per `strip_mode`, then
+/// move `moov` in front of the first `mdat` so playback can start before the whole
+/// file is downloaded. Chunk offsets are rewritten for both steps.
+///
+/// Files of 4 GiB or more keep their box order, since moving `moov` could push
+/// 32-bit `stco` offsets out of range.
+pub fn faststart_mp4(input: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
+    let mut data = Vec::with_capacity(input.len());
+    strip_mp4_stream(&mut &input[..], &mut data, strip_mode)?;
+
+    let mut moov = None;
+    let mut first_mdat = None;
+    let mut pos = 0u64;
+    while let Some(header) = read_box_header(&mut &data[pos as usize..], pos)? {
+        let size = header.size.unwrap_or(data.len() as u64 - pos);
+        match &header.kind {
+            b"mdat" if first_mdat.is_none() => first_mdat = Some(pos as usize),
+            b"moov" => {
+                moov = Some((pos as usize, (pos + size) as usize));
+                break;
+            }
+            _ => {}
+        }
+        if header.size.is_none() || pos + size > data.len() as u64 {
+            break;
+        }
+        pos += size;
+    }
+
+    if is_fragmented(&data) {
+        // Fragments reference absolute offsets and must stay behind their moov
+        return Ok(data);
+    }
+    let (Some((moov_start, moov_end)), Some(insert_at)) = (moov, first_mdat) else {
+        // Already fast start, or no moov/mdat to reorder
+        return Ok(data);
+    };
+    if moov_start < insert_at {
+        return Ok(data);
+    }
+    let moov_len = moov_end - moov_start;
+    if data.len() as u64 + moov_len as u64 > u32::MAX as u64 {
+        log::debug!("MP4 too large to relocate moov with 32-bit chunk offsets, keeping box order");
+        return Ok(data);
+    }
+
+    log::debug!("Moving moov ({} bytes) in front of mdat", moov_len);
+    let mut moov_box = data[moov_start..moov_end].to_vec();
+    let header_len = box_header_len(&moov_box);
+    // Media between the first mdat and the old moov position moves down by moov_len
+    patch_chunk_offsets(&mut moov_box[header_len..], &|offset| {
+        if (insert_at as u64..moov_start as u64).contains(&offset) {
+            offset + moov_len as u64
+        } else {
+            offset
+        }
+    });
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..insert_at]);
+    output.extend_from_slice(&moov_box);
+    output.extend_from_slice(&data[insert_at..moov_start]);
+    output.extend_from_slice(&data[moov_end..]);
+    Ok(output)
+}
+
+/// Header length (8, or 16 with a 64-bit size) of an in-memory box
+fn box_header_len(data: &[u8]) -> usize {
+    if data.len() >= 16 && u32::from_be_bytes([data[0], data[1], data[2], data[3]]) == 1 {
+        16
+    } else {
+        8
+    }
+}
+
+/// Child box located inside an in-memory container body
+struct ChildBox {
+    kind: [u8; 4],
+    start: usize,
+    header_len: usize,
+    end: usize,
+}
+
+/// Child boxes of a container body. Returns `None` if the body is malformed.
+fn child_boxes(body: &[u8]) -> Option<Vec<ChildBox>> {
+    let mut children = Vec::new();
+    let mut pos = 0usize;
+
+    while pos + 8 <= body.len() {
+        let size32 = u32::from_be_bytes([body[pos], body[pos + 1], body[pos + 2], body[pos + 3]]) as usize;
+        let kind = [body[pos + 4], body[pos + 5], body[pos + 6], body[pos + 7]];
+        let (size, header_len) = match size32 {
+            0 => (body.len() - pos, 8),
+            1 => {
+                if pos + 16 > body.len() {
+                    return None;
+                }
+                let large = u64::from_be_bytes(body[pos + 8..pos + 16].try_into().unwrap());
+                (usize::try_from(large).ok()?, 16)
+            }
+            size => (size, 8),
+        };
+
+        if size < header_len || pos + size > body.len() {
+            return None;
+        }
+
+        children.push(ChildBox { kind, start: pos, header_len, end: pos + size });
+        pos += size;
+    }
+
+    Some(children)
+}
+
+/// Type and size of the `udta`/`meta` boxes stripping removes: top-level ones
+/// and those anywhere in the `moov` tree, in file order
+pub(crate) fn metadata_boxes(data: &[u8]) -> Vec<([u8; 4], u64)> {
+    fn collect(body: &[u8], boxes: &mut Vec<([u8; 4], u64)>) {
+        for ChildBox { kind, start, header_len, end } in child_boxes(body).unwrap_or_default() {
+            if STRIPPED_IN_MOOV.contains(&&kind) {
+                boxes.push((kind, (end - start) as u64));
+            } else if MOOV_CONTAINERS.contains(&&kind) {
+                collect(&body[start + header_len..end], boxes);
+            }
+        }
+    }
+
+    let mut boxes = Vec::new();
+    collect(data, &mut boxes);
+    boxes
+}
+
+/// [`metadata_boxes`] of the MP4 in `input`, reading only the `moov` box into
+/// memory and seeking over the media
+pub(crate) fn metadata_boxes_in<R: Read + io::Seek>(input: &mut R) -> Result<Vec<([u8; 4], u64)>, ProcessingError> {
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+    while let Some(BoxHeader { kind, raw: header, size }) = read_box_header(input, offset)? {
+        let size = match size {
+            Some(size) => size,
+            // The last box, running to the end of the file
+            None => {
+                let end = input.seek(io::SeekFrom::End(0))?;
+                input.seek(io::SeekFrom::Start(offset + header.len() as u64))?;
+                end - offset
+            }
+        };
+        if STRIPPED_IN_MOOV.contains(&&kind) {
+            boxes.push((kind, size));
+        } else if MOOV_CONTAINERS.contains(&&kind) {
+            let body_len = size - header.len() as u64;
+            let mut container = header;
+            read_body(input, Some(body_len), offset, &mut container)?;
+            boxes.extend(metadata_boxes(&container));
+        }
+        offset += size;
+        input.seek(io::SeekFrom::Start(offset))?;
+    }
+    Ok(boxes)
+}
+
+/// Rebuild a `moov`-tree container without metadata children.
+/// Malformed containers are returned unchanged.
+fn filter_container(data: &[u8]) -> Vec<u8> {
+    let header_len = box_header_len(data);
+    let Some(children) = child_boxes(&data[header_len..]) else {
+        return data.to_vec();
+    };
+
+    let body = &data[header_len..];
+    let mut new_body = Vec::with_capacity(body.len());
+    for ChildBox { kind, start, end, .. } in children {
+        if STRIPPED_IN_MOOV.contains(&&kind) {
+            log::debug!("Stripping MP4 box: {}", String::from_utf8_lossy(&kind));
+        } else if MOOV_CONTAINERS.contains(&&kind) {
+            new_body.extend_from_slice(&filter_container(&body[start..end]));
+        } else {
+            new_body.extend_from_slice(&body[start..end]);
+        }
+    }
+
+    let mut out = Vec::with_capacity(new_body.len() + 16);
+    let total = new_body.len() as u64 + 8;
+    if total <= u32::MAX as u64 {
+        out.extend_from_slice(&(total as u32).to_be_bytes());
+        out.extend_from_slice(&data[4..8]);
+    } else {
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(&data[4..8]);
+        out.extend_from_slice(&(total + 8).to_be_bytes());
+    }
+    out.extend_from_slice(&new_body);
+    out
+}
+
+/// Map every `stco`/`co64` entry in a `moov`-tree body through `shift`
+fn patch_chunk_offsets(body: &mut [u8], shift: &dyn Fn(u64) -> u64) {
+    let Some(children) = child_boxes(body) else {
+        return;
+    };
+
+    for ChildBox { kind, start, header_len, end } in children {
+        let child = &mut body[start + header_len..end];
+        match &kind {
+            k if MOOV_CONTAINERS.contains(&k) => patch_chunk_offsets(child, shift),
+            b"stco" if child.len() >= 8 => {
+                let count = u32::from_be_bytes([child[4], child[5], child[6], child[7]]) as usize;
+                for entry in child[8..].chunks_exact_mut(4).take(count) {
+                    let old = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as u64;
+                    entry.copy_from_slice(&(shift(old) as u32).to_be_bytes());
+                }
+            }
+            b"co64" if child.len() >= 8 => {
+                let count = u32::from_be_bytes([child[4], child[5], child[6], child[7]]) as usize;
+                for entry in child[8..].chunks_exact_mut(8).take(count) {
+                    let old = u64::from_be_bytes(entry[..8].try_into().unwrap());
+                    entry.copy_from_slice(&shift(old).to_be_bytes());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Child of a container body by type
+fn find_child(body: &[u8], kind: &[u8; 4]) -> Option<ChildBox> {
+    child_boxes(body)?.into_iter().find(|child| &child.kind == kind)
+}
+
+/// `udta` → `meta` → `ilst` inside a `moov` body: the iTunes metadata list
+fn ilst_body(moov: &[u8]) -> Option<&[u8]> {
+    let udta = find_child(moov, b"udta")?;
+    let udta = &moov[udta.start + udta.header_len..udta.end];
+    let meta = find_child(udta, b"meta")?;
+    // `meta` is a full box: version and flags come before its children
+    let meta = udta.get(meta.start + meta.header_len + 4..meta.end)?;
+    let ilst = find_child(meta, b"ilst")?;
+    Some(&meta[ilst.start + ilst.header_len..ilst.end])
+}
+
+/// Body of the top-level `moov` box and its range in `data`
+fn moov_box(data: &[u8]) -> Result<(ChildBox, &[u8]), ProcessingError> {
+    let top = child_boxes(data).ok_or_else(|| corrupt_mp4(0, "malformed top-level boxes".to_string()))?;
+    let moov = top
+        .into_iter()
+        .find(|child| &child.kind == b"moov")
+        .ok_or_else(|| corrupt_mp4(0, "no moov box".to_string()))?;
+    let body = &data[moov.start + moov.header_len..moov.end];
+    Ok((moov, body))
+}
+
+/// iTunes metadata items (`moov/udta/meta/ilst`) as atom and value, in file order.
+/// Track and disc numbers read `3/12`; other binary data reads `<N bytes>`.
+pub fn ilst_items(data: &[u8]) -> Result<Vec<([u8; 4], String)>, ProcessingError> {
+    let (_, moov) = moov_box(data)?;
+    let Some(ilst) = ilst_body(moov) else {
+        return Ok(Vec::new());
+    };
+    let mut items = Vec::new();
+    for item in child_boxes(ilst).unwrap_or_default() {
+        let body = &ilst[item.start + item.header_len..item.end];
+        let Some(value) = find_child(body, b"data") else { continue };
+        // Type indicator (version byte and 24-bit type), locale, then the value
+        let Some(value) = body.get(value.start + value.header_len..value.end).filter(|v| v.len() >= 8) else {
+            continue;
+        };
+        let kind = u32::from_be_bytes([0, value[1], value[2], value[3]]);
+        let payload = &value[8..];
+        let text = match (&item.kind, kind) {
+            (_, 1) => String::from_utf8_lossy(payload).into_owned(),
+            (b"trkn" | b"disk", 0) if payload.len() >= 6 => {
+                let number = u16::from_be_bytes([payload[2], payload[3]]);
+                match u16::from_be_bytes([payload[4], payload[5]]) {
+                    0 => number.to_string(),
+                    total => format!("{}/{}", number, total),
+                }
+            }
+            _ => format!("<{} bytes>", payload.len()),
+        };
+        items.push((item.kind, text));
+    }
+    Ok(items)
+}
+
+/// `data` with the metadata items in `delete` removed and those in `set` replaced
+/// by UTF-8 text items (`trkn` takes `3` or `3/12`). The rebuilt `moov` keeps its
+/// place; chunk offsets behind it are shifted by its change in size.
+pub fn with_ilst_items(data: &[u8], set: &[([u8; 4], String)], delete: &[[u8; 4]]) -> Result<Vec<u8>, ProcessingError> {
+    if is_fragmented(data) {
+        return Err(ProcessingError::UnsupportedFormat(
+            "fragmented MP4 (tags can only be written to unfragmented files)".to_string(),
+        ));
+    }
+    let (moov, moov_body) = moov_box(data)?;
+
+    let mut ilst = Vec::new();
+    if let Some(old) = ilst_body(moov_body) {
+        for item in child_boxes(old).unwrap_or_default() {
+            if !set.iter().map(|(kind, _)| kind).chain(delete).any(|kind| *kind == item.kind) {
+                ilst.extend_from_slice(&old[item.start..item.end]);
+            }
+        }
+    }
+    for (kind, value) in set {
+        let (type_indicator, payload) = match kind {
+            b"trkn" => (0, track_number(value)?),
+            _ => (1, value.as_bytes().to_vec()),
+        };
+        let mut data_body = Vec::with_capacity(payload.len() + 8);
+        data_body.extend_from_slice(&(type_indicator as u32).to_be_bytes());
+        data_body.extend_from_slice(&[0; 4]);
+        data_body.extend_from_slice(&payload);
+        ilst.extend_from_slice(&mp4_box(kind, &mp4_box(b"data", &data_body)));
+    }
+
+    let new_moov = mp4_box(b"moov", &with_udta_ilst(moov_body, &ilst));
+    let delta = new_moov.len() as i64 - (moov.end - moov.start) as i64;
+    if data.len() as i64 + delta > u32::MAX as i64 {
+        return Err(ProcessingError::LimitExceeded("MP4 files of 4 GiB or more cannot be retagged".to_string()));
+    }
+    let mut new_moov = new_moov;
+    // Media stored behind the moov moves with its change in size
+    let moov_end = moov.end as u64;
+    patch_chunk_offsets(&mut new_moov[8..], &|offset| {
+        if offset >= moov_end {
+            (offset as i64 + delta) as u64
+        } else {
+            offset
+        }
+    });
+
+    let mut output = Vec::with_capacity(data.len() + new_moov.len());
+    output.extend_from_slice(&data[..moov.start]);
+    output.extend_from_slice(&new_moov);
+    output.extend_from_slice(&data[moov.end..]);
+    Ok(output)
+}
+
+/// `moov` body with the `ilst` in `udta/meta` replaced by one holding `items`,
+/// creating the boxes on the way and dropping those left empty
+fn with_udta_ilst(moov: &[u8], items: &[u8]) -> Vec<u8> {
+    let udta = find_child(moov, b"udta");
+    let udta_body = udta.as_ref().map_or(&[][..], |udta| &moov[udta.start + udta.header_len..udta.end]);
+    let meta = find_child(udta_body, b"meta").filter(|meta| meta.end - meta.start >= meta.header_len + 4);
+
+    // Meta: version and flags, then the handler (`mdir`, required by players) and the list
+    let mut meta_body = vec![0; 4];
+    let mut has_handler = false;
+    if let Some(meta) = &meta {
+        let old = &udta_body[meta.start + meta.header_len..meta.end];
+        meta_body[..4].copy_from_slice(&old[..4]);
+        for child in child_boxes(&old[4..]).unwrap_or_default() {
+            has_handler |= &child.kind == b"hdlr";
+            if &child.kind != b"ilst" {
+                meta_body.extend_from_slice(&old[4 + child.start..4 + child.end]);
+            }
+        }
+    }
+    if !items.is_empty() {
+        if !has_handler {
+            let mut hdlr = vec![0; 8];
+            hdlr.extend_from_slice(b"mdirappl");
+            hdlr.extend_from_slice(&[0; 9]);
+            meta_body.splice(4..4, mp4_box(b"hdlr", &hdlr));
+        }
+        meta_body.extend_from_slice(&mp4_box(b"ilst", items));
+    }
+    let meta_empty = child_boxes(&meta_body[4..]).unwrap_or_default().iter().all(|child| &child.kind == b"hdlr");
+
+    let mut new_udta = Vec::with_capacity(udta_body.len() + items.len());
+    let mut placed = false;
+    for child in child_boxes(udta_body).unwrap_or_default() {
+        if &child.kind == b"meta" && !placed {
+            placed = true;
+            if !meta_empty {
+                new_udta.extend_from_slice(&mp4_box(b"meta", &meta_body));
+            }
+        } else {
+            new_udta.extend_from_slice(&udta_body[child.start..child.end]);
+        }
+    }
+    if !placed && !meta_empty {
+        new_udta.extend_from_slice(&mp4_box(b"meta", &meta_body));
+    }
+
+    let mut new_moov = Vec::with_capacity(moov.len() + items.len());
+    let mut placed = false;
+    for child in child_boxes(moov).unwrap_or_default() {
+        if &child.kind == b"udta" && !placed {
+            placed = true;
+            if !new_udta.is_empty() {
+                new_moov.extend_from_slice(&mp4_box(b"udta", &new_udta));
+            }
+        } else {
+            new_moov.extend_from_slice(&moov[child.start..child.end]);
+        }
+    }
+    if !placed && !new_udta.is_empty() {
+        new_moov.extend_from_slice(&mp4_box(b"udta", &new_udta));
+    }
+    new_moov
+}
+
+/// `trkn` payload for `3` or `3/12`: reserved, number, total, reserved
+fn track_number(value: &str) -> Result<Vec<u8>, ProcessingError> {
+    let invalid = || ProcessingError::InvalidConfig(format!("track must be a number or number/total, got '{}'", value));
+    let (number, total) = value.split_once('/').unwrap_or((value, "0"));
+    let number: u16 = number.trim().parse().map_err(|_| invalid())?;
+    let total: u16 = total.trim().parse().map_err(|_| invalid())?;
+    let mut payload = vec![0, 0];
+    payload.extend_from_slice(&number.to_be_bytes());
+    payload.extend_from_slice(&total.to_be_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    Ok(payload)
+}
+
+/// A box with an 8-byte header
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// Async counterpart of [`Mp4Processor`]: runs ffmpeg through `tokio::process`
+/// so the calling runtime is never blocked on the subprocess.
+#[cfg(feature = "async")]
+pub async fn compress_mp4_async<'a>(
+    input: &'a [u8],
+    config: &ProcessingConfig,
+    progress: &Progress<'_>,
+) -> Result<ProcessOutcome<'a>, ProcessingError> {
+    let header = validate_mp4(input)?;
+
+    let ffmpeg_available = tokio::task::spawn_blocking(is_ffmpeg_available).await.unwrap_or(false);
+    if !ffmpeg_available {
+        return Ok(ffmpeg_missing(input, header.dimensions));
+    }
+
+    log::debug!("MP4 lossy mode: re-encoding with quality {}", config.quality_for(Format::Mp4));
+    let plan = EncodePlan::new(input, config)?;
+    let command = |args: &[std::ffi::OsString]| {
+        let mut cmd = tokio::process::Command::new(ffmpeg::binary());
+        cmd.args(args);
+        log::debug!("Executing (async): ffmpeg {:?}", cmd.as_std().get_args().collect::<Vec<_>>());
+        cmd
+    };
+
+    let temp = FfmpegTempFiles::new();
+    tokio::fs::write(&temp.input, input)
+        .await
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    progress.start(Stage::Ffmpeg);
+
+    let (encode, analysis, share) = plan.passes(config, &temp);
+    if let Some(args) = analysis {
+        let output = ffmpeg::run_async(&mut command(&args)).await?;
+        if !output.status.success() {
+            return Err(ffmpeg_failed(&output));
+        }
+        progress.report(Stage::Ffmpeg, share);
+    }
+
+    let output = ffmpeg::run_async(&mut command(&encode)).await?;
+    let data = read_ffmpeg_result(output, &temp, input.len())?;
+    progress.finish(Stage::Ffmpeg);
+
+    Ok(encoded_outcome(input, data, config, &header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_boxes_in() {
+        let mut data = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let moov = [mp4_box(b"mvhd", &[0; 100]), mp4_box(b"udta", &[0; 20])].concat();
+        data.extend(mp4_box(b"moov", &moov));
+        data.extend(mp4_box(b"mdat", &[0; 4096]));
+        data.extend(mp4_box(b"meta", &[0; 12]));
+        // A last box of size 0 runs to the end of the file
+        data.extend([0, 0, 0, 0]);
+        data.extend(b"free");
+        data.extend([0; 16]);
+
+        let boxes = metadata_boxes_in(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(boxes, [(*b"udta", 28), (*b"meta", 20)]);
+        assert_eq!(boxes, metadata_boxes(&data));
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_read_png_frame_splits_stream() {
+        let png = |shade: u8| {
+            let mut data = Vec::new();
+            image::GrayImage::from_pixel(4, 4, image::Luma([shade]))
+                .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+                .unwrap();
+            data
+        };
+        let (first, second) = (png(10), png(200));
+        let stream = [first.as_slice(), second.as_slice()].concat();
+        let mut reader = stream.as_slice();
+
+        assert_eq!(read_png_frame(&mut reader).unwrap().unwrap(), first);
+        assert_eq!(read_png_frame(&mut reader).unwrap().unwrap(), second);
+        assert!(read_png_frame(&mut reader).unwrap().is_none());
+
+        let mut truncated = &stream[..first.len() - 3];
+        assert!(matches!(read_png_frame(&mut truncated), Err(ProcessingError::CorruptInput { .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_optimize_frames_stops_on_failure() {
+        let mut good = Vec::new();
+        image::GrayImage::from_fn(32, 32, |x, y| image::Luma([(x * y) as u8]))
+            .write_to(&mut Cursor::new(&mut good), image::ImageFormat::Png)
+            .unwrap();
+        let stream = good.repeat(200);
+        // A directory where the first frame goes makes writing it fail
+        let dir = std::env::temp_dir().join(format!("ip_frames_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("frame_0001.png")).unwrap();
+        let (done, result) = std::sync::mpsc::channel();
+        let worker_dir = dir.clone();
+        std::thread::spawn(move || {
+            let options = FrameExtraction { optimize: true, ..FrameExtraction::default() };
+            let _ = done.send(optimize_frames(&mut stream.as_slice(), &worker_dir, &options, 1));
+        });
+        // The single worker failing on the first frame used to leave the reader blocked for good
+        let outcome = result.recv_timeout(std::time::Duration::from_secs(60)).expect("optimize_frames hung");
+        assert!(outcome.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_strip_mp4_stream_patches_chunk_offsets() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let udta = mp4_box(b"udta", &[0u8; 20]);
+        let free = mp4_box(b"free", &[0u8; 12]);
+
+        // moov -> trak -> mdia -> minf -> stbl -> stco with one entry, pointing into mdat
+        let build_moov = |chunk_offset: u32| {
+            let mut stco_body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco_body.extend_from_slice(&chunk_offset.to_be_bytes());
+            let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco_body));
+            let trak = mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl)));
+            let mut moov_body = trak;
+            moov_body.extend(mp4_box(b"udta", &[0u8; 16]));
+            mp4_box(b"moov", &moov_body)
+        };
+
+        let moov_len = build_moov(0).len();
+        let mdat_start = ftyp.len() + udta.len() + moov_len + free.len();
+        let mut input = ftyp.clone();
+        input.extend(&udta);
+        input.extend(build_moov(mdat_start as u32 + 8));
+        input.extend(&free);
+        input.extend(mp4_box(b"mdat", b"media"));
+
+        let mut output = Vec::new();
+        strip_mp4_stream(&mut input.as_slice(), &mut output, StripMode::All).unwrap();
+
+        // udta, moov/udta (24 bytes) and free are gone
+        let new_mdat_start = mdat_start - udta.len() - 24 - free.len();
+        assert_eq!(output.len(), input.len() - udta.len() - 24 - free.len());
+        assert_eq!(&output[new_mdat_start + 4..new_mdat_start + 8], b"mdat");
+
+        let stco_entry = new_mdat_start - 4;
+        let offset = u32::from_be_bytes(output[stco_entry..stco_entry + 4].try_into().unwrap());
+        assert_eq!(offset as usize, new_mdat_start + 8);
+    }
+
+    #[test]
+    fn test_video_bitrate() {
+        // One second of 64x48 video in ten 1250-byte samples: 100 kbit/s
+        let config = mp4::Mp4Config {
+            major_brand: "isom".parse().unwrap(),
+            minor_version: 512,
+            compatible_brands: vec!["isom".parse().unwrap()],
+            timescale: 1000,
+        };
+        let mut writer = mp4::Mp4Writer::write_start(Cursor::new(Vec::new()), &config).unwrap();
+        let avc = mp4::AvcConfig {
+            width: 64,
+            height: 48,
+            seq_param_set: vec![0x67, 0x42, 0xC0, 0x0A],
+            pic_param_set: vec![0x68, 0xCE, 0x3C, 0x80],
+        };
+        let track = mp4::TrackConfig {
+            track_type: mp4::TrackType::Video,
+            timescale: 1000,
+            language: "und".to_string(),
+            media_conf: mp4::MediaConfig::AvcConfig(avc),
+        };
+        writer.add_track(&track).unwrap();
+        for i in 0..10 {
+            let bytes = vec![0u8; 1250].into();
+            let start_time = i * 100;
+            let sample = mp4::Mp4Sample { start_time, duration: 100, rendering_offset: 0, is_sync: true, bytes };
+            writer.write_sample(1, &sample).unwrap();
+        }
+        writer.write_end().unwrap();
+        let data = writer.into_writer().into_inner();
+
+        let header = validate_mp4(&data).unwrap();
+        assert_eq!(header.dimensions, Some((64, 48)));
+        assert_eq!(header.video_bitrate, Some(100_000));
+        // The file's average also counts the header boxes
+        assert!(average_bitrate(data.len(), header.duration).unwrap() > 100_000);
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_pass_args() {
+        let temp = FfmpegTempFiles::new();
+        let x264 = VideoEncoder { name: "libx264", hwaccel: None };
+        let args = pass_args(1, &temp, &x264);
+        assert_eq!(args[..2], ["-pass", "1"]);
+        assert!(!args.iter().any(|arg| arg == "-x265-params"));
+
+        let x265 = VideoEncoder { name: "libx265", hwaccel: None };
+        let args = pass_args(2, &temp, &x265);
+        let params = args.iter().position(|arg| arg == "-x265-params").map(|i| args[i + 1].to_string_lossy()).unwrap();
+        assert_eq!(params, format!("pass=2:stats='{}-x265.log'", temp.passlog.display()));
+
+        // Stats files of either encoder go with the temp files
+        let mut stats = temp.passlog.clone().into_os_string();
+        stats.push("-x265.log.cutree.temp");
+        std::fs::write(&stats, b"stats").unwrap();
+        drop(temp);
+        assert!(!std::path::Path::new(&stats).exists());
+    }
+
+    #[test]
+    fn test_clear_stale_rotation() {
+        // tkhd v0: version/flags, 20 bytes of times/ids, 16 reserved/layer/volume, matrix, size
+        let rotated_90: [i32; 9] = [0, 0x0001_0000, 0, -0x0001_0000, 0, 0, 0, 0, 0x4000_0000];
+        let mut tkhd = vec![0u8; 40];
+        tkhd.extend(rotated_90.iter().flat_map(|v| v.to_be_bytes()));
+        tkhd.extend([0u8; 8]);
+        let mut data = mp4_box(b"ftyp", b"isom");
+        data.extend(mp4_box(b"moov", &mp4_box(b"trak", &mp4_box(b"tkhd", &tkhd))));
+
+        clear_stale_rotation(&mut data);
+
+        let matrix_start = data.len() - 8 - 36;
+        let identity: Vec<u8> = IDENTITY_MATRIX.iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert_eq!(&data[matrix_start..matrix_start + 36], identity.as_slice());
+    }
+
+    #[test]
+    fn test_detect_hdr() {
+        let sample_entry = |colr: &[u8], extra: &[u8]| {
+            let mut body = vec![0u8; VISUAL_SAMPLE_ENTRY_LEN];
+            body.extend(mp4_box(b"colr", colr));
+            body.extend_from_slice(extra);
+            let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stsd.extend(mp4_box(b"hvc1", &body));
+            let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+            mp4_box(b"moov", &mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl))))
+        };
+
+        // nclx: BT.2020 primaries, PQ transfer, BT.2020nc matrix, limited range
+        let hdr10 = sample_entry(b"nclx\x00\x09\x00\x10\x00\x09\x00", &mp4_box(b"mdcv", &[0u8; 24]));
+        let info = detect_hdr(&hdr10).unwrap();
+        assert_eq!(info.transfer, HdrTransfer::Pq);
+        assert_eq!((info.primaries, info.matrix), (9, 9));
+        assert!(info.mastering_display && !info.content_light);
+
+        let hlg = sample_entry(b"nclx\x00\x09\x00\x12\x00\x09\x00", &[]);
+        assert_eq!(detect_hdr(&hlg).unwrap().transfer, HdrTransfer::Hlg);
+
+        let sdr = sample_entry(b"nclx\x00\x01\x00\x01\x00\x01\x00", &[]);
+        assert_eq!(detect_hdr(&sdr), None);
+    }
+
+    #[test]
+    fn test_strip_patches_fragment_offsets() {
+        let ftyp = mp4_box(b"ftyp", b"iso6");
+        let free = mp4_box(b"free", &[0u8; 12]);
+        let moov = mp4_box(b"moov", &mp4_box(b"mvex", &[]));
+        let moof_at = (ftyp.len() + free.len() + moov.len()) as u64;
+
+        // tfhd with an absolute base data offset (flag 0x1), tfra v0 with one entry
+        let mut tfhd = vec![0, 0, 0, 1, 0, 0, 0, 1];
+        tfhd.extend(moof_at.to_be_bytes());
+        let mut tfra = vec![0u8; 12];
+        tfra.extend(1u32.to_be_bytes());
+        tfra.extend(0u32.to_be_bytes());
+        tfra.extend((moof_at as u32).to_be_bytes());
+        tfra.extend([1, 1, 1]);
+
+        let mut input = [ftyp, free.clone(), moov].concat();
+        input.extend(mp4_box(b"moof", &mp4_box(b"traf", &mp4_box(b"tfhd", &tfhd))));
+        input.extend(mp4_box(b"mdat", &[0u8; 4]));
+        input.extend(mp4_box(b"mfra", &mp4_box(b"tfra", &tfra)));
+        assert!(is_fragmented(&input));
+
+        let mut output = Vec::new();
+        strip_mp4_stream(&mut &input[..], &mut output, StripMode::All).unwrap();
+
+        let new_at = moof_at - free.len() as u64;
+        assert_eq!(&output[new_at as usize + 4..new_at as usize + 8], b"moof");
+        // moof, traf and tfhd headers, then version/flags and track id
+        let base = new_at as usize + 32;
+        assert_eq!(u64::from_be_bytes(output[base..base + 8].try_into().unwrap()), new_at);
+        let end = output.len();
+        assert_eq!(u32::from_be_bytes(output[end - 7..end - 3].try_into().unwrap()) as u64, new_at);
+    }
+
+    #[test]
+    fn test_faststart_moves_moov_before_mdat() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let mdat = mp4_box(b"mdat", b"media");
+        let build_moov = |chunk_offset: u32| {
+            let mut co64_body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            co64_body.extend_from_slice(&(chunk_offset as u64).to_be_bytes());
+            let stbl = mp4_box(b"stbl", &mp4_box(b"co64", &co64_body));
+            mp4_box(b"moov", &mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl))))
+        };
+
+        let udta = mp4_box(b"udta", &[0u8; 10]);
+        let mut input = ftyp.clone();
+        input.extend(&udta);
+        input.extend(&mdat);
+        input.extend(build_moov((ftyp.len() + udta.len()) as u32 + 8));
+
+        let output = faststart_mp4(&input, StripMode::All).unwrap();
+
+        let moov = build_moov(0);
+        let mdat_start = ftyp.len() + moov.len();
+        assert_eq!(output.len(), ftyp.len() + moov.len() + mdat.len());
+        assert_eq!(&output[ftyp.len() + 4..ftyp.len() + 8], b"moov");
+        assert_eq!(&output[mdat_start..], &mdat[..]);
+        assert!(check_fast_start(&output).unwrap());
+
+        let entry = mdat_start - 8;
+        let offset = u64::from_be_bytes(output[entry..mdat_start].try_into().unwrap());
+        assert_eq!(offset as usize, mdat_start + 8);
+
+        // Already fast start: nothing left to do
+        assert_eq!(faststart_mp4(&output, StripMode::None).unwrap(), output);
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn test_read_without_media() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let moov = mp4_box(b"moov", &mp4_box(b"udta", &[0u8; 6]));
+        let path = std::env::temp_dir().join(format!("ip_mp4_spool_{}.mp4", std::process::id()));
+
+        std::fs::write(&path, [ftyp.clone(), mp4_box(b"mdat", &[7u8; 4096]), moov.clone()].concat()).unwrap();
+        assert_eq!(read_without_media(&path).unwrap(), [ftyp.clone(), moov.clone()].concat());
+
+        // An mdat running to the end of the file ends the header
+        let mut open_mdat = [ftyp.clone(), moov.clone()].concat();
+        open_mdat.extend([0, 0, 0, 0]);
+        open_mdat.extend(b"mdat");
+        open_mdat.extend([7u8; 64]);
+        std::fs::write(&path, &open_mdat).unwrap();
+        assert_eq!(read_without_media(&path).unwrap(), [ftyp, moov].concat());
+
+        std::fs::write(&path, b"\0\0\0\x20moov").unwrap();
+        assert!(matches!(read_without_media(&path), Err(ProcessingError::CorruptInput { .. })));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_with_ilst_items() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let build_moov = |chunk_offset: u32| {
+            let stco = mp4_box(b"stco", &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &chunk_offset.to_be_bytes()].concat());
+            mp4_box(b"moov", &mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &mp4_box(b"stbl", &stco)))))
+        };
+        let mdat_body = ftyp.len() + build_moov(0).len() + 8;
+        let input = [ftyp.clone(), build_moov(mdat_body as u32), mp4_box(b"mdat", b"media")].concat();
+        assert_eq!(ilst_items(&input).unwrap(), Vec::new());
+
+        let set = [(*b"\xa9nam", "Title".to_string()), (*b"trkn", "3/12".to_string())];
+        let tagged = with_ilst_items(&input, &set, &[]).unwrap();
+        assert_eq!(ilst_items(&tagged).unwrap(), set);
+        // The chunk offset follows mdat down the file
+        let stco = tagged.windows(4).position(|w| w == b"stco").unwrap();
+        let offset = u32::from_be_bytes(tagged[stco + 12..stco + 16].try_into().unwrap()) as usize;
+        assert_eq!(&tagged[offset..], b"media");
+
+        let untagged = with_ilst_items(&tagged, &[], &[*b"\xa9nam", *b"trkn"]).unwrap();
+        assert_eq!(untagged, input);
+        assert!(with_ilst_items(&input, &[(*b"trkn", "x".to_string())], &[]).is_err());
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_crf_mapping() {
+        // H.264 keeps the historical mapping
+        assert_eq!(quality_to_crf(VideoCodec::H264, 100), 18);
+        assert_eq!(quality_to_crf(VideoCodec::H264, 0), 35);
+        assert_eq!(quality_to_crf(VideoCodec::Vp9, 0), 50);
+        assert!(quality_to_crf(VideoCodec::Av1, 80) > quality_to_crf(VideoCodec::H264, 80));
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_webm_codec() {
+        assert_eq!(webm_codec(VideoCodec::Av1), VideoCodec::Av1);
+        assert_eq!(webm_codec(VideoCodec::Vp9), VideoCodec::Vp9);
+        assert_eq!(webm_codec(VideoCodec::H264), VideoCodec::Vp9);
+        assert_eq!(webm_codec(VideoCodec::Hevc), VideoCodec::Vp9);
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_scale_filter() {
+        let mut config = ProcessingConfig::default();
+        assert_eq!(scale_filter(&config), None);
+
+        config.mp4.max_height = Some(720);
+        assert_eq!(scale_filter(&config).as_deref(), Some("scale=-2:'min(ih,720)'"));
+
+        // An exact size takes precedence over the height cap
+        config.mp4.scale = Some((1280, 720));
+        assert_eq!(scale_filter(&config).as_deref(), Some("scale=1280:720"));
+
+        // VA-API uploads the scaled frames
+        let vaapi = VideoEncoder { name: "h264_vaapi", hwaccel: Some(HwAccel::Vaapi) };
+        let args = video_args(&config, &vaapi, None, 0);
+        let filters = args.iter().position(|arg| arg == "-vf").map(|i| &args[i + 1]);
+        assert_eq!(filters.map(String::as_str), Some("scale=1280:720,format=nv12,hwupload"));
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_rotation_before_scale() {
+        let mut config = ProcessingConfig::default();
+        config.mp4.scale = Some((720, 1280));
+        let x264 = VideoEncoder { name: "libx264", hwaccel: None };
+        let filters = |rotation| {
+            let args = video_args(&config, &x264, None, rotation);
+            args.iter().position(|arg| arg == "-vf").map(|i| args[i + 1].clone())
+        };
+
+        // A 90° phone video is turned upright, then scaled to the displayed size
+        assert_eq!(filters(90).as_deref(), Some("transpose=clock,scale=720:1280"));
+        assert_eq!(filters(270).as_deref(), Some("transpose=cclock,scale=720:1280"));
+        assert_eq!(filters(0).as_deref(), Some("scale=720:1280"));
+        assert!(input_args(&config, 90).contains(&"-noautorotate".to_string()));
+        assert!(input_args(&config, 0).is_empty());
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_selection_args() {
+        assert_eq!(selection_args(FrameSelection::Fps(2.0)).unwrap(), ["-vf", "fps=2"]);
+        assert_eq!(selection_args(FrameSelection::Fps(0.0)).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            selection_args(FrameSelection::EveryNth(10)).unwrap(),
+            ["-vf", "select=not(mod(n\\,10))", "-vsync", "vfr"]
+        );
+        assert_eq!(
+            selection_args(FrameSelection::Scenes(0.3)).unwrap(),
+            ["-vf", "select=eq(n\\,0)+gt(scene\\,0.3)", "-vsync", "vfr"]
+        );
+
+        for selection in [FrameSelection::EveryNth(0), FrameSelection::Scenes(-0.1), FrameSelection::Scenes(1.5)] {
+            assert!(matches!(selection_args(selection), Err(ProcessingError::InvalidConfig(_))));
+        }
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_hw_encoder() {
+        assert_eq!(hw_encoder(HwAccel::Videotoolbox, VideoCodec::Hevc), Some("hevc_videotoolbox"));
+        assert_eq!(hw_encoder(HwAccel::Nvenc, VideoCodec::Av1), Some("av1_nvenc"));
+        assert_eq!(hw_encoder(HwAccel::Qsv, VideoCodec::Vp9), Some("vp9_qsv"));
+        assert_eq!(hw_encoder(HwAccel::Vaapi, VideoCodec::H264), Some("h264_vaapi"));
+        // Families without an encoder for the codec
+        assert_eq!(hw_encoder(HwAccel::Videotoolbox, VideoCodec::Vp9), None);
+        assert_eq!(hw_encoder(HwAccel::Videotoolbox, VideoCodec::Av1), None);
+        assert_eq!(hw_encoder(HwAccel::Nvenc, VideoCodec::Vp9), None);
+        // Not families
+        assert_eq!(hw_encoder(HwAccel::None, VideoCodec::H264), None);
+        assert_eq!(hw_encoder(HwAccel::Auto, VideoCodec::H264), None);
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn test_select_encoder() {
+        let select = |hwaccel, codec| {
+            let mut config = ProcessingConfig::default();
+            config.mp4.hwaccel = hwaccel;
+            config.mp4.codec = codec;
+            select_encoder(&config)
+        };
+        // Whether the local ffmpeg has the encoder decides between the two outcomes
+        let chosen = |result: Result<VideoEncoder, ProcessingError>, expected: VideoEncoder| match result {
+            Ok(encoder) => assert_eq!(encoder, expected),
+            Err(error) => {
+                assert!(matches!(error, ProcessingError::FfmpegEncoderMissing(name) if name == expected.name))
+            }
+        };
+
+        chosen(select(HwAccel::None, VideoCodec::Hevc), VideoEncoder { name: "libx265", hwaccel: None });
+        chosen(
+            select(HwAccel::Nvenc, VideoCodec::H264),
+            VideoEncoder { name: "h264_nvenc", hwaccel: Some(HwAccel::Nvenc) },
+        );
+        assert!(matches!(
+            select(HwAccel::Videotoolbox, VideoCodec::Vp9),
+            Err(ProcessingError::InvalidConfig(_))
+        ));
+    }
 }
@@ -0,0 +1,701 @@
+//! Ogg Opus and Ogg Vorbis: comment header stripping and inspection. Only the
+//! comment packet (`OpusTags`, or the Vorbis comment header) is rewritten; the
+//! audio pages are copied, with new sequence numbers and checksums when the
+//! comments end up on a different number of pages.
+
+use std::collections::HashMap;
+
+use crate::config::{ProcessingConfig, StripMode};
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::outcome::ProcessOutcome;
+use crate::processor::{Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
+
+pub struct OggProcessor;
+
+/// Comment fields kept by `StripMode::Safe`, the ones the MP3 safe frames hold;
+/// ReplayGain and Opus R128 gain fields are kept too (see [`is_safe_field`])
+const SAFE_FIELDS: [&str; 6] = ["TITLE", "ARTIST", "ALBUM", "DATE", "GENRE", "TRACKNUMBER"];
+
+/// Page header flags
+const CONTINUED: u8 = 0x01;
+const FIRST_PAGE: u8 = 0x02;
+
+impl ImageProcessor for OggProcessor {
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Opus, Format::Ogg]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            strip_only: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let format = Format::from_bytes(input).filter(|format| *format == Format::Opus).unwrap_or(Format::Ogg);
+        if config.strip == StripMode::None {
+            return Ok(ProcessOutcome::unchanged(input, format));
+        }
+
+        progress.start(Stage::Strip);
+        let output = strip_ogg_metadata(input, config.strip)?;
+        progress.finish(Stage::Strip);
+
+        let metadata_removed = (input.len() as u64).saturating_sub(output.len() as u64);
+        let mut outcome = if output == input {
+            ProcessOutcome::unchanged(input, format)
+        } else {
+            ProcessOutcome::new(output, format)
+        };
+        outcome.metadata_removed = Some(metadata_removed);
+        Ok(outcome)
+    }
+
+    fn plan(&self, _input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        match config.strip {
+            StripMode::None => ProcessorPlan::new("none: --strip none leaves the file as is"),
+            _ => ProcessorPlan::new("strip comment fields; audio pages copied untouched"),
+        }
+    }
+}
+
+/// Drop comment fields: `All` removes every field, `Safe` keeps
+/// [`SAFE_FIELDS`] and gain fields. The vendor string, which the format
+/// requires, stays; Opus binary data after the comments is dropped. Streams of
+/// other codecs (Theora, FLAC, a skeleton) are copied as they are.
+pub(crate) fn strip_ogg_metadata(input: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
+    if strip_mode == StripMode::None {
+        return Ok(input.to_vec());
+    }
+    let (pages, error) = pages(input);
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut streams: HashMap<u32, Stream> = HashMap::new();
+    for page in &pages {
+        if page.flags & FIRST_PAGE != 0 {
+            match packets(page).next().filter(|(_, complete)| *complete).and_then(|(ident, _)| Codec::detect(ident)) {
+                Some(codec) => streams.insert(page.serial, Stream::new(codec)),
+                None => streams.remove(&page.serial),
+            };
+            output.extend_from_slice(page.raw);
+            continue;
+        }
+        let Some(stream) = streams.get_mut(&page.serial) else {
+            output.extend_from_slice(page.raw);
+            continue;
+        };
+        if stream.done {
+            let sequence = (page.sequence as i64 + stream.shift) as u32;
+            if sequence == page.sequence {
+                output.extend_from_slice(page.raw);
+            } else {
+                write_page(&mut output, page.flags, page.granule, page.serial, sequence, page.lacing, page.body);
+            }
+            continue;
+        }
+
+        // A header page after the identification header: written as it is, and
+        // replaced in place if the comments change once the headers are complete
+        stream.held.push((page, output.len()));
+        output.extend_from_slice(page.raw);
+        for (piece, complete) in packets(page) {
+            stream.partial.extend_from_slice(piece);
+            if complete {
+                stream.packets.push(std::mem::take(&mut stream.partial));
+            }
+        }
+        if stream.packets.len() + 1 < stream.codec.header_count() {
+            continue;
+        }
+        stream.done = true;
+        if stream.packets.len() + 1 > stream.codec.header_count() || !stream.partial.is_empty() {
+            return Err(corrupt_ogg(page.offset, "audio data shares a page with the headers"));
+        }
+        let comments = Comments::parse(stream.codec, &stream.packets[0])
+            .ok_or_else(|| corrupt_ogg(stream.held[0].0.offset, "unreadable comment header"))?;
+        let stripped = comments.strip(strip_mode);
+        if stripped == comments {
+            continue;
+        }
+
+        let removed = comments.fields.len() - stripped.fields.len();
+        log::debug!("Stripping {} of {} Ogg comments", removed, comments.fields.len());
+        stream.packets[0] = stripped.to_packet();
+        let new_pages = paginate(&stream.packets);
+        // Other streams' pages written between the held ones keep their order, after the new headers
+        let start = stream.held[0].1;
+        let written = output.split_off(start);
+        let mut pos = 0;
+        let mut others = Vec::with_capacity(written.len());
+        for &(held, at) in &stream.held {
+            others.extend_from_slice(&written[pos..at - start]);
+            pos = at - start + held.raw.len();
+        }
+        others.extend_from_slice(&written[pos..]);
+        let first = stream.held[0].0.sequence;
+        for (index, (flags, granule, lacing, body)) in new_pages.iter().enumerate() {
+            write_page(&mut output, *flags, *granule, page.serial, first + index as u32, lacing, body);
+        }
+        let headers = output.len() - start;
+        output.extend(others);
+        stream.shift = new_pages.len() as i64 - stream.held.len() as i64;
+
+        // Pages another stream holds after `start` moved with the rewrite
+        let replaced: Vec<(usize, usize)> = stream.held.iter().map(|(held, at)| (*at, held.raw.len())).collect();
+        for other in streams.values_mut().filter(|other| !other.done) {
+            for (_, at) in &mut other.held {
+                if *at > start {
+                    let before: usize = replaced.iter().filter(|(pos, _)| *pos < *at).map(|(_, len)| len).sum();
+                    *at = *at + headers - before;
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// `inspect` for Ogg Opus and Vorbis: codec parameters, vendor, duration and
+/// every comment field
+pub fn inspect_ogg(input: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let headers = headers(input);
+    let format = match headers.as_ref().map(|headers| headers.codec) {
+        Some(Codec::Opus) => Format::Opus,
+        _ => Format::Ogg,
+    };
+    let mut report = InspectionReport::new(format, input.len());
+    let (pages, error) = pages(input);
+    if let Some(error) = error {
+        report.errors.push(error.to_string());
+    }
+    let Some(headers) = headers else {
+        report.errors.push("No Opus or Vorbis stream found".to_string());
+        return Ok(report);
+    };
+
+    report.field("Codec", headers.codec.name());
+    if let Some(ident) = Ident::parse(headers.codec, &headers.ident) {
+        report.field("Channels", ident.channels);
+        report.field("Sample rate", format!("{} Hz", ident.sample_rate));
+        if let Some(pre_skip) = ident.pre_skip {
+            report.field("Pre-skip", format!("{} samples", pre_skip));
+        }
+    }
+    if let Some(duration) = duration(input) {
+        report.field("Duration", format!("{:.3} s", duration));
+    }
+    let serials: std::collections::HashSet<u32> = pages.iter().map(|page| page.serial).collect();
+    report.field("Pages", pages.len());
+    report.field("Logical streams", serials.len());
+
+    let Some(comments) = headers.comments.as_deref().and_then(|packet| Comments::parse(headers.codec, packet)) else {
+        report.errors.push("Could not read the comment header".to_string());
+        return Ok(report);
+    };
+    report.field("Vendor", &comments.vendor);
+    if headers.codec == Codec::Opus && !comments.extra.is_empty() {
+        report.field("Binary data after comments", format!("{} bytes", comments.extra.len()));
+    }
+
+    let mut section = Section::new("Vorbis Comments");
+    for field in &comments.fields {
+        let (key, value) = split_field(field);
+        let mut entry = Entry::new(key.to_ascii_uppercase());
+        entry.class = Some(if is_safe_field(&key) { EntryClass::Safe } else { EntryClass::Unsafe });
+        entry.size = Some(field.len() as u64);
+        if key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE") {
+            entry.description = Some("Cover art".to_string());
+            entry.field("Value", format!("{} bytes of base64 picture data", value.len()));
+        } else {
+            entry.field("Value", value);
+        }
+        section.entries.push(entry);
+    }
+    let safe_count = section.entries.iter().filter(|entry| entry.class == Some(EntryClass::Safe)).count();
+    section.summary =
+        Some(format!("{} safe fields, {} unsafe fields", safe_count, section.entries.len() - safe_count));
+    report.sections.push(section);
+    Ok(report)
+}
+
+/// Each comment field of the first Opus or Vorbis stream, upper-cased key and
+/// size (with its length prefix), in order
+pub(crate) fn metadata_comments(input: &[u8]) -> Vec<(String, u64)> {
+    let Some(comments) = headers(input)
+        .and_then(|headers| Comments::parse(headers.codec, headers.comments.as_deref()?))
+    else {
+        return Vec::new();
+    };
+    comments
+        .fields
+        .iter()
+        .map(|field| (split_field(field).0.to_ascii_uppercase(), field.len() as u64 + 4))
+        .collect()
+}
+
+/// Comment fields `--strip safe` removes
+pub(crate) fn unsafe_comment_count(input: &[u8]) -> usize {
+    metadata_comments(input).iter().filter(|(key, _)| !is_safe_field(key)).count()
+}
+
+/// Playing time of the first stream from its last granule position: 48 kHz
+/// samples less the pre-skip for Opus, samples at the stream's rate for Vorbis
+pub(crate) fn duration(input: &[u8]) -> Option<f64> {
+    let headers = headers(input)?;
+    let ident = Ident::parse(headers.codec, &headers.ident)?;
+    let (pages, _) = pages(input);
+    let granule = pages
+        .iter()
+        .rev()
+        .find(|page| page.serial == headers.serial && page.granule != u64::MAX)
+        .map(|page| page.granule)?;
+    match headers.codec {
+        Codec::Opus => Some(granule.saturating_sub(ident.pre_skip.unwrap_or(0) as u64) as f64 / 48_000.0),
+        Codec::Vorbis if ident.sample_rate > 0 => Some(granule as f64 / ident.sample_rate as f64),
+        Codec::Vorbis => None,
+    }
+}
+
+/// `KEY=value` fields count as safe by key, in any case
+fn is_safe_field(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SAFE_FIELDS.contains(&key.as_str()) || key.starts_with("REPLAYGAIN_") || key.starts_with("R128_")
+}
+
+/// Key and value of a `KEY=value` field; a field without `=` is all key
+fn split_field(field: &[u8]) -> (String, String) {
+    let text = String::from_utf8_lossy(field);
+    match text.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (text.into_owned(), String::new()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Opus,
+    Vorbis,
+}
+
+impl Codec {
+    /// Codec of a stream from its first packet
+    fn detect(ident: &[u8]) -> Option<Self> {
+        if ident.starts_with(b"OpusHead") {
+            Some(Codec::Opus)
+        } else if ident.starts_with(b"\x01vorbis") {
+            Some(Codec::Vorbis)
+        } else {
+            None
+        }
+    }
+
+    /// Header packets before the audio: identification and comments, plus setup for Vorbis
+    fn header_count(self) -> usize {
+        match self {
+            Codec::Opus => 2,
+            Codec::Vorbis => 3,
+        }
+    }
+
+    /// Start of the comment header packet
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Codec::Opus => b"OpusTags",
+            Codec::Vorbis => b"\x03vorbis",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Opus => "Opus",
+            Codec::Vorbis => "Vorbis",
+        }
+    }
+}
+
+/// What the identification header says about the audio
+struct Ident {
+    channels: u8,
+    /// The original input rate for Opus, which always decodes at 48 kHz
+    sample_rate: u32,
+    /// Opus only: samples to drop from the start of the decoded audio
+    pre_skip: Option<u16>,
+}
+
+impl Ident {
+    fn parse(codec: Codec, packet: &[u8]) -> Option<Self> {
+        let le16 = |at: usize| packet.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let le32 = |at: usize| packet.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        match codec {
+            Codec::Opus => {
+                Some(Self { channels: *packet.get(9)?, sample_rate: le32(12)?, pre_skip: Some(le16(10)?) })
+            }
+            Codec::Vorbis => Some(Self { channels: *packet.get(11)?, sample_rate: le32(12)?, pre_skip: None }),
+        }
+    }
+}
+
+/// A comment header: vendor string, `KEY=value` fields as stored, then what
+/// follows them (Vorbis's framing byte, or optional Opus binary data)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comments {
+    codec: Codec,
+    vendor: String,
+    fields: Vec<Vec<u8>>,
+    extra: Vec<u8>,
+}
+
+impl Comments {
+    fn parse(codec: Codec, packet: &[u8]) -> Option<Self> {
+        let mut rest = packet.strip_prefix(codec.comment_magic())?;
+        let mut field = || {
+            let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            let value = rest.get(4..4 + len)?;
+            rest = &rest[4 + len..];
+            Some(value)
+        };
+        let vendor = String::from_utf8_lossy(field()?).into_owned();
+        let count = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+        rest = &rest[4..];
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            fields.push(rest.get(4..4 + len)?.to_vec());
+            rest = &rest[4 + len..];
+        }
+        Some(Self { codec, vendor, fields, extra: rest.to_vec() })
+    }
+
+    fn strip(&self, strip_mode: StripMode) -> Self {
+        let fields = self
+            .fields
+            .iter()
+            .filter(|field| strip_mode == StripMode::Safe && is_safe_field(&split_field(field).0))
+            .cloned()
+            .collect();
+        let extra = match self.codec {
+            Codec::Opus => Vec::new(),
+            Codec::Vorbis => self.extra.clone(),
+        };
+        Self { fields, extra, ..self.clone() }
+    }
+
+    fn to_packet(&self) -> Vec<u8> {
+        let mut packet = self.codec.comment_magic().to_vec();
+        packet.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        packet.extend_from_slice(self.vendor.as_bytes());
+        packet.extend_from_slice(&(self.fields.len() as u32).to_le_bytes());
+        for field in &self.fields {
+            packet.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            packet.extend_from_slice(field);
+        }
+        packet.extend_from_slice(&self.extra);
+        packet
+    }
+}
+
+/// Header packets of the first Opus or Vorbis stream
+struct Headers {
+    codec: Codec,
+    serial: u32,
+    ident: Vec<u8>,
+    /// None when the file ends before the comment header does
+    comments: Option<Vec<u8>>,
+}
+
+fn headers(input: &[u8]) -> Option<Headers> {
+    let (pages, _) = pages(input);
+    let first = pages.iter().find(|page| {
+        page.flags & FIRST_PAGE != 0 && packets(page).next().is_some_and(|(ident, _)| Codec::detect(ident).is_some())
+    })?;
+    let ident = packets(first).next()?.0.to_vec();
+    let codec = Codec::detect(&ident)?;
+
+    let mut partial = Vec::new();
+    let later = pages.iter().filter(|page| page.serial == first.serial && page.offset > first.offset);
+    for page in later {
+        for (piece, complete) in packets(page) {
+            partial.extend_from_slice(piece);
+            if complete {
+                return Some(Headers { codec, serial: first.serial, ident, comments: Some(partial) });
+            }
+        }
+    }
+    Some(Headers { codec, serial: first.serial, ident, comments: None })
+}
+
+/// One Ogg page, borrowed from the input
+struct Page<'a> {
+    offset: usize,
+    /// The whole page, header included
+    raw: &'a [u8],
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    lacing: &'a [u8],
+    body: &'a [u8],
+}
+
+/// Pages in `input` up to the first malformed one, which is reported as the error
+fn pages(input: &[u8]) -> (Vec<Page<'_>>, Option<ProcessingError>) {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let Some(header) = input.get(pos..pos + 27).filter(|header| header.starts_with(b"OggS")) else {
+            return (pages, Some(corrupt_ogg(pos, "expected an OggS page")));
+        };
+        let segments = header[26] as usize;
+        let Some(lacing) = input.get(pos + 27..pos + 27 + segments) else {
+            return (pages, Some(corrupt_ogg(pos, "truncated page header")));
+        };
+        let body_start = pos + 27 + segments;
+        let body_len: usize = lacing.iter().map(|&len| len as usize).sum();
+        let Some(body) = input.get(body_start..body_start + body_len) else {
+            return (pages, Some(corrupt_ogg(pos, "truncated page")));
+        };
+        pages.push(Page {
+            offset: pos,
+            raw: &input[pos..body_start + body_len],
+            flags: header[5],
+            granule: u64::from_le_bytes(header[6..14].try_into().expect("8 bytes")),
+            serial: u32::from_le_bytes(header[14..18].try_into().expect("4 bytes")),
+            sequence: u32::from_le_bytes(header[18..22].try_into().expect("4 bytes")),
+            lacing,
+            body,
+        });
+        pos = body_start + body_len;
+    }
+    (pages, None)
+}
+
+/// Packet pieces of a page, each with whether the packet ends on this page
+fn packets<'a>(page: &'a Page) -> impl Iterator<Item = (&'a [u8], bool)> + 'a {
+    let mut lacing = page.lacing.iter().peekable();
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let start = pos;
+        lacing.peek()?;
+        for &len in lacing.by_ref() {
+            pos += len as usize;
+            if len < 255 {
+                return Some((&page.body[start..pos], true));
+            }
+        }
+        Some((&page.body[start..pos], false))
+    })
+}
+
+/// Header state of one Opus or Vorbis stream while stripping
+struct Stream<'a> {
+    codec: Codec,
+    /// Header packets after the identification header
+    packets: Vec<Vec<u8>>,
+    partial: Vec<u8>,
+    /// Header pages after the first, with where they were written in the output
+    held: Vec<(&'a Page<'a>, usize)>,
+    done: bool,
+    /// Change in page count from rewriting the headers, applied to the
+    /// sequence numbers of the stream's later pages
+    shift: i64,
+}
+
+impl Stream<'_> {
+    fn new(codec: Codec) -> Self {
+        Self { codec, packets: Vec::new(), partial: Vec::new(), held: Vec::new(), done: false, shift: 0 }
+    }
+}
+
+/// Lay `packets` out on pages: flags, granule position, lacing and body of
+/// each. The last packet ends the last page, as audio must start on a new one.
+fn paginate(packets: &[Vec<u8>]) -> Vec<(u8, u64, Vec<u8>, Vec<u8>)> {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+    let body = packets.concat();
+
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    let mut continued = false;
+    for segments in lacing.chunks(255) {
+        let len: usize = segments.iter().map(|&len| len as usize).sum();
+        // Header pages have granule position 0; -1 marks a page where no packet ends
+        let granule = if segments.iter().any(|&len| len < 255) { 0 } else { u64::MAX };
+        let flags = if continued { CONTINUED } else { 0 };
+        pages.push((flags, granule, segments.to_vec(), body[pos..pos + len].to_vec()));
+        pos += len;
+        continued = segments.last() == Some(&255);
+    }
+    pages
+}
+
+fn write_page(output: &mut Vec<u8>, flags: u8, granule: u64, serial: u32, sequence: u32, lacing: &[u8], body: &[u8]) {
+    let start = output.len();
+    output.extend_from_slice(b"OggS\0");
+    output.push(flags);
+    output.extend_from_slice(&granule.to_le_bytes());
+    output.extend_from_slice(&serial.to_le_bytes());
+    output.extend_from_slice(&sequence.to_le_bytes());
+    output.extend_from_slice(&[0; 4]);
+    output.push(lacing.len() as u8);
+    output.extend_from_slice(lacing);
+    output.extend_from_slice(body);
+    let crc = crc32(&output[start..]);
+    output[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Ogg's CRC-32: polynomial 0x04C11DB7, unreflected, zero initial value, over
+/// the page with its checksum field zeroed. Not the zlib CRC crc32fast computes.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = (i as u32) << 24;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    data.iter().fold(0, |crc, &byte| (crc << 8) ^ TABLE[((crc >> 24) as u8 ^ byte) as usize])
+}
+
+fn corrupt_ogg(offset: usize, reason: &str) -> ProcessingError {
+    ProcessingError::CorruptInput {
+        format: "ogg".to_string(),
+        offset: Some(offset as u64),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// OpusHead page, the tags and `padding`, then two audio pages
+    fn opus(fields: &[&str], padding: usize) -> Vec<u8> {
+        let comments = Comments {
+            codec: Codec::Opus,
+            vendor: "libopus 1.4".to_string(),
+            fields: fields.iter().map(|field| field.as_bytes().to_vec()).collect(),
+            extra: vec![0; padding],
+        };
+
+        let mut ident = b"OpusHead\x01\x02".to_vec();
+        ident.extend_from_slice(&312u16.to_le_bytes());
+        ident.extend_from_slice(&44_100u32.to_le_bytes());
+        ident.extend_from_slice(&[0, 0, 0]);
+
+        let mut data = Vec::new();
+        write_page(&mut data, FIRST_PAGE, 0, 7, 0, &[ident.len() as u8], &ident);
+        let tags = paginate(&[comments.to_packet()]);
+        let audio = tags.len() as u32 + 1;
+        for (sequence, (flags, granule, lacing, body)) in tags.into_iter().enumerate() {
+            write_page(&mut data, flags, granule, 7, sequence as u32 + 1, &lacing, &body);
+        }
+        write_page(&mut data, 0, 48_312, 7, audio, &[3], b"\xfc\0\0");
+        write_page(&mut data, 0x04, 96_312, 7, audio + 1, &[3], b"\xfc\0\0");
+        data
+    }
+
+    #[test]
+    fn test_strip_ogg_metadata() {
+        let picture = format!("METADATA_BLOCK_PICTURE={}", "A".repeat(70_000));
+        // Cover art long enough to span pages
+        let data = opus(&["TITLE=Song", "ENCODER=Lavf", "R128_TRACK_GAIN=-512", &picture], 16);
+        assert_eq!(Format::from_bytes(&data), Some(Format::Opus));
+        assert_eq!(metadata_comments(&data).len(), 4);
+        assert_eq!(unsafe_comment_count(&data), 2);
+        assert_eq!(duration(&data), Some(2.0));
+
+        let safe = strip_ogg_metadata(&data, StripMode::Safe).unwrap();
+        let (pages, error) = pages(&safe);
+        assert!(error.is_none());
+        // Ident, one tags page, two audio pages numbered on from the tags
+        let sequences: Vec<u32> = pages.iter().map(|page| page.sequence).collect();
+        assert_eq!(sequences, [0, 1, 2, 3]);
+        for page in &pages {
+            let mut copy = page.raw.to_vec();
+            copy[22..26].fill(0);
+            assert_eq!(crc32(&copy).to_le_bytes(), page.raw[22..26]);
+        }
+        let kept: Vec<String> = metadata_comments(&safe).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(kept, ["TITLE", "R128_TRACK_GAIN"]);
+        assert_eq!(duration(&safe), Some(2.0));
+
+        let all = strip_ogg_metadata(&data, StripMode::All).unwrap();
+        assert!(metadata_comments(&all).is_empty());
+        let report = inspect_ogg(&all).unwrap();
+        assert!(report.fields.iter().any(|field| field.label == "Vendor" && field.value == "libopus 1.4"));
+        assert!(report.errors.is_empty());
+
+        // Nothing to remove leaves the file byte for byte
+        let clean = opus(&["TITLE=Song"], 0);
+        assert_eq!(strip_ogg_metadata(&clean, StripMode::Safe).unwrap(), clean);
+        assert!(strip_ogg_metadata(&data[..data.len() - 1], StripMode::All).is_err());
+    }
+
+    #[test]
+    fn test_strip_interleaved() {
+        let picture = format!("METADATA_BLOCK_PICTURE={}", "A".repeat(70_000));
+        let data = opus(&["TITLE=Song", &picture], 0);
+        // A page of another stream after each Opus page, the first of them its start
+        let mut interleaved = Vec::new();
+        for (sequence, page) in pages(&data).0.iter().enumerate() {
+            interleaved.extend_from_slice(page.raw);
+            let flags = if sequence == 0 { FIRST_PAGE } else { 0 };
+            write_page(&mut interleaved, flags, 0, 9, sequence as u32, &[4], b"data");
+        }
+
+        let stripped = strip_ogg_metadata(&interleaved, StripMode::Safe).unwrap();
+        let (pages, error) = pages(&stripped);
+        assert!(error.is_none());
+        let other: Vec<u32> = pages.iter().filter(|page| page.serial == 9).map(|page| page.sequence).collect();
+        assert_eq!(other, (0..other.len() as u32).collect::<Vec<_>>());
+        // The new tags page takes the place of the first old one; the other stream's pages keep theirs
+        let serials: Vec<u32> = pages.iter().map(|page| page.serial).collect();
+        let mut expected = vec![7, 9, 7];
+        expected.extend(std::iter::repeat_n(9, other.len() - 3));
+        expected.extend([7, 9, 7, 9]);
+        assert_eq!(serials, expected);
+        let kept: Vec<String> = metadata_comments(&stripped).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(kept, ["TITLE"]);
+    }
+
+    #[test]
+    fn test_crc32() {
+        // Reference value from libogg's table
+        assert_eq!(crc32(&[61, 61, 33]), 0x9f85_8776);
+    }
+}
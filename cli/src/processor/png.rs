@@ -1,77 +1,61 @@
-use image::GenericImageView;
+use image::{DynamicImage, GenericImageView, RgbaImage};
 
-use crate::config::{ProcessingConfig, StripMode};
+use crate::config::{PngFilter, PngOptions, ProcessingConfig, StripMode};
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
-use crate::processor::ImageProcessor;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, Field, InspectionReport, Section};
+use crate::limits::{decode_image, Limits};
+use crate::outcome::ProcessOutcome;
+use crate::explain;
+use crate::processor::{quantizes, Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
+use crate::tune::{tune_quality, SsimReference};
 
 pub struct PngProcessor;
 
-/// Display all metadata from a PNG file
-pub fn inspect_png(input: &[u8]) -> Result<(), ProcessingError> {
-    println!("\n═══════════════════════════════════════════════════════");
-    println!("                  PNG Metadata Inspection");
-    println!("═══════════════════════════════════════════════════════\n");
-
-    let file_size = input.len();
-    println!("File size: {} bytes ({:.2} KB)\n", file_size, file_size as f64 / 1024.0);
+/// Inspection report of a PNG file: dimensions, then every chunk with its size
+pub fn inspect_png(input: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Png, input.len());
 
     // Load image to get dimensions and color info
     match image::load_from_memory_with_format(input, image::ImageFormat::Png) {
         Ok(img) => {
             let (width, height) = img.dimensions();
-            let color_type = img.color();
-            println!("Image dimensions: {} x {} pixels", width, height);
-            println!("Color type: {:?}", color_type);
-            println!("Total pixels: {}\n", width * height);
-        }
-        Err(e) => {
-            println!("Could not decode PNG image: {}\n", e);
+            report.field("Image dimensions", format!("{} x {} pixels", width, height));
+            report.field("Color type", format!("{:?}", img.color()));
+            report.field("Total pixels", width as u64 * height as u64);
         }
+        Err(e) => report.errors.push(format!("Could not decode PNG image: {}", e)),
     }
 
-    // Parse PNG chunks
-    println!("PNG Chunks:");
-    println!("───────────────────────────────────────────────────────");
-
     if input.len() < 8 || &input[0..8] != b"\x89PNG\r\n\x1a\n" {
-        println!("  Invalid PNG signature");
-        println!("\n═══════════════════════════════════════════════════════\n");
-        return Ok(());
+        report.errors.push("Invalid PNG signature".to_string());
+        return Ok(report);
     }
 
+    let mut section = Section::new("PNG Chunks");
     let mut pos = 8;
-    let mut chunk_count = 0;
     let mut critical_chunks = 0;
-    let mut ancillary_chunks = 0;
 
     while pos + 8 <= input.len() {
         let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
         let chunk_type = &input[pos + 4..pos + 8];
 
         if let Ok(chunk_name) = std::str::from_utf8(chunk_type) {
-            chunk_count += 1;
-
-            // Check if critical or ancillary
+            // Bit 5 of the first letter: lowercase = ancillary
             let is_critical = chunk_type[0] & 0x20 == 0;
             if is_critical {
                 critical_chunks += 1;
-            } else {
-                ancillary_chunks += 1;
             }
 
-            let chunk_info = get_chunk_info(chunk_name);
-            let criticality = if is_critical { "[CRITICAL]" } else { "[ANCILLARY]" };
-
-            println!("  {} {} - {}", criticality, chunk_name, chunk_info);
-            println!("      Size: {} bytes", length);
-
-            // Display some chunk contents
+            let mut entry = Entry::new(chunk_name);
+            entry.description = Some(get_chunk_info(chunk_name).to_string());
+            entry.class = Some(if is_critical { EntryClass::Critical } else { EntryClass::Ancillary });
+            entry.size = Some(length as u64);
             if pos + 8 + length <= input.len() {
-                display_chunk_content(chunk_name, &input[pos + 8..pos + 8 + length]);
+                entry.fields = chunk_content(chunk_name, &input[pos + 8..pos + 8 + length]);
             }
-
-            println!();
+            section.entries.push(entry);
         }
 
         // Move to next chunk: length (4) + type (4) + data (length) + crc (4)
@@ -82,12 +66,15 @@ pub fn inspect_png(input: &[u8]) -> Result<(), ProcessingError> {
         }
     }
 
-    println!("───────────────────────────────────────────────────────");
-    println!("Summary: {} total chunks ({} critical, {} ancillary)",
-             chunk_count, critical_chunks, ancillary_chunks);
-    println!("\n═══════════════════════════════════════════════════════\n");
-
-    Ok(())
+    let chunk_count = section.entries.len();
+    section.summary = Some(format!(
+        "{} total chunks ({} critical, {} ancillary)",
+        chunk_count,
+        critical_chunks,
+        chunk_count - critical_chunks
+    ));
+    report.sections.push(section);
+    Ok(report)
 }
 
 /// Get human-readable chunk information
@@ -116,18 +103,17 @@ fn get_chunk_info(chunk_type: &str) -> &str {
     }
 }
 
-/// Display relevant chunk content
-fn display_chunk_content(chunk_type: &str, data: &[u8]) {
+/// Decoded contents of the chunks worth showing
+fn chunk_content(chunk_type: &str, data: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
     match chunk_type {
-        "IHDR" => {
-            if data.len() >= 13 {
-                let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-                let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
-                let bit_depth = data[8];
-                let color_type = data[9];
-                println!("      {}x{}, bit depth: {}, color type: {}",
-                         width, height, bit_depth, color_type);
-            }
+        "IHDR" if data.len() >= 13 => {
+            let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+            let bit_depth = data[8];
+            let color_type = data[9];
+            fields.push(Field::new("Header", format!("{}x{}, bit depth: {}, color type: {}",
+                     width, height, bit_depth, color_type)));
         }
         "tEXt" | "zTXt" | "iTXt" => {
             if let Some(null_pos) = data.iter().position(|&b| b == 0) {
@@ -137,68 +123,407 @@ fn display_chunk_content(chunk_type: &str, data: &[u8]) {
                 } else {
                     String::from("<compressed or binary>")
                 };
-                println!("      {}: {}", keyword,
+                fields.push(Field::new(keyword,
                          if value_str.len() > 60 {
                              format!("{}...", &value_str[..60])
                          } else {
                              value_str
-                         });
+                         }));
             }
         }
-        "pHYs" => {
-            if data.len() >= 9 {
-                let x = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-                let y = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
-                let unit = data[8];
-                println!("      {}x{} pixels per {}", x, y,
-                         if unit == 1 { "meter" } else { "unit" });
-            }
+        "pHYs" if data.len() >= 9 => {
+            let x = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            let y = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+            let unit = data[8];
+            fields.push(Field::new("Density", format!("{}x{} pixels per {}", x, y,
+                     if unit == 1 { "meter" } else { "unit" })));
         }
-        "tIME" => {
-            if data.len() >= 7 {
-                let year = u16::from_be_bytes([data[0], data[1]]);
-                let month = data[2];
-                let day = data[3];
-                let hour = data[4];
-                let minute = data[5];
-                let second = data[6];
-                println!("      {}-{:02}-{:02} {:02}:{:02}:{:02}",
-                         year, month, day, hour, minute, second);
-            }
+        "tIME" if data.len() >= 7 => {
+            let year = u16::from_be_bytes([data[0], data[1]]);
+            let month = data[2];
+            let day = data[3];
+            let hour = data[4];
+            let minute = data[5];
+            let second = data[6];
+            fields.push(Field::new("Modified", format!("{}-{:02}-{:02} {:02}:{:02}:{:02}",
+                     year, month, day, hour, minute, second)));
         }
-        "gAMA" => {
-            if data.len() >= 4 {
-                let gamma = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-                println!("      Gamma: {:.5}", gamma as f64 / 100000.0);
-            }
+        "gAMA" if data.len() >= 4 => {
+            let gamma = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            fields.push(Field::new("Gamma", format!("{:.5}", gamma as f64 / 100000.0)));
         }
         _ => {}
     }
+    fields
 }
 
 impl ImageProcessor for PngProcessor {
-    fn supported_formats(&self) -> &[ImageFormat] {
-        &[ImageFormat::Png]
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Png]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: cfg!(feature = "quantize"),
+            ..Capabilities::default()
+        }
     }
 
     fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
-        if config.no_lossy {
-            optimize_lossless(input, config)
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        compress(input, config, progress).map(|(png, _)| png)
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let (data, quality) = compress(input, config, progress)?;
+        let metadata_removed = metadata_chunk_bytes(input).saturating_sub(metadata_chunk_bytes(&data));
+
+        let mut outcome = ProcessOutcome::new(data, Format::Png);
+        outcome.metadata_removed = Some(metadata_removed);
+        outcome.quality = quality;
+        outcome.dimensions = png_dimensions(input);
+        Ok(outcome)
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let png = &config.png;
+        let mut plan = if quantizes(config) {
+            let mut plan = ProcessorPlan::new("quantize to a palette, then optimize losslessly with oxipng");
+            plan.setting("quality", explain::quality(config, Format::Png));
+            plan.setting("max colors", png.max_colors);
+            plan.setting("speed", config.speed);
+            plan
+        } else if config.no_lossy {
+            ProcessorPlan::new("optimize losslessly with oxipng")
         } else {
-            let quantized = quantize_png(input, config)?;
-            optimize_lossless(&quantized, config)
+            ProcessorPlan::new("optimize losslessly with oxipng (built without the quantize feature)")
+        };
+        plan.setting("oxipng preset", png.preset);
+        if let Some(level) = png.deflate_level {
+            plan.setting("deflate level", level);
         }
+        if png.zopfli {
+            plan.setting("zopfli", "yes");
+        }
+        if !png.filters.is_empty() {
+            let filters: Vec<String> = png.filters.iter().map(|f| format!("{:?}", f).to_lowercase()).collect();
+            plan.setting("filters", filters.join(","));
+        }
+        let interlace = match png.interlace {
+            Some(true) => "Adam7",
+            Some(false) => "none",
+            None => "left as is",
+        };
+        plan.setting("interlace", interlace);
+        if png.optimize_alpha {
+            plan.setting("optimize alpha", "yes");
+        }
+        if !png.keep_chunks.is_empty() {
+            let chunks: Vec<String> =
+                png.keep_chunks.iter().map(|chunk| String::from_utf8_lossy(chunk).into_owned()).collect();
+            plan.setting("keep chunks", chunks.join(","));
+        }
+        plan.skip = check_limits(input, config).err().map(|e| e.to_string());
+        plan
+    }
+}
+
+/// Quantize (unless `no_lossy` or built without `quantize`) then optimize
+/// losslessly; the quality is None for lossless runs
+fn compress(
+    input: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    if !quantizes(config) {
+        if !config.no_lossy {
+            log::debug!("quantize not enabled, optimizing PNG losslessly");
+        }
+        Ok((optimize_lossless(input, config, progress)?, None))
+    } else {
+        let (quantized, quality) = quantize(input, config, progress)?;
+        Ok((optimize_lossless(&quantized, config, progress)?, Some(quality)))
+    }
+}
+
+/// [`compress`] for an image decoded from another format: quantized (unless
+/// `no_lossy`) or encoded as is, then optimized losslessly
+pub(crate) fn compress_image(
+    img: &DynamicImage,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    let (png, quality) = if !quantizes(config) {
+        progress.start(Stage::Encode);
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .map_err(|e| ProcessingError::Encode(format!("Failed to encode PNG: {}", e)))?;
+        progress.finish(Stage::Encode);
+        (png, None)
+    } else {
+        let (png, quality) = quantize_image(img, config, progress)?;
+        (png, Some(quality))
+    };
+    Ok((optimize_lossless(&png, config, progress)?, quality))
+}
+
+/// Width and height from the IHDR chunk
+fn png_dimensions(input: &[u8]) -> Option<(u32, u32)> {
+    if input.len() < 24 || &input[12..16] != b"IHDR" {
+        return None;
     }
+    let width = u32::from_be_bytes([input[16], input[17], input[18], input[19]]);
+    let height = u32::from_be_bytes([input[20], input[21], input[22], input[23]]);
+    Some((width, height))
+}
+
+/// Total size of ancillary chunks other than tRNS (which carries pixel data, not metadata)
+fn metadata_chunk_bytes(input: &[u8]) -> u64 {
+    metadata_chunks(input).iter().map(|(_, size)| size).sum()
+}
+
+/// Type and total size (header and CRC included) of each metadata chunk, in file order
+pub(crate) fn metadata_chunks(input: &[u8]) -> Vec<([u8; 4], u64)> {
+    let mut pos = 8;
+    let mut chunks = Vec::new();
+
+    while pos + 8 <= input.len() {
+        let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
+        let chunk_type = [input[pos + 4], input[pos + 5], input[pos + 6], input[pos + 7]];
+        let is_ancillary = chunk_type[0] & 0x20 != 0;
+
+        if is_ancillary && &chunk_type != b"tRNS" {
+            chunks.push((chunk_type, 12 + length as u64));
+        }
+        pos += 12 + length;
+    }
+
+    chunks
+}
+
+/// Data of the first chunk of `kind`
+pub(crate) fn find_chunk<'a>(input: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut pos = 8;
+    while pos + 8 <= input.len() {
+        let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
+        if &input[pos + 4..pos + 8] == kind {
+            return input.get(pos + 8..pos + 8 + length);
+        }
+        pos += 12 + length;
+    }
+    None
+}
+
+/// `input` with every chunk of `kind` removed and one holding `data` inserted
+/// before IEND. Not a PNG or no IEND: returned unchanged.
+pub(crate) fn replace_chunk(input: &[u8], kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + data.len() + 12);
+    output.extend_from_slice(&input[..8.min(input.len())]);
+    let mut pos = 8;
+    while pos + 8 <= input.len() {
+        let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
+        let end = (pos + 12 + length).min(input.len());
+        let chunk_type = &input[pos + 4..pos + 8];
+        if chunk_type == b"IEND" {
+            write_chunk(&mut output, kind, data);
+            output.extend_from_slice(&input[pos..]);
+            return output;
+        }
+        if chunk_type != kind {
+            output.extend_from_slice(&input[pos..end]);
+        }
+        pos = end;
+    }
+    input.to_vec()
+}
+
+/// Append a chunk: length, type, data and the CRC of type and data
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    let crc = crc32fast::hash(&output[start..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Keyword and text of each tEXt, iTXt and zTXt chunk, in file order. Compressed
+/// text is not inflated and reads `<compressed>`.
+pub fn text_chunks(input: &[u8]) -> Vec<(String, String)> {
+    let mut texts = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= input.len() {
+        let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
+        let Some(data) = input.get(pos + 8..pos + 8 + length) else { break };
+        if let Some(text) = text_chunk(&input[pos + 4..pos + 8], data) {
+            texts.push(text);
+        }
+        pos += 12 + length;
+    }
+    texts
+}
+
+fn text_chunk(kind: &[u8], data: &[u8]) -> Option<(String, String)> {
+    let null = data.iter().position(|&b| b == 0)?;
+    let keyword = latin1(&data[..null]);
+    let rest = &data[null + 1..];
+    let text = match kind {
+        b"tEXt" => latin1(rest),
+        b"zTXt" => "<compressed>".to_string(),
+        // Compression flag and method, then language tag and translated keyword
+        b"iTXt" if rest.first() == Some(&0) => {
+            let mut fields = rest.get(2..)?.splitn(3, |&b| b == 0);
+            let text = fields.nth(2)?;
+            String::from_utf8_lossy(text).into_owned()
+        }
+        b"iTXt" => "<compressed>".to_string(),
+        _ => return None,
+    };
+    Some((keyword, text))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
 }
 
-/// Decode PNG → quantize colors → encode as indexed palette PNG
-fn quantize_png(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+/// `input` with the text chunks of the keywords in `delete` and `set` removed
+/// (keywords compare case-insensitively) and one chunk per `set` entry inserted
+/// before IEND: tEXt when the text is Latin-1, uncompressed iTXt otherwise
+pub fn with_text_chunks(input: &[u8], set: &[(String, String)], delete: &[String]) -> Result<Vec<u8>, ProcessingError> {
+    for (keyword, _) in set {
+        if keyword.is_empty() || keyword.chars().count() > 79 || keyword.chars().any(|c| c == '\0' || c as u32 > 0xFF) {
+            return Err(ProcessingError::InvalidConfig(format!(
+                "PNG keyword '{}' must be 1-79 Latin-1 characters without NUL",
+                keyword
+            )));
+        }
+    }
+    if !input.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(ProcessingError::CorruptInput {
+            format: "png".to_string(),
+            offset: Some(0),
+            reason: "invalid PNG signature".to_string(),
+        });
+    }
+    let replaced =
+        |keyword: &str| set.iter().map(|(key, _)| key).chain(delete).any(|key| key.eq_ignore_ascii_case(keyword));
+
+    let mut output = Vec::with_capacity(input.len());
+    output.extend_from_slice(&input[..8]);
+    let mut pos = 8;
+    while pos + 8 <= input.len() {
+        let length = u32::from_be_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]) as usize;
+        let end = (pos + 12 + length).min(input.len());
+        let kind = &input[pos + 4..pos + 8];
+        if kind == b"IEND" {
+            for (keyword, text) in set {
+                let mut data: Vec<u8> = keyword.chars().map(|c| c as u8).collect();
+                data.push(0);
+                if text.chars().all(|c| c as u32 <= 0xFF) {
+                    data.extend(text.chars().map(|c| c as u8));
+                    write_chunk(&mut output, b"tEXt", &data);
+                } else {
+                    // Uncompressed, no language tag or translated keyword
+                    data.extend_from_slice(&[0, 0, 0, 0]);
+                    data.extend_from_slice(text.as_bytes());
+                    write_chunk(&mut output, b"iTXt", &data);
+                }
+            }
+            output.extend_from_slice(&input[pos..]);
+            return Ok(output);
+        }
+        let data = input.get(pos + 8..pos + 8 + length).unwrap_or_default();
+        if !text_chunk(kind, data).is_some_and(|(keyword, _)| replaced(&keyword)) {
+            output.extend_from_slice(&input[pos..end]);
+        }
+        pos = end;
+    }
+    Err(ProcessingError::CorruptInput {
+        format: "png".to_string(),
+        offset: Some(pos as u64),
+        reason: "no IEND chunk".to_string(),
+    })
+}
+
+/// Number of PLTE entries if the image is already indexed (IHDR color type 3)
+fn indexed_palette_len(input: &[u8]) -> Option<usize> {
+    if input.len() < 33 || &input[12..16] != b"IHDR" || input[25] != 3 {
+        return None;
+    }
+    find_chunk(input, b"PLTE").map(|palette| palette.len() / 3)
+}
+
+/// Decode PNG → quantize colors → encode as indexed palette PNG.
+/// An indexed PNG whose palette already fits `max_colors` is returned as is:
+/// re-quantizing it costs time and can only lose colors or grow the file.
+pub(crate) fn quantize_png(
+    input: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    quantize(input, config, progress).map(|(png, _)| png)
+}
+
+/// [`quantize_png`], also returning the quality used: the configured one, or the
+/// one found for `config.target_ssim`
+fn quantize(input: &[u8], config: &ProcessingConfig, progress: &Progress) -> Result<(Vec<u8>, u8), ProcessingError> {
+    let quality = config.quality_for(Format::Png);
+    if let Some(colors) = indexed_palette_len(input) {
+        if colors <= config.png.max_colors as usize {
+            log::debug!("PNG is already indexed with {} colors, skipping quantization", colors);
+            return Ok((input.to_vec(), quality));
+        }
+    }
+
     // Step 1: Decode to RGBA pixels
-    let img = image::load_from_memory_with_format(input, image::ImageFormat::Png)
-        .map_err(|e| ProcessingError::Decode(e.to_string()))?;
+    progress.start(Stage::Decode);
+    let img = decode_image(input, Some(image::ImageFormat::Png), &config.limits)?;
+    progress.finish(Stage::Decode);
 
-    let (width, height) = img.dimensions();
+    quantize_image(&img, config, progress)
+}
+
+/// [`quantize`] for an already decoded image
+fn quantize_image(
+    img: &DynamicImage,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, u8), ProcessingError> {
+    let quality = config.quality_for(Format::Png);
     let rgba = img.to_rgba8();
+    match config.target_ssim {
+        Some(target) => tune_quality(&SsimReference::new(img), target, |quality| {
+            let png = quantize_rgba(&rgba, quality, config, progress)?;
+            let decoded = decode_image(&png, Some(image::ImageFormat::Png), &Limits::unlimited())?;
+            Ok((png, decoded))
+        }),
+        None => Ok((quantize_rgba(&rgba, quality, config, progress)?, quality)),
+    }
+}
+
+/// Quantize RGBA pixels to at most `config.png.max_colors` at `quality` and
+/// encode them as an indexed PNG
+#[cfg(feature = "quantize")]
+fn quantize_rgba(
+    rgba: &RgbaImage,
+    quality: u8,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    let (width, height) = rgba.dimensions();
     let raw_pixels = rgba.as_raw();
 
     // Convert &[u8] to &[imagequant::RGBA]
@@ -210,8 +535,11 @@ fn quantize_png(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, Proc
     };
 
     // Step 2: Quantize with imagequant
+    progress.start(Stage::Quantize);
     let mut attr = imagequant::new();
-    attr.set_quality(0, config.quality)
+    attr.set_quality(0, quality)
+        .map_err(|e| ProcessingError::Quantize(e.to_string()))?;
+    attr.set_max_colors(config.png.max_colors)
         .map_err(|e| ProcessingError::Quantize(e.to_string()))?;
     attr.set_speed(config.speed)
         .map_err(|e| ProcessingError::Quantize(e.to_string()))?;
@@ -228,7 +556,10 @@ fn quantize_png(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, Proc
         .remapped(&mut image)
         .map_err(|e| ProcessingError::Quantize(e.to_string()))?;
 
+    progress.finish(Stage::Quantize);
+
     // Step 3: Encode as indexed PNG with lodepng
+    progress.start(Stage::Encode);
     let lodepng_palette: Vec<lodepng::RGBA> = palette
         .iter()
         .map(|c| lodepng::RGBA {
@@ -259,20 +590,255 @@ fn quantize_png(input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, Proc
     let png_data = encoder
         .encode(&indices, width as usize, height as usize)
         .map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    progress.finish(Stage::Encode);
 
     Ok(png_data)
 }
 
-/// Lossless DEFLATE re-compression + metadata stripping via oxipng
-fn optimize_lossless(png_data: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
-    let mut opts = oxipng::Options::from_preset(4);
+/// Without imagequant only the `quantize` chain step gets here; compression goes lossless
+#[cfg(not(feature = "quantize"))]
+fn quantize_rgba(
+    _rgba: &RgbaImage,
+    _quality: u8,
+    _config: &ProcessingConfig,
+    _progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::Quantize("built without the quantize feature".to_string()))
+}
+
+/// Check the IHDR dimensions against `config.limits` before anything decodes the image
+fn check_limits(png_data: &[u8], config: &ProcessingConfig) -> Result<(), ProcessingError> {
+    match png_dimensions(png_data) {
+        Some((width, height)) => config.limits.check_dimensions(width, height),
+        None => Ok(()),
+    }
+}
 
-    opts.strip = match config.strip {
-        StripMode::All => oxipng::StripChunks::All,
-        StripMode::Safe => oxipng::StripChunks::Safe,
-        StripMode::None => oxipng::StripChunks::None,
+/// Lossless DEFLATE re-compression + metadata stripping via oxipng, tuned by `config.png`
+pub(crate) fn optimize_lossless(
+    png_data: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    // oxipng decodes the full image too
+    check_limits(png_data, config)?;
+
+    progress.start(Stage::Optimize);
+    let opts = oxipng_options(&config.png, config.strip);
+    let optimized = oxipng::optimize_from_memory(png_data, &opts)
+        .map_err(|e| ProcessingError::Optimize(e.to_string()))?;
+    progress.finish(Stage::Optimize);
+
+    Ok(optimized)
+}
+
+/// Drop metadata chunks and copy every other chunk byte for byte, IDAT included.
+/// Critical chunks, tRNS and APNG frames always stay; Safe mode also keeps
+/// [`DISPLAY_CHUNKS`], and `keep_chunks` stay in either mode.
+pub(crate) fn strip_png_metadata(
+    png_data: &[u8],
+    strip_mode: StripMode,
+    keep_chunks: &[[u8; 4]],
+) -> Result<Vec<u8>, ProcessingError> {
+    if !png_data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(ProcessingError::CorruptInput {
+            format: "png".to_string(),
+            offset: Some(0),
+            reason: "invalid PNG signature".to_string(),
+        });
+    }
+    if strip_mode == StripMode::None {
+        return Ok(png_data.to_vec());
+    }
+    let kept = |kind: [u8; 4]| {
+        kind[0].is_ascii_uppercase()
+            || matches!(&kind, b"tRNS" | b"acTL" | b"fcTL" | b"fdAT")
+            || (strip_mode == StripMode::Safe && DISPLAY_CHUNKS.contains(&kind))
+            || keep_chunks.contains(&kind)
     };
 
-    oxipng::optimize_from_memory(png_data, &opts)
-        .map_err(|e| ProcessingError::Optimize(e.to_string()))
+    let mut output = Vec::with_capacity(png_data.len());
+    output.extend_from_slice(&png_data[..8]);
+    let mut pos = 8;
+    while pos + 8 <= png_data.len() {
+        let length = u32::from_be_bytes([png_data[pos], png_data[pos + 1], png_data[pos + 2], png_data[pos + 3]]);
+        let end = pos.saturating_add(12).saturating_add(length as usize);
+        if end > png_data.len() {
+            return Err(ProcessingError::CorruptInput {
+                format: "png".to_string(),
+                offset: Some(pos as u64),
+                reason: "chunk runs past the end of the file".to_string(),
+            });
+        }
+        let kind = [png_data[pos + 4], png_data[pos + 5], png_data[pos + 6], png_data[pos + 7]];
+        if kept(kind) {
+            output.extend_from_slice(&png_data[pos..end]);
+        }
+        pos = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+fn oxipng_options(png: &PngOptions, strip_mode: StripMode) -> oxipng::Options {
+    let mut opts = oxipng::Options::from_preset(png.preset.min(6));
+
+    if png.zopfli {
+        opts.deflater = oxipng::Deflater::Zopfli(oxipng::ZopfliOptions::default());
+    } else if let Some(level) = png.deflate_level {
+        opts.deflater = oxipng::Deflater::Libdeflater { compression: level.min(12) };
+    }
+
+    if !png.filters.is_empty() {
+        opts.filters = png.filters.iter().map(|&f| filter_strategy(f)).collect();
+    }
+
+    opts.interlace = png.interlace;
+    opts.optimize_alpha = png.optimize_alpha;
+    opts.strip = strip_chunks(strip_mode, &png.keep_chunks);
+    opts
+}
+
+fn filter_strategy(filter: PngFilter) -> oxipng::FilterStrategy {
+    match filter {
+        PngFilter::None => oxipng::FilterStrategy::NONE,
+        PngFilter::Sub => oxipng::FilterStrategy::SUB,
+        PngFilter::Up => oxipng::FilterStrategy::UP,
+        PngFilter::Average => oxipng::FilterStrategy::AVERAGE,
+        PngFilter::Paeth => oxipng::FilterStrategy::PAETH,
+        PngFilter::MinSum => oxipng::FilterStrategy::MinSum,
+        PngFilter::Entropy => oxipng::FilterStrategy::Entropy,
+        PngFilter::Bigrams => oxipng::FilterStrategy::Bigrams,
+        PngFilter::BigEnt => oxipng::FilterStrategy::BigEnt,
+        PngFilter::Brute => oxipng::FilterStrategy::Brute { num_lines: 4, level: 8 },
+    }
+}
+
+/// Chunks that affect display, kept by `StripMode::Safe` (same list as oxipng's Safe)
+const DISPLAY_CHUNKS: [[u8; 4]; 7] = [*b"cICP", *b"iCCP", *b"sRGB", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT"];
+
+fn strip_chunks(strip_mode: StripMode, keep: &[[u8; 4]]) -> oxipng::StripChunks {
+    match strip_mode {
+        StripMode::None => oxipng::StripChunks::None,
+        StripMode::All if keep.is_empty() => oxipng::StripChunks::All,
+        StripMode::Safe if keep.is_empty() => oxipng::StripChunks::Safe,
+        StripMode::All => oxipng::StripChunks::Keep(keep.iter().copied().collect()),
+        StripMode::Safe => {
+            oxipng::StripChunks::Keep(DISPLAY_CHUNKS.iter().chain(keep).copied().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x8 indexed PNG with a four-color palette
+    #[cfg(feature = "quantize")]
+    fn indexed_png() -> Vec<u8> {
+        let palette =
+            [[255, 0, 0], [0, 255, 0], [0, 0, 255], [0, 0, 0]].map(|[r, g, b]| lodepng::RGBA { r, g, b, a: 255 });
+        let mut encoder = lodepng::Encoder::new();
+        encoder.set_auto_convert(false);
+        encoder.set_palette(&palette).unwrap();
+        let raw = encoder.info_raw_mut();
+        raw.set_colortype(lodepng::ColorType::PALETTE);
+        raw.set_bitdepth(8);
+        raw.palette_clear();
+        for &color in &palette {
+            raw.palette_add(color).unwrap();
+        }
+        let indices: Vec<u8> = (0..64).map(|i| (i % 4) as u8).collect();
+        encoder.encode(&indices, 8, 8).unwrap()
+    }
+
+    #[test]
+    fn test_with_text_chunks() {
+        let mut png = Vec::new();
+        image::GrayImage::new(2, 2).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let set = vec![("Title".to_string(), "Dawn".to_string()), ("Comment".to_string(), "\u{2713} done".to_string())];
+        let tagged = with_text_chunks(&png, &set, &[]).unwrap();
+        assert_eq!(text_chunks(&tagged), set);
+        assert!(tagged.windows(4).any(|kind| kind == b"iTXt"));
+
+        let untitled = with_text_chunks(&tagged, &[], &["title".to_string()]).unwrap();
+        assert_eq!(text_chunks(&untitled), set[1..]);
+        assert!(image::load_from_memory(&untitled).is_ok());
+        assert!(with_text_chunks(&png, &[(String::new(), "x".to_string())], &[]).is_err());
+    }
+
+    #[test]
+    fn test_oxipng_options() {
+        let defaults = oxipng_options(&PngOptions::default(), StripMode::Safe);
+        assert_eq!(defaults.deflater, oxipng::Options::from_preset(4).deflater);
+        assert_eq!(defaults.interlace, Some(false));
+
+        let png = PngOptions {
+            preset: 9,
+            deflate_level: Some(12),
+            filters: vec![PngFilter::Paeth, PngFilter::Brute],
+            ..PngOptions::default()
+        };
+        let opts = oxipng_options(&png, StripMode::None);
+        assert_eq!(opts.deflater, oxipng::Deflater::Libdeflater { compression: 12 });
+        assert_eq!(opts.filters.len(), 2);
+        assert!(opts.filters.contains(&oxipng::FilterStrategy::PAETH));
+        assert!(matches!(opts.strip, oxipng::StripChunks::None));
+
+        // Zopfli wins over a deflate level
+        let zopfli = PngOptions { zopfli: true, ..png };
+        assert!(matches!(oxipng_options(&zopfli, StripMode::None).deflater, oxipng::Deflater::Zopfli(_)));
+    }
+
+    #[test]
+    fn test_strip_chunks_keeps_listed() {
+        assert!(matches!(strip_chunks(StripMode::All, &[]), oxipng::StripChunks::All));
+        let oxipng::StripChunks::Keep(kept) = strip_chunks(StripMode::All, &[*b"tEXt"]) else {
+            panic!("expected a keep list")
+        };
+        assert_eq!(kept.into_iter().collect::<Vec<_>>(), [*b"tEXt"]);
+        let oxipng::StripChunks::Keep(kept) = strip_chunks(StripMode::Safe, &[*b"tEXt"]) else {
+            panic!("expected a keep list")
+        };
+        assert!(kept.contains(b"tEXt") && kept.contains(b"iCCP"));
+    }
+
+    #[test]
+    fn test_optimize_lossless_honors_png_options() {
+        let mut png = Vec::new();
+        image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([(x * 16) as u8, (y * 16) as u8, 0]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let tagged = with_text_chunks(&png, &[("Title".to_string(), "Dawn".to_string())], &[]).unwrap();
+
+        let mut config = ProcessingConfig { strip: StripMode::All, ..ProcessingConfig::default() };
+        let stripped = optimize_lossless(&tagged, &config, &Progress::none()).unwrap();
+        assert!(text_chunks(&stripped).is_empty());
+
+        config.png.keep_chunks = vec![*b"tEXt"];
+        config.png.interlace = Some(true);
+        let kept = optimize_lossless(&tagged, &config, &Progress::none()).unwrap();
+        assert_eq!(text_chunks(&kept), [("Title".to_string(), "Dawn".to_string())]);
+        // IHDR interlace method byte
+        assert_eq!(kept[28], 1);
+        assert_eq!(image::load_from_memory(&kept).unwrap().to_rgb8(), image::load_from_memory(&png).unwrap().to_rgb8());
+    }
+
+    #[test]
+    #[cfg(feature = "quantize")]
+    fn test_indexed_png_skips_quantization() {
+        let png = indexed_png();
+        assert_eq!(indexed_palette_len(&png), Some(4));
+
+        let config = ProcessingConfig::default();
+        assert_eq!(quantize_png(&png, &config, &Progress::none()).unwrap(), png);
+
+        // A palette larger than the target still gets reduced
+        let mut config = ProcessingConfig::default();
+        config.png.max_colors = 2;
+        let reduced = quantize_png(&png, &config, &Progress::none()).unwrap();
+        assert!(indexed_palette_len(&reduced).unwrap() <= 2);
+    }
 }
@@ -0,0 +1,684 @@
+use std::borrow::Cow;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::config::{ProcessingConfig, SilenceTrim, StripMode, WavOptions};
+use crate::error::ProcessingError;
+use crate::explain;
+#[cfg(feature = "ffmpeg")]
+use crate::ffmpeg::{self, read_ffmpeg_result, FfmpegTempFiles};
+use crate::format::Format;
+use crate::outcome::ProcessOutcome;
+use crate::processor::audio::{wav_format, PcmFormat};
+#[cfg(feature = "ffmpeg")]
+use crate::processor::audio::pcm_codec;
+use crate::processor::{is_ffmpeg_available, process_buffered, Capabilities, ImageProcessor, ProcessorPlan, Spool};
+use crate::progress::{Progress, Stage};
+
+pub struct WavProcessor;
+
+/// INFO fields kept by `StripMode::Safe`: title, artist, album, date, genre, track
+const SAFE_INFO_IDS: [&[u8; 4]; 6] = [b"INAM", b"IART", b"IPRD", b"ICRD", b"IGNR", b"ITRK"];
+
+/// Broadcast WAV chunks kept with `WavOptions::broadcast`: EBU Tech 3285
+/// origination data and the production metadata field recorders write
+const BROADCAST_CHUNK_IDS: [&[u8; 4]; 2] = [b"bext", b"iXML"];
+
+impl ImageProcessor for WavProcessor {
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Wav]
+    }
+
+    /// Lossy only when downsampling or reducing bit depth was asked for
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: cfg!(feature = "ffmpeg"),
+            ..Capabilities::default()
+        }
+    }
+
+    fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_progress(input, config, &Progress::none())
+    }
+
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        self.process_with_stats(input, config, progress).map(|outcome| outcome.data.into_owned())
+    }
+
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let pcm = wav_format(input).ok_or_else(|| corrupt_wav("no fmt chunk"))?;
+
+        let mut warnings = Vec::new();
+        let (sample_rate, bit_depth) = conversion(&pcm, config, &mut warnings);
+        let converts = sample_rate.is_some() || bit_depth.is_some();
+
+        let mut data = Cow::Borrowed(input);
+        if config.strip != StripMode::None {
+            progress.start(Stage::Strip);
+            data = Cow::Owned(strip_wav_metadata(input, config.strip, config.wav.broadcast)?);
+            progress.finish(Stage::Strip);
+        }
+        let metadata_removed = (input.len() as u64).saturating_sub(data.len() as u64);
+
+        if let Some(trim) = &config.trim_silence {
+            if !pcm.pcm {
+                warnings.push("silence trimming needs PCM samples; left untrimmed".to_string());
+            } else if let Some(trimmed) = trim_wav_silence(&data, &pcm, trim)? {
+                data = Cow::Owned(trimmed);
+            }
+        }
+
+        let mut output_format = pcm;
+        if converts {
+            if is_ffmpeg_available() {
+                let encoded = convert_pcm(&data, &pcm, sample_rate, bit_depth, progress)?;
+                output_format = wav_format(&encoded).unwrap_or(pcm);
+                data = Cow::Owned(replace_audio(&data, &encoded)?);
+            } else {
+                log::warn!("ffmpeg not found - WAV resampling requires ffmpeg to be installed");
+                warnings.push("ffmpeg not found, sample rate and bit depth left as is".to_string());
+            }
+        }
+
+        let mut outcome = match data {
+            Cow::Borrowed(_) => ProcessOutcome::unchanged(input, Format::Wav),
+            Cow::Owned(data) => ProcessOutcome::new(data, Format::Wav),
+        };
+        outcome.metadata_removed = Some(metadata_removed);
+        outcome.bitrate = Some(pcm_bitrate(&output_format));
+        outcome.warnings = warnings;
+        Ok(outcome)
+    }
+
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let (sample_rate, bit_depth) = match wav_format(input) {
+            Some(pcm) => conversion(&pcm, config, &mut Vec::new()),
+            None => (None, None),
+        };
+        let mut plan = if sample_rate.is_some() || bit_depth.is_some() {
+            let mut plan = ProcessorPlan::new("strip metadata chunks, then convert the samples");
+            plan.ffmpeg.push("resampling");
+            plan
+        } else {
+            ProcessorPlan::new("strip metadata chunks; samples copied untouched")
+        };
+        if let Some(rate) = sample_rate {
+            plan.setting("sample rate", format!("{} Hz", rate));
+        }
+        if let Some(bits) = bit_depth {
+            plan.setting("bit depth", format!("{}-bit", bits));
+        }
+        if config.wav.broadcast {
+            plan.setting("bext/iXML", "kept");
+        }
+        if let Some(trim) = &config.trim_silence {
+            plan.setting("trim silence", explain::silence(trim));
+        }
+        plan
+    }
+
+    /// Stripping filters chunk by chunk; resampling and silence trimming need the
+    /// whole file.
+    fn supports_streaming(&self, config: &ProcessingConfig) -> bool {
+        let converts = !config.no_lossy && (config.wav.sample_rate.is_some() || config.wav.bit_depth.is_some());
+        !converts && config.trim_silence.is_none()
+    }
+
+    fn process_stream(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        config: &ProcessingConfig,
+    ) -> Result<(), ProcessingError> {
+        if self.supports_streaming(config) {
+            strip_wav_stream(input, output, config.strip, config.wav.broadcast)
+        } else {
+            process_buffered(self, input, output, config)
+        }
+    }
+}
+
+/// [`delivery_plan`], unless `no_lossy` keeps the samples as they are
+fn conversion(pcm: &PcmFormat, config: &ProcessingConfig, warnings: &mut Vec<String>) -> (Option<u32>, Option<u16>) {
+    if config.no_lossy {
+        (None, None)
+    } else {
+        delivery_plan(pcm, &config.wav, warnings)
+    }
+}
+
+/// Target sample rate and bit depth, each only when it is a reduction; WAV is
+/// never upsampled or padded to more bits
+fn delivery_plan(
+    pcm: &PcmFormat,
+    options: &WavOptions,
+    warnings: &mut Vec<String>,
+) -> (Option<u32>, Option<u16>) {
+    let sample_rate = options.sample_rate.filter(|&rate| {
+        let lower = rate < pcm.sample_rate;
+        if !lower {
+            warnings.push(format!("already at {} Hz, not resampled", pcm.sample_rate));
+        }
+        lower
+    });
+    let bit_depth = options.bit_depth.filter(|&bits| {
+        let lower = pcm.float || bits < pcm.bits_per_sample;
+        if !lower {
+            warnings.push(format!("already {}-bit, bit depth left as is", pcm.bits_per_sample));
+        }
+        lower
+    });
+    (sample_rate, bit_depth)
+}
+
+/// Resample and/or requantize with ffmpeg, adding triangular (TPDF) dither when
+/// the bit depth drops. The result carries no metadata chunks.
+#[cfg(feature = "ffmpeg")]
+fn convert_pcm(
+    input: &[u8],
+    pcm: &PcmFormat,
+    sample_rate: Option<u32>,
+    bit_depth: Option<u16>,
+    progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    let mut filter = Vec::new();
+    if let Some(rate) = sample_rate {
+        log::debug!("Downsampling WAV: {} Hz -> {} Hz", pcm.sample_rate, rate);
+        filter.push(format!("osr={}", rate));
+    }
+    let codec = match bit_depth {
+        Some(bits) => {
+            log::debug!("Reducing WAV bit depth: {} -> {} bits", pcm.bits_per_sample, bits);
+            let sample_format = match bits {
+                0..=8 => "u8",
+                9..=16 => "s16",
+                _ => "s32",
+            };
+            filter.push(format!("osf={}", sample_format));
+            filter.push(format!("output_sample_bits={}", bits));
+            filter.push("dither_method=triangular".to_string());
+            pcm_codec(bits)
+        }
+        None if pcm.float && pcm.bits_per_sample == 64 => "pcm_f64le",
+        None if pcm.float => "pcm_f32le",
+        None => pcm_codec(pcm.bits_per_sample),
+    };
+
+    let temp = FfmpegTempFiles::with_extensions("wav", "wav");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    progress.start(Stage::Ffmpeg);
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0:a:0"]);
+    cmd.arg("-af").arg(format!("aresample={}", filter.join(":")));
+    cmd.args(["-c:a", codec]);
+    // Metadata chunks come from the stripped input instead
+    cmd.args(["-map_metadata", "-1", "-fflags", "+bitexact"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    let result = read_ffmpeg_result(output, &temp, input.len())?;
+    progress.finish(Stage::Ffmpeg);
+    Ok(result)
+}
+
+/// Never called: `is_ffmpeg_available` is false without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+fn convert_pcm(
+    _input: &[u8],
+    _pcm: &PcmFormat,
+    _sample_rate: Option<u32>,
+    _bit_depth: Option<u16>,
+    _progress: &Progress,
+) -> Result<Vec<u8>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+/// Cut leading/trailing silence from the `data` chunk on whole sample frames.
+/// Returns None when there is nothing to cut or the file is entirely silent.
+fn trim_wav_silence(input: &[u8], pcm: &PcmFormat, trim: &SilenceTrim) -> Result<Option<Vec<u8>>, ProcessingError> {
+    let sample_len = usize::from(pcm.bits_per_sample).div_ceil(8);
+    let frame_len = sample_len * usize::from(pcm.channels);
+    let Some(amplitude) = sample_reader(pcm) else {
+        return Ok(None);
+    };
+    if frame_len == 0 {
+        return Ok(None);
+    }
+
+    let mut chunks = riff_chunks(input)?;
+    let Some(data) = chunks.iter().position(|chunk| &chunk.id == b"data") else {
+        return Err(corrupt_wav("no data chunk"));
+    };
+    let body = &chunks[data].body;
+    let threshold = 10f64.powf(f64::from(trim.threshold_db) / 20.0);
+    let loud = |frame: &[u8]| frame.chunks_exact(sample_len).any(|s| amplitude(s).abs() > threshold);
+
+    let frames: Vec<&[u8]> = body.chunks_exact(frame_len).collect();
+    let Some(first_loud) = frames.iter().position(|frame| loud(frame)) else {
+        log::debug!("WAV is entirely silent, leaving it untrimmed");
+        return Ok(None);
+    };
+    let last_loud = frames.iter().rposition(|frame| loud(frame)).unwrap_or(first_loud);
+
+    let min_frames = (trim.min_duration.as_secs_f64() * f64::from(pcm.sample_rate)) as usize;
+    let start = if first_loud >= min_frames { first_loud } else { 0 };
+    let end = if frames.len() - 1 - last_loud >= min_frames { last_loud + 1 } else { frames.len() };
+    if start == 0 && end == frames.len() {
+        return Ok(None);
+    }
+
+    log::info!(
+        "Trimmed {:.2} s of leading and {:.2} s of trailing silence",
+        start as f64 / f64::from(pcm.sample_rate),
+        (frames.len() - end) as f64 / f64::from(pcm.sample_rate)
+    );
+    let trimmed = body[start * frame_len..end * frame_len].to_vec();
+    chunks[data].body = Cow::Owned(trimmed);
+    // `fact` holds the sample count for non-integer formats
+    if let Some(fact) = chunks.iter_mut().find(|chunk| &chunk.id == b"fact" && chunk.body.len() >= 4) {
+        let mut body = fact.body.to_vec();
+        body[0..4].copy_from_slice(&((end - start) as u32).to_le_bytes());
+        fact.body = Cow::Owned(body);
+    }
+    Ok(Some(write_wav(&chunks)))
+}
+
+/// Peak amplitude (0.0–1.0) over all channels of each of `columns` equal slices
+/// of the `data` chunk. None when the samples are not plain PCM.
+pub(crate) fn wav_peaks(input: &[u8], columns: usize) -> Result<Option<Vec<f32>>, ProcessingError> {
+    let pcm = wav_format(input).ok_or_else(|| corrupt_wav("no fmt chunk"))?;
+    let sample_len = usize::from(pcm.bits_per_sample).div_ceil(8);
+    let frame_len = sample_len * usize::from(pcm.channels);
+    let (Some(amplitude), true) = (sample_reader(&pcm), pcm.pcm && frame_len > 0) else {
+        return Ok(None);
+    };
+
+    let chunks = riff_chunks(input)?;
+    let data = chunks.iter().find(|chunk| &chunk.id == b"data").ok_or_else(|| corrupt_wav("no data chunk"))?;
+    let frames = data.body.len() / frame_len;
+    let mut peaks = vec![0f32; columns];
+    for (i, frame) in data.body.chunks_exact(frame_len).enumerate() {
+        let peak = frame.chunks_exact(sample_len).map(|s| amplitude(s).abs()).fold(0.0, f64::max);
+        let column = &mut peaks[i * columns / frames];
+        *column = column.max(peak.min(1.0) as f32);
+    }
+    Ok(Some(peaks))
+}
+
+/// Decoder from one little-endian sample to -1.0..1.0, for the layouts WAV stores directly
+fn sample_reader(pcm: &PcmFormat) -> Option<fn(&[u8]) -> f64> {
+    let reader: fn(&[u8]) -> f64 = match (pcm.float, usize::from(pcm.bits_per_sample).div_ceil(8)) {
+        (false, 1) => |s| (f64::from(s[0]) - 128.0) / 128.0,
+        (false, 2) => |s| f64::from(i16::from_le_bytes([s[0], s[1]])) / 32_768.0,
+        (false, 3) => |s| f64::from(i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) / 8_388_608.0,
+        (false, 4) => |s| f64::from(i32::from_le_bytes([s[0], s[1], s[2], s[3]])) / 2_147_483_648.0,
+        (true, 4) => |s| f64::from(f32::from_le_bytes([s[0], s[1], s[2], s[3]])),
+        (true, 8) => |s| f64::from_le_bytes(s.try_into().unwrap_or_default()),
+        _ => return None,
+    };
+    Some(reader)
+}
+
+/// Bits per second of uncompressed PCM
+fn pcm_bitrate(pcm: &PcmFormat) -> u64 {
+    u64::from(pcm.sample_rate) * u64::from(pcm.channels) * u64::from(pcm.bits_per_sample)
+}
+
+/// One RIFF chunk; `body` excludes the header and the pad byte
+#[derive(Debug, Clone)]
+struct Chunk<'a> {
+    id: [u8; 4],
+    body: Cow<'a, [u8]>,
+}
+
+/// Split a RIFF/WAVE file into its top-level chunks
+fn riff_chunks(input: &[u8]) -> Result<Vec<Chunk<'_>>, ProcessingError> {
+    if input.len() < 12 || &input[0..4] != b"RIFF" || &input[8..12] != b"WAVE" {
+        return Err(corrupt_wav("not a RIFF/WAVE file"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= input.len() {
+        let id = [input[pos], input[pos + 1], input[pos + 2], input[pos + 3]];
+        let size = u32::from_le_bytes([input[pos + 4], input[pos + 5], input[pos + 6], input[pos + 7]]) as usize;
+        let start = pos + 8;
+        let body = match input.get(start..start + size) {
+            Some(body) => body,
+            // Recorders that were cut off leave a stale data size; keep what is there
+            None if &id == b"data" => &input[start..],
+            None => {
+                log::debug!("Dropping truncated WAV chunk {}", String::from_utf8_lossy(&id));
+                break;
+            }
+        };
+        chunks.push(Chunk { id, body: Cow::Borrowed(body) });
+        pos = start + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+/// Serialize chunks into a RIFF/WAVE file
+fn write_wav(chunks: &[Chunk]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(12 + chunks.iter().map(|c| 9 + c.body.len()).sum::<usize>());
+    output.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+    for chunk in chunks {
+        output.extend_from_slice(&chunk.id);
+        output.extend_from_slice(&(chunk.body.len() as u32).to_le_bytes());
+        output.extend_from_slice(&chunk.body);
+        if chunk.body.len() % 2 == 1 {
+            output.push(0);
+        }
+    }
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    output
+}
+
+/// Drop metadata chunks: `All` keeps only `fmt `, `fact` and `data`; `Safe`
+/// also keeps a `LIST`/`INFO` chunk reduced to the basic fields. `broadcast`
+/// keeps `bext` and `iXML` in both modes.
+pub(crate) fn strip_wav_metadata(
+    input: &[u8],
+    strip_mode: StripMode,
+    broadcast: bool,
+) -> Result<Vec<u8>, ProcessingError> {
+    if strip_mode == StripMode::None {
+        return Ok(input.to_vec());
+    }
+
+    let kept: Vec<Chunk> = riff_chunks(input)?
+        .into_iter()
+        .filter_map(|chunk| kept_chunk(chunk, strip_mode, broadcast))
+        .collect();
+    Ok(write_wav(&kept))
+}
+
+/// `chunk` as [`strip_wav_metadata`] keeps it, None when it is stripped
+fn kept_chunk(chunk: Chunk, strip_mode: StripMode, broadcast: bool) -> Option<Chunk> {
+    match &chunk.id {
+        b"fmt " | b"fact" | b"data" => Some(chunk),
+        id if broadcast && BROADCAST_CHUNK_IDS.contains(&id) => Some(chunk),
+        b"LIST" if strip_mode == StripMode::Safe && chunk.body.starts_with(b"INFO") => {
+            safe_info(&chunk.body).map(|body| Chunk { id: chunk.id, body: Cow::Owned(body) })
+        }
+        id => {
+            log::debug!("Stripping WAV chunk: {}", String::from_utf8_lossy(id));
+            None
+        }
+    }
+}
+
+/// Streaming [`strip_wav_metadata`]: the chunks around `data` are held in memory
+/// and the audio is spooled to a temp file, since the RIFF size written first
+/// depends on the chunks that follow it.
+fn strip_wav_stream(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    strip_mode: StripMode,
+    broadcast: bool,
+) -> Result<(), ProcessingError> {
+    if strip_mode == StripMode::None {
+        io::copy(input, output)?;
+        return Ok(());
+    }
+
+    let mut header = Vec::with_capacity(12);
+    input.take(12).read_to_end(&mut header)?;
+    if header.len() < 12 || &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(corrupt_wav("not a RIFF/WAVE file"));
+    }
+
+    let (mut before, mut after) = (Vec::new(), Vec::new());
+    let mut audio: Option<(Spool, u64)> = None;
+    loop {
+        let mut chunk_header = Vec::with_capacity(8);
+        input.take(8).read_to_end(&mut chunk_header)?;
+        if chunk_header.len() < 8 {
+            break;
+        }
+        let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+        let size = u64::from(u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]));
+
+        if &id == b"data" && audio.is_none() {
+            let mut spool = Spool::new("wav")?;
+            let len = io::copy(&mut input.take(size), &mut spool.file)?;
+            audio = Some((spool, len));
+            // Recorders that were cut off leave a stale data size; keep what is there
+            if len < size {
+                break;
+            }
+            io::copy(&mut input.take(size & 1), &mut io::sink())?;
+            continue;
+        }
+
+        let mut body = Vec::new();
+        input.take(size + (size & 1)).read_to_end(&mut body)?;
+        if (body.len() as u64) < size {
+            log::debug!("Dropping truncated WAV chunk {}", String::from_utf8_lossy(&id));
+            break;
+        }
+        body.truncate(size as usize);
+        if let Some(chunk) = kept_chunk(Chunk { id, body: Cow::Owned(body) }, strip_mode, broadcast) {
+            match audio {
+                Some(_) => after.push(chunk),
+                None => before.push(chunk),
+            }
+        }
+    }
+    if !before.iter().any(|chunk| &chunk.id == b"fmt ") {
+        return Err(corrupt_wav("no fmt chunk"));
+    }
+
+    let chunks_len = |chunks: &[Chunk]| chunks.iter().map(|c| 8 + padded(c.body.len() as u64)).sum::<u64>();
+    let audio_len = audio.as_ref().map_or(0, |(_, len)| 8 + padded(*len));
+    let riff_size = 4 + chunks_len(&before) + audio_len + chunks_len(&after);
+    output.write_all(b"RIFF")?;
+    output.write_all(&(riff_size as u32).to_le_bytes())?;
+    output.write_all(b"WAVE")?;
+    output.write_all(&write_wav(&before)[12..])?;
+    if let Some((mut spool, len)) = audio {
+        output.write_all(b"data")?;
+        output.write_all(&(len as u32).to_le_bytes())?;
+        spool.file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut spool.file, output)?;
+        if len % 2 == 1 {
+            output.write_all(&[0])?;
+        }
+    }
+    output.write_all(&write_wav(&after)[12..])?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Chunk body length with the pad byte to an even length
+fn padded(len: u64) -> u64 {
+    len + (len & 1)
+}
+
+/// `LIST`/`INFO` body with only [`SAFE_INFO_IDS`] left, or None if none remain
+fn safe_info(body: &[u8]) -> Option<Vec<u8>> {
+    let mut info = b"INFO".to_vec();
+    let mut pos = 4;
+    while pos + 8 <= body.len() {
+        let id = &body[pos..pos + 4];
+        let size = u32::from_le_bytes([body[pos + 4], body[pos + 5], body[pos + 6], body[pos + 7]]) as usize;
+        let end = (pos + 8 + size + (size & 1)).min(body.len());
+        if SAFE_INFO_IDS.iter().any(|safe| &safe[..] == id) {
+            info.extend_from_slice(&body[pos..end]);
+        }
+        pos = end;
+    }
+    (info.len() > 4).then_some(info)
+}
+
+/// Swap the `fmt ` and `data` chunks of `stripped` for those of `encoded`,
+/// keeping the other chunks where they were
+fn replace_audio(stripped: &[u8], encoded: &[u8]) -> Result<Vec<u8>, ProcessingError> {
+    let encoded = riff_chunks(encoded)?;
+    let replacement = |id: &[u8; 4]| encoded.iter().find(|chunk| &chunk.id == id).cloned();
+
+    let mut chunks = Vec::new();
+    for chunk in riff_chunks(stripped)? {
+        match &chunk.id {
+            b"fmt " | b"data" => {
+                chunks.push(replacement(&chunk.id).ok_or_else(|| corrupt_wav("ffmpeg output lacks audio"))?)
+            }
+            // Only needed for compressed formats, which the output never is
+            b"fact" => {}
+            _ => chunks.push(chunk),
+        }
+    }
+    Ok(write_wav(&chunks))
+}
+
+fn corrupt_wav(reason: &str) -> ProcessingError {
+    ProcessingError::CorruptInput {
+        format: "wav".to_string(),
+        offset: None,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_wav_metadata() {
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM\x05\0\0\0Song\0\0");
+        info.extend_from_slice(b"ISFT\x04\0\0\0Lavf");
+        let chunks = [
+            Chunk { id: *b"fmt ", body: Cow::Borrowed(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0]) },
+            Chunk { id: *b"LIST", body: Cow::Owned(info) },
+            Chunk { id: *b"data", body: Cow::Borrowed(&[1, 2, 3, 4]) },
+        ];
+        let wav = write_wav(&chunks);
+
+        let safe = strip_wav_metadata(&wav, StripMode::Safe, false).unwrap();
+        let kept = riff_chunks(&safe).unwrap();
+        assert_eq!(kept.len(), 3);
+        assert_eq!(&kept[1].body[..], b"INFOINAM\x05\0\0\0Song\0\0");
+        assert_eq!(u32::from_le_bytes(safe[4..8].try_into().unwrap()) as usize, safe.len() - 8);
+
+        let all = strip_wav_metadata(&wav, StripMode::All, false).unwrap();
+        let ids: Vec<[u8; 4]> = riff_chunks(&all).unwrap().iter().map(|chunk| chunk.id).collect();
+        assert_eq!(ids, [*b"fmt ", *b"data"]);
+    }
+
+    #[test]
+    fn test_keep_broadcast_chunks() {
+        let chunks = [
+            Chunk { id: *b"fmt ", body: Cow::Borrowed(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0]) },
+            Chunk { id: *b"bext", body: Cow::Borrowed(&[0; 602]) },
+            Chunk { id: *b"JUNK", body: Cow::Borrowed(&[0; 28]) },
+            Chunk { id: *b"iXML", body: Cow::Borrowed(b"<BWFXML></BWFXML>") },
+            Chunk { id: *b"data", body: Cow::Borrowed(&[1, 2, 3, 4]) },
+        ];
+        let wav = write_wav(&chunks);
+
+        let ids = |wav: &[u8]| -> Vec<[u8; 4]> { riff_chunks(wav).unwrap().iter().map(|chunk| chunk.id).collect() };
+        for mode in [StripMode::All, StripMode::Safe] {
+            let kept = strip_wav_metadata(&wav, mode, true).unwrap();
+            assert_eq!(ids(&kept), [*b"fmt ", *b"bext", *b"iXML", *b"data"]);
+        }
+        assert_eq!(ids(&strip_wav_metadata(&wav, StripMode::Safe, false).unwrap()), [*b"fmt ", *b"data"]);
+    }
+
+    #[test]
+    fn test_trim_wav_silence() {
+        // 16-bit mono at 1 kHz: 0.6 s silence, 0.1 s tone, 0.1 s silence
+        let pcm = PcmFormat { channels: 1, sample_rate: 1000, bits_per_sample: 16, float: false, pcm: true };
+        let mut samples = vec![0i16; 600];
+        samples.extend([8000, -8000].repeat(50));
+        samples.extend([3; 100]);
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let chunks = [
+            Chunk { id: *b"fmt ", body: Cow::Borrowed(&[1, 0, 1, 0, 0xE8, 0x03, 0, 0, 0xD0, 0x07, 0, 0, 2, 0, 16, 0]) },
+            Chunk { id: *b"data", body: Cow::Owned(data) },
+        ];
+        let wav = write_wav(&chunks);
+
+        let trim = SilenceTrim::default();
+        let trimmed = trim_wav_silence(&wav, &pcm, &trim).unwrap().unwrap();
+        // The leading 0.6 s goes; the trailing 0.1 s is shorter than min_duration
+        assert_eq!(riff_chunks(&trimmed).unwrap()[1].body.len(), 200 * 2);
+
+        let silent = write_wav(&[chunks[0].clone(), Chunk { id: *b"data", body: Cow::Owned(vec![0; 2000]) }]);
+        assert_eq!(trim_wav_silence(&silent, &pcm, &trim).unwrap(), None);
+    }
+
+    #[test]
+    fn test_strip_wav_stream() {
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM\x05\0\0\0Song\0\0");
+        info.extend_from_slice(b"ISFT\x04\0\0\0Lavf");
+        let chunks = [
+            Chunk { id: *b"fmt ", body: Cow::Borrowed(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0]) },
+            Chunk { id: *b"bext", body: Cow::Borrowed(&[0; 602]) },
+            Chunk { id: *b"data", body: Cow::Borrowed(&[1, 2, 3]) },
+            // Tag chunks written after the audio, as many editors do
+            Chunk { id: *b"LIST", body: Cow::Owned(info) },
+            Chunk { id: *b"JUNK", body: Cow::Borrowed(&[0; 5]) },
+        ];
+        let wav = write_wav(&chunks);
+
+        let stream = |strip_mode, broadcast| {
+            let mut output = Vec::new();
+            strip_wav_stream(&mut wav.as_slice(), &mut output, strip_mode, broadcast).unwrap();
+            output
+        };
+        for (strip_mode, broadcast) in [(StripMode::Safe, false), (StripMode::All, false), (StripMode::All, true)] {
+            assert_eq!(stream(strip_mode, broadcast), strip_wav_metadata(&wav, strip_mode, broadcast).unwrap());
+        }
+        assert_eq!(stream(StripMode::None, false), wav);
+
+        // A stale data size from an interrupted recording keeps the samples there are
+        let mut cut = write_wav(&chunks[..3]);
+        cut[650..654].copy_from_slice(&1000u32.to_le_bytes());
+        let mut output = Vec::new();
+        strip_wav_stream(&mut cut.as_slice(), &mut output, StripMode::All, false).unwrap();
+        let kept = riff_chunks(&output).unwrap();
+        assert_eq!(kept.iter().map(|chunk| chunk.id).collect::<Vec<_>>(), [*b"fmt ", *b"data"]);
+        assert_eq!(&kept[1].body[..], [1, 2, 3, 0]);
+
+        assert!(strip_wav_stream(&mut &b"RIFF\0\0\0\0WAVE"[..], &mut Vec::new(), StripMode::All, false).is_err());
+    }
+
+    #[test]
+    fn test_delivery_plan() {
+        let master = PcmFormat { channels: 2, sample_rate: 96_000, bits_per_sample: 24, float: false, pcm: true };
+        let options = WavOptions { sample_rate: Some(48_000), bit_depth: Some(16), ..WavOptions::default() };
+        let mut warnings = Vec::new();
+        assert_eq!(delivery_plan(&master, &options, &mut warnings), (Some(48_000), Some(16)));
+        assert!(warnings.is_empty());
+
+        // Never upsampled or padded, with a warning for each
+        let cd = PcmFormat { sample_rate: 44_100, bits_per_sample: 16, ..master };
+        assert_eq!(delivery_plan(&cd, &options, &mut warnings), (None, None));
+        assert_eq!(warnings, ["already at 44100 Hz, not resampled", "already 16-bit, bit depth left as is"]);
+
+        // Float samples always go to integers, even at the same width
+        let float = PcmFormat { bits_per_sample: 16, float: true, ..master };
+        let mut warnings = Vec::new();
+        assert_eq!(delivery_plan(&float, &options, &mut warnings), (Some(48_000), Some(16)));
+        assert!(warnings.is_empty());
+
+        assert_eq!(delivery_plan(&master, &WavOptions::default(), &mut warnings), (None, None));
+        assert!(warnings.is_empty());
+    }
+}
@@ -1,54 +1,47 @@
-use image::GenericImageView;
+use image::DynamicImage;
 
 use crate::config::{ProcessingConfig, StripMode};
+use crate::converter::{encode_image_with_quality, encodes_lossy};
 use crate::error::ProcessingError;
-use crate::format::ImageFormat;
-use crate::processor::ImageProcessor;
+use crate::explain;
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+use crate::limits::{check_header, decode_image};
+use crate::outcome::ProcessOutcome;
+use crate::processor::{Capabilities, ImageProcessor, ProcessorPlan};
+use crate::progress::{Progress, Stage};
 
 pub struct WebpProcessor;
 
-/// Display all metadata from a WebP file
-pub fn inspect_webp(input: &[u8]) -> Result<(), ProcessingError> {
-    println!("\n═══════════════════════════════════════════════════════");
-    println!("                 WebP Metadata Inspection");
-    println!("═══════════════════════════════════════════════════════\n");
-
-    let file_size = input.len();
-    println!("File size: {} bytes ({:.2} KB)\n", file_size, file_size as f64 / 1024.0);
+/// Inspection report of a WebP file: dimensions, then every RIFF chunk with its size
+pub fn inspect_webp(input: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Webp, input.len());
 
     // Decode WebP to get image info
-    match webp::Decoder::new(input).decode() {
-        Some(decoded) => {
-            let (width, height) = (decoded.width(), decoded.height());
-            println!("Image dimensions: {} x {} pixels", width, height);
-            println!("Total pixels: {}\n", width * height);
-        }
-        None => {
-            println!("Could not decode WebP image\n");
+    match image::ImageReader::with_format(std::io::Cursor::new(input), image::ImageFormat::WebP).into_dimensions() {
+        Ok((width, height)) => {
+            report.field("Image dimensions", format!("{} x {} pixels", width, height));
+            report.field("Total pixels", width as u64 * height as u64);
         }
+        Err(_) => report.errors.push("Could not decode WebP image".to_string()),
     }
 
     // Parse WebP structure (RIFF container)
     if input.len() < 12 {
-        println!("File too small to be a valid WebP");
-        println!("\n═══════════════════════════════════════════════════════\n");
-        return Ok(());
+        report.errors.push("File too small to be a valid WebP".to_string());
+        return Ok(report);
     }
 
     if &input[0..4] != b"RIFF" || &input[8..12] != b"WEBP" {
-        println!("Invalid WebP signature");
-        println!("\n═══════════════════════════════════════════════════════\n");
-        return Ok(());
+        report.errors.push("Invalid WebP signature".to_string());
+        return Ok(report);
     }
 
     let file_size_riff = u32::from_le_bytes([input[4], input[5], input[6], input[7]]);
-    println!("RIFF container size: {} bytes\n", file_size_riff);
-
-    println!("WebP Chunks:");
-    println!("───────────────────────────────────────────────────────");
+    report.field("RIFF container size", format!("{} bytes", file_size_riff));
 
+    let mut section = Section::new("WebP Chunks");
     let mut pos = 12;
-    let mut chunk_count = 0;
 
     while pos + 8 <= input.len() {
         let chunk_type = &input[pos..pos + 4];
@@ -60,18 +53,16 @@ pub fn inspect_webp(input: &[u8]) -> Result<(), ProcessingError> {
         ]) as usize;
 
         if let Ok(chunk_name) = std::str::from_utf8(chunk_type) {
-            chunk_count += 1;
-            let chunk_info = get_webp_chunk_info(chunk_name);
-
-            println!("  {} - {}", chunk_name, chunk_info);
-            println!("      Size: {} bytes", chunk_size);
-
-            // Display some chunk contents
+            let mut entry = Entry::new(chunk_name);
+            entry.description = Some(get_webp_chunk_info(chunk_name).to_string());
+            // The chunks `--strip safe` keeps (see strip_webp_metadata)
+            let safe = matches!(chunk_name, "VP8 " | "VP8L" | "VP8X" | "ALPH" | "ANIM" | "ANMF");
+            entry.class = Some(if safe { EntryClass::Safe } else { EntryClass::Unsafe });
+            entry.size = Some(chunk_size as u64);
             if pos + 8 + chunk_size <= input.len() {
-                display_webp_chunk_content(chunk_name, &input[pos + 8..pos + 8 + chunk_size]);
+                webp_chunk_content(&mut entry, chunk_name, &input[pos + 8..pos + 8 + chunk_size]);
             }
-
-            println!();
+            section.entries.push(entry);
         }
 
         // WebP chunks are padded to even size
@@ -83,11 +74,9 @@ pub fn inspect_webp(input: &[u8]) -> Result<(), ProcessingError> {
         }
     }
 
-    println!("───────────────────────────────────────────────────────");
-    println!("Summary: {} total chunks", chunk_count);
-    println!("\n═══════════════════════════════════════════════════════\n");
-
-    Ok(())
+    section.summary = Some(format!("{} total chunks", section.entries.len()));
+    report.sections.push(section);
+    Ok(report)
 }
 
 /// Get human-readable chunk information
@@ -106,101 +95,231 @@ fn get_webp_chunk_info(chunk_type: &str) -> &str {
     }
 }
 
-/// Display relevant chunk content
-fn display_webp_chunk_content(chunk_type: &str, data: &[u8]) {
+/// Decoded contents of the chunks worth showing, added to `entry`
+fn webp_chunk_content(entry: &mut Entry, chunk_type: &str, data: &[u8]) {
     match chunk_type {
-        "VP8X" => {
-            if data.len() >= 10 {
-                let flags = data[0];
-                let has_icc = flags & 0x20 != 0;
-                let has_alpha = flags & 0x10 != 0;
-                let has_exif = flags & 0x08 != 0;
-                let has_xmp = flags & 0x04 != 0;
-                let has_anim = flags & 0x02 != 0;
-
-                let width = u32::from_le_bytes([data[4], data[5], data[6], 0]) + 1;
-                let height = u32::from_le_bytes([data[7], data[8], data[9], 0]) + 1;
-
-                println!("      Canvas: {}x{}", width, height);
-                println!("      Has ICC: {}, Alpha: {}, EXIF: {}, XMP: {}, Animation: {}",
-                         has_icc, has_alpha, has_exif, has_xmp, has_anim);
-            }
+        "VP8X" if data.len() >= 10 => {
+            let flags = data[0];
+            let has_icc = flags & 0x20 != 0;
+            let has_alpha = flags & 0x10 != 0;
+            let has_exif = flags & 0x08 != 0;
+            let has_xmp = flags & 0x04 != 0;
+            let has_anim = flags & 0x02 != 0;
+
+            let width = u32::from_le_bytes([data[4], data[5], data[6], 0]) + 1;
+            let height = u32::from_le_bytes([data[7], data[8], data[9], 0]) + 1;
+
+            entry.field("Canvas", format!("{}x{}", width, height));
+            entry.field("Features", format!("ICC: {}, Alpha: {}, EXIF: {}, XMP: {}, Animation: {}",
+                     has_icc, has_alpha, has_exif, has_xmp, has_anim));
         }
-        "VP8 " => {
-            if data.len() >= 10 {
-                // VP8 frame tag
-                let frame_tag = data[0] as u32
-                    | ((data[1] as u32) << 8)
-                    | ((data[2] as u32) << 16);
-                let key_frame = (frame_tag & 1) == 0;
-                let version = (frame_tag >> 1) & 7;
-                let show_frame = (frame_tag >> 4) & 1 == 1;
-
-                println!("      Key frame: {}, Version: {}, Show: {}",
-                         key_frame, version, show_frame);
-
-                if data.len() >= 10 && data[3] == 0x9d && data[4] == 0x01 && data[5] == 0x2a {
-                    let width = ((data[7] as u16) << 8) | (data[6] as u16);
-                    let height = ((data[9] as u16) << 8) | (data[8] as u16);
-                    println!("      Dimensions: {}x{}", width & 0x3fff, height & 0x3fff);
-                }
+        "VP8 " if data.len() >= 10 => {
+            // VP8 frame tag
+            let frame_tag = data[0] as u32
+                | ((data[1] as u32) << 8)
+                | ((data[2] as u32) << 16);
+            let key_frame = (frame_tag & 1) == 0;
+            let version = (frame_tag >> 1) & 7;
+            let show_frame = (frame_tag >> 4) & 1 == 1;
+
+            entry.field("Frame", format!("key frame: {}, version: {}, show: {}",
+                     key_frame, version, show_frame));
+
+            if data.len() >= 10 && data[3] == 0x9d && data[4] == 0x01 && data[5] == 0x2a {
+                let width = ((data[7] as u16) << 8) | (data[6] as u16);
+                let height = ((data[9] as u16) << 8) | (data[8] as u16);
+                entry.field("Dimensions", format!("{}x{}", width & 0x3fff, height & 0x3fff));
             }
         }
-        "EXIF" => {
-            println!("      Contains EXIF metadata ({} bytes)", data.len());
-        }
-        "XMP " => {
-            println!("      Contains XMP metadata ({} bytes)", data.len());
-        }
-        "ICCP" => {
-            println!("      Contains ICC color profile ({} bytes)", data.len());
-        }
+        "EXIF" => entry.warnings.push(format!("Contains EXIF metadata ({} bytes)", data.len())),
+        "XMP " => entry.warnings.push(format!("Contains XMP metadata ({} bytes)", data.len())),
+        "ICCP" => entry.warnings.push(format!("Contains ICC color profile ({} bytes)", data.len())),
         _ => {}
     }
 }
 
 impl ImageProcessor for WebpProcessor {
-    fn supported_formats(&self) -> &[ImageFormat] {
-        &[ImageFormat::Webp]
+    fn supported_formats(&self) -> &[Format] {
+        &[Format::Webp]
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lossy: true,
+            ..Capabilities::default()
+        }
     }
 
     fn process(&self, input: &[u8], config: &ProcessingConfig) -> Result<Vec<u8>, ProcessingError> {
-        // Decode WebP
-        let img = image::load_from_memory_with_format(input, image::ImageFormat::WebP)
-            .map_err(|e| ProcessingError::Decode(e.to_string()))?;
+        self.process_with_progress(input, config, &Progress::none())
+    }
 
-        let (width, height) = img.dimensions();
-        let rgba = img.to_rgba8();
+    fn process_with_progress(
+        &self,
+        input: &[u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, ProcessingError> {
+        compress(input, config, progress).map(|(webp, _)| webp)
+    }
 
-        // Encode with WebP
-        let encoder = if config.no_lossy {
-            // Lossless mode
-            webp::Encoder::from_rgba(rgba.as_raw(), width, height)
-        } else {
-            // Lossy mode with quality setting
-            webp::Encoder::from_rgba(rgba.as_raw(), width, height)
-        };
+    fn process_with_stats<'a>(
+        &self,
+        input: &'a [u8],
+        config: &ProcessingConfig,
+        progress: &Progress,
+    ) -> Result<ProcessOutcome<'a>, ProcessingError> {
+        let (data, quality) = compress(input, config, progress)?;
+        let input_metadata = metadata_chunk_bytes(input);
+        let metadata_removed = input_metadata.saturating_sub(metadata_chunk_bytes(&data));
+
+        let mut outcome = ProcessOutcome::new(data, Format::Webp);
+        outcome.metadata_removed = Some(metadata_removed);
+        outcome.quality = quality;
+        outcome.dimensions = image::ImageReader::with_format(std::io::Cursor::new(input), image::ImageFormat::WebP)
+            .into_dimensions()
+            .ok();
+        if config.strip == StripMode::None && input_metadata > 0 {
+            outcome
+                .warnings
+                .push("re-encoding WebP drops EXIF/XMP/ICC even with --strip none".to_string());
+        }
+        Ok(outcome)
+    }
 
-        let encoded = if config.no_lossy {
-            encoder.encode_lossless()
+    fn plan(&self, input: &[u8], config: &ProcessingConfig) -> ProcessorPlan {
+        let mut plan = if encodes_lossy(Format::Webp, config) {
+            let mut plan = ProcessorPlan::new("decode and re-encode lossy with libwebp");
+            plan.setting("quality", explain::quality(config, Format::Webp));
+            plan
+        } else if config.no_lossy {
+            ProcessorPlan::new("decode and re-encode losslessly")
         } else {
-            // Map quality 0-100 to WebP quality (0-100)
-            encoder.encode(config.quality as f32)
+            ProcessorPlan::new("decode and re-encode losslessly (built without the libwebp feature)")
         };
+        plan.skip = check_header(input, Some(image::ImageFormat::WebP), &config.limits).err().map(|e| e.to_string());
+        plan
+    }
+}
+
+/// Decode, re-encode (tuned to `config.target_ssim` if set) and strip; also
+/// returns the lossy quality used
+fn compress(
+    input: &[u8],
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    // Decode WebP
+    progress.start(Stage::Decode);
+    let img = decode_image(input, Some(image::ImageFormat::WebP), &config.limits)?;
+    progress.finish(Stage::Decode);
+
+    compress_image(&img, config, progress)
+}
 
-        let mut output = encoded.to_vec();
+/// [`compress`] for an already decoded image, possibly from another format
+pub(crate) fn compress_image(
+    img: &DynamicImage,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<(Vec<u8>, Option<u8>), ProcessingError> {
+    // Encode with WebP
+    progress.start(Stage::Encode);
+    let (mut output, quality) = encode_image_with_quality(img, Format::Webp, config)?;
+    progress.finish(Stage::Encode);
+
+    // Strip metadata if requested
+    if config.strip != StripMode::None {
+        progress.start(Stage::Strip);
+        output = strip_webp_metadata(&output, config.strip)?;
+        progress.finish(Stage::Strip);
+    }
 
-        // Strip metadata if requested
-        if config.strip != StripMode::None {
-            output = strip_webp_metadata(&output, config.strip)?;
+    Ok((output, quality))
+}
+
+/// Encode RGBA pixels with libwebp: lossy at `config.quality`, or lossless with `no_lossy`
+#[cfg(feature = "libwebp")]
+pub(crate) fn encode_webp(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    let encoder = webp::Encoder::from_rgba(rgba, width, height);
+    let encoded = if config.no_lossy {
+        encoder.encode_lossless()
+    } else {
+        // Map quality 0-100 to WebP quality (0-100)
+        encoder.encode(config.quality_for(Format::Webp) as f32)
+    };
+    Ok(encoded.to_vec())
+}
+
+/// Pure-Rust fallback without libwebp (e.g. on wasm32): always lossless
+#[cfg(not(feature = "libwebp"))]
+pub(crate) fn encode_webp(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    if !config.no_lossy {
+        log::debug!("libwebp not enabled, encoding WebP losslessly");
+    }
+    let mut output = Vec::new();
+    image::codecs::webp::WebPEncoder::new_lossless(&mut output)
+        .encode(rgba, width, height, image::ExtendedColorType::Rgba8)
+        .map_err(|e| ProcessingError::Encode(e.to_string()))?;
+    Ok(output)
+}
+
+/// Data of the first chunk of `kind`; None if `input` is not a WebP file
+pub(crate) fn find_chunk<'a>(input: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    if input.len() < 12 || &input[0..4] != b"RIFF" || &input[8..12] != b"WEBP" {
+        return None;
+    }
+    let mut pos = 12;
+    while pos + 8 <= input.len() {
+        let chunk_size = u32::from_le_bytes([input[pos + 4], input[pos + 5], input[pos + 6], input[pos + 7]]) as usize;
+        if &input[pos..pos + 4] == kind {
+            return input.get(pos + 8..pos + 8 + chunk_size);
         }
+        pos += 8 + ((chunk_size + 1) & !1);
+    }
+    None
+}
 
-        Ok(output)
+/// Total size of EXIF, XMP and ICC chunks in a WebP file
+fn metadata_chunk_bytes(input: &[u8]) -> u64 {
+    metadata_chunks(input).iter().map(|(_, size)| size).sum()
+}
+
+/// Type and padded size (header included) of each EXIF, XMP and ICC chunk, in file order
+pub(crate) fn metadata_chunks(input: &[u8]) -> Vec<([u8; 4], u64)> {
+    if input.len() < 12 || &input[0..4] != b"RIFF" || &input[8..12] != b"WEBP" {
+        return Vec::new();
+    }
+
+    let mut pos = 12;
+    let mut chunks = Vec::new();
+
+    while pos + 8 <= input.len() {
+        let chunk_type = [input[pos], input[pos + 1], input[pos + 2], input[pos + 3]];
+        let chunk_size = u32::from_le_bytes([input[pos + 4], input[pos + 5], input[pos + 6], input[pos + 7]]) as usize;
+        let padded_size = (chunk_size + 1) & !1;
+
+        if matches!(&chunk_type, b"EXIF" | b"XMP " | b"ICCP") {
+            chunks.push((chunk_type, 8 + padded_size as u64));
+        }
+        pos += 8 + padded_size;
     }
+
+    chunks
 }
 
 /// Strip metadata chunks from WebP file
-fn strip_webp_metadata(input: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
+pub(crate) fn strip_webp_metadata(input: &[u8], strip_mode: StripMode) -> Result<Vec<u8>, ProcessingError> {
     if input.len() < 12 {
         return Ok(input.to_vec());
     }
@@ -0,0 +1,83 @@
+use crate::config::{ProcessingConfig, StripMode};
+
+/// A named, described set of processing settings.
+///
+/// With the `serde` feature it serializes as the name and description next to
+/// the flattened [`ProcessingConfig`] fields, so the same shape works for a
+/// config file entry, a server preset and the settings recorded in a report.
+/// Missing fields fall back to [`ProcessingConfig::default`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub description: Option<String>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub config: ProcessingConfig,
+}
+
+impl Profile {
+    pub fn new(name: impl Into<String>, config: ProcessingConfig) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            config,
+        }
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Broadcast delivery: basic tags plus the Broadcast WAV `bext` and `iXML`
+    /// chunks, which carry legally required origination data; `JUNK` and other
+    /// chunks still go
+    pub fn broadcast() -> Self {
+        let mut config = ProcessingConfig {
+            strip: StripMode::Safe,
+            ..ProcessingConfig::default()
+        };
+        config.wav.broadcast = true;
+        Self::new("broadcast", config).with_description("Keep bext/iXML origination data in WAV")
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::config::PngFilter;
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut config = ProcessingConfig {
+            quality: 65,
+            strip: StripMode::Safe,
+            ..ProcessingConfig::default()
+        };
+        config.png.filters = vec![PngFilter::MinSum, PngFilter::BigEnt];
+        config.png.keep_chunks = vec![*b"iCCP"];
+        config.mp4.crf = Some(26);
+        let profile = Profile::new("web", config).with_description("Small files for the site");
+
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(json["name"], "web");
+        assert_eq!(json["strip"], "safe");
+        assert_eq!(json["png"]["filters"], serde_json::json!(["minsum", "bigent"]));
+        assert_eq!(json["png"]["keep_chunks"], serde_json::json!(["iCCP"]));
+
+        let back: Profile = serde_json::from_value(json).unwrap();
+        assert_eq!(back.description.as_deref(), Some("Small files for the site"));
+        assert_eq!(back.config.quality, 65);
+        assert_eq!(back.config.png, profile.config.png);
+        assert_eq!(back.config.mp4, profile.config.mp4);
+    }
+
+    #[test]
+    fn test_partial_profile_uses_defaults() {
+        let profile: Profile = serde_json::from_str(r#"{"name": "lossless", "no_lossy": true}"#).unwrap();
+        assert!(profile.config.no_lossy);
+        assert_eq!(profile.config.quality, ProcessingConfig::default().quality);
+        assert_eq!(profile.config.png.preset, 4);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::ProcessingError;
+use crate::outcome::StageTimer;
+
+/// Processing stage reported to progress callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Decode,
+    Resize,
+    Quantize,
+    Encode,
+    Optimize,
+    Strip,
+    Ffmpeg,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Resize => "resize",
+            Stage::Quantize => "quantize",
+            Stage::Encode => "encode",
+            Stage::Optimize => "optimize",
+            Stage::Strip => "strip",
+            Stage::Ffmpeg => "ffmpeg",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Callback invoked as `(file, stage, percent)`; percent is 0–100 within the stage.
+pub type StageCallback = Arc<dyn Fn(&Path, Stage, f32) + Send + Sync>;
+
+/// What a [`Pipeline`](crate::pipeline::Pipeline) reports to its [`ProgressSink`]
+/// while it works. Paths are empty for bytes processed without a file name.
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressEvent<'a> {
+    /// [`Pipeline::process_batch`](crate::pipeline::Pipeline::process_batch) is about to process `files` files
+    BatchStarted { files: usize },
+    /// A file of `size` bytes was picked up
+    FileStarted { path: &'a Path, size: u64 },
+    /// A stage of the file reached `percent` (0–100), as reported to `on_stage`
+    Stage { path: &'a Path, stage: Stage, percent: f32 },
+    /// The file was processed; `output_size` equals `original_size` when it was left as is
+    FileDone { path: &'a Path, original_size: u64, output_size: u64, bytes_saved: u64 },
+    /// The file failed (or was cancelled)
+    FileFailed { path: &'a Path, error: &'a ProcessingError },
+    /// `process_batch` finished every file
+    BatchDone { processed: usize, failed: usize, bytes_saved: u64 },
+}
+
+/// Receiver of [`ProgressEvent`]s, called on the worker threads: implementations
+/// should return quickly, e.g. by handing the event to a channel, an SSE stream
+/// or a GUI's event loop. Closures taking `&ProgressEvent` are sinks.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: &ProgressEvent<'_>);
+}
+
+impl<F: Fn(&ProgressEvent<'_>) + Send + Sync> ProgressSink for F {
+    fn event(&self, event: &ProgressEvent<'_>) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn ProgressSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressSink")
+    }
+}
+
+/// Per-file progress handle passed to processors.
+#[derive(Clone, Copy)]
+pub struct Progress<'a> {
+    file: &'a Path,
+    callback: Option<&'a StageCallback>,
+    sink: Option<&'a dyn ProgressSink>,
+    timer: Option<&'a StageTimer>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(file: &'a Path, callback: Option<&'a StageCallback>) -> Self {
+        Self { file, callback, sink: None, timer: None }
+    }
+
+    /// Also send stage reports to `sink`
+    pub fn with_sink(mut self, sink: Option<&'a dyn ProgressSink>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Also record stage durations into `timer`
+    pub fn with_timer(mut self, timer: &'a StageTimer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    /// Handle that discards all reports
+    pub fn none() -> Progress<'static> {
+        Progress {
+            file: Path::new(""),
+            callback: None,
+            sink: None,
+            timer: None,
+        }
+    }
+
+    pub fn report(&self, stage: Stage, percent: f32) {
+        if let Some(timer) = self.timer {
+            timer.record(stage, percent);
+        }
+        let percent = percent.clamp(0.0, 100.0);
+        if let Some(callback) = self.callback {
+            callback(self.file, stage, percent);
+        }
+        if let Some(sink) = self.sink {
+            sink.event(&ProgressEvent::Stage { path: self.file, stage, percent });
+        }
+    }
+
+    /// Report the start of a stage (0%)
+    pub fn start(&self, stage: Stage) {
+        self.report(stage, 0.0);
+    }
+
+    /// Report the end of a stage (100%)
+    pub fn finish(&self, stage: Stage) {
+        self.report(stage, 100.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::config::ProcessingConfig;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn test_progress_sink() {
+        let mut png = Vec::new();
+        image::RgbImage::from_pixel(16, 16, image::Rgb([200, 10, 10]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = Arc::clone(&events);
+        let mut pipeline = Pipeline::with_default_processors();
+        pipeline.set_progress_sink(Arc::new(move |event: &ProgressEvent| {
+            let summary = match *event {
+                ProgressEvent::BatchStarted { files } => format!("batch {}", files),
+                ProgressEvent::FileStarted { path, .. } => format!("start {}", path.display()),
+                ProgressEvent::Stage { .. } => return,
+                ProgressEvent::FileDone { path, original_size, output_size, bytes_saved } => {
+                    assert_eq!(original_size - output_size.min(original_size), bytes_saved);
+                    format!("done {}", path.display())
+                }
+                ProgressEvent::FileFailed { path, .. } => format!("failed {}", path.display()),
+                ProgressEvent::BatchDone { processed, failed, .. } => format!("end {} {}", processed, failed),
+            };
+            sink_events.lock().unwrap().push(summary);
+        }));
+
+        let broken = b"\x89PNG\r\n\x1a\n";
+        let inputs = [(Path::new("red.png"), png.as_slice()), (Path::new("broken.png"), broken.as_slice())];
+        pipeline.process_batch(&inputs, &ProcessingConfig::default());
+        let mut events = events.lock().unwrap().clone();
+        assert_eq!(events.first().map(String::as_str), Some("batch 2"));
+        assert_eq!(events.last().map(String::as_str), Some("end 1 1"));
+        // Files run in parallel, so their events interleave
+        events.sort();
+        assert_eq!(events[1..], ["done red.png", "end 1 1", "failed broken.png", "start broken.png", "start red.png"]);
+    }
+}
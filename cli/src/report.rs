@@ -1,4 +1,9 @@
-use std::path::PathBuf;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::format::Format;
+use crate::term::{self, Color};
 
 /// Result of processing a single file.
 pub struct FileResult {
@@ -6,15 +11,119 @@ pub struct FileResult {
     pub original_size: u64,
     pub compressed_size: u64,
     pub skipped: bool,
+    /// Processing was a pass-through; the file was not rewritten
+    pub unchanged: bool,
+    /// Not processed at all: the cache says it already was, with the same settings
+    /// (also counts as `unchanged`)
+    pub cached: bool,
     pub error: Option<String>,
+    /// Bytes of metadata stripped, when the processor reports it
+    pub metadata_removed: Option<u64>,
+    /// Average bitrate achieved for audio output, or for the video stream of video output, in bits/s
+    pub bitrate: Option<u64>,
+    pub warnings: Vec<String>,
+    /// Hex SHA-256 of what the output path holds after the run; None when nothing was read
+    /// or written there (errors, dry runs)
+    pub output_sha256: Option<String>,
+    /// Hex SHA-256 of the input as read, with `--checksum-input`
+    pub input_sha256: Option<String>,
+    /// Time from reading the file to its result
+    pub duration: Option<Duration>,
+    /// Not processed: an exact copy of this input, whose output it was given with
+    /// `--dedupe-exact` (also counts as `skipped`)
+    pub duplicate_of: Option<PathBuf>,
 }
 
 impl FileResult {
+    /// `path` went from `original_size` to `compressed_size` bytes; nothing else set
+    pub fn new(path: PathBuf, original_size: u64, compressed_size: u64) -> Self {
+        Self {
+            path,
+            original_size,
+            compressed_size,
+            skipped: false,
+            unchanged: false,
+            cached: false,
+            error: None,
+            metadata_removed: None,
+            bitrate: None,
+            warnings: Vec::new(),
+            output_sha256: None,
+            input_sha256: None,
+            duration: None,
+            duplicate_of: None,
+        }
+    }
+
+    /// `path` could not be processed; trying took `duration`
+    pub fn failed(path: PathBuf, error: String, duration: Option<Duration>) -> Self {
+        Self { error: Some(error), duration, ..Self::new(path, 0, 0) }
+    }
+
     pub fn savings_pct(&self) -> f64 {
-        if self.original_size == 0 {
-            return 0.0;
+        savings_pct(self.original_size, self.compressed_size)
+    }
+
+    /// One word for what happened to the file, as machine-readable reports list it
+    pub fn status(&self) -> &'static str {
+        if self.error.is_some() {
+            "error"
+        } else if self.cached {
+            "cached"
+        } else if self.unchanged {
+            "unchanged"
+        } else if self.duplicate_of.is_some() {
+            "duplicate"
+        } else if self.skipped {
+            "skipped"
+        } else {
+            "processed"
+        }
+    }
+
+    fn duration_ms(&self) -> Option<u64> {
+        self.duration.map(|duration| duration.as_millis() as u64)
+    }
+}
+
+/// Machine-readable report formats (`--report-format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ReportFormat {
+    /// One object per file plus totals
+    #[cfg(feature = "serde")]
+    Json,
+    /// One row per file, with a header row
+    Csv,
+    /// A table for PR comments and job summaries
+    Markdown,
+}
+
+impl ReportFormat {
+    /// Format for a report file named `path`, by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "serde")]
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "md" | "markdown" => Some(Self::Markdown),
+            _ => None,
         }
-        (1.0 - self.compressed_size as f64 / self.original_size as f64) * 100.0
+    }
+}
+
+/// Totals over the files of one format, for the per-format breakdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FormatTotals {
+    pub files: usize,
+    pub original_size: u64,
+    pub compressed_size: u64,
+}
+
+impl FormatTotals {
+    pub fn savings_pct(&self) -> f64 {
+        savings_pct(self.original_size, self.compressed_size)
     }
 }
 
@@ -23,6 +132,12 @@ pub struct Report {
     pub results: Vec<FileResult>,
 }
 
+impl Default for Report {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Report {
     pub fn new() -> Self {
         Self {
@@ -43,47 +158,280 @@ impl Report {
     }
 
     pub fn total_savings_pct(&self) -> f64 {
-        let orig = self.total_original();
-        if orig == 0 {
-            return 0.0;
-        }
-        (1.0 - self.total_compressed() as f64 / orig as f64) * 100.0
+        savings_pct(self.total_original(), self.total_compressed())
     }
 
     pub fn success_count(&self) -> usize {
-        self.results.iter().filter(|r| r.error.is_none() && !r.skipped).count()
+        self.results
+            .iter()
+            .filter(|r| r.error.is_none() && !r.skipped && !r.unchanged)
+            .count()
+    }
+
+    pub fn unchanged_count(&self) -> usize {
+        self.results.iter().filter(|r| r.unchanged && !r.cached).count()
+    }
+
+    pub fn cached_count(&self) -> usize {
+        self.results.iter().filter(|r| r.cached).count()
+    }
+
+    pub fn duplicate_count(&self) -> usize {
+        self.results.iter().filter(|r| r.duplicate_of.is_some()).count()
+    }
+
+    /// Metadata bytes removed across written files
+    pub fn total_metadata_removed(&self) -> u64 {
+        self.results
+            .iter()
+            .filter(|r| r.error.is_none() && !r.skipped && !r.unchanged)
+            .filter_map(|r| r.metadata_removed)
+            .sum()
     }
 
     pub fn error_count(&self) -> usize {
         self.results.iter().filter(|r| r.error.is_some()).count()
     }
 
+    /// Totals per input format (by extension) over the files without errors,
+    /// largest original total first. Files of unknown extension are left out.
+    pub fn by_format(&self) -> Vec<(Format, FormatTotals)> {
+        let mut totals: Vec<(Format, FormatTotals)> = Vec::new();
+        for r in self.results.iter().filter(|r| r.error.is_none()) {
+            let Some(format) = Format::from_path(&r.path) else {
+                continue;
+            };
+            let index = match totals.iter().position(|(f, _)| *f == format) {
+                Some(index) => index,
+                None => {
+                    totals.push((format, FormatTotals::default()));
+                    totals.len() - 1
+                }
+            };
+            let entry = &mut totals[index].1;
+            entry.files += 1;
+            entry.original_size += r.original_size;
+            entry.compressed_size += r.compressed_size;
+        }
+        totals.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.original_size));
+        totals
+    }
+
+    /// The per-file results and totals in `format`
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            #[cfg(feature = "serde")]
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Markdown => self.to_markdown(),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> String {
+        let files: Vec<_> = self
+            .results
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "path": r.path,
+                    "status": r.status(),
+                    "original_size": r.original_size,
+                    "compressed_size": r.compressed_size,
+                    "savings_pct": round2(r.savings_pct()),
+                    "duration_ms": r.duration_ms(),
+                    "error": r.error,
+                    "warnings": r.warnings,
+                    "output_sha256": r.output_sha256,
+                    "input_sha256": r.input_sha256,
+                    "duplicate_of": r.duplicate_of,
+                })
+            })
+            .collect();
+        let report = serde_json::json!({
+            "files": files,
+            "totals": {
+                "processed": self.success_count(),
+                "unchanged": self.unchanged_count(),
+                "cached": self.cached_count(),
+                "duplicates": self.duplicate_count(),
+                "errors": self.error_count(),
+                "original_size": self.total_original(),
+                "compressed_size": self.total_compressed(),
+                "savings_pct": round2(self.total_savings_pct()),
+                "metadata_removed": self.total_metadata_removed(),
+            },
+        });
+        serde_json::to_string_pretty(&report).expect("report serializes") + "\n"
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "path,status,original_size,compressed_size,savings_pct,duration_ms,error,input_sha256,output_sha256\n",
+        );
+        for r in &self.results {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{:.2},{},{},{},{}",
+                csv_field(&r.path.display().to_string()),
+                r.status(),
+                r.original_size,
+                r.compressed_size,
+                r.savings_pct(),
+                r.duration_ms().map(|ms| ms.to_string()).unwrap_or_default(),
+                csv_field(r.error.as_deref().unwrap_or("")),
+                r.input_sha256.as_deref().unwrap_or(""),
+                r.output_sha256.as_deref().unwrap_or(""),
+            );
+        }
+        csv
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut table = String::from("| File | Original | Compressed | Savings | Time | Result |\n");
+        table.push_str("|------|---------:|-----------:|--------:|-----:|--------|\n");
+        for r in &self.results {
+            let result = match (&r.error, &r.duplicate_of) {
+                (Some(error), _) => format!("error: {}", error),
+                (None, Some(original)) => format!("duplicate of {}", original.display()),
+                (None, None) => r.status().to_string(),
+            };
+            let _ = writeln!(
+                table,
+                "| {} | {} | {} | {:.1}% | {} | {} |",
+                markdown_cell(&r.path.display().to_string()),
+                format_size(r.original_size),
+                format_size(r.compressed_size),
+                r.savings_pct(),
+                r.duration_ms().map(|ms| format!("{} ms", ms)).unwrap_or_default(),
+                markdown_cell(&result),
+            );
+        }
+        let _ = writeln!(
+            table,
+            "\n**Total:** {} → {} ({:.1}% reduction), {} processed, {} error(s)",
+            format_size(self.total_original()),
+            format_size(self.total_compressed()),
+            self.total_savings_pct(),
+            self.success_count(),
+            self.error_count()
+        );
+        table
+    }
+
     pub fn print_summary(&self) {
-        println!("\n--- Summary ---");
+        println!("\n{}", term::paint("--- Summary ---", Color::Bold));
+        let errors = self.error_count();
         println!(
             "Files processed: {} | Errors: {}",
-            self.success_count(),
-            self.error_count()
+            term::paint(self.success_count(), Color::Green),
+            term::paint(errors, if errors > 0 { Color::Red } else { Color::Green })
         );
 
+        let unchanged = self.unchanged_count();
+        if unchanged > 0 {
+            println!("Unchanged (not rewritten): {}", unchanged);
+        }
+        let cached = self.cached_count();
+        if cached > 0 {
+            println!("Already processed (cached): {}", cached);
+        }
+        let duplicates = self.duplicate_count();
+        if duplicates > 0 {
+            println!("Exact duplicates (given their original's output): {}", duplicates);
+        }
+
         if self.success_count() > 0 {
             println!(
-                "Total: {} → {} ({:.1}% reduction)",
+                "Total: {} → {} ({} reduction)",
                 format_size(self.total_original()),
                 format_size(self.total_compressed()),
-                self.total_savings_pct()
+                term::savings(self.total_savings_pct())
             );
+
+            let metadata = self.total_metadata_removed();
+            if metadata > 0 {
+                println!("Metadata stripped: {}", format_size(metadata));
+            }
+
+            let by_format = self.by_format();
+            if by_format.len() > 1 {
+                self.print_by_format(&by_format);
+            }
+        }
+
+        for r in self.results.iter().filter(|r| r.error.is_none() && !r.skipped) {
+            if let Some(bitrate) = r.bitrate {
+                println!("  {}: {}", r.path.display(), format_bitrate(bitrate));
+            }
         }
 
         for r in &self.results {
+            for warning in &r.warnings {
+                println!("  {} {}: {}", term::paint("WARN", Color::Yellow), r.path.display(), warning);
+            }
             if let Some(ref err) = r.error {
-                println!("  ERROR {}: {}", r.path.display(), err);
+                println!("  {} {}: {}", term::paint("ERROR", Color::Red), r.path.display(), err);
             }
         }
     }
+
+    /// One aligned line per format: files, sizes before and after, savings and bytes saved
+    fn print_by_format(&self, by_format: &[(Format, FormatTotals)]) {
+        println!("By format:");
+        let name_width = by_format.iter().map(|(format, _)| format.as_str().len()).max().unwrap_or(0);
+        for (format, totals) in by_format {
+            let saved = totals.original_size.saturating_sub(totals.compressed_size);
+            println!(
+                "  {:<name_width$}  {:>4} file(s)  {:>9} → {:>9}  {} ({} saved)",
+                format.as_str(),
+                totals.files,
+                format_size(totals.original_size),
+                format_size(totals.compressed_size),
+                term::savings(totals.savings_pct()),
+                format_size(saved),
+            );
+        }
+    }
 }
 
-fn format_size(bytes: u64) -> String {
+#[cfg(feature = "serde")]
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// `value` quoted when it holds a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// `value` on one line with its pipes escaped, so it stays in its cell
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+fn format_bitrate(bits_per_sec: u64) -> String {
+    if bits_per_sec >= 1_000_000 {
+        format!("{:.2} Mbit/s", bits_per_sec as f64 / 1_000_000.0)
+    } else {
+        format!("{:.0} kbit/s", bits_per_sec as f64 / 1_000.0)
+    }
+}
+
+/// Percentage of `original` bytes saved by shrinking to `compressed`; 0 for empty files
+pub fn savings_pct(original: u64, compressed: u64) -> f64 {
+    if original == 0 {
+        return 0.0;
+    }
+    (1.0 - compressed as f64 / original as f64) * 100.0
+}
+
+/// `bytes` in B, KB or MB
+pub fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = 1024 * KB;
     if bytes >= MB {
@@ -94,3 +442,56 @@ fn format_size(bytes: u64) -> String {
         format!("{} B", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(path: &str, original_size: u64, compressed_size: u64) -> FileResult {
+        FileResult::new(PathBuf::from(path), original_size, compressed_size)
+    }
+
+    #[test]
+    fn test_by_format() {
+        let mut report = Report::new();
+        report.add(result("a.png", 100, 40));
+        report.add(result("b.PNG", 100, 60));
+        report.add(result("c.mp4", 1000, 900));
+        report.add(result("notes.txt", 5, 5));
+
+        let by_format = report.by_format();
+        assert_eq!(by_format.iter().map(|(format, _)| *format).collect::<Vec<_>>(), [Format::Mp4, Format::Png]);
+        assert_eq!(by_format[1].1, FormatTotals { files: 2, original_size: 200, compressed_size: 100 });
+        assert_eq!(by_format[1].1.savings_pct(), 50.0);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut report = Report::new();
+        report.add(FileResult { duration: Some(Duration::from_millis(12)), ..result("a,b.png", 200, 50) });
+        report.add(FileResult {
+            input_sha256: Some("aa".to_string()),
+            output_sha256: Some("bb".to_string()),
+            ..result("d.png", 100, 90)
+        });
+        report.add(FileResult::failed(PathBuf::from("c.jpg"), "bad | \"data\"".to_string(), None));
+        report.add(FileResult {
+            skipped: true,
+            duplicate_of: Some(PathBuf::from("d.png")),
+            ..result("e.png", 100, 90)
+        });
+        assert_eq!(report.success_count(), 2);
+        assert_eq!(report.duplicate_count(), 1);
+
+        let csv = report.render(ReportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[1], "\"a,b.png\",processed,200,50,75.00,12,,,");
+        assert_eq!(lines[2], "d.png,processed,100,90,10.00,,,aa,bb");
+        assert_eq!(lines[3], "c.jpg,error,0,0,0.00,,\"bad | \"\"data\"\"\",,");
+        assert_eq!(lines[4], "e.png,duplicate,100,90,10.00,,,,");
+
+        let markdown = report.render(ReportFormat::Markdown);
+        assert!(markdown.contains("| c.jpg | 0 B | 0 B | 0.0% |  | error: bad \\| \"data\" |"));
+        assert!(markdown.contains("| e.png | 100 B | 90 B | 10.0% |  | duplicate of d.png |"));
+    }
+}
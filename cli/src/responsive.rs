@@ -0,0 +1,315 @@
+//! Responsive image sets: one source resized to several widths and encoded in
+//! several formats, plus a `<picture>` snippet (or JSON) listing the variants
+//! for `srcset`.
+//!
+//! Variants are named `{stem}-{width}w.{ext}` and the snippets refer to them by
+//! that name, so they are meant to be served from one directory.
+
+use image::imageops::FilterType;
+
+use crate::config::ProcessingConfig;
+use crate::converter::{decode_source, encode_image};
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::processor::png::PngProcessor;
+use crate::processor::ImageProcessor;
+
+/// Breakpoints covering phones to full-HD screens
+pub const DEFAULT_WIDTHS: [u32; 4] = [480, 960, 1440, 1920];
+
+/// What to generate for one source image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponsiveOptions {
+    /// Widths to resize to. Widths above the source's are dropped (no upscaling);
+    /// a source narrower than all of them is kept at its own width.
+    pub widths: Vec<u32>,
+    /// Formats every width is encoded in, preferred first; the last one is the
+    /// `<img>` fallback
+    pub formats: Vec<Format>,
+    /// `sizes` attribute of the snippet
+    pub sizes: String,
+}
+
+impl Default for ResponsiveOptions {
+    fn default() -> Self {
+        Self { widths: DEFAULT_WIDTHS.to_vec(), formats: default_formats(Format::Jpeg), sizes: "100vw".to_string() }
+    }
+}
+
+impl ResponsiveOptions {
+    /// Defaults for a `source` image: AVIF and WebP, as far as the build has
+    /// them, plus the source's own format as the fallback (WebP for sources
+    /// other than PNG and JPEG)
+    pub fn for_source(source: Format) -> Self {
+        Self { formats: default_formats(source), ..Self::default() }
+    }
+}
+
+fn default_formats(source: Format) -> Vec<Format> {
+    let mut formats = Vec::new();
+    if cfg!(feature = "avif") {
+        formats.push(Format::Avif);
+    }
+    match source {
+        Format::Png | Format::Jpeg => {
+            if cfg!(feature = "webp") {
+                formats.push(Format::Webp);
+            }
+            formats.push(source);
+        }
+        _ => formats.push(Format::Webp),
+    }
+    formats
+}
+
+/// One generated file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+impl Variant {
+    /// `{stem}-{width}w.{ext}`
+    pub fn file_name(&self, stem: &str) -> String {
+        format!("{}-{}w.{}", stem, self.width, self.format.extension())
+    }
+}
+
+/// Decode `input` once and encode it at every width in every format of
+/// `options`, grouped by format in `options.formats` order, narrowest first.
+/// PNG variants also go through quantization and oxipng.
+pub fn render_variants(
+    input: &[u8],
+    options: &ResponsiveOptions,
+    config: &ProcessingConfig,
+) -> Result<Vec<Variant>, ProcessingError> {
+    if options.formats.is_empty() || options.widths.is_empty() {
+        return Err(ProcessingError::InvalidConfig("a responsive set needs at least one width and format".to_string()));
+    }
+    if let Some(format) = options.formats.iter().find(|format| !format.is_image()) {
+        return Err(ProcessingError::UnsupportedFormat(format!("cannot make responsive {} images", format.as_str())));
+    }
+    let source = decode_source(input, config)?;
+    let (source_width, source_height) = (source.width(), source.height());
+
+    let mut widths: Vec<u32> = options.widths.iter().copied().filter(|&w| w > 0 && w <= source_width).collect();
+    if widths.is_empty() {
+        widths.push(source_width);
+    }
+    widths.sort_unstable();
+    widths.dedup();
+
+    let resized: Vec<_> = widths
+        .iter()
+        .map(|&width| {
+            let height = ((u64::from(source_height) * u64::from(width) + u64::from(source_width) / 2)
+                / u64::from(source_width))
+            .max(1) as u32;
+            let image = if width == source_width {
+                source.clone()
+            } else {
+                source.resize_exact(width, height, FilterType::Lanczos3)
+            };
+            (width, height, image)
+        })
+        .collect();
+
+    let mut variants = Vec::with_capacity(resized.len() * options.formats.len());
+    for &format in &options.formats {
+        for (width, height, image) in &resized {
+            let mut data = encode_image(image, format, config)?;
+            if format == Format::Png {
+                data = PngProcessor.process(&data, config)?;
+            }
+            log::debug!("{}w {}: {} bytes", width, format.as_str(), data.len());
+            variants.push(Variant { width: *width, height: *height, format, data });
+        }
+    }
+    Ok(variants)
+}
+
+/// `<picture>` markup for `variants` saved under [`Variant::file_name`]: a
+/// `<source>` per preferred format and an `<img>` with the fallback format's
+/// widest variant as `src` and its dimensions (to reserve layout space)
+pub fn picture_html(stem: &str, variants: &[Variant], sizes: &str) -> String {
+    let mut formats: Vec<Format> = Vec::new();
+    for variant in variants {
+        if !formats.contains(&variant.format) {
+            formats.push(variant.format);
+        }
+    }
+    let srcset = |format: Format| {
+        variants
+            .iter()
+            .filter(|v| v.format == format)
+            .map(|v| format!("{} {}w", url(&v.file_name(stem)), v.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut html = String::from("<picture>\n");
+    let Some((&fallback, preferred)) = formats.split_last() else {
+        return html + "</picture>\n";
+    };
+    for &format in preferred {
+        html += &format!(
+            "  <source type=\"{}\" srcset=\"{}\" sizes=\"{}\">\n",
+            format.mime_type(),
+            srcset(format),
+            escape(sizes)
+        );
+    }
+    if let Some(widest) = variants.iter().filter(|v| v.format == fallback).max_by_key(|v| v.width) {
+        html += &format!(
+            "  <img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" alt=\"\">\n",
+            url(&widest.file_name(stem)),
+            srcset(fallback),
+            escape(sizes),
+            widest.width,
+            widest.height
+        );
+    }
+    html + "</picture>\n"
+}
+
+/// The same listing as JSON: `{"sizes": ..., "variants": [{"path", "type", "width", "height", "size"}]}`
+#[cfg(feature = "serde")]
+pub fn srcset_json(stem: &str, variants: &[Variant], sizes: &str) -> String {
+    #[derive(serde::Serialize)]
+    struct Listing<'a> {
+        sizes: &'a str,
+        variants: Vec<Entry>,
+    }
+    #[derive(serde::Serialize)]
+    struct Entry {
+        path: String,
+        #[serde(rename = "type")]
+        mime_type: &'static str,
+        width: u32,
+        height: u32,
+        size: usize,
+    }
+
+    let listing = Listing {
+        sizes,
+        variants: variants
+            .iter()
+            .map(|v| Entry {
+                path: v.file_name(stem),
+                mime_type: v.format.mime_type(),
+                width: v.width,
+                height: v.height,
+                size: v.data.len(),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&listing).unwrap_or_default() + "\n"
+}
+
+/// File name usable inside `srcset`, where spaces and commas separate candidates
+fn url(name: &str) -> String {
+    escape(&name.replace('%', "%25").replace(' ', "%20").replace(',', "%2C"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "webp")]
+    fn test_render_variants_and_snippet() {
+        let source = image::RgbImage::from_fn(1000, 500, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 90]));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90).encode_image(&source).unwrap();
+
+        let options = ResponsiveOptions {
+            widths: vec![1920, 480, 960],
+            formats: vec![Format::Webp, Format::Jpeg],
+            ..ResponsiveOptions::default()
+        };
+        let variants = render_variants(&jpeg, &options, &ProcessingConfig::default()).unwrap();
+        let listed: Vec<_> = variants.iter().map(|v| (v.format, v.width, v.height)).collect();
+        assert_eq!(
+            listed,
+            [(Format::Webp, 480, 240), (Format::Webp, 960, 480), (Format::Jpeg, 480, 240), (Format::Jpeg, 960, 480)]
+        );
+        let decoded = image::load_from_memory(&variants[3].data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (960, 480));
+
+        let html = picture_html("my photo", &variants, "(min-width: 960px) 50vw, 100vw");
+        let webp = r#"<source type="image/webp" srcset="my%20photo-480w.webp 480w, my%20photo-960w.webp 960w""#;
+        assert!(html.contains(webp));
+        assert!(html.contains(r#"<img src="my%20photo-960w.jpg""#));
+        assert!(html.contains(r#"width="960" height="480""#));
+
+        // Narrower than every width: kept at its own size
+        let small = ResponsiveOptions { formats: vec![Format::Png], ..ResponsiveOptions::default() };
+        let mut png = Vec::new();
+        image::RgbImage::new(100, 40).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let variants = render_variants(&png, &small, &ProcessingConfig::default()).unwrap();
+        assert_eq!(variants.iter().map(|v| (v.width, v.height)).collect::<Vec<_>>(), [(100, 40)]);
+    }
+
+    /// A 32x16 JPEG whose EXIF orientation is `orientation`
+    fn tagged_jpeg(orientation: u16) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90);
+        image::RgbImage::from_fn(32, 16, |x, _| image::Rgb([(x * 8) as u8, 0, 0])).write_with_encoder(encoder).unwrap();
+        let tiff = crate::exif::orientation_block(orientation);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&tiff);
+        tagged.extend_from_slice(&jpeg[2..]);
+        tagged
+    }
+
+    #[test]
+    fn test_auto_orient() {
+        let options = ResponsiveOptions { widths: vec![8], formats: vec![Format::Png], ..ResponsiveOptions::default() };
+        let variants = render_variants(&tagged_jpeg(6), &options, &ProcessingConfig::default()).unwrap();
+        assert_eq!(variants.iter().map(|v| (v.width, v.height)).collect::<Vec<_>>(), [(8, 16)]);
+
+        let config = ProcessingConfig { auto_orient: false, ..ProcessingConfig::default() };
+        let variants = render_variants(&tagged_jpeg(6), &options, &config).unwrap();
+        assert_eq!(variants.iter().map(|v| (v.width, v.height)).collect::<Vec<_>>(), [(8, 4)]);
+    }
+
+    #[test]
+    fn test_default_formats() {
+        let avif = cfg!(feature = "avif").then_some(Format::Avif);
+        let webp = cfg!(feature = "webp").then_some(Format::Webp);
+        let expected = |source: Format| avif.into_iter().chain(webp).chain([source]).collect::<Vec<_>>();
+        assert_eq!(ResponsiveOptions::for_source(Format::Png).formats, expected(Format::Png));
+        assert_eq!(ResponsiveOptions::default().formats, expected(Format::Jpeg));
+        // Other sources get WebP in place of their own format
+        let heic: Vec<_> = avif.into_iter().chain([Format::Webp]).collect();
+        assert_eq!(ResponsiveOptions::for_source(Format::Heic).formats, heic);
+    }
+
+    #[test]
+    #[cfg(feature = "avif")]
+    fn test_avif_variants() {
+        let mut png = Vec::new();
+        image::RgbImage::from_pixel(64, 32, image::Rgb([200, 30, 30]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let options =
+            ResponsiveOptions { widths: vec![32], formats: vec![Format::Avif, Format::Png], ..Default::default() };
+        let variants = render_variants(&png, &options, &ProcessingConfig::default()).unwrap();
+        assert_eq!(variants[0].format, Format::Avif);
+        assert_eq!(Format::from_bytes(&variants[0].data), Some(Format::Avif));
+
+        let html = picture_html("red", &variants, "100vw");
+        assert!(html.contains(r#"<source type="image/avif" srcset="red-32w.avif 32w""#));
+        assert!(html.contains(r#"<img src="red-32w.png""#));
+    }
+}
@@ -0,0 +1,165 @@
+//! Per-file JSON reports (`--sidecar`): `{output}.report.json` next to each
+//! output, recording what processing did to that one asset (sizes, what the
+//! processor reported, the settings used) so downstream tooling can audit a
+//! file without parsing a batch report.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::ProcessingConfig;
+use crate::outcome::ProcessOutcome;
+
+/// Suffix appended to the output's file name
+pub const SIDECAR_SUFFIX: &str = ".report.json";
+
+/// What happened to the output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarStatus {
+    /// The processed data was written
+    Written,
+    /// Processing changed nothing; the file was left as it was
+    Unchanged,
+    /// The processed data was not smaller, so it was not written
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Timing {
+    stage: &'static str,
+    ms: f64,
+}
+
+/// Contents of one sidecar file
+#[derive(Debug, Clone, Serialize)]
+pub struct Sidecar<'a> {
+    source: String,
+    output: String,
+    status: SidecarStatus,
+    format: &'static str,
+    original_size: u64,
+    size: u64,
+    savings_pct: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata_removed: Option<u64>,
+    /// Hex SHA-256 of what the output holds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    /// Hex SHA-256 of the source, with `--checksum-input`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_sha256: Option<String>,
+    timings: Vec<Timing>,
+    warnings: &'a [String],
+    settings: &'a ProcessingConfig,
+}
+
+impl<'a> Sidecar<'a> {
+    /// Report for `source` (`original_size` bytes) processed into `outcome`.
+    /// `size` is what the output holds: the outcome's size when written, else the original's.
+    pub fn new(
+        source: &Path,
+        output: &Path,
+        original_size: u64,
+        outcome: &'a ProcessOutcome,
+        status: SidecarStatus,
+        settings: &'a ProcessingConfig,
+    ) -> Self {
+        let size = match status {
+            SidecarStatus::Written => outcome.data.len() as u64,
+            SidecarStatus::Unchanged | SidecarStatus::Skipped => original_size,
+        };
+        let savings_pct =
+            if original_size == 0 { 0.0 } else { (1.0 - size as f64 / original_size as f64) * 100.0 };
+        let (width, height) = outcome.dimensions.unzip();
+        Self {
+            source: source.to_string_lossy().into_owned(),
+            output: output.to_string_lossy().into_owned(),
+            status,
+            format: outcome.format.as_str(),
+            original_size,
+            size,
+            savings_pct: (savings_pct * 100.0).round() / 100.0,
+            quality: outcome.quality,
+            width,
+            height,
+            bitrate: outcome.bitrate,
+            metadata_removed: outcome.metadata_removed,
+            sha256: None,
+            source_sha256: None,
+            timings: outcome
+                .timings
+                .iter()
+                .map(|(stage, duration)| Timing {
+                    stage: stage.as_str(),
+                    ms: (duration.as_secs_f64() * 1e6).round() / 1e3,
+                })
+                .collect(),
+            warnings: &outcome.warnings,
+            settings,
+        }
+    }
+
+    /// Record the checksums of the output and, when computed, of the source
+    pub fn checksums(mut self, sha256: &str, source_sha256: Option<&str>) -> Self {
+        self.sha256 = Some(sha256.to_string());
+        self.source_sha256 = source_sha256.map(str::to_string);
+        self
+    }
+
+    /// Pretty-printed JSON with a trailing newline
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default() + "\n"
+    }
+}
+
+/// `{output}.report.json`
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+
+    #[test]
+    fn test_sidecar_json() {
+        let mut outcome = ProcessOutcome::new(vec![0; 250], Format::Png);
+        outcome.dimensions = Some((16, 8));
+        outcome.quality = Some(80);
+        outcome.warnings.push("palette reduced".to_string());
+        let config = ProcessingConfig::default();
+
+        let sidecar = Sidecar::new(
+            Path::new("in/a.png"),
+            Path::new("out/a.png"),
+            1000,
+            &outcome,
+            SidecarStatus::Written,
+            &config,
+        )
+        .checksums("ab12", None);
+        let json: serde_json::Value = serde_json::from_str(&sidecar.to_json()).unwrap();
+        assert_eq!(json["status"], "written");
+        assert_eq!((json["size"].as_u64(), json["savings_pct"].as_f64()), (Some(250), Some(75.0)));
+        assert_eq!((json["width"].as_u64(), json["quality"].as_u64()), (Some(16), Some(80)));
+        assert_eq!(json["warnings"][0], "palette reduced");
+        assert_eq!(json["settings"]["quality"], config.quality);
+        assert!(json.get("bitrate").is_none());
+        assert_eq!(json["sha256"], "ab12");
+        assert!(json.get("source_sha256").is_none());
+
+        assert_eq!(sidecar_path(Path::new("out/a.png")), Path::new("out/a.png.report.json"));
+    }
+}
@@ -0,0 +1,281 @@
+//! Descriptive tags read and written by `meta`: ID3v2 text frames in MP3,
+//! Vorbis comments in FLAC, iTunes-style `ilst` items in MP4 and text chunks in
+//! PNG. The common fields go by one name in every format ([`Field`]); any other
+//! key is the format's own: a frame id (`TPE2`), a comment field (`LABEL`), an
+//! atom (`©too`) or a PNG keyword (`Author`).
+
+use std::str::FromStr;
+
+use crate::error::ProcessingError;
+use crate::flac;
+use crate::format::Format;
+use crate::processor::png;
+#[cfg(feature = "mp3")]
+use crate::processor::mp3;
+#[cfg(feature = "mp4")]
+use crate::processor::mp4;
+
+/// Formats whose tags can be read and written
+pub const FORMATS: [Format; 4] = [Format::Mp3, Format::Flac, Format::Mp4, Format::Png];
+
+/// Tag fields shared by the formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Year,
+    Genre,
+    Comment,
+    /// Track number, optionally with the total: `3` or `3/12`
+    Track,
+}
+
+impl Field {
+    pub const ALL: [Field; 7] =
+        [Field::Title, Field::Artist, Field::Album, Field::Year, Field::Genre, Field::Comment, Field::Track];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::Year => "year",
+            Field::Genre => "genre",
+            Field::Comment => "comment",
+            Field::Track => "track",
+        }
+    }
+
+    /// ID3v2.4 frame (COMM is a comment frame, the others text frames)
+    #[cfg(feature = "mp3")]
+    fn id3(self) -> &'static str {
+        match self {
+            Field::Title => "TIT2",
+            Field::Artist => "TPE1",
+            Field::Album => "TALB",
+            Field::Year => "TDRC",
+            Field::Genre => "TCON",
+            Field::Comment => "COMM",
+            Field::Track => "TRCK",
+        }
+    }
+
+    /// Vorbis comment field name
+    fn vorbis(self) -> &'static str {
+        match self {
+            Field::Title => "TITLE",
+            Field::Artist => "ARTIST",
+            Field::Album => "ALBUM",
+            Field::Year => "DATE",
+            Field::Genre => "GENRE",
+            Field::Comment => "COMMENT",
+            Field::Track => "TRACKNUMBER",
+        }
+    }
+
+    /// iTunes metadata atom
+    #[cfg(feature = "mp4")]
+    fn mp4_atom(self) -> [u8; 4] {
+        match self {
+            Field::Title => *b"\xa9nam",
+            Field::Artist => *b"\xa9ART",
+            Field::Album => *b"\xa9alb",
+            Field::Year => *b"\xa9day",
+            Field::Genre => *b"\xa9gen",
+            Field::Comment => *b"\xa9cmt",
+            Field::Track => *b"trkn",
+        }
+    }
+
+    /// Predefined PNG keyword, for the fields the PNG spec has one for
+    fn png_keyword(self) -> Option<&'static str> {
+        match self {
+            Field::Title => Some("Title"),
+            Field::Artist => Some("Author"),
+            Field::Comment => Some("Comment"),
+            Field::Year => Some("Creation Time"),
+            Field::Album | Field::Genre | Field::Track => None,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ProcessingError;
+
+    /// Case-insensitive; `date` is the year
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        if name == "date" {
+            return Ok(Field::Year);
+        }
+        Field::ALL
+            .into_iter()
+            .find(|field| field.as_str() == name)
+            .ok_or_else(|| ProcessingError::InvalidConfig(format!("unknown tag field: {}", s)))
+    }
+}
+
+/// Tags to set and to delete in one rewrite. Keys are [`Field`] names or the format's own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagEdit {
+    /// Replace every value of the key with this one
+    pub set: Vec<(String, String)>,
+    pub delete: Vec<String>,
+}
+
+impl TagEdit {
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// The tags of `data` as `(key, value)`, in file order; shared fields under their
+/// [`Field`] name, the rest under the format's own key
+pub fn read_tags(data: &[u8], format: Format) -> Result<Vec<(String, String)>, ProcessingError> {
+    Ok(match format {
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => named(mp3::text_frames(data), |id, field| id == field.id3()),
+        Format::Flac => named(flac::comments(data)?, |key, field| key.eq_ignore_ascii_case(field.vorbis())),
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => {
+            let items = mp4::ilst_items(data)?.into_iter().map(|(atom, value)| (atom_name(&atom), value)).collect();
+            named(items, |name, field| *name == atom_name(&field.mp4_atom()))
+        }
+        Format::Png => named(png::text_chunks(data), |keyword, field| field.png_keyword() == Some(keyword)),
+        _ => return Err(unsupported(format)),
+    })
+}
+
+/// `data` with `edit` applied. Nothing else in the file changes.
+pub fn edit_tags(data: &[u8], format: Format, edit: &TagEdit) -> Result<Vec<u8>, ProcessingError> {
+    match format {
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => {
+            let (set, delete) = resolve(edit, id3_frame)?;
+            mp3::with_text_frames(data, &set, &delete)
+        }
+        Format::Flac => {
+            let (set, delete) = resolve(edit, vorbis_field)?;
+            flac::with_comments(data, &set, &delete)
+        }
+        #[cfg(feature = "mp4")]
+        Format::Mp4 => {
+            let (set, delete) = resolve(edit, mp4_atom)?;
+            mp4::with_ilst_items(data, &set, &delete)
+        }
+        Format::Png => {
+            let (set, delete) = resolve(edit, png_keyword)?;
+            png::with_text_chunks(data, &set, &delete)
+        }
+        _ => Err(unsupported(format)),
+    }
+}
+
+/// `tags` with the keys `is_field` matches for a [`Field`] renamed to it
+fn named(tags: Vec<(String, String)>, is_field: impl Fn(&str, Field) -> bool) -> Vec<(String, String)> {
+    tags.into_iter()
+        .map(|(key, value)| match Field::ALL.into_iter().find(|&field| is_field(&key, field)) {
+            Some(field) => (field.as_str().to_string(), value),
+            None => (key, value),
+        })
+        .collect()
+}
+
+/// `edit` with its keys turned into the format's by `key`
+#[allow(clippy::type_complexity)]
+fn resolve<K>(
+    edit: &TagEdit,
+    key: impl Fn(&str) -> Result<K, ProcessingError>,
+) -> Result<(Vec<(K, String)>, Vec<K>), ProcessingError> {
+    let set = edit.set.iter().map(|(name, value)| Ok((key(name)?, value.clone())));
+    let set = set.collect::<Result<_, ProcessingError>>()?;
+    let delete = edit.delete.iter().map(|name| key(name)).collect::<Result<_, _>>()?;
+    Ok((set, delete))
+}
+
+/// A [`Field`]'s frame, or a text frame id such as `TPE2`
+#[cfg(feature = "mp3")]
+fn id3_frame(key: &str) -> Result<String, ProcessingError> {
+    if let Ok(field) = key.parse::<Field>() {
+        return Ok(field.id3().to_string());
+    }
+    let id = key.to_ascii_uppercase();
+    if id.len() == 4 && id.starts_with('T') && id != "TXXX" && id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Ok(id);
+    }
+    Err(unknown_key(key, Format::Mp3, "a text frame id such as TPE2"))
+}
+
+/// A [`Field`]'s comment name, or any valid Vorbis field name, upper-cased
+fn vorbis_field(key: &str) -> Result<String, ProcessingError> {
+    if let Ok(field) = key.parse::<Field>() {
+        return Ok(field.vorbis().to_string());
+    }
+    if !key.is_empty() && key.bytes().all(|b| (0x20..=0x7D).contains(&b) && b != b'=') {
+        return Ok(key.to_ascii_uppercase());
+    }
+    Err(unknown_key(key, Format::Flac, "an ASCII field name without '='"))
+}
+
+/// A [`Field`]'s atom, or any four Latin-1 characters such as `©too`
+#[cfg(feature = "mp4")]
+fn mp4_atom(key: &str) -> Result<[u8; 4], ProcessingError> {
+    if let Ok(field) = key.parse::<Field>() {
+        return Ok(field.mp4_atom());
+    }
+    let bytes: Vec<u8> = key.chars().map(|c| u8::try_from(u32::from(c)).unwrap_or(0)).collect();
+    match <[u8; 4]>::try_from(bytes) {
+        Ok(atom) if !atom.contains(&0) => Ok(atom),
+        _ => Err(unknown_key(key, Format::Mp4, "a four-character atom such as ©too")),
+    }
+}
+
+/// A [`Field`]'s predefined keyword, or the key itself
+fn png_keyword(key: &str) -> Result<String, ProcessingError> {
+    let field = key.parse::<Field>().ok();
+    Ok(field.and_then(Field::png_keyword).unwrap_or(key).to_string())
+}
+
+/// `©nam` for `b"\xa9nam"`: atoms are Latin-1
+#[cfg(feature = "mp4")]
+fn atom_name(atom: &[u8; 4]) -> String {
+    atom.iter().map(|&b| char::from(b)).collect()
+}
+
+fn unknown_key(key: &str, format: Format, own: &str) -> ProcessingError {
+    let fields: Vec<&str> = Field::ALL.iter().map(|field| field.as_str()).collect();
+    ProcessingError::InvalidConfig(format!(
+        "unknown {} tag '{}': use {} or {}",
+        format.as_str(),
+        key,
+        fields.join(", "),
+        own
+    ))
+}
+
+fn unsupported(format: Format) -> ProcessingError {
+    ProcessingError::UnsupportedFormat(format!("{} (tags can be edited in MP3, FLAC, MP4 and PNG)", format.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_keys() {
+        assert_eq!("Date".parse::<Field>().unwrap(), Field::Year);
+        assert_eq!(vorbis_field("track").unwrap(), "TRACKNUMBER");
+        assert_eq!(vorbis_field("label").unwrap(), "LABEL");
+        assert!(vorbis_field("a=b").is_err());
+        assert_eq!(png_keyword("artist").unwrap(), "Author");
+        assert_eq!(png_keyword("Software").unwrap(), "Software");
+        #[cfg(feature = "mp3")]
+        {
+            assert_eq!(id3_frame("tpe2").unwrap(), "TPE2");
+            assert!(id3_frame("APIC").unwrap_err().to_string().contains("title, artist"));
+        }
+        #[cfg(feature = "mp4")]
+        assert_eq!(&mp4_atom("©too").unwrap(), b"\xa9too");
+    }
+}
@@ -0,0 +1,103 @@
+//! Styling for what the inspectors and batch reports print: bold headings,
+//! aligned `label: value` fields, green/red safety markers and savings colored
+//! by how much was saved.
+//!
+//! Color is used when stdout is a terminal and `NO_COLOR` is unset (see
+//! <https://no-color.org>); `--no-color` sets `NO_COLOR` before anything prints.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// Width labels are padded to by [`field`]
+const LABEL_WIDTH: usize = 20;
+const RULE_WIDTH: usize = 55;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+    /// Bold, default color
+    Bold,
+    /// Faint, for separators and secondary details
+    Dim,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Cyan => "36",
+            Color::Bold => "1",
+            Color::Dim => "2",
+        }
+    }
+}
+
+/// Whether output is styled, decided once per process
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+        !no_color && !dumb && std::io::stdout().is_terminal()
+    })
+}
+
+/// `text` in `color`, or plain when styling is off
+pub fn paint(text: impl Display, color: Color) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Title of a whole inspection, underlined by a double rule
+pub fn heading(title: &str) {
+    println!("\n{}", paint(title, Color::Bold));
+    println!("{}\n", paint("═".repeat(RULE_WIDTH), Color::Dim));
+}
+
+/// Title of a section within an inspection
+pub fn section(title: &str) {
+    println!("{}", paint(title, Color::Cyan));
+    println!("{}", rule());
+}
+
+/// Thin separator line
+pub fn rule() -> String {
+    paint("─".repeat(RULE_WIDTH), Color::Dim)
+}
+
+/// `label:` indented by `indent` spaces and padded so values line up across
+/// indentation levels
+pub fn field(indent: usize, label: &str, value: impl Display) {
+    let width = LABEL_WIDTH.saturating_sub(indent);
+    let label = format!("{:<width$}", format!("{}:", label));
+    println!("{:indent$}{} {}", "", paint(label, Color::Dim), value);
+}
+
+/// `[SAFE]` in green or `[UNSAFE]` in red
+pub fn safety(safe: bool) -> String {
+    if safe {
+        paint("[SAFE]", Color::Green)
+    } else {
+        paint("[UNSAFE]", Color::Red)
+    }
+}
+
+/// `{pct:.1}%`, greener the more was saved; red when the output grew
+pub fn savings(pct: f64) -> String {
+    let text = format!("{:.1}%", pct);
+    match pct {
+        pct if pct >= 30.0 => paint(paint(text, Color::Green), Color::Bold),
+        pct if pct >= 5.0 => paint(text, Color::Green),
+        pct if pct >= 0.0 => paint(text, Color::Yellow),
+        _ => paint(text, Color::Red),
+    }
+}
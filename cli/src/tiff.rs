@@ -0,0 +1,226 @@
+//! TIFF files, read for conversion and inspection: the chain of pages (IFDs),
+//! each page's tags and the EXIF and GPS sub-IFDs they point to. Decoding goes
+//! through the image crate (`tiff` feature), which reads the first page only;
+//! [`select_page`] makes another page the first.
+//!
+//! Only classic TIFF is parsed. BigTIFF (64-bit offsets) still converts, page 1
+//! only, but shows no tags.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::error::ProcessingError;
+use crate::exif::{describe_value, tag_name, value_size, Directory, Entry as IfdEntry, Tiff};
+use crate::format::Format;
+use crate::inspect::{Entry, EntryClass, InspectionReport, Section};
+
+const IMAGE_WIDTH: u16 = 0x0100;
+const IMAGE_LENGTH: u16 = 0x0101;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+
+/// Page tags that describe the document or its author rather than the pixels
+const SENSITIVE_TAGS: [u16; 11] = [
+    0x010D, // DocumentName
+    0x010E, // ImageDescription
+    0x010F, // Make
+    0x0110, // Model
+    0x0131, // Software
+    0x0132, // DateTime
+    0x013B, // Artist
+    0x013C, // HostComputer
+    0x02BC, // XMP
+    0x8298, // Copyright
+    0x83BB, // IPTC
+];
+
+/// Metadata blocks a page can carry, by tag, as [`crate::metadata`] names them
+const METADATA_TAGS: [(u16, &str); 4] = [(0x02BC, "XMP"), (0x83BB, "IPTC"), (0x8649, "Photoshop"), (0x8773, "ICC")];
+
+/// Offsets of the pages' IFDs, in order. Empty for BigTIFF and non-TIFF data;
+/// a loop in the chain ends it.
+pub fn pages(data: &[u8]) -> Vec<usize> {
+    let Some(tiff) = Tiff::new(data) else {
+        return Vec::new();
+    };
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    let mut next = tiff.u32(4).map(|offset| offset as usize).filter(|&offset| offset != 0);
+    while let Some(ifd) = next.filter(|&ifd| seen.insert(ifd) && tiff.u16(ifd).is_some()) {
+        pages.push(ifd);
+        next = tiff.next_ifd(ifd);
+    }
+    pages
+}
+
+/// Width and height of the first page
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let tiff = Tiff::new(data)?;
+    let ifd = *pages(data).first()?;
+    Some((number(&tiff, &tiff.find(ifd, IMAGE_WIDTH)?)?, number(&tiff, &tiff.find(ifd, IMAGE_LENGTH)?)?))
+}
+
+/// `data` with page `page` (from 1) first, which is the page decoders read.
+/// Page 1 is `data` itself; the copy for another page differs only in the
+/// header's first-IFD offset.
+pub fn select_page(data: &[u8], page: u32) -> Result<Cow<'_, [u8]>, ProcessingError> {
+    if page <= 1 {
+        return Ok(Cow::Borrowed(data));
+    }
+    let pages = pages(data);
+    let ifd = *pages.get(page as usize - 1).ok_or_else(|| {
+        ProcessingError::InvalidConfig(format!("page {} requested, but the TIFF has {} page(s)", page, pages.len()))
+    })?;
+    let mut selected = data.to_vec();
+    let offset = if data.starts_with(b"MM") { (ifd as u32).to_be_bytes() } else { (ifd as u32).to_le_bytes() };
+    selected[4..8].copy_from_slice(&offset);
+    Ok(Cow::Owned(selected))
+}
+
+/// Metadata blocks of the first page: its EXIF and GPS directories and the
+/// XMP, IPTC, Photoshop and ICC tags, with their sizes
+pub(crate) fn metadata_blocks(data: &[u8]) -> Vec<(String, u64)> {
+    let (Some(tiff), Some(&ifd)) = (Tiff::new(data), pages(data).first()) else {
+        return Vec::new();
+    };
+    let mut blocks = Vec::new();
+    for (tag, name) in [(EXIF_IFD, "EXIF"), (GPS_IFD, "GPS")] {
+        if let Some(sub) = tiff.find(ifd, tag).and_then(|entry| tiff.u32(entry.value)) {
+            blocks.push((name.to_string(), directory_size(&tiff, sub as usize)));
+        }
+    }
+    for (tag, name) in METADATA_TAGS {
+        if let Some(entry) = tiff.find(ifd, tag) {
+            blocks.push((name.to_string(), value_size(&entry)));
+        }
+    }
+    blocks
+}
+
+/// `inspect` for TIFF: byte order, page count, then each page's tags with its
+/// EXIF and GPS entries, which are flagged as sensitive
+pub fn inspect_tiff(data: &[u8]) -> Result<InspectionReport, ProcessingError> {
+    let mut report = InspectionReport::new(Format::Tiff, data.len());
+    let Some(tiff) = Tiff::new(data) else {
+        let reason = if data.get(2..4) == Some(&[0x2B, 0]) || data.get(2..4) == Some(&[0, 0x2B]) {
+            "BigTIFF tags are not parsed"
+        } else {
+            "Invalid TIFF header"
+        };
+        report.errors.push(reason.to_string());
+        return Ok(report);
+    };
+    report.field("Byte order", if tiff.big_endian { "big-endian (MM)" } else { "little-endian (II)" });
+    let pages = pages(data);
+    report.field("Pages", pages.len());
+    if let Some((width, height)) = dimensions(data) {
+        report.field("Image dimensions", format!("{} x {} pixels", width, height));
+    }
+    if pages.is_empty() {
+        report.errors.push("No readable image file directory".to_string());
+    }
+
+    for (index, &ifd) in pages.iter().enumerate() {
+        let page = index + 1;
+        report.sections.push(directory_section(&tiff, ifd, Directory::Image, format!("Page {} Tags", page)));
+        for (tag, directory, title) in [(EXIF_IFD, Directory::Exif, "EXIF"), (GPS_IFD, Directory::Gps, "GPS")] {
+            if let Some(sub) = tiff.find(ifd, tag).and_then(|entry| tiff.u32(entry.value)) {
+                let title = format!("Page {} {} Tags (sensitive)", page, title);
+                report.sections.push(directory_section(&tiff, sub as usize, directory, title));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// One entry per tag of the directory at `ifd`
+fn directory_section(tiff: &Tiff, ifd: usize, directory: Directory, title: String) -> Section {
+    let mut section = Section::new(title);
+    for (tag, entry) in tiff.entries(ifd) {
+        let mut item = Entry::new(tag_name(directory, tag).map_or_else(|| format!("Tag 0x{:04X}", tag), String::from));
+        let sensitive = directory != Directory::Image || SENSITIVE_TAGS.contains(&tag);
+        item.class = Some(if sensitive { EntryClass::Unsafe } else { EntryClass::Safe });
+        item.size = Some(value_size(&entry));
+        item.field("Tag", format!("0x{:04X}", tag));
+        item.field("Value", describe_value(tiff, &entry));
+        if directory == Directory::Gps && matches!(tag, 0x0002 | 0x0004) {
+            item.warnings.push("Location of the capture".to_string());
+        }
+        section.entries.push(item);
+    }
+    let sensitive = section.entries.iter().filter(|entry| entry.class == Some(EntryClass::Unsafe)).count();
+    section.summary = Some(format!("{} tags, {} sensitive", section.entries.len(), sensitive));
+    section
+}
+
+/// Bytes a directory takes: its entry table plus values stored out of line
+fn directory_size(tiff: &Tiff, ifd: usize) -> u64 {
+    let entries: Vec<IfdEntry> = tiff.entries(ifd).map(|(_, entry)| entry).collect();
+    let table = 2 + 12 * entries.len() as u64 + 4;
+    table + entries.iter().map(value_size).filter(|&size| size > 4).sum::<u64>()
+}
+
+/// A single SHORT or LONG value
+fn number(tiff: &Tiff, entry: &IfdEntry) -> Option<u32> {
+    match entry.kind {
+        3 => tiff.u16(entry.value).map(u32::from),
+        4 => tiff.u32(entry.value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(tag: u16, kind: u16, count: u32, value: u32) -> Vec<u8> {
+        let mut entry = tag.to_le_bytes().to_vec();
+        entry.extend_from_slice(&kind.to_le_bytes());
+        entry.extend_from_slice(&count.to_le_bytes());
+        entry.extend_from_slice(&value.to_le_bytes());
+        entry
+    }
+
+    /// Two pages (4x2 and 6x3) without pixel data; page 1 points to a GPS directory
+    fn two_pages() -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        // Page 1 at 8: 3 entries, next IFD at 50
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend(entry(IMAGE_WIDTH, 3, 1, 4));
+        tiff.extend(entry(IMAGE_LENGTH, 3, 1, 2));
+        tiff.extend(entry(GPS_IFD, 4, 1, 80));
+        tiff.extend_from_slice(&50u32.to_le_bytes());
+        // Page 2 at 50: 2 entries, last page
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend(entry(IMAGE_WIDTH, 3, 1, 6));
+        tiff.extend(entry(IMAGE_LENGTH, 3, 1, 3));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        // GPS at 80: latitude reference only
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend(entry(0x0001, 2, 2, u32::from_le_bytes(*b"N\0\0\0")));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff
+    }
+
+    #[test]
+    fn test_pages() {
+        let tiff = two_pages();
+        assert_eq!(Format::from_bytes(&tiff), Some(Format::Tiff));
+        assert_eq!(pages(&tiff), [8, 50]);
+        assert_eq!(dimensions(&tiff), Some((4, 2)));
+        assert_eq!(dimensions(&select_page(&tiff, 2).unwrap()), Some((6, 3)));
+        assert!(matches!(select_page(&tiff, 1).unwrap(), Cow::Borrowed(_)));
+        assert!(select_page(&tiff, 3).unwrap_err().to_string().contains("has 2 page(s)"));
+
+        let blocks = metadata_blocks(&tiff);
+        assert_eq!(blocks, [("GPS".to_string(), 18)]);
+
+        let report = inspect_tiff(&tiff).unwrap();
+        let titles: Vec<&str> = report.sections.iter().map(|section| section.title.as_str()).collect();
+        assert_eq!(titles, ["Page 1 Tags", "Page 1 GPS Tags (sensitive)", "Page 2 Tags"]);
+        let gps = &report.sections[1].entries[0];
+        assert_eq!((gps.name.as_str(), gps.class), ("GPSLatitudeRef", Some(EntryClass::Unsafe)));
+        assert_eq!(gps.fields[1].value, "N");
+    }
+}
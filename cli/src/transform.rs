@@ -0,0 +1,249 @@
+//! Resizing: scale an image to a width, a height, a width × height box, a
+//! longest side or a percentage with a chosen resampling filter, then encode it
+//! in its own format, either plainly or through that format's optimizer on the
+//! same decode (what `resize --then-compress` runs).
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use crate::config::ProcessingConfig;
+use crate::converter::{compress_decoded, decode_source, encode_image_with_quality};
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::limits::Limits;
+use crate::outcome::ProcessOutcome;
+use crate::progress::{Progress, Stage};
+
+/// Resampling filter, fastest and blockiest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Filter {
+    /// Nearest neighbour: keeps hard pixel edges, for pixel art
+    Nearest,
+    /// Bilinear
+    Triangle,
+    /// Bicubic
+    CatmullRom,
+    Gaussian,
+    /// Sharpest for photos, and the slowest
+    #[default]
+    Lanczos3,
+}
+
+impl From<Filter> for FilterType {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// How a width × height box is filled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Fit {
+    /// Largest size inside the box that keeps the aspect ratio
+    #[default]
+    Contain,
+    /// Fill the box, keeping the aspect ratio, and crop the overflow around the center
+    Cover,
+    /// Stretch to the box
+    Exact,
+}
+
+/// Size to scale to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// This width, the height following the aspect ratio
+    Width(u32),
+    /// This height, the width following the aspect ratio
+    Height(u32),
+    /// A box filled according to [`Fit`]
+    Box { width: u32, height: u32 },
+    /// Longest side at most this many pixels; smaller images keep their size
+    MaxDimension(u32),
+    /// Both sides scaled by this percentage
+    Percent(f32),
+}
+
+/// What [`resize`] does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeOptions {
+    pub target: Target,
+    /// Only used for [`Target::Box`]
+    pub fit: Fit,
+    pub filter: Filter,
+}
+
+impl ResizeOptions {
+    /// `target` with the default fit (contain) and filter (Lanczos3)
+    pub fn new(target: Target) -> Self {
+        Self { target, fit: Fit::default(), filter: Filter::default() }
+    }
+
+    /// Size of the output for a `width` × `height` source
+    pub fn output_dimensions(&self, width: u32, height: u32) -> Result<(u32, u32), ProcessingError> {
+        let invalid = |what: String| Err(ProcessingError::InvalidConfig(format!("cannot resize to {}", what)));
+        let scale = |value: u32, factor: f64| {
+            let scaled = (value as f64 * factor).round();
+            if scaled > u32::MAX as f64 {
+                let message = format!("{} scaled by {} is too many pixels", value, factor);
+                return Err(ProcessingError::LimitExceeded(message));
+            }
+            Ok((scaled as u32).max(1))
+        };
+        match self.target {
+            Target::Width(0) | Target::Height(0) | Target::MaxDimension(0) => invalid("0 pixels".to_string()),
+            Target::Box { width: 0, .. } | Target::Box { height: 0, .. } => invalid("an empty box".to_string()),
+            Target::Percent(percent) if !(percent > 0.0 && percent.is_finite()) => invalid(format!("{}%", percent)),
+            Target::Width(target) => Ok((target, scale(height, target as f64 / width as f64)?)),
+            Target::Height(target) => Ok((scale(width, target as f64 / height as f64)?, target)),
+            Target::MaxDimension(max) if width.max(height) <= max => Ok((width, height)),
+            Target::MaxDimension(max) => {
+                let factor = max as f64 / width.max(height) as f64;
+                Ok((scale(width, factor)?, scale(height, factor)?))
+            }
+            Target::Percent(percent) => {
+                let factor = percent as f64 / 100.0;
+                Ok((scale(width, factor)?, scale(height, factor)?))
+            }
+            Target::Box { width: box_width, height: box_height } => match self.fit {
+                Fit::Cover | Fit::Exact => Ok((box_width, box_height)),
+                Fit::Contain => {
+                    let factor = (box_width as f64 / width as f64).min(box_height as f64 / height as f64);
+                    Ok((scale(width, factor)?.min(box_width), scale(height, factor)?.min(box_height)))
+                }
+            },
+        }
+    }
+}
+
+/// `img` scaled per `options`; a copy when the size would not change. A size
+/// over `limits` is an error rather than an allocation failure.
+pub fn resize(img: &DynamicImage, options: &ResizeOptions, limits: &Limits) -> Result<DynamicImage, ProcessingError> {
+    let (width, height) = options.output_dimensions(img.width(), img.height())?;
+    if (width, height) == (img.width(), img.height()) {
+        return Ok(img.clone());
+    }
+    limits.check_dimensions(width, height)?;
+    let filter = options.filter.into();
+    Ok(match (options.target, options.fit) {
+        (Target::Box { .. }, Fit::Cover) => img.resize_to_fill(width, height, filter),
+        _ => img.resize_exact(width, height, filter),
+    })
+}
+
+/// Decode `input` (a `format` image), resize it and encode it as `format` again.
+/// With `compress` the encode is the format's optimizer (quantize + oxipng for
+/// PNG, the WebP compressor for WebP) instead of a plain encode. Metadata does
+/// not survive the re-encode. The outcome carries the new dimensions and the
+/// lossy quality used.
+pub fn resize_image(
+    input: &[u8],
+    format: Format,
+    options: &ResizeOptions,
+    compress: bool,
+    config: &ProcessingConfig,
+    progress: &Progress,
+) -> Result<ProcessOutcome<'static>, ProcessingError> {
+    if !format.is_image() {
+        return Err(ProcessingError::UnsupportedFormat(format!("cannot resize {} files", format.as_str())));
+    }
+    progress.start(Stage::Decode);
+    let img = decode_source(input, config)?;
+    progress.finish(Stage::Decode);
+
+    progress.start(Stage::Resize);
+    let resized = resize(&img, options, &config.limits)?;
+    progress.finish(Stage::Resize);
+    log::debug!("Resized {}x{} to {}x{}", img.width(), img.height(), resized.width(), resized.height());
+
+    let (data, quality) = if compress {
+        compress_decoded(&resized, format, config, progress)?
+    } else {
+        progress.start(Stage::Encode);
+        let encoded = encode_image_with_quality(&resized, format, config)?;
+        progress.finish(Stage::Encode);
+        encoded
+    };
+    let mut outcome = ProcessOutcome::new(data, format);
+    outcome.dimensions = Some((resized.width(), resized.height()));
+    outcome.quality = quality;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_dimensions() {
+        let size = |target, fit| ResizeOptions { fit, ..ResizeOptions::new(target) }.output_dimensions(1200, 800);
+        assert_eq!(size(Target::Width(600), Fit::Contain).unwrap(), (600, 400));
+        assert_eq!(size(Target::Height(100), Fit::Contain).unwrap(), (150, 100));
+        assert_eq!(size(Target::MaxDimension(300), Fit::Contain).unwrap(), (300, 200));
+        assert_eq!(size(Target::MaxDimension(2000), Fit::Contain).unwrap(), (1200, 800));
+        assert_eq!(size(Target::Percent(25.0), Fit::Contain).unwrap(), (300, 200));
+        let square = Target::Box { width: 400, height: 400 };
+        assert_eq!(size(square, Fit::Contain).unwrap(), (400, 267));
+        assert_eq!(size(square, Fit::Cover).unwrap(), (400, 400));
+        assert_eq!(size(square, Fit::Exact).unwrap(), (400, 400));
+        assert!(size(Target::Width(0), Fit::Contain).is_err());
+        assert!(size(Target::Percent(f32::NAN), Fit::Contain).is_err());
+        assert!(matches!(size(Target::Percent(1e30), Fit::Contain), Err(ProcessingError::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn test_resize_image() {
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 32, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 8) as u8, 100])
+        }));
+        let mut png = Vec::new();
+        source.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        let options = ResizeOptions { fit: Fit::Cover, ..ResizeOptions::new(Target::Box { width: 16, height: 16 }) };
+        let config = ProcessingConfig::default();
+        let outcome = resize_image(&png, Format::Png, &options, true, &config, &Progress::none()).unwrap();
+        assert_eq!(outcome.dimensions, Some((16, 16)));
+        assert_eq!(image::load_from_memory(&outcome.data).unwrap().width(), 16);
+
+        // 100000% of 64x32 is 2 billion pixels: rejected before allocating
+        let huge = ResizeOptions::new(Target::Percent(100_000.0));
+        let result = resize_image(&png, Format::Png, &huge, false, &config, &Progress::none());
+        assert!(matches!(result, Err(ProcessingError::LimitExceeded(_))));
+    }
+
+    /// A 32x16 JPEG whose EXIF orientation is `orientation`
+    fn tagged_jpeg(orientation: u16) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90);
+        image::RgbImage::from_fn(32, 16, |x, _| image::Rgb([(x * 8) as u8, 0, 0])).write_with_encoder(encoder).unwrap();
+        let tiff = crate::exif::orientation_block(orientation);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        tagged.extend_from_slice(b"Exif\0\0");
+        tagged.extend_from_slice(&tiff);
+        tagged.extend_from_slice(&jpeg[2..]);
+        tagged
+    }
+
+    #[test]
+    fn test_resize_auto_orient() {
+        // Sized after turning upright: 8 wide is 16 high
+        let options = ResizeOptions::new(Target::Width(8));
+        let config = ProcessingConfig::default();
+        let outcome = resize_image(&tagged_jpeg(6), Format::Jpeg, &options, false, &config, &Progress::none());
+        assert_eq!(outcome.unwrap().dimensions, Some((8, 16)));
+
+        let config = ProcessingConfig { auto_orient: false, ..ProcessingConfig::default() };
+        let outcome = resize_image(&tagged_jpeg(6), Format::Jpeg, &options, false, &config, &Progress::none());
+        assert_eq!(outcome.unwrap().dimensions, Some((8, 4)));
+    }
+}
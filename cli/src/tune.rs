@@ -0,0 +1,173 @@
+//! Quality auto-tuning: search the encoder quality per image for the smallest
+//! output whose SSIM against the original still reaches `config.target_ssim`.
+//!
+//! SSIM is computed on luma (alpha-weighted, so fully transparent pixels compare
+//! equal) over 8×8 windows. A fixed quality number means different things for
+//! a screenshot and a photo; a similarity target does not.
+
+use image::DynamicImage;
+
+use crate::config::ProcessingConfig;
+use crate::error::ProcessingError;
+use crate::format::Format;
+
+const WINDOW: usize = 8;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Luma plane of the original, computed once and compared with every candidate
+pub struct SsimReference {
+    width: u32,
+    height: u32,
+    luma: Vec<f64>,
+}
+
+impl SsimReference {
+    pub fn new(image: &DynamicImage) -> Self {
+        Self { width: image.width(), height: image.height(), luma: luma(image) }
+    }
+
+    /// Mean SSIM of `candidate` against the reference, 1.0 for identical images.
+    /// A candidate of other dimensions scores 0.
+    pub fn compare(&self, candidate: &DynamicImage) -> f64 {
+        if (candidate.width(), candidate.height()) != (self.width, self.height) {
+            return 0.0;
+        }
+        ssim(&self.luma, &luma(candidate), self.width as usize, self.height as usize)
+    }
+}
+
+/// Alpha-weighted Rec. 601 luma of every pixel
+fn luma(image: &DynamicImage) -> Vec<f64> {
+    image
+        .to_rgba8()
+        .pixels()
+        .map(|p| {
+            let y = 0.299 * f64::from(p[0]) + 0.587 * f64::from(p[1]) + 0.114 * f64::from(p[2]);
+            y * f64::from(p[3]) / 255.0
+        })
+        .collect()
+}
+
+/// Mean SSIM over non-overlapping windows; images smaller than a window are one window
+fn ssim(a: &[f64], b: &[f64], width: usize, height: usize) -> f64 {
+    let (window_w, window_h) = (WINDOW.min(width), WINDOW.min(height));
+    if window_w == 0 || window_h == 0 {
+        return 1.0;
+    }
+    let mut total = 0.0;
+    let mut windows = 0usize;
+    for top in (0..=height - window_h).step_by(window_h) {
+        for left in (0..=width - window_w).step_by(window_w) {
+            let n = (window_w * window_h) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in top..top + window_h {
+                for x in left..left + window_w {
+                    let (va, vb) = (a[y * width + x], b[y * width + x]);
+                    sum_a += va;
+                    sum_b += vb;
+                    sum_aa += va * va;
+                    sum_bb += vb * vb;
+                    sum_ab += va * vb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// `config` with the quality used for `format` pinned to `quality`
+pub(crate) fn with_quality(config: &ProcessingConfig, format: Format, quality: u8) -> ProcessingConfig {
+    let mut config = config.clone();
+    match format {
+        Format::Png => config.png.quality = Some(quality),
+        Format::Jpeg => config.jpeg.quality = Some(quality),
+        Format::Webp => config.webp.quality = Some(quality),
+        _ => config.quality = quality,
+    }
+    config
+}
+
+/// Binary-search the lowest quality 0–100 whose output reaches `target` SSIM
+/// against `reference`, assuming similarity grows with quality. `encode` returns
+/// the encoded bytes and their decoded pixels. When even quality 100 misses the
+/// target, its output is used.
+pub(crate) fn tune_quality(
+    reference: &SsimReference,
+    target: f64,
+    mut encode: impl FnMut(u8) -> Result<(Vec<u8>, DynamicImage), ProcessingError>,
+) -> Result<(Vec<u8>, u8), ProcessingError> {
+    let (mut low, mut high) = (0u8, 100u8);
+    let mut best: Option<(Vec<u8>, u8)> = None;
+    let mut at_max: Option<Vec<u8>> = None;
+
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let (data, decoded) = encode(quality)?;
+        let score = reference.compare(&decoded);
+        log::debug!("quality {}: SSIM {:.4}, {} bytes", quality, score, data.len());
+
+        if score >= target {
+            best = Some((data, quality));
+            match quality.checked_sub(1) {
+                Some(below) => high = below,
+                None => break,
+            }
+        } else {
+            if quality == 100 {
+                at_max = Some(data);
+            }
+            low = quality + 1;
+        }
+    }
+
+    match best {
+        Some((data, quality)) => {
+            log::info!("Tuned quality to {} for SSIM {}", quality, target);
+            Ok((data, quality))
+        }
+        None => {
+            log::warn!("SSIM {} not reached even at quality 100", target);
+            let data = match at_max {
+                Some(data) => data,
+                None => encode(100)?.0,
+            };
+            Ok((data, 100))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssim_and_tuning() {
+        let original = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        }));
+        let reference = SsimReference::new(&original);
+        assert!((reference.compare(&original) - 1.0).abs() < 1e-9);
+        assert!(reference.compare(&DynamicImage::new_rgb8(64, 64)) < 0.5);
+        assert_eq!(reference.compare(&DynamicImage::new_rgb8(32, 32)), 0.0);
+
+        // Posterize harder at lower quality: the search finds the threshold
+        let posterize = |quality: u8| {
+            let step = 1 + (100 - u32::from(quality)) as u8;
+            let mut image = original.to_rgb8();
+            image.pixels_mut().for_each(|p| p.0 = p.0.map(|c| c / step * step));
+            Ok((vec![quality], DynamicImage::ImageRgb8(image)))
+        };
+        let (data, quality) = tune_quality(&reference, 0.95, posterize).unwrap();
+        assert_eq!(data, [quality]);
+        assert!(reference.compare(&posterize(quality).unwrap().1) >= 0.95);
+        assert!(quality == 0 || reference.compare(&posterize(quality - 1).unwrap().1) < 0.95);
+    }
+}
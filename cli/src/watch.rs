@@ -0,0 +1,265 @@
+//! `watch`: follow a directory with notify and hand back new or rewritten files
+//! once they have gone [`Debouncer`]'s delay without another change, so a file
+//! still being copied in is processed once, complete. Files the caller wrote
+//! (and files it already handled) are recognized by their contents and not
+//! handed back again, which keeps in-place runs from processing their own output.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::cache::Digest;
+use crate::cancel::CancelToken;
+use crate::error::ProcessingError;
+use crate::format::Format;
+use crate::io::IGNORE_FILE_NAME;
+
+/// Longest wait for an event before checking for cancellation
+const POLL: Duration = Duration::from_millis(200);
+
+/// Paths waiting for their changes to settle
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    delay: Duration,
+    /// Time of each path's latest change
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, pending: HashMap::new() }
+    }
+
+    /// Record a change to `path` at `now`, restarting its delay
+    pub fn push(&mut self, path: PathBuf, now: Instant) {
+        self.pending.insert(path, now);
+    }
+
+    /// Paths unchanged for the delay at `now`, sorted; they stop being pending
+    pub fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let delay = self.delay;
+        let mut ready: Vec<PathBuf> =
+            self.pending.iter().filter(|(_, &changed)| now >= changed + delay).map(|(path, _)| path.clone()).collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready.sort();
+        ready
+    }
+
+    /// When the first pending path becomes ready
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().min().map(|&changed| changed + self.delay)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// What to watch and which files count
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Watch subdirectories too
+    pub recursive: bool,
+    /// Quiet time before a changed file is handed back
+    pub debounce: Duration,
+    /// Formats handed back, by extension. Partial writes, `.bak` backups and
+    /// sidecars have no known extension, so they never qualify.
+    pub formats: Vec<Format>,
+    /// Gitignore-style patterns of paths to skip (`--ignore`)
+    pub ignore: Vec<String>,
+    /// Also apply the watched directory's `.gitignore` and `.ipignore`
+    pub ignore_files: bool,
+    /// Directory outputs are written to; when inside the watched directory its
+    /// files are never handed back
+    pub output_dir: Option<PathBuf>,
+}
+
+/// A watched directory
+pub struct Watcher {
+    /// Canonical path of the watched directory; handed-back paths start with it
+    root: PathBuf,
+    options: WatchOptions,
+    ignore: Gitignore,
+    debouncer: Debouncer,
+    /// Contents each path had when last handled or written
+    known: HashMap<PathBuf, Digest>,
+    events: Receiver<notify::Result<Event>>,
+    /// Kept alive for as long as events are wanted
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Start watching `dir`. `options.output_dir` is canonicalized too when it
+    /// exists, so compare outputs against [`Watcher::root`]-based paths.
+    pub fn new(dir: &Path, mut options: WatchOptions) -> Result<Self, ProcessingError> {
+        let root = dir.canonicalize().map_err(|source| ProcessingError::ReadFile { path: dir.to_path_buf(), source })?;
+        if !root.is_dir() {
+            return Err(ProcessingError::InvalidConfig(format!("{} is not a directory", dir.display())));
+        }
+        options.output_dir = options
+            .output_dir
+            .take()
+            .map(|output| output.canonicalize().unwrap_or(output))
+            .filter(|output| *output != root);
+
+        let mut builder = GitignoreBuilder::new(&root);
+        if options.ignore_files {
+            for name in [".gitignore", IGNORE_FILE_NAME] {
+                let path = root.join(name);
+                if let Some(e) = path.is_file().then(|| builder.add(&path)).flatten() {
+                    log::warn!("Could not read {}: {}", path.display(), e);
+                }
+            }
+        }
+        for pattern in &options.ignore {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| ProcessingError::InvalidConfig(format!("invalid ignore pattern '{}': {}", pattern, e)))?;
+        }
+        let ignore = builder.build().map_err(|e| ProcessingError::InvalidConfig(e.to_string()))?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+        let mode = if options.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(&root, mode).map_err(watch_error)?;
+
+        Ok(Self {
+            root,
+            debouncer: Debouncer::new(options.debounce),
+            options,
+            ignore,
+            known: HashMap::new(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Block until changed files are ready. None once `cancel` is cancelled or
+    /// the watcher has stopped.
+    pub fn next_batch(&mut self, cancel: &CancelToken) -> Option<Vec<PathBuf>> {
+        loop {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            let now = Instant::now();
+            let ready = self.debouncer.ready(now);
+            if !ready.is_empty() {
+                return Some(ready);
+            }
+            let wait = self.debouncer.next_deadline().map_or(POLL, |deadline| deadline.saturating_duration_since(now));
+            match self.events.recv_timeout(wait.min(POLL)) {
+                Ok(Ok(event)) => {
+                    for path in changed_paths(&event) {
+                        if self.accepts(path) {
+                            self.debouncer.push(path.clone(), Instant::now());
+                        }
+                    }
+                }
+                Ok(Err(e)) => log::warn!("Watch error: {}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Whether `path` is a file to hand back: under the root (directly unless
+    /// recursive), of a watched format, not ignored and not in the output directory
+    pub fn accepts(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if !self.options.recursive && relative.components().count() != 1 {
+            return false;
+        }
+        if self.options.output_dir.as_deref().is_some_and(|dir| path.starts_with(dir)) {
+            return false;
+        }
+        Format::from_path(path).is_some_and(|format| self.options.formats.contains(&format))
+            && !self.ignore.matched_path_or_any_parents(path, false).is_ignore()
+    }
+
+    /// Whether `data` is what `path` held when last [remembered](Watcher::remember)
+    pub fn is_known(&self, path: &Path, data: &[u8]) -> bool {
+        self.known.get(path) == Some(&Digest::of(data))
+    }
+
+    /// Remember `data` as the contents of `path`, handled or just written: the
+    /// events for it (or for touching it) then hand nothing back
+    pub fn remember(&mut self, path: &Path, data: &[u8]) {
+        self.known.insert(path.to_path_buf(), Digest::of(data));
+    }
+}
+
+/// Paths whose contents may have changed: created, written or renamed into place
+fn changed_paths(event: &Event) -> &[PathBuf] {
+    match event.kind {
+        EventKind::Create(_)
+        | EventKind::Modify(ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_))
+        | EventKind::Access(AccessKind::Close(AccessMode::Write)) => &event.paths,
+        _ => &[],
+    }
+}
+
+fn watch_error(e: notify::Error) -> ProcessingError {
+    ProcessingError::Io(std::io::Error::other(format!("cannot watch: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut debouncer = Debouncer::new(ms(500));
+        debouncer.push(PathBuf::from("b.png"), start);
+        debouncer.push(PathBuf::from("a.png"), start + ms(100));
+        assert_eq!(debouncer.next_deadline(), Some(start + ms(500)));
+        // Still being written: the delay starts over
+        debouncer.push(PathBuf::from("b.png"), start + ms(400));
+        assert!(debouncer.ready(start + ms(550)).is_empty());
+        assert_eq!(debouncer.ready(start + ms(600)), vec![PathBuf::from("a.png")]);
+        assert_eq!(debouncer.ready(start + ms(900)), vec![PathBuf::from("b.png")]);
+        assert!(debouncer.is_empty());
+    }
+
+    #[test]
+    fn test_accepts() {
+        let dir = std::env::temp_dir().join(format!("ip_watch_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        std::fs::write(dir.join(IGNORE_FILE_NAME), "drafts/\n").unwrap();
+        let options = WatchOptions {
+            recursive: true,
+            debounce: Duration::from_millis(10),
+            formats: vec![Format::Png, Format::Jpeg],
+            ignore: vec!["*.tmp.png".to_string()],
+            ignore_files: true,
+            output_dir: Some(dir.join("out")),
+        };
+        let watcher = Watcher::new(&dir, options).unwrap();
+        let root = watcher.root().to_path_buf();
+
+        assert!(watcher.accepts(&root.join("photo.png")));
+        assert!(watcher.accepts(&root.join("sub/photo.jpg")));
+        assert!(!watcher.accepts(&root.join("clip.mp4")));
+        assert!(!watcher.accepts(&root.join(".photo.png.123-0.partial")));
+        assert!(!watcher.accepts(&root.join("photo.png.bak")));
+        assert!(!watcher.accepts(&root.join("out/photo.png")));
+        assert!(!watcher.accepts(&root.join("drafts/photo.png")));
+        assert!(!watcher.accepts(&root.join("photo.tmp.png")));
+        assert!(!watcher.accepts(Path::new("/elsewhere/photo.png")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+//! Waveform images of audio files, for podcast players and audio previews.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::config::ProcessingConfig;
+use crate::converter::encode_image;
+use crate::error::ProcessingError;
+#[cfg(feature = "ffmpeg")]
+use crate::ffmpeg::{self, read_ffmpeg_result, FfmpegTempFiles};
+use crate::format::Format;
+use crate::processor::png::PngProcessor;
+use crate::processor::wav::wav_peaks;
+use crate::processor::ImageProcessor;
+
+/// Sample rate audio is decoded at by ffmpeg; plenty for one peak per pixel column
+#[cfg(feature = "ffmpeg")]
+const DECODE_RATE: u32 = 8000;
+
+/// Size and colors of a rendered waveform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaveformOptions {
+    pub width: u32,
+    pub height: u32,
+    pub color: Rgba<u8>,
+    /// Transparent by default
+    pub background: Rgba<u8>,
+}
+
+impl Default for WaveformOptions {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 200,
+            color: Rgba([0x3f, 0xb9, 0x50, 0xff]),
+            background: Rgba([0, 0, 0, 0]),
+        }
+    }
+}
+
+/// Parse `#rrggbb` or `#rrggbbaa` (the `#` is optional)
+pub fn parse_color(s: &str) -> Option<Rgba<u8>> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    let alpha = if hex.len() == 8 { channel(6)? } else { 0xff };
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+/// Render the waveform of an MP3, WAV or FLAC file as a PNG, optimized by the
+/// PNG pipeline. WAV is read directly; other formats are decoded by ffmpeg.
+pub fn render_waveform(
+    input: &[u8],
+    format: Format,
+    options: &WaveformOptions,
+    config: &ProcessingConfig,
+) -> Result<Vec<u8>, ProcessingError> {
+    if options.width == 0 || options.height == 0 {
+        return Err(ProcessingError::InvalidConfig("waveform width and height must be non-zero".to_string()));
+    }
+    config.limits.check_input(input.len())?;
+    config.limits.check_dimensions(options.width, options.height)?;
+
+    let columns = options.width as usize;
+    let peaks = match format {
+        Format::Wav => match wav_peaks(input, columns)? {
+            Some(peaks) => peaks,
+            None => decoded_peaks(input, format, columns)?,
+        },
+        Format::Mp3 | Format::Flac | Format::Opus | Format::Ogg | Format::Mp4 | Format::Webm => {
+            decoded_peaks(input, format, columns)?
+        }
+        other => {
+            return Err(ProcessingError::UnsupportedFormat(format!(
+                "cannot draw a waveform of {}",
+                other.as_str()
+            )))
+        }
+    };
+
+    let image = draw_waveform(&peaks, options);
+    let png = encode_image(&DynamicImage::ImageRgba8(image), Format::Png, config)?;
+    PngProcessor.process(&png, config)
+}
+
+/// Mirrored bars around the center line, one per peak; silence still shows as a
+/// one pixel line
+fn draw_waveform(peaks: &[f32], options: &WaveformOptions) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(options.width, options.height, options.background);
+    let center = options.height as f32 / 2.0;
+    for (x, &peak) in peaks.iter().enumerate().take(options.width as usize) {
+        let half = peak.clamp(0.0, 1.0) * center;
+        let top = (center - half).floor().max(0.0) as u32;
+        let bottom = ((center + half).ceil() as u32).clamp(top + 1, options.height);
+        for y in top..bottom {
+            image.put_pixel(x as u32, y, options.color);
+        }
+    }
+    image
+}
+
+/// Peak of each of `columns` equal slices of a mono mix decoded by ffmpeg
+#[cfg(feature = "ffmpeg")]
+fn decoded_peaks(input: &[u8], format: Format, columns: usize) -> Result<Vec<f32>, ProcessingError> {
+    if !crate::processor::is_ffmpeg_available() {
+        return Err(ProcessingError::FfmpegMissing);
+    }
+
+    let temp = FfmpegTempFiles::with_extensions(format.extension(), "pcm");
+    std::fs::write(&temp.input, input)
+        .map_err(|e| ProcessingError::Encode(format!("Failed to write temp input: {}", e)))?;
+
+    let mut cmd = ffmpeg::command();
+    cmd.arg("-i").arg(&temp.input);
+    cmd.args(["-y", "-map", "0:a:0", "-ac", "1", "-ar", &DECODE_RATE.to_string(), "-f", "s16le"]);
+    cmd.arg(&temp.output);
+
+    log::debug!("Executing: ffmpeg {:?}", cmd.get_args().collect::<Vec<_>>());
+    let output = ffmpeg::run(&mut cmd)?;
+    let pcm = read_ffmpeg_result(output, &temp, input.len())?;
+
+    let samples = pcm.len() / 2;
+    let mut peaks = vec![0f32; columns];
+    if samples == 0 {
+        return Ok(peaks);
+    }
+    for (i, sample) in pcm.chunks_exact(2).enumerate() {
+        let amplitude = f32::from(i16::from_le_bytes([sample[0], sample[1]])).abs() / 32_768.0;
+        let column = &mut peaks[i * columns / samples];
+        *column = column.max(amplitude);
+    }
+    Ok(peaks)
+}
+
+/// Never called: `is_ffmpeg_available` is false without the `ffmpeg` feature
+#[cfg(not(feature = "ffmpeg"))]
+fn decoded_peaks(_input: &[u8], _format: Format, _columns: usize) -> Result<Vec<f32>, ProcessingError> {
+    Err(ProcessingError::FfmpegMissing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#3fb950"), Some(Rgba([0x3f, 0xb9, 0x50, 0xff])));
+        assert_eq!(parse_color("00000080"), Some(Rgba([0, 0, 0, 0x80])));
+        assert_eq!(parse_color("#3fb95"), None);
+        assert_eq!(parse_color("#zzzzzz"), None);
+    }
+
+    #[test]
+    fn test_render_wav_waveform() {
+        // Mono 16-bit: silent first half, full scale second half
+        let mut samples = vec![0i16; 100];
+        samples.extend(std::iter::repeat_n(i16::MAX, 100));
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        wav.extend_from_slice(&[1, 0, 1, 0]);
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16_000u32.to_le_bytes());
+        wav.extend_from_slice(&[2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&400u32.to_le_bytes());
+        wav.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+
+        let options = WaveformOptions { width: 20, height: 10, ..WaveformOptions::default() };
+        let png = render_waveform(&wav, Format::Wav, &options, &ProcessingConfig::default()).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (20, 10));
+        // Silence is a single center line, full scale fills the column
+        let filled = |x: u32| (0..10).filter(|&y| image.get_pixel(x, y)[3] > 0).count();
+        assert_eq!(filled(0), 1);
+        assert_eq!(filled(19), 10);
+    }
+}
@@ -1,34 +1,91 @@
 use axum::{
-    extract::Multipart,
-    http::{StatusCode, header},
+    body::Body,
+    extract::{Multipart, State},
+    http::{HeaderName, StatusCode, header},
     response::{IntoResponse, Response, Json},
 };
 use serde::Serialize;
-use std::io::Write as IoWrite;
-use tempfile::NamedTempFile;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::upload::Upload;
 
 // Re-export from CLI library
 use image_preparer::config::{ProcessingConfig, StripMode};
-use image_preparer::converter::{ConvertFormat, convert_image};
-use image_preparer::format::ImageFormat;
+use image_preparer::converter::convert_image;
+use image_preparer::error::ProcessingError;
+use image_preparer::format::Format;
+use image_preparer::inspect::{self as inspection, InspectionReport};
+use image_preparer::limits::Limits;
+use image_preparer::metadata::{metadata_blocks, metadata_blocks_in_file, removed_blocks, MetadataBlock};
+use image_preparer::outcome::ProcessOutcome;
 use image_preparer::pipeline::Pipeline;
-use image_preparer::processor::png::PngProcessor;
-use image_preparer::processor::webp::WebpProcessor;
-use image_preparer::processor::mp3::Mp3Processor;
-use image_preparer::processor::mp4::Mp4Processor;
+use image_preparer::report::savings_pct;
+use image_preparer::processor::mp4::{extract_frames, video_dimensions, FrameExtraction, FrameSelection};
 
 #[derive(Debug, Serialize)]
 struct ApiResponse<T> {
     success: bool,
     data: Option<T>,
     error: Option<String>,
+    /// Machine-readable error code, see `ProcessingError::code`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+/// Limits for untrusted uploads, stricter than the library defaults
+pub const UPLOAD_LIMITS: Limits = Limits {
+    max_pixels: Some(50_000_000),
+    max_alloc: Some(256 * 1024 * 1024),
+    max_input_size: Some(200 * 1024 * 1024),
+};
+
+/// Settings shared by the handlers
+pub struct AppState {
+    /// Processing defaults for form fields left out, from image-preparer.toml
+    pub defaults: ProcessingConfig,
+    /// Largest request body accepted, `IP_MAX_UPLOAD`
+    pub max_upload: u64,
+}
+
+impl AppState {
+    /// [`UPLOAD_LIMITS`] with the input size capped at the upload limit
+    fn limits(&self) -> Limits {
+        Limits { max_input_size: Some(self.max_upload), ..UPLOAD_LIMITS }
+    }
+}
+
+/// JSON error body with an HTTP status matching the error category
+fn error_response(e: &ProcessingError) -> Response {
+    let status = match e {
+        ProcessingError::UnsupportedFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ProcessingError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+        ProcessingError::Decode(_) | ProcessingError::CorruptInput { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        ProcessingError::LimitExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        ProcessingError::FfmpegMissing | ProcessingError::FfmpegEncoderMissing(_) | ProcessingError::Cancelled => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        ProcessingError::FfmpegTimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let response = ApiResponse::<()> {
+        success: false,
+        data: None,
+        error: Some(e.to_string()),
+        code: Some(e.code()),
+    };
+    (status, Json(response)).into_response()
 }
 
 #[derive(Debug, Serialize)]
 struct InspectResult {
     format: String,
     size: u64,
-    metadata: serde_json::Value,
+    metadata: InspectionReport,
 }
 
 /// POST /compress
@@ -41,36 +98,175 @@ struct InspectResult {
 /// - speed (optional): 1-10 (default: 3)
 /// - no_lossy (optional): true/false (default: false)
 /// - strip (optional): all/safe/none (default: all)
-pub async fn compress(mut multipart: Multipart) -> Result<Response, StatusCode> {
-    let mut file_data: Option<Vec<u8>> = None;
-    let mut quality = 80u8;
-    let mut speed = 3i32;
-    let mut no_lossy = false;
-    let mut strip = StripMode::All;
+///
+/// Defaults come from image-preparer.toml when the server found one.
+pub async fn compress(State(state): State<Arc<AppState>>, multipart: Multipart) -> Result<Response, StatusCode> {
+    let (upload, format, config) = compress_form(&state, multipart).await?;
+
+    // Build pipeline
+    let pipeline = Arc::new(Pipeline::with_default_processors());
+
+    // Process file off the async runtime threads; streamable files (MP4 always,
+    // spooled on to ffmpeg's temp file) go through the processor without being read in whole
+    let result = if pipeline.supports_streaming(format, &config) {
+        let path = upload.path().to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<Vec<u8>, ProcessingError> {
+            let mut input = std::fs::File::open(&path)?;
+            let mut output = Vec::new();
+            pipeline.process_stream(format, &mut input, &mut output, &config)?;
+            Ok(output)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    } else {
+        pipeline.process_bytes_async(upload.read().await?, format, config).await
+    };
+
+    match result {
+        Ok(compressed) => {
+            Ok((
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, format.mime_type())],
+                compressed,
+            ).into_response())
+        }
+        Err(e) => Ok(error_response(&e)),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EstimateResult {
+    format: String,
+    original_size: u64,
+    estimated_size: u64,
+    savings_pct: f64,
+    /// Processing would leave the file as it is
+    unchanged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<u8>,
+    /// Metadata bytes removed, when the processor can tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata_removed: Option<u64>,
+    /// Metadata blocks the output no longer has
+    stripped: Vec<MetadataBlock>,
+    warnings: Vec<String>,
+}
+
+impl EstimateResult {
+    fn new(original_size: u64, before: &[MetadataBlock], outcome: ProcessOutcome, unchanged: bool) -> Self {
+        let estimated_size = outcome.data.len() as u64;
+        let savings_pct = savings_pct(original_size, estimated_size);
+        Self {
+            format: outcome.format.as_str().to_string(),
+            original_size,
+            estimated_size,
+            savings_pct: (savings_pct * 100.0).round() / 100.0,
+            unchanged,
+            quality: outcome.quality,
+            metadata_removed: outcome.metadata_removed,
+            stripped: removed_blocks(before, &metadata_blocks(&outcome.data, outcome.format)),
+            warnings: outcome.warnings,
+        }
+    }
+}
+
+/// Whether the file at `path` holds exactly `data`, read in chunks
+fn same_contents(path: &Path, data: &[u8]) -> std::io::Result<bool> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = [0u8; 64 * 1024];
+    let mut rest = data;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(rest.is_empty());
+        }
+        if rest.len() < n || rest[..n] != buf[..n] {
+            return Ok(false);
+        }
+        rest = &rest[n..];
+    }
+}
+
+/// POST /estimate
+///
+/// Run compression and report the result without returning the file. Streamable
+/// files (MP4) are processed from the spooled upload like /compress does, and
+/// report no quality, metadata_removed or warnings.
+///
+/// Form fields: same as /compress
+pub async fn estimate(State(state): State<Arc<AppState>>, multipart: Multipart) -> Result<Response, StatusCode> {
+    let (upload, format, config) = compress_form(&state, multipart).await?;
+    let original_size = upload.size();
+    let pipeline = Arc::new(Pipeline::with_default_processors());
+
+    // Streamable files are estimated from the spooled upload, as /compress processes them
+    let result = if pipeline.supports_streaming(format, &config) {
+        let path = upload.path().to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<EstimateResult, ProcessingError> {
+            let before = metadata_blocks_in_file(&path, format)?;
+            let mut output = Vec::new();
+            pipeline.process_stream(format, &mut std::fs::File::open(&path)?, &mut output, &config)?;
+            let unchanged = output.len() as u64 == original_size && same_contents(&path, &output)?;
+            Ok(EstimateResult::new(original_size, &before, ProcessOutcome::new(output, format), unchanged))
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    } else {
+        let data = upload.read().await?;
+        let before = metadata_blocks(&data, format);
+        pipeline.process_bytes_with_stats_async(data, format, config).await.map(|outcome| {
+            let unchanged = outcome.is_unchanged();
+            EstimateResult::new(original_size, &before, outcome, unchanged)
+        })
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return Ok(error_response(&e)),
+    };
+
+    let response = ApiResponse {
+        success: true,
+        data: Some(result),
+        error: None,
+        code: None,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Upload, detected format and config from a /compress or /estimate form;
+/// fields left out keep their value in the config file's defaults
+async fn compress_form(
+    state: &AppState,
+    mut multipart: Multipart,
+) -> Result<(Upload, Format, ProcessingConfig), StatusCode> {
+    let defaults = &state.defaults;
+    let mut upload: Option<Upload> = None;
+    let mut quality = defaults.quality;
+    let mut speed = defaults.speed;
+    let mut no_lossy = defaults.no_lossy;
+    let mut strip = defaults.strip;
 
     // Parse multipart form
     loop {
         let field = match multipart.next_field().await {
             Ok(Some(f)) => f,
             Ok(None) => break,
-            Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => return Err(e.status()),
         };
 
         let name = field.name().unwrap_or("").to_string();
 
         match name.as_str() {
-            "file" => {
-                let bytes = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-                file_data = Some(bytes.to_vec());
-            }
+            "file" => upload = Some(Upload::receive(field).await?),
             "quality" => {
                 if let Ok(text) = field.text().await {
-                    quality = text.parse::<u8>().unwrap_or(80).clamp(0, 100);
+                    quality = text.parse::<u8>().unwrap_or(defaults.quality).clamp(0, 100);
                 }
             }
             "speed" => {
                 if let Ok(text) = field.text().await {
-                    speed = text.parse::<i32>().unwrap_or(3).clamp(1, 10);
+                    speed = text.parse::<i32>().unwrap_or(defaults.speed).clamp(1, 10);
                 }
             }
             "no_lossy" => {
@@ -81,9 +277,10 @@ pub async fn compress(mut multipart: Multipart) -> Result<Response, StatusCode>
             "strip" => {
                 if let Ok(text) = field.text().await {
                     strip = match text.as_str() {
+                        "all" => StripMode::All,
                         "safe" => StripMode::Safe,
                         "none" => StripMode::None,
-                        _ => StripMode::All,
+                        _ => defaults.strip,
                     };
                 }
             }
@@ -91,21 +288,8 @@ pub async fn compress(mut multipart: Multipart) -> Result<Response, StatusCode>
         }
     }
 
-    let data = file_data.ok_or(StatusCode::BAD_REQUEST)?;
-
-    // Create temp file to detect format
-    let mut temp_file = NamedTempFile::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    temp_file.write_all(&data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let _format = ImageFormat::from_path(temp_file.path())
-        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
-
-    // Build pipeline
-    let mut pipeline = Pipeline::new();
-    pipeline.register(Box::new(PngProcessor));
-    pipeline.register(Box::new(WebpProcessor));
-    pipeline.register(Box::new(Mp3Processor));
-    pipeline.register(Box::new(Mp4Processor));
+    let upload = upload.ok_or(StatusCode::BAD_REQUEST)?;
+    let format = upload.format().ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
 
     // Create config
     let config = ProcessingConfig {
@@ -115,28 +299,11 @@ pub async fn compress(mut multipart: Multipart) -> Result<Response, StatusCode>
         strip,
         dry_run: false,
         backup: false,
-        extract_frames: false,
-        fps: 0.0,
+        limits: state.limits(),
+        ..defaults.clone()
     };
 
-    // Process file
-    match pipeline.process_file(temp_file.path(), &data, &config) {
-        Ok(compressed) => {
-            Ok((
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, "application/octet-stream")],
-                compressed,
-            ).into_response())
-        }
-        Err(e) => {
-            let response = ApiResponse::<()> {
-                success: false,
-                data: None,
-                error: Some(e.to_string()),
-            };
-            Ok(Json(response).into_response())
-        }
-    }
+    Ok((upload, format, config))
 }
 
 /// POST /convert
@@ -148,34 +315,33 @@ pub async fn compress(mut multipart: Multipart) -> Result<Response, StatusCode>
 /// - to: target format (png, jpg, jpeg, webp)
 /// - quality (optional): 0-100 (default: 80)
 /// - no_lossy (optional): true/false (default: false)
-pub async fn convert(mut multipart: Multipart) -> Result<Response, StatusCode> {
-    let mut file_data: Option<Vec<u8>> = None;
+///
+/// Defaults come from image-preparer.toml when the server found one.
+pub async fn convert(State(state): State<Arc<AppState>>, mut multipart: Multipart) -> Result<Response, StatusCode> {
+    let mut upload: Option<Upload> = None;
     let mut target_format: Option<String> = None;
-    let mut quality = 80u8;
-    let mut no_lossy = false;
+    let mut quality = state.defaults.quality;
+    let mut no_lossy = state.defaults.no_lossy;
 
     // Parse multipart form
     loop {
         let field = match multipart.next_field().await {
             Ok(Some(f)) => f,
             Ok(None) => break,
-            Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => return Err(e.status()),
         };
 
         let name = field.name().unwrap_or("").to_string();
 
         match name.as_str() {
-            "file" => {
-                let bytes = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-                file_data = Some(bytes.to_vec());
-            }
+            "file" => upload = Some(Upload::receive(field).await?),
             "to" => {
                 let text = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
                 target_format = Some(text);
             }
             "quality" => {
                 if let Ok(text) = field.text().await {
-                    quality = text.parse::<u8>().unwrap_or(80).clamp(0, 100);
+                    quality = text.parse::<u8>().unwrap_or(state.defaults.quality).clamp(0, 100);
                 }
             }
             "no_lossy" => {
@@ -187,92 +353,90 @@ pub async fn convert(mut multipart: Multipart) -> Result<Response, StatusCode> {
         }
     }
 
-    let data = file_data.ok_or(StatusCode::BAD_REQUEST)?;
+    let upload = upload.ok_or(StatusCode::BAD_REQUEST)?;
     let target_format_str = target_format.ok_or(StatusCode::BAD_REQUEST)?;
+    // Like /compress, judge the upload by its content
+    upload.format().ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
 
-    let target_format = ConvertFormat::from_str(&target_format_str)
+    let target_format = target_format_str
+        .parse::<Format>()
+        .ok()
+        .filter(Format::is_image)
         .ok_or(StatusCode::BAD_REQUEST)?;
 
     // Create config
     let config = ProcessingConfig {
         quality,
-        speed: 3,
         no_lossy,
-        strip: StripMode::All,
         dry_run: false,
         backup: false,
-        extract_frames: false,
-        fps: 0.0,
+        limits: state.limits(),
+        ..state.defaults.clone()
     };
 
-    // Convert
-    match convert_image(&data, target_format, &config) {
+    // Convert off the async runtime threads
+    let data = upload.read().await?;
+    let converted = tokio::task::spawn_blocking(move || convert_image(&data, target_format, &config))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match converted {
         Ok(converted) => {
             Ok((
                 StatusCode::OK,
-                [(header::CONTENT_TYPE, "application/octet-stream")],
+                [(header::CONTENT_TYPE, target_format.mime_type())],
                 converted,
             ).into_response())
         }
-        Err(e) => {
-            let response = ApiResponse::<()> {
-                success: false,
-                data: None,
-                error: Some(e.to_string()),
-            };
-            Ok(Json(response).into_response())
-        }
+        Err(e) => Ok(error_response(&e)),
     }
 }
 
 /// POST /inspect
 ///
-/// View file metadata.
+/// View file metadata: the same report as `image_preparer inspect`, as JSON.
+/// PNG, WebP, MP3 and MP4 are supported.
 ///
 /// Form fields:
 /// - file: binary file data
 pub async fn inspect(mut multipart: Multipart) -> Result<Response, StatusCode> {
-    let mut file_data: Option<Vec<u8>> = None;
+    let mut upload: Option<Upload> = None;
 
     // Parse multipart form
     loop {
         let field = match multipart.next_field().await {
             Ok(Some(f)) => f,
             Ok(None) => break,
-            Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => return Err(e.status()),
         };
 
         if field.name() == Some("file") {
-            let bytes = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-            file_data = Some(bytes.to_vec());
+            upload = Some(Upload::receive(field).await?);
             break;
         }
     }
 
-    let data = file_data.ok_or(StatusCode::BAD_REQUEST)?;
-    let size = data.len() as u64;
-
-    // Create temp file to detect format
-    let mut temp_file = NamedTempFile::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    temp_file.write_all(&data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let upload = upload.ok_or(StatusCode::BAD_REQUEST)?;
+    let size = upload.size();
+    let format = upload.format().ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    let data = upload.read().await?;
 
-    let format = ImageFormat::from_path(temp_file.path())
-        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
-
-    // For now, return basic info
-    // TODO: Implement proper metadata extraction for each format
+    // MP4 inspection runs ffprobe, so keep it off the async runtime threads
+    let report = match tokio::task::spawn_blocking(move || inspection::inspect(&data, format)).await {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) => return Ok(error_response(&e)),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
     let result = InspectResult {
         format: format.as_str().to_string(),
         size,
-        metadata: serde_json::json!({
-            "note": "Detailed metadata extraction coming soon"
-        }),
+        metadata: report,
     };
 
     let response = ApiResponse {
         success: true,
         data: Some(result),
         error: None,
+        code: None,
     };
 
     Ok(Json(response).into_response())
@@ -280,49 +444,130 @@ pub async fn inspect(mut multipart: Multipart) -> Result<Response, StatusCode> {
 
 /// POST /extract
 ///
-/// Extract frames from MP4 video.
+/// Extract frames from an MP4 video as PNG images, returned as a ZIP archive
+/// of `frame_0001.png`, `frame_0002.png`, ... The `X-Frame-Count` header
+/// holds the number of frames.
+///
+/// At most [`MAX_FRAMES`] frames are extracted, fewer for large videos (see
+/// [`MAX_FRAME_PIXELS`]); frames over the pixel limit are rejected.
 ///
 /// Form fields:
 /// - file: binary MP4 file
 /// - fps (optional): frames per second (default: 1, 0=all frames)
-pub async fn extract(mut multipart: Multipart) -> Result<Response, StatusCode> {
-    let mut file_data: Option<Vec<u8>> = None;
-    let mut _fps = 1.0f32;
+pub async fn extract(State(state): State<Arc<AppState>>, mut multipart: Multipart) -> Result<Response, StatusCode> {
+    let mut upload: Option<Upload> = None;
+    let mut fps = 1.0f32;
 
     // Parse multipart form
     loop {
         let field = match multipart.next_field().await {
             Ok(Some(f)) => f,
             Ok(None) => break,
-            Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => return Err(e.status()),
         };
 
         let name = field.name().unwrap_or("").to_string();
 
         match name.as_str() {
-            "file" => {
-                let bytes = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-                file_data = Some(bytes.to_vec());
-            }
+            "file" => upload = Some(Upload::receive(field).await?),
             "fps" => {
-                if let Ok(text) = field.text().await {
-                    _fps = text.parse::<f32>().unwrap_or(1.0);
-                }
+                let text = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                fps = text
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|fps| fps.is_finite() && *fps >= 0.0)
+                    .ok_or(StatusCode::BAD_REQUEST)?;
             }
             _ => {}
         }
     }
 
-    let _data = file_data.ok_or(StatusCode::BAD_REQUEST)?;
+    let upload = upload.ok_or(StatusCode::BAD_REQUEST)?;
+    if upload.format() != Some(Format::Mp4) {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
 
-    // TODO: Implement frame extraction
-    // This requires saving temp files and using extract_frames_to_png from CLI
+    let limits = state.limits();
+    if let Err(e) = limits.check_input(upload.size() as usize) {
+        return Ok(error_response(&e));
+    }
 
-    let response = ApiResponse::<()> {
-        success: false,
-        data: None,
-        error: Some("Frame extraction not yet implemented for web API".to_string()),
+    // ffmpeg and the archive both work on files, off the async runtime threads
+    let input = upload.path().to_path_buf();
+    let archived = tokio::task::spawn_blocking(move || {
+        let max_frames = frame_cap(&input, &limits)?;
+        let options = FrameExtraction {
+            selection: FrameSelection::Fps(fps),
+            max_frames: Some(max_frames),
+            ..FrameExtraction::default()
+        };
+        frames_archive(&input, &options)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (archive, frames) = match archived {
+        Ok(archived) => archived,
+        Err(e) => return Ok(error_response(&e)),
     };
 
-    Ok(Json(response).into_response())
+    let size = archive.metadata().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.len();
+    let body = Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(archive)));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"frames.zip\"".to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (HeaderName::from_static("x-frame-count"), frames.to_string()),
+        ],
+        body,
+    ).into_response())
+}
+
+/// Most frames one /extract request returns
+const MAX_FRAMES: u32 = 1_000;
+
+/// Most pixels across the frames one /extract request returns: 1000 frames of 1080p
+const MAX_FRAME_PIXELS: u64 = 1_000 * 1920 * 1080;
+
+/// How many frames of the video at `input` /extract may return: [`MAX_FRAMES`],
+/// fewer when they would add up to more than [`MAX_FRAME_PIXELS`]. Frames over
+/// `limits` fail. A header that cannot be read is left for ffmpeg to reject.
+fn frame_cap(input: &Path, limits: &Limits) -> Result<u32, ProcessingError> {
+    let Some((width, height)) = video_dimensions(input) else {
+        return Ok(MAX_FRAMES);
+    };
+    limits.check_dimensions(width, height)?;
+    let pixels = (u64::from(width) * u64::from(height)).max(1);
+    Ok((MAX_FRAME_PIXELS / pixels).clamp(1, u64::from(MAX_FRAMES)) as u32)
+}
+
+/// The frames of the video at `input`, as a ZIP archive in an anonymous temp
+/// file (gone once closed) rewound for reading, and their count
+fn frames_archive(input: &Path, options: &FrameExtraction) -> Result<(std::fs::File, usize), ProcessingError> {
+    let dir = tempfile::tempdir()?;
+    extract_frames(input, dir.path(), options)?;
+
+    // extract_frames writes into one `{name}_frames` directory
+    let mut frames = Vec::new();
+    for entry in std::fs::read_dir(dir.path())? {
+        for frame in std::fs::read_dir(entry?.path())? {
+            frames.push(frame?.path());
+        }
+    }
+    frames.sort();
+
+    let archive_error = |e: zip::result::ZipError| ProcessingError::Encode(format!("cannot write the archive: {}", e));
+    // PNG frames are compressed already
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(true);
+    let mut zip = ZipWriter::new(tempfile::tempfile()?);
+    for frame in &frames {
+        let name = frame.file_name().unwrap_or_default().to_string_lossy();
+        zip.start_file(name, stored).map_err(archive_error)?;
+        std::io::copy(&mut std::fs::File::open(frame)?, &mut zip)?;
+    }
+    let mut archive = zip.finish().map_err(archive_error)?;
+    archive.rewind()?;
+    Ok((archive, frames.len()))
 }
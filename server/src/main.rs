@@ -1,34 +1,55 @@
 use axum::{
     Router,
+    extract::DefaultBodyLimit,
     routing::{post, get},
     response::Json,
 };
+use image_preparer::config::ProcessingConfig;
+use image_preparer::config_file::{ConfigFile, CONFIG_ENV};
+use image_preparer::format::map_extension;
+use image_preparer::ffmpeg;
+use image_preparer::report::format_size;
+use std::path::Path;
+use std::sync::Arc;
+use image_preparer::pipeline::Pipeline;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 
 mod handlers;
+mod upload;
+
+use handlers::{AppState, UPLOAD_LIMITS};
+
+/// Environment variable for the largest accepted request body, e.g. 500M or 2G
+const MAX_UPLOAD_ENV: &str = "IP_MAX_UPLOAD";
 
 #[tokio::main]
 async fn main() {
     // Initialize logging
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    // Build router
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/health", get(health))
-        .route("/compress", post(handlers::compress))
-        .route("/convert", post(handlers::convert))
-        .route("/inspect", post(handlers::inspect))
-        .route("/extract", post(handlers::extract))
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http());
+    // Form fields left out fall back to the config file's settings
+    let state = Arc::new(AppState { defaults: load_defaults(), max_upload: max_upload() });
+    log::info!("Uploads up to {}, streamed to temp files", format_size(state.max_upload));
+
+    let app = app(state);
+
+    // Probe ffmpeg once up front so missing video support shows in the log
+    match tokio::task::spawn_blocking(ffmpeg::probe).await.ok().flatten() {
+        Some(info) => log::info!("ffmpeg {} at {}", info.version, info.path.display()),
+        None => log::warn!(
+            "ffmpeg not found at {} (set {}) - video processing disabled",
+            ffmpeg::binary().display(),
+            ffmpeg::FFMPEG_ENV
+        ),
+    }
 
     // Server address
     let addr = "0.0.0.0:3000";
     log::info!("🚀 Image Preparer Server running on http://{}", addr);
     log::info!("📖 API endpoints:");
     log::info!("   POST /compress - Compress images/videos");
+    log::info!("   POST /estimate - Predict compression savings");
     log::info!("   POST /convert - Convert between formats");
     log::info!("   POST /inspect - View metadata");
     log::info!("   POST /extract - Extract video frames");
@@ -39,13 +60,230 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// The API routes, with bodies capped at `state.max_upload`
+fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(root))
+        .route("/health", get(health))
+        .route("/compress", post(handlers::compress))
+        .route("/estimate", post(handlers::estimate))
+        .route("/convert", post(handlers::convert))
+        .route("/inspect", post(handlers::inspect))
+        .route("/extract", post(handlers::extract))
+        .layer(DefaultBodyLimit::max(usize::try_from(state.max_upload).unwrap_or(usize::MAX)))
+        .with_state(state)
+        .layer(CorsLayer::permissive())
+        .layer(TraceLayer::new_for_http())
+}
+
+/// Settings from the file named by IP_CONFIG, else image-preparer.toml in the
+/// working directory, else the library defaults. A broken file stops startup.
+fn load_defaults() -> ProcessingConfig {
+    let file = match std::env::var_os(CONFIG_ENV) {
+        Some(path) => ConfigFile::load(Path::new(&path)).map(Some),
+        None => ConfigFile::discover(Path::new(".")),
+    };
+    match file {
+        Ok(Some(file)) => {
+            log::info!("Defaults from {}", file.path.display());
+            for (extension, format) in &file.extensions {
+                map_extension(extension, *format);
+            }
+            file.config
+        }
+        Ok(None) => ProcessingConfig::default(),
+        Err(e) => panic!("Cannot load config file: {}", e),
+    }
+}
+
+/// `IP_MAX_UPLOAD` in bytes, with an optional K, M or G suffix; else the
+/// upload limits' input size
+fn max_upload() -> u64 {
+    let default = UPLOAD_LIMITS.max_input_size.unwrap_or(u64::MAX);
+    let Ok(text) = std::env::var(MAX_UPLOAD_ENV) else {
+        return default;
+    };
+    let text = text.trim();
+    let (number, multiplier) = match text.chars().last() {
+        Some('k' | 'K') => (&text[..text.len() - 1], 1u64 << 10),
+        Some('m' | 'M') => (&text[..text.len() - 1], 1 << 20),
+        Some('g' | 'G') => (&text[..text.len() - 1], 1 << 30),
+        _ => (text, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => n.saturating_mul(multiplier),
+        _ => panic!("{}='{}' is not a size like 500M or 2G", MAX_UPLOAD_ENV, text),
+    }
+}
+
 async fn root() -> &'static str {
-    "Image Preparer Server v0.1.0\n\nAPI Endpoints:\n  POST /compress\n  POST /convert\n  POST /inspect\n  POST /extract\n  GET  /health\n"
+    concat!(
+        "Image Preparer Server v0.1.0\n\nAPI Endpoints:\n",
+        "  POST /compress\n  POST /estimate\n  POST /convert\n  POST /inspect\n  POST /extract\n  GET  /health\n",
+    )
 }
 
 async fn health() -> Json<serde_json::Value> {
+    let ffmpeg_info = tokio::task::spawn_blocking(ffmpeg::probe).await.ok().flatten();
+    let ffmpeg = ffmpeg_info.is_some();
+
+    let pipeline = Pipeline::with_default_processors();
+    let formats: Vec<_> = pipeline
+        .supported_formats()
+        .into_iter()
+        .filter_map(|format| {
+            let caps = pipeline.capabilities(format)?;
+            Some(serde_json::json!({
+                "format": format.as_str(),
+                "mime_type": format.mime_type(),
+                "lossy": caps.lossy,
+                "needs_ffmpeg": caps.needs_ffmpeg,
+                "strip_only": caps.strip_only,
+                "available": ffmpeg || !caps.needs_ffmpeg,
+            }))
+        })
+        .collect();
+
     Json(serde_json::json!({
         "status": "ok",
-        "version": "0.1.0"
+        "version": "0.1.0",
+        "ffmpeg": ffmpeg,
+        "ffmpeg_version": ffmpeg_info.map(|info| info.version.as_str()),
+        "formats": formats
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::extract::Multipart;
+    use axum::http::{header, Request, StatusCode};
+    use image_preparer::format::Format;
+    use std::io::Cursor;
+    use tower::ServiceExt;
+    use upload::Upload;
+
+    const BOUNDARY: &str = "image-preparer-test";
+
+    /// A multipart POST to `uri` with `data` as its `file` field
+    fn upload_request(uri: &str, data: &[u8]) -> Request<Body> {
+        form_request(uri, &[("file", data)])
+    }
+
+    /// A multipart POST to `uri` with `fields` (name, value); `file` is sent as an upload
+    fn form_request(uri: &str, fields: &[(&str, &[u8])]) -> Request<Body> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            let filename = if *name == "file" { "; filename=\"upload\"" } else { "" };
+            let disposition = format!("Content-Disposition: form-data; name=\"{}\"{}", name, filename);
+            body.extend_from_slice(format!("--{}\r\n{}\r\n\r\n", BOUNDARY, disposition).as_bytes());
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn state(max_upload: u64) -> Arc<AppState> {
+        Arc::new(AppState { defaults: ProcessingConfig::default(), max_upload })
+    }
+
+    fn png() -> Vec<u8> {
+        let mut data = Vec::new();
+        image::RgbImage::from_pixel(64, 64, image::Rgb([10, 120, 200]))
+            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_upload_spooled_to_temp_file() {
+        // Large enough to arrive in several chunks
+        let mut data = png();
+        data.resize(1 << 20, 0x5a);
+
+        let expected = data.clone();
+        let app = Router::new().route(
+            "/",
+            post(|mut multipart: Multipart| async move {
+                let field = multipart.next_field().await.unwrap().unwrap();
+                let upload = Upload::receive(field).await.unwrap();
+                assert_eq!(upload.size(), expected.len() as u64);
+                assert_eq!(upload.format(), Some(Format::Png));
+                assert_eq!(std::fs::read(upload.path()).unwrap(), expected);
+                upload.path().display().to_string()
+            }),
+        );
+        let response = app.oneshot(upload_request("/", &data)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        // The temp file goes with the upload
+        let path = std::str::from_utf8(&body).unwrap();
+        assert!(!Path::new(path).exists(), "{} left behind", path);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let data = png();
+
+        let response = app(state(1024)).oneshot(upload_request("/compress", &vec![0u8; 4096])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = app(state(1 << 20)).oneshot(upload_request("/compress", &data)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_estimate() {
+        let data = png();
+
+        // The size /compress would return, without the file
+        let compressed = app(state(1 << 20)).oneshot(upload_request("/compress", &data)).await.unwrap();
+        let compressed = to_bytes(compressed.into_body(), usize::MAX).await.unwrap();
+        let response = app(state(1 << 20)).oneshot(upload_request("/estimate", &data)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["success"], true);
+        assert_eq!(json["data"]["format"], "PNG");
+        assert_eq!(json["data"]["original_size"], data.len());
+        assert_eq!(json["data"]["estimated_size"], compressed.len());
+        let savings = (1.0 - compressed.len() as f64 / data.len() as f64) * 100.0;
+        assert_eq!(json["data"]["savings_pct"], (savings * 100.0).round() / 100.0);
+
+        let response = app(state(1 << 20)).oneshot(upload_request("/estimate", b"plain text")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_extract() {
+        // An MP4 by its signature, with no media for ffmpeg to read
+        let mp4 = b"\0\0\0\x10ftypisom\0\0\0\0".as_slice();
+        let extract = |fields: &[(&str, &[u8])]| app(state(1 << 20)).oneshot(form_request("/extract", fields));
+
+        let response = extract(&[("file", png().as_slice())]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = extract(&[("fps", b"1")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = extract(&[("file", mp4), ("fps", b"-2")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Errors from the extraction come back as JSON
+        let response = extract(&[("file", mp4), ("fps", b"0.5")]).await.unwrap();
+        if !image_preparer::processor::is_ffmpeg_available() {
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_ne!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["success"], false);
+        assert!(json["code"].is_string());
+    }
+}
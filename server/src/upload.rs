@@ -0,0 +1,60 @@
+//! Uploaded files, written to a temp file chunk by chunk as they arrive so a
+//! large video never sits in memory as a request body
+
+use std::path::Path;
+
+use axum::extract::multipart::Field;
+use axum::http::StatusCode;
+use image_preparer::format::Format;
+use tempfile::TempPath;
+use tokio::io::AsyncWriteExt;
+
+/// Bytes kept from the start of an upload for format detection
+const HEAD_LEN: usize = 64;
+
+/// An upload on disk, deleted when dropped
+pub struct Upload {
+    path: TempPath,
+    /// The first [`HEAD_LEN`] bytes
+    head: Vec<u8>,
+    size: u64,
+}
+
+impl Upload {
+    /// Stream `field` into a new temp file. A body over the router's
+    /// `DefaultBodyLimit` ends the stream with 413.
+    pub async fn receive(mut field: Field<'_>) -> Result<Self, StatusCode> {
+        let path = tempfile::NamedTempFile::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.into_temp_path();
+        let mut file = tokio::fs::File::create(&path).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let mut head = Vec::with_capacity(HEAD_LEN);
+        let mut size = 0u64;
+
+        while let Some(chunk) = field.chunk().await.map_err(|e| e.status())? {
+            let wanted = HEAD_LEN.saturating_sub(head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..wanted]);
+            size += chunk.len() as u64;
+            file.write_all(&chunk).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+        file.flush().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        Ok(Self { path, head, size })
+    }
+
+    /// Format by the leading magic bytes; uploads carry no usable file name
+    pub fn format(&self) -> Option<Format> {
+        Format::from_bytes(&self.head)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The whole upload, for processors that need it in memory
+    pub async fn read(&self) -> Result<Vec<u8>, StatusCode> {
+        tokio::fs::read(&self.path).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}